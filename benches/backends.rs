@@ -0,0 +1,77 @@
+// Times the tree-walking interpreter against the bytecode vm on a few
+// programs. Run with `cargo bench`.
+
+mod common;
+
+use clay::interpreter::interpreter::Interpreter;
+use clay::parser::parser::parse;
+use clay::vm::vm::Vm;
+
+use common::{millis, time};
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+        fib(24)",
+    ),
+    (
+        "loop",
+        "let mut total = 0;
+        let mut i = 0;
+        while i < 300000 { total += i % 7; i += 1; }
+        total",
+    ),
+    (
+        "closures",
+        "fn adder(n) { fn(x) { x + n } }
+        let mut total = 0;
+        for i in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9] {
+            let mut j = 0;
+            while j < 5000 { total = adder(i)(total); j += 1; }
+        }
+        total",
+    ),
+    (
+        "lists",
+        "let mut items = [];
+        let mut i = 0;
+        while i < 20000 { items.push(i); i += 1; }
+        let mut total = 0;
+        for item in items.map(fn(x) { x * 2 }).filter(fn(x) { x % 3 == 0 }) {
+            total += item;
+        }
+        total",
+    ),
+    (
+        "strings",
+        "let words = [\"alpha\", \"beta\", \"gamma\", \"delta\"];
+        let mut text = \"\";
+        let mut i = 0;
+        while i < 20000 { text = text + words[i % 4] + \" \"; i += 1; }
+        let mut count = 0;
+        for word in split(upper(replace(text, \"a\", \"o\")), \" \") {
+            if contains(word, \"OM\") { count += len(word); }
+        }
+        count",
+    ),
+];
+
+fn main() {
+    println!(
+        "{:<10} {:>12} {:>12} {:>8}",
+        "program", "tree", "vm", "speedup"
+    );
+    for (name, source) in PROGRAMS {
+        let program = parse(source).expect("benchmarks parse");
+        let tree = time(|| Interpreter::new().run(&program).expect("benchmarks run"));
+        let vm = time(|| Vm::new().run(&program).expect("benchmarks run"));
+        println!(
+            "{:<10} {:>10.2}ms {:>10.2}ms {:>7.2}x",
+            name,
+            millis(tree),
+            millis(vm),
+            tree.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
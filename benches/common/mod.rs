@@ -0,0 +1,21 @@
+// Timing shared by the benchmarks.
+
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 5;
+
+// The fastest of several runs, which is the least disturbed by noise.
+pub fn time<T>(mut run: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .expect("at least one run")
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000.0
+}
@@ -0,0 +1,67 @@
+// Times lexing and parsing a large generated program. Run with
+// `cargo bench --bench frontend`.
+
+mod common;
+
+use clay::lexer::lexer::Lexer;
+use clay::parser::parser::parse;
+
+use common::{millis, time};
+
+const FUNCTIONS: usize = 5000;
+
+// One function of the generated program, with `N` replaced by its number.
+// It touches most of the syntax: declarations, control flow, patterns,
+// literals of every kind and comments.
+const TEMPLATE: &str = "
+// Function N.
+struct PointN { x: Int, y: Int }
+
+fn stepN(points: List<PointN>, limit) -> Int {
+    let mut total = 0;
+    for PointN { x, y } in points {
+        if x < limit && y >= 0 {
+            total += x * 2 + y % 3;
+        } else if x == limit {
+            total -= 1;
+        } else {
+            break;
+        }
+    }
+    let scale = 1.5 * 2_000.25;
+    let names = #{ \"first\": [1, 2, 3], \"second\": [] };
+    match (total, names.len()) {
+        (0, _) => -1,
+        (n, count) if n > count => n - count,
+        _ => total,
+    }
+}
+";
+
+fn main() {
+    let source: String = (0..FUNCTIONS)
+        .map(|n| TEMPLATE.replace('N', &n.to_string()))
+        .collect();
+    let megabytes = source.len() as f64 / 1_000_000.0;
+    let tokens = Lexer::new(&source).count();
+    println!(
+        "{} functions, {:.2}MB, {} tokens",
+        FUNCTIONS, megabytes, tokens
+    );
+
+    let lex = time(|| {
+        Lexer::new(&source)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("the benchmark lexes")
+    });
+    let parse = time(|| parse(&source).expect("the benchmark parses"));
+    println!("{:<6} {:>12} {:>12}", "phase", "time", "throughput");
+    for (name, duration) in [("lex", lex), ("parse", parse)].iter() {
+        println!(
+            "{:<6} {:>10.2}ms {:>9.2}MB/s",
+            name,
+            millis(*duration),
+            megabytes / duration.as_secs_f64()
+        );
+    }
+}
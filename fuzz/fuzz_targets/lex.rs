@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use clay::lexer::lexer::Lexer;
+
+// Any text lexes to tokens and errors; the lexer never panics or gets stuck.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        for _ in Lexer::new(source) {}
+    }
+});
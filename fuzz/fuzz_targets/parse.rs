@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use clay::parser::parser::parse;
+
+// Any text parses to a program or a diagnostic, however deeply it nests.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = parse(source);
+    }
+});
@@ -0,0 +1,253 @@
+use serde::Serialize;
+
+use crate::lexer::symbol::{self, Symbol};
+use crate::parser::ast::{Function, MethodSig, Param, Program, StmtKind, TypeExpr};
+
+// A declaration in a module's documentation, with the text of its `///`
+// comments.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Item {
+    pub name: String,
+    // The declaration without its body, as `fn add(a: Int, b: Int) -> Int`.
+    pub signature: String,
+    pub doc: Option<String>,
+    // A trait's methods, or the methods a struct or enum implements.
+    pub methods: Vec<Item>,
+}
+
+// The functions, types and traits a module declares, in source order. The
+// methods of an impl are listed under the type they are for when the module
+// declares it.
+pub fn items(program: &Program) -> Vec<Item> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut types: Vec<(Symbol, usize)> = Vec::new();
+    for stmt in &program.statements {
+        match &stmt.kind {
+            StmtKind::Function(function) => items.push(function_item(function)),
+            StmtKind::Struct(decl) => {
+                types.push((decl.name, items.len()));
+                let fields: Vec<String> = decl
+                    .fields
+                    .iter()
+                    .map(|field| typed(field.name, &field.ty))
+                    .collect();
+                items.push(Item {
+                    name: decl.name.to_string(),
+                    signature: format!(
+                        "struct {}{}{}",
+                        decl.name,
+                        type_params(&decl.type_params),
+                        braced(&fields)
+                    ),
+                    doc: decl.doc.clone(),
+                    methods: Vec::new(),
+                });
+            }
+            StmtKind::Enum(decl) => {
+                types.push((decl.name, items.len()));
+                let variants: Vec<String> = decl
+                    .variants
+                    .iter()
+                    .map(|variant| match variant.fields.is_empty() {
+                        true => variant.name.to_string(),
+                        false => format!("{}({})", variant.name, list(&variant.fields)),
+                    })
+                    .collect();
+                items.push(Item {
+                    name: decl.name.to_string(),
+                    signature: format!(
+                        "enum {}{}{}",
+                        decl.name,
+                        type_params(&decl.type_params),
+                        braced(&variants)
+                    ),
+                    doc: decl.doc.clone(),
+                    methods: Vec::new(),
+                });
+            }
+            StmtKind::Trait(decl) => items.push(Item {
+                name: decl.name.to_string(),
+                signature: format!("trait {}", decl.name),
+                doc: decl.doc.clone(),
+                methods: decl.methods.iter().map(method_item).collect(),
+            }),
+            StmtKind::Impl(decl) => {
+                if let Some(&(_, index)) = types.iter().find(|(name, _)| *name == decl.target) {
+                    let methods = decl.methods.iter().map(|method| function_item(method));
+                    items[index].methods.extend(methods);
+                }
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+fn function_item(function: &Function) -> Item {
+    let name = function
+        .name
+        .map(|name| name.to_string())
+        .unwrap_or_default();
+    Item {
+        signature: format!(
+            "fn {}{}{}",
+            name,
+            type_params(&function.type_params),
+            signature(&function.params, &function.returns)
+        ),
+        name,
+        doc: function.doc.clone(),
+        methods: Vec::new(),
+    }
+}
+
+fn method_item(method: &MethodSig) -> Item {
+    Item {
+        name: method.name.to_string(),
+        signature: format!(
+            "fn {}{}",
+            method.name,
+            signature(&method.params, &method.returns)
+        ),
+        doc: method.doc.clone(),
+        methods: Vec::new(),
+    }
+}
+
+fn signature(params: &[Param], returns: &Option<TypeExpr>) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| typed(param.name, &param.ty))
+        .collect();
+    match returns {
+        Some(returns) => format!("({}) -> {}", params.join(", "), returns),
+        None => format!("({})", params.join(", ")),
+    }
+}
+
+fn typed(name: Symbol, ty: &Option<TypeExpr>) -> String {
+    match ty {
+        Some(ty) => format!("{}: {}", name, ty),
+        None => name.to_string(),
+    }
+}
+
+fn type_params(params: &[Symbol]) -> String {
+    match params.is_empty() {
+        true => String::new(),
+        false => format!("<{}>", symbol::join(params, ", ")),
+    }
+}
+
+fn list(types: &[TypeExpr]) -> String {
+    let types: Vec<String> = types.iter().map(TypeExpr::to_string).collect();
+    types.join(", ")
+}
+
+fn braced(items: &[String]) -> String {
+    match items.is_empty() {
+        true => " {}".to_string(),
+        false => format!(" {{ {} }}", items.join(", ")),
+    }
+}
+
+// The documentation as Markdown, under a heading naming the module. Doc
+// comments are Markdown already, so they are copied as they are.
+pub fn markdown(module: &str, items: &[Item]) -> String {
+    let mut out = format!("# {}\n", module);
+    for item in items {
+        markdown_item(&mut out, item, "##");
+        for method in &item.methods {
+            markdown_item(&mut out, method, "###");
+        }
+    }
+    out
+}
+
+fn markdown_item(out: &mut String, item: &Item, heading: &str) {
+    out.push_str(&format!(
+        "\n{} {}\n\n```clay\n{}\n```\n",
+        heading, item.name, item.signature
+    ));
+    if let Some(doc) = &item.doc {
+        out.push_str(&format!("\n{}\n", doc));
+    }
+}
+
+// The documentation as a standalone HTML page. Doc comments are escaped and
+// split into paragraphs at their blank lines.
+pub fn html(module: &str, items: &[Item]) -> String {
+    let module = escape(module);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n",
+        module, module
+    );
+    for item in items {
+        html_item(&mut out, item, "h2");
+        for method in &item.methods {
+            html_item(&mut out, method, "h3");
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn html_item(out: &mut String, item: &Item, heading: &str) {
+    out.push_str(&format!(
+        "<{}>{}</{}>\n<pre><code>{}</code></pre>\n",
+        heading,
+        escape(&item.name),
+        heading,
+        escape(&item.signature)
+    ));
+    let doc = item.doc.as_deref().unwrap_or_default();
+    for paragraph in doc.split("\n\n").filter(|text| !text.trim().is_empty()) {
+        out.push_str(&format!("<p>{}</p>\n", escape(paragraph.trim())));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::docs::{html, items, markdown};
+    use crate::parser::parser::parse;
+
+    const SOURCE: &str = "/// Adds `a` and `b`.\n///\n/// Wraps on overflow.\n\
+                          fn add(a: Int, b: Int) -> Int { a + b }\n\
+                          /// A point.\nstruct P<T> { x: T, y }\n\
+                          impl Show for P {\n    /// Shows <it>.\n    fn show(self) -> String { \"\" }\n}\n\
+                          trait Show { fn show(self) -> String; }\n";
+
+    #[test]
+    fn renders_markdown() {
+        let program = parse(SOURCE).unwrap();
+        assert_eq!(
+            markdown("math", &items(&program)),
+            "# math\n\n\
+             ## add\n\n```clay\nfn add(a: Int, b: Int) -> Int\n```\n\n\
+             Adds `a` and `b`.\n\nWraps on overflow.\n\n\
+             ## P\n\n```clay\nstruct P<T> { x: T, y }\n```\n\nA point.\n\n\
+             ### show\n\n```clay\nfn show(self) -> String\n```\n\nShows <it>.\n\n\
+             ## Show\n\n```clay\ntrait Show\n```\n\n\
+             ### show\n\n```clay\nfn show(self) -> String\n```\n"
+        );
+    }
+
+    #[test]
+    fn renders_html() {
+        let program = parse(SOURCE).unwrap();
+        let page = html("math", &items(&program));
+        assert!(page.contains("<title>math</title>"));
+        assert!(page.contains(
+            "<h2>add</h2>\n<pre><code>fn add(a: Int, b: Int) -&gt; Int</code></pre>\n\
+             <p>Adds `a` and `b`.</p>\n<p>Wraps on overflow.</p>\n"
+        ));
+        assert!(page.contains("<h3>show</h3>\n<pre><code>fn show(self) -&gt; String</code></pre>\n<p>Shows &lt;it&gt;.</p>\n"));
+    }
+}
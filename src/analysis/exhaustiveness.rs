@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::stdlib::{prelude_enum, ENUMS};
+use crate::lexer::symbol::{self, Symbol};
+use crate::lint::lint::{Level, LintLevels};
+use crate::parser::ast::{Block, Expr, ExprKind, MatchArm, PatternKind, Program, Stmt, StmtKind};
+use crate::parser::visit::{walk_stmt, Visitor};
+use crate::pipeline::pass::Pass;
+
+pub struct ExhaustivenessPass;
+
+// The warning for an arm no value can reach, which `clay lint` also reports.
+pub const UNREACHABLE_ARM: &str = "unreachable match arm";
+
+impl Pass for ExhaustivenessPass {
+    fn name(&self) -> &str {
+        "exhaustiveness"
+    }
+
+    // `@allow(non-exhaustive-match)` and `@deny(unreachable-match-arm)`
+    // and the like set how seriously the warnings are taken.
+    fn run(&mut self, program: &mut Program, diagnostics: &mut Vec<Diagnostic>) {
+        let levels = LintLevels::of(program);
+        for diagnostic in check(program) {
+            let rule = match diagnostic.message == UNREACHABLE_ARM {
+                true => "unreachable-match-arm",
+                false => "non-exhaustive-match",
+            };
+            match levels.level(rule, diagnostic.span, Level::Warn) {
+                Level::Allow => {}
+                Level::Warn => diagnostics.push(diagnostic),
+                Level::Deny => {
+                    diagnostics.push(Diagnostic::error(diagnostic.message, diagnostic.span))
+                }
+            }
+        }
+    }
+}
+
+// Warns about `match` expressions that can fall through every arm and about
+// arms that can never be reached. This is the usefulness algorithm from
+// Maranget's "Warnings for pattern matching": a pattern row is useful if some
+// value matches it and none of the rows above it. Integers, floats and strings
+// have infinitely many constructors, so only a catch-all covers them.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut types = Types::default();
+    for (name, variants) in ENUMS {
+        let variants = variants
+            .iter()
+            .map(|&(variant, arity)| (Symbol::intern(variant), arity))
+            .collect();
+        types.enums.insert(Symbol::intern(name), variants);
+    }
+    for stmt in &program.statements {
+        types.visit_stmt(stmt);
+    }
+    let mut diagnostics = Vec::new();
+    for stmt in &program.statements {
+        check_stmt(stmt, &types, &mut diagnostics);
+    }
+    diagnostics
+}
+
+// The structs and enums the program declares, and the built-in enums.
+#[derive(Default)]
+struct Types {
+    // Each struct's fields, to line up the fields of struct patterns that
+    // leave some out.
+    structs: HashMap<Symbol, Vec<Symbol>>,
+    // Each enum's variants and how many values they hold, which a match must
+    // cover.
+    enums: HashMap<Symbol, Vec<(Symbol, usize)>>,
+}
+
+impl Visitor for Types {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Struct(decl) => {
+                let fields = decl.fields.iter().map(|field| field.name).collect();
+                self.structs.insert(decl.name, fields);
+            }
+            StmtKind::Enum(decl) => {
+                let variants = decl
+                    .variants
+                    .iter()
+                    .map(|variant| (variant.name, variant.fields.len()))
+                    .collect();
+                self.enums.insert(decl.name, variants);
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt)
+    }
+}
+
+fn check_stmt(stmt: &Stmt, types: &Types, diagnostics: &mut Vec<Diagnostic>) {
+    match &stmt.kind {
+        StmtKind::Expr(expr) => check_expr(expr, types, diagnostics),
+        StmtKind::Function(function) => check_block(&function.body, types, diagnostics),
+        StmtKind::Test { body, .. } => check_block(body, types, diagnostics),
+        StmtKind::Impl(decl) => {
+            for method in &decl.methods {
+                check_block(&method.body, types, diagnostics);
+            }
+        }
+        StmtKind::Import(_) | StmtKind::Struct(_) | StmtKind::Enum(_) | StmtKind::Trait(_) => {}
+        StmtKind::Let { value, .. } | StmtKind::Destructure { value, .. } => {
+            check_expr(value, types, diagnostics)
+        }
+    }
+}
+
+fn check_block(block: &Block, types: &Types, diagnostics: &mut Vec<Diagnostic>) {
+    for stmt in &block.statements {
+        check_stmt(stmt, types, diagnostics);
+    }
+    if let Some(value) = &block.value {
+        check_expr(value, types, diagnostics);
+    }
+}
+
+fn check_expr(expr: &Expr, types: &Types, diagnostics: &mut Vec<Diagnostic>) {
+    match &expr.kind {
+        ExprKind::Integer(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Path(_) => {}
+        ExprKind::Tuple(elements) | ExprKind::List(elements) => {
+            for element in elements {
+                check_expr(element, types, diagnostics);
+            }
+        }
+        ExprKind::Unary { operand, .. } => check_expr(operand, types, diagnostics),
+        ExprKind::Binary { left, right, .. } => {
+            check_expr(left, types, diagnostics);
+            check_expr(right, types, diagnostics);
+        }
+        ExprKind::Assign { target, value } => {
+            check_expr(target, types, diagnostics);
+            check_expr(value, types, diagnostics);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            check_expr(scrutinee, types, diagnostics);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    check_expr(guard, types, diagnostics);
+                }
+                check_expr(&arm.body, types, diagnostics);
+            }
+            check_match(expr, arms, types, diagnostics);
+        }
+        ExprKind::Block(block) => check_block(block, types, diagnostics),
+        ExprKind::Function(function) => check_block(&function.body, types, diagnostics),
+        ExprKind::Call { callee, args } => {
+            check_expr(callee, types, diagnostics);
+            for arg in args {
+                check_expr(arg, types, diagnostics);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                check_expr(key, types, diagnostics);
+                check_expr(value, types, diagnostics);
+            }
+        }
+        ExprKind::MethodCall { receiver, args, .. } => {
+            check_expr(receiver, types, diagnostics);
+            for arg in args {
+                check_expr(arg, types, diagnostics);
+            }
+        }
+        ExprKind::Index { target, index } => {
+            check_expr(target, types, diagnostics);
+            check_expr(index, types, diagnostics);
+        }
+        ExprKind::Slice { target, start, end } => {
+            check_expr(target, types, diagnostics);
+            for bound in start.iter().chain(end) {
+                check_expr(bound, types, diagnostics);
+            }
+        }
+        ExprKind::Return(value) => {
+            if let Some(value) = value {
+                check_expr(value, types, diagnostics);
+            }
+        }
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            check_expr(condition, types, diagnostics);
+            check_block(then_branch, types, diagnostics);
+            if let Some(else_branch) = else_branch {
+                check_expr(else_branch, types, diagnostics);
+            }
+        }
+        ExprKind::While { condition, body } => {
+            check_expr(condition, types, diagnostics);
+            check_block(body, types, diagnostics);
+        }
+        ExprKind::For { iterable, body, .. } => {
+            check_expr(iterable, types, diagnostics);
+            check_block(body, types, diagnostics);
+        }
+        ExprKind::Struct { fields, .. } => {
+            for (_, value) in fields {
+                check_expr(value, types, diagnostics);
+            }
+        }
+        ExprKind::Field { target, .. } => check_expr(target, types, diagnostics),
+        ExprKind::Range { start, end, .. } => {
+            check_expr(start, types, diagnostics);
+            check_expr(end, types, diagnostics);
+        }
+        ExprKind::Break | ExprKind::Continue => {}
+    }
+}
+
+fn check_match(expr: &Expr, arms: &[MatchArm], types: &Types, diagnostics: &mut Vec<Diagnostic>) {
+    let mut matrix: Vec<Vec<Pat>> = Vec::new();
+
+    for arm in arms {
+        let row = vec![Pat::from(&arm.pattern.kind, types)];
+        if !is_useful(&matrix, &row, types) {
+            diagnostics.push(Diagnostic::warning(UNREACHABLE_ARM, arm.span));
+        }
+        // A guarded arm may decline to match, so it never covers anything.
+        if arm.guard.is_none() {
+            matrix.push(row);
+        }
+    }
+
+    if !is_useful(&matrix, &[Pat::Wild], types) {
+        return;
+    }
+    let message = match missing_variants(&matrix, types).as_slice() {
+        [] => "non-exhaustive match: some values are not covered, consider adding a `_` arm"
+            .to_string(),
+        [variant] => format!("non-exhaustive match: `{}` is not covered", variant),
+        variants => format!(
+            "non-exhaustive match: {} are not covered",
+            variants
+                .iter()
+                .map(|variant| format!("`{}`", variant))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    diagnostics.push(Diagnostic::warning(message, expr.span));
+}
+
+// The variants of the enum a match is over that none of its arms mention.
+fn missing_variants(matrix: &[Vec<Pat>], types: &Types) -> Vec<String> {
+    if matrix.iter().any(|row| row[0] == Pat::Wild) {
+        return Vec::new();
+    }
+    let enum_name = match matrix.iter().find_map(|row| match &row[0] {
+        Pat::Constructor(Constructor::Variant(enum_name, ..), _) => Some(enum_name),
+        _ => None,
+    }) {
+        Some(enum_name) => enum_name,
+        None => return Vec::new(),
+    };
+    let declared = match types.enums.get(&Symbol::intern(enum_name)) {
+        Some(declared) => declared,
+        None => return Vec::new(),
+    };
+    declared
+        .iter()
+        .filter(|(name, _)| {
+            !matrix.iter().any(|row| {
+                matches!(&row[0], Pat::Constructor(Constructor::Variant(e, v, _), _)
+                    if e == enum_name && v == name)
+            })
+        })
+        .map(|(name, _)| format!("{}::{}", enum_name, name))
+        .collect()
+}
+
+#[derive(Clone, PartialEq)]
+enum Pat {
+    Wild,
+    Constructor(Constructor, Vec<Pat>),
+}
+
+#[derive(Clone, PartialEq)]
+enum Constructor {
+    Bool(bool),
+    Tuple(usize),
+    // A struct's path and the fields its patterns list, in order.
+    Struct(String, Vec<Symbol>),
+    // An enum's path, the variant's name and how many values it holds.
+    Variant(String, Symbol, usize),
+    // Integer, float and string literals, keyed by their printed value.
+    Literal(String),
+}
+
+impl Pat {
+    fn from(kind: &PatternKind, types: &Types) -> Pat {
+        match kind {
+            PatternKind::Wildcard | PatternKind::Binding(_) => Pat::Wild,
+            PatternKind::Bool(b) => Pat::Constructor(Constructor::Bool(*b), Vec::new()),
+            PatternKind::Tuple(patterns) => Pat::Constructor(
+                Constructor::Tuple(patterns.len()),
+                patterns.iter().map(|p| Pat::from(&p.kind, types)).collect(),
+            ),
+            PatternKind::Variant { path, fields } => {
+                let (name, enum_path) = path.split_last().expect("variant paths have a name");
+                let enum_path = match prelude_enum(name) {
+                    Some(ty) if enum_path.is_empty() => ty.to_string(),
+                    _ => symbol::join(enum_path, "::"),
+                };
+                Pat::Constructor(
+                    Constructor::Variant(enum_path, *name, fields.len()),
+                    fields.iter().map(|p| Pat::from(&p.kind, types)).collect(),
+                )
+            }
+            PatternKind::Struct { path, fields, rest } => {
+                // Patterns of a declared struct are lined up on its fields,
+                // with the ones a `..` skips matching anything. Otherwise
+                // only patterns listing the same fields can be compared.
+                let declared = match path.as_slice() {
+                    [name] => types.structs.get(name).filter(|declared| {
+                        (*rest || fields.len() == declared.len())
+                            && fields.iter().all(|(field, _)| declared.contains(field))
+                    }),
+                    _ => None,
+                };
+                let names = match declared {
+                    Some(declared) => declared.clone(),
+                    None => {
+                        let mut names: Vec<_> = fields.iter().map(|(name, _)| *name).collect();
+                        names.sort();
+                        names
+                    }
+                };
+                let patterns = names
+                    .iter()
+                    .map(
+                        |name| match fields.iter().find(|(field, _)| field == name) {
+                            Some((_, pattern)) => Pat::from(&pattern.kind, types),
+                            None => Pat::Wild,
+                        },
+                    )
+                    .collect();
+                Pat::Constructor(
+                    Constructor::Struct(symbol::join(path, "::"), names),
+                    patterns,
+                )
+            }
+            PatternKind::Integer(n) => literal(format!("i{}", n)),
+            PatternKind::Float(n) => literal(format!("f{}", n)),
+            PatternKind::String(s) => literal(format!("s{}", s)),
+        }
+    }
+}
+
+fn literal(key: String) -> Pat {
+    Pat::Constructor(Constructor::Literal(key), Vec::new())
+}
+
+impl Constructor {
+    fn arity(&self) -> usize {
+        match self {
+            Constructor::Tuple(arity) => *arity,
+            Constructor::Struct(_, fields) => fields.len(),
+            Constructor::Variant(_, _, arity) => *arity,
+            Constructor::Bool(_) | Constructor::Literal(_) => 0,
+        }
+    }
+}
+
+fn is_useful(matrix: &[Vec<Pat>], row: &[Pat], types: &Types) -> bool {
+    let (head, rest) = match row.split_first() {
+        Some(split) => split,
+        None => return matrix.is_empty(),
+    };
+
+    match head {
+        Pat::Constructor(constructor, fields) => {
+            let mut specialized_row = fields.clone();
+            specialized_row.extend_from_slice(rest);
+            is_useful(&specialize(matrix, constructor), &specialized_row, types)
+        }
+        Pat::Wild => match complete_signature(matrix, types) {
+            Some(constructors) => constructors.iter().any(|constructor| {
+                let mut specialized_row = vec![Pat::Wild; constructor.arity()];
+                specialized_row.extend_from_slice(rest);
+                is_useful(&specialize(matrix, constructor), &specialized_row, types)
+            }),
+            None => {
+                let default: Vec<Vec<Pat>> = matrix
+                    .iter()
+                    .filter(|row| row[0] == Pat::Wild)
+                    .map(|row| row[1..].to_vec())
+                    .collect();
+                is_useful(&default, rest, types)
+            }
+        },
+    }
+}
+
+fn specialize(matrix: &[Vec<Pat>], constructor: &Constructor) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter_map(|row| {
+            let mut specialized = match &row[0] {
+                Pat::Wild => vec![Pat::Wild; constructor.arity()],
+                Pat::Constructor(c, fields) if c == constructor => fields.clone(),
+                Pat::Constructor(..) => return None,
+            };
+            specialized.extend_from_slice(&row[1..]);
+            Some(specialized)
+        })
+        .collect()
+}
+
+// Returns every constructor of the first column's type when the column
+// mentions all of them, which is only possible for bools, tuples, structs
+// and the variants of a declared enum.
+fn complete_signature(matrix: &[Vec<Pat>], types: &Types) -> Option<Vec<Constructor>> {
+    let heads: Vec<&Constructor> = matrix
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Constructor(constructor, _) => Some(constructor),
+            Pat::Wild => None,
+        })
+        .collect();
+
+    let first = heads.first()?;
+    match first {
+        Constructor::Bool(_) => {
+            let has = |b| heads.contains(&&Constructor::Bool(b));
+            if has(true) && has(false) {
+                Some(vec![Constructor::Bool(true), Constructor::Bool(false)])
+            } else {
+                None
+            }
+        }
+        Constructor::Tuple(_) | Constructor::Struct(..) => {
+            if heads.iter().all(|c| *c == *first) {
+                Some(vec![(*first).clone()])
+            } else {
+                None
+            }
+        }
+        Constructor::Variant(enum_name, ..) => {
+            let constructors: Vec<_> = types
+                .enums
+                .get(&Symbol::intern(enum_name))?
+                .iter()
+                .map(|(name, arity)| Constructor::Variant(enum_name.clone(), *name, *arity))
+                .collect();
+            if constructors.iter().all(|c| heads.contains(&c)) {
+                Some(constructors)
+            } else {
+                None
+            }
+        }
+        Constructor::Literal(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::exhaustiveness::{check, ExhaustivenessPass};
+    use crate::diagnostic::diagnostic::Severity;
+    use crate::parser::parser::parse;
+    use crate::pipeline::pass::Pass;
+
+    fn warnings(source: &str) -> Vec<String> {
+        check(&parse(source).unwrap())
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn accepts_exhaustive_matches() {
+        assert!(warnings("match x { true => 1, false => 0 }").is_empty());
+        assert!(warnings("match x { (true, _) => 1, (false, n) => n }").is_empty());
+        assert!(warnings("match x { 0 => 1, n if n > 0 => 2, _ => 3 }").is_empty());
+        assert!(warnings(
+            "struct P { x, y }\nmatch p { P { x: true, .. } => 1, P { y, x: false } => y }"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn warns_about_missing_cases() {
+        assert_eq!(
+            warnings("match x { 0 => 1, n if n > 0 => 2 }"),
+            vec!["non-exhaustive match: some values are not covered, consider adding a `_` arm"]
+        );
+        assert_eq!(
+            warnings("match x { (true, false) => 1, (false, _) => 0 }").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn checks_matches_against_enum_variants() {
+        let shape = "enum Shape { Circle(Float), Rect(Float, Float), Empty }\n";
+        let covered =
+            "match s { Shape::Circle(r) => r, Shape::Rect(w, h) => w * h, Shape::Empty => 0 }";
+        assert!(warnings(&(shape.to_string() + covered)).is_empty());
+        assert_eq!(
+            warnings(&(shape.to_string() + "match s { Shape::Circle(r) => r }")),
+            vec!["non-exhaustive match: `Shape::Rect`, `Shape::Empty` are not covered"]
+        );
+        assert_eq!(
+            warnings(
+                &(shape.to_string()
+                    + "match s { Shape::Circle(1.0) => 1, Shape::Rect(_, _) => 2 }")
+            )
+            .len(),
+            1
+        );
+        // Every variant is mentioned, but not with every value it can hold.
+        assert_eq!(
+            warnings("enum B { Yes(Bool), No }\nmatch b { B::Yes(true) => 1, B::No => 0 }"),
+            vec!["non-exhaustive match: some values are not covered, consider adding a `_` arm"]
+        );
+        assert_eq!(
+            warnings("enum B { Yes, No }\nmatch b { B::Yes => 1, _ => 2, B::No => 0 }"),
+            vec!["unreachable match arm"]
+        );
+    }
+
+    #[test]
+    fn knows_the_built_in_enums() {
+        assert_eq!(
+            warnings("match o { Option::Some(x) => x }"),
+            vec!["non-exhaustive match: `Option::None` is not covered"]
+        );
+        assert_eq!(
+            warnings("match o { Some(x) => x }"),
+            vec!["non-exhaustive match: `Option::None` is not covered"]
+        );
+        assert!(warnings("match o { Some(x) => x, None => 0 }").is_empty());
+        // The match `?` stands for covers both enums.
+        assert!(warnings("fn f(r) { r? + 1 }").is_empty());
+    }
+
+    #[test]
+    fn attributes_allow_and_deny_the_warnings() {
+        let source = "@!deny(unreachable-match-arm)\n\
+                      @allow(non-exhaustive) fn f(x) { match x { 1 => 1 } }\n\
+                      fn g(x) { match x { _ => 1, 2 => 2 } }\n\
+                      match 1 { 1 => 1 }";
+        let mut program = parse(source).unwrap();
+        let mut diagnostics = Vec::new();
+        ExhaustivenessPass.run(&mut program, &mut diagnostics);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.span.start.line))
+            .collect();
+        assert_eq!(found, vec![(Severity::Error, 3), (Severity::Warning, 4)]);
+    }
+
+    #[test]
+    fn warns_about_unreachable_arms() {
+        assert_eq!(
+            warnings("match x { _ => 1, 0 => 2 }"),
+            vec!["unreachable match arm"]
+        );
+        assert_eq!(
+            warnings("match x { true => 1, false => 2, true => 3 }"),
+            vec!["unreachable match arm"]
+        );
+        assert_eq!(
+            warnings("struct P { x, y }\nmatch p { P { x, .. } => x, P { x: 1, y: 2 } => 3 }"),
+            vec!["unreachable match arm"]
+        );
+    }
+}
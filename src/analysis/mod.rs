@@ -0,0 +1,4 @@
+pub mod docs;
+pub mod exhaustiveness;
+pub mod slice;
+pub mod symbols;
@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+
+use crate::lexer::symbol::Symbol;
+use crate::parser::ast::{Expr, ExprKind, Pattern, PatternKind, Program, Stmt, StmtKind};
+use crate::parser::visit::{walk_expr, walk_pattern, Visitor};
+
+// Computes the backward slice of `variable` as seen at `line`: the top-level
+// statements up to that line that can influence its value. Statements are
+// walked backwards while tracking the set of relevant names. A statement is
+// kept when it may define a relevant name, and everything it reads becomes
+// relevant in turn. Only a top-level `let` or plain assignment is known to
+// overwrite a name; one nested in a branch or loop might not run, so the earlier
+// definitions stay relevant.
+pub fn backward_slice<'a>(program: &'a Program, variable: &str, line: usize) -> Vec<&'a Stmt> {
+    let mut relevant = HashSet::new();
+    relevant.insert(Symbol::intern(variable));
+
+    let mut slice = Vec::new();
+    for stmt in program
+        .statements
+        .iter()
+        .rev()
+        .skip_while(|stmt| stmt.span.start.line > line)
+    {
+        let mut defined = HashSet::new();
+        stmt_defines(stmt, &mut defined);
+        if defined.is_disjoint(&relevant) {
+            continue;
+        }
+
+        if let Some(name) = overwritten(stmt) {
+            relevant.remove(&name);
+        }
+        stmt_uses(stmt, &mut relevant);
+        slice.push(stmt);
+    }
+
+    slice.reverse();
+    slice
+}
+
+fn overwritten(stmt: &Stmt) -> Option<Symbol> {
+    match &stmt.kind {
+        StmtKind::Let { name, .. } => Some(*name),
+        StmtKind::Expr(Expr {
+            kind: ExprKind::Assign { target, .. },
+            ..
+        }) => match &target.kind {
+            ExprKind::Ident(name) => Some(*name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn stmt_defines(stmt: &Stmt, names: &mut HashSet<Symbol>) {
+    match &stmt.kind {
+        StmtKind::Expr(expr) => expr_defines(expr, names),
+        StmtKind::Function(function) => {
+            names.extend(function.name);
+        }
+        StmtKind::Import(import) => {
+            names.extend(import.bindings());
+        }
+        StmtKind::Struct(decl) => {
+            names.insert(decl.name);
+        }
+        StmtKind::Enum(decl) => {
+            names.insert(decl.name);
+        }
+        StmtKind::Let { name, value, .. } => {
+            names.insert(*name);
+            expr_defines(value, names);
+        }
+        StmtKind::Destructure { pattern, value, .. } => {
+            Binds(names).visit_pattern(pattern);
+            expr_defines(value, names);
+        }
+        StmtKind::Trait(decl) => {
+            names.insert(decl.name);
+        }
+        // Tests have a scope of their own, and impls only add methods to a
+        // struct that is already defined.
+        StmtKind::Test { .. } | StmtKind::Impl(_) => {}
+    }
+}
+
+fn expr_defines(expr: &Expr, names: &mut HashSet<Symbol>) {
+    Defines(names).visit_expr(expr)
+}
+
+struct Defines<'a>(&'a mut HashSet<Symbol>);
+
+impl<'a> Visitor for Defines<'a> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Assign { target, .. } = &expr.kind {
+            // Assigning to a field changes the struct the variable holds.
+            let mut target = &**target;
+            while let ExprKind::Field { target: inner, .. } = &target.kind {
+                target = inner;
+            }
+            if let ExprKind::Ident(name) = &target.kind {
+                self.0.insert(*name);
+            }
+        }
+        walk_expr(self, expr)
+    }
+}
+
+// Collects the names a pattern binds.
+struct Binds<'a>(&'a mut HashSet<Symbol>);
+
+impl<'a> Visitor for Binds<'a> {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Binding(name) = &pattern.kind {
+            self.0.insert(*name);
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+// Collects every name a statement reads, including those read by nested
+// functions, which may run whenever the statement does.
+fn stmt_uses(stmt: &Stmt, names: &mut HashSet<Symbol>) {
+    let mut uses = Uses(names);
+    match &stmt.kind {
+        StmtKind::Expr(Expr {
+            kind: ExprKind::Assign { value, .. },
+            ..
+        }) => uses.visit_expr(value),
+        _ => uses.visit_stmt(stmt),
+    }
+}
+
+struct Uses<'a>(&'a mut HashSet<Symbol>);
+
+impl<'a> Visitor for Uses<'a> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name) => {
+                self.0.insert(*name);
+            }
+            ExprKind::Path(segments) | ExprKind::Struct { path: segments, .. } => {
+                self.0.insert(segments[0]);
+            }
+            // `x.f()` calls the function `f` when `x` has no method `f`.
+            ExprKind::MethodCall { method, .. } => {
+                self.0.insert(*method);
+            }
+            _ => {}
+        }
+        walk_expr(self, expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::slice::backward_slice;
+    use crate::parser::parser::parse;
+
+    fn slice_lines(source: &str, variable: &str, line: usize) -> Vec<usize> {
+        let program = parse(source).unwrap();
+        backward_slice(&program, variable, line)
+            .into_iter()
+            .map(|stmt| stmt.span.start.line)
+            .collect()
+    }
+
+    #[test]
+    fn follows_data_dependencies() {
+        let source = "a = 1;\nb = 2;\nc = a + 1;\nd = b;\ne = c * 2;";
+        assert_eq!(slice_lines(source, "e", 5), vec![1, 3, 5]);
+        assert_eq!(slice_lines(source, "c", 4), vec![1, 3]);
+    }
+
+    #[test]
+    fn keeps_conditional_definitions_and_their_conditions() {
+        let source = "x = 0;\nflag = true;\nunused = 3;\nif flag { x = 1 }\ny = x;";
+        assert_eq!(slice_lines(source, "y", 5), vec![1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn stops_at_unconditional_redefinitions() {
+        let source = "x = 1;\nx = 2;\ny = x;";
+        assert_eq!(slice_lines(source, "y", 3), vec![2, 3]);
+        let source = "let x = 1;\nlet x = x + 1;\nlet y = x;";
+        assert_eq!(slice_lines(source, "y", 3), vec![1, 2, 3]);
+        let source = "a = 1;\nb = 2;\nlet (x, _) = (a, 0);\ny = x;";
+        assert_eq!(slice_lines(source, "y", 4), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn includes_called_functions() {
+        let source = "k = 10;\nfn scale(n) { n * k }\nz = scale(2);";
+        assert_eq!(slice_lines(source, "z", 3), vec![1, 2, 3]);
+    }
+}
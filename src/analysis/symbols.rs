@@ -0,0 +1,494 @@
+use crate::lexer::lexer::Lexer;
+use crate::lexer::token::{Span, TokenType};
+use crate::parser::ast::{
+    Block, Expr, ExprKind, Function, Import, ImportNames, Pattern, PatternKind, Program, Stmt,
+    StmtKind,
+};
+use crate::parser::visit::{walk_block, walk_expr, walk_pattern, walk_stmt, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Parameter,
+    Import,
+    Struct,
+    Field,
+    Enum,
+    Variant,
+    Trait,
+}
+
+// A name a program declares. `span` covers the whole declaration and
+// `name_span` just the name in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+    pub name_span: Span,
+    // What a function declares in its body, a struct's fields or an enum's
+    // variants.
+    pub children: Vec<Symbol>,
+}
+
+// The outline of a program: its imports, functions, types and `let`
+// bindings, with the ones declared in a function's body nested under it.
+pub fn symbols(program: &Program, source: &str) -> Vec<Symbol> {
+    statement_symbols(&program.statements, source)
+}
+
+fn statement_symbols(statements: &[Stmt], source: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for stmt in statements {
+        // Each name a destructuring `let` binds is a variable of its own.
+        if let StmtKind::Destructure { pattern, .. } = &stmt.kind {
+            let mut bindings = Bindings(Vec::new());
+            bindings.visit_pattern(pattern);
+            symbols.extend(bindings.0.into_iter().map(|(name, span)| Symbol {
+                name,
+                kind: SymbolKind::Variable,
+                span: stmt.span,
+                name_span: span,
+                children: Vec::new(),
+            }));
+            continue;
+        }
+        if let StmtKind::Import(import) = &stmt.kind {
+            let bindings = import_bindings(stmt, import, source);
+            symbols.extend(bindings.into_iter().map(|(name, span)| Symbol {
+                name,
+                kind: SymbolKind::Import,
+                span: stmt.span,
+                name_span: span,
+                children: Vec::new(),
+            }));
+            continue;
+        }
+        let (name, kind, name_span) = match declaration(stmt, source) {
+            Some(declaration) => declaration,
+            None => continue,
+        };
+        let mut children = Vec::new();
+        if let Some(function) = stmt_function(stmt) {
+            children.extend(function.params.iter().map(|param| Symbol {
+                name: param.name.to_string(),
+                kind: SymbolKind::Parameter,
+                span: param.span,
+                name_span: param.span,
+                children: Vec::new(),
+            }));
+            children.extend(statement_symbols(&function.body.statements, source));
+        }
+        if let StmtKind::Struct(decl) = &stmt.kind {
+            children.extend(decl.fields.iter().map(|field| Symbol {
+                name: field.name.to_string(),
+                kind: SymbolKind::Field,
+                span: field.span,
+                name_span: field.span,
+                children: Vec::new(),
+            }));
+        }
+        if let StmtKind::Enum(decl) = &stmt.kind {
+            children.extend(decl.variants.iter().map(|variant| Symbol {
+                name: variant.name.to_string(),
+                kind: SymbolKind::Variant,
+                span: variant.span,
+                name_span: first(variant.span, &variant.name),
+                children: Vec::new(),
+            }));
+        }
+        symbols.push(Symbol {
+            name,
+            kind,
+            span: stmt.span,
+            name_span,
+            children,
+        });
+    }
+    symbols
+}
+
+// The names a pattern binds and where each is written.
+struct Bindings(Vec<(String, Span)>);
+
+impl Visitor for Bindings {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Binding(name) = &pattern.kind {
+            self.0.push((name.to_string(), pattern.span));
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+// The function a statement declares, either as `fn name` or as
+// `let name = fn(...)`.
+fn stmt_function(stmt: &Stmt) -> Option<&Function> {
+    match &stmt.kind {
+        StmtKind::Function(function) => Some(function),
+        StmtKind::Let {
+            value:
+                Expr {
+                    kind: ExprKind::Function(function),
+                    ..
+                },
+            ..
+        } => Some(function),
+        _ => None,
+    }
+}
+
+// The name a statement declares, what kind of symbol it is, and where the
+// name is written.
+fn declaration(stmt: &Stmt, source: &str) -> Option<(String, SymbolKind, Span)> {
+    match &stmt.kind {
+        StmtKind::Let { name, value, .. } => {
+            let kind = match value.kind {
+                ExprKind::Function(_) => SymbolKind::Function,
+                _ => SymbolKind::Variable,
+            };
+            Some((name.to_string(), kind, name_span(stmt, name, source)))
+        }
+        StmtKind::Function(function) => {
+            let name = function.name?.to_string();
+            let span = name_span(stmt, &name, source);
+            Some((name, SymbolKind::Function, span))
+        }
+        StmtKind::Struct(decl) => {
+            let span = name_span(stmt, &decl.name, source);
+            Some((decl.name.to_string(), SymbolKind::Struct, span))
+        }
+        StmtKind::Enum(decl) => {
+            let span = name_span(stmt, &decl.name, source);
+            Some((decl.name.to_string(), SymbolKind::Enum, span))
+        }
+        StmtKind::Trait(decl) => {
+            let span = name_span(stmt, &decl.name, source);
+            Some((decl.name.to_string(), SymbolKind::Trait, span))
+        }
+        StmtKind::Expr(_)
+        | StmtKind::Import(_)
+        | StmtKind::Destructure { .. }
+        | StmtKind::Test { .. }
+        | StmtKind::Impl(_) => None,
+    }
+}
+
+// Where `name` is first written in a `let` or `fn` statement.
+fn name_span(stmt: &Stmt, name: &str, source: &str) -> Span {
+    statement_tokens(stmt, source)
+        .into_iter()
+        .find(|(kind, _)| matches!(kind, TokenType::Ident(ident) if *ident == name))
+        .map_or(stmt.span, |(_, span)| span)
+}
+
+fn statement_tokens<'a>(stmt: &Stmt, source: &'a str) -> Vec<(TokenType<'a>, Span)> {
+    let source = &source[..stmt.span.end.char];
+    Lexer::starting_at(source, stmt.span.start)
+        .map_while(Result::ok)
+        .map(|token| (token.kind, token.span))
+        .collect()
+}
+
+// The names an import binds and where each is written: the module's name
+// is the last token of the import, and a member's is the last of the member.
+fn import_bindings(stmt: &Stmt, import: &Import, source: &str) -> Vec<(String, Span)> {
+    match &import.names {
+        ImportNames::Module(_) => {
+            let span = statement_tokens(stmt, source)
+                .last()
+                .map_or(stmt.span, |(_, span)| *span);
+            import
+                .bindings()
+                .into_iter()
+                .map(|name| (name.to_string(), span))
+                .collect()
+        }
+        ImportNames::Members(members) => members
+            .iter()
+            .map(|member| {
+                let name = member.binding();
+                (name.to_string(), last(member.span, &name))
+            })
+            .collect(),
+    }
+}
+
+// Where `name` is written at the end of `span`, which is on one line.
+fn last(span: Span, name: &str) -> Span {
+    let mut last = span;
+    last.start = last.end;
+    last.start.column -= name.chars().count();
+    last.start.char -= name.len();
+    last
+}
+
+// Where `name` is written at the start of `span`.
+fn first(span: Span, name: &str) -> Span {
+    let mut first = span;
+    first.end = first.start;
+    first.end.column += name.chars().count();
+    first.end.char += name.len();
+    first
+}
+
+// Finds where the name at `line` and `column` is declared, following the
+// same scoping the interpreter does. Returns the span of the declaring name,
+// which is the name itself when the position is on a declaration.
+pub fn definition(program: &Program, source: &str, line: usize, column: usize) -> Option<Span> {
+    let mut resolver = Resolver {
+        source,
+        line,
+        column,
+        scopes: vec![Vec::new()],
+        found: None,
+    };
+    for stmt in &program.statements {
+        resolver.visit_stmt(stmt);
+    }
+    resolver.found
+}
+
+struct Resolver<'a> {
+    source: &'a str,
+    line: usize,
+    column: usize,
+    scopes: Vec<Vec<(String, Span)>>,
+    found: Option<Span>,
+}
+
+impl<'a> Resolver<'a> {
+    fn contains(&self, span: Span) -> bool {
+        let start = (span.start.line, span.start.column);
+        let end = (span.end.line, span.end.column);
+        (start..=end).contains(&(self.line, self.column))
+    }
+
+    fn declare(&mut self, name: &str, span: Span) {
+        if self.found.is_none() && self.contains(span) {
+            self.found = Some(span);
+        }
+        let scope = self.scopes.last_mut().expect("there is always a scope");
+        scope.push((name.to_string(), span));
+    }
+
+    fn resolve(&mut self, name: &str, span: Span) {
+        if self.found.is_some() || !self.contains(span) {
+            return;
+        }
+        self.found = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(declared, _)| declared == name)
+            .map(|(_, span)| *span);
+    }
+
+    fn declare_pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Binding(name) => self.declare(name, pattern.span),
+            PatternKind::Tuple(elements) => {
+                for element in elements {
+                    self.declare_pattern(element);
+                }
+            }
+            PatternKind::Struct { path, fields, .. } => {
+                self.resolve(&path[0], first(pattern.span, &path[0]));
+                for (_, field) in fields {
+                    self.declare_pattern(field);
+                }
+            }
+            PatternKind::Variant { path, fields } => {
+                self.resolve(&path[0], first(pattern.span, &path[0]));
+                for field in fields {
+                    self.declare_pattern(field);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scoped(&mut self, visit: impl FnOnce(&mut Resolver<'a>)) {
+        self.scopes.push(Vec::new());
+        visit(self);
+        self.scopes.pop();
+    }
+}
+
+impl<'a> Visitor for Resolver<'a> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            // A `let` is in scope after its value, so `let x = x + 1` reads
+            // the outer `x`.
+            StmtKind::Let { name, value, .. } => {
+                self.visit_expr(value);
+                let span = name_span(stmt, name, self.source);
+                self.declare(name, span);
+            }
+            StmtKind::Destructure { pattern, value, .. } => {
+                self.visit_expr(value);
+                self.declare_pattern(pattern);
+            }
+            // Functions are in scope in their own bodies.
+            StmtKind::Function(function) => {
+                if let Some((name, _, span)) = declaration(stmt, self.source) {
+                    self.declare(&name, span);
+                }
+                self.visit_function(function);
+            }
+            StmtKind::Import(import) => {
+                for (name, span) in import_bindings(stmt, import, self.source) {
+                    self.declare(&name, span);
+                }
+            }
+            StmtKind::Struct(_) | StmtKind::Enum(_) | StmtKind::Trait(_) => {
+                if let Some((name, _, span)) = declaration(stmt, self.source) {
+                    self.declare(&name, span);
+                }
+            }
+            StmtKind::Expr(_) | StmtKind::Test { .. } | StmtKind::Impl(_) => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.scoped(|resolver| {
+            for param in &function.params {
+                resolver.declare(&param.name, param.span);
+            }
+            walk_block(resolver, &function.body);
+        });
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name) => self.resolve(name, expr.span),
+            // Only the first segment of `module::name` is declared here.
+            ExprKind::Path(segments) => self.resolve(&segments[0], first(expr.span, &segments[0])),
+            ExprKind::Struct { path, .. } => {
+                self.resolve(&path[0], first(expr.span, &path[0]));
+                walk_expr(self, expr);
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.visit_expr(iterable);
+                self.scoped(|resolver| {
+                    resolver.declare_pattern(pattern);
+                    walk_block(resolver, body);
+                });
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.visit_expr(scrutinee);
+                for arm in arms {
+                    self.scoped(|resolver| {
+                        resolver.declare_pattern(&arm.pattern);
+                        if let Some(guard) = &arm.guard {
+                            resolver.visit_expr(guard);
+                        }
+                        resolver.visit_expr(&arm.body);
+                    });
+                }
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.scoped(|resolver| walk_block(resolver, block));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::symbols::{definition, symbols, SymbolKind};
+    use crate::parser::parser::parse;
+
+    const SOURCE: &str = "import std::math;
+let x = 1;
+fn double(n) {
+    let twice = n * 2;
+    twice
+}
+let x = double(x);
+for x in [x] { x }
+math::sqrt(x)";
+
+    #[test]
+    fn outlines_declarations() {
+        let program = parse(SOURCE).unwrap();
+        let outline: Vec<_> = symbols(&program, SOURCE)
+            .into_iter()
+            .map(|symbol| {
+                let children: Vec<_> = symbol.children.iter().map(|c| c.name.clone()).collect();
+                (
+                    symbol.name,
+                    symbol.kind,
+                    symbol.name_span.start.line,
+                    children,
+                )
+            })
+            .collect();
+        assert_eq!(
+            outline,
+            vec![
+                ("math".to_string(), SymbolKind::Import, 1, vec![]),
+                ("x".to_string(), SymbolKind::Variable, 2, vec![]),
+                (
+                    "double".to_string(),
+                    SymbolKind::Function,
+                    3,
+                    vec!["n".to_string(), "twice".to_string()]
+                ),
+                ("x".to_string(), SymbolKind::Variable, 7, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_definitions_in_scope() {
+        let program = parse(SOURCE).unwrap();
+        let find = |line, column| {
+            definition(&program, SOURCE, line, column)
+                .map(|span| (span.start.line, span.start.column))
+        };
+        // `n` in `n * 2` is the parameter.
+        assert_eq!(find(4, 16), Some((3, 10)));
+        // `x` in `double(x)` is the first `let x`, not the one being declared.
+        assert_eq!(find(7, 15), Some((2, 4)));
+        // The loop's `x` shadows the second `let x` inside the loop only.
+        assert_eq!(find(8, 10), Some((7, 4)));
+        assert_eq!(find(8, 15), Some((8, 4)));
+        assert_eq!(find(9, 1), Some((1, 12)));
+        assert_eq!(find(9, 11), Some((7, 4)));
+        // A declaration's name is its own definition.
+        assert_eq!(find(3, 4), Some((3, 3)));
+        assert_eq!(find(5, 20), None);
+    }
+
+    #[test]
+    fn finds_the_names_imports_bind() {
+        let source = "import std::path as p;\nimport { join, parent as up } from p;\nup(join)";
+        let program = parse(source).unwrap();
+        let outline: Vec<_> = symbols(&program, source)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind, symbol.name_span.start.column))
+            .collect();
+        assert_eq!(
+            outline,
+            vec![
+                ("p".to_string(), SymbolKind::Import, 20),
+                ("join".to_string(), SymbolKind::Import, 9),
+                ("up".to_string(), SymbolKind::Import, 25),
+            ]
+        );
+        let find = |line, column| {
+            definition(&program, source, line, column)
+                .map(|span| (span.start.line, span.start.column))
+        };
+        assert_eq!(find(3, 1), Some((2, 25)));
+        assert_eq!(find(3, 5), Some((2, 9)));
+    }
+}
@@ -0,0 +1,1263 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::diagnostic::{Diagnostic, Severity};
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::{
+    BinaryOp, Block, Expr, ExprKind, Function, MatchArm, Pattern, PatternKind, Program, Stmt,
+    StmtKind, UnaryOp,
+};
+use crate::typecheck::typecheck::TypeChecker;
+use crate::typecheck::types::Type;
+
+// Lowers a program to a WebAssembly module. Top-level functions are
+// exported under their own names and the rest of the program becomes the
+// exported `main` function, which returns the program's value.
+//
+// Only programs over `Int`, `Float` and `Bool` values compile: they map to
+// `i64`, `f64` and `i32`. Function signatures come from type inference, so
+// a function inference leaves generic has to be annotated. Errors the
+// interpreter would report at run time, like integer overflow, trap.
+pub fn compile(program: &Program) -> Result<Vec<u8>, Diagnostic> {
+    let mut checker = TypeChecker::new();
+    let diagnostics = checker.check(program);
+    if let Some(error) = diagnostics
+        .into_iter()
+        .find(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        return Err(error);
+    }
+
+    let mut compiler = Compiler {
+        functions: HashMap::new(),
+        globals: Vec::new(),
+        global_names: HashMap::new(),
+    };
+    let mut functions = Vec::new();
+    for stmt in &program.statements {
+        if let StmtKind::Function(function) = &stmt.kind {
+            let name = function.name.unwrap_or_default();
+            if name == "main" {
+                return Err(Diagnostic::error(
+                    "`main` is reserved for the program's top-level code in wasm builds",
+                    function.span,
+                ));
+            }
+            if compiler.functions.contains_key(&name) {
+                return Err(unsupported("redefining functions", function.span));
+            }
+            if !function.type_params.is_empty() {
+                return Err(unsupported("generic functions", function.span));
+            }
+            let index = HELPERS.len() as u32 + functions.len() as u32;
+            let signature = signature(&checker, &name, index, function.span)?;
+            compiler.functions.insert(name, signature);
+            functions.push(function.clone());
+        }
+    }
+
+    let mut main = Body::new(&mut compiler, None);
+    let mut result = Ty::Unit;
+    for (index, stmt) in program.statements.iter().enumerate() {
+        let last = index + 1 == program.statements.len();
+        result = main.statement(stmt, last)?;
+    }
+    let main = main.finish();
+
+    let mut bodies = Vec::new();
+    for function in &functions {
+        let signature = compiler.functions[&function.name.unwrap_or_default()].clone();
+        let mut body = Body::new(&mut compiler, Some(signature.result));
+        bodies.push(body.function(function, &signature)?);
+    }
+
+    let mut module = Module::default();
+    for (params, result, code) in HELPERS {
+        module
+            .functions
+            .push((params.to_vec(), result.to_vec(), code.to_vec()));
+    }
+    for (function, code) in functions.iter().zip(bodies) {
+        let name = function.name.unwrap_or_default();
+        let signature = &compiler.functions[&name];
+        let params = signature
+            .params
+            .iter()
+            .filter_map(|ty| ty.value())
+            .collect();
+        let results = signature.result.value().into_iter().collect();
+        module.exports.push((name.to_string(), signature.index));
+        module.functions.push((params, results, code));
+    }
+    module
+        .exports
+        .push(("main".to_string(), module.functions.len() as u32));
+    module
+        .functions
+        .push((Vec::new(), result.value().into_iter().collect(), main));
+    module.globals = compiler
+        .globals
+        .iter()
+        .filter_map(|ty| ty.value())
+        .collect();
+    Ok(module.encode())
+}
+
+fn unsupported(what: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!("the wasm backend does not support {} yet", what),
+        span,
+    )
+}
+
+// The type of a function as far as inference pinned it down, which has to be
+// concrete to compile.
+fn signature(
+    checker: &TypeChecker,
+    name: &str,
+    index: u32,
+    span: Span,
+) -> Result<Signature, Diagnostic> {
+    let ty = checker.binding(name);
+    let concrete = |types: &[Type]| {
+        types
+            .iter()
+            .map(|ty| Ty::from_type(ty, span))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    match &ty {
+        Some(Type::Fn(params, result)) if !has_variables(params, result) => {
+            let params = concrete(params)?;
+            if params.contains(&Ty::Unit) {
+                return Err(unsupported("`()` parameters", span));
+            }
+            Ok(Signature {
+                index,
+                params,
+                result: concrete(std::slice::from_ref(result))?.remove(0),
+            })
+        }
+        Some(ty) => Err(Diagnostic::error(
+            format!(
+                "`{}` has type `{}`, but wasm needs concrete types, consider annotating its parameters",
+                name, ty
+            ),
+            span,
+        )),
+        None => Err(Diagnostic::error(format!("unknown function `{}`", name), span)),
+    }
+}
+
+fn has_variables(params: &[Type], result: &Type) -> bool {
+    let mut variables = Vec::new();
+    for ty in params.iter().chain(std::iter::once(result)) {
+        ty.variables(&mut variables);
+    }
+    !variables.is_empty()
+}
+
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+const F64: u8 = 0x7C;
+// The block type of blocks that leave nothing on the stack.
+const EMPTY: u8 = 0x40;
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0B;
+const BR: u8 = 0x0C;
+const BR_IF: u8 = 0x0D;
+const RETURN: u8 = 0x0F;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1A;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F64_CONST: u8 = 0x44;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I64_EQ: u8 = 0x51;
+const I64_NE: u8 = 0x52;
+const I64_LT_S: u8 = 0x53;
+const I64_GT_S: u8 = 0x55;
+const I64_LE_S: u8 = 0x57;
+const I64_GE_S: u8 = 0x59;
+const F64_EQ: u8 = 0x61;
+const F64_NE: u8 = 0x62;
+const F64_LT: u8 = 0x63;
+const F64_GT: u8 = 0x64;
+const F64_LE: u8 = 0x65;
+const F64_GE: u8 = 0x66;
+const I64_DIV_S: u8 = 0x7F;
+const I64_REM_S: u8 = 0x81;
+const I64_AND: u8 = 0x83;
+const I64_OR: u8 = 0x84;
+const I64_XOR: u8 = 0x85;
+const F64_NEG: u8 = 0x9A;
+const F64_ADD: u8 = 0xA0;
+const F64_SUB: u8 = 0xA1;
+const F64_MUL: u8 = 0xA2;
+const F64_DIV: u8 = 0xA3;
+const F64_CONVERT_I64_S: u8 = 0xB9;
+
+// Functions every module starts with, as their parameters, results and
+// code. Integer arithmetic goes through the first three so that overflow
+// traps instead of wrapping, and wasm has no float remainder. Shifts trap
+// on amounts the interpreter rejects, where wasm would take them modulo 64.
+const HELPERS: [(&[u8], &[u8], &[u8]); 6] = [
+    // a + b, which overflowed if the result's sign differs from both
+    // operands'.
+    (
+        &[I64, I64],
+        &[I64],
+        &[
+            0x01, 0x01, I64, 0x20, 0x00, 0x20, 0x01, 0x7C, 0x22, 0x02, 0x20, 0x00, 0x85, 0x20,
+            0x02, 0x20, 0x01, 0x85, 0x83, 0x42, 0x00, 0x53, 0x04, 0x40, 0x00, 0x0B, 0x20, 0x02,
+            0x0B,
+        ],
+    ),
+    // a - b, which overflowed if the operands' signs differ and the result's
+    // sign differs from a's.
+    (
+        &[I64, I64],
+        &[I64],
+        &[
+            0x01, 0x01, I64, 0x20, 0x00, 0x20, 0x01, 0x7D, 0x21, 0x02, 0x20, 0x00, 0x20, 0x01,
+            0x85, 0x20, 0x00, 0x20, 0x02, 0x85, 0x83, 0x42, 0x00, 0x53, 0x04, 0x40, 0x00, 0x0B,
+            0x20, 0x02, 0x0B,
+        ],
+    ),
+    // a * b, which overflowed if dividing the result by a doesn't give b
+    // back. The division itself traps for `-1 * MIN`.
+    (
+        &[I64, I64],
+        &[I64],
+        &[
+            0x01, 0x01, I64, 0x20, 0x00, 0x20, 0x01, 0x7E, 0x21, 0x02, 0x20, 0x00, 0x50, 0x45,
+            0x04, 0x40, 0x20, 0x02, 0x20, 0x00, 0x7F, 0x20, 0x01, 0x52, 0x04, 0x40, 0x00, 0x0B,
+            0x0B, 0x20, 0x02, 0x0B,
+        ],
+    ),
+    // a - trunc(a / b) * b
+    (
+        &[F64, F64],
+        &[F64],
+        &[
+            0x00, 0x20, 0x00, 0x20, 0x00, 0x20, 0x01, 0xA3, 0x9D, 0x20, 0x01, 0xA2, 0xA1, 0x0B,
+        ],
+    ),
+    // a << b, trapping unless b is below 64. Compared unsigned, negative
+    // amounts are too.
+    (
+        &[I64, I64],
+        &[I64],
+        &[
+            0x00, 0x20, 0x01, 0x42, 0xC0, 0x00, 0x5A, 0x04, 0x40, 0x00, 0x0B, 0x20, 0x00, 0x20,
+            0x01, 0x86, 0x0B,
+        ],
+    ),
+    // a >> b, keeping the sign, and likewise.
+    (
+        &[I64, I64],
+        &[I64],
+        &[
+            0x00, 0x20, 0x01, 0x42, 0xC0, 0x00, 0x5A, 0x04, 0x40, 0x00, 0x0B, 0x20, 0x00, 0x20,
+            0x01, 0x87, 0x0B,
+        ],
+    ),
+];
+const ADD: u32 = 0;
+const SUBTRACT: u32 = 1;
+const MULTIPLY: u32 = 2;
+const REMAINDER: u32 = 3;
+const SHIFT_LEFT: u32 = 4;
+const SHIFT_RIGHT: u32 = 5;
+
+// The types values can have in compiled code. `Never` is the type of
+// expressions that jump away, like `return`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Int,
+    Float,
+    Bool,
+    Unit,
+    Never,
+}
+
+impl Ty {
+    fn from_type(ty: &Type, span: Span) -> Result<Ty, Diagnostic> {
+        match ty {
+            Type::Int => Ok(Ty::Int),
+            Type::Float => Ok(Ty::Float),
+            Type::Bool => Ok(Ty::Bool),
+            Type::Unit => Ok(Ty::Unit),
+            other => Err(unsupported(&format!("`{}` values", other), span)),
+        }
+    }
+
+    // The wasm value type, for types whose values take up a stack slot.
+    fn value(self) -> Option<u8> {
+        match self {
+            Ty::Int => Some(I64),
+            Ty::Float => Some(F64),
+            Ty::Bool => Some(I32),
+            Ty::Unit | Ty::Never => None,
+        }
+    }
+
+    fn block(self) -> u8 {
+        self.value().unwrap_or(EMPTY)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Int => "Int",
+            Ty::Float => "Float",
+            Ty::Bool => "Bool",
+            Ty::Unit => "()",
+            Ty::Never => "!",
+        }
+    }
+}
+
+// The type of an expression with two branches: whichever doesn't jump away.
+fn join(left: Ty, right: Ty, span: Span) -> Result<Ty, Diagnostic> {
+    match (left, right) {
+        (Ty::Never, ty) | (ty, Ty::Never) => Ok(ty),
+        (left, right) if left == right => Ok(left),
+        (left, right) => Err(Diagnostic::error(
+            format!(
+                "branches have different types, `{}` and `{}`",
+                left.name(),
+                right.name()
+            ),
+            span,
+        )),
+    }
+}
+
+#[derive(Clone)]
+struct Signature {
+    index: u32,
+    params: Vec<Ty>,
+    result: Ty,
+}
+
+#[derive(Clone, Copy)]
+struct Variable {
+    // Unit values take up no local or global.
+    index: Option<u32>,
+    ty: Ty,
+    mutable: bool,
+    global: bool,
+}
+
+struct Compiler {
+    functions: HashMap<Symbol, Signature>,
+    globals: Vec<Ty>,
+    // Top-level bindings, which functions can see.
+    global_names: HashMap<Symbol, Variable>,
+}
+
+// The code of one function. `depth` counts the blocks enclosing the current
+// instruction, which branch instructions are relative to.
+struct Body<'a> {
+    compiler: &'a mut Compiler,
+    code: Vec<u8>,
+    locals: Vec<u8>,
+    params: usize,
+    scopes: Vec<HashMap<Symbol, Variable>>,
+    depth: u32,
+    // The depth of the block each enclosing loop breaks out of; its loop
+    // label is one deeper.
+    loops: Vec<u32>,
+    // What the function returns, or `None` for the top level.
+    result: Option<Ty>,
+}
+
+impl<'a> Body<'a> {
+    fn new(compiler: &'a mut Compiler, result: Option<Ty>) -> Body<'a> {
+        Body {
+            compiler,
+            code: Vec::new(),
+            locals: Vec::new(),
+            params: 0,
+            scopes: Vec::new(),
+            depth: 0,
+            loops: Vec::new(),
+            result,
+        }
+    }
+
+    fn function(
+        &mut self,
+        function: &Function,
+        signature: &Signature,
+    ) -> Result<Vec<u8>, Diagnostic> {
+        let mut scope = HashMap::new();
+        for (param, ty) in function.params.iter().zip(&signature.params) {
+            let index = self.local(*ty);
+            let variable = Variable {
+                index,
+                ty: *ty,
+                mutable: false,
+                global: false,
+            };
+            scope.insert(param.name, variable);
+        }
+        self.params = self.locals.len();
+        self.scopes.push(scope);
+
+        let ty = self.block(&function.body)?;
+        if ty != signature.result && ty != Ty::Never {
+            return Err(Diagnostic::error(
+                format!(
+                    "expected `{}` to return `{}`, found `{}`",
+                    function.name.as_deref().unwrap_or_default(),
+                    signature.result.name(),
+                    ty.name()
+                ),
+                function.span,
+            ));
+        }
+        Ok(self.finish())
+    }
+
+    // The function's code section entry: its locals, then its code.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut entry = Vec::new();
+        let locals = &self.locals[self.params..];
+        uleb(&mut entry, locals.len() as u64);
+        for ty in locals {
+            entry.extend([0x01, *ty]);
+        }
+        entry.append(&mut self.code);
+        entry.push(END);
+        entry
+    }
+
+    fn top_level(&self) -> bool {
+        self.result.is_none() && self.scopes.is_empty()
+    }
+
+    fn local(&mut self, ty: Ty) -> Option<u32> {
+        let value = ty.value()?;
+        self.locals.push(value);
+        Some(self.locals.len() as u32 - 1)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_index(&mut self, op: u8, index: u32) {
+        self.code.push(op);
+        uleb(&mut self.code, index as u64);
+    }
+
+    fn drop_value(&mut self, ty: Ty) {
+        if ty.value().is_some() {
+            self.code.push(DROP);
+        }
+    }
+
+    // Compiles into a buffer of its own, for code that has to be preceded by
+    // a block type or a conversion that depends on its type.
+    fn capture(
+        &mut self,
+        compile: impl FnOnce(&mut Self) -> Result<Ty, Diagnostic>,
+    ) -> Result<(Vec<u8>, Ty), Diagnostic> {
+        let outer = std::mem::take(&mut self.code);
+        let result = compile(self);
+        let code = std::mem::replace(&mut self.code, outer);
+        Ok((code, result?))
+    }
+
+    fn resolve(&self, name: Symbol) -> Option<Variable> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .or_else(|| self.compiler.global_names.get(&name))
+            .copied()
+    }
+
+    // Declares a variable holding the value on top of the stack.
+    fn declare(&mut self, name: Symbol, ty: Ty, mutable: bool) {
+        let global = self.top_level();
+        let index = if global {
+            ty.value().map(|_| {
+                self.compiler.globals.push(ty);
+                self.compiler.globals.len() as u32 - 1
+            })
+        } else {
+            self.local(ty)
+        };
+        let variable = Variable {
+            index,
+            ty,
+            mutable,
+            global,
+        };
+        if let Some(index) = index {
+            self.emit_index(if global { GLOBAL_SET } else { LOCAL_SET }, index);
+        }
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name, variable),
+            None => self.compiler.global_names.insert(name, variable),
+        };
+    }
+
+    // Returns the statement's type, which is unit unless `keep` asks for the
+    // value of an expression statement or the statement jumps away.
+    fn statement(&mut self, stmt: &Stmt, keep: bool) -> Result<Ty, Diagnostic> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                let ty = self.expression(expr)?;
+                if keep || ty == Ty::Never {
+                    return Ok(ty);
+                }
+                self.drop_value(ty);
+                Ok(Ty::Unit)
+            }
+            // Top-level functions are compiled on their own.
+            StmtKind::Function(_) if self.top_level() => Ok(Ty::Unit),
+            StmtKind::Function(function) => Err(unsupported("nested functions", function.span)),
+            StmtKind::Import(_) => Err(unsupported("imports", stmt.span)),
+            StmtKind::Struct(_) => Err(unsupported("structs", stmt.span)),
+            StmtKind::Enum(_) => Err(unsupported("enums", stmt.span)),
+            StmtKind::Trait(_) | StmtKind::Impl(_) => Err(unsupported("traits", stmt.span)),
+            StmtKind::Destructure { .. } => Err(unsupported("destructuring", stmt.span)),
+            // Tests aren't part of the compiled program.
+            StmtKind::Test { .. } => Ok(Ty::Unit),
+            StmtKind::Let {
+                name,
+                mutable,
+                value,
+                ..
+            } => {
+                let ty = self.expression(value)?;
+                if ty == Ty::Never {
+                    return Ok(ty);
+                }
+                self.declare(*name, ty, *mutable);
+                Ok(Ty::Unit)
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) -> Result<Ty, Diagnostic> {
+        self.scopes.push(HashMap::new());
+        let mut diverges = false;
+        for stmt in &block.statements {
+            diverges |= self.statement(stmt, false)? == Ty::Never;
+        }
+        let ty = match &block.value {
+            Some(value) => self.expression(value)?,
+            None if diverges => Ty::Never,
+            None => Ty::Unit,
+        };
+        self.scopes.pop();
+        Ok(ty)
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<Ty, Diagnostic> {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Integer(n) => {
+                self.code.push(I64_CONST);
+                sleb(&mut self.code, *n);
+                Ok(Ty::Int)
+            }
+            ExprKind::Float(n) => {
+                self.code.push(F64_CONST);
+                self.emit(&n.to_le_bytes());
+                Ok(Ty::Float)
+            }
+            ExprKind::Bool(b) => {
+                self.emit(&[I32_CONST, *b as u8]);
+                Ok(Ty::Bool)
+            }
+            ExprKind::Ident(name) => match self.resolve(*name) {
+                Some(variable) => {
+                    if let Some(index) = variable.index {
+                        let op = if variable.global {
+                            GLOBAL_GET
+                        } else {
+                            LOCAL_GET
+                        };
+                        self.emit_index(op, index);
+                    }
+                    Ok(variable.ty)
+                }
+                None if self.compiler.functions.contains_key(name) => {
+                    Err(unsupported("function values", span))
+                }
+                None => Err(Diagnostic::error(
+                    format!("unknown variable `{}`", name),
+                    span,
+                )),
+            },
+            ExprKind::Tuple(elements) if elements.is_empty() => Ok(Ty::Unit),
+            ExprKind::String(_) => Err(unsupported("strings", span)),
+            ExprKind::Path(_) => Err(unsupported("module paths", span)),
+            ExprKind::Tuple(_) => Err(unsupported("tuples", span)),
+            ExprKind::List(_) => Err(unsupported("lists", span)),
+            ExprKind::Map(_) => Err(unsupported("maps", span)),
+            ExprKind::Struct { .. } | ExprKind::Field { .. } => Err(unsupported("structs", span)),
+            ExprKind::Function(_) => Err(unsupported("closures", span)),
+            ExprKind::MethodCall { .. } => Err(unsupported("method calls", span)),
+            ExprKind::Index { .. } => Err(unsupported("indexing", span)),
+            ExprKind::Slice { .. } => Err(unsupported("slices", span)),
+            ExprKind::Range { .. } => Err(unsupported("ranges", span)),
+            ExprKind::For { .. } => Err(unsupported("`for` loops", span)),
+            ExprKind::Unary { op, operand } => {
+                let (code, ty) = self.capture(|body| body.expression(operand))?;
+                match (op, ty) {
+                    (UnaryOp::Negate, Ty::Int) => {
+                        self.emit(&[I64_CONST, 0x00]);
+                        self.emit(&code);
+                        self.emit_index(CALL, SUBTRACT);
+                    }
+                    (UnaryOp::Negate, Ty::Float) => {
+                        self.emit(&code);
+                        self.code.push(F64_NEG);
+                    }
+                    (UnaryOp::Not, Ty::Bool) => {
+                        self.emit(&code);
+                        self.code.push(I32_EQZ);
+                    }
+                    (UnaryOp::BitNot, Ty::Int) => {
+                        self.emit(&code);
+                        self.emit(&[I64_CONST, 0x7F]);
+                        self.code.push(I64_XOR);
+                    }
+                    (_, Ty::Never) => self.emit(&code),
+                    (op, ty) => {
+                        return Err(Diagnostic::error(
+                            format!("cannot apply `{}` to `{}`", op, ty.name()),
+                            span,
+                        ))
+                    }
+                }
+                Ok(ty)
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                left,
+                right,
+            } => {
+                let left = self.expression(left)?;
+                self.depth += 1;
+                let right = self.capture(|body| body.expression(right));
+                self.depth -= 1;
+                let (right, _) = right?;
+                self.emit(&[IF, I32]);
+                if *op == BinaryOp::And {
+                    self.emit(&right);
+                    self.emit(&[ELSE, I32_CONST, 0x00, END]);
+                } else {
+                    self.emit(&[I32_CONST, 0x01, ELSE]);
+                    self.emit(&right);
+                    self.code.push(END);
+                }
+                Ok(if left == Ty::Never {
+                    Ty::Never
+                } else {
+                    Ty::Bool
+                })
+            }
+            ExprKind::Binary { op, left, right } => {
+                let left = self.capture(|body| body.expression(left))?;
+                let right = self.capture(|body| body.expression(right))?;
+                self.binary(*op, left, right, span)
+            }
+            ExprKind::Assign { target, value } => {
+                let name = match &target.kind {
+                    ExprKind::Ident(name) => name,
+                    _ => return Err(Diagnostic::error("invalid assignment target", target.span)),
+                };
+                let ty = self.expression(value)?;
+                let variable = match self.resolve(*name) {
+                    Some(variable) => variable,
+                    // Assigning to an unbound name defines it.
+                    None if ty == Ty::Never => return Ok(ty),
+                    None => {
+                        self.declare(*name, ty, true);
+                        let variable = self.resolve(*name).expect("declared above");
+                        if let Some(index) = variable.index {
+                            let op = if variable.global {
+                                GLOBAL_GET
+                            } else {
+                                LOCAL_GET
+                            };
+                            self.emit_index(op, index);
+                        }
+                        return Ok(ty);
+                    }
+                };
+                if !variable.mutable {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "cannot assign to immutable variable `{}`, consider declaring it with `let mut`",
+                            name
+                        ),
+                        target.span,
+                    ));
+                }
+                if ty != variable.ty && ty != Ty::Never {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "cannot assign `{}` to `{}`, which holds `{}`",
+                            ty.name(),
+                            name,
+                            variable.ty.name()
+                        ),
+                        value.span,
+                    ));
+                }
+                match variable.index {
+                    Some(index) if variable.global => {
+                        self.emit_index(GLOBAL_SET, index);
+                        self.emit_index(GLOBAL_GET, index);
+                    }
+                    Some(index) => self.emit_index(LOCAL_TEE, index),
+                    None => {}
+                }
+                Ok(ty)
+            }
+            ExprKind::Match { scrutinee, arms } => self.match_expression(scrutinee, arms),
+            ExprKind::Block(block) => self.block(block),
+            ExprKind::Call { callee, args } => {
+                let name = match &callee.kind {
+                    ExprKind::Ident(name) if self.resolve(*name).is_none() => name,
+                    _ => return Err(unsupported("function values", callee.span)),
+                };
+                let signature = match self.compiler.functions.get(name) {
+                    Some(signature) => signature.clone(),
+                    None => {
+                        return Err(Diagnostic::error(
+                            format!("unknown variable `{}`", name),
+                            callee.span,
+                        ))
+                    }
+                };
+                if args.len() != signature.params.len() {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "`{}` expects {} argument{}, found {}",
+                            name,
+                            signature.params.len(),
+                            if signature.params.len() == 1 { "" } else { "s" },
+                            args.len()
+                        ),
+                        span,
+                    ));
+                }
+                for (arg, param) in args.iter().zip(&signature.params) {
+                    let ty = self.expression(arg)?;
+                    if ty != *param && ty != Ty::Never {
+                        return Err(Diagnostic::error(
+                            format!("expected `{}`, found `{}`", param.name(), ty.name()),
+                            arg.span,
+                        ));
+                    }
+                }
+                self.emit_index(CALL, signature.index);
+                Ok(signature.result)
+            }
+            ExprKind::Return(value) => {
+                let expected = match self.result {
+                    Some(result) => result,
+                    None => return Err(Diagnostic::error("`return` outside of a function", span)),
+                };
+                let ty = match value {
+                    Some(value) => self.expression(value)?,
+                    None => Ty::Unit,
+                };
+                if ty != expected && ty != Ty::Never {
+                    return Err(Diagnostic::error(
+                        format!("expected `{}`, found `{}`", expected.name(), ty.name()),
+                        span,
+                    ));
+                }
+                self.code.push(RETURN);
+                Ok(Ty::Never)
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(condition)?;
+                self.depth += 1;
+                let then_branch = self.capture(|body| body.block(then_branch));
+                let else_branch = else_branch
+                    .as_ref()
+                    .map(|branch| self.capture(|body| body.expression(branch)));
+                self.depth -= 1;
+
+                let (then_code, then_ty) = then_branch?;
+                match else_branch.transpose()? {
+                    Some((else_code, else_ty)) => {
+                        let ty = join(then_ty, else_ty, span)?;
+                        self.emit(&[IF, ty.block()]);
+                        self.emit(&then_code);
+                        self.code.push(ELSE);
+                        self.emit(&else_code);
+                        self.code.push(END);
+                        if ty == Ty::Never {
+                            self.code.push(UNREACHABLE);
+                        }
+                        Ok(ty)
+                    }
+                    // Without an `else`, the `if` is unit whatever its
+                    // branch evaluates to.
+                    None => {
+                        self.emit(&[IF, EMPTY]);
+                        self.emit(&then_code);
+                        self.drop_value(then_ty);
+                        self.code.push(END);
+                        Ok(Ty::Unit)
+                    }
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.emit(&[BLOCK, EMPTY]);
+                self.depth += 1;
+                let exit = self.depth;
+                self.emit(&[LOOP, EMPTY]);
+                self.depth += 1;
+
+                self.condition(condition)?;
+                self.code.push(I32_EQZ);
+                self.emit_index(BR_IF, self.depth - exit);
+                self.loops.push(exit);
+                let ty = self.block(body);
+                self.loops.pop();
+                self.drop_value(ty?);
+                self.emit_index(BR, 0);
+
+                self.emit(&[END, END]);
+                self.depth -= 2;
+                Ok(Ty::Unit)
+            }
+            ExprKind::Break | ExprKind::Continue => {
+                let exit = match self.loops.last() {
+                    Some(exit) => *exit,
+                    None => {
+                        let keyword = if matches!(expr.kind, ExprKind::Break) {
+                            "break"
+                        } else {
+                            "continue"
+                        };
+                        return Err(Diagnostic::error(
+                            format!("`{}` outside of a loop", keyword),
+                            span,
+                        ));
+                    }
+                };
+                let target = if matches!(expr.kind, ExprKind::Break) {
+                    exit
+                } else {
+                    exit + 1
+                };
+                self.emit_index(BR, self.depth - target);
+                Ok(Ty::Never)
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr) -> Result<(), Diagnostic> {
+        match self.expression(condition)? {
+            Ty::Bool | Ty::Never => Ok(()),
+            other => Err(Diagnostic::error(
+                format!("expected `Bool`, found `{}`", other.name()),
+                condition.span,
+            )),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        (left, left_ty): (Vec<u8>, Ty),
+        (right, right_ty): (Vec<u8>, Ty),
+        span: Span,
+    ) -> Result<Ty, Diagnostic> {
+        use BinaryOp::*;
+
+        let comparison = matches!(
+            op,
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual
+        );
+        match (left_ty, right_ty) {
+            (Ty::Never, _) | (_, Ty::Never) => {
+                self.emit(&left);
+                self.emit(&right);
+                self.code.push(UNREACHABLE);
+                Ok(Ty::Never)
+            }
+            (Ty::Int, Ty::Int) => {
+                self.emit(&left);
+                self.emit(&right);
+                match op {
+                    Add => self.emit_index(CALL, ADD),
+                    Subtract => self.emit_index(CALL, SUBTRACT),
+                    Multiply => self.emit_index(CALL, MULTIPLY),
+                    Divide => self.code.push(I64_DIV_S),
+                    Remainder => self.code.push(I64_REM_S),
+                    Equal => self.code.push(I64_EQ),
+                    NotEqual => self.code.push(I64_NE),
+                    Less => self.code.push(I64_LT_S),
+                    LessEqual => self.code.push(I64_LE_S),
+                    Greater => self.code.push(I64_GT_S),
+                    GreaterEqual => self.code.push(I64_GE_S),
+                    BitAnd => self.code.push(I64_AND),
+                    BitOr => self.code.push(I64_OR),
+                    BitXor => self.code.push(I64_XOR),
+                    ShiftLeft => self.emit_index(CALL, SHIFT_LEFT),
+                    ShiftRight => self.emit_index(CALL, SHIFT_RIGHT),
+                    And | Or => unreachable!("compiled with short circuiting"),
+                }
+                Ok(if comparison { Ty::Bool } else { Ty::Int })
+            }
+            // Mixing integers and floats works on floats.
+            (Ty::Int | Ty::Float, Ty::Int | Ty::Float) if !op.is_bitwise() => {
+                for (code, ty) in [(left, left_ty), (right, right_ty)] {
+                    self.emit(&code);
+                    if ty == Ty::Int {
+                        self.code.push(F64_CONVERT_I64_S);
+                    }
+                }
+                match op {
+                    Add => self.code.push(F64_ADD),
+                    Subtract => self.code.push(F64_SUB),
+                    Multiply => self.code.push(F64_MUL),
+                    Divide => self.code.push(F64_DIV),
+                    Remainder => self.emit_index(CALL, REMAINDER),
+                    Equal => self.code.push(F64_EQ),
+                    NotEqual => self.code.push(F64_NE),
+                    Less => self.code.push(F64_LT),
+                    LessEqual => self.code.push(F64_LE),
+                    Greater => self.code.push(F64_GT),
+                    GreaterEqual => self.code.push(F64_GE),
+                    BitAnd | BitOr | BitXor | ShiftLeft | ShiftRight => {
+                        unreachable!("only integers have bits")
+                    }
+                    And | Or => unreachable!("compiled with short circuiting"),
+                }
+                Ok(if comparison { Ty::Bool } else { Ty::Float })
+            }
+            (Ty::Bool, Ty::Bool) if matches!(op, Equal | NotEqual) => {
+                self.emit(&left);
+                self.emit(&right);
+                self.code.push(if op == Equal { I32_EQ } else { I32_NE });
+                Ok(Ty::Bool)
+            }
+            (Ty::Unit, Ty::Unit) if matches!(op, Equal | NotEqual) => {
+                self.emit(&left);
+                self.emit(&right);
+                self.emit(&[I32_CONST, (op == Equal) as u8]);
+                Ok(Ty::Bool)
+            }
+            (left, right) => Err(Diagnostic::error(
+                format!(
+                    "cannot apply `{}` to `{}` and `{}`",
+                    op,
+                    left.name(),
+                    right.name()
+                ),
+                span,
+            )),
+        }
+    }
+
+    // Each arm is a block that the pattern or guard breaks out of when the
+    // arm doesn't apply, so control falls through to the next arm, inside a
+    // block that matching arms break out of with their value.
+    fn match_expression(&mut self, scrutinee: &Expr, arms: &[MatchArm]) -> Result<Ty, Diagnostic> {
+        let ty = self.expression(scrutinee)?;
+        let slot = self.local(ty);
+        if let Some(slot) = slot {
+            self.emit_index(LOCAL_SET, slot);
+        }
+
+        self.depth += 1;
+        let arms = self.capture(|body| {
+            let mut result = Ty::Never;
+            for arm in arms {
+                body.emit(&[BLOCK, EMPTY]);
+                body.depth += 1;
+                body.scopes.push(HashMap::new());
+                body.pattern(&arm.pattern, ty, slot)?;
+                if let Some(guard) = &arm.guard {
+                    body.condition(guard)?;
+                    body.emit(&[I32_EQZ, BR_IF, 0x00]);
+                }
+                let found = body.expression(&arm.body)?;
+                result = join(result, found, arm.body.span)?;
+                body.emit(&[BR, 0x01]);
+                body.scopes.pop();
+                body.depth -= 1;
+                body.code.push(END);
+            }
+            // No arm matched.
+            body.code.push(UNREACHABLE);
+            Ok(result)
+        });
+        self.depth -= 1;
+        let (code, result) = arms?;
+
+        self.emit(&[BLOCK, result.block()]);
+        self.emit(&code);
+        self.code.push(END);
+        if result == Ty::Never {
+            self.code.push(UNREACHABLE);
+        }
+        Ok(result)
+    }
+
+    // Breaks out of the arm's block unless the scrutinee in `slot` matches.
+    fn pattern(&mut self, pattern: &Pattern, ty: Ty, slot: Option<u32>) -> Result<(), Diagnostic> {
+        let literal = |body: &mut Self, bytes: &[u8], expected: Ty, ne: u8| match slot {
+            Some(slot) if ty == expected => {
+                body.emit_index(LOCAL_GET, slot);
+                body.emit(bytes);
+                body.emit(&[ne, BR_IF, 0x00]);
+                Ok(())
+            }
+            _ => Err(Diagnostic::error(
+                format!(
+                    "expected a `{}` pattern, found `{}`",
+                    ty.name(),
+                    expected.name()
+                ),
+                pattern.span,
+            )),
+        };
+        match &pattern.kind {
+            PatternKind::Wildcard => Ok(()),
+            PatternKind::Binding(name) => {
+                if let Some(slot) = slot {
+                    self.emit_index(LOCAL_GET, slot);
+                }
+                self.declare(*name, ty, false);
+                Ok(())
+            }
+            PatternKind::Integer(n) => {
+                let mut bytes = vec![I64_CONST];
+                sleb(&mut bytes, *n);
+                literal(self, &bytes, Ty::Int, I64_NE)
+            }
+            PatternKind::Float(n) => {
+                let mut bytes = vec![F64_CONST];
+                bytes.extend(n.to_le_bytes());
+                literal(self, &bytes, Ty::Float, F64_NE)
+            }
+            PatternKind::Bool(b) => literal(self, &[I32_CONST, *b as u8], Ty::Bool, I32_NE),
+            PatternKind::String(_) => Err(unsupported("strings", pattern.span)),
+            PatternKind::Tuple(_) => Err(unsupported("tuples", pattern.span)),
+            PatternKind::Struct { .. } => Err(unsupported("structs", pattern.span)),
+            PatternKind::Variant { .. } => Err(unsupported("enums", pattern.span)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Module {
+    // Each function's parameters, results and code section entry.
+    functions: Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>,
+    globals: Vec<u8>,
+    exports: Vec<(String, u32)>,
+}
+
+impl Module {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = b"\0asm".to_vec();
+        out.extend([0x01, 0x00, 0x00, 0x00]);
+
+        // Every function gets a type of its own.
+        let mut types = Vec::new();
+        uleb(&mut types, self.functions.len() as u64);
+        for (params, results, _) in &self.functions {
+            types.push(0x60);
+            vector(&mut types, params);
+            vector(&mut types, results);
+        }
+        section(&mut out, 1, &types);
+
+        let mut functions = Vec::new();
+        uleb(&mut functions, self.functions.len() as u64);
+        for index in 0..self.functions.len() {
+            uleb(&mut functions, index as u64);
+        }
+        section(&mut out, 3, &functions);
+
+        // Globals are mutable and start out as zero; the top-level code
+        // assigns them.
+        let mut globals = Vec::new();
+        uleb(&mut globals, self.globals.len() as u64);
+        for ty in &self.globals {
+            globals.extend([*ty, 0x01]);
+            match *ty {
+                F64 => {
+                    globals.push(F64_CONST);
+                    globals.extend(0f64.to_le_bytes());
+                }
+                I64 => globals.extend([I64_CONST, 0x00]),
+                _ => globals.extend([I32_CONST, 0x00]),
+            }
+            globals.push(END);
+        }
+        section(&mut out, 6, &globals);
+
+        let mut exports = Vec::new();
+        uleb(&mut exports, self.exports.len() as u64);
+        for (name, index) in &self.exports {
+            vector(&mut exports, name.as_bytes());
+            exports.push(0x00);
+            uleb(&mut exports, *index as u64);
+        }
+        section(&mut out, 7, &exports);
+
+        let mut code = Vec::new();
+        uleb(&mut code, self.functions.len() as u64);
+        for (_, _, body) in &self.functions {
+            vector(&mut code, body);
+        }
+        section(&mut out, 10, &code);
+        out
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    vector(out, contents);
+}
+
+fn vector(out: &mut Vec<u8>, bytes: &[u8]) {
+    uleb(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::wasm::{compile, sleb, uleb};
+    use crate::parser::parser::parse;
+
+    // The names a module exports, read back from its export section.
+    fn exports(module: &[u8]) -> Vec<String> {
+        let mut position = 8;
+        while position < module.len() {
+            let (id, size) = (module[position], module[position + 1] as usize);
+            let contents = &module[position + 2..position + 2 + size];
+            if id == 7 {
+                let mut names = Vec::new();
+                let mut at = 1;
+                for _ in 0..contents[0] {
+                    let length = contents[at] as usize;
+                    names.push(
+                        String::from_utf8(contents[at + 1..at + 1 + length].to_vec()).unwrap(),
+                    );
+                    at += length + 3;
+                }
+                return names;
+            }
+            position += 2 + size;
+        }
+        Vec::new()
+    }
+
+    fn error(source: &str) -> String {
+        compile(&parse(source).unwrap()).unwrap_err().message
+    }
+
+    #[test]
+    fn encodes_leb128() {
+        let encode = |value: i64| {
+            let (mut unsigned, mut signed) = (Vec::new(), Vec::new());
+            uleb(&mut unsigned, value as u64);
+            sleb(&mut signed, value);
+            (unsigned, signed)
+        };
+        assert_eq!(encode(0), (vec![0x00], vec![0x00]));
+        assert_eq!(encode(64), (vec![0x40], vec![0xC0, 0x00]));
+        assert_eq!(
+            encode(624485),
+            (vec![0xE5, 0x8E, 0x26], vec![0xE5, 0x8E, 0x26])
+        );
+        let mut signed = Vec::new();
+        sleb(&mut signed, -123456);
+        assert_eq!(signed, vec![0xC0, 0xBB, 0x78]);
+    }
+
+    #[test]
+    fn exports_functions_and_the_top_level() {
+        let source = "
+            fn fib(n: Int) -> Int { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+            fn half(x: Float) -> Float { x / 2 }
+            let mut i = 0;
+            while i < 10 { i = i + 1; if i == 5 { break; } }
+            match fib(10) { 55 => half(1.0) > 0.25, _ => false }
+        ";
+        let module = compile(&parse(source).unwrap()).unwrap();
+        assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+        assert_eq!(exports(&module), vec!["fib", "half", "main"]);
+    }
+
+    #[test]
+    fn reports_what_it_cannot_compile() {
+        assert_eq!(
+            error("fn id(x) { x } id(1)"),
+            "`id` has type `Fn('a) -> 'a`, but wasm needs concrete types, consider annotating its parameters"
+        );
+        assert_eq!(
+            error("let s = \"clay\";"),
+            "the wasm backend does not support strings yet"
+        );
+        assert_eq!(
+            error("fn f(xs: List<Int>) -> Int { 1 }"),
+            "the wasm backend does not support `List<Int>` values yet"
+        );
+        assert_eq!(error("return 1"), "`return` outside of a function");
+        assert_eq!(
+            error("let x = 1; x = 2;"),
+            "cannot assign to immutable variable `x`, consider declaring it with `let mut`"
+        );
+        assert_eq!(error("1 + true"), "cannot apply `+` to `Int` and `Bool`");
+    }
+}
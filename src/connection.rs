@@ -0,0 +1,114 @@
+use std::io::{self, BufRead, Read, Write};
+
+use serde_json::Value as Json;
+
+// The longest message read. Clients send documents whole, but nothing near
+// this, and a longer `Content-Length` is skipped rather than allocated.
+const MAX_LENGTH: usize = 64 << 20;
+
+// JSON messages framed by a `Content-Length` header, the way both the
+// Language Server and the Debug Adapter Protocol send them.
+pub struct Connection {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+}
+
+impl Connection {
+    pub fn new(reader: Box<dyn BufRead>, writer: Box<dyn Write>) -> Connection {
+        Connection { reader, writer }
+    }
+
+    pub fn stdio() -> Connection {
+        Connection::new(Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+
+    // Reads one message, or `None` once the client has gone away. A message
+    // that is too long or isn't JSON is an error, and the next one can still
+    // be read.
+    pub fn read(&mut self) -> Option<Result<Json, String>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let length = length?;
+        if length > MAX_LENGTH {
+            let skipped = io::copy(
+                &mut self.reader.by_ref().take(length as u64),
+                &mut io::sink(),
+            );
+            if skipped.ok()? < length as u64 {
+                return None;
+            }
+            return Some(Err(format!(
+                "a message of {} bytes is longer than the {} allowed",
+                length, MAX_LENGTH
+            )));
+        }
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body).ok()?;
+        Some(serde_json::from_slice(&body).map_err(|err| format!("invalid JSON: {}", err)))
+    }
+
+    pub fn write(&mut self, message: &Json) {
+        let body = message.to_string();
+        // A client that stopped reading can't be told about it either.
+        let _ = write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use serde_json::json;
+
+    use crate::connection::{Connection, MAX_LENGTH};
+
+    #[test]
+    fn skips_messages_it_cannot_read() {
+        let long = "x".repeat(MAX_LENGTH + 1);
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: 5\r\n\r\n{{oopsContent-Length: 7\r\n\r\n{{\"a\":1}}",
+            long.len(),
+            long
+        );
+        let mut connection = Connection::new(Box::new(Cursor::new(input)), Box::new(io::sink()));
+        assert_eq!(
+            connection.read().unwrap().unwrap_err(),
+            format!(
+                "a message of {} bytes is longer than the {} allowed",
+                MAX_LENGTH + 1,
+                MAX_LENGTH
+            )
+        );
+        assert!(connection
+            .read()
+            .unwrap()
+            .unwrap_err()
+            .starts_with("invalid JSON"));
+        assert_eq!(connection.read().unwrap().unwrap(), json!({ "a": 1 }));
+        assert!(connection.read().is_none());
+
+        // A length longer than what is left ends the connection.
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", MAX_LENGTH + 1);
+        let mut connection = Connection::new(Box::new(Cursor::new(input)), Box::new(io::sink()));
+        assert!(connection.read().is_none());
+    }
+}
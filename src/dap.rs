@@ -0,0 +1,507 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+use serde_json::{json, Value as Json};
+
+use clay::interpreter::debug::{bindings, Debugger, Frame, Resume, Stops};
+use clay::interpreter::environment::Environment;
+use clay::interpreter::interpreter::Interpreter;
+use clay::interpreter::value::Value;
+use clay::pipeline::pipeline::Pipeline;
+
+use crate::connection::Connection;
+use crate::{import_map, render_diagnostic};
+
+// Clay programs are single threaded, so every request names this thread.
+const THREAD_ID: i64 = 1;
+
+// Serves the Debug Adapter Protocol over stdin and stdout. The adapter runs
+// the program itself and pauses it from inside the interpreter's statement
+// hook, answering requests until the client resumes.
+pub fn start(pipeline: Pipeline) -> i32 {
+    serve(Client::new(Connection::stdio()), pipeline)
+}
+
+struct Client {
+    connection: Connection,
+    seq: i64,
+}
+
+impl Client {
+    fn new(connection: Connection) -> Client {
+        Client { connection, seq: 0 }
+    }
+
+    // Reads the next request, or `None` once the client has gone away. The
+    // protocol has no response to a message that can't be read, so those
+    // are reported as output and skipped.
+    fn read(&mut self) -> Option<Json> {
+        loop {
+            match self.connection.read()? {
+                Ok(request) => return Some(request),
+                Err(message) => self.output("stderr", format!("error: {}\n", message)),
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        self.connection.write(&message);
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn fail(&mut self, request: &Json, message: String) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn output(&mut self, category: &str, text: String) {
+        self.event("output", json!({ "category": category, "output": text }));
+    }
+}
+
+// Something `variables` can expand.
+enum Reference {
+    Environment(Rc<RefCell<Environment>>),
+    Value(Value),
+}
+
+struct Session {
+    client: Client,
+    stops: Stops,
+    // Only valid while paused.
+    frames: Vec<Frame>,
+    references: Vec<Reference>,
+}
+
+fn serve(client: Client, pipeline: Pipeline) -> i32 {
+    let session = Rc::new(RefCell::new(Session {
+        client,
+        stops: Stops::new(),
+        frames: Vec::new(),
+        references: Vec::new(),
+    }));
+
+    // Configure the session until the client has both launched a program
+    // and finished setting breakpoints.
+    let mut program = None;
+    let mut configured = false;
+    while program.is_none() || !configured {
+        let mut session = session.borrow_mut();
+        let request = match session.client.read() {
+            Some(request) => request,
+            None => return 0,
+        };
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                session.client.respond(
+                    &request,
+                    json!({ "supportsConfigurationDoneRequest": true }),
+                );
+                session.client.event("initialized", json!({}));
+            }
+            "launch" => match request["arguments"]["program"].as_str() {
+                Some(path) => {
+                    program = Some(PathBuf::from(path));
+                    if request["arguments"]["stopOnEntry"] == json!(true) {
+                        session.stops.stop_on_entry();
+                    }
+                    session.client.respond(&request, json!({}));
+                }
+                None => session
+                    .client
+                    .fail(&request, "`launch` needs a `program`".to_string()),
+            },
+            "configurationDone" => {
+                configured = true;
+                session.client.respond(&request, json!({}));
+            }
+            "disconnect" => {
+                session.client.respond(&request, json!({}));
+                return 0;
+            }
+            _ => session.handle(&request),
+        }
+    }
+
+    let program = program.expect("checked by the loop above");
+    let exit_code = run_program(&program, pipeline, &session);
+    let mut session = session.borrow_mut();
+    session
+        .client
+        .event("exited", json!({ "exitCode": exit_code }));
+    session.client.event("terminated", json!({}));
+
+    while let Some(request) = session.client.read() {
+        if request["command"] == "disconnect" {
+            session.client.respond(&request, json!({}));
+            break;
+        }
+        session.handle(&request);
+    }
+    0
+}
+
+fn run_program(path: &Path, pipeline: Pipeline, session: &Rc<RefCell<Session>>) -> i32 {
+    let display = path.display().to_string();
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            let message = format!("error: could not read `{}`: {}\n", display, err);
+            session.borrow_mut().client.output("stderr", message);
+            return 1;
+        }
+    };
+
+    let mut interpreter = Interpreter::with_pipeline(pipeline);
+    interpreter.set_file(path);
+    match import_map(path) {
+        Ok(imports) => interpreter.set_import_map(imports),
+        Err(message) => {
+            let message = format!("error: {}\n", message);
+            session.borrow_mut().client.output("stderr", message);
+            return 1;
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    let program = interpreter
+        .pipeline_mut()
+        .process(&source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        let text = render_diagnostic(diagnostic, &display, &source);
+        session.borrow_mut().client.output("stderr", text);
+    }
+    let program = match program {
+        Some(program) => program,
+        None => return 1,
+    };
+
+    interpreter.set_debugger(Box::new(Adapter(session.clone())));
+    match interpreter.run(&program) {
+        Ok(Value::Unit) => 0,
+        Ok(value) => {
+            let text = format!("{}\n", value);
+            session.borrow_mut().client.output("stdout", text);
+            0
+        }
+        Err(diagnostic) => {
+            let text = render_diagnostic(&diagnostic, &display, &source);
+            session.borrow_mut().client.output("stderr", text);
+            1
+        }
+    }
+}
+
+struct Adapter(Rc<RefCell<Session>>);
+
+impl Debugger for Adapter {
+    fn on_statement(&mut self, frames: &[Frame]) {
+        let mut session = self.0.borrow_mut();
+        if let Some(stop) = session.stops.stop(frames) {
+            session.pause(frames, stop.name());
+        }
+    }
+}
+
+impl Session {
+    // Blocks until the client resumes execution.
+    fn pause(&mut self, frames: &[Frame], reason: &str) {
+        self.frames = frames.to_vec();
+        self.references.clear();
+        self.client.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        loop {
+            let request = match self.client.read() {
+                Some(request) => request,
+                None => process::exit(0),
+            };
+            let resume = match request["command"].as_str().unwrap_or_default() {
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepIn,
+                "stepOut" => Resume::StepOut,
+                "disconnect" => {
+                    self.client.respond(&request, json!({}));
+                    process::exit(0);
+                }
+                _ => {
+                    self.handle(&request);
+                    continue;
+                }
+            };
+            self.stops.resume(resume, frames);
+            self.client
+                .respond(&request, json!({ "allThreadsContinued": true }));
+            return;
+        }
+    }
+
+    // Answers the requests that are valid at any point in a session.
+    fn handle(&mut self, request: &Json) {
+        let arguments = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "threads" => self.client.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            ),
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"].as_str().unwrap_or_default();
+                let lines: Vec<usize> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect();
+                let verified: Vec<Json> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                self.stops.set_breakpoints(Path::new(path), lines);
+                self.client
+                    .respond(request, json!({ "breakpoints": verified }));
+            }
+            "stackTrace" => {
+                let frames: Vec<Json> = self
+                    .frames
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(id, frame)| {
+                        let mut json = json!({
+                            "id": id,
+                            "name": frame.name,
+                            "line": frame.span.start.line,
+                            "column": frame.span.start.column + 1,
+                        });
+                        if let Some(file) = &frame.file {
+                            json["source"] = json!({ "path": file.display().to_string() });
+                        }
+                        json
+                    })
+                    .collect();
+                let total = frames.len();
+                self.client.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                );
+            }
+            "scopes" => {
+                let frame = arguments["frameId"]
+                    .as_u64()
+                    .and_then(|id| self.frames.get(id as usize));
+                match frame.map(|frame| frame.environment.clone()) {
+                    Some(environment) => {
+                        let reference = self.reference(Reference::Environment(environment));
+                        self.client.respond(
+                            request,
+                            json!({ "scopes": [{
+                                "name": "Locals",
+                                "variablesReference": reference,
+                                "expensive": false,
+                            }] }),
+                        );
+                    }
+                    None => self.client.fail(request, "unknown stack frame".to_string()),
+                }
+            }
+            "variables" => {
+                let index = arguments["variablesReference"]
+                    .as_u64()
+                    .and_then(|reference| (reference as usize).checked_sub(1));
+                let children = match index.and_then(|index| self.references.get(index)) {
+                    Some(Reference::Environment(environment)) => bindings(environment),
+                    Some(Reference::Value(value)) => elements(value),
+                    None => {
+                        return self
+                            .client
+                            .fail(request, "unknown variables reference".to_string())
+                    }
+                };
+                let variables: Vec<Json> = children
+                    .into_iter()
+                    .map(|(name, value)| self.variable(name, value))
+                    .collect();
+                self.client
+                    .respond(request, json!({ "variables": variables }));
+            }
+            command => self
+                .client
+                .fail(request, format!("unsupported request `{}`", command)),
+        }
+    }
+
+    // References are 1-based; 0 means "nothing to expand".
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    fn variable(&mut self, name: String, value: Value) -> Json {
+        let shown = match &value {
+            Value::String(s) => format!("{:?}", s),
+            other => other.to_string(),
+        };
+        let kind = value.type_name();
+        let reference = match value {
+            Value::Tuple(_) | Value::List(_) | Value::Map(_) => {
+                self.reference(Reference::Value(value))
+            }
+            _ => 0,
+        };
+        json!({ "name": name, "value": shown, "type": kind, "variablesReference": reference })
+    }
+}
+
+fn elements(value: &Value) -> Vec<(String, Value)> {
+    let indexed = |values: &[Value]| {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (i.to_string(), value.clone()))
+            .collect()
+    };
+    match value {
+        Value::Tuple(values) => indexed(values),
+        Value::List(values) => indexed(&values.borrow()),
+        Value::Map(entries) => entries
+            .borrow()
+            .iter()
+            .map(|(key, value)| (key.to_value().to_string(), value.clone()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    use serde_json::{json, Value as Json};
+
+    use clay::pipeline::pipeline::Pipeline;
+
+    use crate::connection::Connection;
+    use crate::dap::{serve, Client};
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(messages: &[Json]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for (seq, message) in messages.iter().enumerate() {
+            let mut message = message.clone();
+            message["seq"] = json!(seq + 1);
+            message["type"] = json!("request");
+            let body = message.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_inspects_variables() {
+        let dir = std::env::temp_dir().join(format!("clay-dap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("main.clay");
+        std::fs::write(&program, "let xs = [1, 2];\nlet n = xs.len();\nn + 1").unwrap();
+        let path = program.display().to_string();
+
+        let mut input = frame(&[json!({ "command": "initialize", "arguments": {} })]);
+        // A message that isn't JSON is reported, and the session goes on.
+        input.extend_from_slice(b"Content-Length: 6\r\n\r\n{oops}");
+        input.extend(frame(&[
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": path }, "breakpoints": [{ "line": 2 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]));
+        let output = Output::default();
+        let connection = Connection::new(Box::new(Cursor::new(input)), Box::new(output.clone()));
+        assert_eq!(serve(Client::new(connection), Pipeline::new()), 0);
+
+        let mut reader = Connection::new(
+            Box::new(Cursor::new(output.0.borrow().clone())),
+            Box::new(io::sink()),
+        );
+        let mut messages = Vec::new();
+        while let Some(message) = reader.read() {
+            messages.push(message.unwrap());
+        }
+        let find = |key: &str, name: &str| {
+            messages
+                .iter()
+                .find(|message| message[key] == name)
+                .unwrap_or_else(|| panic!("no {} {}", key, name))
+        };
+
+        assert_eq!(find("event", "stopped")["body"]["reason"], "breakpoint");
+        assert_eq!(
+            find("command", "stackTrace")["body"]["stackFrames"][0]["line"],
+            2
+        );
+        let variables: Vec<&Json> = messages
+            .iter()
+            .filter(|message| message["command"] == "variables")
+            .collect();
+        assert_eq!(
+            variables[0]["body"]["variables"][0],
+            json!({ "name": "xs", "value": "[1, 2]", "type": "list", "variablesReference": 2 })
+        );
+        assert_eq!(variables[1]["body"]["variables"][1]["value"], "2");
+        let output: Vec<&Json> = messages
+            .iter()
+            .filter(|message| message["event"] == "output")
+            .collect();
+        assert!(output[0]["body"]["output"]
+            .as_str()
+            .unwrap()
+            .starts_with("error: invalid JSON"));
+        assert_eq!(output[1]["body"]["output"], "3\n");
+        assert_eq!(find("event", "exited")["body"]["exitCode"], 0);
+    }
+}
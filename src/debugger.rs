@@ -0,0 +1,421 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+use clay::interpreter::debug::{bindings, Debugger, Frame, Resume, Stop, Stops};
+use clay::interpreter::interpreter::Interpreter;
+use clay::interpreter::module::display_path;
+use clay::interpreter::value::Value;
+use clay::lexer::symbol::Symbol;
+use clay::pipeline::pipeline::Pipeline;
+
+use crate::{import_map, Reporter, EXIT_FAILURE};
+
+const PROMPT: &str = "(clay) ";
+
+const HELP: &str = "commands:
+    break [<file>:]<line>   pause before the statements on a line, in the
+                            file paused in unless another is named
+    delete [<file>:]<line>  remove a breakpoint
+    breakpoints             list the breakpoints
+    continue, c             run until the next breakpoint
+    step, s                 run to the next statement, going into calls
+    next, n                 run to the next statement, running calls whole
+    finish, f               run until the function paused in returns
+    backtrace, bt           list the calls on the stack, innermost first
+    frame <n>               select a call from the backtrace
+    locals                  print the variables the selected call sees
+    print <name>, p         print a variable the selected call sees
+    quit, q                 stop the program
+    help, h                 show this message";
+
+// Runs the file at `path` under a debugger that reads commands from stdin.
+// The program pauses before its first statement, so breakpoints can be set
+// before anything runs.
+pub fn start(source: &str, path: &str, pipeline: Pipeline, reporter: &mut Reporter) -> i32 {
+    let console = Console::new(
+        Box::new(io::stdin().lock()),
+        Box::new(io::stdout()),
+        Path::new(path),
+    );
+    debug(source, path, pipeline, console, reporter)
+}
+
+struct Console {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+    stops: Stops,
+    // Where breakpoints go when no file is named and nothing is paused.
+    file: PathBuf,
+    // Only valid while paused.
+    frames: Vec<Frame>,
+    // The frame `locals` and `print` look at, counted from the innermost.
+    selected: usize,
+    // The lines of the files shown so far.
+    sources: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Console {
+    fn new(reader: Box<dyn BufRead>, writer: Box<dyn Write>, file: &Path) -> Console {
+        let mut stops = Stops::new();
+        stops.stop_on_entry();
+        Console {
+            reader,
+            writer,
+            stops,
+            file: file.canonicalize().unwrap_or_else(|_| file.to_path_buf()),
+            frames: Vec::new(),
+            selected: 0,
+            sources: HashMap::new(),
+        }
+    }
+
+    // The user can't be told about a terminal that went away either.
+    fn say(&mut self, text: impl AsRef<str>) {
+        let _ = writeln!(self.writer, "{}", text.as_ref());
+        let _ = self.writer.flush();
+    }
+
+    // Reads the next command, or `None` once input has ended.
+    fn read(&mut self) -> Option<String> {
+        let _ = write!(self.writer, "{}", PROMPT);
+        let _ = self.writer.flush();
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    // Blocks until a command resumes the program.
+    fn pause(&mut self, frames: &[Frame], stop: Stop) {
+        self.frames = frames.to_vec();
+        self.selected = 0;
+        let location = self.location(0);
+        let reason = match stop {
+            Stop::Entry | Stop::Step => "",
+            Stop::Breakpoint => " at a breakpoint",
+        };
+        self.say(format!("paused{} in {}", reason, location));
+        self.show_line(0);
+
+        loop {
+            let command = match self.read() {
+                Some(command) => command,
+                None => self.quit(),
+            };
+            if let Some(resume) = self.execute(&command) {
+                self.stops.resume(resume, frames);
+                return;
+            }
+        }
+    }
+
+    // Runs a command, returning how to resume if it resumes the program.
+    fn execute(&mut self, command: &str) -> Option<Resume> {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        match (name, argument) {
+            ("", _) => {}
+            ("continue" | "c", "") => return Some(Resume::Continue),
+            ("step" | "s", "") => return Some(Resume::StepIn),
+            ("next" | "n", "") => return Some(Resume::StepOver),
+            ("finish" | "f", "") => return Some(Resume::StepOut),
+            ("quit" | "q", "") => self.quit(),
+            ("help" | "h", "") => self.say(HELP),
+            ("break" | "b", location) => match self.breakpoint(location) {
+                Some((file, line)) => {
+                    self.stops.add_breakpoint(&file, line);
+                    self.say(format!("breakpoint at {}:{}", display_path(&file), line));
+                }
+                None => self.say("expected `break [<file>:]<line>`"),
+            },
+            ("delete" | "d", location) => match self.breakpoint(location) {
+                Some((file, line)) if self.stops.remove_breakpoint(&file, line) => self.say(
+                    format!("removed the breakpoint at {}:{}", display_path(&file), line),
+                ),
+                Some((file, line)) => {
+                    self.say(format!("no breakpoint at {}:{}", display_path(&file), line))
+                }
+                None => self.say("expected `delete [<file>:]<line>`"),
+            },
+            ("breakpoints", "") => {
+                let breakpoints = self.stops.breakpoints();
+                if breakpoints.is_empty() {
+                    self.say("no breakpoints");
+                }
+                for (file, line) in breakpoints {
+                    self.say(format!("{}:{}", display_path(&file), line));
+                }
+            }
+            ("backtrace" | "bt", "") => {
+                for index in 0..self.frames.len() {
+                    let marker = if index == self.selected { '>' } else { ' ' };
+                    let location = self.location(index);
+                    self.say(format!("{} #{} {}", marker, index, location));
+                }
+            }
+            ("frame", index) => match index.parse::<usize>() {
+                Ok(index) if index < self.frames.len() => {
+                    self.selected = index;
+                    let location = self.location(index);
+                    self.say(format!("#{} {}", index, location));
+                    self.show_line(index);
+                }
+                _ => self.say(format!(
+                    "expected a frame from 0 to {}",
+                    self.frames.len().saturating_sub(1)
+                )),
+            },
+            ("locals", "") => match self.frame(self.selected) {
+                Some(frame) => {
+                    let bindings = bindings(&frame.environment);
+                    for (name, value) in bindings {
+                        self.say(format!("{} = {}", name, shown(&value)));
+                    }
+                }
+                None => self.say("no frame is selected"),
+            },
+            ("print" | "p", name) if !name.is_empty() => {
+                let value = self
+                    .frame(self.selected)
+                    .and_then(|frame| frame.environment.borrow().get(Symbol::intern(name)));
+                match value {
+                    Some(value) => self.say(shown(&value)),
+                    None => self.say(format!("no variable `{}` in this frame", name)),
+                }
+            }
+            _ => self.say(format!("unknown command `{}`; `help` lists them", command)),
+        }
+        None
+    }
+
+    // The frame `index` calls from the innermost.
+    fn frame(&self, index: usize) -> Option<&Frame> {
+        self.frames.iter().rev().nth(index)
+    }
+
+    // Parses `[<file>:]<line>`, taking the file paused in when none is named.
+    fn breakpoint(&self, location: &str) -> Option<(PathBuf, usize)> {
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file, line)) => (PathBuf::from(file), line),
+            None => {
+                let paused = self
+                    .frame(self.selected)
+                    .and_then(|frame| frame.file.clone());
+                (paused.unwrap_or_else(|| self.file.clone()), location)
+            }
+        };
+        Some((file, line.parse().ok().filter(|line| *line > 0)?))
+    }
+
+    fn location(&self, index: usize) -> String {
+        match self.frame(index) {
+            Some(frame) => match &frame.file {
+                Some(file) => format!(
+                    "`{}` at {}:{}",
+                    frame.name,
+                    display_path(file),
+                    frame.span.start.line
+                ),
+                None => format!("`{}` at line {}", frame.name, frame.span.start.line),
+            },
+            None => "nothing".to_string(),
+        }
+    }
+
+    // Shows the line the frame `index` calls from the innermost is at.
+    fn show_line(&mut self, index: usize) {
+        let (file, line) = match self.frame(index) {
+            Some(Frame {
+                file: Some(file),
+                span,
+                ..
+            }) => (file.clone(), span.start.line),
+            _ => return,
+        };
+        let lines = self.sources.entry(file.clone()).or_insert_with(|| {
+            fs::read_to_string(&file)
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        });
+        if let Some(text) = line.checked_sub(1).and_then(|index| lines.get(index)) {
+            let text = format!("{:>4} | {}", line, text);
+            self.say(text);
+        }
+    }
+
+    fn quit(&mut self) -> ! {
+        let _ = self.writer.flush();
+        let _ = io::stdout().flush();
+        process::exit(0)
+    }
+}
+
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+struct Prompt(Rc<RefCell<Console>>);
+
+impl Debugger for Prompt {
+    fn on_statement(&mut self, frames: &[Frame]) {
+        let mut console = self.0.borrow_mut();
+        if let Some(stop) = console.stops.stop(frames) {
+            console.pause(frames, stop);
+        }
+    }
+}
+
+fn debug(
+    source: &str,
+    path: &str,
+    pipeline: Pipeline,
+    console: Console,
+    reporter: &mut Reporter,
+) -> i32 {
+    let mut interpreter = Interpreter::with_pipeline(pipeline);
+    interpreter.set_file(Path::new(path));
+    interpreter.set_sources(reporter.sources.clone());
+    match import_map(Path::new(path)) {
+        Ok(imports) => interpreter.set_import_map(imports),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return EXIT_FAILURE;
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    let program =
+        interpreter
+            .pipeline_mut()
+            .process_file(Path::new(path), source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        reporter.report(diagnostic);
+    }
+    let program = match program {
+        Some(program) => program,
+        None => return EXIT_FAILURE,
+    };
+
+    let console = Rc::new(RefCell::new(console));
+    interpreter.set_debugger(Box::new(Prompt(console.clone())));
+    interpreter.preload_imports(&program);
+    let code = match interpreter.run_script(&program) {
+        Ok(value) => {
+            if value != Value::Unit {
+                console.borrow_mut().say(value.to_string());
+            }
+            interpreter.exit_code().unwrap_or(0)
+        }
+        Err(diagnostic) => {
+            reporter.report(&diagnostic);
+            interpreter.exit_code().unwrap_or(EXIT_FAILURE)
+        }
+    };
+    console
+        .borrow_mut()
+        .say(format!("the program exited with code {}", code));
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    use clay::diagnostic::source_map::SourceMap;
+    use clay::pipeline::pipeline::Pipeline;
+
+    use crate::debugger::{debug, Console};
+    use crate::{ErrorFormat, Reporter};
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn steps_through_a_program_and_inspects_frames() {
+        let dir = std::env::temp_dir().join(format!("clay-debugger-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("main.clay");
+        let source = "fn double(n) {\n  let twice = n * 2;\n  twice\n}\nlet x = double(4);\nx + 1";
+        std::fs::write(&program, source).unwrap();
+        let path = program.display().to_string();
+
+        let commands = [
+            "break 3",
+            "bogus",
+            "continue",
+            "backtrace",
+            "locals",
+            "frame 1",
+            "print x",
+            "print n",
+            "delete 3",
+            "next",
+            "next",
+            "continue",
+        ];
+        let input = Cursor::new(commands.join("\n").into_bytes());
+        let output = Output::default();
+        let console = Console::new(Box::new(input), Box::new(output.clone()), &program);
+        let mut reporter = Reporter {
+            path: &path,
+            format: ErrorFormat::Human,
+            sources: SourceMap::new(),
+            failed: false,
+        };
+        assert_eq!(
+            debug(source, &path, Pipeline::new(), console, &mut reporter),
+            0
+        );
+
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output
+            .lines()
+            .map(|line| line.trim_start_matches("(clay) "))
+            .collect();
+        let file = clay::interpreter::module::display_path(&program.canonicalize().unwrap());
+        let at = |line: usize| format!("at {}:{}", file, line);
+        assert_eq!(lines[0], format!("paused in `<main>` {}", at(1)));
+        assert_eq!(lines[1], "   1 | fn double(n) {");
+        assert_eq!(lines[2], format!("breakpoint at {}:3", file));
+        assert_eq!(lines[3], "unknown command `bogus`; `help` lists them");
+        assert_eq!(
+            lines[4],
+            format!("paused at a breakpoint in `double` {}", at(3))
+        );
+        assert_eq!(lines[6], format!("> #0 `double` {}", at(3)));
+        assert_eq!(lines[7], format!("  #1 `<main>` {}", at(5)));
+        assert_eq!(&lines[8..10], ["n = 4", "twice = 8"]);
+        assert_eq!(lines[11], format!("#1 `<main>` {}", at(5)));
+        assert_eq!(lines[13], "no variable `x` in this frame");
+        assert_eq!(lines[14], "no variable `n` in this frame");
+        assert_eq!(lines[15], format!("removed the breakpoint at {}:3", file));
+        assert_eq!(lines[16], format!("paused in `<main>` {}", at(6)));
+        assert_eq!(lines[18], "9");
+        assert_eq!(lines[19], "the program exited with code 0");
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::lexer::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    // The file the span points into, when it isn't the file being compiled.
+    pub file: Option<String>,
+    // The calls a runtime error unwound through, innermost first. Boxed to
+    // keep diagnostics small, since most have none.
+    pub trace: Option<Box<Vec<Call>>>,
+    // Notes, a code and fixes, which few diagnostics have. Boxed for the
+    // same reason as `trace`.
+    pub details: Option<Box<Details>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Details {
+    // Shown under the source line, like the values a failed assertion
+    // compared.
+    pub notes: Vec<String>,
+    // What found the problem, for tools to filter on: the name of the lint
+    // rule, or `syntax-error`, `type-error` or `runtime-error`.
+    pub code: Option<String>,
+    // Edits that would fix the problem.
+    pub suggestions: Vec<Suggestion>,
+}
+
+// A fix for a diagnostic: replacing the text `span` covers with
+// `replacement`, which inserts when the span is empty.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+// A call on the way to a runtime error: the function called and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub function: String,
+    pub span: Span,
+    // The file the call is in, when it isn't the file being compiled.
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            file: None,
+            trace: None,
+            details: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            file: None,
+            trace: None,
+            details: None,
+        }
+    }
+
+    // Attributes the diagnostic to `file` unless it already names one, so
+    // errors keep the innermost file they were raised in. The calls traced so
+    // far are attributed to it the same way.
+    pub fn in_file(mut self, file: impl Into<String>) -> Diagnostic {
+        let file = file.into();
+        for call in self.trace.iter_mut().flat_map(|trace| trace.iter_mut()) {
+            if call.file.is_none() {
+                call.file = Some(file.clone());
+            }
+        }
+        if self.file.is_none() {
+            self.file = Some(file);
+        }
+        self
+    }
+
+    pub fn calls(&self) -> &[Call] {
+        self.trace.as_deref().map_or(&[], |trace| trace.as_slice())
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        let details = self.details.get_or_insert_with(Box::default);
+        details.notes.push(note.into());
+        self
+    }
+
+    pub fn notes(&self) -> &[String] {
+        self.details
+            .as_deref()
+            .map_or(&[], |details| details.notes.as_slice())
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Diagnostic {
+        self.details.get_or_insert_with(Box::default).code = Some(code.into());
+        self
+    }
+
+    // Gives the diagnostic `code` unless it has one already, as when an
+    // import's syntax error surfaces while running a program.
+    pub fn or_code(self, code: &str) -> Diagnostic {
+        match self.code() {
+            Some(_) => self,
+            None => self.with_code(code),
+        }
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.details
+            .as_ref()
+            .and_then(|details| details.code.as_deref())
+    }
+
+    pub fn with_suggestion(
+        mut self,
+        message: impl Into<String>,
+        span: Span,
+        replacement: impl Into<String>,
+    ) -> Diagnostic {
+        let details = self.details.get_or_insert_with(Box::default);
+        details.suggestions.push(Suggestion {
+            message: message.into(),
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    pub fn suggestions(&self) -> &[Suggestion] {
+        self.details
+            .as_deref()
+            .map_or(&[], |details| details.suggestions.as_slice())
+    }
+
+    // Records that the error unwound through a call to `function` at `span`.
+    pub fn called_from(mut self, function: impl Into<String>, span: Span) -> Diagnostic {
+        self.trace.get_or_insert_with(Box::default).push(Call {
+            function: function.into(),
+            span,
+            file: None,
+        });
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} at {}:{}",
+            self.severity,
+            self.message,
+            self.span.start.line,
+            self.span.start.column + 1
+        )
+    }
+}
@@ -0,0 +1,119 @@
+use serde_json::{json, Value};
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::token::Span;
+
+// A diagnostic as one line of JSON, for editors and CI tools to read
+// instead of the rendered text. Lines and columns count from 1, as they do
+// in rendered diagnostics. The file is the one the diagnostic names, or
+// `file` if it names none, and so is the file of each call traced.
+pub fn render_json(diagnostic: &Diagnostic, file: &str) -> String {
+    let calls: Vec<Value> = diagnostic
+        .calls()
+        .iter()
+        .map(|call| {
+            json!({
+                "function": call.function,
+                "file": call.file.as_deref().unwrap_or(file),
+                "range": range(call.span),
+            })
+        })
+        .collect();
+    let suggestions: Vec<Value> = diagnostic
+        .suggestions()
+        .iter()
+        .map(|suggestion| {
+            json!({
+                "message": suggestion.message,
+                "range": range(suggestion.span),
+                "replacement": suggestion.replacement,
+            })
+        })
+        .collect();
+    let json = json!({
+        "code": diagnostic.code(),
+        "severity": diagnostic.severity.to_string(),
+        "message": diagnostic.message,
+        "file": diagnostic.file.as_deref().unwrap_or(file),
+        "range": range(diagnostic.span),
+        "notes": diagnostic.notes(),
+        "calls": calls,
+        "suggestions": suggestions,
+    });
+    json.to_string()
+}
+
+fn range(span: Span) -> Value {
+    json!({
+        "start": { "line": span.start.line, "column": span.start.column + 1 },
+        "end": { "line": span.end.line, "column": span.end.column + 1 },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::diagnostic::diagnostic::Diagnostic;
+    use crate::diagnostic::json::render_json;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::lexer::token::{Position, Span};
+    use crate::parser::parser::parse;
+    use crate::typecheck::typecheck::check;
+    use crate::vm::vm::Vm;
+
+    #[test]
+    fn renders_diagnostics_as_json_lines() {
+        let span = Span::new(Position::new(2, 4, 10), Position::new(2, 7, 13));
+        let diagnostic = Diagnostic::warning("unused variable `foo`", span)
+            .with_code("unused-variable")
+            .with_note("`unused-variable` is set to warn")
+            .with_suggestion("name it `_foo`", span, "_foo");
+        let line = render_json(&diagnostic, "main.clay");
+        assert!(!line.contains('\n'));
+        let range = json!({
+            "start": { "line": 2, "column": 5 },
+            "end": { "line": 2, "column": 8 },
+        });
+        assert_eq!(
+            serde_json::from_str::<Value>(&line).unwrap(),
+            json!({
+                "code": "unused-variable",
+                "severity": "warning",
+                "message": "unused variable `foo`",
+                "file": "main.clay",
+                "range": range,
+                "notes": ["`unused-variable` is set to warn"],
+                "calls": [],
+                "suggestions": [
+                    { "message": "name it `_foo`", "range": range, "replacement": "_foo" }
+                ],
+            })
+        );
+
+        let error = Diagnostic::error("division by zero", span)
+            .called_from("f", span)
+            .in_file("lib.clay");
+        let json: Value = serde_json::from_str(&render_json(&error, "main.clay")).unwrap();
+        assert_eq!(json["code"], Value::Null);
+        assert_eq!(json["file"], "lib.clay");
+        assert_eq!(json["calls"][0]["function"], "f");
+        assert_eq!(json["calls"][0]["file"], "lib.clay");
+    }
+
+    #[test]
+    fn gives_errors_from_every_phase_a_code() {
+        let code = |diagnostic: &Diagnostic| {
+            let json: Value = serde_json::from_str(&render_json(diagnostic, "main.clay")).unwrap();
+            json["code"].clone()
+        };
+        assert_eq!(code(&parse("1 +").unwrap_err()), "syntax-error");
+        assert_eq!(code(&parse("\"open").unwrap_err()), "syntax-error");
+        assert_eq!(code(&check(&parse("1 + true").unwrap())[0]), "type-error");
+        let program = parse("fn f() { 1 / 0 }\nf()").unwrap();
+        let error = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(code(&error), "runtime-error");
+        let error = Vm::new().run(&program).unwrap_err();
+        assert_eq!(code(&error), "runtime-error");
+    }
+}
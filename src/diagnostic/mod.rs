@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+pub mod diagnostic;
+pub mod json;
+pub mod render;
+pub mod source_map;
+pub mod suggest;
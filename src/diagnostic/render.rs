@@ -0,0 +1,160 @@
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::diagnostic::source_map::SourceMap;
+
+// Renders a diagnostic with the offending source line and a caret underline:
+//
+//   error: unexpected token `)`
+//    --> main.clay:1:5
+//     |
+//   1 | 1 + )
+//     |     ^
+//
+// followed by a line for each note and each call a runtime error unwound
+// through:
+//
+//     = note: left: 1 (integer)
+//     = in `f`, called at main.clay:3:1
+//
+// Consecutive calls from the same place, as in a recursion, share a line.
+pub fn render(diagnostic: &Diagnostic, filename: &str, source: &str) -> String {
+    let start = diagnostic.span.start;
+    let end = diagnostic.span.end;
+
+    let line = source.lines().nth(start.line - 1).unwrap_or("");
+    let line_number = start.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    let line_width = line.chars().count();
+    let underline_end = if end.line == start.line {
+        end.column
+    } else {
+        line_width
+    };
+    let underline_width = underline_end.saturating_sub(start.column).max(1);
+
+    let mut rendered = format!(
+        "{severity}: {message}\n{gutter}--> {filename}:{line}:{column}\n{gutter} |\n{line_number} | {source_line}\n{gutter} | {padding}{carets}\n",
+        severity = diagnostic.severity,
+        message = diagnostic.message,
+        gutter = gutter,
+        filename = filename,
+        line = start.line,
+        column = start.column + 1,
+        line_number = line_number,
+        source_line = line,
+        padding = " ".repeat(start.column),
+        carets = "^".repeat(underline_width),
+    );
+
+    for note in diagnostic.notes() {
+        rendered.push_str(&format!("{} = note: {}\n", gutter, note));
+    }
+    for suggestion in diagnostic.suggestions() {
+        rendered.push_str(&format!("{} = help: {}\n", gutter, suggestion.message));
+    }
+
+    let mut calls = diagnostic.calls().iter().peekable();
+    while let Some(call) = calls.next() {
+        let mut times = 1;
+        while calls.next_if(|next| *next == call).is_some() {
+            times += 1;
+        }
+        rendered.push_str(&format!(
+            "{} = in `{}`, called at {}:{}:{}",
+            gutter,
+            call.function,
+            call.file.as_deref().unwrap_or(filename),
+            call.span.start.line,
+            call.span.start.column + 1,
+        ));
+        if times > 1 {
+            rendered.push_str(&format!(" ({} times)", times));
+        }
+        rendered.push('\n');
+    }
+    rendered
+}
+
+// Renders against the file the diagnostic names as `sources` has it, or
+// against `file` if it names none. Calls traced without a file are in `file`.
+pub fn render_in(diagnostic: &Diagnostic, sources: &SourceMap, file: &str) -> String {
+    let named = diagnostic.file.as_deref().unwrap_or(file);
+    let source = sources.get(named).unwrap_or_else(|| "".into());
+    render(&diagnostic.clone().in_file(file), named, &source)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::diagnostic::Diagnostic;
+    use crate::diagnostic::render::{render, render_in};
+    use crate::diagnostic::source_map::SourceMap;
+    use crate::lexer::token::{Position, Span};
+
+    #[test]
+    fn renders_caret_under_span() {
+        let span = Span::new(Position::new(2, 4, 10), Position::new(2, 7, 13));
+        let diagnostic = Diagnostic::error("unknown variable `foo`", span);
+        let rendered = render(&diagnostic, "main.clay", "a = 1;\n1 + foo;\n");
+
+        assert_eq!(
+            rendered,
+            "error: unknown variable `foo`\n --> main.clay:2:5\n  |\n2 | 1 + foo;\n  |     ^^^\n"
+        );
+    }
+
+    #[test]
+    fn renders_the_calls_an_error_unwound_through() {
+        let at = |line, column| {
+            let position = Position::new(line, column, 0);
+            Span::new(position, position)
+        };
+        let diagnostic = Diagnostic::error("division by zero", at(1, 14))
+            .called_from("f", at(2, 17))
+            .called_from("f", at(2, 17))
+            .in_file("lib.clay")
+            .called_from("g", at(3, 0))
+            .in_file("main.clay");
+        let rendered = render(&diagnostic, "lib.clay", "fn f(n) { 1 / 0 }\n");
+
+        assert_eq!(
+            rendered,
+            "error: division by zero\n --> lib.clay:1:15\n  |\n1 | fn f(n) { 1 / 0 }\n  |               ^\n  = in `f`, called at lib.clay:2:18 (2 times)\n  = in `g`, called at main.clay:3:1\n"
+        );
+    }
+
+    #[test]
+    fn renders_notes_and_suggestions_before_the_calls() {
+        let span = Span::new(Position::new(1, 0, 0), Position::new(1, 15, 15));
+        let diagnostic = Diagnostic::error("assertion `left == right` failed", span)
+            .with_note("left: 1 (integer)")
+            .with_note("right: \"1\" (string)")
+            .with_suggestion("try `assert`", span, "assert")
+            .called_from("check", span);
+        let rendered = render(&diagnostic, "main.clay", "assert_eq(1, \"1\")\n");
+
+        assert_eq!(
+            rendered,
+            "error: assertion `left == right` failed\n --> main.clay:1:1\n  |\n1 | assert_eq(1, \"1\")\n  | ^^^^^^^^^^^^^^^\n  = note: left: 1 (integer)\n  = note: right: \"1\" (string)\n  = help: try `assert`\n  = in `check`, called at main.clay:1:1\n"
+        );
+    }
+
+    #[test]
+    fn renders_against_the_file_the_diagnostic_names() {
+        let sources = SourceMap::new();
+        sources.insert("main.clay", "import \"lib.clay\";\nlib::f()\n");
+        sources.insert("lib.clay", "fn f() { 1 / 0 }\n");
+        let at = |line, column| {
+            let position = Position::new(line, column, 0);
+            Span::new(position, position)
+        };
+        let diagnostic = Diagnostic::error("division by zero", at(1, 11))
+            .in_file("lib.clay")
+            .called_from("f", at(2, 0));
+        let rendered = render_in(&diagnostic, &sources, "main.clay");
+
+        assert_eq!(
+            rendered,
+            "error: division by zero\n --> lib.clay:1:12\n  |\n1 | fn f() { 1 / 0 }\n  |            ^\n  = in `f`, called at main.clay:2:1\n"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::lexer::token::Position;
+
+// The source of every file read while running a program, keyed by the name
+// diagnostics give the file, so errors in a module are shown against the
+// text it was parsed from. Each file is given its own range of offsets, laid
+// end to end in the order the files were added, so an offset alone says
+// which file it is in and where. Modules are read on several threads at
+// once, so the map is behind a lock; clones share the same map.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Arc<Mutex<Files>>,
+}
+
+#[derive(Debug, Default)]
+struct Files {
+    // In the order they were added, which is the order of their offsets.
+    files: Vec<Arc<SourceFile>>,
+    by_name: BTreeMap<String, usize>,
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: Arc<str>,
+    // The offset of the file's first byte.
+    pub start: usize,
+    // Where each line starts, counted from the start of the file.
+    lines: Vec<usize>,
+}
+
+impl SourceFile {
+    // One past the offset of the file's last byte, which is where spans at
+    // the end of the file point.
+    pub fn end(&self) -> usize {
+        self.start + self.source.len()
+    }
+
+    // Where `offset` is in the file, counted the way the lexer counts: lines
+    // from one, columns in characters from zero and `char` in bytes from the
+    // start of the file.
+    pub fn position(&self, offset: usize) -> Position {
+        let char = offset.clamp(self.start, self.end()) - self.start;
+        let line = self.lines.partition_point(|&start| start <= char);
+        let line_start = self.lines[line - 1];
+        let column = self.source[line_start..]
+            .char_indices()
+            .take_while(|(index, _)| line_start + index < char)
+            .count();
+        Position::new(line, column, char)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    // Adds `source` after the files added so far. Adding a file again gives
+    // it new offsets, and offsets into the old text still find it.
+    pub fn insert(&self, file: impl Into<String>, source: impl Into<Arc<str>>) -> Arc<SourceFile> {
+        let source = source.into();
+        let lines = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        let mut files = self.files.lock().unwrap();
+        // A byte apart, so the end of one file isn't the start of the next.
+        let start = files.files.last().map_or(0, |last| last.end() + 1);
+        let file = Arc::new(SourceFile {
+            name: file.into(),
+            source,
+            start,
+            lines,
+        });
+        let index = files.files.len();
+        files.by_name.insert(file.name.clone(), index);
+        files.files.push(file.clone());
+        file
+    }
+
+    pub fn get(&self, file: &str) -> Option<Arc<str>> {
+        self.file(file).map(|file| file.source.clone())
+    }
+
+    pub fn file(&self, file: &str) -> Option<Arc<SourceFile>> {
+        let files = self.files.lock().unwrap();
+        let index = *files.by_name.get(file)?;
+        Some(files.files[index].clone())
+    }
+
+    // The names of the files read so far, sorted whatever order they were
+    // read in.
+    pub fn files(&self) -> Vec<String> {
+        self.files.lock().unwrap().by_name.keys().cloned().collect()
+    }
+
+    // The offset of `position` in `file`, which is how a span in one file is
+    // told apart from the same span in another.
+    pub fn offset(&self, file: &str, position: Position) -> Option<usize> {
+        self.file(file).map(|file| file.start + position.char)
+    }
+
+    // The file `offset` is in and where in it.
+    pub fn locate(&self, offset: usize) -> Option<(Arc<SourceFile>, Position)> {
+        let files = self.files.lock().unwrap();
+        let index = files.files.partition_point(|file| file.start <= offset);
+        let file = files.files.get(index.checked_sub(1)?)?;
+        match offset <= file.end() {
+            true => Some((file.clone(), file.position(offset))),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::diagnostic::source_map::SourceMap;
+    use crate::lexer::token::Position;
+
+    #[test]
+    fn is_filled_from_several_threads() {
+        let sources = SourceMap::new();
+        thread::scope(|scope| {
+            for n in (0..8).rev() {
+                let sources = sources.clone();
+                scope.spawn(move || sources.insert(format!("m{}.clay", n), format!("x = {};", n)));
+            }
+        });
+        let files: Vec<String> = (0..8).map(|n| format!("m{}.clay", n)).collect();
+        assert_eq!(sources.files(), files);
+        assert_eq!(sources.get("m3.clay").as_deref(), Some("x = 3;"));
+    }
+
+    #[test]
+    fn maps_offsets_back_to_files() {
+        let sources = SourceMap::new();
+        let main = sources.insert("main.clay", "import \"lib.clay\";\nlib::f()\n");
+        let lib = sources.insert("lib.clay", "fn f() {\n  \"é\" + 1\n}");
+        assert_eq!(lib.start, main.end() + 1);
+
+        let (file, position) = sources.locate(main.start + 19).unwrap();
+        assert_eq!(
+            (file.name.as_str(), position),
+            ("main.clay", Position::new(2, 0, 19))
+        );
+        let offset = sources.offset("lib.clay", Position::new(2, 5, 15)).unwrap();
+        let (file, position) = sources.locate(offset).unwrap();
+        assert_eq!(
+            (file.name.as_str(), position),
+            ("lib.clay", Position::new(2, 5, 15))
+        );
+        assert_eq!(
+            sources.locate(lib.end()).unwrap().1,
+            Position::new(3, 1, 21)
+        );
+        assert!(sources.locate(lib.end() + 1).is_none());
+    }
+}
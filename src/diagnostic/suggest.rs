@@ -0,0 +1,72 @@
+// The candidate `name` is most likely a misspelling of: the nearest one, as
+// long as at most a third of its characters, or one for short names, had to
+// change and not all of them did. Ties go to the candidate that sorts
+// first, so the answer doesn't depend on the order of a hash map.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let allowed = (length / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= allowed && *distance < length.min(candidate.chars().count())
+        })
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// The optimal string alignment distance: how many characters must be
+// inserted, removed, replaced or swapped with their neighbour to turn `a`
+// into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows of the distance table for the prefixes of `a` one and two
+    // characters shorter than the current one.
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (previous[j] + 1)
+                .min(row[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::suggest::{closest, edit_distance};
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("while", "while"), 0);
+        assert_eq!(edit_distance("whlie", "while"), 1);
+        assert_eq!(edit_distance("retun", "return"), 1);
+        assert_eq!(edit_distance("retrn", "return"), 1);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+        assert_eq!(edit_distance("", "fn"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn finds_the_closest_name() {
+        let names = ["length", "list", "print", "println", "x"];
+        assert_eq!(closest("lenght", names), Some("length"));
+        assert_eq!(closest("prnt", names), Some("print"));
+        assert_eq!(closest("printn", names), Some("print"));
+        assert_eq!(closest("y", names), None);
+        assert_eq!(closest("lst", names), Some("list"));
+        assert_eq!(closest("total", names), None);
+        assert_eq!(closest("length", names), None);
+        assert_eq!(closest("lit", ["lot", "list"]), Some("list"));
+    }
+}
@@ -0,0 +1,208 @@
+pub const INDENT: usize = 4;
+
+// A layout for the printer: text, and line breaks that are only taken when the
+// group around them does not fit in what is left of the line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Doc {
+    Text(String),
+    // A space when its group fits on one line, a newline otherwise.
+    Line,
+    // Nothing when its group fits on one line, a newline otherwise.
+    SoftLine,
+    // Always a newline, so the groups around it never fit on one line.
+    HardLine,
+    // Text that is only printed when its group is broken, like a trailing
+    // comma.
+    IfBreak(String),
+    // Text held back until the end of the line, for trailing comments.
+    LineSuffix(String),
+    // Indents the lines inside it by one level.
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+pub fn text(text: impl Into<String>) -> Doc {
+    Doc::Text(text.into())
+}
+
+pub fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+// `docs` with `separator` between each of them.
+pub fn join(docs: Vec<Doc>, separator: Doc) -> Doc {
+    let mut joined = Vec::with_capacity(docs.len() * 2);
+    for (index, doc) in docs.into_iter().enumerate() {
+        if index > 0 {
+            joined.push(separator.clone());
+        }
+        joined.push(doc);
+    }
+    Doc::Concat(joined)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+type Command<'d> = (usize, Mode, &'d Doc);
+
+// Lays `doc` out in lines of at most `width` columns where it can, breaking
+// each group only when it does not fit flat.
+pub fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut suffix = String::new();
+    let mut column = 0;
+    let mut commands: Vec<Command> = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = commands.pop() {
+        match doc {
+            Doc::Text(text) => {
+                out.push_str(text);
+                column = match text.rfind('\n') {
+                    Some(newline) => text[newline + 1..].chars().count(),
+                    None => column + text.chars().count(),
+                };
+            }
+            Doc::Line if mode == Mode::Flat => {
+                out.push(' ');
+                column += 1;
+            }
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                out.push_str(&suffix);
+                suffix.clear();
+                let trimmed = out.trim_end_matches(' ').len();
+                out.truncate(trimmed);
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::IfBreak(text) => {
+                if mode == Mode::Break {
+                    out.push_str(text);
+                    column += text.chars().count();
+                }
+            }
+            Doc::LineSuffix(text) => suffix.push_str(text),
+            Doc::Nest(doc) => commands.push((indent + INDENT, mode, doc)),
+            Doc::Group(doc) => {
+                let mode = match mode {
+                    Mode::Flat => Mode::Flat,
+                    Mode::Break => {
+                        let remaining = width as isize - column as isize;
+                        if fits((indent, Mode::Flat, doc), &commands, remaining) {
+                            Mode::Flat
+                        } else {
+                            Mode::Break
+                        }
+                    }
+                };
+                commands.push((indent, mode, doc));
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    commands.push((indent, mode, doc));
+                }
+            }
+        }
+    }
+
+    out.push_str(&suffix);
+    out
+}
+
+// Whether `next` fits flat in `remaining` columns, along with whatever follows
+// it up to the next line break.
+fn fits(next: Command, rest: &[Command], mut remaining: isize) -> bool {
+    let mut stack = vec![next];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        if remaining < 0 {
+            return false;
+        }
+        let (indent, mode, doc) = match stack.pop() {
+            Some(command) => command,
+            None => match rest.next() {
+                Some(command) => *command,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(text) => match text.find('\n') {
+                Some(newline) => return text[..newline].chars().count() as isize <= remaining,
+                None => remaining -= text.chars().count() as isize,
+            },
+            Doc::Line if mode == Mode::Flat => remaining -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine => return true,
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::IfBreak(text) => {
+                if mode == Mode::Break {
+                    remaining -= text.chars().count() as isize;
+                }
+            }
+            // A trailing comment ends the line it is on.
+            Doc::LineSuffix(_) => {
+                if mode == Mode::Flat {
+                    return false;
+                }
+            }
+            Doc::Nest(doc) | Doc::Group(doc) => stack.push((indent, mode, doc)),
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent, mode, doc));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formatter::doc::{concat, group, join, nest, render, text, Doc};
+
+    #[test]
+    fn breaks_groups_that_do_not_fit() {
+        let list = |items: &[&str]| {
+            group(concat(vec![
+                text("["),
+                nest(concat(vec![
+                    Doc::SoftLine,
+                    join(
+                        items.iter().map(|item| text(*item)).collect(),
+                        concat(vec![text(","), Doc::Line]),
+                    ),
+                    Doc::IfBreak(",".to_string()),
+                ])),
+                Doc::SoftLine,
+                text("]"),
+            ]))
+        };
+        assert_eq!(render(&list(&["a", "b"]), 10), "[a, b]");
+        assert_eq!(
+            render(&list(&["alpha", "beta"]), 10),
+            "[\n    alpha,\n    beta,\n]"
+        );
+
+        let commented = concat(vec![
+            text("a;"),
+            Doc::LineSuffix(" // note".to_string()),
+            Doc::HardLine,
+            text("b;"),
+        ]);
+        assert_eq!(render(&commented, 80), "a; // note\nb;");
+    }
+}
@@ -0,0 +1,1028 @@
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::formatter::doc::{concat, group, join, nest, render, text, Doc};
+use crate::lexer::lexer::Lexer;
+use crate::lexer::symbol;
+use crate::lexer::token::{Comment, Span, Token, TokenType};
+use crate::parser::ast::display::type_params;
+use crate::parser::ast::{
+    Attribute, BinaryOp, Block, Expr, ExprKind, Function, MatchArm, Param, Pattern, PatternKind,
+    Program, Stmt, StmtKind, TypeExpr, TypeExprKind,
+};
+use crate::parser::macros;
+use crate::parser::parser::{ends_with_block, parse};
+
+// The column lines are wrapped at.
+pub const WIDTH: usize = 100;
+
+// Formats a file. The syntax tree gives the layout, and the source fills in
+// what the tree drops: comments, the spelling of literals, parentheses,
+// compound assignments and blank lines between statements.
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let program = parse(source)?;
+    let mut lexer = Lexer::new(source);
+    let tokens = (&mut lexer).collect::<Result<Vec<_>, _>>()?;
+    // The tree has the macros expanded, and printing it would lose them.
+    if macros::first_definition(&tokens).is_some() {
+        return Ok(reindent(source, &tokens));
+    }
+
+    let mut formatter = Formatter {
+        source,
+        comments: lexer.comments().to_vec(),
+        next: 0,
+    };
+    let mut formatted = render(&formatter.program(&program), WIDTH);
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+// Formats a file from its tokens rather than its tree: each line keeps its
+// text, indented by how many brackets are open where it starts, without
+// trailing spaces, and with no more than one blank line in a row. The lines
+// a multiline string goes on to are left as they are.
+fn reindent(source: &str, tokens: &[Token]) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    // Indexed by line number, from 1.
+    let mut depths = vec![0; lines.len() + 2];
+    let mut verbatim = vec![false; lines.len() + 2];
+    let mut open = 0usize;
+    let mut line = 0;
+    for token in tokens {
+        let closes = matches!(
+            token.kind,
+            TokenType::RParen | TokenType::RBrace | TokenType::RBracket
+        );
+        if token.span.start.line > line {
+            while line < token.span.start.line {
+                line += 1;
+                depths[line] = open;
+            }
+            if closes {
+                depths[line] = open.saturating_sub(1);
+            }
+        }
+        verbatim[token.span.start.line + 1..=token.span.end.line].fill(true);
+        match token.kind {
+            TokenType::LParen | TokenType::LBrace | TokenType::LBracket => open += 1,
+            _ if closes => open = open.saturating_sub(1),
+            _ => {}
+        }
+    }
+    for depth in &mut depths[line + 1..] {
+        *depth = open;
+    }
+
+    let mut formatted = String::new();
+    let mut blank = true;
+    for (index, text) in lines.iter().enumerate() {
+        let number = index + 1;
+        if verbatim[number] {
+            formatted.push_str(text);
+        } else if text.trim().is_empty() {
+            if !blank {
+                formatted.push('\n');
+                blank = true;
+            }
+            continue;
+        } else {
+            formatted.push_str(&"    ".repeat(depths[number]));
+            formatted.push_str(text.trim());
+        }
+        formatted.push('\n');
+        blank = false;
+    }
+    if formatted.ends_with("\n\n") {
+        formatted.pop();
+    }
+    formatted
+}
+
+// Something laid out on a line of its own.
+enum Item<'p> {
+    // One of the file's attributes.
+    Attribute(&'p Attribute),
+    // A statement, and whether it is followed by `;`.
+    Stmt(&'p Stmt, bool),
+    // A block's trailing expression.
+    Value(&'p Expr),
+    Arm(&'p MatchArm),
+}
+
+impl<'p> Item<'p> {
+    fn span(&self) -> Span {
+        match self {
+            Item::Attribute(attribute) => attribute.span,
+            Item::Stmt(stmt, _) => stmt.span,
+            Item::Value(expr) => expr.span,
+            Item::Arm(arm) => arm.span,
+        }
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment<'a>>,
+    // The first comment not printed yet.
+    next: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn program(&mut self, program: &Program) -> Doc {
+        let attributes = program.attributes.iter().map(Item::Attribute);
+        let statements = program
+            .statements
+            .iter()
+            .map(|stmt| Item::Stmt(stmt, !ends_with_block(stmt)));
+        let items: Vec<_> = attributes.chain(statements).collect();
+        self.lines(&items, self.source.len())
+    }
+
+    // Lays items out one per line with the comments around them, keeping one
+    // blank line wherever the source has any. Comments are printed before the
+    // item that follows them, or at the end of the line when they share it
+    // with an item; ones inside an item that nothing else printed end up
+    // there too, so none are lost.
+    fn lines(&mut self, items: &[Item], end: usize) -> Doc {
+        let mut docs = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            let span = item.span();
+            self.leading(&mut docs, span.start.char);
+            self.separate(&mut docs, span.start.char);
+            docs.push(self.item(item));
+
+            let next = items
+                .get(index + 1)
+                .map_or(end, |item| item.span().start.char);
+            while let Some(comment) = self.take_comment(|comment| {
+                let start = comment.span.start;
+                start.char < span.end.char || (start.line == span.end.line && start.char < next)
+            }) {
+                docs.push(Doc::LineSuffix(format!(" {}", comment.text)));
+            }
+        }
+        self.leading(&mut docs, end);
+        concat(docs)
+    }
+
+    // Prints the comments before `position` on lines of their own.
+    fn leading(&mut self, docs: &mut Vec<Doc>, position: usize) {
+        while let Some(comment) = self.take_comment(|comment| comment.span.start.char < position) {
+            self.separate(docs, comment.span.start.char);
+            docs.push(text(comment.text));
+        }
+    }
+
+    // Starts a new line unless nothing has been printed yet, leaving a blank
+    // line if the source has one before `position`.
+    fn separate(&self, docs: &mut Vec<Doc>, position: usize) {
+        if docs.is_empty() {
+            return;
+        }
+        docs.push(Doc::HardLine);
+        let before = &self.source[..position];
+        if before[before.trim_end().len()..].matches('\n').count() > 1 {
+            docs.push(Doc::HardLine);
+        }
+    }
+
+    fn take_comment(&mut self, take: impl Fn(&Comment) -> bool) -> Option<Comment<'a>> {
+        let comment = *self.comments.get(self.next)?;
+        if !take(&comment) {
+            return None;
+        }
+        self.next += 1;
+        Some(comment)
+    }
+
+    fn has_comment_before(&self, position: usize) -> bool {
+        self.comments
+            .get(self.next)
+            .is_some_and(|comment| comment.span.start.char < position)
+    }
+
+    fn item(&mut self, item: &Item) -> Doc {
+        match item {
+            Item::Attribute(attribute) => text(format!("{:#}", attribute)),
+            Item::Stmt(stmt, semicolon) => {
+                let doc = self.stmt(stmt);
+                if *semicolon {
+                    concat(vec![doc, text(";")])
+                } else {
+                    doc
+                }
+            }
+            Item::Value(expr) => self.expr(expr),
+            Item::Arm(arm) => {
+                let mut docs = vec![text(self.pattern(&arm.pattern))];
+                if let Some(guard) = &arm.guard {
+                    docs.push(text(" if "));
+                    docs.push(self.expr(guard));
+                }
+                docs.push(text(" => "));
+                docs.push(self.expr(&arm.body));
+                docs.push(text(","));
+                concat(docs)
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Doc {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Function(function) => self.function(function),
+            StmtKind::Import(import) => text(import.to_string()),
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                value,
+            } => {
+                let mut head = String::from("let ");
+                if *mutable {
+                    head.push_str("mut ");
+                }
+                head.push_str(name);
+                if let Some(ty) = ty {
+                    head.push_str(": ");
+                    head.push_str(&type_expr(ty));
+                }
+                head.push_str(" = ");
+                concat(vec![text(head), self.expr(value)])
+            }
+            StmtKind::Destructure {
+                pattern,
+                mutable,
+                ty,
+                value,
+            } => {
+                let mut head = String::from("let ");
+                if *mutable {
+                    head.push_str("mut ");
+                }
+                head.push_str(&self.pattern(pattern));
+                if let Some(ty) = ty {
+                    head.push_str(": ");
+                    head.push_str(&type_expr(ty));
+                }
+                head.push_str(" = ");
+                concat(vec![text(head), self.expr(value)])
+            }
+            StmtKind::Struct(decl) => {
+                let fields = decl
+                    .fields
+                    .iter()
+                    .map(|field| match &field.ty {
+                        Some(ty) => text(format!("{}: {}", field.name, type_expr(ty))),
+                        None => text(field.name.as_str()),
+                    })
+                    .collect();
+                let head = format!("struct {}{} ", decl.name, type_params(&decl.type_params));
+                attributed(&decl.attributes, concat(vec![text(head), braced(fields)]))
+            }
+            StmtKind::Enum(decl) => {
+                let variants = decl
+                    .variants
+                    .iter()
+                    .map(|variant| match variant.fields.len() {
+                        0 => text(variant.name.as_str()),
+                        _ => {
+                            let fields: Vec<_> = variant.fields.iter().map(type_expr).collect();
+                            text(format!("{}({})", variant.name, fields.join(", ")))
+                        }
+                    })
+                    .collect();
+                let head = format!("enum {}{} ", decl.name, type_params(&decl.type_params));
+                attributed(&decl.attributes, concat(vec![text(head), braced(variants)]))
+            }
+            StmtKind::Test { name, body } => concat(vec![
+                text(format!("test \"{}\" ", name)),
+                self.block(body, false),
+            ]),
+            StmtKind::Trait(decl) => {
+                let methods = decl
+                    .methods
+                    .iter()
+                    .map(|method| {
+                        let head = format!("fn {}", method.name);
+                        let mut docs = signature(head, &method.params, &method.returns);
+                        docs.push(text(";"));
+                        attributed(&method.attributes, concat(docs))
+                    })
+                    .collect();
+                let doc = concat(vec![
+                    text(format!("trait {} ", decl.name)),
+                    members(methods),
+                ]);
+                attributed(&decl.attributes, doc)
+            }
+            StmtKind::Impl(decl) => {
+                let methods = decl
+                    .methods
+                    .iter()
+                    .map(|method| self.function(method))
+                    .collect();
+                let doc = concat(vec![
+                    text(format!("impl {} for {} ", decl.trait_name, decl.target)),
+                    members(methods),
+                ]);
+                attributed(&decl.attributes, doc)
+            }
+        }
+    }
+
+    // Named functions always get their body on lines of its own; anonymous
+    // ones stay on one line when they fit.
+    fn function(&mut self, function: &Function) -> Doc {
+        let head = match &function.name {
+            Some(name) => format!("fn {}{}", name, type_params(&function.type_params)),
+            None => format!("fn{}", type_params(&function.type_params)),
+        };
+        let mut docs = signature(head, &function.params, &function.returns);
+        docs.push(text(" "));
+        docs.push(match function.name {
+            Some(_) => self.block(&function.body, false),
+            None => group(self.block(&function.body, true)),
+        });
+        attributed(&function.attributes, concat(docs))
+    }
+
+    // Lays out a block. Blocks holding only a value may share a line with
+    // the code around them if `flat` is set and the group they are in fits.
+    fn block(&mut self, block: &Block, flat: bool) -> Doc {
+        let end = block.span.end.char;
+        let comments = self.has_comment_before(end);
+        match (&block.statements[..], &block.value) {
+            ([], None) if !comments => return text("{}"),
+            ([], Some(value)) if flat && !comments => {
+                return concat(vec![
+                    text("{"),
+                    nest(concat(vec![Doc::Line, self.expr(value)])),
+                    Doc::Line,
+                    text("}"),
+                ]);
+            }
+            _ => {}
+        }
+
+        let last = block.statements.len();
+        let mut items: Vec<_> = block
+            .statements
+            .iter()
+            .enumerate()
+            .map(|(index, stmt)| {
+                // A block-like statement ending a block keeps its `;`, or it
+                // would become the block's value.
+                let ends_block = index + 1 == last
+                    && block.value.is_none()
+                    && matches!(stmt.kind, StmtKind::Expr(_));
+                Item::Stmt(stmt, !ends_with_block(stmt) || ends_block)
+            })
+            .collect();
+        if let Some(value) = &block.value {
+            items.push(Item::Value(value));
+        }
+        concat(vec![
+            text("{"),
+            nest(concat(vec![Doc::HardLine, self.lines(&items, end)])),
+            Doc::HardLine,
+            text("}"),
+        ])
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Doc {
+        let doc = match &expr.kind {
+            ExprKind::Integer(_) | ExprKind::Float(_) | ExprKind::String(_) | ExprKind::Bool(_) => {
+                text(self.literal(expr.span))
+            }
+            ExprKind::Ident(name) => text(name.as_str()),
+            ExprKind::Path(segments) => text(symbol::join(segments, "::")),
+            ExprKind::Tuple(elements) if elements.len() == 1 => {
+                concat(vec![text("("), self.expr(&elements[0]), text(",)")])
+            }
+            ExprKind::Tuple(elements) => {
+                let elements = elements.iter().map(|e| self.expr(e)).collect();
+                delimited("(", elements, ")")
+            }
+            ExprKind::List(elements) => {
+                let elements = elements.iter().map(|e| self.expr(e)).collect();
+                delimited("[", elements, "]")
+            }
+            ExprKind::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| concat(vec![self.expr(key), text(": "), self.expr(value)]))
+                    .collect();
+                delimited("#{", entries, "}")
+            }
+            ExprKind::Struct { path, fields } => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, value)| {
+                        if self.shorthand(value.span) {
+                            text(name.as_str())
+                        } else {
+                            concat(vec![text(format!("{}: ", name)), self.expr(value)])
+                        }
+                    })
+                    .collect();
+                concat(vec![
+                    text(format!("{} ", symbol::join(path, "::"))),
+                    braced(fields),
+                ])
+            }
+            ExprKind::Field { target, name } => {
+                concat(vec![self.expr(target), text(format!(".{}", name))])
+            }
+            ExprKind::Unary { op, operand } => {
+                concat(vec![text(op.to_string()), self.expr(operand)])
+            }
+            ExprKind::Binary { op, left, right } => self.binary(*op, left, right),
+            ExprKind::Assign { target, value } => match &value.kind {
+                // `x += y` is parsed as `x = x + y` where the `x + y` starts
+                // at the target.
+                ExprKind::Binary { op, right, .. }
+                    if value.span.start.char == target.span.start.char =>
+                {
+                    concat(vec![
+                        self.expr(target),
+                        text(format!(" {}= ", op)),
+                        self.expr(right),
+                    ])
+                }
+                _ => concat(vec![self.expr(target), text(" = "), self.expr(value)]),
+            },
+            // `value?` is parsed as a match that ends where the `?` does.
+            ExprKind::Match { scrutinee, .. } if self.is_try(expr) => {
+                concat(vec![self.expr(scrutinee), text("?")])
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let head = concat(vec![text("match "), self.expr(scrutinee), text(" {")]);
+                let end = expr.span.end.char;
+                if arms.is_empty() && !self.has_comment_before(end) {
+                    concat(vec![head, text("}")])
+                } else {
+                    let arms: Vec<_> = arms.iter().map(Item::Arm).collect();
+                    concat(vec![
+                        head,
+                        nest(concat(vec![Doc::HardLine, self.lines(&arms, end)])),
+                        Doc::HardLine,
+                        text("}"),
+                    ])
+                }
+            }
+            ExprKind::Block(block) => group(self.block(block, true)),
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Call { .. } if is_pipeline(expr) => self.pipeline(expr),
+            ExprKind::Call { callee, args } => {
+                let callee = self.expr(callee);
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                concat(vec![callee, delimited("(", args, ")")])
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let receiver = self.expr(receiver);
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                concat(vec![
+                    receiver,
+                    text(format!(".{}", method)),
+                    delimited("(", args, ")"),
+                ])
+            }
+            ExprKind::Index { target, index } => concat(vec![
+                self.expr(target),
+                text("["),
+                self.expr(index),
+                text("]"),
+            ]),
+            ExprKind::Slice { target, start, end } => {
+                let mut docs = vec![self.expr(target), text("[")];
+                if let Some(start) = start {
+                    docs.push(self.expr(start));
+                }
+                docs.push(text(".."));
+                if let Some(end) = end {
+                    docs.push(self.expr(end));
+                }
+                docs.push(text("]"));
+                concat(docs)
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => concat(vec![
+                self.expr(start),
+                text(if *inclusive { "..=" } else { ".." }),
+                self.expr(end),
+            ]),
+            ExprKind::Return(None) => text("return"),
+            ExprKind::Return(Some(value)) => concat(vec![text("return "), self.expr(value)]),
+            ExprKind::If { .. } => group(self.if_chain(expr)),
+            ExprKind::While { condition, body } => concat(vec![
+                text("while "),
+                self.expr(condition),
+                text(" "),
+                group(self.block(body, true)),
+            ]),
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => concat(vec![
+                text(format!("for {} in ", self.pattern(pattern))),
+                self.expr(iterable),
+                text(" "),
+                group(self.block(body, true)),
+            ]),
+            ExprKind::Break => text("break"),
+            ExprKind::Continue => text("continue"),
+        };
+
+        if self.parenthesized(expr) {
+            concat(vec![text("("), doc, text(")")])
+        } else {
+            doc
+        }
+    }
+
+    // An `if` and its `else` branches, which the caller groups so they break
+    // together.
+    fn if_chain(&mut self, expr: &Expr) -> Doc {
+        let (condition, then_branch, else_branch) = match &expr.kind {
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => (condition, then_branch, else_branch),
+            ExprKind::Block(block) => return self.block(block, true),
+            _ => return self.expr(expr),
+        };
+        let mut docs = vec![
+            text("if "),
+            self.expr(condition),
+            text(" "),
+            self.block(then_branch, true),
+        ];
+        if let Some(branch) = else_branch {
+            docs.push(text(" else "));
+            docs.push(self.if_chain(branch));
+        }
+        concat(docs)
+    }
+
+    // Lays out a run of operators of the same precedence, like `a + b - c`,
+    // breaking before each operator when it does not fit.
+    fn binary(&mut self, op: BinaryOp, left: &Expr, right: &Expr) -> Doc {
+        let mut operands = vec![(op, right)];
+        let mut first = left;
+        while let ExprKind::Binary { op, left, right } = &first.kind {
+            if op.precedence() != operands[0].0.precedence() || self.parenthesized(first) {
+                break;
+            }
+            operands.push((*op, right));
+            first = left;
+        }
+
+        let mut rest = Vec::new();
+        for (op, operand) in operands.into_iter().rev() {
+            rest.push(Doc::Line);
+            rest.push(text(format!("{} ", op)));
+            rest.push(self.expr(operand));
+        }
+        group(concat(vec![self.expr(first), nest(concat(rest))]))
+    }
+
+    // Lays out a run of pipeline stages, breaking before each `|>` when it
+    // does not fit.
+    fn pipeline(&mut self, expr: &Expr) -> Doc {
+        let mut stages = Vec::new();
+        let mut first = expr;
+        while let ExprKind::Call { callee, args } = &first.kind {
+            if !is_pipeline(first) || (!stages.is_empty() && self.parenthesized(first)) {
+                break;
+            }
+            stages.push((first, callee, &args[1..]));
+            first = &args[0];
+        }
+
+        let mut rest = Vec::new();
+        for (call, callee, args) in stages.into_iter().rev() {
+            rest.push(Doc::Line);
+            rest.push(text("|> "));
+            rest.push(self.expr(callee));
+            // `value |> f` has no parentheses of its own to keep.
+            let end = callee.span.end.char;
+            if self.source[end..call.span.end.char]
+                .trim_start()
+                .starts_with('(')
+            {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                rest.push(delimited("(", args, ")"));
+            }
+        }
+        group(concat(vec![self.expr(first), nest(concat(rest))]))
+    }
+
+    fn is_try(&self, expr: &Expr) -> bool {
+        self.source[..expr.span.end.char].ends_with('?')
+    }
+
+    // The parser keeps no node for parentheses, only the span they cover.
+    fn parenthesized(&self, expr: &Expr) -> bool {
+        if !self.source[expr.span.start.char..].starts_with('(') {
+            return false;
+        }
+        match &expr.kind {
+            ExprKind::Match { scrutinee, .. } if self.is_try(expr) => {
+                expr.span.start.char < scrutinee.span.start.char
+            }
+            ExprKind::Tuple(_) => false,
+            ExprKind::Call { args, .. } if is_pipeline(expr) => {
+                expr.span.start.char < args[0].span.start.char
+            }
+            ExprKind::Binary { left: first, .. }
+            | ExprKind::Assign { target: first, .. }
+            | ExprKind::Call { callee: first, .. }
+            | ExprKind::MethodCall {
+                receiver: first, ..
+            }
+            | ExprKind::Index { target: first, .. }
+            | ExprKind::Slice { target: first, .. }
+            | ExprKind::Field { target: first, .. }
+            | ExprKind::Range { start: first, .. } => expr.span.start.char < first.span.start.char,
+            _ => true,
+        }
+    }
+
+    // A literal as it is written, so `1_000` and `2f64` keep their spelling.
+    fn literal(&self, span: Span) -> String {
+        let paren = |c: char| c == '(' || c == ')' || c.is_whitespace();
+        self.source[span.start.char..span.end.char]
+            .trim_start_matches(paren)
+            .trim_end_matches(paren)
+            .to_string()
+    }
+
+    // Whether a struct field's value at `span` was written without `name:`,
+    // as in `Point { x, y }`.
+    fn shorthand(&self, span: Span) -> bool {
+        !self.source[..span.start.char].trim_end().ends_with(':')
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> String {
+        match &pattern.kind {
+            PatternKind::Wildcard => "_".to_string(),
+            PatternKind::Binding(name) => name.to_string(),
+            // `- 1` is printed as `-1`.
+            PatternKind::Integer(_) | PatternKind::Float(_) => {
+                self.literal(pattern.span).split_whitespace().collect()
+            }
+            PatternKind::String(_) | PatternKind::Bool(_) => self.literal(pattern.span),
+            PatternKind::Tuple(elements) if elements.len() == 1 => {
+                format!("({},)", self.pattern(&elements[0]))
+            }
+            PatternKind::Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(|e| self.pattern(e)).collect();
+                format!("({})", elements.join(", "))
+            }
+            PatternKind::Variant { path, fields } if fields.is_empty() => symbol::join(path, "::"),
+            PatternKind::Variant { path, fields } => {
+                let fields: Vec<_> = fields.iter().map(|f| self.pattern(f)).collect();
+                format!("{}({})", symbol::join(path, "::"), fields.join(", "))
+            }
+            PatternKind::Struct { path, fields, rest } => {
+                let mut fields: Vec<_> = fields
+                    .iter()
+                    .map(|(name, pattern)| {
+                        if self.shorthand(pattern.span) {
+                            name.to_string()
+                        } else {
+                            format!("{}: {}", name, self.pattern(pattern))
+                        }
+                    })
+                    .collect();
+                if *rest {
+                    fields.push("..".to_string());
+                }
+                if fields.is_empty() {
+                    format!("{} {{}}", symbol::join(path, "::"))
+                } else {
+                    format!("{} {{ {} }}", symbol::join(path, "::"), fields.join(", "))
+                }
+            }
+        }
+    }
+}
+
+// `value |> f(args)` is parsed as `f(value, args)`, with the value before the
+// callee.
+fn is_pipeline(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Call { callee, args } => args
+            .first()
+            .is_some_and(|value| value.span.start.char < callee.span.start.char),
+        _ => false,
+    }
+}
+
+// `open items close`, on one line if it fits and otherwise with each item on
+// a line of its own and a trailing comma.
+// A declaration with its attributes on the lines before it.
+fn attributed(attributes: &[Attribute], doc: Doc) -> Doc {
+    let mut docs = Vec::new();
+    for attribute in attributes {
+        docs.push(text(attribute.to_string()));
+        docs.push(Doc::HardLine);
+    }
+    docs.push(doc);
+    concat(docs)
+}
+
+fn delimited(open: &str, items: Vec<Doc>, close: &str) -> Doc {
+    if items.is_empty() {
+        return text(format!("{}{}", open, close));
+    }
+    group(concat(vec![
+        text(open),
+        nest(concat(vec![
+            Doc::SoftLine,
+            join(items, concat(vec![text(","), Doc::Line])),
+            Doc::IfBreak(",".to_string()),
+        ])),
+        Doc::SoftLine,
+        text(close),
+    ]))
+}
+
+// `{ items }` for struct fields, like `delimited` but with spaces inside the
+// braces when it fits on one line.
+fn braced(items: Vec<Doc>) -> Doc {
+    if items.is_empty() {
+        return text("{}");
+    }
+    group(concat(vec![
+        text("{"),
+        nest(concat(vec![
+            Doc::Line,
+            join(items, concat(vec![text(","), Doc::Line])),
+            Doc::IfBreak(",".to_string()),
+        ])),
+        Doc::Line,
+        text("}"),
+    ]))
+}
+
+// `head(params) -> returns`, shared by functions and the methods of traits.
+fn signature(head: String, params: &[Param], returns: &Option<TypeExpr>) -> Vec<Doc> {
+    let params = params
+        .iter()
+        .map(|param| match &param.ty {
+            Some(ty) => text(format!("{}: {}", param.name, type_expr(ty))),
+            None => text(param.name.as_str()),
+        })
+        .collect();
+    let mut docs = vec![text(head), delimited("(", params, ")")];
+    if let Some(returns) = returns {
+        docs.push(text(format!(" -> {}", type_expr(returns))));
+    }
+    docs
+}
+
+// The methods of a trait or impl, each on a line of its own.
+fn members(items: Vec<Doc>) -> Doc {
+    if items.is_empty() {
+        return text("{}");
+    }
+    concat(vec![
+        text("{"),
+        nest(concat(vec![Doc::HardLine, join(items, Doc::HardLine)])),
+        Doc::HardLine,
+        text("}"),
+    ])
+}
+
+fn type_expr(ty: &TypeExpr) -> String {
+    let list = |types: &[TypeExpr]| types.iter().map(type_expr).collect::<Vec<_>>().join(", ");
+    match &ty.kind {
+        TypeExprKind::Named { name, args } if args.is_empty() => name.to_string(),
+        TypeExprKind::Named { name, args } => format!("{}<{}>", name, list(args)),
+        TypeExprKind::Tuple(elements) => format!("({})", list(elements)),
+        TypeExprKind::Function { params, returns } => {
+            format!("Fn({}) -> {}", list(params), type_expr(returns))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formatter::formatter::format;
+
+    #[test]
+    fn formats_layout_and_spacing() {
+        let source = "let   x=1_000+2f64*( 3-1 );
+fn add(a:Int,b :Int)->Int{a+b}
+let xs=[1,2,3];let m=#{\"a\":1};
+if x>1{print(x);}else if x<0 {x-=1}else{0}
+match (x, 1) { (1,y) if y>0=>y, ( - 1 ,_)=>0,_=>x }
+let f = fn(n) { n * 2 };
+while true { break }
+";
+        let expected = "let x = 1_000 + 2f64 * (3 - 1);
+fn add(a: Int, b: Int) -> Int {
+    a + b
+}
+let xs = [1, 2, 3];
+let m = #{\"a\": 1};
+if x > 1 {
+    print(x);
+} else if x < 0 {
+    x -= 1
+} else {
+    0
+}
+match (x, 1) {
+    (1, y) if y > 0 => y,
+    (-1, _) => 0,
+    _ => x,
+}
+let f = fn(n) { n * 2 };
+while true { break }
+";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn reindents_files_that_define_macros() {
+        let source = "macro twice {
+($x) => { $x * 2 }   
+}
+
+
+  // doubled
+let s = \"a
+  b\";
+if true {
+        twice!(1)
+}";
+        let expected = "macro twice {
+    ($x) => { $x * 2 }
+}
+
+// doubled
+let s = \"a
+  b\";
+if true {
+    twice!(1)
+}
+";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+        assert!(format("macro m { () => { 1 } }\nm!(").is_err());
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source = "// header
+
+let a = 1; // one
+
+
+// about b
+let b = {
+    // inside
+    a + // stray
+        1
+};
+// the end
+";
+        let expected = "// header
+
+let a = 1; // one
+
+// about b
+let b = {
+    // inside
+    a + 1 // stray
+};
+// the end
+";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn keeps_the_shebang_line() {
+        let source = "#!/usr/bin/env clay\nprintln( args() );\n";
+        let expected = "#!/usr/bin/env clay\nprintln(args());\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let source = format!(
+            "let total = first_value_{0} + second_value_{0} + third_value_{0} + fourth_value_{0};\n\
+             call(first_argument_{0}, second_argument_{0}, third_argument_{0}, fourth_argument);\n",
+            "with_a_long_name"
+        );
+        let formatted = format(&source).unwrap();
+        assert_eq!(
+            formatted,
+            "let total = first_value_with_a_long_name
+    + second_value_with_a_long_name
+    + third_value_with_a_long_name
+    + fourth_value_with_a_long_name;
+call(
+    first_argument_with_a_long_name,
+    second_argument_with_a_long_name,
+    third_argument_with_a_long_name,
+    fourth_argument,
+);
+"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert!(formatted.lines().all(|line| line.len() <= 100));
+    }
+
+    #[test]
+    fn formats_structs() {
+        let source = "struct Point{x:Int,y}
+struct Empty{}
+let p=Point{x:1,y:y};p.x=Point {x, y:2}.x;
+match p{Point{x:0,..}=>1,geo::Point{x,y:_}=>2,Empty{}=>3}
+";
+        let expected = "struct Point { x: Int, y }
+struct Empty {}
+let p = Point { x: 1, y: y };
+p.x = Point { x, y: 2 }.x;
+match p {
+    Point { x: 0, .. } => 1,
+    geo::Point { x, y: _ } => 2,
+    Empty {} => 3,
+}
+";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_question_marks() {
+        let source = "fn f(a){let n=parse(a) ?.len()?;(a,n)?}\n";
+        let expected = "fn f(a) {\n    let n = parse(a)?.len()?;\n    (a, n)?\n}\n";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_ranges() {
+        let source = "for i in 0 .. n+1 {i}\nlet r=(1..=3).len();\n";
+        let expected = "for i in 0..n + 1 { i }\nlet r = (1..=3).len();\n";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_pipelines() {
+        let source = "let t=xs|>keep(p)  |>sum;\nlet n=(3|>f)*2;\n";
+        let expected = "let t = xs |> keep(p) |> sum;\nlet n = (3 |> f) * 2;\n";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_let_patterns() {
+        let source = "let mut(a,(b,_))=(1,(2,3));let Point{x,..}=p\n";
+        let expected = "let mut (a, (b, _)) = (1, (2, 3));\nlet Point { x, .. } = p;\n";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_attributes() {
+        let source = "// Header.\n@!allow( unused-variable )\n\n/// Adds.\n@deny(shadowed-binding,constant-condition) @inline\nfn add(a,b){a+b}\nimpl S for P{@allow(unused) fn s(self){1}}\n";
+        let expected = "// Header.\n@!allow(unused-variable)\n\n/// Adds.\n@deny(shadowed-binding, constant-condition)\n@inline\nfn add(a, b) {\n    a + b\n}\nimpl S for P {\n    @allow(unused)\n    fn s(self) {\n        1\n    }\n}\n";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_enums() {
+        let source = "enum Shape{Circle(Float),Rect(Float,Float),Empty,}
+match s{Shape::Circle(r)=>r,Shape::Rect(w,_)=>w,Shape::Empty=>Shape::Circle(0.0)}
+";
+        let expected = "enum Shape { Circle(Float), Rect(Float, Float), Empty }
+match s {
+    Shape::Circle(r) => r,
+    Shape::Rect(w, _) => w,
+    Shape::Empty => Shape::Circle(0.0),
+}
+";
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+}
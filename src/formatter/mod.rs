@@ -0,0 +1,3 @@
+pub mod doc;
+#[allow(clippy::module_inception)]
+pub mod formatter;
@@ -0,0 +1,232 @@
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::lexer::lexer::Lexer;
+use crate::lexer::token::{Span, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Class {
+    Keyword,
+    // Numbers, strings, `true` and `false`.
+    Literal,
+    Operator,
+    // Brackets and separators: `(`, `,`, `;`, `::` and the like.
+    Punctuation,
+    Comment,
+    Identifier,
+}
+
+// A classified piece of source.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Highlight {
+    pub class: Class,
+    pub span: Span,
+}
+
+impl Highlight {
+    // The bytes of the source it covers.
+    pub fn range(&self) -> Range<usize> {
+        self.span.start.char..self.span.end.char
+    }
+}
+
+// Classifies every token and comment in `source`, in source order. Text the
+// lexer rejects is left out, so half-typed code still gets highlighted.
+pub fn highlight(source: &str) -> Vec<Highlight> {
+    let mut lexer = Lexer::new(source);
+    let mut highlights: Vec<_> = (&mut lexer)
+        .filter_map(Result::ok)
+        .map(|token| Highlight {
+            class: classify(token.kind),
+            span: token.span,
+        })
+        .collect();
+    highlights.extend(lexer.comments().iter().map(|comment| Highlight {
+        class: Class::Comment,
+        span: comment.span,
+    }));
+    highlights.sort_by_key(|highlight| highlight.span.start.char);
+    highlights
+}
+
+pub fn classify(kind: TokenType) -> Class {
+    match kind {
+        TokenType::Integer(_)
+        | TokenType::Float(_)
+        | TokenType::String(_)
+        | TokenType::RawString(_)
+        | TokenType::MultilineString(_) => Class::Literal,
+        TokenType::True | TokenType::False => Class::Literal,
+        TokenType::Ident(_) => Class::Identifier,
+        TokenType::Fn
+        | TokenType::Return
+        | TokenType::If
+        | TokenType::Else
+        | TokenType::While
+        | TokenType::For
+        | TokenType::In
+        | TokenType::Break
+        | TokenType::Continue
+        | TokenType::Match
+        | TokenType::Import
+        | TokenType::Let
+        | TokenType::Mut
+        | TokenType::Struct
+        | TokenType::Enum
+        | TokenType::Trait
+        | TokenType::Impl => Class::Keyword,
+        TokenType::RParen
+        | TokenType::LParen
+        | TokenType::RBrace
+        | TokenType::LBrace
+        | TokenType::RBracket
+        | TokenType::LBracket
+        | TokenType::Period
+        | TokenType::Comma
+        | TokenType::Colon
+        | TokenType::ColonColon
+        | TokenType::Semicolon
+        | TokenType::Hash
+        | TokenType::At
+        | TokenType::Dollar => Class::Punctuation,
+        TokenType::Percent
+        | TokenType::Plus
+        | TokenType::Minus
+        | TokenType::Slash
+        | TokenType::Asterisk
+        | TokenType::Equal
+        | TokenType::DoubleEqual
+        | TokenType::FatArrow
+        | TokenType::Arrow
+        | TokenType::Bang
+        | TokenType::BangEqual
+        | TokenType::Less
+        | TokenType::LessEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::DotDot
+        | TokenType::DotDotEq
+        | TokenType::Ampersand
+        | TokenType::And
+        | TokenType::Bar
+        | TokenType::Or
+        | TokenType::Pipeline
+        | TokenType::Caret
+        | TokenType::Tilde
+        | TokenType::ShiftLeft
+        | TokenType::ShiftRight
+        | TokenType::PlusEqual
+        | TokenType::MinusEqual
+        | TokenType::SlashEqual
+        | TokenType::AsteriskEqual
+        | TokenType::AmpersandEqual
+        | TokenType::BarEqual
+        | TokenType::CaretEqual
+        | TokenType::ShiftLeftEqual
+        | TokenType::ShiftRightEqual
+        | TokenType::Question => Class::Operator,
+    }
+}
+
+// The token types `semantic_tokens` refers to by index, for the legend a
+// language server advertises.
+pub const LEGEND: [&str; 6] = [
+    "keyword", "string", "number", "operator", "comment", "variable",
+];
+
+// Encodes highlights as LSP semantic tokens: for each token, its line and
+// start relative to the previous token's, its length, its index in `LEGEND`
+// and no modifiers. Positions count UTF-16 code units, and a token spanning
+// lines, like a multi-line string, is sent as one token per line.
+// Punctuation has no semantic token type and is left out.
+pub fn semantic_tokens(source: &str, highlights: &[Highlight]) -> Vec<u32> {
+    let mut data = Vec::new();
+    let (mut previous_line, mut previous_start) = (0, 0);
+
+    for highlight in highlights {
+        let text = &source[highlight.range()];
+        let token_type = match highlight.class {
+            Class::Keyword => 0,
+            Class::Literal if text.starts_with('"') => 1,
+            Class::Literal if text.starts_with(|c: char| c.is_ascii_digit()) => 2,
+            // `true` and `false`.
+            Class::Literal => 0,
+            Class::Operator => 3,
+            Class::Comment => 4,
+            Class::Identifier => 5,
+            Class::Punctuation => continue,
+        };
+
+        let line_start = source[..highlight.span.start.char]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let first_line = highlight.span.start.line as u32 - 1;
+        let mut start = source[line_start..highlight.span.start.char]
+            .encode_utf16()
+            .count() as u32;
+        for (line, part) in (first_line..).zip(text.split('\n')) {
+            let length = part.encode_utf16().count() as u32;
+            if length > 0 {
+                let delta_start = if line == previous_line {
+                    start - previous_start
+                } else {
+                    start
+                };
+                data.extend([line - previous_line, delta_start, length, token_type, 0]);
+                previous_line = line;
+                previous_start = start;
+            }
+            start = 0;
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::highlight::{highlight, semantic_tokens, Class};
+
+    #[test]
+    fn classifies_tokens_and_comments() {
+        let source = "let x = 1; // one\nx += \"a\"";
+        let classes: Vec<_> = highlight(source)
+            .into_iter()
+            .map(|h| (h.class, &source[h.range()]))
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                (Class::Keyword, "let"),
+                (Class::Identifier, "x"),
+                (Class::Operator, "="),
+                (Class::Literal, "1"),
+                (Class::Punctuation, ";"),
+                (Class::Comment, "// one"),
+                (Class::Identifier, "x"),
+                (Class::Operator, "+="),
+                (Class::Literal, "\"a\""),
+            ]
+        );
+        // Text the lexer rejects is skipped.
+        assert_eq!(highlight("a \\ b").len(), 2);
+    }
+
+    #[test]
+    fn encodes_semantic_tokens() {
+        let source = "let s = \"😀\nb\"; true // c";
+        assert_eq!(
+            semantic_tokens(source, &highlight(source)),
+            vec![
+                0, 0, 3, 0, 0, // let
+                0, 4, 1, 5, 0, // s
+                0, 2, 1, 3, 0, // =
+                0, 2, 3, 1, 0, // "😀
+                1, 0, 2, 1, 0, // b"
+                0, 4, 4, 0, 0, // true
+                0, 5, 4, 4, 0, // // c
+            ]
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::convert::TryFrom;
+
+// Dates in the proleptic Gregorian calendar, in UTC, for `std::time`.
+// Timestamps count milliseconds from the Unix epoch, 1970-01-01T00:00:00Z,
+// and ignore leap seconds the way Unix time does.
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    // From 1 to 12.
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+    pub millisecond: i64,
+}
+
+impl DateTime {
+    pub fn from_timestamp(timestamp: i64) -> DateTime {
+        let (year, month, day) = civil_from_days(timestamp.div_euclid(MILLIS_PER_DAY));
+        let millis = timestamp.rem_euclid(MILLIS_PER_DAY);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: millis / 3_600_000,
+            minute: millis / 60_000 % 60,
+            second: millis / 1_000 % 60,
+            millisecond: millis % 1_000,
+        }
+    }
+
+    // Fails for dates too far from the epoch to count in milliseconds.
+    pub fn timestamp(&self) -> Option<i64> {
+        let days = days_from_civil(self.year, self.month, self.day)?;
+        let time = ((self.hour * 60 + self.minute) * 60 + self.second) * 1_000 + self.millisecond;
+        days.checked_mul(MILLIS_PER_DAY)?.checked_add(time)
+    }
+
+    // From 0 for Monday to 6 for Sunday.
+    fn weekday(&self) -> usize {
+        let days = days_from_civil(self.year, self.month, self.day).unwrap_or(0);
+        // The epoch was a Thursday.
+        (days + 3).rem_euclid(7) as usize
+    }
+
+    // From 1 for January 1st.
+    fn day_of_year(&self) -> i64 {
+        let first = days_from_civil(self.year, 1, 1).unwrap_or(0);
+        days_from_civil(self.year, self.month, self.day).unwrap_or(0) - first + 1
+    }
+}
+
+// The year, month and day of the day `days` after the epoch. This and
+// `days_from_civil` are Howard Hinnant's algorithms, which count in 400
+// year eras so that leap years fall out of the arithmetic.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Counting from March, so the leap day comes last.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146_097)?.checked_add(day_of_era - 719_468)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The letters of the fields a format can have after `%`.
+const FIELDS: &str = "YmdjHMSfsbBaA";
+
+// What a format is made of: characters to write or match as they are, and
+// the fields `%` introduces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Item {
+    Literal(char),
+    Field(char),
+}
+
+// `%F` and `%T` are short for `%Y-%m-%d` and `%H:%M:%S`.
+fn items(format: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            items.push(Item::Literal(c));
+            continue;
+        }
+        match chars.next() {
+            Some('%') => items.push(Item::Literal('%')),
+            Some('F') => items.extend(items_of("%Y-%m-%d")),
+            Some('T') => items.extend(items_of("%H:%M:%S")),
+            Some(field) if FIELDS.contains(field) => items.push(Item::Field(field)),
+            Some(other) => return Err(format!("unknown time format `%{}`", other)),
+            None => return Err("a time format can't end with `%`".to_string()),
+        }
+    }
+    Ok(items)
+}
+
+fn items_of(format: &'static str) -> Vec<Item> {
+    items(format).expect("the shorthands are valid")
+}
+
+// Writes the time at `timestamp` as `format` lays it out, like `strftime`:
+//
+// - `%Y` the year, `%m` the month, `%d` the day and `%j` the day of the
+//   year, as numbers;
+// - `%H`, `%M` and `%S` the hours, minutes and seconds, and `%f` the
+//   milliseconds;
+// - `%b` and `%B` the month's name, short and in full, and `%a` and `%A`
+//   the weekday's;
+// - `%s` the seconds since the epoch, and `%%` a `%`.
+pub fn format(timestamp: i64, format: &str) -> Result<String, String> {
+    let date = DateTime::from_timestamp(timestamp);
+    let mut text = String::new();
+    for item in items(format)? {
+        let field = match item {
+            Item::Literal(c) => {
+                text.push(c);
+                continue;
+            }
+            Item::Field(field) => field,
+        };
+        let written = match field {
+            'Y' if date.year < 0 => format!("-{:04}", -date.year),
+            'Y' => format!("{:04}", date.year),
+            'm' => format!("{:02}", date.month),
+            'd' => format!("{:02}", date.day),
+            'j' => format!("{:03}", date.day_of_year()),
+            'H' => format!("{:02}", date.hour),
+            'M' => format!("{:02}", date.minute),
+            'S' => format!("{:02}", date.second),
+            'f' => format!("{:03}", date.millisecond),
+            's' => timestamp.div_euclid(1_000).to_string(),
+            'b' => MONTHS[date.month as usize - 1][..3].to_string(),
+            'B' => MONTHS[date.month as usize - 1].to_string(),
+            'a' => WEEKDAYS[date.weekday()][..3].to_string(),
+            _ => WEEKDAYS[date.weekday()].to_string(),
+        };
+        text.push_str(&written);
+    }
+    Ok(text)
+}
+
+// Reads the timestamp of the time `text` writes as `format` lays it out,
+// taking the fields `format` reads. Fields it leaves out are those of the
+// epoch, and weekdays are read but not checked. `%j` can't be read.
+pub fn parse(text: &str, format: &str) -> Result<i64, String> {
+    let mismatch = || format!("`{}` does not match the format `{}`", text, format);
+    let mut date = DateTime::from_timestamp(0);
+    let mut seconds = None;
+    let mut rest = text;
+    for item in items(format)? {
+        let field = match item {
+            Item::Literal(c) => {
+                rest = rest.strip_prefix(c).ok_or_else(mismatch)?;
+                continue;
+            }
+            Item::Field(field) => field,
+        };
+        match field {
+            'b' | 'B' => {
+                let (month, after) = name(rest, &MONTHS).ok_or_else(mismatch)?;
+                date.month = month as i64 + 1;
+                rest = after;
+                continue;
+            }
+            'a' | 'A' => {
+                rest = name(rest, &WEEKDAYS).ok_or_else(mismatch)?.1;
+                continue;
+            }
+            'j' => return Err("`%j` can't be parsed".to_string()),
+            _ => {}
+        }
+        let (signed, digits) = match field {
+            'Y' => (true, 4),
+            's' => (true, 19),
+            'f' => (false, 3),
+            _ => (false, 2),
+        };
+        let (number, length, after) = number(rest, signed, digits).ok_or_else(mismatch)?;
+        rest = after;
+        match field {
+            'Y' => date.year = number,
+            'm' => date.month = number,
+            'd' => date.day = number,
+            'H' => date.hour = number,
+            'M' => date.minute = number,
+            'S' => date.second = number,
+            // Fractions of a second: `%f` reads "5" as 500 milliseconds.
+            'f' => date.millisecond = number * 10_i64.pow(3 - length as u32),
+            _ => seconds = Some(number),
+        }
+    }
+    if !rest.is_empty() {
+        return Err(mismatch());
+    }
+
+    if let Some(seconds) = seconds {
+        return seconds
+            .checked_mul(1_000)
+            .ok_or_else(|| format!("`{}` is too far from 1970", text));
+    }
+    let fields = [
+        ("month", date.month, 12),
+        ("day", date.day, days_in_month(date.year, date.month)),
+        ("hour", date.hour, 23),
+        ("minute", date.minute, 59),
+        ("second", date.second, 59),
+    ];
+    for (name, value, max) in fields {
+        let min = if matches!(name, "month" | "day") {
+            1
+        } else {
+            0
+        };
+        if value < min || value > max {
+            return Err(format!("{} {} is out of range in `{}`", name, value, text));
+        }
+    }
+    date.timestamp()
+        .ok_or_else(|| format!("`{}` is too far from 1970", text))
+}
+
+// The index of the name `text` starts with, matched without regard to case,
+// in full or by its first three letters, and the text after it.
+fn name<'a>(text: &'a str, names: &[&str]) -> Option<(usize, &'a str)> {
+    let starts = |prefix: &str| {
+        text.get(..prefix.len())
+            .filter(|start| start.eq_ignore_ascii_case(prefix))
+            .map(|_| &text[prefix.len()..])
+    };
+    names.iter().enumerate().find_map(|(index, name)| {
+        starts(name)
+            .or_else(|| starts(&name[..3]))
+            .map(|rest| (index, rest))
+    })
+}
+
+// The number of at most `digits` digits `text` starts with, how many digits
+// it had, and the text after it.
+fn number(text: &str, signed: bool, digits: usize) -> Option<(i64, usize, &str)> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) if signed => (true, rest),
+        _ => (false, text),
+    };
+    let length = text
+        .bytes()
+        .take(digits)
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if length == 0 {
+        return None;
+    }
+    let number = i64::try_from(text[..length].parse::<u64>().ok()?).ok()?;
+    let number = if negative { -number } else { number };
+    Some((number, length, &text[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::calendar::{format, parse, DateTime};
+
+    #[test]
+    fn converts_between_timestamps_and_dates() {
+        let cases = [
+            (0, (1970, 1, 1, 0, 0, 0, 0)),
+            (951_825_600_000, (2000, 2, 29, 12, 0, 0, 0)),
+            (-1, (1969, 12, 31, 23, 59, 59, 999)),
+            (-62_135_596_800_000, (1, 1, 1, 0, 0, 0, 0)),
+            (4_107_542_400_123, (2100, 3, 1, 0, 0, 0, 123)),
+        ];
+        for (timestamp, (year, month, day, hour, minute, second, millisecond)) in cases {
+            let date = DateTime {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                millisecond,
+            };
+            assert_eq!(DateTime::from_timestamp(timestamp), date);
+            assert_eq!(date.timestamp(), Some(timestamp));
+        }
+    }
+
+    #[test]
+    fn formats_and_parses_times() {
+        let timestamp = 1_714_558_245_067;
+        assert_eq!(
+            format(timestamp, "%a %d %b %Y, %T.%f (%j) 100%%").unwrap(),
+            "Wed 01 May 2024, 10:10:45.067 (122) 100%"
+        );
+        assert_eq!(
+            format(timestamp, "%A %B %s").unwrap(),
+            "Wednesday May 1714558245"
+        );
+        assert_eq!(format(-1, "%F %T").unwrap(), "1969-12-31 23:59:59");
+        assert_eq!(format(0, "%q").unwrap_err(), "unknown time format `%q`");
+
+        assert_eq!(parse("2024-05-01 10:10:45.067", "%F %T.%f"), Ok(timestamp));
+        assert_eq!(
+            parse("wednesday, 1 MAY 2024", "%A, %d %b %Y"),
+            Ok(1_714_521_600_000)
+        );
+        assert_eq!(parse("20240501", "%Y%m%d"), Ok(1_714_521_600_000));
+        assert_eq!(parse("12:30", "%H:%M"), Ok(45_000_000));
+        assert_eq!(parse("1714558245", "%s"), Ok(1_714_558_245_000));
+        assert_eq!(parse("1.5", "%S.%f"), Ok(1_500));
+
+        let errors = [
+            ("2024-02-30", "%F", "day 30 is out of range in `2024-02-30`"),
+            ("2024-05", "%F", "`2024-05` does not match the format `%F`"),
+            (
+                "2024-05-01x",
+                "%F",
+                "`2024-05-01x` does not match the format `%F`",
+            ),
+            ("24:00", "%H:%M", "hour 24 is out of range in `24:00`"),
+        ];
+        for (text, pattern, message) in errors {
+            assert_eq!(parse(text, pattern).unwrap_err(), message);
+        }
+    }
+}
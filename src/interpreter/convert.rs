@@ -0,0 +1,201 @@
+use crate::interpreter::gc;
+use crate::interpreter::value::Value;
+
+// A clay value that isn't what a conversion expected, such as an argument of
+// the wrong type passed to a host function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    // What was expected, with an article: "an integer".
+    pub expected: &'static str,
+    // The type name of what was found.
+    pub found: &'static str,
+}
+
+fn mismatch(expected: &'static str, found: &Value) -> Mismatch {
+    Mismatch {
+        expected,
+        found: found.type_name(),
+    }
+}
+
+// Rust types a clay value can be converted to, for the arguments of host
+// functions and the results of clay code.
+pub trait FromClay: Sized {
+    fn from_clay(value: &Value) -> Result<Self, Mismatch>;
+}
+
+// Rust types that can be handed to clay code.
+pub trait IntoClay {
+    fn into_clay(self) -> Value;
+}
+
+impl FromClay for Value {
+    fn from_clay(value: &Value) -> Result<Value, Mismatch> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoClay for Value {
+    fn into_clay(self) -> Value {
+        self
+    }
+}
+
+impl FromClay for () {
+    fn from_clay(value: &Value) -> Result<(), Mismatch> {
+        match value {
+            Value::Unit => Ok(()),
+            other => Err(mismatch("unit", other)),
+        }
+    }
+}
+
+impl IntoClay for () {
+    fn into_clay(self) -> Value {
+        Value::Unit
+    }
+}
+
+impl FromClay for bool {
+    fn from_clay(value: &Value) -> Result<bool, Mismatch> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            other => Err(mismatch("a bool", other)),
+        }
+    }
+}
+
+impl IntoClay for bool {
+    fn into_clay(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromClay for i64 {
+    fn from_clay(value: &Value) -> Result<i64, Mismatch> {
+        match value {
+            Value::Integer(n) => Ok(*n),
+            other => Err(mismatch("an integer", other)),
+        }
+    }
+}
+
+impl IntoClay for i64 {
+    fn into_clay(self) -> Value {
+        Value::Integer(self)
+    }
+}
+
+// Integers convert to floats, as they do in arithmetic.
+impl FromClay for f64 {
+    fn from_clay(value: &Value) -> Result<f64, Mismatch> {
+        match value {
+            Value::Float(n) => Ok(*n),
+            Value::Integer(n) => Ok(*n as f64),
+            other => Err(mismatch("a number", other)),
+        }
+    }
+}
+
+impl IntoClay for f64 {
+    fn into_clay(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromClay for String {
+    fn from_clay(value: &Value) -> Result<String, Mismatch> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            other => Err(mismatch("a string", other)),
+        }
+    }
+}
+
+impl IntoClay for String {
+    fn into_clay(self) -> Value {
+        gc::build(self.len());
+        Value::String(self)
+    }
+}
+
+impl IntoClay for &str {
+    fn into_clay(self) -> Value {
+        self.to_string().into_clay()
+    }
+}
+
+// Copies the list's items, so changes to the `Vec` aren't seen by clay.
+impl<T: FromClay> FromClay for Vec<T> {
+    fn from_clay(value: &Value) -> Result<Vec<T>, Mismatch> {
+        match value {
+            Value::List(list) => list.borrow().iter().map(T::from_clay).collect(),
+            other => Err(mismatch("a list", other)),
+        }
+    }
+}
+
+impl<T: IntoClay> IntoClay for Vec<T> {
+    fn into_clay(self) -> Value {
+        Value::list(self.into_iter().map(IntoClay::into_clay).collect())
+    }
+}
+
+// `None` is unit.
+impl<T: FromClay> FromClay for Option<T> {
+    fn from_clay(value: &Value) -> Result<Option<T>, Mismatch> {
+        match value {
+            Value::Unit => Ok(None),
+            value => T::from_clay(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoClay> IntoClay for Option<T> {
+    fn into_clay(self) -> Value {
+        self.map_or(Value::Unit, IntoClay::into_clay)
+    }
+}
+
+impl<A: FromClay, B: FromClay> FromClay for (A, B) {
+    fn from_clay(value: &Value) -> Result<(A, B), Mismatch> {
+        match value {
+            Value::Tuple(values) if values.len() == 2 => {
+                Ok((A::from_clay(&values[0])?, B::from_clay(&values[1])?))
+            }
+            other => Err(mismatch("a pair", other)),
+        }
+    }
+}
+
+impl<A: IntoClay, B: IntoClay> IntoClay for (A, B) {
+    fn into_clay(self) -> Value {
+        Value::Tuple(vec![self.0.into_clay(), self.1.into_clay()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::convert::{FromClay, IntoClay, Mismatch};
+    use crate::interpreter::value::Value;
+
+    #[test]
+    fn converts_values_both_ways() {
+        let value = vec![(1_i64, "one".to_string()), (2, "two".to_string())].into_clay();
+        assert_eq!(value.to_string(), "[(1, one), (2, two)]");
+        let back: Vec<(i64, String)> = FromClay::from_clay(&value).unwrap();
+        assert_eq!(back, vec![(1, "one".to_string()), (2, "two".to_string())]);
+
+        assert_eq!(f64::from_clay(&Value::Integer(2)), Ok(2.0));
+        assert_eq!(Option::<bool>::from_clay(&Value::Unit), Ok(None));
+        // A mismatch inside a list names the item that didn't convert.
+        let err = Vec::<i64>::from_clay(&vec!["1"].into_clay()).unwrap_err();
+        assert_eq!(
+            err,
+            Mismatch {
+                expected: "an integer",
+                found: "string"
+            }
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::interpreter::environment::Environment;
+use crate::interpreter::value::Value;
+use crate::lexer::token::Span;
+
+// One activation on the interpreter's call stack, innermost last.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub file: Option<PathBuf>,
+    // The statement the frame is executing.
+    pub span: Span,
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+// Called by the interpreter before it executes each statement. A debugger
+// pauses execution simply by not returning until it wants to resume.
+pub trait Debugger {
+    fn on_statement(&mut self, frames: &[Frame]);
+}
+
+// How a paused program goes on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    StepIn,
+    // Runs the rest of the statement, calls included.
+    StepOver,
+    // Runs the rest of the function.
+    StepOut,
+}
+
+// Why a program paused, by the names the Debug Adapter Protocol gives them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Entry,
+    Step,
+    Breakpoint,
+}
+
+impl Stop {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stop::Entry => "entry",
+            Stop::Step => "step",
+            Stop::Breakpoint => "breakpoint",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    Entry,
+    StepIn,
+    // Stop at the next statement whose stack is at most this deep.
+    StepOver(usize),
+    StepOut(usize),
+}
+
+// Where a debugger pauses the program it runs: at breakpoints, by file and
+// line, and wherever stepping ends.
+#[derive(Debug, Clone)]
+pub struct Stops {
+    breakpoints: HashMap<PathBuf, BTreeSet<usize>>,
+    mode: Mode,
+}
+
+impl Default for Stops {
+    fn default() -> Stops {
+        Stops {
+            breakpoints: HashMap::new(),
+            mode: Mode::Continue,
+        }
+    }
+}
+
+impl Stops {
+    pub fn new() -> Stops {
+        Stops::default()
+    }
+
+    // Pauses before the program's first statement.
+    pub fn stop_on_entry(&mut self) {
+        self.mode = Mode::Entry;
+    }
+
+    // Replaces the breakpoints in `file` with ones on `lines`.
+    pub fn set_breakpoints(&mut self, file: &Path, lines: impl IntoIterator<Item = usize>) {
+        self.breakpoints
+            .insert(canonical(file), lines.into_iter().collect());
+    }
+
+    // Returns false if there already is one.
+    pub fn add_breakpoint(&mut self, file: &Path, line: usize) -> bool {
+        self.breakpoints
+            .entry(canonical(file))
+            .or_default()
+            .insert(line)
+    }
+
+    // Returns false if there was none.
+    pub fn remove_breakpoint(&mut self, file: &Path, line: usize) -> bool {
+        let file = canonical(file);
+        match self.breakpoints.get_mut(&file) {
+            Some(lines) => lines.remove(&line),
+            None => false,
+        }
+    }
+
+    // Every breakpoint, sorted by file and line.
+    pub fn breakpoints(&self) -> Vec<(PathBuf, usize)> {
+        let mut breakpoints: Vec<_> = self
+            .breakpoints
+            .iter()
+            .flat_map(|(file, lines)| lines.iter().map(move |line| (file.clone(), *line)))
+            .collect();
+        breakpoints.sort();
+        breakpoints
+    }
+
+    // Resumes the program paused with `frames` on the stack.
+    pub fn resume(&mut self, resume: Resume, frames: &[Frame]) {
+        let depth = frames.len();
+        self.mode = match resume {
+            Resume::Continue => Mode::Continue,
+            Resume::StepIn => Mode::StepIn,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+        };
+    }
+
+    // Whether to pause before the statement the innermost of `frames` is
+    // about to execute, and why.
+    pub fn stop(&self, frames: &[Frame]) -> Option<Stop> {
+        let depth = frames.len();
+        match self.mode {
+            Mode::Entry => return Some(Stop::Entry),
+            Mode::StepIn => return Some(Stop::Step),
+            Mode::StepOver(max) if depth <= max => return Some(Stop::Step),
+            Mode::StepOut(max) if depth < max => return Some(Stop::Step),
+            _ => {}
+        }
+
+        let frame = frames.last()?;
+        let lines = self.breakpoints.get(frame.file.as_ref()?)?;
+        if lines.contains(&frame.span.start.line) {
+            Some(Stop::Breakpoint)
+        } else {
+            None
+        }
+    }
+}
+
+// Frames name the files they run by their canonical paths.
+fn canonical(file: &Path) -> PathBuf {
+    file.canonicalize().unwrap_or_else(|_| file.to_path_buf())
+}
+
+// Every name visible from `environment`, innermost bindings shadowing outer
+// ones.
+pub fn bindings(environment: &Rc<RefCell<Environment>>) -> Vec<(String, Value)> {
+    let mut seen = HashSet::new();
+    let mut bindings = Vec::new();
+    let mut scope = Some(environment.clone());
+    while let Some(environment) = scope {
+        let environment = environment.borrow();
+        let mut names: Vec<_> = environment
+            .bindings()
+            .filter(|(name, _)| seen.insert(*name))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings.extend(names);
+        scope = environment.parent().cloned();
+    }
+    bindings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use crate::interpreter::debug::{Frame, Resume, Stop, Stops};
+    use crate::interpreter::environment::Environment;
+    use crate::lexer::token::{Position, Span};
+
+    fn frames(lines: &[usize]) -> Vec<Frame> {
+        lines
+            .iter()
+            .map(|line| {
+                let start = Position::new(*line, 0, 0);
+                Frame {
+                    name: "f".to_string(),
+                    file: Some(PathBuf::from("/main.clay")),
+                    span: Span::new(start, start),
+                    environment: Rc::new(RefCell::new(Environment::new())),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_after_steps() {
+        let file = Path::new("/main.clay");
+        let mut stops = Stops::new();
+        assert_eq!(stops.stop(&frames(&[1])), None);
+        stops.stop_on_entry();
+        assert_eq!(stops.stop(&frames(&[1])), Some(Stop::Entry));
+
+        stops.resume(Resume::Continue, &frames(&[1]));
+        assert!(stops.add_breakpoint(file, 3));
+        assert!(!stops.add_breakpoint(file, 3));
+        assert_eq!(stops.stop(&frames(&[2])), None);
+        assert_eq!(stops.stop(&frames(&[1, 3])), Some(Stop::Breakpoint));
+        stops.set_breakpoints(file, [5, 4]);
+        assert_eq!(stops.breakpoints(), [(file.into(), 4), (file.into(), 5)]);
+        assert!(!stops.remove_breakpoint(file, 3));
+        assert!(stops.remove_breakpoint(file, 5));
+
+        stops.resume(Resume::StepOver, &frames(&[1, 2]));
+        assert_eq!(stops.stop(&frames(&[1, 2, 7])), None);
+        assert_eq!(stops.stop(&frames(&[1, 3])), Some(Stop::Step));
+        stops.resume(Resume::StepOut, &frames(&[1, 2]));
+        assert_eq!(stops.stop(&frames(&[1, 3])), None);
+        assert_eq!(stops.stop(&frames(&[2])), Some(Stop::Step));
+        stops.resume(Resume::StepIn, &frames(&[2]));
+        assert_eq!(stops.stop(&frames(&[2, 1])), Some(Stop::Step));
+    }
+}
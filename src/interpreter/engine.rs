@@ -0,0 +1,417 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::convert::{FromClay, IntoClay, Mismatch};
+use crate::interpreter::interpreter::Interpreter;
+use crate::interpreter::io::{StdIo, StdoutIo};
+use crate::interpreter::limits::{Limit, Limits};
+use crate::interpreter::system::{FixedSystem, HostSystem};
+use crate::interpreter::value::{Native, Value};
+use crate::lexer::token::{Position, Span};
+use crate::parser::parser::parse;
+
+// Clay embedded in a Rust program. The host registers functions for clay
+// code to call, runs source, and calls back into the functions it defined:
+//
+//     let mut engine = Engine::new();
+//     engine.register_fn("double", |n: i64| n * 2);
+//     engine.run("fn quadruple(n) { double(double(n)) }")?;
+//     let sixteen: i64 = engine.call("quadruple", (4_i64,))?;
+//
+// Sources run one after another share their top-level bindings, like the
+// entries of a REPL.
+#[derive(Default)]
+pub struct Engine {
+    interpreter: Interpreter,
+    limits: Limits,
+}
+
+// Why running clay code from the host failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    // The code, or the host's call into it, was wrong.
+    Error(Diagnostic),
+    // The code went past one of the engine's limits and was stopped where
+    // the diagnostic points. Boxed so that results stay as small as other
+    // results with diagnostics.
+    LimitExceeded(Limit, Box<Diagnostic>),
+}
+
+impl RuntimeError {
+    pub fn diagnostic(&self) -> &Diagnostic {
+        match self {
+            RuntimeError::Error(diagnostic) => diagnostic,
+            RuntimeError::LimitExceeded(_, diagnostic) => diagnostic,
+        }
+    }
+}
+
+impl From<Diagnostic> for RuntimeError {
+    fn from(diagnostic: Diagnostic) -> RuntimeError {
+        RuntimeError::Error(diagnostic)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.diagnostic().fmt(f)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    // The interpreter underneath, for everything else a host can configure,
+    // like its `Io` and native modules.
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    // Bounds what each `run`, `eval` and `call` may use, so that code the
+    // host doesn't trust can't hang it or exhaust its memory.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Makes code compute the same results wherever and whenever it runs,
+    // for hosts using clay for configuration: `env` finds no variables,
+    // `std::os` describes an unknown machine, `std::time` stays at the
+    // epoch, `read_line` reads nothing and files can't be read or written.
+    // Output still goes to stdout. A host can then give code deterministic
+    // answers of its own with `set_system` and `set_io` on the interpreter.
+    // Turning it off goes back to the real machine, undoing those too.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        if deterministic {
+            self.interpreter.set_system(Rc::new(FixedSystem::new()));
+            self.interpreter.set_io(Rc::new(RefCell::new(StdoutIo)));
+        } else {
+            self.interpreter.set_system(Rc::new(HostSystem));
+            self.interpreter.set_io(Rc::new(RefCell::new(StdIo)));
+        }
+    }
+
+    // Makes a Rust function callable from clay as `name`. Its arguments are
+    // converted with `FromClay` and checked when it is called, and its result
+    // is converted with `IntoClay`. It can fail by returning a `Result`,
+    // whose error becomes the message of a clay error.
+    pub fn register_fn<Args, F: HostFn<Args>>(&mut self, name: &'static str, function: F) {
+        let native = Native::new(name, F::ARITY, move |args, span| {
+            function.call(name, args, span)
+        });
+        self.interpreter
+            .register_builtin(name, Value::Native(native));
+    }
+
+    pub fn run(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        let program = parse(source)?;
+        self.interpreter.set_limits(self.limits);
+        let result = self.interpreter.run(&program);
+        self.limited(result)
+    }
+
+    // Runs `source` and converts the value it evaluates to.
+    pub fn eval<T: FromClay>(&mut self, source: &str) -> Result<T, RuntimeError> {
+        let program = parse(source)?;
+        self.interpreter.set_limits(self.limits);
+        let result = self.interpreter.run(&program);
+        let value = self.limited(result)?;
+        let span = match (program.statements.first(), program.statements.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => host(),
+        };
+        Ok(convert(&value, span)?)
+    }
+
+    // Calls the clay function bound to `name` with `args`, a tuple of
+    // values that convert to clay.
+    pub fn call<T: FromClay>(
+        &mut self,
+        name: &str,
+        args: impl IntoArgs,
+    ) -> Result<T, RuntimeError> {
+        let function = self
+            .interpreter
+            .global(name)
+            .ok_or_else(|| Diagnostic::error(format!("unknown function `{}`", name), host()))?;
+        self.interpreter.set_limits(self.limits);
+        let result = self
+            .interpreter
+            .call_function(function, args.into_args(), host());
+        let value = self.limited(result)?;
+        Ok(convert(&value, host())?)
+    }
+
+    // Tells an error from code stopped at a limit apart from other errors.
+    fn limited(&self, result: Result<Value, Diagnostic>) -> Result<Value, RuntimeError> {
+        result.map_err(|diagnostic| match self.interpreter.exceeded() {
+            Some(limit) => RuntimeError::LimitExceeded(limit, Box::new(diagnostic)),
+            None => RuntimeError::Error(diagnostic),
+        })
+    }
+}
+
+// Errors about the host's own calls have no place in clay source, so they
+// point at its start.
+fn host() -> Span {
+    let start = Position::new(1, 0, 0);
+    Span::new(start, start)
+}
+
+fn convert<T: FromClay>(value: &Value, span: Span) -> Result<T, Diagnostic> {
+    T::from_clay(value).map_err(|mismatch| {
+        Diagnostic::error(
+            format!("expected {}, found {}", mismatch.expected, mismatch.found),
+            span,
+        )
+    })
+}
+
+fn argument<T: FromClay>(
+    args: &[Value],
+    index: usize,
+    name: &str,
+    span: Span,
+) -> Result<T, Diagnostic> {
+    T::from_clay(&args[index]).map_err(|Mismatch { expected, found }| {
+        Diagnostic::error(
+            format!("`{}` expects {}, found {}", name, expected, found),
+            span,
+        )
+    })
+}
+
+// What a host function returns: anything that converts to clay, or a
+// `Result` of it.
+pub trait HostResult {
+    fn into_result(self) -> Result<Value, String>;
+}
+
+impl<T: IntoClay> HostResult for T {
+    fn into_result(self) -> Result<Value, String> {
+        Ok(self.into_clay())
+    }
+}
+
+impl<T: IntoClay, E: fmt::Display> HostResult for Result<T, E> {
+    fn into_result(self) -> Result<Value, String> {
+        self.map(IntoClay::into_clay).map_err(|err| err.to_string())
+    }
+}
+
+// A Rust function clay can call, taking `Args`, a tuple of its argument
+// types. Implemented for closures of up to four arguments.
+pub trait HostFn<Args>: 'static {
+    const ARITY: usize;
+
+    fn call(&self, name: &str, args: &[Value], span: Span) -> Result<Value, Diagnostic>;
+}
+
+// Arguments for calling a clay function from Rust: a tuple of values that
+// convert to clay, or the clay values themselves.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+// Implements `HostFn` and `IntoArgs` for a tuple of `$arity` types.
+macro_rules! tuple_impls {
+    ($arity:expr; $($arg:ident $index:tt),*) => {
+        impl<F, R, $($arg: FromClay),*> HostFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: HostResult,
+        {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, args: &[Value], span: Span) -> Result<Value, Diagnostic> {
+                let result = self($(argument::<$arg>(args, $index, name, span)?),*);
+                result
+                    .into_result()
+                    .map_err(|message| Diagnostic::error(message, span))
+            }
+        }
+
+        impl<$($arg: IntoClay),*> IntoArgs for ($($arg,)*) {
+            fn into_args(self) -> Vec<Value> {
+                vec![$(self.$index.into_clay()),*]
+            }
+        }
+    };
+}
+
+tuple_impls!(0;);
+tuple_impls!(1; A 0);
+tuple_impls!(2; A 0, B 1);
+tuple_impls!(3; A 0, B 1, C 2);
+tuple_impls!(4; A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::interpreter::engine::{Engine, RuntimeError};
+    use crate::interpreter::limits::{Limit, Limits};
+    use crate::interpreter::system::FixedSystem;
+
+    #[test]
+    fn calls_between_rust_and_clay() {
+        let mut engine = Engine::new();
+        engine.register_fn("double", |n: i64| n * 2);
+        engine.register_fn("greet", |name: String, times: i64| {
+            if times < 0 {
+                return Err(format!("cannot greet {} {} times", name, times));
+            }
+            Ok(vec![format!("hi {}", name); times as usize])
+        });
+        engine.register_fn("answer", || 42_i64);
+
+        engine.run("fn quadruple(n) { double(double(n)) }").unwrap();
+        assert_eq!(engine.call::<i64>("quadruple", (4_i64,)).unwrap(), 16);
+        let greetings: Vec<String> = engine.eval("greet(\"ada\", 2)").unwrap();
+        assert_eq!(greetings, vec!["hi ada", "hi ada"]);
+        assert_eq!(engine.eval::<i64>("answer() + 1").unwrap(), 43);
+
+        let err = engine.run("greet(\"ada\", -1)").unwrap_err();
+        assert_eq!(err.diagnostic().message, "cannot greet ada -1 times");
+        let err = engine.run("double(\"two\")").unwrap_err();
+        assert_eq!(
+            err.diagnostic().message,
+            "`double` expects an integer, found string"
+        );
+        let err = engine.run("double()").unwrap_err();
+        assert_eq!(
+            err.diagnostic().message,
+            "`double` expects 1 argument, found 0"
+        );
+        let err = engine.eval::<String>("double(1)").unwrap_err();
+        assert_eq!(err.diagnostic().message, "expected a string, found integer");
+        let err = engine.call::<i64>("missing", ()).unwrap_err();
+        assert_eq!(err.diagnostic().message, "unknown function `missing`");
+    }
+
+    #[test]
+    fn stops_code_that_goes_past_its_limits() {
+        let limited = |limits: Limits, source: &str| {
+            let mut engine = Engine::new();
+            engine.set_limits(limits);
+            engine.run(source)
+        };
+        let steps = Limits {
+            steps: Some(10_000),
+            ..Limits::default()
+        };
+        match limited(steps, "while true {}").unwrap_err() {
+            RuntimeError::LimitExceeded(limit, diagnostic) => {
+                assert_eq!(limit, Limit::Steps(10_000));
+                assert_eq!(diagnostic.message, "the program took more than 10000 steps");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let depth = Limits {
+            depth: Some(5),
+            ..Limits::default()
+        };
+        let source = "fn down(n) { if n == 0 { 0 } else { 1 + down(n - 1) } }";
+        let err = limited(depth, &format!("{}\ndown(10)", source)).unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::LimitExceeded(Limit::Depth(5), _)
+        ));
+        assert_eq!(
+            limited(depth, &format!("{}\ndown(4)", source))
+                .unwrap()
+                .to_string(),
+            "4"
+        );
+
+        let allocations = Limits {
+            allocations: Some(100),
+            ..Limits::default()
+        };
+        let source = "let mut all = []; while true { all.push([1]) }";
+        let err = limited(allocations, source).unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::LimitExceeded(Limit::Allocations(100), _)
+        ));
+
+        // Strings count by their bytes, so one that doubles each step is
+        // stopped long before it fills memory.
+        let bytes = Limits {
+            bytes: Some(1 << 20),
+            ..Limits::default()
+        };
+        let err = limited(bytes, "let mut s = \"ab\"; while true { s = s + s }").unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::LimitExceeded(Limit::Bytes(_), _)
+        ));
+
+        let timeout = Limits {
+            timeout: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        };
+        let err = limited(timeout, "while true {}").unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::LimitExceeded(Limit::Timeout(_), _)
+        ));
+
+        // Each run gets the limits afresh, and other errors stay errors.
+        let mut engine = Engine::new();
+        engine.set_limits(steps);
+        engine
+            .run("fn spin(n) { let mut i = 0; while i < n { i = i + 1 } }")
+            .unwrap();
+        for _ in 0..3 {
+            engine.call::<()>("spin", (1_000_i64,)).unwrap();
+        }
+        assert!(matches!(
+            engine.call::<()>("spin", (100_000_i64,)),
+            Err(RuntimeError::LimitExceeded(..))
+        ));
+        assert!(matches!(engine.run("1 / 0"), Err(RuntimeError::Error(_))));
+    }
+
+    #[test]
+    fn gives_the_same_answers_everywhere_when_deterministic() {
+        let source = "import std::os; import std::time;
+            (env(\"PATH\"), os::platform, os::family, time::now(), time::clock(), read_line())";
+        let mut engine = Engine::new();
+        engine.set_deterministic(true);
+        assert_eq!(
+            engine.run(source).unwrap().to_string(),
+            "(Option::None, unknown, unknown, 0, 0.0, )"
+        );
+        for source in [
+            "read_file(\"Cargo.toml\")",
+            "import std::os; os::home_dir()",
+        ] {
+            assert!(engine.run(source).is_err(), "{}", source);
+        }
+
+        let mut system = FixedSystem::new();
+        system.set_var("MODE", "release");
+        engine.interpreter_mut().set_system(Rc::new(system));
+        let release: bool = engine.eval("env(\"MODE\") == Some(\"release\")").unwrap();
+        assert!(release);
+
+        engine.set_deterministic(false);
+        let platform: String = engine.eval("import std::os; os::platform").unwrap();
+        assert_eq!(platform, std::env::consts::OS);
+    }
+}
@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::value::Value;
+use crate::lexer::symbol::Symbol;
+
+#[derive(Debug)]
+struct Binding {
+    value: Value,
+    mutable: bool,
+}
+
+// The outcome of assigning to a name.
+#[derive(Debug, PartialEq)]
+pub enum Assignment {
+    Assigned,
+    Immutable,
+    Unbound,
+}
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<Symbol, Binding>,
+    parent: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    pub fn with_parent(parent: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            values: HashMap::new(),
+            parent: Some(parent),
+        }
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        match self.values.get(&name) {
+            Some(binding) => Some(binding.value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (Symbol, &Value)> {
+        self.values
+            .iter()
+            .map(|(&name, binding)| (name, &binding.value))
+    }
+
+    // Every name bound here or in an enclosing scope.
+    pub fn visible(&self) -> Vec<Symbol> {
+        let mut names: Vec<Symbol> = self.values.keys().copied().collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.borrow().visible());
+        }
+        names
+    }
+
+    pub fn parent(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.parent.as_ref()
+    }
+
+    // Binds a mutable name, replacing any binding of it in this scope.
+    pub fn define(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.declare(name, value, true);
+    }
+
+    // Binds a name as `let` does. A new binding shadows any earlier one of
+    // the same name, in this scope or an enclosing one.
+    pub fn declare(&mut self, name: impl Into<Symbol>, value: Value, mutable: bool) {
+        self.values.insert(name.into(), Binding { value, mutable });
+    }
+
+    // Updates the innermost existing binding of `name`.
+    pub fn assign(&mut self, name: Symbol, value: Value) -> Assignment {
+        if let Some(binding) = self.values.get_mut(&name) {
+            if !binding.mutable {
+                return Assignment::Immutable;
+            }
+            binding.value = value;
+            return Assignment::Assigned;
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().assign(name, value),
+            None => Assignment::Unbound,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::interpreter::value::Value;
+
+// TOML and YAML, for scripts that edit configuration files. They convert
+// values the way `json` does, and each is behind the cargo feature of its
+// name. Without it, the functions fail.
+
+#[cfg(feature = "toml")]
+pub fn to_toml(value: &Value) -> Result<String, String> {
+    toml::to_string(value).map_err(|err| err.to_string())
+}
+
+// A TOML document is always a table, so this returns a map.
+#[cfg(feature = "toml")]
+pub fn from_toml(text: &str) -> Result<Value, String> {
+    match toml::from_str::<toml::Table>(text) {
+        Ok(table) => Ok(toml_value(toml::Value::Table(table))),
+        Err(err) => Err(match err.span() {
+            Some(span) => {
+                let line = text[..span.start].matches('\n').count() + 1;
+                format!("{} at line {}", err.message().trim_end(), line)
+            }
+            None => err.message().trim_end().to_string(),
+        }),
+    }
+}
+
+// Dates and times, which clay has no values for, become strings.
+#[cfg(feature = "toml")]
+fn toml_value(value: toml::Value) -> Value {
+    use std::collections::BTreeMap;
+
+    use crate::interpreter::value::Key;
+
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(n) => Value::Integer(n),
+        toml::Value::Float(n) => Value::Float(n),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(values) => Value::list(values.into_iter().map(toml_value).collect()),
+        toml::Value::Table(table) => Value::map(
+            table
+                .into_iter()
+                .map(|(key, value)| (Key::String(key), toml_value(value)))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+    }
+}
+
+#[cfg(not(feature = "toml"))]
+pub fn to_toml(_value: &Value) -> Result<String, String> {
+    Err(missing("toml"))
+}
+
+#[cfg(not(feature = "toml"))]
+pub fn from_toml(_text: &str) -> Result<Value, String> {
+    Err(missing("toml"))
+}
+
+#[cfg(feature = "yaml")]
+pub fn to_yaml(value: &Value) -> Result<String, String> {
+    serde_yaml::to_string(value).map_err(|err| err.to_string())
+}
+
+// Maps in YAML can have integer and bool keys, which clay maps keep, but
+// tagged values have nothing to become and fail.
+#[cfg(feature = "yaml")]
+pub fn from_yaml(text: &str) -> Result<Value, String> {
+    serde_yaml::from_str(text).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "yaml"))]
+pub fn to_yaml(_value: &Value) -> Result<String, String> {
+    Err(missing("yaml"))
+}
+
+#[cfg(not(feature = "yaml"))]
+pub fn from_yaml(_text: &str) -> Result<Value, String> {
+    Err(missing("yaml"))
+}
+
+#[cfg(not(all(feature = "toml", feature = "yaml")))]
+fn missing(feature: &str) -> String {
+    format!("clay was built without the `{}` feature", feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::formats::{from_toml, from_yaml, to_toml, to_yaml};
+    use crate::interpreter::value::Value;
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn reads_and_writes_toml() {
+        let text = "
+            name = \"web\"
+            started = 2024-05-01T10:00:00Z
+
+            [server]
+            ports = [80, 443]
+            ratio = 0.5
+        ";
+        let value = from_toml(text).unwrap();
+        assert_eq!(
+            value.to_string(),
+            "#{ name: web, server: #{ ports: [80, 443], ratio: 0.5 }, started: 2024-05-01T10:00:00Z }"
+        );
+        let value = from_toml("name = 'web'\nserver = { ports = [80, 443] }").unwrap();
+        assert_eq!(
+            to_toml(&value).unwrap(),
+            "name = \"web\"\n\n[server]\nports = [80, 443]\n"
+        );
+
+        assert_eq!(
+            from_toml("a = 1\nb = ").unwrap_err(),
+            "string values must be quoted, expected literal string at line 2"
+        );
+        assert!(to_toml(&Value::Integer(1)).is_err());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn reads_and_writes_yaml() {
+        let text = "name: web\nports:\n  - 80\n  - 443\n1: one\nextra: ~\n";
+        assert_eq!(
+            from_yaml(text).unwrap().to_string(),
+            "#{ 1: one, extra: Option::None, name: web, ports: [80, 443] }"
+        );
+        let value = from_yaml("{ name: web, ports: [80, 443] }").unwrap();
+        assert_eq!(to_yaml(&value).unwrap(), "name: web\nports:\n- 80\n- 443\n");
+        assert!(from_yaml("a: [1").is_err());
+    }
+
+    #[cfg(not(all(feature = "toml", feature = "yaml")))]
+    #[test]
+    fn fails_without_the_features() {
+        let results = [
+            (cfg!(feature = "toml"), from_toml("").map(|_| ())),
+            (cfg!(feature = "toml"), to_toml(&Value::Unit).map(|_| ())),
+            (cfg!(feature = "yaml"), from_yaml("").map(|_| ())),
+            (cfg!(feature = "yaml"), to_yaml(&Value::Unit).map(|_| ())),
+        ];
+        for (enabled, result) in results {
+            if !enabled {
+                let err = result.unwrap_err();
+                assert!(err.starts_with("clay was built without the"), "{}", err);
+            }
+        }
+    }
+}
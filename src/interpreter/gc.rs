@@ -0,0 +1,418 @@
+// Values are reference counted: a list, map, struct or function is freed as
+// soon as nothing refers to it any more. Counting alone never frees a cycle,
+// such as a list pushed into itself or a function defined inside another
+// one, which its environment refers back to. So every shared object that
+// can be part of a cycle is also tracked here, and every so often `collect`
+// finds the groups of objects that only refer to each other and clears them,
+// which frees them.
+//
+// References from outside clay's values can't be seen, such as those a host
+// program, a native function or a vm closure holds. Whatever they refer to
+// is kept, along with everything it refers to: the collector only ever frees
+// what nothing could still reach.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::interpreter::environment::Environment;
+use crate::interpreter::value::{Closure, Instance, Key, Value, Variant};
+
+// How many objects are tracked before the first collection. Later ones wait
+// until twice as many are tracked as survived the last.
+const THRESHOLD: usize = 10_000;
+
+// A shared object that can be part of a cycle.
+pub trait Collectable {
+    fn tracked(object: &Rc<Self>) -> Tracked;
+}
+
+// A tracked object, held weakly so that tracking doesn't keep it alive.
+pub enum Tracked {
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<BTreeMap<Key, Value>>>),
+    Struct(Weak<Instance>),
+    Variant(Weak<Variant>),
+    Function(Weak<Closure>),
+    Environment(Weak<RefCell<Environment>>),
+}
+
+impl Collectable for RefCell<Vec<Value>> {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::List(Rc::downgrade(object))
+    }
+}
+
+impl Collectable for RefCell<BTreeMap<Key, Value>> {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::Map(Rc::downgrade(object))
+    }
+}
+
+impl Collectable for Instance {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::Struct(Rc::downgrade(object))
+    }
+}
+
+impl Collectable for Variant {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::Variant(Rc::downgrade(object))
+    }
+}
+
+impl Collectable for Closure {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::Function(Rc::downgrade(object))
+    }
+}
+
+impl Collectable for RefCell<Environment> {
+    fn tracked(object: &Rc<Self>) -> Tracked {
+        Tracked::Environment(Rc::downgrade(object))
+    }
+}
+
+struct Registry {
+    tracked: Vec<Tracked>,
+    threshold: usize,
+    // How many objects were ever tracked.
+    allocated: u64,
+    // How many bytes the strings ever built hold, which aren't tracked but
+    // count towards a `Budget` all the same.
+    built: u64,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = const {
+        RefCell::new(Registry {
+            tracked: Vec::new(),
+            threshold: THRESHOLD,
+            allocated: 0,
+            built: 0,
+        })
+    };
+}
+
+// Tracks a newly allocated object, collecting cycles first if enough have
+// been tracked since the last collection.
+pub fn track<T: Collectable>(object: &Rc<T>) {
+    let due = REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.tracked.push(T::tracked(object));
+        registry.allocated += 1;
+        registry.tracked.len() >= registry.threshold
+    });
+    if due {
+        collect();
+    }
+}
+
+// Frees every tracked object that only unreachable objects refer to, and
+// returns how many there were.
+pub fn collect() -> usize {
+    // Each live object once, with where it is in `objects`.
+    let mut index = HashMap::new();
+    let objects: Vec<Object> = REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let objects: Vec<_> = registry
+            .tracked
+            .iter()
+            .filter_map(Tracked::upgrade)
+            .filter(|object| {
+                let next = index.len();
+                *index.entry(object.address()).or_insert(next) == next
+            })
+            .collect();
+        registry.tracked = objects.iter().map(Object::downgrade).collect();
+        objects
+    });
+
+    // Subtracting the references tracked objects hold to each other from
+    // their reference counts leaves the references from elsewhere. One of
+    // each count is the collector's own.
+    let mut outside: Vec<usize> = objects
+        .iter()
+        .map(|object| object.strong_count() - 1)
+        .collect();
+    let mut alive = vec![false; objects.len()];
+    for (i, object) in objects.iter().enumerate() {
+        let seen = object.references(&mut |address| {
+            if let Some(&j) = index.get(&address) {
+                outside[j] -= 1;
+            }
+        });
+        // An object in use can't be looked into, so it is kept.
+        if !seen {
+            alive[i] = true;
+        }
+    }
+
+    // What is referred to from elsewhere is alive, and so is everything it
+    // refers to.
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&i| outside[i] > 0 || alive[i])
+        .collect();
+    for &i in &pending {
+        alive[i] = true;
+    }
+    while let Some(i) = pending.pop() {
+        objects[i].references(&mut |address| {
+            if let Some(&j) = index.get(&address) {
+                if !alive[j] {
+                    alive[j] = true;
+                    pending.push(j);
+                }
+            }
+        });
+    }
+
+    // Clearing the rest breaks their cycles; they are freed once `objects`
+    // lets go of them.
+    let mut freed = 0;
+    for (object, alive) in objects.iter().zip(&alive) {
+        if !alive {
+            object.clear();
+            freed += 1;
+        }
+    }
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.threshold = THRESHOLD.max(2 * (objects.len() - freed));
+    });
+    freed
+}
+
+// How many objects were ever tracked on this thread, freed ones included.
+pub fn allocated() -> u64 {
+    REGISTRY.with(|registry| registry.borrow().allocated)
+}
+
+// Counts a newly built string of `bytes` bytes.
+pub fn build(bytes: usize) {
+    REGISTRY.with(|registry| registry.borrow_mut().built += bytes as u64);
+}
+
+// How many bytes the strings built on this thread held, freed ones included.
+pub fn built() -> u64 {
+    REGISTRY.with(|registry| registry.borrow().built)
+}
+
+// How many tracked objects are still alive.
+pub fn tracked() -> usize {
+    REGISTRY.with(|registry| {
+        registry
+            .borrow()
+            .tracked
+            .iter()
+            .filter(|tracked| tracked.upgrade().is_some())
+            .count()
+    })
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        Some(match self {
+            Tracked::List(list) => Object::List(list.upgrade()?),
+            Tracked::Map(map) => Object::Map(map.upgrade()?),
+            Tracked::Struct(instance) => Object::Struct(instance.upgrade()?),
+            Tracked::Variant(variant) => Object::Variant(variant.upgrade()?),
+            Tracked::Function(closure) => Object::Function(closure.upgrade()?),
+            Tracked::Environment(environment) => Object::Environment(environment.upgrade()?),
+        })
+    }
+}
+
+enum Object {
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<Key, Value>>>),
+    Struct(Rc<Instance>),
+    Variant(Rc<Variant>),
+    Function(Rc<Closure>),
+    Environment(Rc<RefCell<Environment>>),
+}
+
+impl Object {
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Object::List(list) => Tracked::List(Rc::downgrade(list)),
+            Object::Map(map) => Tracked::Map(Rc::downgrade(map)),
+            Object::Struct(instance) => Tracked::Struct(Rc::downgrade(instance)),
+            Object::Variant(variant) => Tracked::Variant(Rc::downgrade(variant)),
+            Object::Function(closure) => Tracked::Function(Rc::downgrade(closure)),
+            Object::Environment(environment) => Tracked::Environment(Rc::downgrade(environment)),
+        }
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Object::List(list) => Rc::as_ptr(list) as *const (),
+            Object::Map(map) => Rc::as_ptr(map) as *const (),
+            Object::Struct(instance) => Rc::as_ptr(instance) as *const (),
+            Object::Variant(variant) => Rc::as_ptr(variant) as *const (),
+            Object::Function(closure) => Rc::as_ptr(closure) as *const (),
+            Object::Environment(environment) => Rc::as_ptr(environment) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::List(list) => Rc::strong_count(list),
+            Object::Map(map) => Rc::strong_count(map),
+            Object::Struct(instance) => Rc::strong_count(instance),
+            Object::Variant(variant) => Rc::strong_count(variant),
+            Object::Function(closure) => Rc::strong_count(closure),
+            Object::Environment(environment) => Rc::strong_count(environment),
+        }
+    }
+
+    // Calls `visit` with the address of each shared object this one refers
+    // to, once per reference. Returns false if the object is borrowed
+    // mutably, so what it refers to can't be seen.
+    fn references(&self, visit: &mut dyn FnMut(*const ())) -> bool {
+        match self {
+            Object::List(list) => match list.try_borrow() {
+                Ok(values) => values
+                    .iter()
+                    .for_each(|value| value_references(value, visit)),
+                Err(_) => return false,
+            },
+            Object::Map(map) => match map.try_borrow() {
+                Ok(entries) => entries
+                    .values()
+                    .for_each(|value| value_references(value, visit)),
+                Err(_) => return false,
+            },
+            Object::Struct(instance) => match instance.fields.try_borrow() {
+                Ok(fields) => fields
+                    .iter()
+                    .for_each(|value| value_references(value, visit)),
+                Err(_) => return false,
+            },
+            Object::Variant(variant) => variant
+                .fields
+                .iter()
+                .for_each(|value| value_references(value, visit)),
+            Object::Function(closure) => visit(Rc::as_ptr(&closure.environment) as *const ()),
+            Object::Environment(environment) => match environment.try_borrow() {
+                Ok(environment) => {
+                    for (_, value) in environment.bindings() {
+                        value_references(value, visit);
+                    }
+                    if let Some(parent) = environment.parent() {
+                        visit(Rc::as_ptr(parent) as *const ());
+                    }
+                }
+                Err(_) => return false,
+            },
+        }
+        true
+    }
+
+    // Drops what the object holds. Variants and functions hold nothing
+    // mutable, but they are only in a cycle through something that does.
+    fn clear(&self) {
+        match self {
+            Object::List(list) => {
+                if let Ok(mut values) = list.try_borrow_mut() {
+                    drop(mem::take(&mut *values));
+                }
+            }
+            Object::Map(map) => {
+                if let Ok(mut entries) = map.try_borrow_mut() {
+                    drop(mem::take(&mut *entries));
+                }
+            }
+            Object::Struct(instance) => {
+                if let Ok(mut fields) = instance.fields.try_borrow_mut() {
+                    drop(mem::take(&mut *fields));
+                }
+            }
+            Object::Environment(environment) => {
+                if let Ok(mut environment) = environment.try_borrow_mut() {
+                    drop(mem::take(&mut *environment));
+                }
+            }
+            Object::Variant(_) | Object::Function(_) => {}
+        }
+    }
+}
+
+fn value_references(value: &Value, visit: &mut dyn FnMut(*const ())) {
+    match value {
+        Value::List(list) => visit(Rc::as_ptr(list) as *const ()),
+        Value::Map(map) => visit(Rc::as_ptr(map) as *const ()),
+        Value::Struct(instance) => visit(Rc::as_ptr(instance) as *const ()),
+        Value::Variant(variant) => visit(Rc::as_ptr(variant) as *const ()),
+        Value::Function(closure) => visit(Rc::as_ptr(closure) as *const ()),
+        Value::Tuple(values) => values
+            .iter()
+            .for_each(|value| value_references(value, visit)),
+        // Modules, native and host values and vm closures aren't tracked.
+        // What they refer to counts as referred to from elsewhere.
+        Value::Module(_)
+        | Value::Native(_)
+        | Value::Host(_)
+        | Value::Compiled(_)
+        | Value::Integer(_)
+        | Value::Float(_)
+        | Value::String(_)
+        | Value::Bool(_)
+        | Value::Unit
+        | Value::StructType(_)
+        | Value::EnumType(_)
+        | Value::Constructor(..)
+        | Value::Range(..) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::interpreter::gc::{collect, tracked};
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::value::Value;
+    use crate::parser::parser::parse;
+
+    #[test]
+    fn frees_a_list_that_contains_itself() {
+        let list = Value::list(Vec::new());
+        let weak = match &list {
+            Value::List(items) => {
+                items.borrow_mut().push(list.clone());
+                Rc::downgrade(items)
+            }
+            _ => unreachable!(),
+        };
+        // The host still holds it.
+        assert_eq!(collect(), 0);
+        drop(list);
+        assert!(weak.upgrade().is_some());
+        assert_eq!(collect(), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn frees_the_cycles_a_program_leaves_behind() {
+        let mut interpreter = Interpreter::new();
+        let mut run = |source: &str| interpreter.run(&parse(source).unwrap()).unwrap();
+        run("fn leak() {
+                let xs = [];
+                xs.push(Some(xs));
+                fn count() { xs.len() }
+                count
+            }
+            let kept = leak();");
+
+        run("for i in 0..10 { leak(); }");
+        collect();
+        let live = tracked();
+        run("for i in 0..1000 { leak(); }");
+        collect();
+        assert_eq!(tracked(), live);
+        // What is still reachable survives.
+        assert_eq!(run("kept()"), Value::Integer(1));
+    }
+}
@@ -0,0 +1,354 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::interpreter::environment::Environment;
+use crate::interpreter::module::Module;
+use crate::interpreter::value::{Closure, Instance, Key, Value};
+
+// A dump of every shared object reachable from the interpreter's globals.
+// Strings, tuples and enum variants live inline in the value holding them and count towards
+// its size. Object ids are only meaningful within one snapshot; snapshots are
+// compared by retaining path instead.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeapSnapshot {
+    pub objects: Vec<HeapObject>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeapObject {
+    pub id: usize,
+    pub kind: String,
+    // Approximate shallow size in bytes.
+    pub size: usize,
+    // The shortest chain of bindings that keeps the object alive, such as
+    // `xs[2]` or `util::cache`.
+    pub path: String,
+    pub references: Vec<usize>,
+}
+
+impl HeapSnapshot {
+    pub fn total_size(&self) -> usize {
+        self.objects.iter().map(|object| object.size).sum()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).expect("snapshots always serialize");
+        fs::write(path, json)
+            .map_err(|err| format!("could not write `{}`: {}", path.display(), err))
+    }
+
+    pub fn load(path: &Path) -> Result<HeapSnapshot, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("could not read `{}`: {}", path.display(), err))?;
+        serde_json::from_str(&json)
+            .map_err(|err| format!("`{}` is not a heap snapshot: {}", path.display(), err))
+    }
+}
+
+pub fn snapshot(globals: &Rc<RefCell<Environment>>) -> HeapSnapshot {
+    let mut builder = Builder::default();
+    builder.add(
+        Node::Environment(globals.clone()),
+        "<globals>".to_string(),
+        "",
+    );
+
+    while let Some((id, node, prefix)) = builder.queue.pop_front() {
+        let mut children = Vec::new();
+        node.children(&builder.objects[id].path, &prefix, &mut children);
+        for (child, path, prefix) in children {
+            let child = builder.add(child, path, &prefix);
+            builder.objects[id].references.push(child);
+        }
+    }
+
+    HeapSnapshot {
+        objects: builder.objects,
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    ids: HashMap<*const (), usize>,
+    objects: Vec<HeapObject>,
+    // Objects whose references are still to be walked, breadth first so that
+    // the first path to reach an object is a shortest one.
+    queue: VecDeque<(usize, Node, String)>,
+}
+
+impl Builder {
+    fn add(&mut self, node: Node, path: String, prefix: &str) -> usize {
+        if let Some(id) = self.ids.get(&node.address()) {
+            return *id;
+        }
+        let id = self.objects.len();
+        self.ids.insert(node.address(), id);
+        self.objects.push(HeapObject {
+            id,
+            kind: node.kind().to_string(),
+            size: node.size(),
+            path,
+            references: Vec::new(),
+        });
+        self.queue.push_back((id, node, prefix.to_string()));
+        id
+    }
+}
+
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<Key, Value>>>),
+    Closure(Rc<Closure>),
+    Module(Rc<Module>),
+    Struct(Rc<Instance>),
+}
+
+impl Node {
+    fn address(&self) -> *const () {
+        match self {
+            Node::Environment(environment) => Rc::as_ptr(environment) as *const (),
+            Node::List(list) => Rc::as_ptr(list) as *const (),
+            Node::Map(map) => Rc::as_ptr(map) as *const (),
+            Node::Closure(closure) => Rc::as_ptr(closure) as *const (),
+            Node::Module(module) => Rc::as_ptr(module) as *const (),
+            Node::Struct(instance) => Rc::as_ptr(instance) as *const (),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Environment(_) => "environment",
+            Node::List(_) => "list",
+            Node::Map(_) => "map",
+            Node::Closure(_) => "function",
+            Node::Module(_) => "module",
+            Node::Struct(_) => "struct",
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Node::Environment(environment) => {
+                mem::size_of::<Environment>()
+                    + environment
+                        .borrow()
+                        .bindings()
+                        .map(|(name, value)| name.len() + value_size(value))
+                        .sum::<usize>()
+            }
+            Node::List(list) => {
+                mem::size_of::<Vec<Value>>() + list.borrow().iter().map(value_size).sum::<usize>()
+            }
+            Node::Map(map) => {
+                mem::size_of::<BTreeMap<Key, Value>>()
+                    + map
+                        .borrow()
+                        .iter()
+                        .map(|(key, value)| value_size(&key.to_value()) + value_size(value))
+                        .sum::<usize>()
+            }
+            Node::Closure(_) => mem::size_of::<Closure>(),
+            Node::Module(module) => mem::size_of::<Module>() + module.name.len(),
+            Node::Struct(instance) => {
+                mem::size_of::<Instance>()
+                    + instance
+                        .fields
+                        .borrow()
+                        .iter()
+                        .map(value_size)
+                        .sum::<usize>()
+            }
+        }
+    }
+
+    // Collects the objects this one refers to along with their paths. Names
+    // bound in an environment are reached as `prefix` followed by the name.
+    fn children(&self, path: &str, prefix: &str, children: &mut Vec<(Node, String, String)>) {
+        match self {
+            Node::Environment(environment) => {
+                let environment = environment.borrow();
+                let mut bindings: Vec<_> = environment.bindings().collect();
+                bindings.sort_by_key(|a| a.0);
+                for (name, value) in bindings {
+                    value_children(value, format!("{}{}", prefix, name), children);
+                }
+                if let Some(parent) = environment.parent() {
+                    let path = format!("{}.<parent>", path);
+                    let prefix = format!("{}.", path);
+                    children.push((Node::Environment(parent.clone()), path, prefix));
+                }
+            }
+            Node::List(list) => {
+                for (i, value) in list.borrow().iter().enumerate() {
+                    value_children(value, format!("{}[{}]", path, i), children);
+                }
+            }
+            Node::Map(map) => {
+                for (key, value) in map.borrow().iter() {
+                    value_children(value, format!("{}[{}]", path, key.to_value()), children);
+                }
+            }
+            Node::Closure(closure) => {
+                let path = format!("{}.<env>", path);
+                let prefix = format!("{}.", path);
+                children.push((Node::Environment(closure.environment.clone()), path, prefix));
+            }
+            Node::Module(module) => {
+                let path = format!("{}.<env>", path);
+                let prefix = format!("{}::", module.name);
+                children.push((Node::Environment(module.environment.clone()), path, prefix));
+            }
+            Node::Struct(instance) => {
+                let fields = instance.fields.borrow();
+                for (name, value) in instance.ty.fields.iter().zip(fields.iter()) {
+                    value_children(value, format!("{}.{}", path, name), children);
+                }
+            }
+        }
+    }
+}
+
+fn value_children(value: &Value, path: String, children: &mut Vec<(Node, String, String)>) {
+    match value {
+        Value::List(list) => children.push((Node::List(list.clone()), path, String::new())),
+        Value::Map(map) => children.push((Node::Map(map.clone()), path, String::new())),
+        Value::Function(closure) => {
+            children.push((Node::Closure(closure.clone()), path, String::new()))
+        }
+        Value::Module(module) => children.push((Node::Module(module.clone()), path, String::new())),
+        Value::Struct(instance) => {
+            children.push((Node::Struct(instance.clone()), path, String::new()))
+        }
+        Value::Tuple(values) => {
+            for (i, value) in values.iter().enumerate() {
+                value_children(value, format!("{}.{}", path, i), children);
+            }
+        }
+        Value::Variant(variant) => {
+            for (i, value) in variant.fields.iter().enumerate() {
+                value_children(value, format!("{}.{}", path, i), children);
+            }
+        }
+        // The vm keeps captured variables outside any environment, so its
+        // closures are counted as leaves.
+        Value::Compiled(_)
+        | Value::Native(_)
+        | Value::Host(_)
+        | Value::Integer(_)
+        | Value::Float(_)
+        | Value::String(_)
+        | Value::Bool(_)
+        | Value::StructType(_)
+        | Value::EnumType(_)
+        | Value::Constructor(..)
+        | Value::Range(..)
+        | Value::Unit => {}
+    }
+}
+
+fn value_size(value: &Value) -> usize {
+    mem::size_of::<Value>()
+        + match value {
+            Value::String(s) => s.len(),
+            Value::Tuple(values) => values.iter().map(value_size).sum(),
+            Value::Variant(variant) => variant.fields.iter().map(value_size).sum(),
+            _ => 0,
+        }
+}
+
+// Objects are matched across snapshots by retaining path.
+#[derive(Debug, Default)]
+pub struct HeapDiff<'a> {
+    pub added: Vec<&'a HeapObject>,
+    pub removed: Vec<&'a HeapObject>,
+    // Objects present in both snapshots whose size changed, old then new.
+    pub resized: Vec<(&'a HeapObject, &'a HeapObject)>,
+}
+
+pub fn diff<'a>(old: &'a HeapSnapshot, new: &'a HeapSnapshot) -> HeapDiff<'a> {
+    let by_path = |snapshot: &'a HeapSnapshot| -> HashMap<&'a str, &'a HeapObject> {
+        snapshot
+            .objects
+            .iter()
+            .map(|object| (object.path.as_str(), object))
+            .collect()
+    };
+    let (old_objects, new_objects) = (by_path(old), by_path(new));
+
+    let mut diff = HeapDiff::default();
+    for object in &new.objects {
+        match old_objects.get(object.path.as_str()) {
+            None => diff.added.push(object),
+            Some(previous) if previous.size != object.size => diff.resized.push((previous, object)),
+            Some(_) => {}
+        }
+    }
+    for object in &old.objects {
+        if !new_objects.contains_key(object.path.as_str()) {
+            diff.removed.push(object);
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::heap::diff;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::parser::parser::parse;
+
+    fn paths(interpreter: &Interpreter) -> Vec<(String, String)> {
+        interpreter
+            .heap_snapshot()
+            .objects
+            .into_iter()
+            .map(|object| (object.kind, object.path))
+            .collect()
+    }
+
+    #[test]
+    fn records_reachable_objects_once() {
+        let mut interpreter = Interpreter::new();
+        let source = "let xs = [[1], 2]; let ys = xs; fn f() { xs } let pair = (f, 1);";
+        interpreter.run(&parse(source).unwrap()).unwrap();
+        assert_eq!(
+            paths(&interpreter),
+            vec![
+                ("environment".to_string(), "<globals>".to_string()),
+                ("function".to_string(), "f".to_string()),
+                ("list".to_string(), "xs".to_string()),
+                ("list".to_string(), "xs[0]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_snapshots_by_path() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(&parse("let xs = [1]; let old = [];").unwrap())
+            .unwrap();
+        let before = interpreter.heap_snapshot();
+        let program = parse("xs.push(2); let old = 0; let new = [];").unwrap();
+        interpreter.run(&program).unwrap();
+        let after = interpreter.heap_snapshot();
+
+        let changes = diff(&before, &after);
+        assert_eq!(changes.added.len(), 1);
+        assert_eq!(changes.added[0].path, "new");
+        assert_eq!(changes.removed[0].path, "old");
+        let resized: Vec<&str> = changes
+            .resized
+            .iter()
+            .map(|(_, object)| object.path.as_str())
+            .collect();
+        assert_eq!(resized, vec!["<globals>", "xs"]);
+    }
+}
@@ -0,0 +1,2668 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::diagnostic::source_map::SourceMap;
+use crate::diagnostic::suggest::closest;
+use crate::interpreter::debug::{Debugger, Frame};
+use crate::interpreter::environment::{Assignment, Environment};
+use crate::interpreter::gc;
+use crate::interpreter::heap::{self, HeapSnapshot};
+use crate::interpreter::io::Io;
+use crate::interpreter::limits::{Budget, Limit, Limits};
+use crate::interpreter::module::{display_path, ImportMap, Module, ModuleLoader};
+use crate::interpreter::native::NativeModule;
+use crate::interpreter::profile::{self, Profile};
+use crate::interpreter::stack;
+use crate::interpreter::stdlib::{self, prelude_enum, Builtins};
+use crate::interpreter::system::System;
+use crate::interpreter::value::{Closure, EnumType, Instance, Key, StructType, Value, Variant};
+use crate::lexer::symbol::{self, Symbol};
+use crate::lexer::token::Span;
+use crate::parser::ast::{
+    BinaryOp, Block, Expr, ExprKind, Function, ImportNames, ImportPath, MatchArm, Pattern,
+    PatternKind, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::parser::parser::{is_try, not_try};
+use crate::pipeline::pipeline::Pipeline;
+
+// Non-local exits travel up through the evaluator in the error half of its
+// results, alongside real errors.
+enum Unwind {
+    Error(Diagnostic),
+    Return(Value, Span),
+    Break(Span),
+    Continue(Span),
+    // A call in tail position, made by `call` once the caller's frame is gone.
+    TailCall(Value, Vec<Value>, Span),
+}
+
+impl From<Diagnostic> for Unwind {
+    fn from(diagnostic: Diagnostic) -> Unwind {
+        Unwind::Error(diagnostic)
+    }
+}
+
+impl Unwind {
+    fn in_file(self, file: impl Into<String>) -> Unwind {
+        match self {
+            Unwind::Error(diagnostic) => Unwind::Error(diagnostic.in_file(file)),
+            other => other,
+        }
+    }
+
+    fn called_from(self, function: &str, span: Span) -> Unwind {
+        match self {
+            Unwind::Error(diagnostic) => Unwind::Error(diagnostic.called_from(function, span)),
+            other => other,
+        }
+    }
+
+    // Converts an exit that escaped to the top level of a program.
+    fn into_diagnostic(self) -> Diagnostic {
+        let diagnostic = match self {
+            Unwind::Error(diagnostic) => diagnostic,
+            Unwind::Return(_, span) => Diagnostic::error("`return` outside of a function", span),
+            Unwind::Break(span) => Diagnostic::error("`break` outside of a loop", span),
+            Unwind::Continue(span) => Diagnostic::error("`continue` outside of a loop", span),
+            // `call` makes every tail call, and none are made outside of a
+            // function, so this is never reached.
+            Unwind::TailCall(_, _, span) => {
+                Diagnostic::error("`return` outside of a function", span)
+            }
+        };
+        diagnostic.or_code("runtime-error")
+    }
+}
+
+type Flow = Result<Value, Unwind>;
+
+// How many of the calls a chain of tail calls replaced an error is traced
+// through.
+const TRACED_TAIL_CALLS: usize = 64;
+
+#[derive(Default)]
+pub struct Interpreter {
+    environment: Rc<RefCell<Environment>>,
+    loader: ModuleLoader,
+    file: Option<PathBuf>,
+    // The call stack is only tracked while a debugger is attached.
+    debugger: Option<Box<dyn Debugger>>,
+    frames: Vec<Frame>,
+    // Modules the host registered, by import path.
+    natives: HashMap<Vec<Symbol>, Rc<Module>>,
+    builtins: Builtins,
+    // The code the program called `exit` with, once it has.
+    exit_code: Option<i32>,
+    // Recorded while the host asks for it.
+    profile: Option<Profile>,
+    // What the code being run may still use, if the host limited it.
+    budget: Option<Budget>,
+    // How many calls to clay functions are running.
+    depth: usize,
+    // Whether a function body is running, where `return` makes tail calls.
+    in_function: bool,
+    // Whether the expression about to be evaluated is in tail position,
+    // where a call to a clay function hands its frame to the callee.
+    tail: bool,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::default()
+    }
+
+    // Uses `pipeline` to process imported modules.
+    pub fn with_pipeline(pipeline: Pipeline) -> Interpreter {
+        Interpreter {
+            loader: ModuleLoader::new(pipeline),
+            ..Interpreter::default()
+        }
+    }
+
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        self.loader.pipeline_mut()
+    }
+
+    // Sets the file the program being run comes from. Imports are resolved
+    // relative to it, and importing it again is reported as a cycle.
+    pub fn set_file(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.loader.set_root(&path);
+        self.file = Some(path);
+    }
+
+    // Resolves imports through `imports` as well as relative to the
+    // importing file.
+    pub fn set_import_map(&mut self, imports: ImportMap) {
+        self.loader.set_import_map(imports);
+    }
+
+    // Parses the modules `program` imports, directly or not, ahead of running
+    // it, several at a time.
+    pub fn preload_imports(&mut self, program: &Program) {
+        self.loader.preload(program, self.file.as_deref());
+    }
+
+    // The sources of the modules imported so far.
+    pub fn sources(&self) -> SourceMap {
+        self.loader.sources()
+    }
+
+    // Records the sources of the modules imported in `sources` rather than
+    // a map of the interpreter's own.
+    pub fn set_sources(&mut self, sources: SourceMap) {
+        self.loader.set_sources(sources);
+    }
+
+    // Makes `module` importable by its path, ahead of the standard library.
+    pub fn register_module(&mut self, module: NativeModule) {
+        let path = module.path().iter().map(|s| Symbol::intern(s)).collect();
+        self.natives.insert(path, Rc::new(module.load()));
+    }
+
+    // Makes `value` callable as `name` from every program and module, unless
+    // they bind `name` themselves.
+    pub fn register_builtin(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.builtins.register(name, value);
+    }
+
+    // Sends the I/O functions of the standard library through `io`.
+    pub fn set_io(&mut self, io: Rc<RefCell<dyn Io>>) {
+        self.builtins.set_io(io);
+    }
+
+    // Makes `env` and `std::os` describe `system` rather than the machine
+    // clay runs on.
+    pub fn set_system(&mut self, system: Rc<dyn System>) {
+        self.builtins.set_system(system);
+    }
+
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger>) {
+        self.debugger = Some(debugger);
+    }
+
+    // Bounds what code run from now on may use, counting from now. Going
+    // past a limit fails the code with an error, and `exceeded` tells which.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = match limits == Limits::default() {
+            true => None,
+            false => Some(Budget::new(limits)),
+        };
+    }
+
+    // The limit the code went past, if it did.
+    pub fn exceeded(&self) -> Option<Limit> {
+        self.budget.as_ref().and_then(Budget::exceeded)
+    }
+
+    // Starts recording how often each function is called and how long its
+    // calls take, counting everything run until `take_profile` as part of a
+    // call to the top level.
+    pub fn start_profile(&mut self) {
+        let mut profile = Profile::new();
+        profile.enter(profile::ROOT);
+        self.profile = Some(profile);
+    }
+
+    // The profile recorded since `start_profile`.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        let mut profile = self.profile.take()?;
+        profile.finish();
+        Some(profile)
+    }
+
+    // Sets the arguments the `args` builtin returns.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.builtins.set_args(args);
+    }
+
+    // The code the program asked to exit with, by calling `exit` or by
+    // returning it from `main` in `run_script`.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<Value, Diagnostic> {
+        if let (true, Some(first)) = (self.frames.is_empty(), program.statements.first()) {
+            self.frames.push(Frame {
+                name: "<main>".to_string(),
+                file: self.file.clone(),
+                span: first.span,
+                environment: self.environment.clone(),
+            });
+        }
+
+        let mut last = Value::Unit;
+        for stmt in &program.statements {
+            last = match self.execute(stmt) {
+                Ok(value) => value,
+                Err(unwind) => return self.stop(unwind),
+            };
+        }
+        Ok(last)
+    }
+
+    // Runs `program` the way `clay run` does: its statements, then the
+    // `main` function it declares, if it declares one. An integer `main`
+    // returns is the exit code rather than the program's value.
+    pub fn run_script(&mut self, program: &Program) -> Result<Value, Diagnostic> {
+        let value = self.run(program)?;
+        let main = self.environment.borrow().get(Symbol::intern("main"));
+        let (main, span) = match main {
+            Some(Value::Function(closure)) if self.exit_code.is_none() => {
+                let span = closure.function.span;
+                (Value::Function(closure), span)
+            }
+            _ => return Ok(value),
+        };
+        match self.call_function(main, Vec::new(), span)? {
+            Value::Integer(code) if self.exit_code.is_none() => {
+                self.exit_code = Some(stdlib::exit_code(&Value::Integer(code), span)?);
+                Ok(Value::Unit)
+            }
+            value => Ok(value),
+        }
+    }
+
+    // Runs `program`, then `test`, the body of one of its test blocks, in a
+    // scope of its own. Each test should get an interpreter of its own, so
+    // none sees what another left behind.
+    pub fn run_test(&mut self, program: &Program, test: &Block) -> Result<(), Diagnostic> {
+        self.run(program)?;
+        let scope = Environment::with_parent(self.environment.clone());
+        self.in_scope(scope, |interpreter| interpreter.evaluate_block(test))
+            .map(|_| ())
+            .map_err(Unwind::into_diagnostic)
+    }
+
+    // The value of a top-level binding of the programs run so far, or of the
+    // builtin `name` names.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let name = Symbol::intern(name);
+        self.environment
+            .borrow()
+            .get(name)
+            .or_else(|| self.builtins.get(name))
+    }
+
+    // The names `global` knows, sorted: the top-level bindings of the
+    // programs run so far and the builtins.
+    pub fn global_names(&self) -> Vec<Symbol> {
+        let environment = self.environment.borrow();
+        let mut names: Vec<Symbol> = environment
+            .bindings()
+            .map(|(name, _)| name)
+            .chain(self.builtins.names())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Calls a clay function from the host. `span` is where errors about the
+    // call itself, like a wrong number of arguments, are reported.
+    pub fn call_function(
+        &mut self,
+        function: Value,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        match self.call(function, args, span) {
+            Ok(value) => Ok(value),
+            Err(unwind) => self.stop(unwind),
+        }
+    }
+
+    // Ends a run the host started that unwound to the top. `exit` unwinds
+    // like an error does, but ends the run without one.
+    fn stop(&mut self, unwind: Unwind) -> Result<Value, Diagnostic> {
+        match self.builtins.take_exit() {
+            Some(code) => {
+                self.exit_code = Some(code);
+                Ok(Value::Unit)
+            }
+            None => Err(unwind.into_diagnostic()),
+        }
+    }
+
+    pub fn heap_snapshot(&self) -> HeapSnapshot {
+        heap::snapshot(&self.environment)
+    }
+
+    // Lets an attached debugger see the statement about to run.
+    fn trace(&mut self, span: Span) {
+        if let Some(debugger) = &mut self.debugger {
+            if let Some(frame) = self.frames.last_mut() {
+                frame.span = span;
+                frame.file = self.file.clone();
+                frame.environment = self.environment.clone();
+            }
+            debugger.on_statement(&self.frames);
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Flow {
+        self.trace(stmt.span);
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.evaluate(expr),
+            StmtKind::Function(function) => {
+                let closure = self.closure(function);
+                self.environment
+                    .borrow_mut()
+                    .define(function.name.unwrap_or_default(), closure);
+                Ok(Value::Unit)
+            }
+            StmtKind::Let {
+                name,
+                mutable,
+                value,
+                ..
+            } => {
+                let value = self.evaluate(value)?;
+                self.environment
+                    .borrow_mut()
+                    .declare(*name, value, *mutable);
+                Ok(Value::Unit)
+            }
+            StmtKind::Destructure {
+                pattern,
+                mutable,
+                value,
+                ..
+            } => {
+                let value = self.evaluate(value)?;
+                let mut bindings = Vec::new();
+                if !match_pattern(pattern, &value, &mut bindings) {
+                    return Err(Diagnostic::error(
+                        format!("`let` pattern does not match value `{}`", value),
+                        pattern.span,
+                    )
+                    .into());
+                }
+                let mut environment = self.environment.borrow_mut();
+                for (name, value) in bindings {
+                    environment.declare(name, value, *mutable);
+                }
+                Ok(Value::Unit)
+            }
+            StmtKind::Import(import) => {
+                let module = self.import(&import.path, stmt.span)?;
+                let bindings = match &import.names {
+                    ImportNames::Module(alias) => {
+                        let name = alias.unwrap_or_else(|| import.path.binding());
+                        vec![(name, Value::Module(module))]
+                    }
+                    ImportNames::Members(members) => {
+                        let environment = module.environment.borrow();
+                        let mut bindings = Vec::new();
+                        for member in members {
+                            let value = environment.get(member.name).ok_or_else(|| {
+                                let message = format!(
+                                    "module `{}` has no member `{}`",
+                                    module.name, member.name
+                                );
+                                let diagnostic = Diagnostic::error(message, member.span);
+                                let names = environment.visible();
+                                let names = names.iter().map(|name| name.as_str());
+                                match closest(member.name.as_str(), names) {
+                                    // The member's span takes in its alias.
+                                    Some(found) => diagnostic.with_suggestion(
+                                        format!("did you mean `{}`?", found),
+                                        member.span,
+                                        match member.alias {
+                                            Some(alias) => format!("{} as {}", found, alias),
+                                            None => found.to_string(),
+                                        },
+                                    ),
+                                    None => diagnostic,
+                                }
+                            })?;
+                            bindings.push((member.binding(), value));
+                        }
+                        bindings
+                    }
+                };
+                let mut environment = self.environment.borrow_mut();
+                for (name, value) in bindings {
+                    environment.define(name, value);
+                }
+                Ok(Value::Unit)
+            }
+            StmtKind::Struct(decl) => {
+                let ty = StructType {
+                    name: decl.name,
+                    fields: decl.fields.iter().map(|field| field.name).collect(),
+                    methods: RefCell::default(),
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(decl.name, Value::StructType(Rc::new(ty)));
+                Ok(Value::Unit)
+            }
+            StmtKind::Enum(decl) => {
+                let ty = EnumType {
+                    name: decl.name,
+                    variants: decl
+                        .variants
+                        .iter()
+                        .map(|variant| (variant.name, variant.fields.len()))
+                        .collect(),
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(decl.name, Value::EnumType(Rc::new(ty)));
+                Ok(Value::Unit)
+            }
+            // Traits only matter to the type checker.
+            StmtKind::Trait(_) => Ok(Value::Unit),
+            StmtKind::Impl(decl) => {
+                let target = self.environment.borrow().get(decl.target);
+                let target = target.ok_or_else(|| {
+                    Diagnostic::error(format!("unknown struct `{}`", decl.target), stmt.span)
+                })?;
+                for method in &decl.methods {
+                    let closure = self.closure(method);
+                    define_method(&target, method.name.unwrap_or_default(), closure, stmt.span)?;
+                }
+                Ok(Value::Unit)
+            }
+            // Tests only run through `run_test`.
+            StmtKind::Test { .. } => Ok(Value::Unit),
+        }
+    }
+
+    fn import(&mut self, path: &ImportPath, span: Span) -> Result<Rc<Module>, Diagnostic> {
+        // A project can replace the standard library by aliasing `std`.
+        if let ImportPath::Module(segments) = path {
+            if let Some(module) = self.natives.get(segments) {
+                return Ok(module.clone());
+            }
+        }
+        let aliased =
+            matches!(path, ImportPath::Module(segments) if self.loader.is_aliased(&segments[0]));
+        if !aliased {
+            if let Some(module) = self.builtins.module(path) {
+                return Ok(Rc::new(module));
+            }
+        }
+
+        let resolved = self.loader.resolve(path, self.file.as_deref(), span)?;
+        if let Some(module) = self.loader.cached(&resolved) {
+            return Ok(module);
+        }
+
+        self.loader.enter(&resolved, span)?;
+        let result = self.evaluate_module(path.binding().to_string(), resolved, span);
+        self.loader.exit();
+
+        let module = Rc::new(result?);
+        self.loader.insert(module.clone());
+        Ok(module)
+    }
+
+    fn evaluate_module(
+        &mut self,
+        name: String,
+        path: PathBuf,
+        span: Span,
+    ) -> Result<Module, Diagnostic> {
+        let program = self.loader.parse(&path, span)?;
+
+        let environment = Rc::new(RefCell::new(Environment::new()));
+        let previous_environment = std::mem::replace(&mut self.environment, environment.clone());
+        let previous_file = self.file.replace(path.clone());
+        let in_function = std::mem::replace(&mut self.in_function, false);
+        // Not `run`, which would stop just the module if it called `exit`
+        // rather than letting the exit stop the whole program.
+        let result = program
+            .statements
+            .iter()
+            .try_for_each(|stmt| self.execute(stmt).map(drop))
+            .map_err(Unwind::into_diagnostic);
+        self.environment = previous_environment;
+        self.file = previous_file;
+        self.in_function = in_function;
+
+        result.map_err(|diagnostic| diagnostic.in_file(display_path(&path)))?;
+        Ok(Module {
+            name,
+            path,
+            environment,
+        })
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Flow {
+        let tail = std::mem::take(&mut self.tail);
+        if stack::exhausted() {
+            return Err(Diagnostic::error("stack overflow", expr.span).into());
+        }
+        let depth = self.depth;
+        if let Some(limit) = self.budget.as_mut().and_then(|budget| budget.step(depth)) {
+            return Err(limit_exceeded(limit, expr.span).into());
+        }
+        match &expr.kind {
+            ExprKind::Integer(n) => Ok(Value::Integer(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::String(s) => Ok(Value::String(s.clone())),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Ident(name) => match self
+                .environment
+                .borrow()
+                .get(*name)
+                .or_else(|| self.builtins.get(*name))
+            {
+                Some(value) => Ok(value),
+                None => Err(self.unknown_variable(*name, expr.span).into()),
+            },
+            ExprKind::Path(segments) => Ok(self.evaluate_path(segments, expr.span)?),
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = self.evaluate(start)?;
+                let end = self.evaluate(end)?;
+                Ok(range(start, end, *inclusive, expr.span)?)
+            }
+            ExprKind::Tuple(elements) => {
+                let values = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Tuple(values))
+            }
+            ExprKind::List(elements) => {
+                let values = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::list(values))
+            }
+            ExprKind::Map(entries) => {
+                let mut map = BTreeMap::new();
+                for (key, value) in entries {
+                    let key = map_key(&self.evaluate(key)?, key.span)?;
+                    map.insert(key, self.evaluate(value)?);
+                }
+                Ok(Value::map(map))
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => self.evaluate_method_call(receiver, *method, args, expr.span),
+            ExprKind::Struct { path, fields } => self.evaluate_struct(path, fields, expr.span),
+            ExprKind::Field { target, name } => {
+                let target = self.evaluate(target)?;
+                Ok(get_field(&target, *name, expr.span)?)
+            }
+            ExprKind::Index { target, index } => {
+                let target = self.evaluate(target)?;
+                let index = self.evaluate(index)?;
+                Ok(index_value(&target, &index, expr.span)?)
+            }
+            ExprKind::Slice { target, start, end } => {
+                let target = self.evaluate(target)?;
+                let start = match start {
+                    Some(start) => Some(self.evaluate(start)?),
+                    None => None,
+                };
+                let end = match end {
+                    Some(end) => Some(self.evaluate(end)?),
+                    None => None,
+                };
+                Ok(slice_value(&target, start, end, expr.span)?)
+            }
+            ExprKind::Unary { op, operand } => {
+                let value = self.evaluate(operand)?;
+                Ok(unary(*op, value, expr.span)?)
+            }
+            ExprKind::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => match self.evaluate_bool(left)? {
+                true => Ok(Value::Bool(self.evaluate_bool(right)?)),
+                false => Ok(Value::Bool(false)),
+            },
+            ExprKind::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => match self.evaluate_bool(left)? {
+                true => Ok(Value::Bool(true)),
+                false => Ok(Value::Bool(self.evaluate_bool(right)?)),
+            },
+            ExprKind::Binary { op, left, right } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                Ok(binary(*op, left, right, expr.span)?)
+            }
+            ExprKind::Assign { target, value } => self.evaluate_assign(target, value, expr.span),
+            ExprKind::Match { scrutinee, arms } => {
+                let value = self.evaluate(scrutinee)?;
+                self.evaluate_match(&value, arms, tail, expr.span)
+            }
+            ExprKind::Block(block) => {
+                let scope = Environment::with_parent(self.environment.clone());
+                self.tail = tail;
+                self.in_scope(scope, |interpreter| interpreter.evaluate_block(block))
+            }
+            ExprKind::Function(function) => Ok(self.closure(function)),
+            ExprKind::Call { callee, args } => {
+                let callee = self.evaluate(callee)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match callee {
+                    Value::Function(_) if tail => Err(Unwind::TailCall(callee, args, expr.span)),
+                    _ => self.call(callee, args, expr.span),
+                }
+            }
+            ExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => {
+                        self.tail = self.in_function;
+                        self.evaluate(value)?
+                    }
+                    None => Value::Unit,
+                };
+                Err(Unwind::Return(value, expr.span))
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate_bool(condition)? {
+                    let scope = Environment::with_parent(self.environment.clone());
+                    self.tail = tail;
+                    self.in_scope(scope, |interpreter| interpreter.evaluate_block(then_branch))
+                } else {
+                    match else_branch {
+                        Some(else_branch) => {
+                            self.tail = tail;
+                            self.evaluate(else_branch)
+                        }
+                        None => Ok(Value::Unit),
+                    }
+                }
+            }
+            ExprKind::While { condition, body } => {
+                while self.evaluate_bool(condition)? {
+                    if !self.run_iteration(body, Vec::new())? {
+                        break;
+                    }
+                }
+                Ok(Value::Unit)
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => self.evaluate_for(pattern, iterable, body),
+            ExprKind::Break => Err(Unwind::Break(expr.span)),
+            ExprKind::Continue => Err(Unwind::Continue(expr.span)),
+        }
+    }
+
+    fn evaluate_method_call(
+        &mut self,
+        receiver: &Expr,
+        method: Symbol,
+        args: &[Expr],
+        span: Span,
+    ) -> Flow {
+        let receiver = self.evaluate(receiver)?;
+        let args = args
+            .iter()
+            .map(|arg| self.evaluate(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let function = self
+            .environment
+            .borrow()
+            .get(method)
+            .or_else(|| self.builtins.get(method));
+        call_method(receiver, &method, args, function, span, |function, args| {
+            self.call(function, args, span)
+        })
+    }
+
+    fn evaluate_struct(&mut self, path: &[Symbol], fields: &[(Symbol, Expr)], span: Span) -> Flow {
+        let ty = match path {
+            [name] => self
+                .environment
+                .borrow()
+                .get(*name)
+                .ok_or_else(|| Diagnostic::error(format!("unknown struct `{}`", name), span))?,
+            path => self.evaluate_path(path, span)?,
+        };
+        let fields = fields
+            .iter()
+            .map(|(name, value)| Ok((*name, self.evaluate(value)?)))
+            .collect::<Result<Vec<_>, Unwind>>()?;
+        Ok(construct(ty, &symbol::join(path, "::"), fields, span)?)
+    }
+
+    fn evaluate_assign(&mut self, target: &Expr, value: &Expr, span: Span) -> Flow {
+        let value = self.evaluate(value)?;
+        match &target.kind {
+            ExprKind::Ident(name) => {
+                let mut environment = self.environment.borrow_mut();
+                match environment.assign(*name, value.clone()) {
+                    Assignment::Assigned => {}
+                    Assignment::Unbound => environment.define(*name, value.clone()),
+                    Assignment::Immutable => {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "cannot assign to immutable variable `{}`, consider declaring it with `let mut`",
+                                name
+                            ),
+                            target.span,
+                        )
+                        .into())
+                    }
+                }
+                Ok(value)
+            }
+            ExprKind::Field { target, name } => {
+                let object = self.evaluate(target)?;
+                set_field(&object, *name, value.clone(), span)?;
+                Ok(value)
+            }
+            _ => Err(Diagnostic::error("invalid assignment target", target.span).into()),
+        }
+    }
+
+    fn evaluate_for(&mut self, pattern: &Pattern, iterable: &Expr, body: &Block) -> Flow {
+        let items = iterate(self.evaluate(iterable)?, iterable.span)?;
+        for item in items {
+            let mut bindings = Vec::new();
+            if !match_pattern(pattern, &item, &mut bindings) {
+                return Err(Diagnostic::error(
+                    format!("loop pattern does not match value `{}`", item),
+                    pattern.span,
+                )
+                .into());
+            }
+            if !self.run_iteration(body, bindings)? {
+                break;
+            }
+        }
+        Ok(Value::Unit)
+    }
+
+    // Runs one pass of a loop body in a fresh scope. Returns false when the
+    // body breaks out of the loop.
+    fn run_iteration(
+        &mut self,
+        body: &Block,
+        bindings: Vec<(Symbol, Value)>,
+    ) -> Result<bool, Unwind> {
+        let mut scope = Environment::with_parent(self.environment.clone());
+        for (name, value) in bindings {
+            scope.define(name, value);
+        }
+        match self.in_scope(scope, |interpreter| interpreter.evaluate_block(body)) {
+            Ok(_) | Err(Unwind::Continue(_)) => Ok(true),
+            Err(Unwind::Break(_)) => Ok(false),
+            Err(other) => Err(other),
+        }
+    }
+
+    // Runs a block's statements in the current scope. Its value is in tail
+    // position if the block is.
+    fn evaluate_block(&mut self, block: &Block) -> Flow {
+        let tail = std::mem::take(&mut self.tail);
+        for stmt in &block.statements {
+            self.execute(stmt)?;
+        }
+        match &block.value {
+            Some(value) => {
+                self.trace(value.span);
+                self.tail = tail;
+                self.evaluate(value)
+            }
+            None => Ok(Value::Unit),
+        }
+    }
+
+    fn closure(&self, function: &Arc<Function>) -> Value {
+        let closure = Rc::new(Closure {
+            function: function.clone(),
+            environment: self.environment.clone(),
+            file: self.file.clone(),
+        });
+        gc::track(&closure);
+        Value::Function(closure)
+    }
+
+    // Calls `callee`, then each function it hands its frame to with a call in
+    // tail position, so recursion in tail position runs in constant stack.
+    // An error is traced through the most recent of the calls that were
+    // replaced, once for each run of calls to one function from one place.
+    fn call(&mut self, callee: Value, args: Vec<Value>, span: Span) -> Flow {
+        let mut replaced: VecDeque<(Rc<Closure>, Span)> = VecDeque::new();
+        let (mut callee, mut args, mut span) = (callee, args, span);
+        loop {
+            let closure = match &callee {
+                Value::Function(closure) => Some(closure.clone()),
+                _ => None,
+            };
+            match self.call_once(callee, args, span) {
+                Err(Unwind::TailCall(next, next_args, next_span)) => {
+                    if let Some(closure) = closure {
+                        let repeated = replaced
+                            .back()
+                            .is_some_and(|(last, at)| Rc::ptr_eq(last, &closure) && *at == span);
+                        if !repeated {
+                            if replaced.len() == TRACED_TAIL_CALLS {
+                                replaced.pop_front();
+                            }
+                            replaced.push_back((closure, span));
+                        }
+                    }
+                    callee = next;
+                    args = next_args;
+                    span = next_span;
+                }
+                result => {
+                    return replaced
+                        .iter()
+                        .rev()
+                        .fold(result, |result, (closure, span)| {
+                            self.traced(result, closure, *span)
+                        })
+                }
+            }
+        }
+    }
+
+    fn call_once(&mut self, callee: Value, args: Vec<Value>, span: Span) -> Flow {
+        let closure = match callee {
+            Value::Function(closure) => closure,
+            Value::Native(native) => {
+                self.enter_call(native.name);
+                let result = native.call(&args, span);
+                self.exit_call();
+                return Ok(result?);
+            }
+            Value::Constructor(ty, index) => return Ok(construct_variant(&ty, index, args, span)?),
+            other => {
+                return Err(
+                    Diagnostic::error(format!("cannot call a {}", other.type_name()), span).into(),
+                )
+            }
+        };
+
+        let params = &closure.function.params;
+        if args.len() != params.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` expects {} argument{}, found {}",
+                    closure.name(),
+                    params.len(),
+                    if params.len() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                span,
+            )
+            .into());
+        }
+
+        let mut scope = Environment::with_parent(closure.environment.clone());
+        for (param, arg) in params.iter().zip(args) {
+            scope.define(param.name, arg);
+        }
+
+        let tracked = self.debugger.is_some();
+        if tracked {
+            self.frames.push(Frame {
+                name: closure.name().to_string(),
+                file: closure.file.clone(),
+                span,
+                environment: closure.environment.clone(),
+            });
+        }
+        let previous_file = std::mem::replace(&mut self.file, closure.file.clone());
+        let in_function = std::mem::replace(&mut self.in_function, true);
+        self.tail = true;
+        self.enter_call(closure.name());
+        self.depth += 1;
+        let result = self.in_scope(scope, |interpreter| {
+            interpreter.evaluate_block(&closure.function.body)
+        });
+        self.depth -= 1;
+        self.exit_call();
+        self.in_function = in_function;
+        self.file = previous_file;
+        if tracked {
+            self.frames.pop();
+        }
+
+        let result = match result {
+            Err(Unwind::Return(value, _)) => Ok(value),
+            // Loops cannot be exited from inside a function called in them.
+            Err(unwind @ Unwind::Break(_)) | Err(unwind @ Unwind::Continue(_)) => {
+                Err(unwind.into_diagnostic().into())
+            }
+            other => other,
+        };
+        self.traced(result, &closure, span)
+    }
+
+    // Tells the profile, if one is being recorded, that a call started.
+    fn enter_call(&mut self, name: &str) {
+        if let Some(profile) = &mut self.profile {
+            profile.enter(name);
+        }
+    }
+
+    fn exit_call(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            profile.exit();
+        }
+    }
+
+    // Traces an error out of `closure`, called at `span`. The call is in the
+    // caller's file, so the callee's file is settled before it is traced.
+    fn traced(&self, result: Flow, closure: &Closure, span: Span) -> Flow {
+        let result = match &closure.file {
+            Some(file) if closure.file != self.file => {
+                result.map_err(|unwind| unwind.in_file(display_path(file)))
+            }
+            _ => result,
+        };
+        result.map_err(|unwind| unwind.called_from(closure.name(), span))
+    }
+
+    // The names a bare name can refer to: those in scope and the builtins.
+    fn names_in_scope(&self) -> Vec<Symbol> {
+        let mut names = self.environment.borrow().visible();
+        names.extend(self.builtins.names());
+        names
+    }
+
+    fn unknown_variable(&self, name: Symbol, span: Span) -> Diagnostic {
+        let message = format!("unknown variable `{}`", name);
+        let diagnostic = Diagnostic::error(message, span);
+        unknown(diagnostic, &[name], 0, span, &self.names_in_scope())
+    }
+
+    fn evaluate_path(&mut self, segments: &[Symbol], span: Span) -> Result<Value, Diagnostic> {
+        let first = self.environment.borrow().get(segments[0]);
+        let mut value = match first.or_else(|| self.builtins.get(segments[0])) {
+            Some(value) => value,
+            None => {
+                let message = format!("unknown module `{}`", segments[0]);
+                let diagnostic = Diagnostic::error(message, span);
+                let names = self.names_in_scope();
+                return Err(unknown(diagnostic, segments, 0, span, &names));
+            }
+        };
+
+        for (i, segment) in segments.iter().enumerate().skip(1) {
+            let module = match value {
+                Value::Module(module) => module,
+                Value::EnumType(ty) if i + 1 == segments.len() => {
+                    return enum_variant(&ty, *segment, span)
+                }
+                other => {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "`{}` is a {}, not a module",
+                            symbol::join(&segments[..i], "::"),
+                            other.type_name()
+                        ),
+                        span,
+                    ))
+                }
+            };
+            value = match module.environment.borrow().get(*segment) {
+                Some(value) => value,
+                None => {
+                    let message = format!("module `{}` has no member `{}`", module.name, segment);
+                    let diagnostic = Diagnostic::error(message, span);
+                    let members = module.environment.borrow().visible();
+                    return Err(unknown(diagnostic, segments, i, span, &members));
+                }
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn evaluate_match(&mut self, value: &Value, arms: &[MatchArm], tail: bool, span: Span) -> Flow {
+        for arm in arms {
+            let mut bindings = Vec::new();
+            if !match_pattern(&arm.pattern, value, &mut bindings) {
+                continue;
+            }
+
+            let mut scope = Environment::with_parent(self.environment.clone());
+            for (name, value) in bindings {
+                scope.define(name, value);
+            }
+
+            let result = self.in_scope(scope, |interpreter| {
+                if let Some(guard) = &arm.guard {
+                    if !interpreter.evaluate_bool(guard)? {
+                        return Ok(None);
+                    }
+                }
+                interpreter.tail = tail;
+                interpreter.evaluate(&arm.body).map(Some)
+            })?;
+
+            if let Some(result) = result {
+                return Ok(result);
+            }
+        }
+
+        if is_try(arms) {
+            return Err(not_try(value, span).into());
+        }
+        Err(Diagnostic::error(format!("no match arm matched value `{}`", value), span).into())
+    }
+
+    fn in_scope<T>(&mut self, scope: Environment, f: impl FnOnce(&mut Self) -> T) -> T {
+        let scope = Rc::new(RefCell::new(scope));
+        gc::track(&scope);
+        let previous = std::mem::replace(&mut self.environment, scope);
+        let result = f(self);
+        self.environment = previous;
+        result
+    }
+
+    fn evaluate_bool(&mut self, expr: &Expr) -> Result<bool, Unwind> {
+        match self.evaluate(expr)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(Diagnostic::error(
+                format!("expected bool, found {}", other.type_name()),
+                expr.span,
+            )
+            .into()),
+        }
+    }
+}
+
+pub(crate) fn unary(op: UnaryOp, value: Value, span: Span) -> Result<Value, Diagnostic> {
+    match (op, value) {
+        (UnaryOp::Negate, Value::Integer(n)) => n
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| Diagnostic::error("integer overflow", span)),
+        (UnaryOp::Negate, Value::Float(n)) => Ok(Value::Float(-n)),
+        (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (UnaryOp::BitNot, Value::Integer(n)) => Ok(Value::Integer(!n)),
+        (op, value) => Err(Diagnostic::error(
+            format!("cannot apply `{}` to {}", op, value.type_name()),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn binary(
+    op: BinaryOp,
+    left: Value,
+    right: Value,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    use Value::*;
+
+    let overflow = || Diagnostic::error("integer overflow", span);
+    // Shifting by a negative amount, or by all 64 bits or more, is an error
+    // rather than a shift by the amount modulo 64. Bits shifted out are lost.
+    let shift = |b: i64| {
+        u32::try_from(b)
+            .ok()
+            .filter(|b| *b < i64::BITS)
+            .ok_or_else(|| Diagnostic::error(format!("cannot shift by {}", b), span))
+    };
+
+    match (op, left, right) {
+        (BinaryOp::Equal, left, right) => Ok(Bool(values_equal(&left, &right))),
+        (BinaryOp::NotEqual, left, right) => Ok(Bool(!values_equal(&left, &right))),
+
+        (BinaryOp::Divide, Integer(_), Integer(0))
+        | (BinaryOp::Remainder, Integer(_), Integer(0)) => {
+            Err(Diagnostic::error("division by zero", span))
+        }
+        (BinaryOp::Add, Integer(a), Integer(b)) => {
+            a.checked_add(b).map(Integer).ok_or_else(overflow)
+        }
+        (BinaryOp::Subtract, Integer(a), Integer(b)) => {
+            a.checked_sub(b).map(Integer).ok_or_else(overflow)
+        }
+        (BinaryOp::Multiply, Integer(a), Integer(b)) => {
+            a.checked_mul(b).map(Integer).ok_or_else(overflow)
+        }
+        (BinaryOp::Divide, Integer(a), Integer(b)) => {
+            a.checked_div(b).map(Integer).ok_or_else(overflow)
+        }
+        (BinaryOp::Remainder, Integer(a), Integer(b)) => {
+            a.checked_rem(b).map(Integer).ok_or_else(overflow)
+        }
+        (BinaryOp::Less, Integer(a), Integer(b)) => Ok(Bool(a < b)),
+        (BinaryOp::LessEqual, Integer(a), Integer(b)) => Ok(Bool(a <= b)),
+        (BinaryOp::Greater, Integer(a), Integer(b)) => Ok(Bool(a > b)),
+        (BinaryOp::GreaterEqual, Integer(a), Integer(b)) => Ok(Bool(a >= b)),
+        (BinaryOp::BitAnd, Integer(a), Integer(b)) => Ok(Integer(a & b)),
+        (BinaryOp::BitOr, Integer(a), Integer(b)) => Ok(Integer(a | b)),
+        (BinaryOp::BitXor, Integer(a), Integer(b)) => Ok(Integer(a ^ b)),
+        (BinaryOp::ShiftLeft, Integer(a), Integer(b)) => Ok(Integer(a << shift(b)?)),
+        (BinaryOp::ShiftRight, Integer(a), Integer(b)) => Ok(Integer(a >> shift(b)?)),
+
+        (op, Integer(a), Float(b)) if !op.is_bitwise() => float_binary(op, a as f64, b, span),
+        (op, Float(a), Integer(b)) if !op.is_bitwise() => float_binary(op, a, b as f64, span),
+        (op, Float(a), Float(b)) if !op.is_bitwise() => float_binary(op, a, b, span),
+
+        (BinaryOp::Add, String(a), String(b)) => {
+            gc::build(a.len() + b.len());
+            Ok(String(a + &b))
+        }
+        (BinaryOp::Less, String(a), String(b)) => Ok(Bool(a < b)),
+        (BinaryOp::LessEqual, String(a), String(b)) => Ok(Bool(a <= b)),
+        (BinaryOp::Greater, String(a), String(b)) => Ok(Bool(a > b)),
+        (BinaryOp::GreaterEqual, String(a), String(b)) => Ok(Bool(a >= b)),
+
+        (op, left, right) => Err(Diagnostic::error(
+            format!(
+                "cannot apply `{}` to {} and {}",
+                op,
+                left.type_name(),
+                right.type_name()
+            ),
+            span,
+        )),
+    }
+}
+
+fn float_binary(op: BinaryOp, a: f64, b: f64, span: Span) -> Result<Value, Diagnostic> {
+    match op {
+        BinaryOp::Add => Ok(Value::Float(a + b)),
+        BinaryOp::Subtract => Ok(Value::Float(a - b)),
+        BinaryOp::Multiply => Ok(Value::Float(a * b)),
+        BinaryOp::Divide => Ok(Value::Float(a / b)),
+        BinaryOp::Remainder => Ok(Value::Float(a % b)),
+        BinaryOp::Less => Ok(Value::Bool(a < b)),
+        BinaryOp::LessEqual => Ok(Value::Bool(a <= b)),
+        BinaryOp::Greater => Ok(Value::Bool(a > b)),
+        BinaryOp::GreaterEqual => Ok(Value::Bool(a >= b)),
+        _ => Err(Diagnostic::error(
+            format!("cannot apply `{}` to numbers", op),
+            span,
+        )),
+    }
+}
+
+// The built-in methods of lists, maps and strings, the methods impls give
+// structs and the functions stored in struct fields. Failing those,
+// `function` is the function of the method's name in scope, if any, and is
+// called with the receiver as its first argument, so `x.f(y)` is `f(x, y)`.
+// `call` applies a function to arguments, such as the one given to `map` to
+// an item, so each backend can call its own closures.
+pub(crate) fn call_method<E: From<Diagnostic>>(
+    receiver: Value,
+    method: &str,
+    mut args: Vec<Value>,
+    function: Option<Value>,
+    span: Span,
+    mut call: impl FnMut(Value, Vec<Value>) -> Result<Value, E>,
+) -> Result<Value, E> {
+    if !has_method(&receiver, method) {
+        if let Some(function) = function.filter(is_function) {
+            args.insert(0, receiver);
+            return call(function, args);
+        }
+    }
+    match receiver {
+        Value::List(list) => list_method(list, method, args, span, call),
+        Value::Struct(instance) => {
+            let name = Symbol::intern(method);
+            let function = instance.ty.methods.borrow().get(&name).cloned();
+            match (function, instance.get(name)) {
+                (Some(function), _) => {
+                    args.insert(0, Value::Struct(instance));
+                    call(function, args)
+                }
+                (None, Some(field)) => call(field, args),
+                (None, None) => Err(no_method(&Value::Struct(instance), method, span).into()),
+            }
+        }
+        Value::Map(map) => Ok(map_method(&map, method, args, span)?),
+        Value::String(s) if method == "len" => {
+            expect_arguments(method, &args, 0, span)?;
+            Ok(Value::Integer(s.chars().count() as i64))
+        }
+        other => Err(no_method(&other, method, span).into()),
+    }
+}
+
+fn has_method(receiver: &Value, method: &str) -> bool {
+    match receiver {
+        Value::List(_) => matches!(method, "len" | "push" | "pop" | "map" | "filter"),
+        Value::Map(_) => matches!(
+            method,
+            "len" | "contains" | "insert" | "remove" | "keys" | "values"
+        ),
+        Value::String(_) => method == "len",
+        Value::Struct(instance) => {
+            let name = Symbol::intern(method);
+            instance.ty.methods.borrow().contains_key(&name) || instance.ty.fields.contains(&name)
+        }
+        _ => false,
+    }
+}
+
+fn is_function(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Function(_) | Value::Compiled(_) | Value::Native(_)
+    )
+}
+
+fn list_method<E: From<Diagnostic>>(
+    list: Rc<RefCell<Vec<Value>>>,
+    method: &str,
+    args: Vec<Value>,
+    span: Span,
+    mut call: impl FnMut(Value, Vec<Value>) -> Result<Value, E>,
+) -> Result<Value, E> {
+    match method {
+        "len" => {
+            expect_arguments(method, &args, 0, span)?;
+            let len = list.borrow().len();
+            Ok(Value::Integer(len as i64))
+        }
+        "push" => {
+            expect_arguments(method, &args, 1, span)?;
+            list.borrow_mut().extend(args);
+            Ok(Value::Unit)
+        }
+        "pop" => {
+            expect_arguments(method, &args, 0, span)?;
+            let last = list.borrow_mut().pop();
+            last.ok_or_else(|| Diagnostic::error("cannot pop from an empty list", span).into())
+        }
+        "map" | "filter" => {
+            expect_arguments(method, &args, 1, span)?;
+            let function = args.into_iter().next().expect("checked above");
+            // Iterate over a copy so the callback may change the list.
+            let items = list.borrow().clone();
+            let mut results = Vec::new();
+            for item in items {
+                let result = call(function.clone(), vec![item.clone()])?;
+                match (method, result) {
+                    ("map", result) => results.push(result),
+                    (_, Value::Bool(true)) => results.push(item),
+                    (_, Value::Bool(false)) => {}
+                    (_, other) => {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "`filter` expects a function returning bool, found {}",
+                                other.type_name()
+                            ),
+                            span,
+                        )
+                        .into())
+                    }
+                }
+            }
+            Ok(Value::list(results))
+        }
+        _ => Err(no_method(&Value::List(list), method, span).into()),
+    }
+}
+
+// The items a `for` loop over `value` visits.
+pub(crate) fn range(
+    start: Value,
+    end: Value,
+    inclusive: bool,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    let (start, end) = match (start, end) {
+        (Value::Integer(start), Value::Integer(end)) => (start, end),
+        (Value::Integer(_), other) | (other, _) => {
+            return Err(Diagnostic::error(
+                format!("range bounds must be integers, found {}", other.type_name()),
+                span,
+            ))
+        }
+    };
+    let end = match inclusive {
+        true => end
+            .checked_add(1)
+            .ok_or_else(|| Diagnostic::error("range end is too large", span))?,
+        false => end,
+    };
+    Ok(Value::Range(start, end))
+}
+
+pub(crate) fn iterate(value: Value, span: Span) -> Result<Vec<Value>, Diagnostic> {
+    match value {
+        Value::Tuple(values) => Ok(values),
+        Value::List(values) => Ok(values.borrow().clone()),
+        Value::Map(map) => Ok(map
+            .borrow()
+            .iter()
+            .map(|(key, value)| Value::Tuple(vec![key.to_value(), value.clone()]))
+            .collect()),
+        Value::String(s) => Ok(s.chars().map(|c| Value::String(c.to_string())).collect()),
+        Value::Range(start, end) => Ok((start..end).map(Value::Integer).collect()),
+        other => Err(Diagnostic::error(
+            format!("cannot iterate over {}", other.type_name()),
+            span,
+        )),
+    }
+}
+
+fn map_method(
+    map: &Rc<RefCell<BTreeMap<Key, Value>>>,
+    method: &str,
+    args: Vec<Value>,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    match method {
+        "len" => {
+            expect_arguments(method, &args, 0, span)?;
+            Ok(Value::Integer(map.borrow().len() as i64))
+        }
+        "contains" => {
+            expect_arguments(method, &args, 1, span)?;
+            let key = map_key(&args[0], span)?;
+            Ok(Value::Bool(map.borrow().contains_key(&key)))
+        }
+        "insert" => {
+            expect_arguments(method, &args, 2, span)?;
+            let mut args = args.into_iter();
+            let key = map_key(&args.next().expect("checked above"), span)?;
+            let value = args.next().expect("checked above");
+            map.borrow_mut().insert(key, value);
+            Ok(Value::Unit)
+        }
+        "remove" => {
+            expect_arguments(method, &args, 1, span)?;
+            let key = map_key(&args[0], span)?;
+            let removed = map.borrow_mut().remove(&key);
+            removed.ok_or_else(|| missing_key(&key, span))
+        }
+        "keys" => {
+            expect_arguments(method, &args, 0, span)?;
+            Ok(Value::list(
+                map.borrow().keys().map(Key::to_value).collect(),
+            ))
+        }
+        "values" => {
+            expect_arguments(method, &args, 0, span)?;
+            Ok(Value::list(map.borrow().values().cloned().collect()))
+        }
+        _ => Err(no_method(&Value::Map(map.clone()), method, span)),
+    }
+}
+
+pub(crate) fn map_key(value: &Value, span: Span) -> Result<Key, Diagnostic> {
+    Key::from_value(value).ok_or_else(|| {
+        Diagnostic::error(
+            format!("cannot use {} as a map key", value.type_name()),
+            span,
+        )
+    })
+}
+
+// An unbound name, the `index`th segment of `path` at `span`, with the
+// closest of `candidates` as the name that was probably meant.
+pub(crate) fn unknown(
+    diagnostic: Diagnostic,
+    path: &[Symbol],
+    index: usize,
+    span: Span,
+    candidates: &[Symbol],
+) -> Diagnostic {
+    let candidates = candidates.iter().map(|name| name.as_str());
+    match closest(path[index].as_str(), candidates) {
+        Some(found) => {
+            let mut fixed: Vec<&str> = path.iter().map(|segment| segment.as_str()).collect();
+            fixed[index] = found;
+            diagnostic.with_suggestion(format!("did you mean `{}`?", found), span, fixed.join("::"))
+        }
+        None => diagnostic,
+    }
+}
+
+// Kept out of `evaluate`, whose stack frame every level of recursion pays
+// for.
+#[inline(never)]
+fn limit_exceeded(limit: Limit, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("the program {}", limit), span)
+}
+
+fn missing_key(key: &Key, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("key `{}` not found in map", key.to_value()), span)
+}
+
+fn no_method(receiver: &Value, method: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!("{} has no method `{}`", receiver.type_name(), method),
+        span,
+    )
+}
+
+fn expect_arguments(
+    method: &str,
+    args: &[Value],
+    count: usize,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if args.len() == count {
+        return Ok(());
+    }
+    Err(Diagnostic::error(
+        format!(
+            "`{}` expects {} argument{}, found {}",
+            method,
+            count,
+            if count == 1 { "" } else { "s" },
+            args.len()
+        ),
+        span,
+    ))
+}
+
+pub(crate) fn index_value(target: &Value, index: &Value, span: Span) -> Result<Value, Diagnostic> {
+    if let Value::Map(map) = target {
+        let key = map_key(index, span)?;
+        let value = map.borrow().get(&key).cloned();
+        return value.ok_or_else(|| missing_key(&key, span));
+    }
+
+    let index = match index {
+        Value::Integer(index) => *index,
+        other => {
+            return Err(Diagnostic::error(
+                format!("index must be an integer, found {}", other.type_name()),
+                span,
+            ))
+        }
+    };
+
+    let get = |values: &[Value]| {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| values.get(index).cloned())
+            .ok_or_else(|| {
+                Diagnostic::error(
+                    format!(
+                        "index {} is out of bounds for length {}",
+                        index,
+                        values.len()
+                    ),
+                    span,
+                )
+            })
+    };
+
+    match target {
+        Value::List(values) => get(&values.borrow()),
+        Value::Tuple(values) => get(values),
+        other => Err(Diagnostic::error(
+            format!("cannot index into {}", other.type_name()),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn slice_value(
+    target: &Value,
+    start: Option<Value>,
+    end: Option<Value>,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    let bound = |value: Option<Value>, default: usize| match value {
+        None => Ok(default),
+        Some(Value::Integer(n)) => usize::try_from(n)
+            .map_err(|_| Diagnostic::error(format!("slice bound {} is negative", n), span)),
+        Some(other) => Err(Diagnostic::error(
+            format!("slice bounds must be integers, found {}", other.type_name()),
+            span,
+        )),
+    };
+    let range = |len: usize| {
+        let start = bound(start, 0)?;
+        let end = bound(end, len)?;
+        if start > end || end > len {
+            return Err(Diagnostic::error(
+                format!(
+                    "slice {}..{} is out of bounds for length {}",
+                    start, end, len
+                ),
+                span,
+            ));
+        }
+        Ok(start..end)
+    };
+
+    match target {
+        Value::List(values) => {
+            let values = values.borrow();
+            Ok(Value::list(values[range(values.len())?].to_vec()))
+        }
+        Value::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let slice: String = chars[range(chars.len())?].iter().collect();
+            gc::build(slice.len());
+            Ok(Value::String(slice))
+        }
+        other => Err(Diagnostic::error(
+            format!("cannot slice {}", other.type_name()),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn match_pattern(
+    pattern: &Pattern,
+    value: &Value,
+    bindings: &mut Vec<(Symbol, Value)>,
+) -> bool {
+    match (&pattern.kind, value) {
+        (PatternKind::Wildcard, _) => true,
+        (PatternKind::Binding(name), value) => {
+            bindings.push((*name, value.clone()));
+            true
+        }
+        (PatternKind::Integer(n), value) => values_equal(&Value::Integer(*n), value),
+        (PatternKind::Float(n), value) => values_equal(&Value::Float(*n), value),
+        (PatternKind::String(s), Value::String(v)) => s == v,
+        (PatternKind::Bool(b), Value::Bool(v)) => b == v,
+        (PatternKind::Tuple(patterns), Value::Tuple(values)) => {
+            patterns.len() == values.len()
+                && patterns
+                    .iter()
+                    .zip(values)
+                    .all(|(pattern, value)| match_pattern(pattern, value, bindings))
+        }
+        (PatternKind::Variant { path, fields }, Value::Variant(variant)) => {
+            let ty = match path.as_slice() {
+                [name] => prelude_enum(name),
+                [.., ty, _] => Some(ty.as_str()),
+                [] => None,
+            };
+            ty.is_some_and(|ty| variant.ty.name == ty)
+                && path.last() == Some(&variant.name())
+                && fields.len() == variant.fields.len()
+                && fields
+                    .iter()
+                    .zip(&variant.fields)
+                    .all(|(pattern, value)| match_pattern(pattern, value, bindings))
+        }
+        (PatternKind::Struct { path, fields, rest }, Value::Struct(instance)) => {
+            path.last() == Some(&instance.ty.name)
+                && (*rest || fields.len() == instance.ty.fields.len())
+                && fields
+                    .iter()
+                    .all(|(name, pattern)| match instance.get(*name) {
+                        Some(value) => match_pattern(pattern, &value, bindings),
+                        None => false,
+                    })
+        }
+        _ => false,
+    }
+}
+
+// Builds an instance of the struct type `ty`, written as `name`, from the
+// values of its fields in any order.
+pub(crate) fn construct(
+    ty: Value,
+    name: &str,
+    fields: Vec<(Symbol, Value)>,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    let ty = match ty {
+        Value::StructType(ty) => ty,
+        other => {
+            return Err(Diagnostic::error(
+                format!("`{}` is a {}, not a struct", name, other.type_name()),
+                span,
+            ))
+        }
+    };
+    let mut values = vec![None; ty.fields.len()];
+    for (field, value) in fields {
+        match ty.fields.iter().position(|name| *name == field) {
+            Some(index) => values[index] = Some(value),
+            None => {
+                return Err(Diagnostic::error(
+                    format!("struct `{}` has no field `{}`", ty.name, field),
+                    span,
+                ))
+            }
+        }
+    }
+    let values = values
+        .into_iter()
+        .zip(&ty.fields)
+        .map(|(value, name)| {
+            value.ok_or_else(|| {
+                Diagnostic::error(format!("missing field `{}` in `{}`", name, ty.name), span)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let instance = Rc::new(Instance {
+        ty,
+        fields: RefCell::new(values),
+    });
+    gc::track(&instance);
+    Ok(Value::Struct(instance))
+}
+
+// `Enum::name`, for the enum type `ty`.
+pub(crate) fn enum_variant(
+    ty: &Rc<EnumType>,
+    name: Symbol,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    ty.variant(name).ok_or_else(|| {
+        Diagnostic::error(
+            format!("enum `{}` has no variant `{}`", ty.name, name),
+            span,
+        )
+    })
+}
+
+// Calls the constructor of the variant at `index` of `ty`.
+pub(crate) fn construct_variant(
+    ty: &Rc<EnumType>,
+    index: usize,
+    args: Vec<Value>,
+    span: Span,
+) -> Result<Value, Diagnostic> {
+    let (name, arity) = &ty.variants[index];
+    if args.len() != *arity {
+        return Err(Diagnostic::error(
+            format!(
+                "`{}::{}` expects {} argument{}, found {}",
+                ty.name,
+                name,
+                arity,
+                if *arity == 1 { "" } else { "s" },
+                args.len()
+            ),
+            span,
+        ));
+    }
+    let variant = Rc::new(Variant {
+        ty: ty.clone(),
+        index,
+        fields: args,
+    });
+    gc::track(&variant);
+    Ok(Value::Variant(variant))
+}
+
+// Gives the struct type `target` the method `name`, for an `impl`.
+pub(crate) fn define_method(
+    target: &Value,
+    name: Symbol,
+    method: Value,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    match target {
+        Value::StructType(ty) => {
+            ty.methods.borrow_mut().insert(name, method);
+            Ok(())
+        }
+        other => Err(Diagnostic::error(
+            format!("cannot define method `{}` on {}", name, other.type_name()),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn get_field(target: &Value, name: Symbol, span: Span) -> Result<Value, Diagnostic> {
+    match target {
+        Value::Struct(instance) => instance.get(name).ok_or_else(|| {
+            Diagnostic::error(
+                format!("struct `{}` has no field `{}`", instance.ty.name, name),
+                span,
+            )
+        }),
+        other => Err(Diagnostic::error(
+            format!("cannot read field `{}` of a {}", name, other.type_name()),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn set_field(
+    target: &Value,
+    name: Symbol,
+    value: Value,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    match target {
+        Value::Struct(instance) if instance.set(name, value) => Ok(()),
+        Value::Struct(instance) => Err(Diagnostic::error(
+            format!("struct `{}` has no field `{}`", instance.ty.name, name),
+            span,
+        )),
+        other => Err(Diagnostic::error(
+            format!(
+                "cannot assign to field `{}` of a {}",
+                name,
+                other.type_name()
+            ),
+            span,
+        )),
+    }
+}
+
+pub(crate) fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => {
+            *a as f64 == *b
+        }
+        (left, right) => left == right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::interpreter::debug::{Debugger, Frame};
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::module::{Alias, ImportMap, ModuleLoader};
+    use crate::interpreter::stack;
+    use crate::interpreter::value::Value;
+    use crate::parser::ast::StmtKind;
+    use crate::parser::parser::parse;
+    use crate::pipeline::pipeline::Pipeline;
+    use crate::typecheck::typecheck::TypeCheckPass;
+
+    fn run(source: &str) -> Value {
+        Interpreter::new().run(&parse(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn evaluates_arithmetic() {
+        assert_eq!(run("1 + 2 * 3 - 4 / 2"), Value::Integer(5));
+        assert_eq!(run("7 % 4 + 0.5"), Value::Float(3.5));
+        assert_eq!(run("\"clay\" + \"!\""), Value::String("clay!".to_string()));
+        assert_eq!(run("6 & 3 | 8 ^ 1"), Value::Integer(11));
+        assert_eq!(
+            run("(~5, -16 >> 2, 1 << 63)").to_string(),
+            "(-6, -4, -9223372036854775808)"
+        );
+        let errors = [
+            ("1 << 64", "cannot shift by 64"),
+            ("1 >> -1", "cannot shift by -1"),
+            ("1.0 & 1", "cannot apply `&` to float and integer"),
+            ("~true", "cannot apply `~` to bool"),
+        ];
+        for (source, message) in errors.iter() {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, *message);
+        }
+    }
+
+    #[test]
+    fn runs_test_blocks_on_their_own() {
+        let source = "
+            fn double(n) { n * 2 }
+            test \"passes\" { assert_eq(double(2), 4); assert(true) }
+            test \"fails\" { assert_eq(\"a\" + \"a\", \"a\") }
+            total = 1;
+            test \"sees the whole file\" { total = total + 1; assert_eq(total, 2) }
+            total
+        ";
+        let program = parse(source).unwrap();
+        assert_eq!(Interpreter::new().run(&program).unwrap(), Value::Integer(1));
+
+        let outcomes: Vec<Result<(), String>> = program
+            .statements
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StmtKind::Test { body, .. } => Some(body),
+                _ => None,
+            })
+            .map(|body| {
+                let result = Interpreter::new().run_test(&program, body);
+                result.map_err(|diagnostic| diagnostic.notes().join(", "))
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Ok(()),
+                Err("left: \"aa\" (string), right: \"a\" (string)".to_string()),
+                Ok(())
+            ]
+        );
+        let err = Interpreter::new()
+            .run(&parse("assert(1)").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "`assert` expects a bool, found integer");
+    }
+
+    #[test]
+    fn assigns_variables() {
+        assert_eq!(run("a = 2; b = a * a; b + 1"), Value::Integer(5));
+    }
+
+    #[test]
+    fn declares_variables_with_let() {
+        let source = "
+            let x = 1;
+            let mut y = x;
+            { let x = 10; y = y + x; }
+            let x = x + 1;
+            (x, y)
+        ";
+        assert_eq!(run(source).to_string(), "(2, 11)");
+
+        let program = parse("let x = 1;\nx = 2").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(
+            err.message,
+            "cannot assign to immutable variable `x`, consider declaring it with `let mut`"
+        );
+        assert_eq!(err.span.start.line, 2);
+    }
+
+    #[test]
+    fn applies_compound_assignment() {
+        let source = "
+            let mut n = 10;
+            n += 5; n -= 3; n *= 2; n /= 4;
+            let mut s = \"cl\";
+            s += \"ay\";
+            (n, s)
+        ";
+        assert_eq!(run(source).to_string(), "(6, clay)");
+
+        let err = Interpreter::new()
+            .run(&parse("missing += 1").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "unknown variable `missing`");
+
+        let err = Interpreter::new()
+            .run(&parse("let n = 1; n += 1").unwrap())
+            .unwrap_err();
+        assert!(err
+            .message
+            .starts_with("cannot assign to immutable variable `n`"));
+    }
+
+    #[test]
+    fn short_circuits_logical_operators() {
+        assert_eq!(run("false && missing"), Value::Bool(false));
+        assert_eq!(run("1 < 2 || missing"), Value::Bool(true));
+    }
+
+    #[test]
+    fn evaluates_match_expressions() {
+        let source = "
+            classify = (3, \"x\");
+            match classify {
+                (0, _) => \"zero\",
+                (n, s) if n > 2 => s + \"!\",
+                _ => \"other\",
+            }
+        ";
+        assert_eq!(run(source), Value::String("x!".to_string()));
+        assert_eq!(
+            run("match -1 { -1 => true, _ => false }"),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn builds_and_matches_structs() {
+        let source = "
+            struct Point { x: Int, y: Int }
+            let p = Point { y: 2, x: 1 };
+            let q = p;
+            q.x = 10;
+            let moved = match p {
+                Point { x: 0, .. } => \"origin\",
+                Point { x, y } if x > y => \"x=\" + \"\",
+                _ => \"other\",
+            };
+            (p, p.x + p.y, moved)
+        ";
+        assert_eq!(run(source).to_string(), "(Point { x: 10, y: 2 }, 12, x=)");
+
+        for (source, message) in [
+            (
+                "struct P { x }\nP { x: 1, y: 2 }",
+                "struct `P` has no field `y`",
+            ),
+            ("struct P { x, y }\nP { x: 1 }", "missing field `y` in `P`"),
+            (
+                "struct P { x }\nP { x: 1 }.z",
+                "struct `P` has no field `z`",
+            ),
+            (
+                "let n = 1;\nn.x = 2",
+                "cannot assign to field `x` of a integer",
+            ),
+            ("Q { x: 1 }", "unknown struct `Q`"),
+        ] {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn propagates_errors_with_question_marks() {
+        let source = "
+            fn parse_bit(s) { if s == \"1\" { Ok(1) } else { Err(\"bad \" + s) } }
+            fn add(a, b) { Ok(parse_bit(a)? + parse_bit(b)?) }
+            fn first(xs) { if xs.len() == 0 { None } else { Some(xs[0]) } }
+            fn double_first(xs) { Some(first(xs)? * 2) }
+            let parsed = match add(\"1\", \"x\") { Result::Ok(n) => n, Result::Err(e) => e };
+            (add(\"1\", \"1\"), parsed, double_first([4]), double_first([]))
+        ";
+        assert_eq!(
+            run(source).to_string(),
+            "(Result::Ok(2), bad x, Option::Some(8), Option::None)"
+        );
+
+        let program = parse("fn f() { 5? } f()").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        let message = "`?` applied to `5`, which is not an Option or Result";
+        assert_eq!(err.message, message);
+        let err = parse("let x = None?;").unwrap_err();
+        assert_eq!(err.message, "`?` used outside a function");
+        assert_eq!(err.span.start.column, 12);
+    }
+
+    #[test]
+    fn iterates_over_ranges_lists_and_strings() {
+        let source = "
+            let mut total = 0;
+            for i in 1..=4 { total += i; }
+            for i in 10..10 { total += i; }
+            let mut letters = [];
+            for c in \"ab\" { letters.push(c); }
+            for (i, c) in [(0, \"x\")] { letters.push(c); }
+            let r = 2..5;
+            (total, letters, r, len(r), (1..=3) == (1..4), [(0..3)])
+        ";
+        assert_eq!(
+            run(source).to_string(),
+            "(10, [a, b, x], 2..5, 3, true, [0..3])"
+        );
+
+        for (source, message) in [
+            ("1..\"a\"", "range bounds must be integers, found string"),
+            ("1.5..2", "range bounds must be integers, found float"),
+        ] {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn destructures_in_let() {
+        let source = "
+            struct Point { x, y }
+            let (a, (b, _)) = (1, (\"two\", 3));
+            let mut Point { x, .. } = Point { x: 4, y: 5 };
+            x += 1;
+            let single = (6,);
+            (a, b, x, single, single[0], (1, \"a\") == (1, \"a\"))
+        ";
+        assert_eq!(run(source).to_string(), "(1, two, 5, (6,), 6, true)");
+
+        let err = Interpreter::new()
+            .run(&parse("let (a, b) = (1, 2, 3)").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "`let` pattern does not match value `(1, 2, 3)`"
+        );
+        let err = Interpreter::new()
+            .run(&parse("let (a, b) = (1, 2); a = 3").unwrap())
+            .unwrap_err();
+        assert!(err
+            .message
+            .starts_with("cannot assign to immutable variable `a`"));
+    }
+
+    #[test]
+    fn constructs_and_matches_enums() {
+        let source = "
+            enum Shape { Circle(Float), Rect(Float, Float), Empty }
+            fn area(shape) {
+                match shape {
+                    Shape::Circle(r) => 3.0 * r * r,
+                    Shape::Rect(w, h) => w * h,
+                    Shape::Empty => 0.0,
+                }
+            }
+            let shapes = [Shape::Circle(1.0), Shape::Rect(2.0, 3.0), Shape::Empty];
+            (shapes.map(area), shapes[1], Shape::Empty == Shape::Empty)
+        ";
+        assert_eq!(
+            run(source).to_string(),
+            "([3.0, 6.0, 0.0], Shape::Rect(2.0, 3.0), true)"
+        );
+
+        for (source, message) in [
+            (
+                "enum E { A(Int) }\nE::A(1, 2)",
+                "`E::A` expects 1 argument, found 2",
+            ),
+            ("enum E { A }\nE::B", "enum `E` has no variant `B`"),
+        ] {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn matches_the_prelude_variants_by_their_own_names() {
+        let source = "
+            fn describe(value) {
+                match value {
+                    None => \"none\",
+                    Some(Ok(n)) => n * 10,
+                    Some(Err(e)) => \"err \" + e,
+                    Some(_) => \"some\",
+                }
+            }
+            [None, Some(Ok(1)), Some(Err(\"no\")), Some(2)].map(describe)
+        ";
+        assert_eq!(run(source).to_string(), "[none, 10, err no, some]");
+        // `None` is the variant, not a binding that catches everything.
+        assert_eq!(
+            run("match Some(1) { None => \"none\", _ => \"some\" }").to_string(),
+            "some"
+        );
+        assert_eq!(
+            run("match Some(1) { Option::Some(n) => n, None => 0 }").to_string(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn match_bindings_do_not_leak() {
+        let program = parse("match 1 { n => n }; n").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "unknown variable `n`");
+    }
+
+    fn write_modules(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("clay-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn imports_modules_into_their_own_namespace() {
+        let dir = write_modules(
+            "imports",
+            &[
+                ("main.clay", "x = 1; import \"util.clay\"; x + util::x"),
+                ("util.clay", "x = 41;"),
+            ],
+        );
+        let main = dir.join("main.clay");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&main);
+        let program = parse(&std::fs::read_to_string(&main).unwrap()).unwrap();
+        assert_eq!(interpreter.run(&program).unwrap(), Value::Integer(42));
+    }
+
+    #[test]
+    fn imports_modules_under_aliases_and_their_members_by_name() {
+        let dir = write_modules(
+            "selective",
+            &[
+                (
+                    "main.clay",
+                    "import \"util.clay\" as u;\n\
+                     import { double, x as base } from \"util.clay\";\n\
+                     import std::path as paths;\n\
+                     import { join } from std::path;\n\
+                     (u::x, double(base), join(\"a\", \"b\") == paths::join(\"a\", \"b\"))",
+                ),
+                ("util.clay", "let x = 21; fn double(n) { n * 2 }"),
+                ("missing.clay", "import { triple } from \"util.clay\";"),
+                (
+                    "misspelled.clay",
+                    "import { doubel as twice } from \"util.clay\";",
+                ),
+                ("path.clay", "import \"util.clay\"; util::dobule(1)"),
+                (
+                    "exits.clay",
+                    "let mut ran = false; import \"quits.clay\"; ran = true;",
+                ),
+                ("quits.clay", "exit(5);"),
+            ],
+        );
+        let run = |file: &str| {
+            let path = dir.join(file);
+            let mut interpreter = Interpreter::new();
+            interpreter.set_file(&path);
+            let program = parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let result = interpreter.run(&program);
+            (result, interpreter)
+        };
+        let (result, _) = run("main.clay");
+        assert_eq!(result.unwrap().to_string(), "(21, 42, true)");
+
+        let err = run("missing.clay").0.unwrap_err();
+        assert_eq!(err.message, "module `util` has no member `triple`");
+        assert_eq!(err.span.start.column, 9);
+        assert!(err.suggestions().is_empty());
+
+        // Misspelled members are fixed in place, keeping any alias.
+        let fixes = |file: &str| -> Vec<(String, String)> {
+            let err = run(file).0.unwrap_err();
+            let fixes = err.suggestions().iter();
+            fixes
+                .map(|fix| (fix.message.clone(), fix.replacement.clone()))
+                .collect()
+        };
+        let double = "did you mean `double`?".to_string();
+        assert_eq!(
+            fixes("misspelled.clay"),
+            [(double.clone(), "double as twice".to_string())]
+        );
+        assert_eq!(fixes("path.clay"), [(double, "util::double".to_string())]);
+        let err = run("path.clay").0.unwrap_err();
+        assert_eq!(err.message, "module `util` has no member `dobule`");
+
+        // An `exit` in a module stops the program that imports it.
+        let (result, interpreter) = run("exits.clay");
+        assert_eq!(result.unwrap(), Value::Unit);
+        assert_eq!(interpreter.exit_code(), Some(5));
+        assert_eq!(interpreter.global("ran"), Some(Value::Bool(false)));
+    }
+
+    #[test]
+    fn preloads_the_import_graph() {
+        let dir = write_modules(
+            "preload",
+            &[
+                (
+                    "main.clay",
+                    "import \"a.clay\"; import \"b.clay\"; a::x + b::x",
+                ),
+                ("a.clay", "import \"c.clay\"; x = c::x + 1;"),
+                ("b.clay", "import \"c.clay\"; x = c::x + 2;"),
+                ("c.clay", "x = 19;"),
+                ("broken.clay", "import \"bad.clay\";"),
+                ("bad.clay", "x = ;"),
+            ],
+        );
+        let main = dir.join("main.clay");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&main);
+        let program = parse(&std::fs::read_to_string(&main).unwrap()).unwrap();
+        interpreter.preload_imports(&program);
+        let files: Vec<String> = interpreter.sources().files();
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().all(|file| !file.ends_with("main.clay")));
+        assert_eq!(interpreter.run(&program).unwrap(), Value::Integer(41));
+
+        let broken = dir.join("broken.clay");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&broken);
+        let program = parse(&std::fs::read_to_string(&broken).unwrap()).unwrap();
+        interpreter.preload_imports(&program);
+        let err = interpreter.run(&program).unwrap_err();
+        assert!(err.file.unwrap().ends_with("bad.clay"));
+    }
+
+    #[test]
+    fn checks_imported_modules_without_running_them() {
+        let dir = write_modules(
+            "check",
+            &[
+                (
+                    "main.clay",
+                    "import \"a.clay\"; import \"missing.clay\"; import std::os;",
+                ),
+                ("a.clay", "import \"b.clay\"; print(\"ran\"); y = 1 + true;"),
+                ("b.clay", "z = ;"),
+            ],
+        );
+        let main = dir.join("main.clay").canonicalize().unwrap();
+        let mut pipeline = Pipeline::new();
+        pipeline.register(TypeCheckPass);
+        let mut loader = ModuleLoader::new(pipeline);
+        loader.set_root(&main);
+        let program = parse(&std::fs::read_to_string(&main).unwrap()).unwrap();
+        let reported: Vec<(String, Option<String>)> = loader
+            .check(&program, Some(&main))
+            .into_iter()
+            .map(|diagnostic| {
+                let file = diagnostic
+                    .file
+                    .map(|file| file.rsplit('/').next().unwrap().into());
+                (diagnostic.message, file)
+            })
+            .collect();
+        assert_eq!(reported.len(), 3);
+        assert_eq!(
+            reported[0],
+            (
+                "cannot apply `+` to `Int` and `Bool`".into(),
+                Some("a.clay".into())
+            )
+        );
+        assert!(reported[1]
+            .0
+            .starts_with("could not find module \"missing.clay\""));
+        assert_eq!(reported[1].1, None);
+        assert_eq!(reported[2].1.as_deref(), Some("b.clay"));
+    }
+
+    #[test]
+    fn reports_import_cycles() {
+        let dir = write_modules(
+            "cycles",
+            &[
+                ("a.clay", "import \"b.clay\";"),
+                ("b.clay", "import \"a.clay\";"),
+            ],
+        );
+        let main = dir.join("a.clay");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&main);
+        let err = interpreter
+            .run(&parse("import \"b.clay\";").unwrap())
+            .unwrap_err();
+        assert!(err.message.starts_with("import cycle detected: "));
+        assert!(err.file.unwrap().ends_with("b.clay"));
+    }
+
+    #[test]
+    fn resolves_imports_through_the_import_map() {
+        let dir = write_modules(
+            "import-map",
+            &[
+                ("src/main.clay", ""),
+                ("lib/utils/strings.clay", "x = 1;"),
+                ("lib/shapes.clay", "x = 2;"),
+                ("vendor/std/math.clay", "x = 3;"),
+            ],
+        );
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&dir.join("src/main.clay"));
+        interpreter.set_import_map(ImportMap {
+            aliases: vec![
+                Alias {
+                    name: "@utils".to_string(),
+                    target: dir.join("lib/utils"),
+                    declared: "`@utils`".to_string(),
+                },
+                Alias {
+                    name: "std".to_string(),
+                    target: dir.join("vendor/std"),
+                    declared: "`std`".to_string(),
+                },
+            ],
+            roots: vec![dir.join("lib")],
+        });
+
+        let source = "
+            import \"@utils/strings.clay\";
+            import shapes;
+            import std::math;
+            strings::x + shapes::x + math::x
+        ";
+        let program = parse(source).unwrap();
+        assert_eq!(interpreter.run(&program).unwrap(), Value::Integer(6));
+
+        let program = parse("import \"@utils/missing.clay\";").unwrap();
+        let err = interpreter.run(&program).unwrap_err();
+        assert!(
+            err.message
+                .contains("missing.clay`, resolved from `@utils`: "),
+            "{}",
+            err.message
+        );
+        let program = parse("import missing;").unwrap();
+        let err = interpreter.run(&program).unwrap_err();
+        assert!(err
+            .message
+            .starts_with("could not find module missing, looked for `"));
+        assert!(
+            err.message.ends_with("lib/missing.clay`"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn calls_functions_and_closures() {
+        let source = "
+            fn make_adder(n) { fn(x) { x + n } }
+            fn fact(n) { match n { 0 => 1, _ => n * fact(n - 1) } }
+            add = fn(a) { fn(b) { a + b } };
+            (make_adder(2)(3), fact(5), add(1)(2))
+        ";
+        assert_eq!(
+            run(source),
+            Value::Tuple(vec![
+                Value::Integer(5),
+                Value::Integer(120),
+                Value::Integer(3)
+            ])
+        );
+    }
+
+    #[test]
+    fn supports_higher_order_functions() {
+        // Lists are cons cells built from pairs and terminated by `false`.
+        let source = "
+            fn map(f, xs) { match xs { (x, rest) => (f(x), map(f, rest)), _ => false } }
+            fn filter(p, xs) {
+                match xs {
+                    (x, rest) if p(x) => (x, filter(p, rest)),
+                    (_, rest) => filter(p, rest),
+                    _ => false,
+                }
+            }
+            xs = (1, (2, (3, (4, false))));
+            map(fn(x) { x * 10 }, filter(fn(x) { x % 2 == 0 }, xs))
+        ";
+        assert_eq!(run(source).to_string(), "(20, (40, false))");
+    }
+
+    #[test]
+    fn returns_early_from_functions() {
+        let source = "
+            fn sign(n) { match n { 0 => return 0, _ => {} }; match n > 0 { true => 1, false => -1 } }
+            (sign(0), sign(-5))
+        ";
+        assert_eq!(run(source).to_string(), "(0, -1)");
+
+        let err = Interpreter::new()
+            .run(&parse("return 1").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "`return` outside of a function");
+    }
+
+    #[test]
+    fn evaluates_conditionals() {
+        let source = "
+            fn sign(n) { if n > 0 { 1 } else if n < 0 { -1 } else { 0 } }
+            (sign(5), sign(-5), sign(0), if false { 1 })
+        ";
+        assert_eq!(run(source).to_string(), "(1, -1, 0, ())");
+
+        let err = Interpreter::new()
+            .run(&parse("if 1 { 2 }").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "expected bool, found integer");
+    }
+
+    #[test]
+    fn runs_loops() {
+        let source = "
+            total = 0;
+            i = 0;
+            while true {
+                i = i + 1;
+                if i > 10 { break }
+                if i % 2 == 0 { continue }
+                total = total + i;
+            }
+            for (a, b) in ((1, 2), (3, 4)) { total = total + a * b }
+            letters = \"\";
+            for c in \"abc\" { letters = c + letters }
+            (total, letters)
+        ";
+        assert_eq!(run(source).to_string(), "(39, cba)");
+
+        let err = Interpreter::new()
+            .run(&parse("fn f() { break }; while true { f() }").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "`break` outside of a loop");
+
+        let err = Interpreter::new()
+            .run(&parse("for x in 3 {}").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "cannot iterate over integer");
+    }
+
+    #[test]
+    fn supports_lists() {
+        let source = "
+            let xs = [1, 2, 3];
+            let ys = xs;
+            ys.push(4);
+            let doubled = xs.map(fn(x) { x * 2 }).filter(fn(x) { x > 2 });
+            let last = doubled.pop();
+            (xs.len(), xs[0], xs[1..3], xs[..1], doubled, \"clay\"[1..], last)
+        ";
+        assert_eq!(
+            run(source).to_string(),
+            "(4, 1, [2, 3], [1], [4, 6], lay, 8)"
+        );
+        assert_eq!(run("[1, (2, 3)] == [1, (2, 3)]"), Value::Bool(true));
+
+        let errors = [
+            ("[1, 2][2]", "index 2 is out of bounds for length 2"),
+            ("[1, 2][1..3]", "slice 1..3 is out of bounds for length 2"),
+            ("[1].push()", "`push` expects 1 argument, found 0"),
+            ("1.size()", "integer has no method `size`"),
+        ];
+        for (source, message) in errors.iter() {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, *message);
+        }
+    }
+
+    #[test]
+    fn supports_maps() {
+        let source = "
+            let ages = #{ \"ada\": 36, \"alan\": 41 };
+            ages.insert(\"grace\", 85);
+            let removed = ages.remove(\"alan\");
+            let mut total = 0;
+            for (name, age) in ages { total += age }
+            (ages[\"ada\"], removed, total, ages.contains(\"alan\"), ages.keys(), ages)
+        ";
+        assert_eq!(
+            run(source).to_string(),
+            "(36, 41, 121, false, [ada, grace], #{ ada: 36, grace: 85 })"
+        );
+        assert_eq!(
+            run("#{ (1, true): [1] } == #{ (1, true): [1] }"),
+            Value::Bool(true)
+        );
+        assert_eq!(run("#{}").to_string(), "#{}");
+
+        let errors = [
+            ("#{ 1: 2 }[3]", "key `3` not found in map"),
+            ("#{ 1.5: 2 }", "cannot use float as a map key"),
+            ("#{}.remove([])", "cannot use list as a map key"),
+        ];
+        for (source, message) in errors.iter() {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, *message);
+        }
+    }
+
+    // Records each paused-at statement as `line: frame > frame`.
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Debugger for Recorder {
+        fn on_statement(&mut self, frames: &[Frame]) {
+            let names: Vec<&str> = frames.iter().map(|frame| frame.name.as_str()).collect();
+            let line = frames.last().unwrap().span.start.line;
+            self.0
+                .borrow_mut()
+                .push(format!("{}: {}", line, names.join(" > ")));
+        }
+    }
+
+    #[test]
+    fn reports_statements_to_debuggers() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Box::new(Recorder(trace.clone())));
+        let source = "fn double(n) {\n  n * 2\n}\nlet x = double(1);\nx";
+        interpreter.run(&parse(source).unwrap()).unwrap();
+        assert_eq!(
+            *trace.borrow(),
+            vec!["1: <main>", "4: <main>", "2: <main> > double", "5: <main>"]
+        );
+    }
+
+    #[test]
+    fn checks_call_arity() {
+        let program = parse("fn f(a, b) { a }; f(1)").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "`f` expects 2 arguments, found 1");
+    }
+
+    #[test]
+    fn reports_runtime_errors() {
+        let program = parse("1 / 0").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "division by zero");
+
+        let program = parse("1 + true").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "cannot apply `+` to integer and bool");
+    }
+
+    #[test]
+    fn reports_deep_recursion_as_a_stack_overflow() {
+        let source = "fn down(n) { 1 + down(n + 1) }\ndown(0)";
+        let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+        assert_eq!(err.message, "stack overflow");
+        assert!(err.calls().len() > 10);
+        assert!(err.calls().iter().all(|call| call.function == "down"));
+
+        // A thread with a bigger stack lets programs recurse deeper.
+        let countdown = "fn count(n) { if n == 0 { 0 } else { 1 + count(n - 1) } }\ncount(500)";
+        let value = stack::grow(64 << 20, || {
+            Interpreter::new()
+                .run(&parse(countdown).unwrap())
+                .map(|value| value.to_string())
+        });
+        assert_eq!(value.unwrap(), "500");
+    }
+
+    #[test]
+    fn runs_recursion_in_tail_position_in_constant_stack() {
+        let source = "
+            fn count(n, total) { if n == 0 { total } else { count(n - 1, total + 1) } }
+            fn even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fn odd(n) { if n == 0 { false } else { return even(n - 1); } }
+            (count(1000000, 0), even(1001))
+        ";
+        let value = Interpreter::new().run(&parse(source).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "(1000000, false)");
+
+        // The calls that were replaced still show in the trace, once for
+        // each place they were made from.
+        let source = "
+            fn down(n) { if n == 0 { 1 / 0 } else { down(n - 1) } }
+            fn start() { down(5) }
+            start()
+        ";
+        let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+        let calls: Vec<_> = err
+            .calls()
+            .iter()
+            .map(|call| (call.function.as_str(), call.span.start.line))
+            .collect();
+        assert_eq!(calls, [("down", 2), ("down", 2), ("down", 3), ("start", 4)]);
+        let err = Interpreter::new()
+            .run(&parse("fn f() { 1 }\nreturn f()").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "`return` outside of a function");
+    }
+
+    #[test]
+    fn profiles_the_calls_a_program_makes() {
+        let source = "fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }\nlen([fib(5)])";
+        let mut interpreter = Interpreter::new();
+        interpreter.start_profile();
+        interpreter.run(&parse(source).unwrap()).unwrap();
+        let profile = interpreter.take_profile().unwrap();
+        let mut calls: Vec<_> = profile
+            .functions()
+            .into_iter()
+            .map(|(name, calls)| (name.to_string(), calls.count))
+            .collect();
+        calls.sort();
+        assert_eq!(
+            calls,
+            [
+                ("<main>".to_string(), 1),
+                ("fib".to_string(), 15),
+                ("len".to_string(), 1)
+            ]
+        );
+        assert!(profile.opcodes().is_empty());
+        assert!(interpreter.take_profile().is_none());
+    }
+
+    #[test]
+    fn suggests_names_close_to_unknown_ones() {
+        let fix = |source: &str| {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            let fix = err.suggestions().first().cloned()?;
+            Some((fix.message, fix.span.start.column, fix.replacement))
+        };
+        let length = (
+            "did you mean `length`?".to_string(),
+            8,
+            "length".to_string(),
+        );
+        assert_eq!(fix("let length = 3;\nfn f(){ lenght }\nf()"), Some(length));
+        let print = ("did you mean `print`?".to_string(), 0, "print".to_string());
+        assert_eq!(fix("prnt(1)"), Some(print));
+        assert_eq!(fix("let x = 1; y"), None);
+        assert_eq!(fix("let total = 1; count"), None);
+    }
+
+    #[test]
+    fn traces_the_calls_an_error_unwinds_through() {
+        let source = "fn divide(n) { n / 0 }\nfn apply(f, n) { f(n) }\napply(divide, 1)";
+        let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+        assert_eq!(err.message, "division by zero");
+        let trace: Vec<_> = err
+            .calls()
+            .iter()
+            .map(|call| {
+                (
+                    call.function.as_str(),
+                    call.span.start.line,
+                    call.span.start.column,
+                )
+            })
+            .collect();
+        assert_eq!(trace, vec![("divide", 2, 17), ("apply", 3, 0)]);
+
+        // Calling something that isn't a function is an error at the call.
+        let source = "fn f() { 1() }\nf()";
+        let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+        assert_eq!(err.message, "cannot call a integer");
+        assert_eq!(err.calls().len(), 1);
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+// Where the standard library's I/O functions read and write. Hosts swap it
+// out to capture a program's output or feed it input. Files can't be
+// touched unless an implementation opts in, so a host that only overrides
+// the console has sandboxed the file system too.
+pub trait Io {
+    fn write(&mut self, text: &str) -> io::Result<()>;
+
+    // The next line of input including its line ending, so that only the
+    // end of the input reads as "".
+    fn read_line(&mut self) -> io::Result<String>;
+
+    fn read_file(&mut self, _path: &Path) -> io::Result<String> {
+        Err(denied())
+    }
+
+    fn write_file(&mut self, _path: &Path, _contents: &str) -> io::Result<()> {
+        Err(denied())
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "file access is not allowed",
+    )
+}
+
+// The process's standard input and output and the real file system.
+#[derive(Debug, Default)]
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        // `print` doesn't end the line, and prompts should still show up.
+        stdout.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        Ok(line)
+    }
+
+    fn read_file(&mut self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+}
+
+// The process's standard output, with no input to read, so `read_line`
+// always returns "". It denies file access.
+#[derive(Debug, Default)]
+pub struct StdoutIo;
+
+impl Io for StdoutIo {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        StdIo.write(text)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        Ok(String::new())
+    }
+}
+
+// Console I/O in memory: input is read from a string and output is
+// collected into one. It denies file access.
+#[derive(Debug, Default)]
+pub struct BufferIo {
+    input: String,
+    // How much of `input` has been read.
+    read: usize,
+    output: String,
+}
+
+impl BufferIo {
+    pub fn new() -> BufferIo {
+        BufferIo::default()
+    }
+
+    pub fn with_input(input: impl Into<String>) -> BufferIo {
+        BufferIo {
+            input: input.into(),
+            ..BufferIo::default()
+        }
+    }
+
+    // Everything written so far.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+}
+
+impl Io for BufferIo {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let rest = &self.input[self.read..];
+        let end = rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+        self.read += end;
+        Ok(rest[..end].to_string())
+    }
+}
@@ -0,0 +1,306 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+use serde::{Deserialize, Deserializer};
+
+use crate::interpreter::stdlib::{option, ENUMS};
+use crate::interpreter::value::{Key, Value};
+
+// Clay values go to and from any format serde supports, JSON included:
+//
+// - unit is null, and tuples, lists and ranges are sequences;
+// - maps and structs are maps, structs by their field names;
+// - options are what serde makes of Rust's: `None` is null and `Some(1)`
+//   is 1;
+// - other enum values are tagged the way serde tags Rust enums: `Dot` is
+//   "Dot" and `Circle(2)` is `{ "Circle": 2 }`, with several values in a
+//   list.
+//
+// Functions, modules and types have no data to write and fail, as do
+// lists, maps and structs that contain themselves. What comes back from a
+// format is built from `None`, numbers, strings, bools, lists and maps.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let open = RefCell::new(Vec::new());
+        Nested {
+            value: self,
+            open: &open,
+        }
+        .serialize(serializer)
+    }
+}
+
+// A value inside the lists, maps and structs `open` points to, which are
+// being written.
+struct Nested<'a> {
+    value: &'a Value,
+    open: &'a RefCell<Vec<usize>>,
+}
+
+impl Nested<'_> {
+    fn nested<'a>(&'a self, value: &'a Value) -> Nested<'a> {
+        Nested {
+            value,
+            open: self.open,
+        }
+    }
+
+    // Writes what the shared value at `address` contains with `write`.
+    fn enter<S: Serializer>(
+        &self,
+        address: usize,
+        write: impl FnOnce() -> Result<S::Ok, S::Error>,
+    ) -> Result<S::Ok, S::Error> {
+        if self.open.borrow().contains(&address) {
+            return Err(ser::Error::custom(format!(
+                "can't serialize a {} that contains itself",
+                self.value.type_name()
+            )));
+        }
+        self.open.borrow_mut().push(address);
+        let result = write();
+        self.open.borrow_mut().pop();
+        result
+    }
+}
+
+impl Serialize for Nested<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Unit => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Integer(n) => serializer.serialize_i64(*n),
+            Value::Float(n) => serializer.serialize_f64(*n),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Tuple(values) => {
+                serializer.collect_seq(values.iter().map(|value| self.nested(value)))
+            }
+            Value::Range(start, end) => serializer.collect_seq(*start..*end),
+            Value::List(list) => self.enter::<S>(Rc::as_ptr(list) as usize, || {
+                let list = list.borrow();
+                serializer.collect_seq(list.iter().map(|value| self.nested(value)))
+            }),
+            Value::Map(map) => self.enter::<S>(Rc::as_ptr(map) as usize, || {
+                let map = map.borrow();
+                serializer.collect_map(map.iter().map(|(key, value)| (key, self.nested(value))))
+            }),
+            Value::Struct(instance) => self.enter::<S>(Rc::as_ptr(instance) as usize, || {
+                let fields = instance.fields.borrow();
+                let names = instance.ty.fields.iter().map(|name| name.as_str());
+                serializer.collect_map(names.zip(fields.iter().map(|value| self.nested(value))))
+            }),
+            Value::Variant(variant) if variant.ty.name == ENUMS[0].0 => match &variant.fields[..] {
+                [value] => self.nested(value).serialize(serializer),
+                _ => serializer.serialize_none(),
+            },
+            Value::Variant(variant) => {
+                let name = variant.name().as_str();
+                match &variant.fields[..] {
+                    [] => serializer.serialize_str(name),
+                    [value] => serializer.collect_map([(name, self.nested(value))]),
+                    values => {
+                        let values: Vec<_> =
+                            values.iter().map(|value| self.nested(value)).collect();
+                        serializer.collect_map([(name, values)])
+                    }
+                }
+            }
+            other => Err(ser::Error::custom(format!(
+                "can't serialize {} values",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Key::Bool(b) => serializer.serialize_bool(*b),
+            Key::Integer(n) => serializer.serialize_i64(*n),
+            Key::String(s) => serializer.serialize_str(s),
+            Key::Tuple(keys) => serializer.collect_seq(keys),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a clay value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(option(None))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(option(None))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Integer(n))
+    }
+
+    // Integers too big for clay become floats, as they do in JavaScript.
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        Ok(i64::try_from(n).map_or(Value::Float(n as f64), Value::Integer))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Float(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::list(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+            let key = Key::from_value(&key).ok_or_else(|| {
+                de::Error::custom(format!("a {} can't be a map key", key.type_name()))
+            })?;
+            entries.insert(key, value);
+        }
+        Ok(Value::map(entries))
+    }
+}
+
+// `value` as JSON text, on one line.
+pub fn to_json(value: &Value) -> serde_json::Result<String> {
+    serde_json::to_string(value)
+}
+
+pub fn from_json(text: &str) -> serde_json::Result<Value> {
+    serde_json::from_str(text)
+}
+
+// Converts data a host has in Rust to clay, by way of JSON, for handing
+// structured data across to clay code.
+pub fn to_value<T: Serialize + ?Sized>(data: &T) -> serde_json::Result<Value> {
+    serde_json::from_value(serde_json::to_value(data)?)
+}
+
+// Converts a clay value to data in Rust, by way of JSON, so map keys have
+// to be strings.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> serde_json::Result<T> {
+    serde_json::from_value(serde_json::to_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::json::{from_json, from_value, to_json, to_value};
+    use crate::interpreter::value::Value;
+    use crate::parser::parser::parse;
+
+    fn eval(source: &str) -> Value {
+        Interpreter::new().run(&parse(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn converts_values_to_json_and_back() {
+        let source = "
+            struct Point { x, y }
+            enum Shape { Dot, Circle(r), Rect(w, h) }
+            let unit = {};
+            #{
+                \"point\": Point { x: 1, y: 2.5 },
+                \"shapes\": [Shape::Dot, Shape::Circle(2), Shape::Rect(1, 2)],
+                \"misc\": (unit, true, \"hi\", 0..3, None, Some(1), Some(None)),
+            }
+        ";
+        let json = to_json(&eval(source)).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"misc":[null,true,"hi",[0,1,2],null,1,null],"#,
+                r#""point":{"x":1,"y":2.5},"#,
+                r#""shapes":["Dot",{"Circle":2},{"Rect":[1,2]}]}"#
+            )
+        );
+        let value =
+            from_json(r#"{"a": [1, 2.5, null, 18446744073709551615], "b": {"c": false}}"#).unwrap();
+        assert_eq!(
+            value.to_string(),
+            "#{ a: [1, 2.5, Option::None, 1.8446744073709552e19], b: #{ c: false } }"
+        );
+
+        let errors = [
+            (
+                "let l = [1]; l.push(l); l",
+                "can't serialize a list that contains itself",
+            ),
+            ("fn f() {} [f]", "can't serialize function values"),
+        ];
+        for (source, message) in errors {
+            assert_eq!(to_json(&eval(source)).unwrap_err().to_string(), message);
+        }
+        // Lists seen twice side by side are fine.
+        assert_eq!(to_json(&eval("let l = [1]; [l, l]")).unwrap(), "[[1],[1]]");
+    }
+
+    #[test]
+    fn converts_rust_data_through_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            name: String,
+            ports: Vec<u16>,
+            tags: BTreeMap<String, bool>,
+            proxy: Option<String>,
+        }
+
+        let config = Config {
+            name: "web".to_string(),
+            ports: vec![80, 443],
+            tags: BTreeMap::from([("public".to_string(), true)]),
+            proxy: None,
+        };
+        let value = to_value(&config).unwrap();
+        assert_eq!(
+            value.to_string(),
+            "#{ name: web, ports: [80, 443], proxy: Option::None, tags: #{ public: true } }"
+        );
+        assert_eq!(from_value::<Config>(&value).unwrap(), config);
+        assert!(from_value::<Config>(&Value::Integer(1)).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::interpreter::gc;
+use crate::interpreter::profile::format_duration;
+
+// How often, in steps, the clock is read for the timeout.
+const CLOCK_INTERVAL: u64 = 256;
+
+// Bounds on what running code may use, for hosts running code they don't
+// trust. `None` leaves a resource unbounded.
+//
+// Native functions run to completion once called, so a single call, like
+// building a huge range, can still take long or allocate much.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    // How many expressions may be evaluated.
+    pub steps: Option<u64>,
+    // How many lists, maps, structs, enum values, functions and scopes may
+    // be allocated, counting those freed since.
+    pub allocations: Option<u64>,
+    // How many bytes the strings built may hold in all, counting those
+    // freed since.
+    pub bytes: Option<u64>,
+    // How many calls to clay functions may be running at once.
+    pub depth: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+// A limit running code went past.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Allocations(u64),
+    Bytes(u64),
+    Depth(usize),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(steps) => write!(f, "took more than {} steps", steps),
+            Limit::Allocations(allocations) => {
+                write!(f, "made more than {} heap allocations", allocations)
+            }
+            Limit::Bytes(bytes) => write!(f, "built more than {} bytes of strings", bytes),
+            Limit::Depth(depth) => write!(f, "made more than {} nested calls", depth),
+            Limit::Timeout(timeout) => {
+                write!(f, "ran for longer than {}", format_duration(*timeout))
+            }
+        }
+    }
+}
+
+// What code has used of its limits since it started running. Once a limit
+// is exceeded it stays exceeded, so code can't run on past it.
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    // The allocation count and the bytes of strings built when the code
+    // started.
+    allocated: u64,
+    built: u64,
+    deadline: Option<Instant>,
+    exceeded: Option<Limit>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            steps: 0,
+            allocated: gc::allocated(),
+            built: gc::built(),
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            exceeded: None,
+        }
+    }
+
+    // Counts one step at a call depth of `depth`, returning the limit the
+    // code has now gone past, if any.
+    pub fn step(&mut self, depth: usize) -> Option<Limit> {
+        if self.exceeded.is_some() {
+            return self.exceeded;
+        }
+        self.steps += 1;
+        let limits = self.limits;
+        self.exceeded = match limits {
+            Limits {
+                steps: Some(max), ..
+            } if self.steps > max => Some(Limit::Steps(max)),
+            Limits {
+                depth: Some(max), ..
+            } if depth > max => Some(Limit::Depth(max)),
+            Limits {
+                allocations: Some(max),
+                ..
+            } if gc::allocated() - self.allocated > max => Some(Limit::Allocations(max)),
+            Limits {
+                bytes: Some(max), ..
+            } if gc::built() - self.built > max => Some(Limit::Bytes(max)),
+            Limits {
+                timeout: Some(timeout),
+                ..
+            } if self.steps.is_multiple_of(CLOCK_INTERVAL)
+                && self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() > deadline) =>
+            {
+                Some(Limit::Timeout(timeout))
+            }
+            _ => None,
+        };
+        self.exceeded
+    }
+
+    pub fn exceeded(&self) -> Option<Limit> {
+        self.exceeded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::interpreter::convert::IntoClay;
+    use crate::interpreter::limits::{Budget, Limit, Limits};
+    use crate::interpreter::value::Value;
+
+    #[test]
+    fn stops_at_the_first_limit_exceeded() {
+        let mut budget = Budget::new(Limits {
+            steps: Some(2),
+            depth: Some(3),
+            ..Limits::default()
+        });
+        assert_eq!(budget.step(3), None);
+        assert_eq!(budget.step(4), Some(Limit::Depth(3)));
+        // It stays exceeded.
+        assert_eq!(budget.step(0), Some(Limit::Depth(3)));
+        assert_eq!(budget.exceeded(), Some(Limit::Depth(3)));
+
+        let mut budget = Budget::new(Limits {
+            steps: Some(2),
+            ..Limits::default()
+        });
+        assert_eq!(budget.step(0), None);
+        assert_eq!(budget.step(0), None);
+        assert_eq!(budget.step(0), Some(Limit::Steps(2)));
+
+        let mut budget = Budget::new(Limits {
+            allocations: Some(1),
+            ..Limits::default()
+        });
+        let _one = Value::list(Vec::new());
+        assert_eq!(budget.step(0), None);
+        let _two = Value::list(Vec::new());
+        assert_eq!(budget.step(0), Some(Limit::Allocations(1)));
+
+        let mut budget = Budget::new(Limits {
+            bytes: Some(4),
+            ..Limits::default()
+        });
+        let _four = "four".into_clay();
+        assert_eq!(budget.step(0), None);
+        let _more = "more".into_clay();
+        assert_eq!(budget.step(0), Some(Limit::Bytes(4)));
+
+        let timeout = Duration::from_millis(1);
+        let mut budget = Budget::new(Limits {
+            timeout: Some(timeout),
+            ..Limits::default()
+        });
+        thread::sleep(timeout * 2);
+        let steps = (0..256).map(|_| budget.step(0)).last().unwrap();
+        assert_eq!(steps, Some(Limit::Timeout(timeout)));
+        assert_eq!(
+            Limit::Timeout(timeout).to_string(),
+            "ran for longer than 1.00ms"
+        );
+    }
+}
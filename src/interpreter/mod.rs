@@ -0,0 +1,20 @@
+pub mod calendar;
+pub mod convert;
+pub mod debug;
+pub mod engine;
+pub mod environment;
+pub mod formats;
+pub mod gc;
+pub mod heap;
+#[allow(clippy::module_inception)]
+pub mod interpreter;
+pub mod io;
+pub mod json;
+pub mod limits;
+pub mod module;
+pub mod native;
+pub mod profile;
+pub mod stack;
+pub mod stdlib;
+pub mod system;
+pub mod value;
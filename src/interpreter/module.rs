@@ -0,0 +1,477 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+
+use crate::diagnostic::diagnostic::{Diagnostic, Severity};
+use crate::diagnostic::source_map::SourceMap;
+use crate::interpreter::environment::Environment;
+use crate::interpreter::stdlib;
+use crate::lexer::lexer::LexerOptions;
+use crate::lexer::token::Span;
+use crate::parser::ast::{ImportPath, Program, Stmt, StmtKind};
+use crate::parser::cache::ParseCache;
+use crate::parser::parser::parse_with_options;
+use crate::parser::visit::{walk_stmt, Visitor};
+use crate::pipeline::pipeline::Pipeline;
+
+pub const EXTENSION: &str = "clay";
+
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Module) -> bool {
+        self.path == other.path
+    }
+}
+
+// Where imports look besides the importing file's directory, usually read
+// from the project manifest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportMap {
+    pub aliases: Vec<Alias>,
+    // Searched in order for `import a::b` when the importing file's
+    // directory has no `a/b.clay`.
+    pub roots: Vec<PathBuf>,
+}
+
+// Maps the first segment of a module path, or the leading component of a
+// file import, to a directory or file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    pub name: String,
+    pub target: PathBuf,
+    // Where the alias was declared, for diagnostics.
+    pub declared: String,
+}
+
+impl ImportMap {
+    fn alias(&self, name: &str) -> Option<&Alias> {
+        self.aliases.iter().find(|alias| alias.name == name)
+    }
+
+    // The candidate files for `path`, in the order they are tried, and the
+    // alias that produced them if there was one.
+    fn candidates(&self, path: &ImportPath, base: &Path) -> (Vec<PathBuf>, Option<&Alias>) {
+        match path {
+            ImportPath::File(file) => {
+                let (first, rest) = file.split_once('/').unwrap_or((file, ""));
+                match self.alias(first) {
+                    Some(alias) => (vec![alias.target.join(rest)], Some(alias)),
+                    None => (vec![base.join(file)], None),
+                }
+            }
+            ImportPath::Module(segments) => {
+                let mut relative: PathBuf = segments.iter().map(|s| s.as_str()).collect();
+                relative.set_extension(EXTENSION);
+                if let Some(alias) = self.alias(&segments[0]) {
+                    let mut target: PathBuf = alias.target.clone();
+                    target.extend(segments[1..].iter().map(|s| s.as_str()));
+                    target.set_extension(EXTENSION);
+                    return (vec![target], Some(alias));
+                }
+                let roots = self.roots.iter().map(|root| root.join(&relative));
+                (
+                    std::iter::once(base.join(&relative)).chain(roots).collect(),
+                    None,
+                )
+            }
+        }
+    }
+}
+
+// Resolves, parses and caches modules. Each module is loaded once; the stack
+// of modules currently being loaded is used to report import cycles.
+#[derive(Default)]
+pub struct ModuleLoader {
+    pipeline: Pipeline,
+    modules: HashMap<PathBuf, Rc<Module>>,
+    loading: Vec<PathBuf>,
+    imports: ImportMap,
+    // Modules `preload` parsed ahead of their imports being evaluated, or
+    // the error parsing them found.
+    parsed: HashMap<PathBuf, Result<Program, Diagnostic>>,
+    sources: SourceMap,
+}
+
+impl ModuleLoader {
+    pub fn new(pipeline: Pipeline) -> ModuleLoader {
+        ModuleLoader {
+            pipeline,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            imports: ImportMap::default(),
+            parsed: HashMap::new(),
+            sources: SourceMap::new(),
+        }
+    }
+
+    // The sources of the modules read so far.
+    pub fn sources(&self) -> SourceMap {
+        self.sources.clone()
+    }
+
+    pub fn set_sources(&mut self, sources: SourceMap) {
+        self.sources = sources;
+    }
+
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    pub fn set_import_map(&mut self, imports: ImportMap) {
+        self.imports = imports;
+    }
+
+    // Whether `name` is mapped somewhere else, which for `std` replaces the
+    // built in standard library.
+    pub fn is_aliased(&self, name: &str) -> bool {
+        self.imports.alias(name).is_some()
+    }
+
+    // Resolves an import through the import map's aliases, or relative to the
+    // directory of the importing file (the working directory when there is
+    // none, e.g. in the repl) and then the import map's roots.
+    pub fn resolve(
+        &self,
+        path: &ImportPath,
+        importer: Option<&Path>,
+        span: Span,
+    ) -> Result<PathBuf, Diagnostic> {
+        let base = match importer.and_then(Path::parent) {
+            Some(parent) => parent.to_path_buf(),
+            None => env::current_dir().unwrap_or_default(),
+        };
+
+        if let ImportPath::Module(segments) = path {
+            if segments[0] == "std" && !self.is_aliased("std") {
+                return Err(Diagnostic::error(
+                    format!("unknown standard library module `{}`", path),
+                    span,
+                ));
+            }
+        }
+
+        let (candidates, alias) = self.imports.candidates(path, &base);
+        let mut error = None;
+        for candidate in &candidates {
+            match candidate.canonicalize() {
+                Ok(resolved) => return Ok(resolved),
+                Err(err) => error = error.or(Some(err)),
+            }
+        }
+        let error = error.expect("every import has a candidate");
+
+        let message = match (alias, &candidates[..]) {
+            (Some(alias), [candidate]) => format!(
+                "could not find module {} at `{}`, resolved from {}: {}",
+                path,
+                display_path(candidate),
+                alias.declared,
+                error
+            ),
+            (None, [_]) => format!("could not find module {}: {}", path, error),
+            (_, candidates) => {
+                let tried: Vec<String> = candidates
+                    .iter()
+                    .map(|candidate| format!("`{}`", display_path(candidate)))
+                    .collect();
+                format!(
+                    "could not find module {}, looked for {}",
+                    path,
+                    tried.join(", ")
+                )
+            }
+        };
+        Err(Diagnostic::error(message, span))
+    }
+
+    pub fn cached(&self, path: &Path) -> Option<Rc<Module>> {
+        self.modules.get(path).cloned()
+    }
+
+    // Records the entry file so that importing it is reported as a cycle.
+    pub fn set_root(&mut self, path: &Path) {
+        self.loading.clear();
+        self.loading.push(path.to_path_buf());
+    }
+
+    // Marks `path` as being loaded, failing if that would close a cycle.
+    pub fn enter(&mut self, path: &Path, span: Span) -> Result<(), Diagnostic> {
+        if let Some(start) = self.loading.iter().position(|loading| loading == path) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|path| display_path(path))
+                .collect();
+            return Err(Diagnostic::error(
+                format!("import cycle detected: {}", cycle.join(" -> ")),
+                span,
+            ));
+        }
+        self.loading.push(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn exit(&mut self) {
+        self.loading.pop();
+    }
+
+    // Reads and parses every module `program` imports, and every module
+    // those import in turn, so evaluating the imports finds them parsed.
+    // Imports that don't resolve are left to fail when they are evaluated,
+    // and so are modules that don't parse.
+    pub fn preload(&mut self, program: &Program, importer: Option<&Path>) {
+        self.load_imports(program, importer);
+    }
+
+    // Parses every module `program` imports, directly or not, and runs the
+    // pipeline's passes over each without evaluating anything. Returns what
+    // the modules report, and the imports that don't resolve, in the order
+    // the modules are found.
+    pub fn check(&mut self, program: &Program, importer: Option<&Path>) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for import in self.load_imports(program, importer) {
+            let (found, in_file) = match import {
+                Import::Found {
+                    path,
+                    span,
+                    in_file,
+                } => match self.parsed.remove(&path) {
+                    Some(Ok(program)) => {
+                        let mut found = Vec::new();
+                        self.pipeline.run_passes(program, &mut found);
+                        (found, Some(display_path(&path)))
+                    }
+                    Some(Err(diagnostic)) => (vec![diagnostic], Some(display_path(&path))),
+                    None => match self.parse(&path, span) {
+                        Ok(_) => (Vec::new(), None),
+                        Err(diagnostic) => (vec![diagnostic], in_file),
+                    },
+                },
+                Import::Unresolved { error, in_file } => (vec![error], in_file),
+            };
+            diagnostics.extend(found.into_iter().map(|diagnostic| match &in_file {
+                Some(file) => diagnostic.in_file(file.as_str()),
+                None => diagnostic,
+            }));
+        }
+        diagnostics
+    }
+
+    // The files `program` imports, directly or not, leaving out the imports
+    // that don't resolve.
+    pub fn imports(&mut self, program: &Program, importer: Option<&Path>) -> Vec<PathBuf> {
+        self.load_imports(program, importer)
+            .into_iter()
+            .filter_map(|import| match import {
+                Import::Found { path, .. } => Some(path),
+                Import::Unresolved { .. } => None,
+            })
+            .collect()
+    }
+
+    // Finds the modules `program` imports, and the modules those import in
+    // turn, parsing each round of them spread across threads. What they
+    // parse to is kept for `parse`. The imports come back in the order they
+    // were found, so the errors reported come in the same order however the
+    // threads were scheduled.
+    fn load_imports(&mut self, program: &Program, importer: Option<&Path>) -> Vec<Import> {
+        let mut seen: HashSet<PathBuf> = self.loading.iter().cloned().collect();
+        let mut found = Vec::new();
+        let mut pending = self.imported(program, importer, false, &mut seen);
+        while !pending.is_empty() {
+            let paths: Vec<PathBuf> = pending
+                .iter()
+                .filter_map(|import| match import {
+                    Import::Found { path, .. } => Some(path.clone()),
+                    Import::Unresolved { .. } => None,
+                })
+                .collect();
+            let options = self.pipeline.lexer_options();
+            let cache = self.pipeline.cache();
+            let mut parsed = parse_all(&paths, options, cache, &self.sources).into_iter();
+            let mut next = Vec::new();
+            for import in &pending {
+                if let Import::Found { path, .. } = import {
+                    if let Some(parsed) = parsed.next().flatten() {
+                        if let Ok(program) = &parsed {
+                            next.extend(self.imported(program, Some(path), true, &mut seen));
+                        }
+                        self.parsed.insert(path.clone(), parsed);
+                    }
+                }
+            }
+            found.append(&mut pending);
+            pending = next;
+        }
+        found
+    }
+
+    // The imports in `program` that haven't been seen, in the order they are
+    // written, leaving out the standard library modules built into the
+    // interpreter. `in_module` says whether `importer` is a module, whose
+    // name the errors in it are reported with, or the file being compiled.
+    fn imported(
+        &self,
+        program: &Program,
+        importer: Option<&Path>,
+        in_module: bool,
+        seen: &mut HashSet<PathBuf>,
+    ) -> Vec<Import> {
+        let mut imports = Imports(Vec::new());
+        for stmt in &program.statements {
+            imports.visit_stmt(stmt);
+        }
+        let in_file = importer.filter(|_| in_module).map(display_path);
+        imports
+            .0
+            .into_iter()
+            .filter(|(path, _)| !self.is_builtin(path))
+            .filter_map(|(path, span)| match self.resolve(&path, importer, span) {
+                Ok(path) if self.modules.contains_key(&path) || !seen.insert(path.clone()) => None,
+                Ok(path) => Some(Import::Found {
+                    path,
+                    span,
+                    in_file: in_file.clone(),
+                }),
+                Err(error) => Some(Import::Unresolved {
+                    error,
+                    in_file: in_file.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    // Whether `path` names a standard library module built into the
+    // interpreter rather than a file.
+    fn is_builtin(&self, path: &ImportPath) -> bool {
+        match path {
+            ImportPath::Module(segments) if !self.is_aliased(&segments[0]) => {
+                stdlib::is_module(path)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn parse(&mut self, path: &Path, span: Span) -> Result<Program, Diagnostic> {
+        let parsed = match self.parsed.remove(path) {
+            Some(parsed) => parsed,
+            None => {
+                let source = fs::read_to_string(path).map_err(|err| {
+                    Diagnostic::error(
+                        format!("could not read `{}`: {}", display_path(path), err),
+                        span,
+                    )
+                })?;
+                self.sources.insert(display_path(path), source.as_str());
+                self.pipeline.parse_file(path, &source)
+            }
+        };
+
+        let mut diagnostics = Vec::new();
+        let program = match parsed {
+            Ok(program) => self.pipeline.run_passes(program, &mut diagnostics),
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                None
+            }
+        };
+        match program {
+            Some(program) => Ok(program),
+            None => {
+                let error = diagnostics
+                    .into_iter()
+                    .find(|diagnostic| diagnostic.severity == Severity::Error)
+                    .expect("failed pipelines report an error");
+                Err(error.in_file(display_path(path)))
+            }
+        }
+    }
+
+    pub fn insert(&mut self, module: Rc<Module>) {
+        self.modules.insert(module.path.clone(), module);
+    }
+}
+
+// An import `load_imports` found, and the module it is written in when
+// that isn't the file being compiled.
+enum Import {
+    Found {
+        path: PathBuf,
+        span: Span,
+        in_file: Option<String>,
+    },
+    Unresolved {
+        error: Diagnostic,
+        in_file: Option<String>,
+    },
+}
+
+// The imports anywhere in a program and where each is written.
+struct Imports(Vec<(ImportPath, Span)>);
+
+impl Visitor for Imports {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::Import(import) = &stmt.kind {
+            self.0.push((import.path.clone(), stmt.span));
+        }
+        walk_stmt(self, stmt)
+    }
+}
+
+// Reads and parses `paths` on as many threads as there are cores, returning
+// what each parsed to in the order of `paths`, or nothing for the files that
+// can't be read. Files the cache has are taken from it instead.
+fn parse_all(
+    paths: &[PathBuf],
+    options: LexerOptions,
+    cache: Option<&ParseCache>,
+    sources: &SourceMap,
+) -> Vec<Option<Result<Program, Diagnostic>>> {
+    let parse = |path: &PathBuf| {
+        let source = fs::read_to_string(path).ok()?;
+        let parsed = match cache {
+            Some(cache) => cache.parse(path, &source, options),
+            None => parse_with_options(&source, options),
+        };
+        sources.insert(display_path(path), source);
+        Some(parsed)
+    };
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    if paths.len() == 1 || threads == 1 {
+        return paths.iter().map(parse).collect();
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = paths
+            .chunks(paths.len().div_ceil(threads))
+            .map(|paths| scope.spawn(move || paths.iter().map(parse).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("parsing does not panic"))
+            .collect()
+    })
+}
+
+pub fn display_path(path: &Path) -> String {
+    let relative = env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf));
+    match relative {
+        // The working directory itself.
+        Some(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        relative => relative
+            .unwrap_or_else(|| path.to_path_buf())
+            .display()
+            .to_string(),
+    }
+}
@@ -0,0 +1,131 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::environment::Environment;
+use crate::interpreter::module::Module;
+use crate::interpreter::value::{register_printer, Native, Value};
+use crate::lexer::token::Span;
+
+// A module a host program provides, imported by its path like the standard
+// library's modules are, e.g. `import host::db;`. Printers for the host
+// values its functions hand out are registered with it.
+pub struct NativeModule {
+    path: Vec<String>,
+    members: Vec<(String, Value)>,
+    printers: Vec<Box<dyn FnOnce()>>,
+}
+
+impl NativeModule {
+    pub fn new(path: &str) -> NativeModule {
+        NativeModule {
+            path: path.split("::").map(str::to_string).collect(),
+            members: Vec::new(),
+            printers: Vec::new(),
+        }
+    }
+
+    // Adds a function. `name` is what errors call it, and its last `::`
+    // segment is the member's name, so `db::open` is imported as `open`.
+    pub fn function(
+        mut self,
+        name: &'static str,
+        arity: usize,
+        function: impl Fn(&[Value], Span) -> Result<Value, Diagnostic> + 'static,
+    ) -> NativeModule {
+        let member = name.rsplit("::").next().unwrap_or(name).to_string();
+        let native = Native::new(name, arity, function);
+        self.members.push((member, Value::Native(native)));
+        self
+    }
+
+    pub fn value(mut self, name: &str, value: Value) -> NativeModule {
+        self.members.push((name.to_string(), value));
+        self
+    }
+
+    // Displays host values holding a `T` with `print`, once the module is
+    // registered.
+    pub fn printer<T: Any>(mut self, print: impl Fn(&T) -> String + 'static) -> NativeModule {
+        self.printers
+            .push(Box::new(move || register_printer::<T>(print)));
+        self
+    }
+
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    // Registers the module's printers and builds the module its imports bind.
+    pub(crate) fn load(self) -> Module {
+        for register in self.printers {
+            register();
+        }
+        let mut environment = Environment::new();
+        for (name, value) in self.members {
+            environment.declare(name, value, false);
+        }
+        Module {
+            name: self.path.last().cloned().unwrap_or_default(),
+            path: PathBuf::from(self.path.join("::")),
+            environment: Rc::new(RefCell::new(environment)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::diagnostic::Diagnostic;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::native::NativeModule;
+    use crate::interpreter::value::{Host, Value};
+    use crate::lexer::token::Span;
+    use crate::parser::parser::parse;
+
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    struct Handle;
+
+    fn point(args: &[Value], span: Span) -> Result<Value, Diagnostic> {
+        match (&args[0], &args[1]) {
+            (Value::Integer(x), Value::Integer(y)) => {
+                Ok(Value::Host(Host::new("Point", Point { x: *x, y: *y })))
+            }
+            _ => Err(Diagnostic::error(
+                "`geometry::point` expects integers",
+                span,
+            )),
+        }
+    }
+
+    #[test]
+    fn imports_host_modules_and_prints_host_values() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_module(
+            NativeModule::new("host::geometry")
+                .function("geometry::point", 2, point)
+                .value(
+                    "origin",
+                    Value::Host(Host::new("Point", Point { x: 0, y: 0 })),
+                )
+                .value("handle", Value::Host(Host::new("Handle", Handle)))
+                .printer(|point: &Point| format!("Point({}, {})", point.x, point.y)),
+        );
+        let mut run = |source: &str| interpreter.run(&parse(source).unwrap());
+
+        let value = run("import host::geometry; [geometry::point(1, 2), geometry::origin]");
+        assert_eq!(value.unwrap().to_string(), "[Point(1, 2), Point(0, 0)]");
+        // Types without a printer stay opaque.
+        assert_eq!(run("geometry::handle").unwrap().to_string(), "<Handle>");
+
+        let err = run("geometry::origin + 1").unwrap_err();
+        assert_eq!(err.message, "cannot apply `+` to Point and integer");
+        let err = run("match geometry::origin { 1 => 1 }").unwrap_err();
+        assert_eq!(err.message, "no match arm matched value `Point(0, 0)`");
+    }
+}
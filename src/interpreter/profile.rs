@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+// What the top level of a program counts as a call to.
+pub const ROOT: &str = "<main>";
+
+// Where a program spent its time: how often each function was called, how
+// long its calls took, and how often each instruction ran when the program
+// was compiled to bytecode. The backends report each call as it starts and
+// ends, the top level of the program included.
+#[derive(Debug)]
+pub struct Profile {
+    // The calls running, outermost first.
+    running: Vec<Running>,
+    // How many calls to each function are running, to tell the outermost
+    // from the recursive ones.
+    active: HashMap<String, usize>,
+    functions: BTreeMap<String, Calls>,
+    // Every stack of calls that ran, as a tree: each call under the one
+    // that made it, starting from an empty stack.
+    stacks: Vec<Stack>,
+    opcodes: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug)]
+struct Running {
+    name: String,
+    // Where the call is in `stacks`.
+    stack: usize,
+    started: Instant,
+    // How long the calls it made took.
+    callees: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Stack {
+    name: String,
+    parent: usize,
+    calls: HashMap<String, usize>,
+    // The time spent with this stack running, not counting the calls the
+    // innermost of them made.
+    own: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Calls {
+    pub count: u64,
+    // From the start of each call to its end, except for calls made while
+    // the function was already running, whose time the outer call counts.
+    pub total: Duration,
+    // Like `total`, less the time spent in the calls it made.
+    pub own: Duration,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            running: Vec::new(),
+            active: HashMap::new(),
+            functions: BTreeMap::new(),
+            stacks: vec![Stack::default()],
+            opcodes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.functions.entry(name.to_string()).or_default().count += 1;
+        *self.active.entry(name.to_string()).or_default() += 1;
+        let caller = self.running.last().map_or(0, |call| call.stack);
+        let stack = match self.stacks[caller].calls.get(name) {
+            Some(&stack) => stack,
+            None => {
+                let stack = self.stacks.len();
+                self.stacks.push(Stack {
+                    name: name.to_string(),
+                    parent: caller,
+                    ..Stack::default()
+                });
+                self.stacks[caller].calls.insert(name.to_string(), stack);
+                stack
+            }
+        };
+        self.running.push(Running {
+            name: name.to_string(),
+            stack,
+            started: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    // Ends the innermost call running.
+    pub fn exit(&mut self) {
+        let call = match self.running.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = call.started.elapsed();
+        let own = elapsed.saturating_sub(call.callees);
+        let active = self.active.get_mut(&call.name).expect("entered");
+        *active -= 1;
+        let recursive = *active > 0;
+        let calls = self.functions.entry(call.name).or_default();
+        calls.own += own;
+        if !recursive {
+            calls.total += elapsed;
+        }
+        self.stacks[call.stack].own += own;
+        if let Some(caller) = self.running.last_mut() {
+            caller.callees += elapsed;
+        }
+    }
+
+    pub fn count(&mut self, opcode: &'static str) {
+        *self.opcodes.entry(opcode).or_default() += 1;
+    }
+
+    // Ends every call still running, the top level included, as those an
+    // error unwound through never return.
+    pub fn finish(&mut self) {
+        while !self.running.is_empty() {
+            self.exit();
+        }
+    }
+
+    // The names of the calls in the stack at `index`, joined with `;`.
+    fn stack(&self, mut index: usize) -> String {
+        let mut names = Vec::new();
+        while index != 0 {
+            names.push(self.stacks[index].name.as_str());
+            index = self.stacks[index].parent;
+        }
+        names.reverse();
+        names.join(";")
+    }
+
+    // Each function, the one that took longest first.
+    pub fn functions(&self) -> Vec<(&str, Calls)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, calls)| (name.as_str(), *calls))
+            .collect();
+        functions.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+        functions
+    }
+
+    // Each instruction that ran, the most frequent first.
+    pub fn opcodes(&self) -> Vec<(&'static str, u64)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(op, n)| (*op, *n)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        opcodes
+    }
+
+    // A table of the functions, and of the instructions if any were counted.
+    pub fn table(&self) -> String {
+        let functions = self.functions();
+        let width = functions
+            .iter()
+            .map(|(name, _)| name.chars().count())
+            .chain(self.opcodes.keys().map(|op| op.len()))
+            .fold("function".len(), usize::max);
+        let mut table = format!(
+            "{:<width$} {:>10} {:>10} {:>10}\n",
+            "function",
+            "calls",
+            "total",
+            "self",
+            width = width
+        );
+        for (name, calls) in functions {
+            let _ = writeln!(
+                table,
+                "{:<width$} {:>10} {:>10} {:>10}",
+                name,
+                calls.count,
+                format_duration(calls.total),
+                format_duration(calls.own),
+                width = width
+            );
+        }
+        if !self.opcodes.is_empty() {
+            let _ = writeln!(
+                table,
+                "\n{:<width$} {:>10}",
+                "opcode",
+                "count",
+                width = width
+            );
+            for (op, count) in self.opcodes() {
+                let _ = writeln!(table, "{:<width$} {:>10}", op, count, width = width);
+            }
+        }
+        table
+    }
+
+    // The stacks of calls in the folded format flame graph tools read: each
+    // on a line of its own, outermost first, with the microseconds spent in
+    // it.
+    pub fn folded(&self) -> String {
+        let stacks: BTreeMap<String, u128> = (1..self.stacks.len())
+            .map(|index| (index, self.stacks[index].own.as_micros()))
+            .filter(|&(_, micros)| micros > 0)
+            .map(|(index, micros)| (self.stack(index), micros))
+            .collect();
+        let mut folded = String::new();
+        for (stack, micros) in stacks {
+            let _ = writeln!(folded, "{} {}", stack, micros);
+        }
+        folded
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros < 1_000 {
+        format!("{}µs", micros)
+    } else if micros < 1_000_000 {
+        format!("{:.2}ms", micros as f64 / 1_000.0)
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::interpreter::profile::{format_duration, Profile, ROOT};
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_micros(42)), "42µs");
+        assert_eq!(format_duration(Duration::from_micros(1_500)), "1.50ms");
+        assert_eq!(format_duration(Duration::from_millis(2_250)), "2.25s");
+    }
+
+    #[test]
+    fn attributes_time_to_calls_and_their_stacks() {
+        let mut profile = Profile::new();
+        profile.enter(ROOT);
+        profile.enter("fib");
+        profile.enter("fib");
+        thread::sleep(Duration::from_millis(2));
+        profile.exit();
+        profile.exit();
+        profile.enter("print");
+        profile.count("Call");
+        profile.count("Call");
+        profile.count("Return");
+        profile.finish();
+
+        let functions = profile.functions();
+        let names: Vec<_> = functions
+            .iter()
+            .map(|(name, calls)| (*name, calls.count))
+            .collect();
+        assert_eq!(names, [(ROOT, 1), ("fib", 2), ("print", 1)]);
+        let fib = functions[1].1;
+        // The inner call's time is already in the outer one's.
+        assert!(fib.own >= Duration::from_millis(2));
+        assert!(fib.total >= fib.own && fib.total < fib.own * 2);
+        assert_eq!(profile.opcodes(), [("Call", 2), ("Return", 1)]);
+
+        let folded = profile.folded();
+        let stacks: Vec<_> = folded
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .collect();
+        assert!(stacks.iter().any(|(stack, _)| *stack == "<main>;fib;fib"));
+        assert!(stacks
+            .iter()
+            .all(|(_, micros)| micros.parse::<u64>().is_ok()));
+        let table = profile.table();
+        assert!(table.starts_with("function"));
+        assert!(table.contains("\nopcode"));
+    }
+
+    #[test]
+    fn keeps_one_entry_per_stack_of_deep_recursion() {
+        let mut profile = Profile::new();
+        profile.enter(ROOT);
+        for _ in 0..2 {
+            for _ in 0..10_000 {
+                profile.enter("down");
+            }
+            for _ in 0..10_000 {
+                profile.exit();
+            }
+        }
+        profile.finish();
+        // The second descent goes down the stacks the first one made.
+        assert_eq!(profile.stacks.len(), 10_002);
+        let down = profile.functions()[1];
+        assert_eq!(down.0, "down");
+        assert_eq!(down.1.count, 20_000);
+        assert!(down.1.total <= profile.functions()[0].1.total);
+    }
+}
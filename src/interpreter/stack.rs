@@ -0,0 +1,89 @@
+// The interpreter walks the syntax tree recursively, so a program that
+// recurses deeply uses up the native stack, and overflowing it aborts the
+// whole process. Instead, each expression checks how much of its thread's
+// stack is left and fails with a diagnostic when it runs low.
+//
+// Nothing portable tells how big a thread's stack is or where it starts, so
+// threads started by `grow` record both, and on any other thread the stack
+// is taken to start where it is first checked and to be as big as Rust makes
+// the threads it spawns.
+
+use std::cell::Cell;
+use std::panic;
+use std::thread;
+
+// How much stack a thread is assumed to have unless `grow` started it.
+pub const DEFAULT_SIZE: usize = 2 << 20;
+
+// Left free below the limit, for whatever runs between two checks: native
+// functions and the formatting of the error itself.
+const RESERVE: usize = 256 << 10;
+
+thread_local! {
+    static BASE: Cell<Option<usize>> = const { Cell::new(None) };
+    static SIZE: Cell<usize> = const { Cell::new(DEFAULT_SIZE) };
+}
+
+// Runs `f` on a new thread with `size` bytes of stack, for hosts that run
+// deeply recursive programs. Only the pages a thread touches are ever
+// allocated, so a big stack costs nothing until a program recurses into it.
+pub fn grow<T: Send>(size: usize, f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        let thread = thread::Builder::new().stack_size(size);
+        let handle = thread
+            .spawn_scoped(scope, || {
+                BASE.with(|base| base.set(Some(here())));
+                SIZE.with(|cell| cell.set(size));
+                f()
+            })
+            .expect("could not start a thread");
+        handle
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+// Whether so little of this thread's stack is left that recursing further
+// could overflow it.
+pub fn exhausted() -> bool {
+    let here = here();
+    let base = BASE.with(|base| match base.get() {
+        Some(base) => base,
+        None => {
+            base.set(Some(here));
+            here
+        }
+    });
+    // Stacks grow down on every platform clay runs on, but measuring the
+    // distance either way costs nothing.
+    let used = base.abs_diff(here);
+    used + RESERVE > SIZE.with(Cell::get)
+}
+
+// An address just past the caller's stack frame.
+#[inline(never)]
+fn here() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::stack::{exhausted, grow};
+
+    fn depth(levels: usize) -> usize {
+        let padding = std::hint::black_box([0u8; 1024]);
+        if exhausted() {
+            return levels;
+        }
+        depth(levels + 1) + usize::from(padding[0])
+    }
+
+    #[test]
+    fn stops_recursion_before_the_stack_overflows() {
+        let default = depth(0);
+        assert!(default > 100);
+        let grown = grow(16 << 20, || depth(0));
+        assert!(grown > default * 4, "{} and {} frames", default, grown);
+    }
+}
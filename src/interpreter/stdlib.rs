@@ -0,0 +1,1276 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR_STR};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::calendar;
+use crate::interpreter::environment::Environment;
+use crate::interpreter::formats;
+use crate::interpreter::gc;
+use crate::interpreter::interpreter::values_equal;
+use crate::interpreter::io::{Io, StdIo};
+use crate::interpreter::json;
+use crate::interpreter::module::Module;
+use crate::interpreter::system::{HostSystem, System};
+use crate::interpreter::value::{EnumType, Native, Value, Variant};
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::ImportPath;
+
+// Builds the standard library module `path` names, if there is one,
+// describing the machine through `system`.
+pub fn module(path: &ImportPath, system: &Rc<dyn System>) -> Option<Module> {
+    let members = match segments(path)?[..] {
+        ["std", "os"] => os(system.clone()),
+        ["std", "path"] => paths(),
+        ["std", "regex"] => regexes(),
+        ["std", "time"] => times(system.clone()),
+        _ => return None,
+    };
+
+    let mut environment = Environment::new();
+    for (name, value) in members {
+        environment.declare(name, value, false);
+    }
+    Some(Module {
+        name: path.binding().to_string(),
+        path: PathBuf::from(path.to_string()),
+        environment: Rc::new(RefCell::new(environment)),
+    })
+}
+
+// Whether `path` names a standard library module.
+pub fn is_module(path: &ImportPath) -> bool {
+    matches!(
+        segments(path).as_deref(),
+        Some(["std", "os" | "path" | "regex" | "time"])
+    )
+}
+
+fn segments(path: &ImportPath) -> Option<Vec<&str>> {
+    match path {
+        ImportPath::Module(segments) => {
+            Some(segments.iter().map(|segment| segment.as_str()).collect())
+        }
+        ImportPath::File(_) => None,
+    }
+}
+
+// The functions every program can call without importing anything. They
+// live outside any environment, so a program or module can shadow them with
+// its own bindings and they never show up among its globals.
+#[derive(Clone)]
+pub struct Builtins {
+    values: HashMap<Symbol, Value>,
+    // The code `exit` was called with, until the host takes it.
+    exit: Rc<Cell<Option<i32>>>,
+    // What `env` and `std::os` describe.
+    system: Rc<dyn System>,
+}
+
+impl Builtins {
+    // Builtins without the standard ones, for hosts that want to choose
+    // every name a program can see.
+    pub fn empty() -> Builtins {
+        Builtins {
+            values: HashMap::new(),
+            exit: Rc::new(Cell::new(None)),
+            system: Rc::new(HostSystem),
+        }
+    }
+
+    // Makes `value` callable as `name`, replacing any builtin of that name.
+    pub fn register(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.values.insert(name.into(), value);
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        self.values.get(&name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.values.keys().copied()
+    }
+
+    // Sends the I/O functions through `io`.
+    pub fn set_io(&mut self, io: Rc<RefCell<dyn Io>>) {
+        for (name, value) in console(io) {
+            self.register(name, value);
+        }
+    }
+
+    // Makes `env` and the `std::os` module describe `system` rather than
+    // the machine clay runs on.
+    pub fn set_system(&mut self, system: Rc<dyn System>) {
+        self.register("env", env(system.clone()));
+        self.system = system;
+    }
+
+    // The standard library module `path` names, if there is one.
+    pub fn module(&self, path: &ImportPath) -> Option<Module> {
+        module(path, &self.system)
+    }
+
+    // Makes `args` return `args`, the arguments the program was run with.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        let args = native("args", 0, move |_, _| {
+            Ok(Value::list(args.iter().map(string).collect()))
+        });
+        self.register("args", args);
+    }
+
+    // The code the program called `exit` with, if it did since the last
+    // time this was asked. `exit` stops a program by failing the call, so a
+    // host that finds a code here should drop the error it got.
+    pub fn take_exit(&self) -> Option<i32> {
+        self.exit.take()
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Builtins {
+        let mut builtins = Builtins::empty();
+        let values = math()
+            .into_iter()
+            .chain(strings())
+            .chain(assertions())
+            .chain(serialization())
+            .chain(prelude())
+            .chain(process(builtins.exit.clone()))
+            .chain([("env", env(builtins.system.clone()))]);
+        for (name, value) in values {
+            builtins.register(name, value);
+        }
+        builtins.set_io(Rc::new(RefCell::new(StdIo)));
+        builtins
+    }
+}
+
+fn native(
+    name: &'static str,
+    arity: usize,
+    function: impl Fn(&[Value], Span) -> Result<Value, Diagnostic> + 'static,
+) -> Value {
+    Value::Native(Native::new(name, arity, function))
+}
+
+fn string(value: impl Into<String>) -> Value {
+    let value = value.into();
+    gc::build(value.len());
+    Value::String(value)
+}
+
+fn path_value(path: &Path) -> Value {
+    string(path.to_string_lossy())
+}
+
+// The string argument at `index` of a call to `name`.
+fn string_argument<'a>(
+    args: &'a [Value],
+    index: usize,
+    name: &str,
+    span: Span,
+) -> Result<&'a str, Diagnostic> {
+    match &args[index] {
+        Value::String(s) => Ok(s),
+        other => Err(Diagnostic::error(
+            format!("`{}` expects a string, found {}", name, other.type_name()),
+            span,
+        )),
+    }
+}
+
+// The number argument at `index` of a call to `name`, as a float.
+fn number_argument(
+    args: &[Value],
+    index: usize,
+    name: &str,
+    span: Span,
+) -> Result<f64, Diagnostic> {
+    match args[index] {
+        Value::Integer(n) => Ok(n as f64),
+        Value::Float(n) => Ok(n),
+        ref other => Err(Diagnostic::error(
+            format!("`{}` expects a number, found {}", name, other.type_name()),
+            span,
+        )),
+    }
+}
+
+// The integer argument at `index` of a call to `name`.
+fn integer_argument(
+    args: &[Value],
+    index: usize,
+    name: &str,
+    span: Span,
+) -> Result<i64, Diagnostic> {
+    match args[index] {
+        Value::Integer(n) => Ok(n),
+        ref other => Err(Diagnostic::error(
+            format!("`{}` expects an integer, found {}", name, other.type_name()),
+            span,
+        )),
+    }
+}
+
+// Converts a float that has been rounded to a whole number.
+fn to_integer(n: f64, name: &str, span: Span) -> Result<Value, Diagnostic> {
+    // `i64::MAX as f64` rounds up to 2^63, which is out of range.
+    if n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(Value::Integer(n as i64))
+    } else {
+        Err(Diagnostic::error(
+            format!("`{}` of {} does not fit in an integer", name, n),
+            span,
+        ))
+    }
+}
+
+// Compares the two number arguments of a call to `name`. Integers are
+// compared exactly, rather than as floats.
+fn compare(args: &[Value], name: &str, span: Span) -> Result<Option<Ordering>, Diagnostic> {
+    let a = number_argument(args, 0, name, span)?;
+    let b = number_argument(args, 1, name, span)?;
+    Ok(match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        _ => a.partial_cmp(&b),
+    })
+}
+
+// Integers stay integers where the result is always whole: `abs`, `min`,
+// `max` and `pow` with a non-negative exponent. `floor`, `ceil` and `round`
+// turn floats into integers, and `sqrt` always returns a float.
+fn math() -> Vec<(&'static str, Value)> {
+    vec![
+        (
+            "abs",
+            native("abs", 1, |args, span| match args[0] {
+                Value::Integer(n) => n
+                    .checked_abs()
+                    .map(Value::Integer)
+                    .ok_or_else(|| Diagnostic::error("integer overflow", span)),
+                _ => Ok(Value::Float(number_argument(args, 0, "abs", span)?.abs())),
+            }),
+        ),
+        // Both return the first argument when the two are equal.
+        (
+            "min",
+            native("min", 2, |args, span| {
+                let greater = compare(args, "min", span)? == Some(Ordering::Greater);
+                Ok(args[greater as usize].clone())
+            }),
+        ),
+        (
+            "max",
+            native("max", 2, |args, span| {
+                let less = compare(args, "max", span)? == Some(Ordering::Less);
+                Ok(args[less as usize].clone())
+            }),
+        ),
+        (
+            "floor",
+            native("floor", 1, |args, span| match args[0] {
+                Value::Integer(n) => Ok(Value::Integer(n)),
+                _ => to_integer(
+                    number_argument(args, 0, "floor", span)?.floor(),
+                    "floor",
+                    span,
+                ),
+            }),
+        ),
+        (
+            "ceil",
+            native("ceil", 1, |args, span| match args[0] {
+                Value::Integer(n) => Ok(Value::Integer(n)),
+                _ => to_integer(number_argument(args, 0, "ceil", span)?.ceil(), "ceil", span),
+            }),
+        ),
+        // Halfway cases round away from zero.
+        (
+            "round",
+            native("round", 1, |args, span| match args[0] {
+                Value::Integer(n) => Ok(Value::Integer(n)),
+                _ => to_integer(
+                    number_argument(args, 0, "round", span)?.round(),
+                    "round",
+                    span,
+                ),
+            }),
+        ),
+        (
+            "sqrt",
+            native("sqrt", 1, |args, span| {
+                Ok(Value::Float(number_argument(args, 0, "sqrt", span)?.sqrt()))
+            }),
+        ),
+        (
+            "pow",
+            native("pow", 2, |args, span| {
+                let base = number_argument(args, 0, "pow", span)?;
+                let exponent = number_argument(args, 1, "pow", span)?;
+                match (&args[0], &args[1]) {
+                    (Value::Integer(base), Value::Integer(exponent)) if *exponent >= 0 => {
+                        u32::try_from(*exponent)
+                            .ok()
+                            .and_then(|exponent| base.checked_pow(exponent))
+                            .map(Value::Integer)
+                            .ok_or_else(|| Diagnostic::error("integer overflow", span))
+                    }
+                    _ => Ok(Value::Float(base.powf(exponent))),
+                }
+            }),
+        ),
+    ]
+}
+
+// Strings are sequences of Unicode scalar values: `len` counts them and
+// `chars` splits a string into them, the same as indexing and slicing do.
+fn strings() -> Vec<(&'static str, Value)> {
+    vec![
+        // Also counts the items of a collection.
+        (
+            "len",
+            native("len", 1, |args, span| {
+                let len = match &args[0] {
+                    Value::String(s) => s.chars().count(),
+                    Value::List(list) => list.borrow().len(),
+                    Value::Map(map) => map.borrow().len(),
+                    Value::Tuple(values) => values.len(),
+                    Value::Range(start, end) => (end - start).max(0) as usize,
+                    other => {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "`len` expects a string or a collection, found {}",
+                                other.type_name()
+                            ),
+                            span,
+                        ))
+                    }
+                };
+                Ok(Value::Integer(len as i64))
+            }),
+        ),
+        (
+            "upper",
+            native("upper", 1, |args, span| {
+                Ok(string(
+                    string_argument(args, 0, "upper", span)?.to_uppercase(),
+                ))
+            }),
+        ),
+        (
+            "lower",
+            native("lower", 1, |args, span| {
+                Ok(string(
+                    string_argument(args, 0, "lower", span)?.to_lowercase(),
+                ))
+            }),
+        ),
+        (
+            "trim",
+            native("trim", 1, |args, span| {
+                Ok(string(string_argument(args, 0, "trim", span)?.trim()))
+            }),
+        ),
+        (
+            "split",
+            native("split", 2, |args, span| {
+                let s = string_argument(args, 0, "split", span)?;
+                let separator = string_argument(args, 1, "split", span)?;
+                if separator.is_empty() {
+                    return Err(Diagnostic::error(
+                        "`split` expects a non-empty separator, use `chars` to split a string into characters",
+                        span,
+                    ));
+                }
+                Ok(Value::list(s.split(separator).map(string).collect()))
+            }),
+        ),
+        (
+            "chars",
+            native("chars", 1, |args, span| {
+                let s = string_argument(args, 0, "chars", span)?;
+                Ok(Value::list(s.chars().map(string).collect()))
+            }),
+        ),
+        (
+            "contains",
+            native("contains", 2, |args, span| {
+                let s = string_argument(args, 0, "contains", span)?;
+                let pattern = string_argument(args, 1, "contains", span)?;
+                Ok(Value::Bool(s.contains(pattern)))
+            }),
+        ),
+        (
+            "starts_with",
+            native("starts_with", 2, |args, span| {
+                let s = string_argument(args, 0, "starts_with", span)?;
+                let prefix = string_argument(args, 1, "starts_with", span)?;
+                Ok(Value::Bool(s.starts_with(prefix)))
+            }),
+        ),
+        // Replaces every occurrence.
+        (
+            "replace",
+            native("replace", 3, |args, span| {
+                let s = string_argument(args, 0, "replace", span)?;
+                let from = string_argument(args, 1, "replace", span)?;
+                let to = string_argument(args, 2, "replace", span)?;
+                Ok(string(s.replace(from, to)))
+            }),
+        ),
+    ]
+}
+
+// The enums every program can use without declaring them, with each
+// variant's name and how many values it holds. `?` unwraps their `Some` and
+// `Ok` values and returns their `None` and `Err` ones.
+pub const ENUMS: [(&str, [(&str, usize); 2]); 2] = [
+    ("Option", [("Some", 1), ("None", 0)]),
+    ("Result", [("Ok", 1), ("Err", 1)]),
+];
+
+// The enum of a variant the prelude names on its own, so the pattern
+// `Some(x)` matches like `Option::Some(x)`, as the expression builds it.
+pub fn prelude_enum(variant: &str) -> Option<&'static str> {
+    ENUMS
+        .iter()
+        .find(|(_, variants)| variants.iter().any(|&(name, _)| name == variant))
+        .map(|&(name, _)| name)
+}
+
+// The `ENUMS`, along with their variants under their own names, so
+// `Some(1)` is short for `Option::Some(1)`.
+fn prelude() -> Vec<(&'static str, Value)> {
+    let mut values = Vec::new();
+    for (name, variants) in ENUMS {
+        let ty = enum_type(name, variants);
+        for (variant, _) in variants {
+            let value = ty.variant(Symbol::intern(variant)).expect("declared above");
+            values.push((variant, value));
+        }
+        values.push((name, Value::EnumType(ty)));
+    }
+    values
+}
+
+fn enum_type(name: &str, variants: [(&str, usize); 2]) -> Rc<EnumType> {
+    Rc::new(EnumType {
+        name: Symbol::intern(name),
+        variants: variants
+            .iter()
+            .map(|&(variant, arity)| (Symbol::intern(variant), arity))
+            .collect(),
+    })
+}
+
+// `Ok(value)` or `Err(error)`, matched by name like `option`'s values.
+fn result(value: Result<Value, Value>) -> Value {
+    let (name, variants) = ENUMS[1];
+    let (index, field) = match value {
+        Ok(value) => (0, value),
+        Err(error) => (1, error),
+    };
+    Value::Variant(Rc::new(Variant {
+        ty: enum_type(name, variants),
+        index,
+        fields: vec![field],
+    }))
+}
+
+// `Some(value)`, or `None` without a value. Patterns match variants by
+// name, so these match `Some` and `None` like the prelude's do.
+pub(crate) fn option(value: Option<Value>) -> Value {
+    let (name, variants) = ENUMS[0];
+    let (index, fields) = match value {
+        Some(value) => (0, vec![value]),
+        None => (1, Vec::new()),
+    };
+    Value::Variant(Rc::new(Variant {
+        ty: enum_type(name, variants),
+        index,
+        fields,
+    }))
+}
+
+// Fail with a runtime error when they don't hold, which is how tests fail.
+// `assert_eq` compares the way `==` does, and shows both values and their
+// types when they differ.
+fn assertions() -> Vec<(&'static str, Value)> {
+    vec![
+        (
+            "assert",
+            native("assert", 1, |args, span| match args[0] {
+                Value::Bool(true) => Ok(Value::Unit),
+                Value::Bool(false) => Err(Diagnostic::error("assertion failed", span)),
+                ref other => Err(Diagnostic::error(
+                    format!("`assert` expects a bool, found {}", other.type_name()),
+                    span,
+                )),
+            }),
+        ),
+        (
+            "assert_eq",
+            native("assert_eq", 2, |args, span| {
+                if values_equal(&args[0], &args[1]) {
+                    return Ok(Value::Unit);
+                }
+                let note = |side, value: &Value| {
+                    format!("{}: {} ({})", side, quoted(value), value.type_name())
+                };
+                Err(Diagnostic::error("assertion `left == right` failed", span)
+                    .with_note(note("left", &args[0]))
+                    .with_note(note("right", &args[1])))
+            }),
+        ),
+    ]
+}
+
+// `to_json` writes a value as JSON on one line and `from_json` reads it
+// back, the way `json` converts them. The TOML and YAML functions work the
+// same way, when clay is built with their features.
+fn serialization() -> Vec<(&'static str, Value)> {
+    let to = |name: &'static str, format: &'static str, write: Write| {
+        native(name, 1, move |args, span| match write(&args[0]) {
+            Ok(text) => Ok(string(text)),
+            Err(err) => Err(Diagnostic::error(
+                format!("could not convert to {}: {}", format, err),
+                span,
+            )),
+        })
+    };
+    // Text comes from outside the program, so failing to parse it is a
+    // result to handle rather than an error.
+    let from = |name: &'static str, format: &'static str, read: Read| {
+        native(name, 1, move |args, span| {
+            let text = string_argument(args, 0, name, span)?;
+            Ok(result(read(text).map_err(|err| {
+                string(format!("could not parse {}: {}", format, err))
+            })))
+        })
+    };
+    vec![
+        (
+            "to_json",
+            to("to_json", "JSON", |value| {
+                json::to_json(value).map_err(|err| err.to_string())
+            }),
+        ),
+        (
+            "from_json",
+            from("from_json", "JSON", |text| {
+                json::from_json(text).map_err(|err| err.to_string())
+            }),
+        ),
+        ("to_toml", to("to_toml", "TOML", formats::to_toml)),
+        ("from_toml", from("from_toml", "TOML", formats::from_toml)),
+        ("to_yaml", to("to_yaml", "YAML", formats::to_yaml)),
+        ("from_yaml", from("from_yaml", "YAML", formats::from_yaml)),
+    ]
+}
+
+type Write = fn(&Value) -> Result<String, String>;
+type Read = fn(&str) -> Result<Value, String>;
+
+// Strings in quotes, so `"1"` can be told apart from `1`.
+fn quoted(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+// `print` and `println` write any value as it displays, strings without
+// quotes. `read_line` keeps the line ending and returns "" once the input
+// runs out.
+fn console(io: Rc<RefCell<dyn Io>>) -> Vec<(&'static str, Value)> {
+    let write = |name: &'static str, end: &'static str, io: Rc<RefCell<dyn Io>>| {
+        native(name, 1, move |args, span| {
+            let text = format!("{}{}", args[0], end);
+            io.borrow_mut().write(&text).map_err(|err| {
+                Diagnostic::error(format!("could not write output: {}", err), span)
+            })?;
+            Ok(Value::Unit)
+        })
+    };
+    vec![
+        ("print", write("print", "", io.clone())),
+        ("println", write("println", "\n", io.clone())),
+        ("read_line", {
+            let io = io.clone();
+            native("read_line", 0, move |_, span| {
+                match io.borrow_mut().read_line() {
+                    Ok(line) => Ok(string(line)),
+                    Err(err) => Err(Diagnostic::error(
+                        format!("could not read input: {}", err),
+                        span,
+                    )),
+                }
+            })
+        }),
+        ("read_file", {
+            let io = io.clone();
+            native("read_file", 1, move |args, span| {
+                let path = string_argument(args, 0, "read_file", span)?;
+                match io.borrow_mut().read_file(Path::new(path)) {
+                    Ok(contents) => Ok(string(contents)),
+                    Err(err) => Err(Diagnostic::error(
+                        format!("could not read `{}`: {}", path, err),
+                        span,
+                    )),
+                }
+            })
+        }),
+        (
+            "write_file",
+            native("write_file", 2, move |args, span| {
+                let path = string_argument(args, 0, "write_file", span)?;
+                let contents = string_argument(args, 1, "write_file", span)?;
+                match io.borrow_mut().write_file(Path::new(path), contents) {
+                    Ok(()) => Ok(Value::Unit),
+                    Err(err) => Err(Diagnostic::error(
+                        format!("could not write `{}`: {}", path, err),
+                        span,
+                    )),
+                }
+            }),
+        ),
+    ]
+}
+
+// What scripts need from the process they run in. `args` returns no
+// arguments until the host sets them, and `exit` stops the program,
+// leaving its code in `exit`. `env` comes from the `System`.
+fn process(exit: Rc<Cell<Option<i32>>>) -> Vec<(&'static str, Value)> {
+    vec![
+        (
+            "args",
+            native("args", 0, |_, _| Ok(Value::list(Vec::new()))),
+        ),
+        (
+            "exit",
+            native("exit", 1, move |args, span| {
+                let code = exit_code(&args[0], span)?;
+                exit.set(Some(code));
+                Err(Diagnostic::error(
+                    format!("the program exited with code {}", code),
+                    span,
+                ))
+            }),
+        ),
+    ]
+}
+
+fn env(system: Rc<dyn System>) -> Value {
+    native("env", 1, move |args, span| {
+        let name = string_argument(args, 0, "env", span)?;
+        Ok(option(system.var(name).map(string)))
+    })
+}
+
+// `value` as the code a process exits with, which every platform keeps
+// whole from 0 to 255.
+pub fn exit_code(value: &Value, span: Span) -> Result<i32, Diagnostic> {
+    match value {
+        Value::Integer(code @ 0..=255) => Ok(*code as i32),
+        Value::Integer(code) => Err(Diagnostic::error(
+            format!("exit codes go from 0 to 255, found {}", code),
+            span,
+        )),
+        other => Err(Diagnostic::error(
+            format!(
+                "an exit code must be an integer, found {}",
+                other.type_name()
+            ),
+            span,
+        )),
+    }
+}
+
+fn os(system: Rc<dyn System>) -> Vec<(&'static str, Value)> {
+    vec![
+        ("platform", string(system.platform())),
+        ("family", string(system.family())),
+        ("temp_dir", {
+            let system = system.clone();
+            native("os::temp_dir", 0, move |_, span| match system.temp_dir() {
+                Some(dir) => Ok(path_value(&dir)),
+                None => Err(Diagnostic::error(
+                    "could not find the temporary directory",
+                    span,
+                )),
+            })
+        }),
+        (
+            "home_dir",
+            native("os::home_dir", 0, move |_, span| match system.home_dir() {
+                Some(home) => Ok(path_value(&home)),
+                None => Err(Diagnostic::error("could not find the home directory", span)),
+            }),
+        ),
+    ]
+}
+
+// Paths are strings, split and joined with the separator of the platform
+// clay runs on.
+fn paths() -> Vec<(&'static str, Value)> {
+    vec![
+        ("separator", string(MAIN_SEPARATOR_STR)),
+        (
+            "join",
+            native("path::join", 2, |args, span| {
+                let base = string_argument(args, 0, "path::join", span)?;
+                let path = string_argument(args, 1, "path::join", span)?;
+                Ok(path_value(&Path::new(base).join(path)))
+            }),
+        ),
+        (
+            "normalize",
+            native("path::normalize", 1, |args, span| {
+                let path = string_argument(args, 0, "path::normalize", span)?;
+                Ok(path_value(&normalize(Path::new(path))))
+            }),
+        ),
+        // `parent`, `file_name` and `extension` return "" for paths without
+        // one.
+        (
+            "parent",
+            native("path::parent", 1, |args, span| {
+                let path = string_argument(args, 0, "path::parent", span)?;
+                Ok(path_value(
+                    Path::new(path).parent().unwrap_or_else(|| Path::new("")),
+                ))
+            }),
+        ),
+        (
+            "file_name",
+            native("path::file_name", 1, |args, span| {
+                let path = string_argument(args, 0, "path::file_name", span)?;
+                let name = Path::new(path).file_name().unwrap_or_default();
+                Ok(string(name.to_string_lossy()))
+            }),
+        ),
+        (
+            "extension",
+            native("path::extension", 1, |args, span| {
+                let path = string_argument(args, 0, "path::extension", span)?;
+                let extension = Path::new(path).extension().unwrap_or_default();
+                Ok(string(extension.to_string_lossy()))
+            }),
+        ),
+        (
+            "is_absolute",
+            native("path::is_absolute", 1, |args, span| {
+                let path = string_argument(args, 0, "path::is_absolute", span)?;
+                Ok(Value::Bool(Path::new(path).is_absolute()))
+            }),
+        ),
+    ]
+}
+
+// Times are integers, the milliseconds since 1970-01-01T00:00:00Z for
+// dates and the milliseconds they last for durations, so they can be added
+// and subtracted, and `seconds`, `minutes`, `hours` and `days` make
+// durations from those units. `clock` is for timing code and returns
+// fractions of a millisecond too. `format` and `parse` lay dates out the
+// way `calendar` does, in UTC, and `parse` returns `Ok` with the date or
+// `Err` with a message. The time comes from the `System`.
+fn times(system: Rc<dyn System>) -> Vec<(&'static str, Value)> {
+    let duration = |name: &'static str, millis: i64| {
+        native(name, 1, move |args, span| match args[0] {
+            Value::Integer(n) => n
+                .checked_mul(millis)
+                .map(Value::Integer)
+                .ok_or_else(|| Diagnostic::error("integer overflow", span)),
+            _ => {
+                let n = number_argument(args, 0, name, span)?;
+                to_integer((n * millis as f64).round(), name, span)
+            }
+        })
+    };
+    vec![
+        ("now", {
+            let system = system.clone();
+            native("time::now", 0, move |_, _| {
+                let millis = match system.now().duration_since(SystemTime::UNIX_EPOCH) {
+                    Ok(since) => since.as_millis() as i64,
+                    Err(before) => -(before.duration().as_millis() as i64),
+                };
+                Ok(Value::Integer(millis))
+            })
+        }),
+        (
+            "clock",
+            native("time::clock", 0, move |_, _| {
+                Ok(Value::Float(system.clock().as_secs_f64() * 1_000.0))
+            }),
+        ),
+        (
+            "format",
+            native("time::format", 2, |args, span| {
+                let timestamp = integer_argument(args, 0, "time::format", span)?;
+                let format = string_argument(args, 1, "time::format", span)?;
+                match calendar::format(timestamp, format) {
+                    Ok(text) => Ok(string(text)),
+                    Err(message) => Err(Diagnostic::error(message, span)),
+                }
+            }),
+        ),
+        (
+            "parse",
+            native("time::parse", 2, |args, span| {
+                let text = string_argument(args, 0, "time::parse", span)?;
+                let format = string_argument(args, 1, "time::parse", span)?;
+                Ok(result(
+                    calendar::parse(text, format)
+                        .map(Value::Integer)
+                        .map_err(string),
+                ))
+            }),
+        ),
+        ("seconds", duration("time::seconds", 1_000)),
+        ("minutes", duration("time::minutes", 60_000)),
+        ("hours", duration("time::hours", 3_600_000)),
+        ("days", duration("time::days", 86_400_000)),
+    ]
+}
+
+// Regular expressions, with the syntax of the `regex` crate. `compile`
+// returns `Ok` with the regex, or `Err` with a message for a pattern that
+// isn't valid, and the others take the compiled regex first. `find` returns
+// the first match, `captures` each group of the first match, `None` for
+// those that didn't take part, and `replace` replaces every match, with
+// `$1` or `$name` in the replacement standing for a group.
+#[cfg(feature = "regex")]
+fn regexes() -> Vec<(&'static str, Value)> {
+    use crate::interpreter::value::{register_printer, Host};
+
+    register_printer::<regex::Regex>(|regex| format!("regex(\"{}\")", regex.as_str()));
+    vec![
+        (
+            "compile",
+            native("regex::compile", 1, |args, span| {
+                let pattern = string_argument(args, 0, "regex::compile", span)?;
+                Ok(match regex::Regex::new(pattern) {
+                    Ok(regex) => result(Ok(Value::Host(Host::new("regex", regex)))),
+                    Err(err) => result(Err(string(err.to_string()))),
+                })
+            }),
+        ),
+        (
+            "is_match",
+            native("regex::is_match", 2, |args, span| {
+                let regex = regex_argument(args, "regex::is_match", span)?;
+                let text = string_argument(args, 1, "regex::is_match", span)?;
+                Ok(Value::Bool(regex.is_match(text)))
+            }),
+        ),
+        (
+            "find",
+            native("regex::find", 2, |args, span| {
+                let regex = regex_argument(args, "regex::find", span)?;
+                let text = string_argument(args, 1, "regex::find", span)?;
+                Ok(option(regex.find(text).map(|found| string(found.as_str()))))
+            }),
+        ),
+        (
+            "captures",
+            native("regex::captures", 2, |args, span| {
+                let regex = regex_argument(args, "regex::captures", span)?;
+                let text = string_argument(args, 1, "regex::captures", span)?;
+                Ok(option(regex.captures(text).map(|captures| {
+                    let groups = captures
+                        .iter()
+                        .map(|group| option(group.map(|group| string(group.as_str()))));
+                    Value::list(groups.collect())
+                })))
+            }),
+        ),
+        (
+            "replace",
+            native("regex::replace", 3, |args, span| {
+                let regex = regex_argument(args, "regex::replace", span)?;
+                let text = string_argument(args, 1, "regex::replace", span)?;
+                let replacement = string_argument(args, 2, "regex::replace", span)?;
+                Ok(string(regex.replace_all(text, replacement)))
+            }),
+        ),
+    ]
+}
+
+// Without the `regex` feature, every function fails.
+#[cfg(not(feature = "regex"))]
+fn regexes() -> Vec<(&'static str, Value)> {
+    let missing = |name: &'static str, arity| {
+        native(name, arity, |_, span| {
+            Err(Diagnostic::error(
+                "clay was built without the `regex` feature",
+                span,
+            ))
+        })
+    };
+    vec![
+        ("compile", missing("regex::compile", 1)),
+        ("is_match", missing("regex::is_match", 2)),
+        ("find", missing("regex::find", 2)),
+        ("captures", missing("regex::captures", 2)),
+        ("replace", missing("regex::replace", 3)),
+    ]
+}
+
+// The compiled regex a call to `name` takes first.
+#[cfg(feature = "regex")]
+fn regex_argument<'a>(
+    args: &'a [Value],
+    name: &str,
+    span: Span,
+) -> Result<&'a regex::Regex, Diagnostic> {
+    let regex = match &args[0] {
+        Value::Host(host) => host.downcast_ref::<regex::Regex>(),
+        _ => None,
+    };
+    regex.ok_or_else(|| {
+        Diagnostic::error(
+            format!("`{}` expects a regex, found {}", name, args[0].type_name()),
+            span,
+        )
+    })
+}
+
+// Removes `.` components and resolves `..` against the components before it
+// without touching the file system, so symlinks are not followed.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // There is nothing above the root.
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::path::{Path, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+    use std::rc::Rc;
+    use std::time::{Duration, SystemTime};
+
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::io::BufferIo;
+    use crate::interpreter::stdlib::normalize;
+    use crate::interpreter::system::FixedSystem;
+    use crate::interpreter::value::Value;
+    use crate::parser::parser::parse;
+    use crate::vm::vm::Vm;
+
+    fn run(source: &str) -> String {
+        let program = parse(source).unwrap();
+        Interpreter::new().run(&program).unwrap().to_string()
+    }
+
+    #[test]
+    fn normalizes_paths_lexically() {
+        let normalized = |path: &str| normalize(Path::new(path)).to_string_lossy().into_owned();
+        let native = |path: &str| path.replace('/', MAIN_SEPARATOR_STR);
+        assert_eq!(normalized("a/./b/../c"), native("a/c"));
+        assert_eq!(normalized("../a/../../b"), native("../../b"));
+        assert_eq!(normalized("/../a"), native("/a"));
+        assert_eq!(normalized("a/.."), ".");
+    }
+
+    #[test]
+    fn imports_the_path_and_os_modules() {
+        let source = "
+            import std::path;
+            let file = path::join(path::join(\"src\", \"lib\"), \"main.clay\");
+            (path::parent(file), path::file_name(file), path::extension(file), path::extension(\"src\"))
+        ";
+        let expected = format!("(src{}lib, main.clay, clay, )", MAIN_SEPARATOR);
+        assert_eq!(run(source), expected);
+
+        let source = "import std::os; (os::platform, os::temp_dir() != \"\")";
+        assert_eq!(run(source), format!("({}, true)", std::env::consts::OS));
+
+        let program = parse("import std::path;\npath::join(\"a\", 1)").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "`path::join` expects a string, found integer");
+        let program = parse("import std::path; path::parent()").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "`path::parent` expects 1 argument, found 0");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_regular_expressions() {
+        let source = r#"
+            import std::regex;
+            let date = match regex::compile("(?P<year>\d{4})-(\d{2})(x)?") {
+                Result::Ok(date) => date,
+                Result::Err(message) => panic(message),
+            };
+            (
+                date,
+                regex::is_match(date, "on 2024-05-01"),
+                regex::find(date, "from 2024-05 to 2025-06"),
+                regex::find(date, "never"),
+                regex::captures(date, "2024-05-01"),
+                regex::replace(date, "2024-05, 2025-06", "$2/$year"),
+            )
+        "#;
+        let expected = concat!(
+            r#"(regex("(?P<year>\d{4})-(\d{2})(x)?"), true, Option::Some(2024-05), Option::None, "#,
+            "Option::Some([Option::Some(2024-05), Option::Some(2024), Option::Some(05), Option::None]), ",
+            "05/2024, 06/2025)"
+        );
+        assert_eq!(run(source), expected);
+
+        let source = r#"import std::regex; regex::compile("a(")"#;
+        assert!(run(source).starts_with("Result::Err(regex parse error:"));
+        let program = parse("import std::regex; regex::find(\"a\", \"a\")").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "`regex::find` expects a regex, found string");
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn fails_to_match_without_the_regex_feature() {
+        let program = parse("import std::regex; regex::compile(\"a\")").unwrap();
+        let err = Interpreter::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "clay was built without the `regex` feature");
+    }
+
+    #[test]
+    fn tells_and_formats_the_time() {
+        let source = r#"
+            import std::time;
+            let start = time::now();
+            let later = start + time::days(1) + time::hours(1.5) + time::seconds(2);
+            let parsed = match time::parse("2024-05-01T10:00", "%Y-%m-%dT%H:%M") {
+                Result::Ok(date) => time::format(date + time::minutes(90), "%F %T"),
+                Result::Err(message) => message,
+            };
+            (time::format(later, "%a %F %T"), parsed, time::parse("May 32", "%b %d"), time::clock() >= 0.0)
+        "#;
+        let mut system = FixedSystem::new();
+        system.set_time(SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_521_600_000));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_system(Rc::new(system));
+        let value = interpreter.run(&parse(source).unwrap()).unwrap();
+        assert_eq!(
+            value.to_string(),
+            "(Thu 2024-05-02 01:30:02, 2024-05-01 11:30:00, Result::Err(day 32 is out of range in `May 32`), true)"
+        );
+
+        let now = run("import std::time; time::now()").parse::<i64>().unwrap();
+        assert!(now > 1_700_000_000_000);
+        let errors = [
+            ("time::format(0, \"%Q\")", "unknown time format `%Q`"),
+            (
+                "time::format(1.5, \"%F\")",
+                "`time::format` expects an integer, found float",
+            ),
+            ("time::days(1 << 62)", "integer overflow"),
+        ];
+        for (source, message) in errors {
+            let program = parse(&format!("import std::time; {}", source)).unwrap();
+            let err = Interpreter::new().run(&program).unwrap_err();
+            assert_eq!(err.message, message);
+        }
+    }
+
+    #[test]
+    fn calls_math_builtins() {
+        let source = "(abs(-3), abs(-2.5), min(2, 1.5), max(2, 1.5), floor(-1.5), ceil(1.2), round(2.5), sqrt(16), pow(2, 10), pow(2, -1))";
+        let expected = "(3, 2.5, 1.5, 2, -2, 2, 3, 4.0, 1024, 0.5)";
+        assert_eq!(run(source), expected);
+        let program = parse(source).unwrap();
+        assert_eq!(Vm::new().run(&program).unwrap().to_string(), expected);
+
+        // Programs can shadow a builtin, and hosts can add their own.
+        assert_eq!(run("fn abs(x) { x } abs(-1)"), "-1");
+        let mut interpreter = Interpreter::new();
+        interpreter.register_builtin("answer", Value::Integer(42));
+        let program = parse("answer + 1").unwrap();
+        assert_eq!(interpreter.run(&program).unwrap().to_string(), "43");
+
+        let errors = [
+            ("sqrt(\"4\")", "`sqrt` expects a number, found string"),
+            ("pow(2, 64)", "integer overflow"),
+            (
+                "floor(sqrt(-1))",
+                "`floor` of NaN does not fit in an integer",
+            ),
+            ("min(1)", "`min` expects 2 arguments, found 1"),
+        ];
+        for (source, message) in errors {
+            let err = Interpreter::new().run(&parse(source).unwrap()).unwrap_err();
+            assert_eq!(err.message, message);
+        }
+    }
+
+    #[test]
+    fn calls_string_builtins_on_unicode() {
+        let source = "let s = \" Grüße, 東京! \";
+            (len(s), upper(s), lower(\"ÀÉÎ\"), trim(s), len(trim(s)))";
+        assert_eq!(run(source), "(12,  GRÜSSE, 東京! , àéî, Grüße, 東京!, 10)");
+
+        let source = "let s = \"naïve😀café\";
+            (chars(\"é😀\"), split(s, \"😀\"), contains(s, \"ï\"), starts_with(s, \"naï\"), replace(s, \"é\", \"e\"))";
+        assert_eq!(
+            run(source),
+            "([é, 😀], [naïve, café], true, true, naïve😀cafe)"
+        );
+        assert_eq!(
+            run("(len([1, 2]), len(#{ \"a\": 1 }), len(\"\"))"),
+            "(2, 1, 0)"
+        );
+
+        let err = Interpreter::new()
+            .run(&parse("split(\"a\", \"\")").unwrap())
+            .unwrap_err();
+        assert!(err
+            .message
+            .starts_with("`split` expects a non-empty separator"));
+        let err = Interpreter::new()
+            .run(&parse("upper(1)").unwrap())
+            .unwrap_err();
+        assert_eq!(err.message, "`upper` expects a string, found integer");
+    }
+
+    #[test]
+    fn reads_and_writes_json() {
+        let source = r#"
+            fn round_trip() {
+                let config = from_json(to_json(#{ "name": "web", "ports": 80..82 }))?;
+                config["ports"].push(8080);
+                (config["name"], to_json(config), from_json(to_json([None, Some(1)])))
+            }
+            round_trip()
+        "#;
+        assert_eq!(
+            run(source),
+            r#"(web, {"name":"web","ports":[80,81,8080]}, Result::Ok([Option::None, 1]))"#
+        );
+        assert!(run("from_json(\"[1,\")")
+            .starts_with("Result::Err(could not parse JSON: EOF while parsing"));
+
+        let err = Interpreter::new()
+            .run(&parse("to_json(print)").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "could not convert to JSON: can't serialize function values"
+        );
+    }
+
+    #[test]
+    fn runs_scripts_with_arguments_and_exit_codes() {
+        let source = "
+            let unset = env(\"CLAY_TEST_UNSET\");
+            fn main() {
+                if len(args()) > 1 { exit(3) }
+                println(unset == None);
+                len(args())
+            }
+        ";
+        let program = parse(source).unwrap();
+        let runs = [
+            (vec!["a"], Some(1)),
+            (vec!["a", "b"], Some(3)),
+            (vec![], Some(0)),
+        ];
+        for (args, code) in runs {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_io(Rc::new(RefCell::new(BufferIo::new())));
+            interpreter.set_args(args.clone());
+            assert_eq!(interpreter.run_script(&program).unwrap(), Value::Unit);
+            assert_eq!(interpreter.exit_code(), code);
+
+            let mut vm = Vm::new();
+            vm.set_io(Rc::new(RefCell::new(BufferIo::new())));
+            vm.set_args(args);
+            assert_eq!(vm.run_script(&program).unwrap(), Value::Unit);
+            assert_eq!(vm.exit_code(), code);
+        }
+
+        // `exit` stops the program where it is called, and `run` doesn't
+        // call `main`.
+        let program = parse("let mut x = 1; exit(2); x = 3; fn main() { 4 }").unwrap();
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run(&program).unwrap(), Value::Unit);
+        assert_eq!(interpreter.exit_code(), Some(2));
+        assert_eq!(interpreter.global("x"), Some(Value::Integer(1)));
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&parse("fn main() { 4 }").unwrap()).unwrap();
+        assert_eq!(interpreter.exit_code(), None);
+
+        let errors = [
+            ("exit(256)", "exit codes go from 0 to 255, found 256"),
+            ("fn main() { -1 }", "exit codes go from 0 to 255, found -1"),
+            (
+                "exit(\"1\")",
+                "an exit code must be an integer, found string",
+            ),
+            ("env(1)", "`env` expects a string, found integer"),
+        ];
+        for (source, message) in errors {
+            let program = parse(source).unwrap();
+            let err = Interpreter::new().run_script(&program).unwrap_err();
+            assert_eq!(err.message, message);
+            assert_eq!(Vm::new().run_script(&program).unwrap_err().message, message);
+        }
+    }
+
+    #[test]
+    fn runs_io_through_the_host() {
+        let source = "
+            let name = trim(read_line());
+            print(\"hello, \");
+            println(name);
+            println([len(read_line()) == 1, read_line() == \"last\", read_line() == \"\"]);
+            read_file(\"secret.txt\")
+        ";
+        let program = parse(source).unwrap();
+        let io = Rc::new(RefCell::new(BufferIo::with_input("wörld\r\n\nlast")));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(io.clone());
+        let err = interpreter.run(&program).unwrap_err();
+        assert_eq!(io.borrow().output(), "hello, wörld\n[true, true, true]\n");
+        assert_eq!(
+            err.message,
+            "could not read `secret.txt`: file access is not allowed"
+        );
+
+        let io = Rc::new(RefCell::new(BufferIo::new()));
+        let mut vm = Vm::new();
+        vm.set_io(io.clone());
+        vm.run(&parse("println(1 + 1)").unwrap()).unwrap();
+        assert_eq!(io.borrow().output(), "2\n");
+
+        // The default goes to the real file system.
+        let dir = std::env::temp_dir().join(format!("clay-io-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("note.txt").to_string_lossy().replace('\\', "\\\\");
+        let source = format!("write_file(\"{0}\", \"ünïcode\"); read_file(\"{0}\")", path);
+        assert_eq!(run(&source), "ünïcode");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
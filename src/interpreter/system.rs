@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+// What the standard library tells a program about the machine it runs on,
+// through `env` and the `std::os` and `std::time` modules. Clay has no
+// random numbers, so along with its `Io` this is all that can make a
+// program's results differ from one run to the next. Hosts swap it out to
+// give programs the same answers wherever and whenever they run.
+pub trait System {
+    // The value of the environment variable `name`, if it is set.
+    fn var(&self, name: &str) -> Option<String>;
+
+    // "linux", "macos", "windows" and so on.
+    fn platform(&self) -> String;
+
+    // "unix" or "windows".
+    fn family(&self) -> String;
+
+    fn temp_dir(&self) -> Option<PathBuf>;
+
+    fn home_dir(&self) -> Option<PathBuf>;
+
+    // The time of day, for dates.
+    fn now(&self) -> SystemTime;
+
+    // The time on a clock that never goes back, from some point it fixes,
+    // for measuring how long things take.
+    fn clock(&self) -> Duration;
+}
+
+// The machine clay is running on.
+#[derive(Debug, Default)]
+pub struct HostSystem;
+
+impl System for HostSystem {
+    fn var(&self, name: &str) -> Option<String> {
+        // Names `var_os` can't look up are never set.
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return None;
+        }
+        env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+    }
+
+    fn platform(&self) -> String {
+        env::consts::OS.to_string()
+    }
+
+    fn family(&self) -> String {
+        env::consts::FAMILY.to_string()
+    }
+
+    fn temp_dir(&self) -> Option<PathBuf> {
+        Some(env::temp_dir())
+    }
+
+    fn home_dir(&self) -> Option<PathBuf> {
+        let variable = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+        env::var_os(variable)
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+    }
+
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    // From the first time it is read.
+    fn clock(&self) -> Duration {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed()
+    }
+}
+
+// A machine that is the same everywhere: it has only the environment
+// variables the host sets, an "unknown" platform and family, and no
+// temporary or home directory. Its time stands still, at the epoch until
+// the host sets it.
+#[derive(Debug)]
+pub struct FixedSystem {
+    vars: BTreeMap<String, String>,
+    time: SystemTime,
+}
+
+impl Default for FixedSystem {
+    fn default() -> FixedSystem {
+        FixedSystem {
+            vars: BTreeMap::new(),
+            time: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl FixedSystem {
+    pub fn new() -> FixedSystem {
+        FixedSystem::default()
+    }
+
+    pub fn set_time(&mut self, time: SystemTime) {
+        self.time = time;
+    }
+
+    pub fn set_var(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.vars.insert(name.into(), value.into());
+    }
+}
+
+impl System for FixedSystem {
+    fn var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+
+    fn platform(&self) -> String {
+        "unknown".to_string()
+    }
+
+    fn family(&self) -> String {
+        "unknown".to_string()
+    }
+
+    fn temp_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn home_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn now(&self) -> SystemTime {
+        self.time
+    }
+
+    fn clock(&self) -> Duration {
+        Duration::ZERO
+    }
+}
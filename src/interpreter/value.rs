@@ -0,0 +1,552 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::environment::Environment;
+use crate::interpreter::gc;
+use crate::interpreter::module::Module;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::Function;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Tuple(Vec<Value>),
+    // Lists are shared: every copy of a list value sees pushes to it.
+    List(Rc<RefCell<Vec<Value>>>),
+    // Maps are shared like lists and iterate in key order.
+    Map(Rc<RefCell<BTreeMap<Key, Value>>>),
+    Function(Rc<Closure>),
+    // A function compiled for the bytecode vm.
+    Compiled(Rc<crate::vm::vm::Closure>),
+    Native(Native),
+    Host(Host),
+    Module(Rc<Module>),
+    Unit,
+    // Structs are shared like lists: every copy sees assignments to their
+    // fields.
+    Struct(Rc<Instance>),
+    // What a `struct` declaration binds its name to.
+    StructType(Rc<StructType>),
+    // A value of an enum: one of its variants and the values it holds.
+    Variant(Rc<Variant>),
+    // What an `enum` declaration binds its name to.
+    EnumType(Rc<EnumType>),
+    // `Enum::Variant` for a variant that holds values, called to make one.
+    Constructor(Rc<EnumType>, usize),
+    // The integers from the first up to but not including the second, what
+    // `a..b` evaluates to. `a..=b` is `a..b + 1`.
+    Range(i64, i64),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StructType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+    // The methods `impl` blocks give the type, called with the instance as
+    // their first argument. The collector doesn't see types, so whatever
+    // their methods refer to is kept for as long as the type is.
+    pub methods: RefCell<HashMap<Symbol, Value>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Instance {
+    pub ty: Rc<StructType>,
+    // The value of each of the type's fields, in the order it declares them.
+    pub fields: RefCell<Vec<Value>>,
+}
+
+impl Instance {
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        let index = self.ty.fields.iter().position(|&field| field == name)?;
+        Some(self.fields.borrow()[index].clone())
+    }
+
+    // Returns false if the type has no field `name`.
+    pub fn set(&self, name: Symbol, value: Value) -> bool {
+        match self.ty.fields.iter().position(|&field| field == name) {
+            Some(index) => {
+                self.fields.borrow_mut()[index] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EnumType {
+    pub name: Symbol,
+    // Each variant's name and how many values it holds.
+    pub variants: Vec<(Symbol, usize)>,
+}
+
+impl EnumType {
+    // `Enum::name`: the variant itself if it holds no values, and otherwise
+    // its constructor.
+    pub fn variant(self: &Rc<Self>, name: Symbol) -> Option<Value> {
+        let index = self
+            .variants
+            .iter()
+            .position(|&(variant, _)| variant == name)?;
+        Some(match self.variants[index].1 {
+            0 => Value::Variant(Rc::new(Variant {
+                ty: self.clone(),
+                index,
+                fields: Vec::new(),
+            })),
+            _ => Value::Constructor(self.clone(), index),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Variant {
+    pub ty: Rc<EnumType>,
+    // Which of the type's variants it is.
+    pub index: usize,
+    pub fields: Vec<Value>,
+}
+
+impl Variant {
+    pub fn name(&self) -> Symbol {
+        self.ty.variants[self.index].0
+    }
+}
+
+// The values that can be used as map keys: those with a total order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Tuple(Vec<Key>),
+}
+
+impl Key {
+    pub fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            Value::Integer(n) => Some(Key::Integer(*n)),
+            Value::String(s) => Some(Key::String(s.clone())),
+            Value::Tuple(values) => values
+                .iter()
+                .map(Key::from_value)
+                .collect::<Option<_>>()
+                .map(Key::Tuple),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Integer(n) => Value::Integer(*n),
+            Key::String(s) => Value::String(s.clone()),
+            Key::Tuple(keys) => Value::Tuple(keys.iter().map(Key::to_value).collect()),
+        }
+    }
+}
+
+pub struct Closure {
+    pub function: Arc<Function>,
+    pub environment: Rc<RefCell<Environment>>,
+    // The file the function was defined in.
+    pub file: Option<PathBuf>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        self.function.name.as_deref().unwrap_or("<anonymous>")
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        Arc::ptr_eq(&self.function, &other.function)
+            && Rc::ptr_eq(&self.environment, &other.environment)
+    }
+}
+
+// Closures usually capture an environment that contains themselves, so the
+// derived Debug would never terminate.
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+// The Rust code behind a native function. It may capture state, such as the
+// `Io` the standard library's I/O functions go through.
+pub type NativeFn = Rc<dyn Fn(&[Value], Span) -> Result<Value, Diagnostic>>;
+
+// A function implemented in Rust, such as those of the standard library.
+#[derive(Clone)]
+pub struct Native {
+    // The name it is called by, including its module.
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl Native {
+    pub fn new(
+        name: &'static str,
+        arity: usize,
+        function: impl Fn(&[Value], Span) -> Result<Value, Diagnostic> + 'static,
+    ) -> Native {
+        Native {
+            name,
+            arity,
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn call(&self, args: &[Value], span: Span) -> Result<Value, Diagnostic> {
+        if args.len() != self.arity {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` expects {} argument{}, found {}",
+                    self.name,
+                    self.arity,
+                    if self.arity == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                span,
+            ));
+        }
+        (self.function)(args, span)
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+// A value a host program hands to clay, which clay code can only pass
+// around. It displays through the printer registered for its Rust type, or
+// as `<type name>` without one.
+#[derive(Clone)]
+pub struct Host {
+    // What error messages call values of this type.
+    pub type_name: &'static str,
+    pub value: Rc<dyn Any>,
+}
+
+impl Host {
+    pub fn new<T: Any>(type_name: &'static str, value: T) -> Host {
+        Host {
+            type_name,
+            value: Rc::new(value),
+        }
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+impl PartialEq for Host {
+    fn eq(&self, other: &Host) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl fmt::Debug for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Cloned out so printers can display other host values.
+        let printer =
+            PRINTERS.with(|printers| printers.borrow().get(&(*self.value).type_id()).cloned());
+        match printer {
+            Some(printer) => write!(f, "{}", printer(&*self.value)),
+            None => write!(f, "<{}>", self.type_name),
+        }
+    }
+}
+
+type Printer = Rc<dyn Fn(&dyn Any) -> String>;
+
+thread_local! {
+    static PRINTERS: RefCell<HashMap<TypeId, Printer>> = RefCell::new(HashMap::new());
+}
+
+// Makes host values holding a `T` display as `print` renders them, wherever
+// clay shows a value: the repl, the debugger and error messages.
+pub fn register_printer<T: Any>(print: impl Fn(&T) -> String + 'static) {
+    let printer: Printer = Rc::new(move |value| {
+        print(
+            value
+                .downcast_ref()
+                .expect("printers are looked up by type"),
+        )
+    });
+    PRINTERS.with(|printers| printers.borrow_mut().insert(TypeId::of::<T>(), printer));
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Tuple(_) => "tuple",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) | Value::Compiled(_) | Value::Native(_) => "function",
+            Value::Host(host) => host.type_name,
+            Value::Module(_) => "module",
+            Value::Unit => "unit",
+            Value::Struct(_) | Value::StructType(_) => "struct",
+            Value::Variant(_) | Value::EnumType(_) => "enum",
+            Value::Constructor(..) => "function",
+            Value::Range(..) => "range",
+        }
+    }
+}
+
+impl Value {
+    pub fn list(values: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(values));
+        gc::track(&list);
+        Value::List(list)
+    }
+
+    pub fn map(entries: BTreeMap<Key, Value>) -> Value {
+        let map = Rc::new(RefCell::new(entries));
+        gc::track(&map);
+        Value::Map(map)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::List(values) => display_once(f, Rc::as_ptr(values) as *const (), "[...]", |f| {
+                write!(f, "[")?;
+                for (i, value) in values.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(entries) if entries.borrow().is_empty() => write!(f, "#{{}}"),
+            Value::Map(entries) => {
+                display_once(f, Rc::as_ptr(entries) as *const (), "#{...}", |f| {
+                    write!(f, "#{{")?;
+                    for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, " {}: {}", key.to_value(), value)?;
+                    }
+                    write!(f, " }}")
+                })
+            }
+            Value::Function(closure) => write!(f, "{:?}", closure),
+            Value::Compiled(closure) => write!(f, "{:?}", closure),
+            Value::Native(native) => write!(f, "{:?}", native),
+            Value::Host(host) => write!(f, "{}", host),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Unit => write!(f, "()"),
+            Value::Struct(instance) => {
+                let fields = instance.fields.borrow();
+                if fields.is_empty() {
+                    return write!(f, "{} {{}}", instance.ty.name);
+                }
+                let placeholder = format!("{} {{...}}", instance.ty.name);
+                display_once(f, Rc::as_ptr(instance) as *const (), &placeholder, |f| {
+                    write!(f, "{} {{", instance.ty.name)?;
+                    for (i, (name, value)) in
+                        instance.ty.fields.iter().zip(fields.iter()).enumerate()
+                    {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, " {}: {}", name, value)?;
+                    }
+                    write!(f, " }}")
+                })
+            }
+            Value::StructType(ty) => write!(f, "<struct {}>", ty.name),
+            Value::Range(start, end) => write!(f, "{}..{}", start, end),
+            Value::Variant(variant) => {
+                write!(f, "{}::{}", variant.ty.name, variant.name())?;
+                if variant.fields.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "(")?;
+                for (i, value) in variant.fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+            Value::EnumType(ty) => write!(f, "<enum {}>", ty.name),
+            Value::Constructor(ty, index) => {
+                write!(f, "<fn {}::{}>", ty.name, ty.variants[*index].0)
+            }
+        }
+    }
+}
+
+thread_local! {
+    // The shared values being displayed, outermost first.
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+// Displays the shared value at `address` with `display`, or as `placeholder`
+// inside itself, so a list that contains itself shows as `[[...]]`.
+fn display_once(
+    f: &mut fmt::Formatter,
+    address: *const (),
+    placeholder: &str,
+    display: impl FnOnce(&mut fmt::Formatter) -> fmt::Result,
+) -> fmt::Result {
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&address)) {
+        return write!(f, "{}", placeholder);
+    }
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(address));
+    let result = display(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
+}
+
+thread_local! {
+    // The pairs of shared values being compared, outermost first.
+    static COMPARING: RefCell<Vec<(*const (), *const ())>> = const { RefCell::new(Vec::new()) };
+}
+
+// Compares the shared values at `left` and `right` with `compare`, taking
+// them as equal when the same pair is being compared further out already,
+// so two lists that each contain themselves compare as equal rather than
+// without end.
+fn compare_once(left: *const (), right: *const (), compare: impl FnOnce() -> bool) -> bool {
+    let pair = (left, right);
+    if left == right || COMPARING.with(|comparing| comparing.borrow().contains(&pair)) {
+        return true;
+    }
+    COMPARING.with(|comparing| comparing.borrow_mut().push(pair));
+    let equal = compare();
+    COMPARING.with(|comparing| comparing.borrow_mut().pop());
+    equal
+}
+
+// Shared values are equal to themselves without comparing what they hold,
+// and a pair of them met again inside itself is taken as equal, so values
+// that contain themselves can be compared.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::List(a), Value::List(b)) => compare_once(
+                Rc::as_ptr(a) as *const (),
+                Rc::as_ptr(b) as *const (),
+                || a == b,
+            ),
+            (Value::Map(a), Value::Map(b)) => compare_once(
+                Rc::as_ptr(a) as *const (),
+                Rc::as_ptr(b) as *const (),
+                || a == b,
+            ),
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Compiled(a), Value::Compiled(b)) => a == b,
+            (Value::Native(a), Value::Native(b)) => a == b,
+            (Value::Host(a), Value::Host(b)) => a == b,
+            (Value::Module(a), Value::Module(b)) => a == b,
+            (Value::Unit, Value::Unit) => true,
+            (Value::Struct(a), Value::Struct(b)) => compare_once(
+                Rc::as_ptr(a) as *const (),
+                Rc::as_ptr(b) as *const (),
+                || a == b,
+            ),
+            (Value::StructType(a), Value::StructType(b)) => a == b,
+            (Value::Variant(a), Value::Variant(b)) => compare_once(
+                Rc::as_ptr(a) as *const (),
+                Rc::as_ptr(b) as *const (),
+                || a == b,
+            ),
+            (Value::EnumType(a), Value::EnumType(b)) => a == b,
+            (Value::Constructor(a, i), Value::Constructor(b, j)) => a == b && i == j,
+            (Value::Range(a, b), Value::Range(c, d)) => a == c && b == d,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::parser::parser::parse;
+
+    #[test]
+    fn displays_and_compares_values_that_contain_themselves() {
+        let source = "struct Node { next: Int }
+            let xs = [1];
+            xs.push(xs);
+            let m = #{ \"self\": xs };
+            m.insert(\"m\", m);
+            let node = Node { next: 0 };
+            node.next = node;
+            let ys = [1];
+            ys.push(ys);
+            let zs = [2];
+            zs.push(zs);
+            let other = Node { next: 0 };
+            other.next = other;
+            (xs, m, node, xs == xs, [xs] == [xs], xs == ys, xs == zs, node == other)";
+        let value = Interpreter::new().run(&parse(source).unwrap()).unwrap();
+        assert_eq!(
+            value.to_string(),
+            "([1, [...]], #{ m: #{...}, self: [1, [...]] }, Node { next: Node {...} }, true, true, true, false, true)"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::ops::Range;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::lexer::Lexer;
+use crate::lexer::token::{Comment, Position, Span, Token};
+
+// How many characters past the end of a token the lexer may look at to decide
+// where it ends: `1` is only known to be an integer once the two characters
+// after it are seen, since `1.5` is a float.
+const LOOKAHEAD: usize = 2;
+
+// An edit to a source text: the bytes in `range` are replaced with `text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_string();
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+}
+
+// Everything lexing a source produced: each token or error in order, and the
+// comments between them. Kept around so an edit only re-lexes what it
+// touched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexed<'a> {
+    pub tokens: Vec<Result<Token<'a>, Diagnostic>>,
+    pub comments: Vec<Comment<'a>>,
+}
+
+impl<'a> Lexed<'a> {
+    pub fn new(source: &'a str) -> Lexed<'a> {
+        let mut lexer = Lexer::new(source);
+        let tokens = (&mut lexer).collect();
+        Lexed {
+            tokens,
+            comments: lexer.comments().to_vec(),
+        }
+    }
+
+    // Lexes `source`, the text this was lexed from with `edit` applied. Tokens
+    // far enough before the edit are kept, and lexing restarts after the last
+    // of them. Once it reaches a token ending past the edit where an old token
+    // ended too, the rest of the text is the same as before, so the old
+    // tokens from there on are moved to their new positions instead.
+    pub fn relex<'b>(&self, source: &'b str, edit: &Edit) -> Lexed<'b> {
+        let kept = self
+            .tokens
+            .iter()
+            .take_while(|token| {
+                let end = span(token).end.char;
+                end <= edit.range.start
+                    && source[end..edit.range.start].chars().count() >= LOOKAHEAD
+            })
+            .count();
+        let restart = match kept {
+            0 => Position::new(1, 0, 0),
+            _ => span(&self.tokens[kept - 1]).end,
+        };
+
+        let mut tokens: Vec<_> = self.tokens[..kept]
+            .iter()
+            .map(|token| rebase(token, restart, restart, source))
+            .collect();
+        let mut comments: Vec<_> = self
+            .comments
+            .iter()
+            .take_while(|comment| comment.span.end.char <= restart.char)
+            .map(|comment| rebase_comment(comment, restart, restart, source))
+            .collect();
+
+        // Where the edited text ends, before and after the edit.
+        let old_end = edit.range.end;
+        let new_end = edit.range.start + edit.text.len();
+
+        let mut lexer = Lexer::starting_at(source, restart);
+        let mut old = kept;
+        let mut synced = None;
+        for token in &mut lexer {
+            let end = span(&token).end;
+            tokens.push(token);
+            if end.char < new_end {
+                continue;
+            }
+            let target = end.char - new_end + old_end;
+            while old < self.tokens.len() && span(&self.tokens[old]).end.char < target {
+                old += 1;
+            }
+            if old < self.tokens.len() && span(&self.tokens[old]).end.char == target {
+                synced = Some((old, span(&self.tokens[old]).end, end));
+                break;
+            }
+        }
+        comments.extend_from_slice(lexer.comments());
+
+        if let Some((index, from, to)) = synced {
+            tokens.extend(
+                self.tokens[index + 1..]
+                    .iter()
+                    .map(|token| rebase(token, from, to, source)),
+            );
+            comments.extend(
+                self.comments
+                    .iter()
+                    .filter(|comment| comment.span.start.char >= from.char)
+                    .map(|comment| rebase_comment(comment, from, to, source)),
+            );
+        }
+        Lexed { tokens, comments }
+    }
+}
+
+fn span(token: &Result<Token, Diagnostic>) -> Span {
+    match token {
+        Ok(token) => token.span,
+        Err(diagnostic) => diagnostic.span,
+    }
+}
+
+// Moves a position after `from` in the old text to the same place after `to`
+// in the new one.
+fn shift(position: Position, from: Position, to: Position) -> Position {
+    let column = if position.line == from.line {
+        position.column + to.column - from.column
+    } else {
+        position.column
+    };
+    Position::new(
+        position.line + to.line - from.line,
+        column,
+        position.char + to.char - from.char,
+    )
+}
+
+fn shift_span(span: Span, from: Position, to: Position) -> Span {
+    Span::new(shift(span.start, from, to), shift(span.end, from, to))
+}
+
+// Moves an old token to the new text. A token's own text always lexes back
+// to the same token, which gives one borrowing from the new text.
+fn rebase<'b>(
+    token: &Result<Token, Diagnostic>,
+    from: Position,
+    to: Position,
+    source: &'b str,
+) -> Result<Token<'b>, Diagnostic> {
+    match token {
+        Ok(token) => {
+            let span = shift_span(token.span, from, to);
+            let kind = match Lexer::new(&source[span.start.char..span.end.char]).next() {
+                Some(Ok(token)) => token.kind,
+                Some(Err(diagnostic)) => return Err(diagnostic),
+                None => return Err(Diagnostic::error("expected a token", span)),
+            };
+            Ok(Token::new(kind, span))
+        }
+        Err(diagnostic) => Err(Diagnostic {
+            span: shift_span(diagnostic.span, from, to),
+            ..diagnostic.clone()
+        }),
+    }
+}
+
+fn rebase_comment<'b>(
+    comment: &Comment,
+    from: Position,
+    to: Position,
+    source: &'b str,
+) -> Comment<'b> {
+    let span = shift_span(comment.span, from, to);
+    Comment {
+        text: source[span.start.char..span.end.char].trim_end(),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::incremental::{Edit, Lexed};
+    use crate::random::Random;
+
+    // Pieces random sources are made of, chosen to hit the places where
+    // tokens depend on what follows them.
+    const PIECES: [&str; 24] = [
+        "let", " ", "\n", "x", "1", "2", ".", "..", "5", "_", "f64", "\"", "//", "/", "=", "+",
+        "é", "\\", "(", ")", "{", "}", ";", "  ",
+    ];
+
+    #[test]
+    fn relexing_matches_lexing_from_scratch() {
+        for seed in 1..=2000 {
+            let mut random = Random(seed);
+            let pieces = random.below(40);
+            let source = random.text(&PIECES, pieces);
+            let (a, b) = (random.boundary(&source), random.boundary(&source));
+            let length = random.below(4);
+            let edit = Edit {
+                range: a.min(b)..a.max(b),
+                text: random.text(&PIECES, length),
+            };
+            let edited = edit.apply(&source);
+
+            let relexed = Lexed::new(&source).relex(&edited, &edit);
+            assert_eq!(
+                relexed,
+                Lexed::new(&edited),
+                "seed {}: {:?} edited by {:?}",
+                seed,
+                source,
+                edit
+            );
+        }
+    }
+
+    #[test]
+    fn relexes_a_series_of_edits() {
+        let replacements = [
+            ("total", "sum"),
+            ("", "// start\n"),
+            ("1.5", "1..5"),
+            (";\n", " // done\n"),
+            ("x", "\"x"),
+        ];
+        let mut sources = vec!["let total = 1.5;\nlet x = total..2;".to_string()];
+        let mut edits = Vec::new();
+        for (from, to) in replacements {
+            let source = sources.last().unwrap();
+            let start = source.find(from).unwrap();
+            let edit = Edit {
+                range: start..start + from.len(),
+                text: to.to_string(),
+            };
+            sources.push(edit.apply(source));
+            edits.push(edit);
+        }
+
+        let mut lexed = Lexed::new(&sources[0]);
+        for (edit, source) in edits.iter().zip(&sources[1..]) {
+            lexed = lexed.relex(source, edit);
+            assert_eq!(lexed, Lexed::new(source));
+        }
+        assert!(lexed.tokens.last().unwrap().is_err());
+    }
+}
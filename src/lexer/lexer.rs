@@ -1,233 +1,515 @@
-use core::num;
-use std::usize;
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::token::{Comment, Position, Span, Token, TokenType};
 
-use crate::lexer::token::{Position, Token, TokenType};
+// Which numeric literal forms the lexer accepts, so embedders can hold
+// source to a house style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexerOptions {
+    // Rejects ambiguous forms: leading zeros such as `007`, and `1.` with no
+    // digits after the point.
+    pub strict: bool,
+    // Allows `_` between digits, as in `1_000_000`.
+    pub separators: bool,
+    // Allows the type suffixes `i64` and `f64`, as in `2f64`.
+    pub suffixes: bool,
+}
+
+impl Default for LexerOptions {
+    fn default() -> LexerOptions {
+        LexerOptions {
+            strict: false,
+            separators: true,
+            suffixes: true,
+        }
+    }
+}
 
 pub struct Lexer<'a> {
     input: &'a str,
     position: Position,
+    options: LexerOptions,
+    comments: Vec<Comment<'a>>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &'a str) -> Lexer {
+    pub fn new(input: &'a str) -> Lexer<'a> {
+        Lexer::with_options(input, LexerOptions::default())
+    }
+
+    pub fn with_options(input: &'a str, options: LexerOptions) -> Lexer<'a> {
         Lexer {
             input,
             position: Position::new(1, 0, 0),
+            options,
+            comments: Vec::new(),
         }
     }
 
+    // Lexes `input` from part way through, keeping spans relative to the
+    // start of `input`.
+    pub fn starting_at(input: &'a str, position: Position) -> Lexer<'a> {
+        Lexer {
+            input,
+            position,
+            options: LexerOptions::default(),
+            comments: Vec::new(),
+        }
+    }
+
+    // The comments skipped so far, in source order.
+    pub fn comments(&self) -> &[Comment<'a>] {
+        &self.comments
+    }
+
     pub fn consume_char(&mut self) {
-        self.position.column += 1;
-        self.position.char += 1;
+        if let Some(ch) = self.get_current_char() {
+            self.position.column += 1;
+            self.position.char += ch.len_utf8();
+        }
+    }
+
+    pub fn consume_newline(&mut self) {
+        self.consume_char();
+        self.position.line += 1;
+        self.position.column = 0;
     }
 
     pub fn get_nth_char(&self, position: usize) -> Option<char> {
-        return self.input.chars().nth(position);
+        self.input.chars().nth(position)
     }
 
     pub fn get_current_char(&self) -> Option<char> {
-        return self.input.chars().nth(self.position.char);
+        self.input[self.position.char..].chars().next()
     }
 
     pub fn get_peek_char(&self) -> Option<char> {
-        return self.input.chars().nth(self.position.char + 1);
+        self.input[self.position.char..].chars().nth(1)
     }
 
-    pub fn lex_single_char<'b>(&mut self, kind: TokenType<'b>) -> Option<Token<'b>> {
+    pub fn lex_single_char(
+        &mut self,
+        kind: TokenType<'a>,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
         let position = self.position;
         self.consume_char();
-        return Some(Token { position, kind });
+        Some(Ok(Token::new(kind, Span::new(position, self.position))))
     }
 
-    pub fn lex_double_char<'b>(&mut self, kind: TokenType<'b>) -> Option<Token<'b>> {
+    pub fn lex_double_char(
+        &mut self,
+        kind: TokenType<'a>,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
         let position = self.position;
         self.consume_char();
         self.consume_char();
-        Some(Token { kind, position })
+        Some(Ok(Token::new(kind, Span::new(position, self.position))))
+    }
+
+    pub fn lex_triple_char(
+        &mut self,
+        kind: TokenType<'a>,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
+        let position = self.position;
+        self.consume_char();
+        self.consume_char();
+        self.consume_char();
+        Some(Ok(Token::new(kind, Span::new(position, self.position))))
+    }
+
+    fn lex_with_equal(
+        &mut self,
+        single: TokenType<'a>,
+        with_equal: TokenType<'a>,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
+        match self.get_peek_char() {
+            Some('=') => self.lex_double_char(with_equal),
+            _ => self.lex_single_char(single),
+        }
     }
+
+    // `<<` or `>>`, or the compound assignment with an `=` after it.
+    fn lex_shift(
+        &mut self,
+        shift: TokenType<'a>,
+        with_equal: TokenType<'a>,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
+        match self.input[self.position.char..].chars().nth(2) {
+            Some('=') => self.lex_triple_char(with_equal),
+            _ => self.lex_double_char(shift),
+        }
+    }
+
+    // Whether an `r` starts a raw string: `r"..."`, or `r#"..."#` with any
+    // number of `#`s, which lets the string hold quotes.
+    fn is_raw_string(&self) -> bool {
+        let rest = self.input[self.position.char + 1..].trim_start_matches('#');
+        rest.starts_with('"')
+    }
+
+    // A string from its opening quote up to a closing quote, starting at
+    // `position` so a raw string's `r` is part of it. A raw string ends at a
+    // quote followed by its `hashes` `#`s and has no escapes; any other holds
+    // its escapes as written, and `unescape` gives its value. Strings may
+    // span lines.
+    fn lex_string(
+        &mut self,
+        position: Position,
+        hashes: Option<usize>,
+    ) -> Result<Token<'a>, Diagnostic> {
+        self.consume_char();
+        let start = self.position.char;
+        let close = format!("\"{}", "#".repeat(hashes.unwrap_or(0)));
+        // The first escape that isn't one, reported for the whole string once
+        // it has been skipped, so lexing goes on after it.
+        let mut unknown = None;
+        while !self.input[self.position.char..].starts_with(close.as_str()) {
+            match self.get_current_char() {
+                Some('\\') if hashes.is_none() => {
+                    self.consume_char();
+                    match self.get_current_char() {
+                        Some('n' | 't' | '\\' | '"') => self.consume_char(),
+                        Some(ch) if unknown.is_none() => unknown = Some(ch),
+                        _ => {}
+                    }
+                }
+                Some('\n') => self.consume_newline(),
+                Some(_) => self.consume_char(),
+                None => return Err(self.error("unterminated string literal", position)),
+            }
+        }
+        let end = self.position.char;
+        for _ in 0..close.len() {
+            self.consume_char();
+        }
+        if let Some(ch) = unknown {
+            return Err(Diagnostic::error(
+                format!("unknown escape `\\{}` in string", ch.escape_default()),
+                Span::new(position, self.position),
+            )
+            .with_note(
+                "escapes are `\\n`, `\\t`, `\\\\` and `\\\"`; a raw string, `r\"...\"`, has none",
+            ));
+        }
+        let text = &self.input[start..end];
+        let kind = match hashes {
+            Some(_) => TokenType::RawString(text),
+            None => TokenType::String(text),
+        };
+        Ok(Token::new(kind, Span::new(position, self.position)))
+    }
+
+    // A `"""` string, up to the next `"""`. The token holds the text as
+    // written; `dedent` gives its value.
+    fn lex_multiline_string(&mut self) -> Result<Token<'a>, Diagnostic> {
+        let position = self.position;
+        for _ in 0..3 {
+            self.consume_char();
+        }
+        let start = self.position.char;
+        while !self.input[self.position.char..].starts_with("\"\"\"") {
+            match self.get_current_char() {
+                Some('\n') => self.consume_newline(),
+                Some(_) => self.consume_char(),
+                None => return Err(self.error("unterminated string literal", position)),
+            }
+        }
+        let end = self.position.char;
+        for _ in 0..3 {
+            self.consume_char();
+        }
+        Ok(Token::new(
+            TokenType::MultilineString(&self.input[start..end]),
+            Span::new(position, self.position),
+        ))
+    }
+
+    fn lex_number(&mut self) -> Result<Token<'a>, Diagnostic> {
+        let position = self.position;
+        let integer = self.lex_digits(position)?;
+
+        let mut fraction = None;
+        if self.get_current_char() == Some('.') {
+            match self.get_peek_char() {
+                Some('0'..='9') => {
+                    self.consume_char();
+                    fraction = Some(self.lex_digits(position)?);
+                }
+                // `1..2` is a range, not a float.
+                Some('.') => {}
+                _ if self.options.strict => {
+                    self.consume_char();
+                    return Err(self.error("expected digits after `.` in number literal", position));
+                }
+                _ => {}
+            }
+        }
+
+        let suffix_start = self.position.char;
+        while let Some('A'..='Z' | 'a'..='z' | '0'..='9' | '_') = self.get_current_char() {
+            self.consume_char();
+        }
+        let suffix = &self.input[suffix_start..self.position.char];
+        if !suffix.is_empty() {
+            if !self.options.suffixes {
+                return Err(self.error("number literal suffixes are not allowed", position));
+            }
+            match suffix {
+                "i64" if fraction.is_some() => {
+                    return Err(self.error("float literal cannot have suffix `i64`", position))
+                }
+                "i64" | "f64" => {}
+                _ => {
+                    return Err(self.error(
+                        format!("invalid suffix `{}` for number literal", suffix),
+                        position,
+                    ))
+                }
+            }
+        }
+
+        if self.options.strict && integer.len() > 1 && integer.starts_with('0') {
+            return Err(self.error("number literal has leading zeros", position));
+        }
+
+        let span = Span::new(position, self.position);
+        if fraction.is_none() && suffix != "f64" {
+            return match integer.parse::<usize>() {
+                Ok(n) => Ok(Token::new(TokenType::Integer(n), span)),
+                Err(_) => Err(self.error("integer literal is too large", position)),
+            };
+        }
+        let number = format!("{}.{}", integer, fraction.unwrap_or_default());
+        match number.parse::<f64>() {
+            Ok(n) => Ok(Token::new(TokenType::Float(n), span)),
+            Err(_) => Err(self.error("invalid float literal", position)),
+        }
+    }
+
+    // Consumes a run of digits and returns them without separators. Errors
+    // cover the number from `start`, so none of it is left to lex again.
+    fn lex_digits(&mut self, start: Position) -> Result<String, Diagnostic> {
+        let mut digits = String::new();
+        let mut separated = false;
+        loop {
+            match self.get_current_char() {
+                Some(ch @ '0'..='9') => {
+                    digits.push(ch);
+                    self.consume_char();
+                }
+                Some('_') if !digits.is_empty() => {
+                    separated = true;
+                    self.consume_char();
+                }
+                _ => break,
+            }
+        }
+        if separated && !self.options.separators {
+            return Err(self.error("digit separators are not allowed", start));
+        }
+        Ok(digits)
+    }
+
+    // Skips to the end of the line, keeping what it skipped as a comment.
+    fn lex_comment(&mut self) {
+        let position = self.position;
+        while !matches!(self.get_current_char(), None | Some('\n')) {
+            self.consume_char();
+        }
+        self.comments.push(Comment {
+            text: self.input[position.char..self.position.char].trim_end(),
+            span: Span::new(position, self.position),
+        });
+    }
+
+    fn error(&self, message: impl Into<String>, start: Position) -> Diagnostic {
+        Diagnostic::error(message, Span::new(start, self.position))
+    }
+}
+
+// The value of a string written with escapes, which the lexer has checked.
+pub fn unescape(text: &str) -> String {
+    if !text.contains('\\') {
+        return text.to_string();
+    }
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        let ch = match ch {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                // `\\` and `\"`.
+                Some(escaped) => escaped,
+                None => break,
+            },
+            ch => ch,
+        };
+        value.push(ch);
+    }
+    value
+}
+
+// The value of a `"""` string. The line break after the opening quotes and
+// the line the closing quotes are on are dropped when they are blank, and so
+// is the indentation the other lines share, so the string can be indented
+// with the code around it. Blank lines become empty.
+pub fn dedent(text: &str) -> String {
+    let blank = |line: &str| line.trim_start_matches([' ', '\t', '\r']).is_empty();
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    if lines.len() > 1 && blank(lines[0]) {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines.last().is_some_and(|line| blank(line)) {
+        lines.pop();
+    }
+    let indent = lines
+        .iter()
+        .filter(|line| !blank(line))
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|line| if blank(line) { "" } else { &line[indent..] })
+        .collect();
+    lines.join("\n")
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
+    type Item = Result<Token<'a>, Diagnostic>;
 
-    fn next(&mut self) -> Option<Token<'a>> {
-        let current_char = self.get_current_char();
-        if current_char == None {
-            return None;
+    fn next(&mut self) -> Option<Result<Token<'a>, Diagnostic>> {
+        // Whitespace and comments are skipped here in a loop, so however
+        // many of them there are in a row, skipping them takes no stack.
+        loop {
+            match (self.get_current_char()?, self.get_peek_char()) {
+                ('\n', _) => self.consume_newline(),
+                (' ' | '\t' | '\r', _) => self.consume_char(),
+                ('/', Some('/')) => self.lex_comment(),
+                // A `#!` line at the start of a file, like
+                // `#!/usr/bin/env clay`, names what runs it. It's kept as a
+                // comment.
+                ('#', Some('!')) if self.position.char == 0 => self.lex_comment(),
+                _ => break,
+            }
         }
-
+        let current_char = self.get_current_char()?;
         let peek_char = self.get_peek_char();
 
-        match current_char.unwrap() {
+        match current_char {
             '(' => self.lex_single_char(TokenType::LParen),
             ')' => self.lex_single_char(TokenType::RParen),
             '[' => self.lex_single_char(TokenType::LBracket),
             ']' => self.lex_single_char(TokenType::RBracket),
             '{' => self.lex_single_char(TokenType::LBrace),
             '}' => self.lex_single_char(TokenType::RBrace),
-            '!' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::BangEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => self.lex_single_char(TokenType::Bang),
-                _ => panic!("Undefined token."),
-            },
-            '=' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::DoubleEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Equal)
+            '.' => match peek_char {
+                Some('.') if self.input[self.position.char..].chars().nth(2) == Some('=') => {
+                    self.lex_triple_char(TokenType::DotDotEq)
                 }
-                _ => panic!("Undefined token."),
+                Some('.') => self.lex_double_char(TokenType::DotDot),
+                _ => self.lex_single_char(TokenType::Period),
             },
-
+            ',' => self.lex_single_char(TokenType::Comma),
+            ';' => self.lex_single_char(TokenType::Semicolon),
+            ':' => match peek_char {
+                Some(':') => self.lex_double_char(TokenType::ColonColon),
+                _ => self.lex_single_char(TokenType::Colon),
+            },
+            '%' => self.lex_single_char(TokenType::Percent),
+            '#' => self.lex_single_char(TokenType::Hash),
+            '@' => self.lex_single_char(TokenType::At),
+            '$' => self.lex_single_char(TokenType::Dollar),
+            '?' => self.lex_single_char(TokenType::Question),
+            '!' => self.lex_with_equal(TokenType::Bang, TokenType::BangEqual),
             '=' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::DoubleEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Equal)
-                }
-                _ => panic!("Undefined token."),
+                Some('>') => self.lex_double_char(TokenType::FatArrow),
+                _ => self.lex_with_equal(TokenType::Equal, TokenType::DoubleEqual),
+            },
+            '<' => match peek_char {
+                Some('<') => self.lex_shift(TokenType::ShiftLeft, TokenType::ShiftLeftEqual),
+                _ => self.lex_with_equal(TokenType::Less, TokenType::LessEqual),
+            },
+            '>' => match peek_char {
+                Some('>') => self.lex_shift(TokenType::ShiftRight, TokenType::ShiftRightEqual),
+                _ => self.lex_with_equal(TokenType::Greater, TokenType::GreaterEqual),
             },
+            '^' => self.lex_with_equal(TokenType::Caret, TokenType::CaretEqual),
+            '~' => self.lex_single_char(TokenType::Tilde),
+            '+' => self.lex_with_equal(TokenType::Plus, TokenType::PlusEqual),
+            '-' => match peek_char {
+                Some('>') => self.lex_double_char(TokenType::Arrow),
+                _ => self.lex_with_equal(TokenType::Minus, TokenType::MinusEqual),
+            },
+            '/' => self.lex_with_equal(TokenType::Slash, TokenType::SlashEqual),
+            '*' => self.lex_with_equal(TokenType::Asterisk, TokenType::AsteriskEqual),
 
             '|' => match peek_char {
                 Some('|') => self.lex_double_char(TokenType::Or),
-                None | Some(' ') | Some('\t') | Some('\r') => self.lex_single_char(TokenType::Bar),
-                _ => panic!("Undefined token."),
+                Some('>') => self.lex_double_char(TokenType::Pipeline),
+                _ => self.lex_with_equal(TokenType::Bar, TokenType::BarEqual),
             },
 
             '&' => match peek_char {
                 Some('&') => self.lex_double_char(TokenType::And),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Ampersand)
-                }
-                _ => panic!("Undefined token."),
-            },
-            '+' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::PlusEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => self.lex_single_char(TokenType::Plus),
-                _ => panic!("Undefined token."),
-            },
-            '-' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::MinusEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Minus)
-                }
-                _ => panic!("Undefined token."),
-            },
-            '/' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::SlashEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Slash)
-                }
-                _ => panic!("Undefined token."),
+                _ => self.lex_with_equal(TokenType::Ampersand, TokenType::AmpersandEqual),
             },
-
-            '*' => match peek_char {
-                Some('=') => self.lex_double_char(TokenType::AsteriskEqual),
-                None | Some(' ') | Some('\t') | Some('\r') => {
-                    self.lex_single_char(TokenType::Asterisk)
-                }
-                _ => panic!("Undefined token."),
-            },
-            '0'..='9' => {
-                enum NumberTypes {
-                    Int,
-                    Float,
-                }
-
-                let position = self.position;
-                let mut num = String::new();
-                let mut num_type = NumberTypes::Int;
-
-                while let Some(ch) = self.get_current_char() {
-                    match ch {
-                        '0'..='9' => {
-                            num.push(ch);
-                            self.consume_char();
-                        }
-                        '.' if matches!(self.get_peek_char(), Some('0'..='9')) => {
-                            num_type = NumberTypes::Float;
-                            num.push(ch);
-                            self.consume_char();
-                        }
-                        _ => break,
-                    }
-                }
-
-                match num_type {
-                    NumberTypes::Int => match num.parse::<usize>() {
-                        Ok(n) => Some(Token {
-                            position,
-                            kind: TokenType::Integer(n),
-                        }),
-                        Err(e) => panic!("{}", e),
-                    },
-                    NumberTypes::Float => match num.parse::<f32>() {
-                        Ok(n) => Some(Token {
-                            position,
-                            kind: TokenType::Float(n),
-                        }),
-                        Err(e) => panic!("{}", e),
-                    },
-                }
+            '0'..='9' => Some(self.lex_number()),
+            '"' if self.input[self.position.char..].starts_with("\"\"\"") => {
+                Some(self.lex_multiline_string())
             }
-            '"' => {
-                self.consume_char();
+            '"' => Some(self.lex_string(self.position, None)),
+            'r' if self.is_raw_string() => {
                 let position = self.position;
-                let mut end: usize = 0;
-                while let Some(ch) = self.get_current_char() {
-                    match (ch) {
-                        '"' => {
-                            self.consume_char();
-                            end = self.position.char;
-                            break;
-                        }
-                        '\n' => {
-                            self.position.line += 1;
-                            self.position.column = 0;
-                            self.consume_char();
-                        }
-                        _ => self.consume_char(),
-                    }
+                self.consume_char();
+                let mut hashes = 0;
+                while self.get_current_char() == Some('#') {
+                    self.consume_char();
+                    hashes += 1;
                 }
-                return Some(Token {
-                    kind: TokenType::String(&self.input[position.char..end]),
-                    position: self.position,
-                });
+                Some(self.lex_string(position, Some(hashes)))
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 let position = self.position;
-                let end: usize = 0;
                 while let Some(ch) = self.get_current_char() {
                     match ch {
-                        'A'..='Z' | 'a'..='z' | '_' => {
+                        'A'..='Z' | 'a'..='z' | '0'..='9' | '_' => {
                             self.consume_char();
                         }
                         _ => break,
                     }
                 }
 
-                let slice = &self.input[position.char..end];
-                let kind = TokenType::match_keyword(slice);
+                let slice = &self.input[position.char..self.position.char];
 
-                Some(Token { kind, position })
-            }
-            '\n' => {
-                self.position.line += 1;
-                self.position.column = 0;
-                self.consume_char();
-                self.next()
+                Some(Ok(Token::from_keyword(
+                    slice,
+                    Span::new(position, self.position),
+                )))
             }
-            ' ' | '\t' | '\r' => {
+            ch => {
+                let position = self.position;
                 self.consume_char();
-                self.next()
+                Some(Err(
+                    self.error(format!("unexpected character `{}`", ch), position)
+                ))
             }
-
-            _ => panic!("Undefined token."),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::lexer::{Lexer, Token};
+    use crate::lexer::lexer::{dedent, unescape, Lexer, LexerOptions};
+    use crate::lexer::symbol::Symbol;
+    use crate::lexer::token::{quote, Position, Span, TokenType};
+    use crate::parser::parser::parse;
+    use crate::random::Random;
+
+    fn kinds(input: &str) -> Vec<TokenType<'_>> {
+        Lexer::new(input).map(|t| t.unwrap().kind).collect()
+    }
+
     #[test]
     fn it_works() {
         let test_str = "1 + 2.3555";
@@ -235,4 +517,449 @@ mod tests {
         let z = l.collect::<Vec<_>>();
         println!("{:#?}", z);
     }
+
+    #[test]
+    fn skips_long_runs_of_whitespace_and_comments_on_a_small_stack() {
+        let source = format!(
+            "{}1{}2\n{}3",
+            " ".repeat(1_000_000),
+            "\n\t\r".repeat(100_000),
+            "// comment\n".repeat(100_000)
+        );
+        let count = std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || Lexer::new(&source).filter_map(Result::ok).count())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn lexes_operators_without_whitespace() {
+        assert_eq!(
+            kinds("a+=1*(b-2)<=c?"),
+            vec![
+                TokenType::Ident(Symbol::intern("a")),
+                TokenType::PlusEqual,
+                TokenType::Integer(1),
+                TokenType::Asterisk,
+                TokenType::LParen,
+                TokenType::Ident(Symbol::intern("b")),
+                TokenType::Minus,
+                TokenType::Integer(2),
+                TokenType::RParen,
+                TokenType::LessEqual,
+                TokenType::Ident(Symbol::intern("c")),
+                TokenType::Question,
+            ]
+        );
+        assert_eq!(
+            kinds("a|>b||c"),
+            vec![
+                TokenType::Ident(Symbol::intern("a")),
+                TokenType::Pipeline,
+                TokenType::Ident(Symbol::intern("b")),
+                TokenType::Or,
+                TokenType::Ident(Symbol::intern("c")),
+            ]
+        );
+        assert_eq!(
+            kinds("a<<=b>>c^~d&=e|f"),
+            vec![
+                TokenType::Ident(Symbol::intern("a")),
+                TokenType::ShiftLeftEqual,
+                TokenType::Ident(Symbol::intern("b")),
+                TokenType::ShiftRight,
+                TokenType::Ident(Symbol::intern("c")),
+                TokenType::Caret,
+                TokenType::Tilde,
+                TokenType::Ident(Symbol::intern("d")),
+                TokenType::AmpersandEqual,
+                TokenType::Ident(Symbol::intern("e")),
+                TokenType::Bar,
+                TokenType::Ident(Symbol::intern("f")),
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_strings_and_keywords() {
+        assert_eq!(
+            kinds("match \"héllo\" true x1"),
+            vec![
+                TokenType::Match,
+                TokenType::String("héllo"),
+                TokenType::True,
+                TokenType::Ident(Symbol::intern("x1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_raw_and_multiline_strings() {
+        assert_eq!(
+            kinds("r\"a\\d\" r##\"say \"#hi\"#\"## r + 1"),
+            vec![
+                TokenType::RawString("a\\d"),
+                TokenType::RawString("say \"#hi\"#"),
+                TokenType::Ident(Symbol::intern("r")),
+                TokenType::Plus,
+                TokenType::Integer(1),
+            ]
+        );
+        let tokens: Vec<_> = Lexer::new("x = \"\"\"\n  é\n    \"\"\";\n\"\"")
+            .map(|token| token.unwrap())
+            .collect();
+        assert_eq!(tokens[2].kind, TokenType::MultilineString("\n  é\n    "));
+        assert_eq!(tokens[2].span.end, Position::new(3, 7, 20));
+        assert_eq!(tokens[3].span.start, Position::new(3, 7, 20));
+        assert_eq!(tokens[4].kind, TokenType::String(""));
+        assert_eq!(tokens[4].span.start, Position::new(4, 0, 22));
+
+        let err = Lexer::new("r#\"open\"").next().unwrap().unwrap_err();
+        assert_eq!(err.message, "unterminated string literal");
+        assert_eq!(err.span.start.column, 0);
+    }
+
+    #[test]
+    fn lexes_escapes_in_strings_but_not_in_raw_strings() {
+        let source = r#""a\"b\n\t\\" r"a\n" "#;
+        assert_eq!(
+            kinds(source),
+            vec![
+                TokenType::String(r#"a\"b\n\t\\"#),
+                TokenType::RawString(r"a\n"),
+            ]
+        );
+        assert_eq!(unescape(r#"a\"b\n\t\\"#), "a\"b\n\t\\");
+
+        let mut lexer = Lexer::new(r#""a\qb" 1"#);
+        let err = lexer.next().unwrap().unwrap_err();
+        assert_eq!(err.message, "unknown escape `\\q` in string");
+        assert_eq!((err.span.start.column, err.span.end.column), (0, 6));
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenType::Integer(1));
+    }
+
+    #[test]
+    fn dedents_multiline_strings() {
+        assert_eq!(dedent("\n    a\n      b\n\n    c\n    "), "a\n  b\n\nc");
+        assert_eq!(dedent("  one line"), "one line");
+        assert_eq!(dedent("\n\ta\n\t"), "a");
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("say \"hi\" \\o/"), "\"say \\\"hi\\\" \\\\o/\"");
+    }
+
+    #[test]
+    fn skips_comments_and_keeps_them_as_trivia() {
+        let mut lexer = Lexer::new("a / b // half  \n// done\na /= 2");
+        let kinds: Vec<_> = (&mut lexer).map(|t| t.unwrap().kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Ident(Symbol::intern("a")),
+                TokenType::Slash,
+                TokenType::Ident(Symbol::intern("b")),
+                TokenType::Ident(Symbol::intern("a")),
+                TokenType::SlashEqual,
+                TokenType::Integer(2),
+            ]
+        );
+        let comments: Vec<_> = lexer
+            .comments()
+            .iter()
+            .map(|c| (c.text, c.span.start.line))
+            .collect();
+        assert_eq!(comments, vec![("// half", 1), ("// done", 2)]);
+    }
+
+    #[test]
+    fn tells_doc_comments_apart() {
+        let mut lexer = Lexer::new("/// Adds.\n///\n///x\n//// rule\n// plain\nf");
+        (&mut lexer).for_each(drop);
+        let docs: Vec<_> = lexer.comments().iter().map(|c| c.doc()).collect();
+        assert_eq!(docs, vec![Some("Adds."), Some(""), Some("x"), None, None]);
+    }
+
+    #[test]
+    fn skips_a_leading_shebang_line() {
+        let mut lexer = Lexer::new("#!/usr/bin/env clay\r\nlet x = @;");
+        let tokens: Vec<_> = (&mut lexer).take_while(Result::is_ok).flatten().collect();
+        let starts: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.span.start.line, t.span.start.column))
+            .collect();
+        assert_eq!(starts, vec![(2, 0), (2, 4), (2, 6), (2, 8), (2, 9)]);
+        assert_eq!(lexer.comments()[0].text, "#!/usr/bin/env clay");
+
+        // Only at the very start of a file.
+        assert_eq!(
+            kinds(" #!x"),
+            vec![
+                TokenType::Hash,
+                TokenType::Bang,
+                TokenType::Ident(Symbol::intern("x"))
+            ]
+        );
+        let error = parse("#!/usr/bin/env clay\nlet = 1;").unwrap_err();
+        assert_eq!((error.span.start.line, error.span.start.column), (2, 4));
+    }
+
+    #[test]
+    fn lexes_separators_and_suffixes() {
+        assert_eq!(
+            kinds("1_000 2.5_0 3f64 4i64 1..2 1.foo 1..=2"),
+            vec![
+                TokenType::Integer(1000),
+                TokenType::Float(2.5),
+                TokenType::Float(3.0),
+                TokenType::Integer(4),
+                TokenType::Integer(1),
+                TokenType::DotDot,
+                TokenType::Integer(2),
+                TokenType::Integer(1),
+                TokenType::Period,
+                TokenType::Ident(Symbol::intern("foo")),
+                TokenType::Integer(1),
+                TokenType::DotDotEq,
+                TokenType::Integer(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_numbers_the_options_disallow() {
+        let strict = LexerOptions {
+            strict: true,
+            separators: false,
+            suffixes: false,
+        };
+        let cases = [
+            ("007", LexerOptions::default(), None),
+            ("007", strict, Some("number literal has leading zeros")),
+            (
+                "1.",
+                strict,
+                Some("expected digits after `.` in number literal"),
+            ),
+            (
+                "1.len()",
+                strict,
+                Some("expected digits after `.` in number literal"),
+            ),
+            ("1_000", strict, Some("digit separators are not allowed")),
+            (
+                "2f64",
+                strict,
+                Some("number literal suffixes are not allowed"),
+            ),
+            ("0 0.5 1..2", strict, None),
+            (
+                "2.5i64",
+                LexerOptions::default(),
+                Some("float literal cannot have suffix `i64`"),
+            ),
+            (
+                "2px",
+                LexerOptions::default(),
+                Some("invalid suffix `px` for number literal"),
+            ),
+        ];
+        for (source, options, message) in cases.iter() {
+            let error = Lexer::with_options(source, *options)
+                .find_map(Result::err)
+                .map(|err| err.message);
+            assert_eq!(error.as_deref(), *message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_unexpected_characters() {
+        let err = Lexer::new("1 \\").nth(1).unwrap().unwrap_err();
+        assert_eq!(err.message, "unexpected character `\\`");
+        assert_eq!(err.span.start.column, 2);
+    }
+
+    #[test]
+    fn serializes_tokens_to_json() {
+        let token = Lexer::new("42").next().unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            r#"{"kind":{"Integer":42},"span":{"start":{"line":1,"column":0,"char":0},"end":{"line":1,"column":2,"char":2}}}"#
+        );
+    }
+
+    // Pieces random sources are made of: every kind of token, and the
+    // whitespace, comments and stray characters between them.
+    const PIECES: [&str; 47] = [
+        "let",
+        "x",
+        "_y2",
+        "true",
+        " ",
+        "\n",
+        "\t",
+        "0",
+        "7",
+        "1_000",
+        ".",
+        "..",
+        "..=",
+        "5",
+        "2.5",
+        "f64",
+        "i64",
+        "px",
+        "\"",
+        "\"a b\"",
+        "//",
+        "/",
+        "=",
+        "==",
+        "=>",
+        "-",
+        ">",
+        "!",
+        "&",
+        "|",
+        ":",
+        "é",
+        "😀",
+        "\\",
+        "(",
+        "}",
+        ";",
+        "#",
+        "@",
+        "$",
+        "?",
+        "\r",
+        "<<",
+        "^",
+        "~",
+        "r#\"\"a\"#",
+        "\"\"\"",
+    ];
+
+    // A random source, the same for every run with the same seed.
+    fn source(seed: u64) -> String {
+        let mut random = Random(seed);
+        let pieces = random.below(40);
+        random.text(&PIECES, pieces)
+    }
+
+    const OPTIONS: [LexerOptions; 2] = [
+        LexerOptions {
+            strict: false,
+            separators: true,
+            suffixes: true,
+        },
+        LexerOptions {
+            strict: true,
+            separators: false,
+            suffixes: false,
+        },
+    ];
+
+    // Where `offset` is in `source`, counted the way the lexer counts.
+    fn position(source: &str, offset: usize) -> Position {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Position::new(
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count(),
+            offset,
+        )
+    }
+
+    // The spans of everything the lexer found, errors and comments
+    // included, in source order.
+    fn spans(source: &str, options: LexerOptions) -> Vec<Span> {
+        let mut lexer = Lexer::with_options(source, options);
+        let mut spans: Vec<_> = (&mut lexer)
+            .map(|token| match token {
+                Ok(token) => token.span,
+                Err(err) => err.span,
+            })
+            .collect();
+        spans.extend(lexer.comments().iter().map(|comment| comment.span));
+        spans.sort_by_key(|span| span.start.char);
+        spans
+    }
+
+    #[test]
+    fn printed_tokens_lex_back_to_the_same_tokens() {
+        for seed in 1..=2000 {
+            let source = source(seed);
+            for options in OPTIONS.iter() {
+                let kinds: Vec<_> = Lexer::with_options(&source, *options)
+                    .filter_map(Result::ok)
+                    .map(|token| token.kind)
+                    .collect();
+                let printed: Vec<_> = kinds.iter().map(TokenType::text).collect();
+                let printed = printed.join(" ");
+                let relexed: Result<Vec<_>, _> = Lexer::with_options(&printed, *options)
+                    .map(|token| token.map(|token| token.kind))
+                    .collect();
+                assert_eq!(
+                    relexed.as_ref(),
+                    Ok(&kinds),
+                    "seed {}: {:?} printed as {:?}",
+                    seed,
+                    source,
+                    printed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spans_are_positions_in_order() {
+        for seed in 1..=2000 {
+            let source = source(seed);
+            for options in OPTIONS.iter() {
+                let mut end = position(&source, 0);
+                for span in spans(&source, *options) {
+                    for at in [span.start, span.end].iter() {
+                        assert!(source.is_char_boundary(at.char), "seed {}", seed);
+                        assert_eq!(*at, position(&source, at.char), "seed {}", seed);
+                    }
+                    assert!(
+                        end.char <= span.start.char && span.start.char <= span.end.char,
+                        "seed {}: {:?} overlaps in {:?}",
+                        seed,
+                        span,
+                        source
+                    );
+                    end = span.end;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_character_is_lexed_or_reported() {
+        for seed in 1..=2000 {
+            let source = source(seed);
+            for options in OPTIONS.iter() {
+                let mut end = 0;
+                for span in spans(&source, *options).iter().chain(Some(&Span::new(
+                    position(&source, source.len()),
+                    position(&source, source.len()),
+                ))) {
+                    // Only whitespace is skipped without a token, error or
+                    // comment to account for it.
+                    let gap = &source[end..span.start.char];
+                    assert!(
+                        gap.chars().all(|c| matches!(c, ' ' | '\t' | '\r' | '\n')),
+                        "seed {}: {:?} skipped in {:?}",
+                        seed,
+                        gap,
+                        source
+                    );
+                    end = span.end.char;
+                }
+            }
+        }
+    }
 }
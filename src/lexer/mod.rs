@@ -1,2 +1,19 @@
+// No input may panic the frontend: what it can't read is a diagnostic.
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::todo,
+        clippy::unimplemented
+    )
+)]
+
+pub mod incremental;
+#[allow(clippy::module_inception)]
 pub mod lexer;
+pub mod symbol;
 pub mod token;
+pub mod tree;
@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// An interned name. Each distinct identifier is stored once and handed out
+// as a number, so names are cheap to copy, hash and compare. There is one
+// interner for the whole process, so files parsed on different threads
+// agree on their symbols, and plugins are handed the host's with `share`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+// A plugin links its own copy of clay, with its own copy of this static,
+// which is pointed at the host's interner before it interns anything.
+static INTERNER: OnceLock<&'static RwLock<Interner>> = OnceLock::new();
+
+fn interner() -> &'static RwLock<Interner> {
+    INTERNER.get_or_init(|| Box::leak(Box::default()))
+}
+
+// The interner this copy of clay looks symbols up in.
+#[derive(Clone, Copy)]
+pub struct Symbols(&'static RwLock<Interner>);
+
+pub fn symbols() -> Symbols {
+    Symbols(interner())
+}
+
+// Makes this copy of clay look symbols up in `symbols`. Returns whether it
+// does, which it can't once it has interned a name in its own.
+pub fn share(symbols: Symbols) -> bool {
+    std::ptr::eq(*INTERNER.get_or_init(|| symbols.0), symbols.0)
+}
+
+// A thread that panicked elsewhere while holding the lock leaves the
+// interner as it was, since names are only ever added whole, so the lock
+// is taken regardless.
+fn read() -> RwLockReadGuard<'static, Interner> {
+    interner().read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write() -> RwLockWriteGuard<'static, Interner> {
+    interner().write().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        if let Some(&symbol) = read().symbols.get(name) {
+            return symbol;
+        }
+        let mut interner = write();
+        // Another thread may have interned it since the read lock was let go.
+        if let Some(&symbol) = interner.symbols.get(name) {
+            return symbol;
+        }
+        // Names are never freed, so a symbol's name can be handed out
+        // without holding on to the interner. There are only as many as
+        // there are distinct names in the programs the process reads.
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let symbol = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.symbols.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        read().names[self.0 as usize]
+    }
+}
+
+// `segments.join("::")` for paths of symbols.
+pub fn join(symbols: &[Symbol], separator: &str) -> String {
+    let names: Vec<&str> = symbols.iter().map(|symbol| symbol.as_str()).collect();
+    names.join(separator)
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+// Symbols sort by name, so whatever is sorted by them comes out the same
+// whichever order the names were interned in.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        match self == other {
+            true => Ordering::Equal,
+            false => self.as_str().cmp(other.as_str()),
+        }
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Symbol {
+        Symbol::intern(&name)
+    }
+}
+
+// The empty name, which is never an identifier.
+impl Default for Symbol {
+    fn default() -> Symbol {
+        Symbol::intern("")
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+// Serialized as their names, so syntax trees written by one process can be
+// read by another.
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use crate::lexer::symbol::{self, Symbol, Symbols};
+
+    #[test]
+    fn interns_each_name_once() {
+        let (a, b) = (Symbol::intern("count"), Symbol::intern("total"));
+        assert_eq!(Symbol::intern("count"), a);
+        assert_ne!(a, b);
+        assert_eq!(b.as_str(), "total");
+        assert!(a < b && a == "count");
+        assert_eq!(serde_json::to_string(&a).unwrap(), "\"count\"");
+        assert_eq!(serde_json::from_str::<Symbol>("\"total\"").unwrap(), b);
+    }
+
+    #[test]
+    fn keeps_the_interner_it_has_interned_names_in() {
+        let name = Symbol::intern("host");
+        assert!(symbol::share(symbol::symbols()));
+        assert!(!symbol::share(Symbols(Box::leak(Box::new(
+            RwLock::default()
+        )))));
+        assert_eq!(Symbol::intern("host"), name);
+    }
+
+    #[test]
+    fn symbols_are_shared_between_threads() {
+        let names = ["shared", "by", "threads"];
+        let symbols: Vec<Vec<Symbol>> = (0..4)
+            .map(|_| std::thread::spawn(move || names.iter().map(|&n| Symbol::intern(n)).collect()))
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        for symbols in symbols {
+            assert_eq!(
+                symbols,
+                names.iter().map(|&n| Symbol::intern(n)).collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -1,4 +1,10 @@
-#[derive(Debug, Clone, Copy)]
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::symbol::Symbol;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum TokenType<'a> {
     RParen,   // )
     LParen,   // (
@@ -14,60 +20,257 @@ pub enum TokenType<'a> {
     Asterisk,
     Equal,
     DoubleEqual,
+    FatArrow,
+    Arrow,
     Bang,
     BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
     Period,
+    DotDot,
+    DotDotEq,
+    Comma,
+    Colon,
+    ColonColon,
     Semicolon,
+    Hash,
+    At,
+    Dollar,
+    Question,
     Ampersand,
     And,
     Bar,
     Or,
+    Pipeline,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
     PlusEqual,
     MinusEqual,
     SlashEqual,
     AsteriskEqual,
+    AmpersandEqual,
+    BarEqual,
+    CaretEqual,
+    ShiftLeftEqual,
+    ShiftRightEqual,
 
     Integer(usize),
-    Float(f32),
+    Float(f64),
+    // The text between the quotes, escapes and all.
     String(&'a str),
+    // The text between the quotes of a raw string, which has no escapes.
+    RawString(&'a str),
+    // The text between the quotes of a `"""` string, indentation and all.
+    MultilineString(&'a str),
 
     // Keywords
-    Ident(&'a str),
+    Ident(Symbol),
+    True,
+    False,
+    Fn,
+    Return,
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     Match,
     Import,
+    Let,
+    Mut,
+    Struct,
+    Enum,
+    Trait,
+    Impl,
+}
+
+// `s` as a string literal, with its backslashes, quotes and tabs escaped.
+// Line breaks are kept, since strings may span lines.
+pub fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+// `s` as a raw string, with enough `#`s that the quotes it holds don't end
+// it.
+fn raw(s: &str) -> String {
+    let mut hashes = String::new();
+    while s.contains(&format!("\"{}", hashes)) {
+        hashes.push('#');
+    }
+    format!("r{}\"{}\"{}", hashes, s, hashes)
 }
 
+// The words `match_keyword` doesn't lex as names.
+pub const KEYWORDS: [&str; 19] = [
+    "true", "false", "fn", "return", "if", "else", "while", "for", "in", "break", "continue",
+    "match", "import", "let", "mut", "struct", "enum", "trait", "impl",
+];
+
 impl<'a> TokenType<'a> {
-    pub fn match_keyword(string: &'a str) -> TokenType {
+    pub fn match_keyword(string: &'a str) -> TokenType<'a> {
         match string {
+            "true" => TokenType::True,
+            "false" => TokenType::False,
+            "fn" => TokenType::Fn,
+            "return" => TokenType::Return,
+            "if" => TokenType::If,
+            "else" => TokenType::Else,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "match" => TokenType::Match,
             "import" => TokenType::Import,
-            _ => TokenType::Ident(string),
+            "let" => TokenType::Let,
+            "mut" => TokenType::Mut,
+            "struct" => TokenType::Struct,
+            "enum" => TokenType::Enum,
+            "trait" => TokenType::Trait,
+            "impl" => TokenType::Impl,
+            _ => TokenType::Ident(Symbol::intern(string)),
         }
     }
+
+    // The token written as source, which lexes back to the same token.
+    pub fn text(&self) -> String {
+        let symbol = match self {
+            TokenType::Integer(n) => return n.to_string(),
+            // Whole floats keep their `.0`, so they don't lex as integers.
+            TokenType::Float(n) if n.fract() == 0.0 => return format!("{:.1}", n),
+            TokenType::Float(n) => return n.to_string(),
+            TokenType::String(s) => return format!("\"{}\"", s),
+            TokenType::RawString(s) => return raw(s),
+            TokenType::MultilineString(s) => return format!("\"\"\"{}\"\"\"", s),
+            TokenType::Ident(name) => return name.to_string(),
+            TokenType::RParen => ")",
+            TokenType::LParen => "(",
+            TokenType::RBrace => "}",
+            TokenType::LBrace => "{",
+            TokenType::RBracket => "]",
+            TokenType::LBracket => "[",
+            TokenType::Percent => "%",
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Slash => "/",
+            TokenType::Asterisk => "*",
+            TokenType::Equal => "=",
+            TokenType::DoubleEqual => "==",
+            TokenType::FatArrow => "=>",
+            TokenType::Arrow => "->",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Period => ".",
+            TokenType::DotDot => "..",
+            TokenType::DotDotEq => "..=",
+            TokenType::Comma => ",",
+            TokenType::Colon => ":",
+            TokenType::ColonColon => "::",
+            TokenType::Semicolon => ";",
+            TokenType::Hash => "#",
+            TokenType::At => "@",
+            TokenType::Dollar => "$",
+            TokenType::Question => "?",
+            TokenType::Ampersand => "&",
+            TokenType::And => "&&",
+            TokenType::Bar => "|",
+            TokenType::Or => "||",
+            TokenType::Pipeline => "|>",
+            TokenType::Caret => "^",
+            TokenType::Tilde => "~",
+            TokenType::ShiftLeft => "<<",
+            TokenType::ShiftRight => ">>",
+            TokenType::PlusEqual => "+=",
+            TokenType::MinusEqual => "-=",
+            TokenType::SlashEqual => "/=",
+            TokenType::AsteriskEqual => "*=",
+            TokenType::AmpersandEqual => "&=",
+            TokenType::BarEqual => "|=",
+            TokenType::CaretEqual => "^=",
+            TokenType::ShiftLeftEqual => "<<=",
+            TokenType::ShiftRightEqual => ">>=",
+            TokenType::True => "true",
+            TokenType::False => "false",
+            TokenType::Fn => "fn",
+            TokenType::Return => "return",
+            TokenType::If => "if",
+            TokenType::Else => "else",
+            TokenType::While => "while",
+            TokenType::For => "for",
+            TokenType::In => "in",
+            TokenType::Break => "break",
+            TokenType::Continue => "continue",
+            TokenType::Match => "match",
+            TokenType::Import => "import",
+            TokenType::Let => "let",
+            TokenType::Mut => "mut",
+            TokenType::Struct => "struct",
+            TokenType::Enum => "enum",
+            TokenType::Trait => "trait",
+            TokenType::Impl => "impl",
+        };
+        symbol.to_string()
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub struct Token<'a> {
     pub kind: TokenType<'a>,
-    pub position: Position,
+    pub span: Span,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(kind: TokenType, position: Position) -> Token {
-        Token { kind, position }
+    pub fn new(kind: TokenType<'a>, span: Span) -> Token<'a> {
+        Token { kind, span }
     }
 
-    pub fn from_keyword(keyword: &'a str, position: Position) -> Token {
+    pub fn from_keyword(keyword: &'a str, span: Span) -> Token<'a> {
         Token {
             kind: TokenType::match_keyword(keyword),
 
-            position,
+            span,
+        }
+    }
+}
+
+// A `//` comment, which runs to the end of its line. Comments are not
+// tokens; tools that keep the source's layout read them from the lexer, and
+// the parser reads doc comments from it to document declarations.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Comment<'a> {
+    // The comment's text, `//` included.
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl<'a> Comment<'a> {
+    // The text of a `///` doc comment after the slashes and the space that
+    // usually follows them. `////` starts an ordinary comment, as in Rust,
+    // so lines of slashes don't document anything.
+    pub fn doc(&self) -> Option<&'a str> {
+        let text = self.text.strip_prefix("///")?;
+        if text.starts_with('/') {
+            return None;
         }
+        Some(text.strip_prefix(' ').unwrap_or(text))
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -79,3 +282,33 @@ impl Position {
         Position { line, column, char }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+impl<'a> fmt::Display for TokenType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenType::Integer(n) => write!(f, "integer `{}`", n),
+            TokenType::Float(n) => write!(f, "float `{}`", n),
+            TokenType::String(s) | TokenType::RawString(s) | TokenType::MultilineString(s) => {
+                write!(f, "string \"{}\"", s)
+            }
+            TokenType::Ident(name) => write!(f, "identifier `{}`", name),
+            _ => write!(f, "`{}`", self.text()),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use serde::Serialize;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::token::{Span, Token, TokenType};
+
+// The brackets a group of tokens is between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delimiter {
+    Paren,
+    Bracket,
+    Brace,
+}
+
+impl Delimiter {
+    // The delimiter `kind` opens, if it opens one.
+    pub fn opened_by(kind: TokenType) -> Option<Delimiter> {
+        match kind {
+            TokenType::LParen => Some(Delimiter::Paren),
+            TokenType::LBracket => Some(Delimiter::Bracket),
+            TokenType::LBrace => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+
+    pub fn closed_by(kind: TokenType) -> Option<Delimiter> {
+        match kind {
+            TokenType::RParen => Some(Delimiter::Paren),
+            TokenType::RBracket => Some(Delimiter::Bracket),
+            TokenType::RBrace => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+
+    pub fn open(self) -> TokenType<'static> {
+        match self {
+            Delimiter::Paren => TokenType::LParen,
+            Delimiter::Bracket => TokenType::LBracket,
+            Delimiter::Brace => TokenType::LBrace,
+        }
+    }
+
+    pub fn close(self) -> TokenType<'static> {
+        match self {
+            Delimiter::Paren => TokenType::RParen,
+            Delimiter::Bracket => TokenType::RBracket,
+            Delimiter::Brace => TokenType::RBrace,
+        }
+    }
+}
+
+// A token, or a group of them between matching brackets. Tools that work on
+// the structure of code without parsing it, like macro expansion, see a
+// file as a list of these.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenTree<'a> {
+    Token(Token<'a>),
+    Group(Group<'a>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Group<'a> {
+    pub delimiter: Delimiter,
+    pub open: Token<'a>,
+    pub trees: Vec<TokenTree<'a>>,
+    // Missing when the file ends before the group is closed.
+    pub close: Option<Token<'a>>,
+}
+
+impl<'a> TokenTree<'a> {
+    // The token, or the opening bracket of the group.
+    pub fn kind(&self) -> TokenType<'a> {
+        match self {
+            TokenTree::Token(token) => token.kind,
+            TokenTree::Group(group) => group.open.kind,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            TokenTree::Token(token) => token.span,
+            TokenTree::Group(group) => group.span(),
+        }
+    }
+}
+
+impl<'a> Group<'a> {
+    // From the opening bracket to the closing one, or to the end of the last
+    // tree in the group when it isn't closed.
+    pub fn span(&self) -> Span {
+        let end = self
+            .close
+            .map(|close| close.span)
+            .or_else(|| self.trees.last().map(TokenTree::span))
+            .unwrap_or(self.open.span);
+        self.open.span.to(end)
+    }
+}
+
+// How deeply groups may nest. Trees are walked recursively, so deeper
+// brackets are an error rather than a stack overflow.
+pub const MAX_NESTING: usize = 256;
+
+// Groups `tokens` between their brackets. Nothing is lost, so `flatten`
+// gives the tokens back: a closing bracket that matches no open group is
+// kept as a token of its own, and groups still open at the end are left
+// without a closing bracket. `unbalanced` finds either.
+pub fn token_trees(tokens: Vec<Token>) -> Result<Vec<TokenTree>, Diagnostic> {
+    // The groups still open, innermost last, below the trees of the file.
+    let mut open: Vec<Group> = Vec::new();
+    let mut trees = Vec::new();
+    for token in tokens {
+        if let Some(delimiter) = Delimiter::opened_by(token.kind) {
+            if open.len() == MAX_NESTING {
+                return Err(Diagnostic::error(
+                    format!("brackets nested more than {} levels deep", MAX_NESTING),
+                    token.span,
+                ));
+            }
+            open.push(Group {
+                delimiter,
+                open: token,
+                trees: Vec::new(),
+                close: None,
+            });
+            continue;
+        }
+        let mut tree = TokenTree::Token(token);
+        if let Some(mut group) = open.pop() {
+            if Delimiter::closed_by(token.kind) == Some(group.delimiter) {
+                group.close = Some(token);
+                tree = TokenTree::Group(group);
+            } else {
+                open.push(group);
+            }
+        }
+        match open.last_mut() {
+            Some(group) => group.trees.push(tree),
+            None => trees.push(tree),
+        }
+    }
+    while let Some(group) = open.pop() {
+        let tree = TokenTree::Group(group);
+        match open.last_mut() {
+            Some(group) => group.trees.push(tree),
+            None => trees.push(tree),
+        }
+    }
+    Ok(trees)
+}
+
+// The tokens of `trees`, in order.
+pub fn flatten<'a>(trees: &[TokenTree<'a>]) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    flatten_into(trees, &mut tokens);
+    tokens
+}
+
+pub fn flatten_into<'a>(trees: &[TokenTree<'a>], out: &mut Vec<Token<'a>>) {
+    for tree in trees {
+        match tree {
+            TokenTree::Token(token) => out.push(*token),
+            TokenTree::Group(group) => {
+                out.push(group.open);
+                flatten_into(&group.trees, out);
+                out.extend(group.close);
+            }
+        }
+    }
+}
+
+// A bracket without a partner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unbalanced<'a> {
+    // An opening bracket the tokens end before closing.
+    Unclosed(Token<'a>),
+    // A closing bracket that matches no open group.
+    Unmatched(Token<'a>),
+}
+
+impl<'a> Unbalanced<'a> {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Unbalanced::Unclosed(token) => Diagnostic::error(
+                format!("`{}` is never closed", token.kind.text()),
+                token.span,
+            ),
+            Unbalanced::Unmatched(token) => Diagnostic::error(
+                format!("`{}` closes nothing", token.kind.text()),
+                token.span,
+            ),
+        }
+    }
+}
+
+// The first bracket in `trees` without a partner, innermost first.
+pub fn unbalanced<'a>(trees: &[TokenTree<'a>]) -> Option<Unbalanced<'a>> {
+    for tree in trees {
+        match tree {
+            TokenTree::Token(token) if Delimiter::closed_by(token.kind).is_some() => {
+                return Some(Unbalanced::Unmatched(*token))
+            }
+            TokenTree::Token(_) => {}
+            TokenTree::Group(group) => {
+                if let Some(inner) = unbalanced(&group.trees) {
+                    return Some(inner);
+                }
+                if group.close.is_none() {
+                    return Some(Unbalanced::Unclosed(group.open));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::lexer::Lexer;
+    use crate::lexer::token::{Token, TokenType};
+    use crate::lexer::tree::{flatten, token_trees, unbalanced, Delimiter, TokenTree, MAX_NESTING};
+
+    fn lex(source: &str) -> Vec<Token<'_>> {
+        Lexer::new(source).collect::<Result<_, _>>().unwrap()
+    }
+
+    // The trees as text, with groups in brackets and stray tokens as they are.
+    fn shape(trees: &[TokenTree]) -> String {
+        let shapes: Vec<String> = trees
+            .iter()
+            .map(|tree| match tree {
+                TokenTree::Token(token) => token.kind.text(),
+                TokenTree::Group(group) => {
+                    let close = group.close.map_or(String::new(), |close| close.kind.text());
+                    format!("{}{}{}", group.open.kind.text(), shape(&group.trees), close)
+                }
+            })
+            .collect();
+        shapes.join(" ")
+    }
+
+    #[test]
+    fn groups_tokens_between_brackets() {
+        let tokens = lex("f(a, [1, 2]) { #{x: 1} }");
+        let trees = token_trees(tokens.clone()).unwrap();
+        assert_eq!(trees.len(), 3);
+        assert_eq!(shape(&trees), "f (a , [1 , 2]) {# {x : 1}}");
+        match &trees[2] {
+            TokenTree::Group(group) => {
+                assert_eq!(group.delimiter, Delimiter::Brace);
+                assert_eq!(group.span().start.column, 13);
+                assert_eq!(group.span().end.column, 24);
+            }
+            tree => panic!("expected a group, found {:?}", tree),
+        }
+        assert_eq!(flatten(&trees), tokens);
+        assert!(unbalanced(&trees).is_none());
+    }
+
+    #[test]
+    fn keeps_unbalanced_brackets() {
+        let tokens = lex("a) (b [c }");
+        let trees = token_trees(tokens.clone()).unwrap();
+        assert_eq!(shape(&trees), "a ) (b [c }");
+        assert_eq!(flatten(&trees), tokens);
+        let error = unbalanced(&trees).unwrap().diagnostic();
+        assert_eq!(error.message, "`)` closes nothing");
+
+        let trees = token_trees(lex("(b [c]")).unwrap();
+        let error = unbalanced(&trees).unwrap().diagnostic();
+        assert_eq!(error.message, "`(` is never closed");
+        assert_eq!(trees[0].kind(), TokenType::LParen);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let source = "(".repeat(MAX_NESTING) + &")".repeat(MAX_NESTING);
+        assert!(token_trees(lex(&source)).is_ok());
+        let error = token_trees(lex(&format!("({})", source))).unwrap_err();
+        assert_eq!(error.message, "brackets nested more than 256 levels deep");
+    }
+}
@@ -1,4 +1,21 @@
-mod lexer;
+pub mod analysis;
+pub mod codegen;
+pub mod diagnostic;
+pub mod formatter;
+pub mod highlight;
+pub mod interpreter;
+pub mod lexer;
+pub mod lint;
+pub mod optimize;
+pub mod parser;
+pub mod pipeline;
+pub mod project;
+pub mod rewrite;
+pub mod typecheck;
+pub mod vm;
+
+#[cfg(test)]
+mod random;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,336 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::diagnostic::diagnostic::{Diagnostic, Suggestion};
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::lint::rules;
+use crate::parser::ast::{Attribute, Function, Program, Stmt, StmtKind};
+use crate::parser::visit::{walk_function, walk_stmt, Visitor};
+
+// How a rule's findings are reported: not at all, as warnings, or as errors
+// that fail `clay lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    // The level an `@allow`, `@warn` or `@deny` attribute sets.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warn"),
+            Level::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+// A check over a whole program. Rules report what they find as warnings;
+// the linter decides how seriously each is taken.
+pub trait Rule {
+    // What configuration and output call the rule, like `unused-variable`.
+    fn id(&self) -> &str;
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic>;
+}
+
+// Something a rule found, at the level the rule is set to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Lint {
+    pub rule: String,
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+    // Fixes the rule offers.
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Lint {
+    // An error for a denied rule and a warning otherwise, noting which rule
+    // found it so it can be allowed.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = match self.level {
+            Level::Deny => Diagnostic::error(self.message.clone(), self.span),
+            _ => Diagnostic::warning(self.message.clone(), self.span),
+        };
+        let diagnostic = self.suggestions.iter().fold(diagnostic, |diagnostic, fix| {
+            diagnostic.with_suggestion(fix.message.clone(), fix.span, fix.replacement.clone())
+        });
+        diagnostic
+            .with_code(self.rule.clone())
+            .with_note(format!("`{}` is set to {}", self.rule, self.level))
+    }
+}
+
+// The rules to run and the level each is set to.
+pub struct Linter {
+    rules: Vec<(Box<dyn Rule>, Level)>,
+}
+
+impl Linter {
+    // A linter with the built-in rules at their default levels.
+    pub fn new() -> Linter {
+        let mut linter = Linter::empty();
+        for rule in rules::builtin() {
+            linter.register_boxed(rule);
+        }
+        linter
+    }
+
+    pub fn empty() -> Linter {
+        Linter { rules: Vec::new() }
+    }
+
+    pub fn register(&mut self, rule: impl Rule + 'static) {
+        self.register_boxed(Box::new(rule));
+    }
+
+    fn register_boxed(&mut self, rule: Box<dyn Rule>) {
+        let level = rule.default_level();
+        self.rules.push((rule, level));
+    }
+
+    pub fn rule_ids(&self) -> Vec<&str> {
+        self.rules.iter().map(|(rule, _)| rule.id()).collect()
+    }
+
+    pub fn set_level(&mut self, id: &str, level: Level) -> Result<(), String> {
+        match self.rules.iter_mut().find(|(rule, _)| rule.id() == id) {
+            Some((_, current)) => {
+                *current = level;
+                Ok(())
+            }
+            None => Err(format!("unknown lint `{}`", id)),
+        }
+    }
+
+    // What the rules find, in source order, leaving out what is allowed by
+    // the rule's level or by the program's attributes.
+    pub fn lint(&self, program: &Program) -> Vec<Lint> {
+        let levels = LintLevels::of(program);
+        let mut lints = Vec::new();
+        for (rule, level) in &self.rules {
+            for diagnostic in rule.check(program) {
+                let level = levels.level(rule.id(), diagnostic.span, *level);
+                if level != Level::Allow {
+                    let suggestions = diagnostic.suggestions().to_vec();
+                    lints.push(Lint {
+                        rule: rule.id().to_string(),
+                        level,
+                        message: diagnostic.message,
+                        span: diagnostic.span,
+                        suggestions,
+                    });
+                }
+            }
+        }
+        lints.sort_by_key(|lint| lint.span.start.char);
+        lints
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        Linter::new()
+    }
+}
+
+// The levels a program's `@allow`, `@warn` and `@deny` attributes set
+// rules to, for the whole file or for the declarations they are put on.
+pub struct LintLevels {
+    // In source order, so declarations come before the ones inside them.
+    // Attributes of the file have no span.
+    settings: Vec<(Option<Span>, Level, Symbol)>,
+}
+
+impl LintLevels {
+    pub fn of(program: &Program) -> LintLevels {
+        let mut levels = LintLevels {
+            settings: Vec::new(),
+        };
+        levels.add(None, &program.attributes);
+        for stmt in &program.statements {
+            levels.visit_stmt(stmt);
+        }
+        levels
+    }
+
+    fn add(&mut self, span: Option<Span>, attributes: &[Attribute]) {
+        for attribute in attributes {
+            if let Some(level) = Level::parse(&attribute.name) {
+                let settings = attribute.args.iter().map(|&rule| (span, level, rule));
+                self.settings.extend(settings);
+            }
+        }
+    }
+
+    // The level of `rule` for something found at `span`: what the innermost
+    // declaration around it sets, or else the file, or else `default`. An
+    // attribute names a rule by its ID or by the start of it, so `unused`
+    // covers `unused-variable` and `unused-import`.
+    pub fn level(&self, rule: &str, span: Span, default: Level) -> Level {
+        let mut level = default;
+        let mut innermost: Option<Span> = None;
+        for (within, set, name) in &self.settings {
+            let named = rule
+                .strip_prefix(name.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'));
+            if !named {
+                continue;
+            }
+            match within {
+                None if innermost.is_none() => level = *set,
+                Some(within)
+                    if contains(*within, span)
+                        && innermost.is_none_or(|inner| within.start.char >= inner.start.char) =>
+                {
+                    innermost = Some(*within);
+                    level = *set;
+                }
+                _ => {}
+            }
+        }
+        level
+    }
+}
+
+fn contains(outer: Span, inner: Span) -> bool {
+    outer.start.char <= inner.start.char && inner.end.char <= outer.end.char
+}
+
+impl Visitor for LintLevels {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Struct(decl) => self.add(Some(stmt.span), &decl.attributes),
+            StmtKind::Enum(decl) => self.add(Some(stmt.span), &decl.attributes),
+            StmtKind::Trait(decl) => {
+                self.add(Some(stmt.span), &decl.attributes);
+                for method in &decl.methods {
+                    self.add(Some(method.span), &method.attributes);
+                }
+            }
+            StmtKind::Impl(decl) => self.add(Some(stmt.span), &decl.attributes),
+            _ => {}
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.add(Some(function.span), &function.attributes);
+        walk_function(self, function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::diagnostic::{Diagnostic, Severity};
+    use crate::lint::lint::{Level, Linter, Rule};
+    use crate::parser::ast::{ExprKind, Program, StmtKind};
+    use crate::parser::parser::parse;
+
+    // Finds every integer statement.
+    struct Integers;
+
+    impl Rule for Integers {
+        fn id(&self) -> &str {
+            "integers"
+        }
+
+        fn default_level(&self) -> Level {
+            Level::Deny
+        }
+
+        fn check(&self, program: &Program) -> Vec<Diagnostic> {
+            let integers = program.statements.iter().filter(|stmt| {
+                matches!(&stmt.kind, StmtKind::Expr(expr) if matches!(expr.kind, ExprKind::Integer(_)))
+            });
+            integers
+                .map(|stmt| Diagnostic::warning("an integer", stmt.span))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn runs_registered_rules_at_their_levels() {
+        let program = parse("if true { 1 }; 2;").unwrap();
+        let mut linter = Linter::new();
+        linter.register(Integers);
+        let lints = linter.lint(&program);
+        let found: Vec<_> = lints
+            .iter()
+            .map(|lint| (lint.rule.as_str(), lint.level))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("constant-condition", Level::Warn),
+                ("integers", Level::Deny)
+            ]
+        );
+        let diagnostic = lints[1].diagnostic();
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.notes(), ["`integers` is set to deny"]);
+
+        linter.set_level("integers", Level::Allow).unwrap();
+        linter.set_level("constant-condition", Level::Deny).unwrap();
+        let levels: Vec<_> = linter
+            .lint(&program)
+            .iter()
+            .map(|lint| lint.level)
+            .collect();
+        assert_eq!(levels, vec![Level::Deny]);
+        assert_eq!(
+            linter.set_level("unused", Level::Warn).unwrap_err(),
+            "unknown lint `unused`"
+        );
+    }
+
+    #[test]
+    fn attributes_set_levels_for_files_and_declarations() {
+        let source = "@!deny(unused)\n\
+                      fn f() { let a = 1; }\n\
+                      @warn(unused-variable)\n\
+                      fn g() {\n\
+                      let b = 1;\n\
+                      @allow(unused) fn h() { let c = 1; }\n\
+                      }\n\
+                      @allow(constant-condition) fn k() { if true { 1 } }\n\
+                      if true { 2 }";
+        let program = parse(source).unwrap();
+        let mut linter = Linter::new();
+        linter.set_level("constant-condition", Level::Deny).unwrap();
+        let found: Vec<_> = linter
+            .lint(&program)
+            .iter()
+            .map(|lint| (lint.rule.clone(), lint.level, lint.span.start.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("unused-variable".to_string(), Level::Deny, 2),
+                ("unused-variable".to_string(), Level::Warn, 5),
+                ("constant-condition".to_string(), Level::Deny, 9),
+            ]
+        );
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::HashSet;
+
+use crate::analysis::exhaustiveness::{self, UNREACHABLE_ARM};
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::lint::lint::Rule;
+use crate::optimize::optimize::fold;
+use crate::parser::ast::{
+    Block, Expr, ExprKind, Function, ImportNames, Pattern, PatternKind, Program, Stmt, StmtKind,
+};
+use crate::parser::visit::{walk_expr, walk_pattern, walk_stmt, Visitor};
+
+// The rules every linter starts with.
+pub fn builtin() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(UnusedVariable),
+        Box::new(UnusedImport),
+        Box::new(UnreachableMatchArm),
+        Box::new(ShadowedBinding),
+        Box::new(ConstantCondition),
+    ]
+}
+
+// A `let` or pattern binding that is never read. Top-level bindings are left
+// alone, since other modules can read them, and so are names starting with
+// `_`.
+pub struct UnusedVariable;
+
+impl Rule for UnusedVariable {
+    fn id(&self) -> &str {
+        "unused-variable"
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let scopes = Scopes::resolve(program);
+        let unused = scopes.unused.into_iter().map(|(name, span)| {
+            let diagnostic = Diagnostic::warning(
+                format!(
+                    "unused variable `{}`, consider naming it `_{}` if that is intended",
+                    name, name
+                ),
+                span,
+            );
+            // Bindings in patterns span just their name, which can then be
+            // renamed in place; a `let` spans its whole statement.
+            let width = span.end.char - span.start.char;
+            if width != name.len() {
+                return diagnostic;
+            }
+            diagnostic.with_suggestion(format!("name it `_{}`", name), span, format!("_{}", name))
+        });
+        unused.collect()
+    }
+}
+
+// A binding in a block, function or arm that hides one of the same name from
+// an enclosing scope. Binding a name again in the same scope is how a value
+// is replaced, so that is left alone.
+pub struct ShadowedBinding;
+
+impl Rule for ShadowedBinding {
+    fn id(&self) -> &str {
+        "shadowed-binding"
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let scopes = Scopes::resolve(program);
+        let shadowed = scopes.shadowed.into_iter().map(|(name, span)| {
+            Diagnostic::warning(
+                format!("`{}` shadows a binding from an enclosing scope", name),
+                span,
+            )
+        });
+        shadowed.collect()
+    }
+}
+
+// A top-level `import` whose name nothing refers to.
+pub struct UnusedImport;
+
+impl Rule for UnusedImport {
+    fn id(&self) -> &str {
+        "unused-import"
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut names = Names::default();
+        for stmt in &program.statements {
+            names.visit_stmt(stmt);
+        }
+        let mut unused = Vec::new();
+        for stmt in &program.statements {
+            let import = match &stmt.kind {
+                StmtKind::Import(import) => import,
+                _ => continue,
+            };
+            match &import.names {
+                ImportNames::Module(_) if !names.0.contains(&import.bindings()[0]) => {
+                    let message = format!("unused import `{}`", import.path);
+                    unused.push(Diagnostic::warning(message, stmt.span));
+                }
+                ImportNames::Module(_) => {}
+                // Each member is used or not on its own.
+                ImportNames::Members(members) => {
+                    let members = members.iter().filter(|m| !names.0.contains(&m.binding()));
+                    unused.extend(members.map(|member| {
+                        let message = format!("unused import `{}`", member.name);
+                        Diagnostic::warning(message, member.span)
+                    }));
+                }
+            }
+        }
+        unused
+    }
+}
+
+// The first segment of every path and every name read, which is what an
+// import can be used through.
+#[derive(Default)]
+struct Names(HashSet<Symbol>);
+
+impl Visitor for Names {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name) => {
+                self.0.insert(*name);
+            }
+            ExprKind::Path(path) | ExprKind::Struct { path, .. } => {
+                self.0.extend(path.first());
+            }
+            _ => {}
+        }
+        walk_expr(self, expr)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Struct { path, .. } | PatternKind::Variant { path, .. } = &pattern.kind
+        {
+            self.0.extend(path.first());
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+// A match arm no value reaches, because the arms above it match everything
+// it does.
+pub struct UnreachableMatchArm;
+
+impl Rule for UnreachableMatchArm {
+    fn id(&self) -> &str {
+        "unreachable-match-arm"
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut diagnostics = exhaustiveness::check(program);
+        diagnostics.retain(|diagnostic| diagnostic.message == UNREACHABLE_ARM);
+        diagnostics
+    }
+}
+
+// An `if` or `while` whose condition folds to a constant. `while true` is
+// how a loop is written to run until it breaks, so it is left alone.
+pub struct ConstantCondition;
+
+impl Rule for ConstantCondition {
+    fn id(&self) -> &str {
+        "constant-condition"
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut conditions = Conditions(Vec::new());
+        for stmt in &program.statements {
+            conditions.visit_stmt(stmt);
+        }
+        conditions.0
+    }
+}
+
+struct Conditions(Vec<Diagnostic>);
+
+impl Visitor for Conditions {
+    fn visit_expr(&mut self, expr: &Expr) {
+        let condition = match &expr.kind {
+            ExprKind::If { condition, .. } => Some(condition),
+            ExprKind::While { condition, .. } if condition.kind != ExprKind::Bool(true) => {
+                Some(condition)
+            }
+            _ => None,
+        };
+        if let Some(condition) = condition {
+            let mut folded = (**condition).clone();
+            fold(&mut folded);
+            if let ExprKind::Bool(value) = folded.kind {
+                self.0.push(Diagnostic::warning(
+                    format!("this condition is always `{}`", value),
+                    condition.span,
+                ));
+            }
+        }
+        walk_expr(self, expr)
+    }
+}
+
+// Which local bindings are never read and which hide another, found by
+// walking the program with the scopes the interpreter would create.
+#[derive(Default)]
+struct Scopes {
+    // Innermost last. The first is the top level.
+    scopes: Vec<Vec<Binding>>,
+    unused: Vec<(Symbol, Span)>,
+    shadowed: Vec<(Symbol, Span)>,
+}
+
+struct Binding {
+    name: Symbol,
+    span: Span,
+    read: bool,
+    // Whether it is reported when it is never read: parameters and
+    // top-level bindings are not.
+    checked: bool,
+}
+
+impl Scopes {
+    fn resolve(program: &Program) -> Scopes {
+        let mut scopes = Scopes::default();
+        scopes.scopes.push(Vec::new());
+        for stmt in &program.statements {
+            scopes.visit_stmt(stmt);
+        }
+        scopes.unused.sort_by_key(|(_, span)| span.start.char);
+        scopes
+    }
+
+    fn scoped(&mut self, visit: impl FnOnce(&mut Scopes)) {
+        self.scopes.push(Vec::new());
+        visit(self);
+        let scope = self.scopes.pop().expect("pushed above");
+        for binding in scope {
+            if binding.checked && !binding.read && !binding.name.starts_with('_') {
+                self.unused.push((binding.name, binding.span));
+            }
+        }
+    }
+
+    fn bind(&mut self, name: Symbol, span: Span, checked: bool) {
+        let enclosing = &self.scopes[..self.scopes.len() - 1];
+        let hides = enclosing
+            .iter()
+            .flatten()
+            .any(|binding| binding.name == name);
+        if hides && !name.starts_with('_') {
+            self.shadowed.push((name, span));
+        }
+        let checked = checked && self.scopes.len() > 1;
+        self.scopes.last_mut().expect("never empty").push(Binding {
+            name,
+            span,
+            read: false,
+            checked,
+        });
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern) {
+        let mut bindings = Bindings(Vec::new());
+        bindings.visit_pattern(pattern);
+        for (name, span) in bindings.0 {
+            self.bind(name, span, true);
+        }
+    }
+
+    fn visit_statements(&mut self, block: &Block) {
+        for stmt in &block.statements {
+            self.visit_stmt(stmt);
+        }
+        if let Some(value) = &block.value {
+            self.visit_expr(value);
+        }
+    }
+
+    fn lookup(&mut self, name: Symbol) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|binding| binding.name == name)
+    }
+
+    fn read(&mut self, name: Symbol) {
+        if let Some(binding) = self.lookup(name) {
+            binding.read = true;
+        }
+    }
+}
+
+impl Visitor for Scopes {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } => {
+                self.visit_expr(value);
+                self.bind(*name, stmt.span, true);
+            }
+            StmtKind::Destructure { pattern, value, .. } => {
+                self.visit_expr(value);
+                self.bind_pattern(pattern);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.scoped(|scopes| scopes.visit_statements(block));
+    }
+
+    // Parameters are in the same scope as the body, so `let n = n + 1`
+    // replaces the parameter `n` rather than hiding it.
+    fn visit_function(&mut self, function: &Function) {
+        self.scoped(|scopes| {
+            for param in function.params.iter().filter(|param| param.name != "self") {
+                scopes.bind(param.name, param.span, false);
+            }
+            scopes.visit_statements(&function.body);
+        });
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name) => self.read(*name),
+            // Writing a variable doesn't read it, and writing one that
+            // isn't bound yet binds it.
+            ExprKind::Assign { target, value } => {
+                self.visit_expr(value);
+                match &target.kind {
+                    ExprKind::Ident(name) => {
+                        if self.lookup(*name).is_none() {
+                            self.bind(*name, target.span, false);
+                        }
+                    }
+                    _ => self.visit_expr(target),
+                }
+            }
+            // `x.f()` calls a variable `f` when `x` has no method `f`.
+            ExprKind::MethodCall { method, .. } => {
+                self.read(*method);
+                walk_expr(self, expr);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.visit_expr(scrutinee);
+                for arm in arms {
+                    self.scoped(|scopes| {
+                        scopes.bind_pattern(&arm.pattern);
+                        if let Some(guard) = &arm.guard {
+                            scopes.visit_expr(guard);
+                        }
+                        scopes.visit_expr(&arm.body);
+                    });
+                }
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.visit_expr(iterable);
+                self.scoped(|scopes| {
+                    scopes.bind_pattern(pattern);
+                    scopes.visit_block(body);
+                });
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+// The names a pattern binds.
+struct Bindings(Vec<(Symbol, Span)>);
+
+impl Visitor for Bindings {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Binding(name) = &pattern.kind {
+            self.0.push((*name, pattern.span));
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::lint::Rule;
+    use crate::lint::rules::{
+        ConstantCondition, ShadowedBinding, UnreachableMatchArm, UnusedImport, UnusedVariable,
+    };
+    use crate::parser::parser::parse;
+
+    fn check(rule: impl Rule, source: &str) -> Vec<(String, usize)> {
+        let program = parse(source).unwrap();
+        rule.check(&program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.message, diagnostic.span.start.line))
+            .collect()
+    }
+
+    #[test]
+    fn finds_unused_variables() {
+        let source = "let top = 1;\n\
+                      fn f(unused_param) {\n\
+                      let a = 1;\n\
+                      let b = 2;\n\
+                      let _c = 3;\n\
+                      let (d, e) = (b, 4);\n\
+                      let g = fn(x) { x };\n\
+                      [1].map(g);\n\
+                      let h = 1; let h = h + 1;\n\
+                      match d { Option::Some(m) => 0, n => n }\n\
+                      }";
+        assert_eq!(
+            check(UnusedVariable, source),
+            vec![
+                (
+                    "unused variable `a`, consider naming it `_a` if that is intended".to_string(),
+                    3
+                ),
+                (
+                    "unused variable `e`, consider naming it `_e` if that is intended".to_string(),
+                    6
+                ),
+                (
+                    "unused variable `h`, consider naming it `_h` if that is intended".to_string(),
+                    9
+                ),
+                (
+                    "unused variable `m`, consider naming it `_m` if that is intended".to_string(),
+                    10
+                ),
+            ]
+        );
+        // Assigning doesn't read, but `x += 1` does, and so does calling a
+        // variable through method syntax.
+        let source = "fn f(list) { let mut x = 0; x = 1; let mut y = 0; y += 1; let g = fn(l) { l }; list.g() }";
+        let unused: Vec<_> = check(UnusedVariable, source)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        assert_eq!(
+            unused,
+            vec!["unused variable `x`, consider naming it `_x` if that is intended"]
+        );
+
+        // Only names that can be renamed in place get a suggested fix.
+        let program = parse("fn f() { let a = 1; let (b, c) = (1, 2); c }").unwrap();
+        let fixes: Vec<_> = UnusedVariable
+            .check(&program)
+            .iter()
+            .map(|diagnostic| diagnostic.suggestions().to_vec())
+            .collect();
+        assert_eq!(fixes[0], []);
+        assert_eq!(fixes[1][0].replacement, "_b");
+        assert_eq!(fixes[1][0].span.start.column, 25);
+    }
+
+    #[test]
+    fn finds_shadowed_bindings() {
+        let source = "let limit = 10;\n\
+                      fn f(n) {\n\
+                      let n = n + 1;\n\
+                      let total = 0;\n\
+                      for total in 0..n { total }\n\
+                      if n > 0 { let limit = 5; limit }\n\
+                      let total = total + 1;\n\
+                      let _n = fn(_n) { _n };\n\
+                      total\n\
+                      }";
+        assert_eq!(
+            check(ShadowedBinding, source),
+            vec![
+                (
+                    "`total` shadows a binding from an enclosing scope".to_string(),
+                    5
+                ),
+                (
+                    "`limit` shadows a binding from an enclosing scope".to_string(),
+                    6
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_unused_imports() {
+        let source = "import std::math;\nimport std::io;\nimport \"lib/shapes.clay\";\n\
+                      import std::list;\n\
+                      math::pi; match s { shapes::Shape::Dot => list };";
+        assert_eq!(
+            check(UnusedImport, source),
+            vec![("unused import `std::io`".to_string(), 2)]
+        );
+
+        let source =
+            "import std::path as p;\nimport { join, parent as up } from std::path;\nup(p::x)";
+        assert_eq!(
+            check(UnusedImport, source),
+            vec![("unused import `join`".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn finds_unreachable_match_arms() {
+        let source = "match x {\n_ => 1,\n2 => 2,\n}";
+        assert_eq!(
+            check(UnreachableMatchArm, source),
+            vec![("unreachable match arm".to_string(), 3)]
+        );
+        // A match that misses values is left to the exhaustiveness check.
+        assert!(check(UnreachableMatchArm, "match x { 1 => 1 }").is_empty());
+    }
+
+    #[test]
+    fn finds_constant_conditions() {
+        let source = "if true { 1 }\n\
+                      if 1 + 1 > 3 { 2 } else if x { 3 }\n\
+                      while true { break }\n\
+                      while !true { 4 }\n\
+                      while x < 2 { x += 1 }";
+        assert_eq!(
+            check(ConstantCondition, source),
+            vec![
+                ("this condition is always `true`".to_string(), 1),
+                ("this condition is always `false`".to_string(), 2),
+                ("this condition is always `false`".to_string(), 4),
+            ]
+        );
+    }
+}
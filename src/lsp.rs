@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value as Json};
+
+use clay::analysis::slice::backward_slice;
+use clay::analysis::symbols::{definition, symbols, Symbol, SymbolKind};
+use clay::diagnostic::diagnostic::{Diagnostic, Severity};
+use clay::highlight::highlight::{highlight, semantic_tokens, LEGEND};
+use clay::lexer::token::{Position, Span};
+use clay::parser::ast::{Program, StmtKind};
+use clay::parser::parser::parse;
+
+use crate::connection::Connection;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+const PARSE_ERROR: i64 = -32700;
+
+// Documents are always sent whole.
+const FULL_SYNC: i64 = 1;
+
+// Serves the Language Server Protocol over stdin and stdout. Every change
+// re-parses the document and publishes its lexer and parser errors; other
+// requests parse the latest text they were sent.
+pub fn start() -> i32 {
+    serve(Client::new(Connection::stdio()))
+}
+
+struct Client {
+    connection: Connection,
+}
+
+impl Client {
+    fn new(connection: Connection) -> Client {
+        Client { connection }
+    }
+
+    // Reads the next message, answering any that can't be read with a
+    // parse error, or `None` once the client has gone away.
+    fn read(&mut self) -> Option<Json> {
+        loop {
+            match self.connection.read()? {
+                Ok(message) => return Some(message),
+                Err(message) => self.send(json!({
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": message },
+                })),
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Json) {
+        message["jsonrpc"] = json!("2.0");
+        self.connection.write(&message);
+    }
+
+    fn respond(&mut self, request: &Json, result: Json) {
+        self.send(json!({ "id": request["id"], "result": result }));
+    }
+
+    fn fail(&mut self, request: &Json, code: i64, message: String) {
+        self.send(json!({
+            "id": request["id"],
+            "error": { "code": code, "message": message },
+        }));
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(json!({ "method": method, "params": params }));
+    }
+}
+
+// Returns the exit code: 0 when the client shut the server down before
+// telling it to exit, as the protocol asks, and 1 otherwise.
+fn serve(mut client: Client) -> i32 {
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut shut_down = false;
+
+    while let Some(message) = client.read() {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let is_request = message.get("id").is_some();
+
+        if shut_down && is_request {
+            client.fail(
+                &message,
+                INVALID_REQUEST,
+                "the server is shutting down".to_string(),
+            );
+            continue;
+        }
+
+        match method {
+            "initialize" => client.respond(
+                &message,
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": FULL_SYNC,
+                        "definitionProvider": true,
+                        "documentSymbolProvider": true,
+                        "codeLensProvider": {},
+                        "semanticTokensProvider": {
+                            "legend": { "tokenTypes": LEGEND, "tokenModifiers": [] },
+                            "full": true,
+                        },
+                    },
+                    "serverInfo": { "name": "clay", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            ),
+            "shutdown" => {
+                shut_down = true;
+                client.respond(&message, Json::Null);
+            }
+            "exit" => return if shut_down { 0 } else { 1 },
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                documents.insert(uri.clone(), text.to_string());
+                publish(&mut client, &uri, text);
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    documents.insert(uri.clone(), text.to_string());
+                    publish(&mut client, &uri, text);
+                }
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                client.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+            }
+            "textDocument/definition" => {
+                let source = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let (line, column) = from_lsp(source, &params["position"]);
+                let location = parse(source)
+                    .ok()
+                    .and_then(|program| definition(&program, source, line, column))
+                    .map(|span| json!({ "uri": uri, "range": range(source, span) }));
+                client.respond(&message, location.unwrap_or(Json::Null));
+            }
+            "textDocument/documentSymbol" => {
+                let source = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let outline = match parse(source) {
+                    Ok(program) => symbols(&program, source)
+                        .iter()
+                        .map(|symbol| document_symbol(source, symbol))
+                        .collect(),
+                    Err(_) => Vec::new(),
+                };
+                client.respond(&message, json!(outline));
+            }
+            "textDocument/codeLens" => {
+                let source = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let lenses = match parse(source) {
+                    Ok(program) => slice_lenses(&program, source, &uri),
+                    Err(_) => Vec::new(),
+                };
+                client.respond(&message, json!(lenses));
+            }
+            "textDocument/semanticTokens/full" => {
+                let source = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let data = semantic_tokens(source, &highlight(source));
+                client.respond(&message, json!({ "data": data }));
+            }
+            _ if is_request => client.fail(
+                &message,
+                METHOD_NOT_FOUND,
+                format!("`{}` is not supported", method),
+            ),
+            // Notifications that need no answer, like `initialized`.
+            _ => {}
+        }
+    }
+
+    if shut_down {
+        0
+    } else {
+        1
+    }
+}
+
+fn publish(client: &mut Client, uri: &str, source: &str) {
+    let diagnostics: Vec<Json> = match parse(source) {
+        Ok(_) => Vec::new(),
+        Err(diagnostic) => vec![lsp_diagnostic(source, &diagnostic)],
+    };
+    client.notify(
+        "textDocument/publishDiagnostics",
+        json!({ "uri": uri, "diagnostics": diagnostics }),
+    );
+}
+
+fn lsp_diagnostic(source: &str, diagnostic: &Diagnostic) -> Json {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    json!({
+        "range": range(source, diagnostic.span),
+        "severity": severity,
+        "source": "clay",
+        "message": diagnostic.message,
+    })
+}
+
+fn document_symbol(source: &str, symbol: &Symbol) -> Json {
+    // The protocol's `SymbolKind` numbers.
+    let kind = match symbol.kind {
+        SymbolKind::Import => 2,
+        SymbolKind::Function => 12,
+        SymbolKind::Field => 8,
+        SymbolKind::Variable | SymbolKind::Parameter => 13,
+        SymbolKind::Enum => 10,
+        SymbolKind::Trait => 11,
+        SymbolKind::Struct => 23,
+        SymbolKind::Variant => 22,
+    };
+    let children: Vec<Json> = symbol
+        .children
+        .iter()
+        .map(|child| document_symbol(source, child))
+        .collect();
+    json!({
+        "name": symbol.name,
+        "kind": kind,
+        "range": range(source, symbol.span),
+        "selectionRange": range(source, symbol.name_span),
+        "children": children,
+    })
+}
+
+// Puts the backward slice of each top-level `let` above it, as the lines of
+// the statements its value depends on. The title carries the whole answer;
+// the command names the same slice for editors that want to show it.
+fn slice_lenses(program: &Program, source: &str, uri: &str) -> Vec<Json> {
+    program
+        .statements
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Let { name, .. } => Some((stmt, name.as_str())),
+            _ => None,
+        })
+        .map(|(stmt, name)| {
+            let line = stmt.span.end.line;
+            let lines: Vec<String> = backward_slice(program, name, line)
+                .iter()
+                .filter(|dependency| !std::ptr::eq(**dependency, stmt))
+                .map(|dependency| dependency.span.start.line.to_string())
+                .collect();
+            let title = match lines.len() {
+                0 => format!("`{}` depends on no other lines", name),
+                1 => format!("`{}` depends on line {}", name, lines[0]),
+                _ => format!("`{}` depends on lines {}", name, lines.join(", ")),
+            };
+            json!({
+                "range": range(source, stmt.span),
+                "command": {
+                    "title": title,
+                    "command": "clay.slice",
+                    "arguments": [uri, name, line],
+                },
+            })
+        })
+        .collect()
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units.
+fn range(source: &str, span: Span) -> Json {
+    json!({ "start": to_lsp(source, span.start), "end": to_lsp(source, span.end) })
+}
+
+fn to_lsp(source: &str, position: Position) -> Json {
+    let before = &source[..position.char];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    json!({
+        "line": position.line - 1,
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+// Turns an LSP position into a line counted from 1 and a column in chars.
+fn from_lsp(source: &str, position: &Json) -> (usize, usize) {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let text = source.lines().nth(line).unwrap_or_default();
+
+    let mut units = 0;
+    let column = text
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    (line + 1, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    use serde_json::{json, Value as Json};
+
+    use crate::connection::Connection;
+    use crate::lsp::{serve, Client};
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(messages: &[Json]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for message in messages {
+            let body = message.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn publishes_diagnostics_and_answers_queries() {
+        let uri = "file:///main.clay";
+        let document = json!({ "uri": uri });
+        let mut input = frame(&[json!({ "id": 1, "method": "initialize", "params": {} })]);
+        // A message that isn't JSON gets an error, and the rest still count.
+        input.extend_from_slice(b"Content-Length: 6\r\n\r\n{oops}");
+        input.extend(frame(&[
+            json!({ "method": "initialized", "params": {} }),
+            json!({ "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": uri, "text": "let s = \"😀\" + ;" } } }),
+            json!({ "method": "textDocument/didChange", "params": {
+                "textDocument": document,
+                "contentChanges": [{ "text": "fn double(n) {\n    n * 2\n}\ndouble(1)" }] } }),
+            json!({ "id": 2, "method": "textDocument/definition", "params": {
+                "textDocument": document, "position": { "line": 3, "character": 2 } } }),
+            json!({ "id": 3, "method": "textDocument/documentSymbol", "params": {
+                "textDocument": document } }),
+            json!({ "id": 4, "method": "textDocument/hover", "params": {} }),
+            json!({ "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": "file:///slice.clay",
+                    "text": "let a = 1;\nlet b = 2;\nlet c = a * 3;" } } }),
+            json!({ "id": 7, "method": "textDocument/codeLens", "params": {
+                "textDocument": { "uri": "file:///slice.clay" } } }),
+            json!({ "id": 6, "method": "textDocument/semanticTokens/full", "params": {
+                "textDocument": document } }),
+            json!({ "id": 5, "method": "shutdown" }),
+            json!({ "method": "exit" }),
+        ]));
+        let output = Output::default();
+        let connection = Connection::new(Box::new(Cursor::new(input)), Box::new(output.clone()));
+        assert_eq!(serve(Client::new(connection)), 0);
+
+        let mut reader = Connection::new(
+            Box::new(Cursor::new(output.0.borrow().clone())),
+            Box::new(io::sink()),
+        );
+        let mut messages = Vec::new();
+        while let Some(message) = reader.read() {
+            messages.push(message.unwrap());
+        }
+        let response = |id: i64| {
+            messages
+                .iter()
+                .find(|message| message["id"] == id)
+                .unwrap_or_else(|| panic!("no response to {}", id))
+        };
+
+        assert_eq!(
+            response(1)["result"]["capabilities"]["definitionProvider"],
+            true
+        );
+        assert_eq!(messages[1]["id"], Json::Null);
+        assert_eq!(messages[1]["error"]["code"], -32700);
+        let published: Vec<&Json> = messages
+            .iter()
+            .filter(|message| message["method"] == "textDocument/publishDiagnostics")
+            .collect();
+        assert_eq!(
+            published[0]["params"]["diagnostics"][0],
+            json!({
+                "range": {
+                    "start": { "line": 0, "character": 15 },
+                    "end": { "line": 0, "character": 16 },
+                },
+                "severity": 1,
+                "source": "clay",
+                "message": "expected expression, found `;`",
+            })
+        );
+        assert_eq!(published[1]["params"]["diagnostics"], json!([]));
+
+        assert_eq!(
+            response(2)["result"]["range"],
+            json!({
+                "start": { "line": 0, "character": 3 },
+                "end": { "line": 0, "character": 9 },
+            })
+        );
+        let symbol = &response(3)["result"][0];
+        assert_eq!(symbol["name"], "double");
+        assert_eq!(symbol["kind"], 12);
+        assert_eq!(symbol["range"]["end"], json!({ "line": 2, "character": 1 }));
+        assert_eq!(symbol["children"][0]["name"], "n");
+        assert_eq!(response(4)["error"]["code"], -32601);
+        // `fn` and `double`.
+        let data = response(6)["result"]["data"].as_array().unwrap();
+        assert_eq!(
+            data[..10],
+            json!([0, 0, 2, 0, 0, 0, 3, 6, 5, 0]).as_array().unwrap()[..]
+        );
+        let lenses = response(7)["result"].as_array().unwrap();
+        assert_eq!(lenses.len(), 3);
+        assert_eq!(
+            lenses[0]["command"]["title"],
+            "`a` depends on no other lines"
+        );
+        assert_eq!(lenses[2]["command"]["title"], "`c` depends on line 1");
+        assert_eq!(
+            lenses[2]["command"]["arguments"],
+            json!(["file:///slice.clay", "c", 3])
+        );
+        assert_eq!(
+            lenses[2]["range"]["start"],
+            json!({ "line": 2, "character": 0 })
+        );
+        assert_eq!(response(5)["result"], Json::Null);
+    }
+}
@@ -0,0 +1,986 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde::Serialize;
+
+use clay::analysis::docs;
+use clay::analysis::slice::backward_slice;
+use clay::codegen::wasm;
+use clay::diagnostic::diagnostic::{Diagnostic, Severity};
+use clay::diagnostic::json::render_json;
+use clay::diagnostic::render::{render, render_in};
+use clay::diagnostic::source_map::SourceMap;
+use clay::formatter::formatter::format;
+use clay::interpreter::heap::{diff, HeapSnapshot};
+use clay::interpreter::interpreter::Interpreter;
+use clay::interpreter::module::{ImportMap, ModuleLoader};
+use clay::interpreter::profile::Profile;
+use clay::interpreter::stack;
+use clay::interpreter::value::Value;
+use clay::lexer::lexer::Lexer;
+use clay::lint::lint::{Level, Linter};
+use clay::optimize::optimize::OptimizePass;
+use clay::parser::binary::encode;
+use clay::parser::cache::{ParseCache, CACHE_DIR};
+use clay::parser::parser::parse;
+use clay::pipeline::pipeline::Pipeline;
+use clay::project::dependencies;
+use clay::project::manifest::{Manifest, MANIFEST};
+use clay::typecheck::typecheck::TypeCheckPass;
+use clay::vm::vm::Vm;
+
+use crate::scaffold::Template;
+
+mod connection;
+mod dap;
+mod debugger;
+mod lsp;
+mod repl;
+mod scaffold;
+mod stats;
+mod testing;
+mod watch;
+
+const USAGE: &str = "usage: clay <command> [options] [file] [args]
+       clay [options] <file> [args]
+
+commands:
+    lex        print the tokens in a file
+    parse      print the syntax tree of a file
+    run        run a file, passing it the arguments after the file; the
+               program exits with the code it calls `exit` with or
+               returns from `main`. A file given without a command is
+               run, so scripts starting with `#!/usr/bin/env clay` can
+               be made executable. Without a file, run, check and build
+               use the `entry` of the project's clay.toml, as does debug
+    check      report the errors in a file and the modules it imports,
+               types included, without running it
+    test       run the test blocks in a file, or in the files under a
+               directory (default: the current directory)
+    build      compile a file: build --target wasm32 <file> -o <out.wasm>
+    fmt        format a file in place
+    lint       report likely mistakes in a file, like unused variables;
+               rules are set to allow, warn or deny in the `[lints]`
+               section of clay.toml, or with `@!deny(rule)` for a file and
+               `@allow(rule)` on a declaration
+    doc        print the documentation of a file's declarations, taken
+               from their `///` comments, as Markdown or HTML
+    slice      print the statements that can affect a variable: slice <file> <name>:<line>
+    heap-diff  compare two heap snapshots: heap-diff <old> <new>
+    repl       start an interactive session; tab completes names, and
+               history is kept in ~/.clay_history
+    debug      run a file under a debugger that pauses before its first
+               statement and reads commands like `break 12`, `step`,
+               `locals` and `print x` from stdin; `help` lists them
+    dap        serve the Debug Adapter Protocol on stdin and stdout, for
+               debugging from an editor
+    lsp        serve the Language Server Protocol on stdin and stdout
+    new        create a project in a new directory: new <name>
+    init       create a project in the current directory
+
+options:
+    --format <text|json|binary|html>
+                            output format for lex, parse, lint and doc
+                            (default: text, which doc prints as Markdown);
+                            binary is only supported by parse and html only
+                            by doc
+    --error-format <human|json>
+                            how errors and warnings are written to stderr:
+                            rendered under the source line, or as one JSON
+                            object per line with the code, severity,
+                            message, file, range, notes and suggested fixes
+                            (default: human)
+    --plugin <path>         load compiler passes from a plugin library
+    --heap-snapshot <path>  write a heap snapshot after run finishes
+    --profile               make run print to stderr, once the program ends,
+                            how often each function was called and how long
+                            its calls took, and with the vm backend how often
+                            each instruction ran
+    --profile-output <path> make run write the time spent in each stack of
+                            calls to a file, in the folded format flame graph
+                            tools like flamegraph.pl and inferno read
+    --backend <tree|vm>     how run executes a file: walking the syntax tree
+                            or compiling it to bytecode (default: tree)
+    --typecheck             check types before running (experimental)
+    --optimize              fold constants and remove dead code before running
+    --target <wasm32>       what build compiles to (default: wasm32)
+    -o, --output <path>     where build writes its output (default: the
+                            file's name with the target's extension)
+    --no-cache              make run and check parse every file again
+                            instead of reusing the syntax trees a project
+                            keeps in .clay/cache
+    --frozen                make run and check fail when a cached syntax
+                            tree was written by another version of clay or
+                            with other options, instead of parsing the file
+                            again
+    --watch                 run check, run or test again whenever a clay
+                            file or manifest in the project changes
+    --check                 make fmt report whether a file is formatted
+                            instead of rewriting it
+    --filter <text>         make test run only the tests whose names
+                            contain the text
+    --template <script|library|playground>
+                            what new and init create; asked for when
+                            omitted and stdin is a terminal (default: script)";
+
+// The commands USAGE lists. Anything else given in their place is taken
+// for a file to run.
+const COMMANDS: [&str; 17] = [
+    "lex",
+    "parse",
+    "run",
+    "check",
+    "test",
+    "build",
+    "fmt",
+    "lint",
+    "doc",
+    "slice",
+    "heap-diff",
+    "repl",
+    "debug",
+    "dap",
+    "lsp",
+    "new",
+    "init",
+];
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+// The stack clay runs on, which bounds how deeply a program can recurse
+// before failing with a stack overflow.
+const STACK_SIZE: usize = 256 << 20;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(stack::grow(STACK_SIZE, || run(&args)));
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Binary,
+    Html,
+}
+
+// How diagnostics are written to stderr: rendered against the source, or
+// as a line of JSON each.
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Wasm32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Backend {
+    Tree,
+    Vm,
+}
+
+struct Options {
+    format: Format,
+    error_format: ErrorFormat,
+    plugins: Vec<String>,
+    heap_snapshot: Option<String>,
+    // Whether run prints a profile of the program, and where it writes the
+    // profile's stacks of calls.
+    profile: bool,
+    profile_output: Option<String>,
+    backend: Backend,
+    target: Target,
+    output: Option<String>,
+    typecheck: bool,
+    optimize: bool,
+    check: bool,
+    filter: Option<String>,
+    template: Option<Template>,
+    // Whether run and check keep what a project's files parse to in its
+    // cache, and whether a stale cache is an error.
+    cache: bool,
+    frozen: bool,
+    // What `args()` returns in the program `run` runs.
+    args: Vec<String>,
+}
+
+// Renders diagnostics as soon as they are produced and remembers whether any
+// of them was an error, which decides the exit code.
+struct Reporter<'a> {
+    path: &'a str,
+    format: ErrorFormat,
+    // The file being compiled and the modules the program being run has
+    // read, so errors in them are shown against the text that was parsed
+    // rather than the file as it is when the error is reported.
+    sources: SourceMap,
+    failed: bool,
+}
+
+impl<'a> Reporter<'a> {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        if diagnostic.severity == Severity::Error {
+            self.failed = true;
+        }
+        // Errors can name a file no module was read from, such as the file
+        // being compiled by another name.
+        if let Some(file) = &diagnostic.file {
+            if self.sources.file(file).is_none() {
+                self.sources
+                    .insert(file.as_str(), fs::read_to_string(file).unwrap_or_default());
+            }
+        }
+        if self.format == ErrorFormat::Json {
+            return eprintln!("{}", render_json(diagnostic, self.path));
+        }
+        eprint!("{}", render_in(diagnostic, &self.sources, self.path));
+    }
+}
+
+// Renders against the file a diagnostic names, falling back to the source
+// being compiled. Calls traced without a file are in the source being
+// compiled.
+fn render_diagnostic(diagnostic: &Diagnostic, path: &str, source: &str) -> String {
+    match &diagnostic.file {
+        Some(file) if file != path => {
+            let source = fs::read_to_string(file).unwrap_or_default();
+            render(&diagnostic.clone().in_file(path), file, &source)
+        }
+        _ => render(diagnostic, path, source),
+    }
+}
+
+fn run(args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut options = Options {
+        format: Format::Text,
+        error_format: ErrorFormat::Human,
+        plugins: Vec::new(),
+        heap_snapshot: None,
+        profile: false,
+        profile_output: None,
+        backend: Backend::Tree,
+        target: Target::Wasm32,
+        output: None,
+        typecheck: false,
+        optimize: false,
+        check: false,
+        filter: None,
+        template: None,
+        cache: true,
+        frozen: false,
+        args: Vec::new(),
+    };
+
+    // Where `--watch` is, so the command can be run again without it.
+    let mut watch = None;
+    let given = args;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // Everything after the file `run` runs is the program's, flags
+        // included.
+        if let ["run", _] = positional[..] {
+            options.args = iter::once(arg).chain(args).cloned().collect();
+            break;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            "--error-format" => match args.next().map(String::as_str) {
+                Some("human") => options.error_format = ErrorFormat::Human,
+                Some("json") => options.error_format = ErrorFormat::Json,
+                Some(other) => {
+                    eprintln!(
+                        "error: unknown error format `{}`, expected `human` or `json`",
+                        other
+                    );
+                    return EXIT_USAGE;
+                }
+                None => {
+                    eprintln!("error: `--error-format` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--format" => match args.next().map(String::as_str) {
+                Some("text") => options.format = Format::Text,
+                Some("json") => options.format = Format::Json,
+                Some("binary") => options.format = Format::Binary,
+                Some("html") => options.format = Format::Html,
+                Some(other) => {
+                    eprintln!(
+                        "error: unknown format `{}`, expected `text`, `json`, `binary` or `html`",
+                        other
+                    );
+                    return EXIT_USAGE;
+                }
+                None => {
+                    eprintln!("error: `--format` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--plugin" => match args.next() {
+                Some(path) => options.plugins.push(path.clone()),
+                None => {
+                    eprintln!("error: `--plugin` needs a path\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--heap-snapshot" => match args.next() {
+                Some(path) => options.heap_snapshot = Some(path.clone()),
+                None => {
+                    eprintln!("error: `--heap-snapshot` needs a path\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--profile-output" => match args.next() {
+                Some(path) => options.profile_output = Some(path.clone()),
+                None => {
+                    eprintln!("error: `--profile-output` needs a path\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--backend" => match args.next().map(String::as_str) {
+                Some("tree") => options.backend = Backend::Tree,
+                Some("vm") => options.backend = Backend::Vm,
+                Some(other) => {
+                    eprintln!(
+                        "error: unknown backend `{}`, expected `tree` or `vm`",
+                        other
+                    );
+                    return EXIT_USAGE;
+                }
+                None => {
+                    eprintln!("error: `--backend` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--template" => match args.next() {
+                Some(name) => match Template::parse(name) {
+                    Some(template) => options.template = Some(template),
+                    None => {
+                        eprintln!(
+                            "error: unknown template `{}`, expected `script`, `library` or `playground`",
+                            name
+                        );
+                        return EXIT_USAGE;
+                    }
+                },
+                None => {
+                    eprintln!("error: `--template` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--target" => match args.next().map(String::as_str) {
+                Some("wasm32") => options.target = Target::Wasm32,
+                Some(other) => {
+                    eprintln!("error: unknown target `{}`, expected `wasm32`", other);
+                    return EXIT_USAGE;
+                }
+                None => {
+                    eprintln!("error: `--target` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "-o" | "--output" => match args.next() {
+                Some(path) => options.output = Some(path.clone()),
+                None => {
+                    eprintln!("error: `{}` needs a path\n\n{}", arg, USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--filter" => match args.next() {
+                Some(filter) => options.filter = Some(filter.clone()),
+                None => {
+                    eprintln!("error: `--filter` needs a value\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--profile" => options.profile = true,
+            "--typecheck" => options.typecheck = true,
+            "--optimize" => options.optimize = true,
+            "--check" => options.check = true,
+            "--no-cache" => options.cache = false,
+            "--frozen" => options.frozen = true,
+            "--watch" => watch = Some(given.len() - args.as_slice().len() - 1),
+            flag if flag.starts_with("--") => {
+                eprintln!("error: unknown option `{}`\n\n{}", flag, USAGE);
+                return EXIT_USAGE;
+            }
+            // `clay <file>` runs the file, so scripts starting with
+            // `#!/usr/bin/env clay` can be run directly.
+            path if positional.is_empty()
+                && !COMMANDS.contains(&path)
+                && Path::new(path).is_file() =>
+            {
+                positional.extend(["run", path])
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    if let Some(index) = watch {
+        let path = match positional[..] {
+            ["check" | "run" | "test"] => ".",
+            ["check" | "run" | "test", path] => path,
+            _ => {
+                eprintln!(
+                    "error: `--watch` works with check, run and test\n\n{}",
+                    USAGE
+                );
+                return EXIT_USAGE;
+            }
+        };
+        let mut args = given.to_vec();
+        args.remove(index);
+        return watch::start(&args, Path::new(path));
+    }
+
+    let mut pipeline = match pipeline_for(positional.first().copied().unwrap_or_default(), &options)
+    {
+        Ok(pipeline) => pipeline,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let entry;
+    let (command, path, argument) = match positional[..] {
+        ["new", name] => return scaffold::new(name, options.template),
+        ["init"] => return scaffold::init(options.template),
+        ["test"] => return testing::run(Path::new("."), &options),
+        ["test", path] => return testing::run(Path::new(path), &options),
+        [command, path] => (command, path, None),
+        [command @ ("run" | "check" | "build" | "debug")] => {
+            entry = match project_entry() {
+                Ok(entry) => entry,
+                Err(message) => {
+                    eprintln!("error: {}", message);
+                    return EXIT_FAILURE;
+                }
+            };
+            (command, entry.as_str(), None)
+        }
+        ["slice", path, target] => ("slice", path, Some(target)),
+        ["repl"] => return repl::start(pipeline),
+        ["dap"] => return dap::start(pipeline),
+        ["lsp"] => return lsp::start(),
+        ["heap-diff", old, new] => return heap_diff(Path::new(old), Path::new(new)),
+        _ => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: could not read `{}`: {}", path, err);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let mut reporter = Reporter {
+        path,
+        format: options.error_format,
+        sources: SourceMap::new(),
+        failed: false,
+    };
+    reporter.sources.insert(path, source.as_str());
+
+    if matches!(command, "lex" | "doc" | "lint") && options.format == Format::Binary {
+        eprintln!("error: `{}` has no binary format\n\n{}", command, USAGE);
+        return EXIT_USAGE;
+    }
+    if command != "doc" && options.format == Format::Html {
+        eprintln!("error: only `doc` has an html format\n\n{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    if matches!(command, "run" | "check" | "debug") {
+        use_cache(&mut pipeline, Path::new(path), &options);
+    }
+
+    match command {
+        "lex" => lex(&source, &options, &mut reporter),
+        "parse" => parse_file(&source, &options, &mut reporter),
+        "run" => {
+            let code = run_file(&source, path, pipeline, &options, &mut reporter);
+            // A program that exits by itself picks the code, unless
+            // something after it, like saving a heap snapshot, failed.
+            if let (Some(code), false) = (code, reporter.failed) {
+                return code;
+            }
+        }
+        "check" => check_file(&source, path, pipeline, &mut reporter),
+        "debug" => return debugger::start(&source, path, pipeline, &mut reporter),
+        "build" => build(&source, path, pipeline, &options, &mut reporter),
+        "fmt" => format_file(&source, path, &options, &mut reporter),
+        "doc" => document(&source, path, &options, &mut reporter),
+        "lint" => lint(&source, path, &options, &mut reporter),
+        "slice" => {
+            let criterion = argument.and_then(|target| {
+                let (name, line) = target.rsplit_once(':')?;
+                Some((name, line.parse::<usize>().ok()?))
+            });
+            match criterion {
+                Some((name, line)) => slice(&source, name, line, &mut reporter),
+                None => {
+                    eprintln!("error: `slice` expects `<name>:<line>`\n\n{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            }
+        }
+        _ => {
+            eprintln!("error: unknown command `{}`\n\n{}", command, USAGE);
+            return EXIT_USAGE;
+        }
+    }
+
+    if reporter.failed {
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
+
+// The pipeline `command` compiles with, with the plugins and passes the
+// options ask for.
+fn pipeline_for(command: &str, options: &Options) -> Result<Pipeline, String> {
+    let mut pipeline = build_pipeline(options)?;
+    // Compiling needs types, so `build` always checks them, and so does
+    // `check`, which is for finding errors before running.
+    if options.typecheck || matches!(command, "build" | "check") {
+        pipeline.register(TypeCheckPass);
+    }
+    if options.optimize {
+        pipeline.register(OptimizePass);
+    }
+    Ok(pipeline)
+}
+
+#[cfg(feature = "dynamic-plugins")]
+fn build_pipeline(options: &Options) -> Result<Pipeline, String> {
+    let mut pipeline = Pipeline::new();
+    for plugin in &options.plugins {
+        pipeline.load_plugin(Path::new(plugin))?;
+    }
+    Ok(pipeline)
+}
+
+#[cfg(not(feature = "dynamic-plugins"))]
+fn build_pipeline(options: &Options) -> Result<Pipeline, String> {
+    if !options.plugins.is_empty() {
+        return Err("clay was built without the `dynamic-plugins` feature".to_string());
+    }
+    Ok(Pipeline::new())
+}
+
+fn lex(source: &str, options: &Options, reporter: &mut Reporter) {
+    let tokens = match Lexer::new(source).collect::<Result<Vec<_>, _>>() {
+        Ok(tokens) => tokens,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    match options.format {
+        Format::Text => {
+            for token in tokens {
+                println!(
+                    "{}:{}\t{:?}",
+                    token.span.start.line,
+                    token.span.start.column + 1,
+                    token.kind
+                );
+            }
+        }
+        Format::Json => print_json(&tokens),
+        Format::Binary | Format::Html => unreachable!("rejected before lexing"),
+    }
+}
+
+fn parse_file(source: &str, options: &Options, reporter: &mut Reporter) {
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    match options.format {
+        Format::Text => println!("{:#?}", program),
+        Format::Json => print_json(&program),
+        Format::Html => unreachable!("rejected before parsing"),
+        Format::Binary => {
+            let bytes = match encode(&program) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    reporter.failed = true;
+                    return;
+                }
+            };
+            if let Err(err) = io::stdout().write_all(&bytes) {
+                eprintln!("error: could not write syntax tree: {}", err);
+                reporter.failed = true;
+            }
+        }
+    }
+}
+
+// Reports what the lint rules find in the file, at the levels the project's
+// manifest sets them to. Denied rules fail the command.
+fn lint(source: &str, path: &str, options: &Options, reporter: &mut Reporter) {
+    let mut linter = Linter::new();
+    let manifest = match Manifest::find(Path::new(path)) {
+        Some(manifest) => Manifest::load(&manifest).map(Some),
+        None => Ok(None),
+    };
+    let levels = match manifest {
+        Ok(manifest) => manifest.map(|manifest| manifest.lints).unwrap_or_default(),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            reporter.failed = true;
+            return;
+        }
+    };
+    for (rule, level) in levels {
+        if let Err(message) = linter.set_level(&rule, level) {
+            eprintln!("error: {} in {}", message, MANIFEST);
+            reporter.failed = true;
+            return;
+        }
+    }
+
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    let lints = linter.lint(&program);
+    match options.format {
+        Format::Json => print_json(&lints),
+        _ => {
+            for lint in &lints {
+                reporter.report(&lint.diagnostic());
+            }
+        }
+    }
+    if lints.iter().any(|lint| lint.level == Level::Deny) {
+        reporter.failed = true;
+    }
+}
+
+// Prints the documentation of the file's declarations, under the name of
+// the module it is imported as.
+fn document(source: &str, path: &str, options: &Options, reporter: &mut Reporter) {
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    let module = Path::new(path)
+        .file_stem()
+        .map_or(path.into(), |stem| stem.to_string_lossy());
+    let items = docs::items(&program);
+    match options.format {
+        Format::Text => print!("{}", docs::markdown(&module, &items)),
+        Format::Json => print_json(&items),
+        Format::Html => print!("{}", docs::html(&module, &items)),
+        Format::Binary => unreachable!("rejected before parsing"),
+    }
+}
+
+// Rewrites the file formatted, or with `--check` only reports that it is not.
+fn format_file(source: &str, path: &str, options: &Options, reporter: &mut Reporter) {
+    let formatted = match format(source) {
+        Ok(formatted) => formatted,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    if formatted == source {
+        return;
+    }
+    if options.check {
+        eprintln!("error: `{}` is not formatted", path);
+        reporter.failed = true;
+    } else if let Err(err) = fs::write(path, formatted) {
+        eprintln!("error: could not write `{}`: {}", path, err);
+        reporter.failed = true;
+    }
+}
+
+// Runs the file, returning the code the program asked to exit with, if it
+// did.
+fn run_file(
+    source: &str,
+    path: &str,
+    pipeline: Pipeline,
+    options: &Options,
+    reporter: &mut Reporter,
+) -> Option<i32> {
+    if options.backend == Backend::Vm {
+        return run_bytecode(source, path, pipeline, options, reporter);
+    }
+
+    let mut interpreter = Interpreter::with_pipeline(pipeline);
+    interpreter.set_file(Path::new(path));
+    interpreter.set_sources(reporter.sources.clone());
+    interpreter.set_args(options.args.clone());
+    match import_map(Path::new(path)) {
+        Ok(imports) => interpreter.set_import_map(imports),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            reporter.failed = true;
+            return None;
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    let program =
+        interpreter
+            .pipeline_mut()
+            .process_file(Path::new(path), source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        reporter.report(diagnostic);
+    }
+    let program = program?;
+
+    interpreter.preload_imports(&program);
+    if profiling(options) {
+        interpreter.start_profile();
+    }
+    let result = interpreter.run_script(&program);
+    let profile = interpreter.take_profile();
+    finish(
+        result,
+        || interpreter.heap_snapshot(),
+        profile,
+        options,
+        reporter,
+    );
+    interpreter.exit_code()
+}
+
+// Reports everything the frontend and the passes find in the file and in the
+// modules it imports, without running any of them.
+fn check_file(source: &str, path: &str, pipeline: Pipeline, reporter: &mut Reporter) {
+    let root = Path::new(path);
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let mut loader = ModuleLoader::new(pipeline);
+    loader.set_root(&root);
+    loader.set_sources(reporter.sources.clone());
+    match import_map(&root) {
+        Ok(imports) => loader.set_import_map(imports),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            reporter.failed = true;
+            return;
+        }
+    }
+
+    let program = match loader.pipeline_mut().parse_file(&root, source) {
+        Ok(program) => program,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    let imported = loader.check(&program, Some(&root));
+    let mut diagnostics = Vec::new();
+    loader.pipeline_mut().run_passes(program, &mut diagnostics);
+    for diagnostic in diagnostics.iter().chain(&imported) {
+        reporter.report(diagnostic);
+    }
+}
+
+// Keeps the syntax trees of the files in the project `path` is in, if it is
+// in one, so the next run or check only parses the files that changed.
+fn use_cache(pipeline: &mut Pipeline, path: &Path, options: &Options) {
+    if !options.cache {
+        return;
+    }
+    if let Some(manifest) = Manifest::find(path) {
+        let dir = manifest.with_file_name(CACHE_DIR);
+        pipeline.set_cache(ParseCache::new(dir, options.frozen));
+    }
+}
+
+// The `entry` of the project the current directory is in, for commands
+// given no file.
+fn project_entry() -> Result<String, String> {
+    let manifest = match Manifest::find(Path::new(".")) {
+        Some(manifest) => Manifest::load(&manifest)?,
+        None => {
+            return Err(format!(
+                "no file given, and no {} in this directory or above it",
+                MANIFEST
+            ))
+        }
+    };
+    match &manifest.entry {
+        Some(entry) => Ok(manifest.dir.join(entry).to_string_lossy().into_owned()),
+        None => Err(format!("{} has no `entry` to run", MANIFEST)),
+    }
+}
+
+// The import map of the project `path` is in, if it is in one, with an
+// alias for each of its dependencies.
+fn import_map(path: &Path) -> Result<ImportMap, String> {
+    match Manifest::find(path) {
+        Some(manifest) => dependencies::import_map(&Manifest::load(&manifest)?),
+        None => Ok(ImportMap::default()),
+    }
+}
+
+fn run_bytecode(
+    source: &str,
+    path: &str,
+    mut pipeline: Pipeline,
+    options: &Options,
+    reporter: &mut Reporter,
+) -> Option<i32> {
+    let mut diagnostics = Vec::new();
+    let program = pipeline.process_file(Path::new(path), source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        reporter.report(diagnostic);
+    }
+    let program = program?;
+
+    let mut vm = Vm::new();
+    vm.set_args(options.args.clone());
+    if profiling(options) {
+        vm.start_profile();
+    }
+    let result = vm.run_script(&program);
+    let profile = vm.take_profile();
+    finish(result, || vm.heap_snapshot(), profile, options, reporter);
+    vm.exit_code()
+}
+
+fn build(
+    source: &str,
+    path: &str,
+    mut pipeline: Pipeline,
+    options: &Options,
+    reporter: &mut Reporter,
+) {
+    let mut diagnostics = Vec::new();
+    let program = pipeline.process(source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        reporter.report(diagnostic);
+    }
+    let program = match program {
+        Some(program) => program,
+        None => return,
+    };
+
+    let (output, extension) = match options.target {
+        Target::Wasm32 => (wasm::compile(&program), "wasm"),
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    let destination = match &options.output {
+        Some(destination) => PathBuf::from(destination),
+        None => Path::new(path).with_extension(extension),
+    };
+    if let Err(err) = fs::write(&destination, output) {
+        eprintln!(
+            "error: could not write `{}`: {}",
+            destination.display(),
+            err
+        );
+        reporter.failed = true;
+    }
+}
+
+// Whether the run is profiled, for a table or a file of folded stacks.
+fn profiling(options: &Options) -> bool {
+    options.profile || options.profile_output.is_some()
+}
+
+// Prints what a program evaluated to, then the profile and the heap
+// snapshot, if they were asked for.
+fn finish(
+    result: Result<Value, Diagnostic>,
+    snapshot: impl FnOnce() -> HeapSnapshot,
+    profile: Option<Profile>,
+    options: &Options,
+    reporter: &mut Reporter,
+) {
+    match result {
+        Ok(Value::Unit) => {}
+        Ok(value) => println!("{}", value),
+        Err(diagnostic) => reporter.report(&diagnostic),
+    }
+
+    if let Some(profile) = profile {
+        if options.profile {
+            eprint!("\n{}", profile.table());
+        }
+        if let Some(path) = &options.profile_output {
+            if let Err(err) = fs::write(path, profile.folded()) {
+                eprintln!("error: could not write `{}`: {}", path, err);
+                reporter.failed = true;
+            }
+        }
+    }
+
+    if let Some(path) = &options.heap_snapshot {
+        if let Err(message) = snapshot().save(Path::new(path)) {
+            eprintln!("error: {}", message);
+            reporter.failed = true;
+        }
+    }
+}
+
+fn heap_diff(old: &Path, new: &Path) -> i32 {
+    let (old, new) = match (HeapSnapshot::load(old), HeapSnapshot::load(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(message), _) | (_, Err(message)) => {
+            eprintln!("error: {}", message);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let changes = diff(&old, &new);
+    for object in &changes.added {
+        println!("+ {} {} ({} bytes)", object.kind, object.path, object.size);
+    }
+    for object in &changes.removed {
+        println!("- {} {} ({} bytes)", object.kind, object.path, object.size);
+    }
+    for (before, after) in &changes.resized {
+        println!(
+            "~ {} {} ({} -> {} bytes)",
+            after.kind, after.path, before.size, after.size
+        );
+    }
+    println!(
+        "total: {} objects, {} bytes -> {} objects, {} bytes",
+        old.objects.len(),
+        old.total_size(),
+        new.objects.len(),
+        new.total_size()
+    );
+    0
+}
+
+fn slice(source: &str, variable: &str, line: usize, reporter: &mut Reporter) {
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(diagnostic) => return reporter.report(&diagnostic),
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    for stmt in backward_slice(&program, variable, line) {
+        for number in stmt.span.start.line..=stmt.span.end.line {
+            println!("{:>4} | {}", number, lines[number - 1]);
+        }
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    let json = serde_json::to_string_pretty(value).expect("syntax trees always serialize");
+    println!("{}", json);
+}
@@ -0,0 +1,291 @@
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::interpreter::{binary, match_pattern, unary};
+use crate::interpreter::value::Value;
+use crate::lexer::token::Span;
+use crate::parser::ast::{
+    BinaryOp, Block, Expr, ExprKind, MatchArm, PatternKind, Program, Stmt, StmtKind,
+};
+use crate::parser::visit::{walk_expr_mut, VisitorMut};
+use crate::pipeline::pass::{Pass, Stage};
+
+pub struct OptimizePass;
+
+impl Pass for OptimizePass {
+    fn name(&self) -> &str {
+        "optimize"
+    }
+
+    // Runs after analysis, so warnings describe the program as written
+    // rather than what is left of it.
+    fn stage(&self) -> Stage {
+        Stage::Output
+    }
+
+    fn run(&mut self, program: &mut Program, _: &mut Vec<Diagnostic>) {
+        optimize(program);
+    }
+}
+
+// Folds operators and conditions whose operands are literals, and removes
+// code that can never run: statements after a `return`, `break` or
+// `continue`, and match arms no value can reach. Folding evaluates operators
+// exactly as the interpreter does and leaves any that would fail alone, so
+// the error is still reported when the program runs.
+pub fn optimize(program: &mut Program) {
+    optimize_statements(&mut Optimizer, &mut program.statements, None);
+}
+
+// Folds `expr` on its own, as `optimize` would inside a program.
+pub fn fold(expr: &mut Expr) {
+    Optimizer.visit_expr(expr);
+}
+
+struct Optimizer;
+
+impl VisitorMut for Optimizer {
+    fn visit_block(&mut self, block: &mut Block) {
+        optimize_statements(self, &mut block.statements, Some(&mut block.value));
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        // Operands are optimized first, so they are literals if they can be.
+        walk_expr_mut(self, expr);
+
+        let span = expr.span;
+        let replacement = match &mut expr.kind {
+            ExprKind::Unary { op, operand } => literal(operand)
+                .and_then(|value| unary(*op, value, span).ok())
+                .and_then(to_literal),
+            ExprKind::Binary {
+                op: op @ BinaryOp::And,
+                left,
+                right,
+            }
+            | ExprKind::Binary {
+                op: op @ BinaryOp::Or,
+                left,
+                right,
+            } => {
+                // The right operand decides the result only when the left
+                // one doesn't, and still has to be checked to be a bool.
+                let short = *op == BinaryOp::Or;
+                match (&left.kind, &right.kind) {
+                    (ExprKind::Bool(left), _) if *left == short => Some(ExprKind::Bool(short)),
+                    (ExprKind::Bool(_), ExprKind::Bool(right)) => Some(ExprKind::Bool(*right)),
+                    _ => None,
+                }
+            }
+            ExprKind::Binary { op, left, right } => match (literal(left), literal(right)) {
+                (Some(left), Some(right)) => {
+                    binary(*op, left, right, span).ok().and_then(to_literal)
+                }
+                _ => None,
+            },
+            ExprKind::Match { scrutinee, arms } => {
+                remove_unreachable_arms(arms, literal(scrutinee));
+                None
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => match condition.kind {
+                ExprKind::Bool(true) => {
+                    Some(ExprKind::Block(std::mem::replace(then_branch, empty(span))))
+                }
+                ExprKind::Bool(false) => Some(match else_branch.take() {
+                    Some(else_branch) => else_branch.kind,
+                    None => ExprKind::Block(empty(span)),
+                }),
+                _ => None,
+            },
+            ExprKind::While { condition, .. } => match condition.kind {
+                ExprKind::Bool(false) => Some(ExprKind::Block(empty(span))),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(kind) = replacement {
+            expr.kind = kind;
+        }
+    }
+}
+
+// Optimizes each statement, dropping those after one that diverges along
+// with the block's value, if it has one.
+fn optimize_statements(
+    optimizer: &mut Optimizer,
+    statements: &mut Vec<Stmt>,
+    value: Option<&mut Option<Box<Expr>>>,
+) {
+    let mut end = None;
+    for (i, stmt) in statements.iter_mut().enumerate() {
+        optimizer.visit_stmt(stmt);
+        if stmt_diverges(stmt) {
+            end = Some(i + 1);
+            break;
+        }
+    }
+    match (end, value) {
+        (Some(end), value) => {
+            statements.truncate(end);
+            if let Some(value) = value {
+                *value = None;
+            }
+        }
+        (None, Some(Some(value))) => optimizer.visit_expr(value),
+        (None, _) => {}
+    }
+}
+
+// Drops the arms after one that matches every value the scrutinee can have,
+// and, when the scrutinee is a literal, the arms it doesn't match.
+fn remove_unreachable_arms(arms: &mut Vec<MatchArm>, scrutinee: Option<Value>) {
+    let mut reachable = Vec::new();
+    for arm in arms.drain(..) {
+        let catches_all = match &scrutinee {
+            Some(value) => {
+                if !match_pattern(&arm.pattern, value, &mut Vec::new()) {
+                    continue;
+                }
+                true
+            }
+            None => matches!(
+                arm.pattern.kind,
+                PatternKind::Wildcard | PatternKind::Binding(_)
+            ),
+        };
+        let guarded = arm.guard.is_some();
+        reachable.push(arm);
+        if catches_all && !guarded {
+            break;
+        }
+    }
+    *arms = reachable;
+}
+
+// The value of an expression made only of literals.
+fn literal(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::Integer(n) => Some(Value::Integer(*n)),
+        ExprKind::Float(n) => Some(Value::Float(*n)),
+        ExprKind::String(s) => Some(Value::String(s.clone())),
+        ExprKind::Bool(b) => Some(Value::Bool(*b)),
+        ExprKind::Tuple(elements) => elements
+            .iter()
+            .map(literal)
+            .collect::<Option<_>>()
+            .map(Value::Tuple),
+        _ => None,
+    }
+}
+
+fn to_literal(value: Value) -> Option<ExprKind> {
+    match value {
+        Value::Integer(n) => Some(ExprKind::Integer(n)),
+        Value::Float(n) => Some(ExprKind::Float(n)),
+        Value::String(s) => Some(ExprKind::String(s)),
+        Value::Bool(b) => Some(ExprKind::Bool(b)),
+        _ => None,
+    }
+}
+
+// An empty block, which evaluates to unit.
+fn empty(span: Span) -> Block {
+    Block {
+        statements: Vec::new(),
+        value: None,
+        span,
+    }
+}
+
+fn stmt_diverges(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Expr(expr)
+        | StmtKind::Let { value: expr, .. }
+        | StmtKind::Destructure { value: expr, .. } => diverges(expr),
+        StmtKind::Function(_)
+        | StmtKind::Import(_)
+        | StmtKind::Struct(_)
+        | StmtKind::Enum(_)
+        | StmtKind::Test { .. }
+        | StmtKind::Trait(_)
+        | StmtKind::Impl(_) => false,
+    }
+}
+
+// Whether evaluating `expr` always leaves the enclosing block early.
+fn diverges(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Return(_) | ExprKind::Break | ExprKind::Continue => true,
+        ExprKind::Block(block) => {
+            block.statements.iter().any(stmt_diverges)
+                || block.value.as_deref().is_some_and(diverges)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::optimize::optimize::optimize;
+    use crate::parser::parser::parse;
+
+    // Each case is a program and a snapshot of it optimized. Both versions
+    // must run to the same value, or fail with the same error.
+    const SNAPSHOTS: &[(&str, &str)] = &[
+        ("1 + 2 * 3 - 4 / 2", "5;"),
+        ("2.5 * 2 + 1", "6.0;"),
+        ("\"clay\" + \"!\" == \"clay!\"", "true;"),
+        ("-(3 - 5) < 3 && !false", "true;"),
+        (
+            "let x = 2; (x + 1 * 2, (1, 2) == (1, 2))",
+            "let x = 2;\n(x + 2, true);",
+        ),
+        ("let x = true; false && x", "let x = true;\nfalse;"),
+        ("let x = true; x || true", "let x = true;\nx || true;"),
+        ("let x = 1; 1 / 0 + x", "let x = 1;\n1 / 0 + x;"),
+        ("if 1 < 2 { \"yes\" } else { \"no\" }", "{\n    \"yes\"\n}"),
+        ("if 1 > 2 { 1 } else if true { 2 } else { 3 }", "{\n    2\n}"),
+        ("while 1 == 2 { 3 }", "{}"),
+        (
+            "fn f(x) { if x { return 1; x + 1; } return 2; 3 } (f(true), f(false))",
+            "fn f(x) {\n    if x {\n        return 1;\n    }\n    return 2;\n}\n(f(true), f(false));",
+        ),
+        (
+            "match 2 { 1 => \"one\", n if n > 5 => \"big\", n => \"other\", _ => \"never\" }",
+            "match 2 {\n    n if n > 5 => \"big\",\n    n => \"other\",\n}",
+        ),
+        (
+            "let y = 3; match y { 1 => \"one\", _ => \"many\", 2 => \"two\" }",
+            "let y = 3;\nmatch y {\n    1 => \"one\",\n    _ => \"many\",\n}",
+        ),
+        (
+            "match (1, \"a\") { (1, \"b\") => 1, (1, s) => 2 }",
+            "match (1, \"a\") {\n    (1, s) => 2,\n}",
+        ),
+    ];
+
+    #[test]
+    fn matches_snapshots_and_preserves_behavior() {
+        for (source, snapshot) in SNAPSHOTS {
+            let program = parse(source).unwrap();
+            let mut optimized = program.clone();
+            optimize(&mut optimized);
+            assert_eq!(optimized.to_string(), *snapshot, "optimizing {}", source);
+
+            let before = Interpreter::new().run(&program).map_err(|err| err.message);
+            let after = Interpreter::new()
+                .run(&optimized)
+                .map_err(|err| err.message);
+            assert_eq!(
+                after.map(|value| value.to_string()),
+                before.map(|value| value.to_string()),
+                "running {}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::symbol::{self, Symbol};
+use crate::lexer::token::{quote, Span};
+
+pub mod display;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    // `@!name(...)` attributes at the top of the file, which apply to all of
+    // it.
+    pub attributes: Vec<Attribute>,
+    pub statements: Vec<Stmt>,
+}
+
+// `@name(arg, ...)` before a declaration, or `@!name(...)` at the top of a
+// file, like `@allow(unused-variable)`. Arguments are names, which may have
+// `-`s in them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: Symbol,
+    pub args: Vec<Symbol>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StmtKind {
+    Expr(Expr),
+    Function(Arc<Function>),
+    Import(Import),
+    // `let name = value` or `let mut name = value`, optionally annotated as
+    // `let name: Type = value`.
+    Let {
+        name: Symbol,
+        mutable: bool,
+        ty: Option<TypeExpr>,
+        value: Expr,
+    },
+    Struct(StructDecl),
+    Enum(EnumDecl),
+    // `let (a, b) = value`: a `let` whose target is a pattern rather than a
+    // name. A value the pattern doesn't match is an error.
+    Destructure {
+        pattern: Pattern,
+        mutable: bool,
+        ty: Option<TypeExpr>,
+        value: Expr,
+    },
+    // `test "name" { ... }` at the top level of a file. Running the file
+    // skips it; `clay test` runs each one on its own.
+    Test {
+        name: String,
+        body: Block,
+    },
+    Trait(TraitDecl),
+    Impl(ImplDecl),
+}
+
+// `struct Name { field: Type, ... }`; field types are optional. A generic
+// struct names its type parameters after its name: `struct Pair<A, B>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructDecl {
+    pub name: Symbol,
+    // The `///` comments before it, joined by newlines.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub type_params: Vec<Symbol>,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Field {
+    pub name: Symbol,
+    pub ty: Option<TypeExpr>,
+    pub span: Span,
+}
+
+// `enum Name { Variant(Type, ...), Variant, ... }`, or `enum Name<T> ...`
+// for a generic one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnumDecl {
+    pub name: Symbol,
+    // The `///` comments before it, joined by newlines.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub type_params: Vec<Symbol>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    pub name: Symbol,
+    // The types of the values it holds, none for a variant like `None`.
+    pub fields: Vec<TypeExpr>,
+    pub span: Span,
+}
+
+// `trait Name { fn method(self, ...) -> Type; ... }`: the methods a struct
+// must have to implement the trait.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraitDecl {
+    pub name: Symbol,
+    // The `///` comments before it, joined by newlines.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub methods: Vec<MethodSig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MethodSig {
+    pub name: Symbol,
+    // The `///` comments before it, joined by newlines.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    // Starting with `self`, the value the method is called on.
+    pub params: Vec<Param>,
+    pub returns: Option<TypeExpr>,
+    pub span: Span,
+}
+
+// `impl Trait for Struct { fn method(self, ...) { ... } ... }`. Each method
+// takes the struct it is called on as `self`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImplDecl {
+    pub attributes: Vec<Attribute>,
+    pub trait_name: Symbol,
+    pub target: Symbol,
+    pub methods: Vec<Arc<Function>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Import {
+    pub path: ImportPath,
+    pub names: ImportNames,
+}
+
+// What an import binds in the importing scope.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ImportNames {
+    // `import std::path`, or `import std::path as p`: the module, under
+    // the name after `as` if there is one.
+    Module(Option<Symbol>),
+    // `import { join, parent as up } from std::path`: members of the
+    // module, rather than the module.
+    Members(Vec<ImportMember>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportMember {
+    pub name: Symbol,
+    pub alias: Option<Symbol>,
+    // From the name to the alias.
+    pub span: Span,
+}
+
+impl Import {
+    // The names the import binds, in the order they are written.
+    pub fn bindings(&self) -> Vec<Symbol> {
+        match &self.names {
+            ImportNames::Module(alias) => vec![alias.unwrap_or_else(|| self.path.binding())],
+            ImportNames::Members(members) => members.iter().map(ImportMember::binding).collect(),
+        }
+    }
+}
+
+impl ImportMember {
+    pub fn binding(&self) -> Symbol {
+        self.alias.unwrap_or(self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ImportPath {
+    // import "path/to/file.clay"
+    File(String),
+    // import std::math
+    Module(Vec<Symbol>),
+}
+
+impl ImportPath {
+    // The name an import binds in the importing scope: the file stem for file
+    // imports and the last segment for module paths.
+    pub fn binding(&self) -> Symbol {
+        match self {
+            ImportPath::File(path) => Symbol::intern(
+                &std::path::Path::new(path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.clone()),
+            ),
+            ImportPath::Module(segments) => match segments.last() {
+                Some(&segment) => segment,
+                None => Symbol::intern(""),
+            },
+        }
+    }
+}
+
+// `{:#}` prints a file's attribute, with `@!`.
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let at = if f.alternate() { "@!" } else { "@" };
+        write!(f, "{}{}", at, self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "({})", symbol::join(&self.args, ", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.names {
+            ImportNames::Module(None) => write!(f, "import {}", self.path),
+            ImportNames::Module(Some(alias)) => write!(f, "import {} as {}", self.path, alias),
+            ImportNames::Members(members) => {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|member| match member.alias {
+                        Some(alias) => format!("{} as {}", member.name, alias),
+                        None => member.name.to_string(),
+                    })
+                    .collect();
+                write!(f, "import {{ {} }} from {}", members.join(", "), self.path)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ImportPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportPath::File(path) => write!(f, "{}", quote(path)),
+            ImportPath::Module(segments) => write!(f, "{}", symbol::join(segments, "::")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExprKind {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Ident(Symbol),
+    Path(Vec<Symbol>),
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+    // `#{ key: value, ... }`; the `#` keeps it apart from blocks.
+    Map(Vec<(Expr, Expr)>),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Assign {
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    Block(Block),
+    Function(Arc<Function>),
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    // `receiver.method(args)`, used for the built-in methods of values.
+    MethodCall {
+        receiver: Box<Expr>,
+        method: Symbol,
+        args: Vec<Expr>,
+    },
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // `target[start..end]`; either bound may be left out.
+    Slice {
+        target: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    Return(Option<Box<Expr>>),
+    // `else` holds either a block or another `if`.
+    If {
+        condition: Box<Expr>,
+        then_branch: Block,
+        else_branch: Option<Box<Expr>>,
+    },
+    While {
+        condition: Box<Expr>,
+        body: Block,
+    },
+    For {
+        pattern: Pattern,
+        iterable: Box<Expr>,
+        body: Block,
+    },
+    Break,
+    Continue,
+    // `Name { field: value, ... }`, where `Name` may be a `module::Name`
+    // path. `Name { field }` is short for `Name { field: field }`.
+    Struct {
+        path: Vec<Symbol>,
+        fields: Vec<(Symbol, Expr)>,
+    },
+    // `target.name`, reading a struct's field.
+    Field {
+        target: Box<Expr>,
+        name: Symbol,
+    },
+    // `start..end`, or `start..=end` when `inclusive`.
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
+}
+
+// `{ statements; value }`: the block evaluates to its trailing expression,
+// or to unit when it ends with a semicolon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub value: Option<Box<Expr>>,
+    pub span: Span,
+}
+
+// `fn name(params) -> returns { body }`; anonymous functions have no name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub name: Option<Symbol>,
+    // The `///` comments before it, joined by newlines. Only named
+    // functions and methods have any.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    // `fn id<T>(x: T) -> T` is generic over `T`.
+    pub type_params: Vec<Symbol>,
+    pub params: Vec<Param>,
+    pub returns: Option<TypeExpr>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Param {
+    pub name: Symbol,
+    pub ty: Option<TypeExpr>,
+    pub span: Span,
+}
+
+// A type written in the source, such as `Int`, `List<String>` or
+// `Fn(Int) -> Bool`. Names are resolved by the type checker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TypeExprKind {
+    // A type name and its arguments, e.g. `Map<String, Int>`.
+    Named {
+        name: Symbol,
+        args: Vec<TypeExpr>,
+    },
+    // `(Int, String)`; `()` is the unit type.
+    Tuple(Vec<TypeExpr>),
+    Function {
+        params: Vec<TypeExpr>,
+        returns: Box<TypeExpr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PatternKind {
+    Wildcard,
+    Binding(Symbol),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Tuple(Vec<Pattern>),
+    // `Name { field: pattern, field, .. }`. Without the trailing `..` it
+    // only matches if it lists every field, like a tuple of the right length.
+    Struct {
+        path: Vec<Symbol>,
+        fields: Vec<(Symbol, Pattern)>,
+        rest: bool,
+    },
+    // `Enum::Variant(pattern, ...)`, or `Enum::Variant` for one that holds
+    // no values. `path` ends with the enum's name and the variant's.
+    Variant {
+        path: Vec<Symbol>,
+        fields: Vec<Pattern>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// The precedence table the parser is driven by. Tools that print
+// expressions ask it where parentheses are needed.
+impl BinaryOp {
+    // Higher binds tighter: `a + b * c` is `a + (b * c)`. All of them bind
+    // looser than prefix operators and tighter than ranges and assignment.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal | BinaryOp::NotEqual => 3,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+            // Unlike C, bitwise operators bind tighter than comparisons, so
+            // `a & mask == 0` is `(a & mask) == 0`.
+            BinaryOp::BitOr => 5,
+            BinaryOp::BitXor => 6,
+            BinaryOp::BitAnd => 7,
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 8,
+            BinaryOp::Add | BinaryOp::Subtract => 9,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder => 10,
+        }
+    }
+
+    // Whether the operator works on the bits of integers.
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::BitAnd
+                | BinaryOp::BitOr
+                | BinaryOp::BitXor
+                | BinaryOp::ShiftLeft
+                | BinaryOp::ShiftRight
+        )
+    }
+
+    // How a run of operators of the same precedence groups: `a - b - c` is
+    // `(a - b) - c`.
+    pub fn associativity(self) -> Associativity {
+        Associativity::Left
+    }
+
+    // Whether an `operand` operation on the left, or on the right when
+    // `right`, of this operator needs parentheses to keep its grouping.
+    pub fn needs_parentheses(self, operand: BinaryOp, right: bool) -> bool {
+        match operand.precedence().cmp(&self.precedence()) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Greater => false,
+            std::cmp::Ordering::Equal => match self.associativity() {
+                Associativity::Left => right,
+                Associativity::Right => !right,
+            },
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+        };
+        write!(f, "{}", symbol)
+    }
+}
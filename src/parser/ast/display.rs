@@ -0,0 +1,956 @@
+use std::fmt;
+
+use crate::lexer::symbol::{self, Symbol};
+use crate::lexer::token::quote;
+use crate::parser::ast::{
+    Attribute, Block, Expr, ExprKind, Function, MatchArm, Param, Pattern, PatternKind, Program,
+    Stmt, StmtKind, TypeExpr, TypeExprKind,
+};
+use crate::parser::parser::{
+    binding_power, ASSIGNMENT_POWER, CALL_POWER, PREFIX_POWER, RANGE_POWER,
+};
+
+// Prints syntax trees as clay source that parses back to the same tree, up
+// to spans. Parentheses are only added where the grouping needs them, and
+// each statement goes on its own line with blocks indented by four spaces.
+// Sugar comes out desugared, as `x = x + 1` for `x += 1`, except for `?`.
+// Trees the parser can't produce, like strings containing `"`, print as
+// close to source as they can.
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::default();
+        for attribute in &self.attributes {
+            printer.push(&format!("{:#}", attribute));
+            printer.newline();
+        }
+        for (i, stmt) in self.statements.iter().enumerate() {
+            if i > 0 {
+                printer.newline();
+            }
+            printer.stmt(stmt, false);
+        }
+        f.write_str(&printer.out)
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::default();
+        printer.stmt(self, false);
+        f.write_str(&printer.out)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::default();
+        printer.expr(self, 0);
+        f.write_str(&printer.out)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::default();
+        printer.pattern(self);
+        f.write_str(&printer.out)
+    }
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut printer = Printer::default();
+        printer.ty(self);
+        f.write_str(&printer.out)
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    // Mirrors the parser's flag: set while printing the head of an `if`,
+    // `while`, `for` or `match`, where a struct literal needs parentheses.
+    no_struct_literals: bool,
+}
+
+impl Printer {
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.out.push_str(&"    ".repeat(self.indent));
+    }
+
+    // Prints `print`'s output in parentheses, inside which struct literals
+    // are allowed again.
+    fn parenthesized(&mut self, print: impl FnOnce(&mut Printer)) {
+        self.delimited("(", ")", print);
+    }
+
+    fn delimited(&mut self, open: &str, close: &str, print: impl FnOnce(&mut Printer)) {
+        let previous = std::mem::replace(&mut self.no_struct_literals, false);
+        self.push(open);
+        print(self);
+        self.push(close);
+        self.no_struct_literals = previous;
+    }
+
+    fn comma_separated<T>(&mut self, items: &[T], mut print: impl FnMut(&mut Printer, &T)) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            print(self, item);
+        }
+    }
+
+    // `{ a, b }` on one line, or `{}`.
+    fn braced<T>(&mut self, items: &[T], print: impl FnMut(&mut Printer, &T)) {
+        self.delimited("{", "}", |printer| {
+            if !items.is_empty() {
+                printer.push(" ");
+                printer.comma_separated(items, print);
+                printer.push(" ");
+            }
+        });
+    }
+
+    // `last` is set for the last statement of a block without a value, which
+    // would be read as the value if it were a block-like expression with no
+    // `;` after it.
+    fn stmt(&mut self, stmt: &Stmt, last: bool) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.statement_expr(expr);
+                if last || !is_block_like(expr) {
+                    self.push(";");
+                }
+            }
+            StmtKind::Function(function) => self.function(function),
+            StmtKind::Import(import) => {
+                self.push(&format!("{};", import));
+            }
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                value,
+            } => {
+                self.push(if *mutable { "let mut " } else { "let " });
+                self.push(name);
+                self.let_rest(ty, value);
+            }
+            StmtKind::Destructure {
+                pattern,
+                mutable,
+                ty,
+                value,
+            } => {
+                self.push(if *mutable { "let mut " } else { "let " });
+                self.pattern(pattern);
+                self.let_rest(ty, value);
+            }
+            StmtKind::Struct(decl) => {
+                self.doc(&decl.doc, &decl.attributes);
+                self.push(&format!(
+                    "struct {}{} ",
+                    decl.name,
+                    type_params(&decl.type_params)
+                ));
+                self.braced(&decl.fields, |printer, field| {
+                    printer.push(&field.name);
+                    if let Some(ty) = &field.ty {
+                        printer.push(": ");
+                        printer.ty(ty);
+                    }
+                });
+            }
+            StmtKind::Test { name, body } => {
+                self.push(&format!("test {} ", quote(name)));
+                self.block(body);
+            }
+            StmtKind::Trait(decl) => {
+                self.doc(&decl.doc, &decl.attributes);
+                self.push(&format!("trait {} ", decl.name));
+                self.items(&decl.methods, |printer, method| {
+                    printer.doc(&method.doc, &method.attributes);
+                    printer.push("fn ");
+                    printer.push(&method.name);
+                    printer.signature(&method.params, &method.returns);
+                    printer.push(";");
+                });
+            }
+            StmtKind::Impl(decl) => {
+                self.doc(&None, &decl.attributes);
+                self.push(&format!("impl {} for {} ", decl.trait_name, decl.target));
+                self.items(&decl.methods, |printer, method| printer.function(method));
+            }
+            StmtKind::Enum(decl) => {
+                self.doc(&decl.doc, &decl.attributes);
+                self.push(&format!(
+                    "enum {}{} ",
+                    decl.name,
+                    type_params(&decl.type_params)
+                ));
+                self.braced(&decl.variants, |printer, variant| {
+                    printer.push(&variant.name);
+                    if !variant.fields.is_empty() {
+                        printer.parenthesized(|printer| {
+                            printer.comma_separated(&variant.fields, Printer::ty)
+                        });
+                    }
+                });
+            }
+        }
+    }
+
+    fn let_rest(&mut self, ty: &Option<TypeExpr>, value: &Expr) {
+        if let Some(ty) = ty {
+            self.push(": ");
+            self.ty(ty);
+        }
+        self.push(" = ");
+        self.expr(value, 0);
+        self.push(";");
+    }
+
+    // Statements that start with `if`, `{` and the like end at their `}`, so
+    // an expression that only starts with one is parenthesized.
+    fn statement_expr(&mut self, expr: &Expr) {
+        if !is_block_like(expr) && starts_with_block(expr) {
+            self.parenthesized(|printer| printer.expr(expr, 0));
+        } else {
+            self.expr(expr, 0);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.statements.is_empty() && block.value.is_none() {
+            self.push("{}");
+            return;
+        }
+        let previous = std::mem::replace(&mut self.no_struct_literals, false);
+        self.push("{");
+        self.indent += 1;
+        for (i, stmt) in block.statements.iter().enumerate() {
+            self.newline();
+            let last = i + 1 == block.statements.len() && block.value.is_none();
+            self.stmt(stmt, last);
+        }
+        if let Some(value) = &block.value {
+            self.newline();
+            self.statement_expr(value);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+        self.no_struct_literals = previous;
+    }
+
+    // A declaration's doc comment, a line of its own for each of its lines,
+    // and then its attributes, one to a line.
+    fn doc(&mut self, doc: &Option<String>, attributes: &[Attribute]) {
+        for line in doc.iter().flat_map(|doc| doc.split('\n')) {
+            match line.is_empty() {
+                true => self.push("///"),
+                false => self.push(&format!("/// {}", line)),
+            }
+            self.newline();
+        }
+        for attribute in attributes {
+            self.push(&attribute.to_string());
+            self.newline();
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.doc(&function.doc, &function.attributes);
+        self.push("fn");
+        if let Some(name) = &function.name {
+            self.push(" ");
+            self.push(name);
+        }
+        self.push(&type_params(&function.type_params));
+        self.signature(&function.params, &function.returns);
+        self.push(" ");
+        self.block(&function.body);
+    }
+
+    fn signature(&mut self, params: &[Param], returns: &Option<TypeExpr>) {
+        self.parenthesized(|printer| {
+            printer.comma_separated(params, |printer, param| {
+                printer.push(&param.name);
+                if let Some(ty) = &param.ty {
+                    printer.push(": ");
+                    printer.ty(ty);
+                }
+            })
+        });
+        if let Some(returns) = returns {
+            self.push(" -> ");
+            self.ty(returns);
+        }
+    }
+
+    // `{` and `}` around items on lines of their own, or `{}`.
+    fn items<T>(&mut self, items: &[T], mut print: impl FnMut(&mut Printer, &T)) {
+        if items.is_empty() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        for item in items {
+            self.newline();
+            print(self, item);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    // Prints `expr` where the parser reads it with binding power `min`,
+    // parenthesized if it would otherwise bind differently.
+    fn expr(&mut self, expr: &Expr, min: u8) {
+        let struct_literal = matches!(expr.kind, ExprKind::Struct { .. });
+        if left_power(expr) < min || (struct_literal && self.no_struct_literals) {
+            self.parenthesized(|printer| printer.expr(expr, 0));
+            return;
+        }
+
+        match &expr.kind {
+            ExprKind::Integer(n) => self.push(&n.to_string()),
+            ExprKind::Float(n) => self.push(&float(*n)),
+            ExprKind::String(s) => self.push(&quote(s)),
+            ExprKind::Bool(b) => self.push(&b.to_string()),
+            ExprKind::Ident(name) => self.push(name),
+            ExprKind::Path(segments) => self.push(&symbol::join(segments, "::")),
+            ExprKind::Tuple(elements) => self.parenthesized(|printer| {
+                printer.comma_separated(elements, |printer, element| printer.expr(element, 0));
+                if elements.len() == 1 {
+                    printer.push(",");
+                }
+            }),
+            ExprKind::List(elements) => self.delimited("[", "]", |printer| {
+                printer.comma_separated(elements, |printer, element| printer.expr(element, 0))
+            }),
+            ExprKind::Map(entries) => self.delimited("#{", "}", |printer| {
+                printer.comma_separated(entries, |printer, (key, value)| {
+                    printer.followed(key);
+                    printer.push(": ");
+                    printer.expr(value, 0);
+                })
+            }),
+            ExprKind::Unary { op, operand } => {
+                self.push(&op.to_string());
+                self.expr(operand, PREFIX_POWER);
+            }
+            ExprKind::Binary { op, left, right } => {
+                let (left_power, right_power) = binding_power(*op);
+                self.left_operand(left, left_power);
+                self.push(&format!(" {} ", op));
+                self.expr(right, right_power);
+            }
+            ExprKind::Assign { target, value } => {
+                self.left_operand(target, ASSIGNMENT_POWER.0);
+                self.push(" = ");
+                self.expr(value, ASSIGNMENT_POWER.1);
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.left_operand(start, RANGE_POWER.0);
+                self.push(if *inclusive { "..=" } else { ".." });
+                self.expr(end, RANGE_POWER.1);
+            }
+            ExprKind::Match { scrutinee, arms } if is_try(arms) => {
+                self.left_operand(scrutinee, CALL_POWER);
+                self.push("?");
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.push("match ");
+                self.head(scrutinee);
+                self.push(" {");
+                self.indent += 1;
+                for arm in arms {
+                    self.newline();
+                    self.arm(arm);
+                }
+                self.indent -= 1;
+                self.newline();
+                self.push("}");
+            }
+            ExprKind::Block(block) => self.block(block),
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Call { callee, args } => {
+                self.left_operand(callee, CALL_POWER);
+                self.parenthesized(|printer| {
+                    printer.comma_separated(args, |printer, arg| printer.expr(arg, 0))
+                });
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                self.left_operand(receiver, CALL_POWER);
+                self.push(".");
+                self.push(method);
+                self.parenthesized(|printer| {
+                    printer.comma_separated(args, |printer, arg| printer.expr(arg, 0))
+                });
+            }
+            ExprKind::Index { target, index } => {
+                self.left_operand(target, CALL_POWER);
+                self.delimited("[", "]", |printer| printer.expr(index, 0));
+            }
+            ExprKind::Slice { target, start, end } => {
+                self.left_operand(target, CALL_POWER);
+                self.delimited("[", "]", |printer| {
+                    // The bounds are read like the operands of a range.
+                    if let Some(start) = start {
+                        if right_power(start) <= RANGE_POWER.0 {
+                            printer.parenthesized(|printer| printer.expr(start, 0));
+                        } else {
+                            printer.expr(start, RANGE_POWER.1);
+                        }
+                    }
+                    printer.push("..");
+                    if let Some(end) = end {
+                        printer.expr(end, RANGE_POWER.1);
+                    }
+                });
+            }
+            ExprKind::Return(value) => {
+                self.push("return");
+                if let Some(value) = value {
+                    self.push(" ");
+                    self.expr(value, 0);
+                }
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.push("if ");
+                self.condition(condition);
+                self.push(" ");
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.push(" else ");
+                    match &else_branch.kind {
+                        ExprKind::If { .. } | ExprKind::Block(_) => self.expr(else_branch, 0),
+                        _ => {
+                            let previous = std::mem::replace(&mut self.no_struct_literals, false);
+                            self.push("{ ");
+                            self.expr(else_branch, 0);
+                            self.push(" }");
+                            self.no_struct_literals = previous;
+                        }
+                    }
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.push("while ");
+                self.condition(condition);
+                self.push(" ");
+                self.block(body);
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.push("for ");
+                self.pattern(pattern);
+                self.push(" in ");
+                self.head(iterable);
+                self.push(" ");
+                self.block(body);
+            }
+            ExprKind::Break => self.push("break"),
+            ExprKind::Continue => self.push("continue"),
+            ExprKind::Struct { path, fields } => {
+                self.push(&symbol::join(path, "::"));
+                self.push(" ");
+                self.braced(fields, |printer, (name, value)| {
+                    printer.push(name);
+                    printer.push(": ");
+                    printer.expr(value, 0);
+                });
+            }
+            ExprKind::Field { target, name } => {
+                self.left_operand(target, CALL_POWER);
+                self.push(".");
+                self.push(name);
+            }
+        }
+    }
+
+    // Prints the operand left of an operator with left binding power
+    // `power`, which it can't be allowed to take as its own operand.
+    fn left_operand(&mut self, operand: &Expr, power: u8) {
+        if right_power(operand) <= power {
+            self.parenthesized(|printer| printer.expr(operand, 0));
+        } else {
+            self.expr(operand, power);
+        }
+    }
+
+    // Prints an expression followed by a token a bare `return` would take
+    // as the start of its value, like the `{` after a head or the `=>` after
+    // a guard.
+    fn followed(&mut self, expr: &Expr) {
+        if ends_with_return(expr) {
+            self.parenthesized(|printer| printer.expr(expr, 0));
+        } else {
+            self.expr(expr, 0);
+        }
+    }
+
+    fn head(&mut self, expr: &Expr) {
+        let previous = std::mem::replace(&mut self.no_struct_literals, true);
+        self.followed(expr);
+        self.no_struct_literals = previous;
+    }
+
+    // An assignment is only read as a condition in parentheses.
+    fn condition(&mut self, expr: &Expr) {
+        match expr.kind {
+            ExprKind::Assign { .. } => {
+                self.push("(");
+                self.expr(expr, 0);
+                self.push(")");
+            }
+            _ => self.head(expr),
+        }
+    }
+
+    fn arm(&mut self, arm: &MatchArm) {
+        self.pattern(&arm.pattern);
+        if let Some(guard) = &arm.guard {
+            self.push(" if ");
+            self.followed(guard);
+        }
+        self.push(" => ");
+        self.expr(&arm.body, 0);
+        self.push(",");
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Wildcard => self.push("_"),
+            PatternKind::Binding(name) => self.push(name),
+            PatternKind::Integer(n) => self.push(&n.to_string()),
+            PatternKind::Float(n) => self.push(&float(*n)),
+            PatternKind::String(s) => self.push(&quote(s)),
+            PatternKind::Bool(b) => self.push(&b.to_string()),
+            PatternKind::Tuple(patterns) => self.parenthesized(|printer| {
+                printer.comma_separated(patterns, Printer::pattern);
+                if patterns.len() == 1 {
+                    printer.push(",");
+                }
+            }),
+            PatternKind::Struct { path, fields, rest } => {
+                self.push(&symbol::join(path, "::"));
+                self.push(" ");
+                // `..` goes last, after the fields.
+                let mut fields: Vec<_> = fields.iter().map(Some).collect();
+                if *rest {
+                    fields.push(None);
+                }
+                self.braced(&fields, |printer, field| match field {
+                    Some((name, pattern)) => {
+                        printer.push(name);
+                        printer.push(": ");
+                        printer.pattern(pattern);
+                    }
+                    None => printer.push(".."),
+                });
+            }
+            PatternKind::Variant { path, fields } => {
+                self.push(&symbol::join(path, "::"));
+                if !fields.is_empty() {
+                    self.parenthesized(|printer| printer.comma_separated(fields, Printer::pattern));
+                }
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &TypeExpr) {
+        match &ty.kind {
+            TypeExprKind::Named { name, args } => {
+                self.push(name);
+                if !args.is_empty() {
+                    self.push("<");
+                    self.comma_separated(args, Printer::ty);
+                    self.push(">");
+                }
+            }
+            TypeExprKind::Tuple(elements) => {
+                self.push("(");
+                self.comma_separated(elements, Printer::ty);
+                self.push(")");
+            }
+            TypeExprKind::Function { params, returns } => {
+                self.push("Fn(");
+                self.comma_separated(params, Printer::ty);
+                self.push(") -> ");
+                self.ty(returns);
+            }
+        }
+    }
+}
+
+// `<A, B>` after the name of something generic, or nothing.
+pub fn type_params(params: &[Symbol]) -> String {
+    match params.is_empty() {
+        true => String::new(),
+        false => format!("<{}>", symbol::join(params, ", ")),
+    }
+}
+
+// Floats keep a `.` so they don't read back as integers. Those without a
+// literal are written as the division that makes them.
+fn float(n: f64) -> String {
+    if n.is_nan() {
+        "(0.0 / 0.0)".to_string()
+    } else if n.is_infinite() {
+        let sign = if n < 0.0 { "-" } else { "" };
+        format!("({}1.0 / 0.0)", sign)
+    } else if n.fract() == 0.0 {
+        format!("{:.1}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+// The power an expression's operator binds with on its left: only
+// operators at least as strong as the surrounding one can continue it.
+fn left_power(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Assign { .. } => ASSIGNMENT_POWER.0,
+        ExprKind::Range { .. } => RANGE_POWER.0,
+        ExprKind::Binary { op, .. } => binding_power(*op).0,
+        ExprKind::Call { .. }
+        | ExprKind::MethodCall { .. }
+        | ExprKind::Field { .. }
+        | ExprKind::Index { .. }
+        | ExprKind::Slice { .. } => CALL_POWER,
+        ExprKind::Match { arms, .. } if is_try(arms) => CALL_POWER,
+        _ => u8::MAX,
+    }
+}
+
+// The power an expression holds on to what follows it with: an operator
+// binding no stronger would be taken into its last operand. Expressions
+// that end in a delimiter hold on to nothing.
+fn right_power(expr: &Expr) -> u8 {
+    let (power, operand) = match &expr.kind {
+        ExprKind::Assign { value, .. } => (ASSIGNMENT_POWER.1, value),
+        ExprKind::Range { end, .. } => (RANGE_POWER.1, end),
+        ExprKind::Binary { op, right, .. } => (binding_power(*op).1, right),
+        ExprKind::Unary { operand, .. } => (PREFIX_POWER, operand),
+        ExprKind::Integer(n) if *n < 0 => return PREFIX_POWER,
+        ExprKind::Float(n) if n.is_finite() && n.is_sign_negative() => return PREFIX_POWER,
+        ExprKind::Return(_) => return 0,
+        _ => return u8::MAX,
+    };
+    match left_power(operand) < power {
+        // The operand is parenthesized.
+        true => power,
+        false => power.min(right_power(operand)),
+    }
+}
+
+fn ends_with_return(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Return(None) => true,
+        ExprKind::Return(Some(operand))
+        | ExprKind::Assign { value: operand, .. }
+        | ExprKind::Range { end: operand, .. }
+        | ExprKind::Binary { right: operand, .. }
+        | ExprKind::Unary { operand, .. } => ends_with_return(operand),
+        _ => false,
+    }
+}
+
+// Expressions the parser reads as whole statements, ending at their `}`.
+fn is_block_like(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Match { arms, .. } => !is_try(arms),
+        ExprKind::If { .. } | ExprKind::While { .. } | ExprKind::For { .. } => true,
+        ExprKind::Block(_) => true,
+        _ => false,
+    }
+}
+
+fn starts_with_block(expr: &Expr) -> bool {
+    if is_block_like(expr) {
+        return true;
+    }
+    let (operand, power) = match &expr.kind {
+        ExprKind::Assign { target, .. } => (target, ASSIGNMENT_POWER.0),
+        ExprKind::Range { start, .. } => (start, RANGE_POWER.0),
+        ExprKind::Binary { op, left, .. } => (left, binding_power(*op).0),
+        ExprKind::Call {
+            callee: operand, ..
+        }
+        | ExprKind::MethodCall {
+            receiver: operand, ..
+        }
+        | ExprKind::Field {
+            target: operand, ..
+        }
+        | ExprKind::Index {
+            target: operand, ..
+        }
+        | ExprKind::Slice {
+            target: operand, ..
+        } => (operand, CALL_POWER),
+        ExprKind::Match { scrutinee, .. } => (scrutinee, CALL_POWER),
+        _ => return false,
+    };
+    right_power(operand) > power && starts_with_block(operand)
+}
+
+// Whether a match is the one `value?` stands for, which binds `?`.
+fn is_try(arms: &[MatchArm]) -> bool {
+    match arms.first().map(|arm| &arm.pattern.kind) {
+        Some(PatternKind::Variant { fields, .. }) => matches!(
+            fields.as_slice(),
+            [Pattern {
+                kind: PatternKind::Binding(name),
+                ..
+            }] if name == "?"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use crate::lexer::symbol::Symbol;
+    use crate::lexer::token::{Position, Span};
+    use crate::parser::ast::{
+        BinaryOp, Block, Expr, ExprKind, MatchArm, Pattern, PatternKind, Program, Stmt, StmtKind,
+        UnaryOp,
+    };
+    use crate::parser::parser::parse;
+    use crate::random::Random;
+
+    // The tree without its spans, which printing doesn't keep.
+    fn shape(program: &Program) -> Json {
+        fn strip(json: &mut Json) {
+            match json {
+                Json::Object(fields) => {
+                    fields.remove("span");
+                    fields.values_mut().for_each(strip);
+                }
+                Json::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut json = serde_json::to_value(program).unwrap();
+        strip(&mut json);
+        json
+    }
+
+    fn round_trip(program: &Program) {
+        let printed = program.to_string();
+        let reparsed = match parse(&printed) {
+            Ok(reparsed) => reparsed,
+            Err(err) => panic!("{} in:\n{}", err.message, printed),
+        };
+        assert_eq!(shape(&reparsed), shape(program), "printed as:\n{}", printed);
+    }
+
+    #[test]
+    fn prints_programs_that_parse_back() {
+        let sources = [
+            "import std::math; import \"lib/util.clay\"; math::pi",
+            "let x = 1_000 + 2f64 * (3 - 1); let mut y: List<Int> = [x, 2.5, -1.0]",
+            "fn f(a: Int, b) -> Fn(Int) -> (Int, Bool) { a + b } f(1, 2)",
+            "struct Point { x: Int, y } enum Shape { Circle(Float), Square(Int, Int), Dot }",
+            "let p = Point { x: 1, y }; let Point { x, .. } = p; p.x = p.y - 1; p.x += 2",
+            "let (a, (b, _)) = (1, (2, 3)); let Shape::Circle(r) = Shape::Circle(1.5);",
+            "if x > 1 { print(x); } else if x < 0 { x -= 1 } else { 0 }",
+            "match (x, 1) { (1, y) if y > 0 => y, (-1, _) => 0, Point { x: 0, y } => y, _ => x }",
+            "while true { break; continue } for (i, x) in 0..=n { [1, 2][i..]; xs[..j] }",
+            "if (Point { x: 1 }) == p { 0 }; match f(Point { x: 1 }) { _ => Point {} }",
+            "let f = fn(n) { n * 2 }; f(1).len(); #{\"a\": 1, 2: (3,)}[\"a\"]",
+            "fn g(o) { let v = o?.len()?; Option::Some(v) } (if a { b } else { c })(1)",
+            "{ 1 } - 1; ({ 1 } - 1); (match x { _ => f })(); -(1 + 2) * !true",
+            "a = b = c; (a = b)..c; a..b..c; 1 - (2 - 3) - 4; fn() { return }; return 1 + 2",
+            "fn h() { if a { return } while a { return; } (return) + 1; -return 1 }",
+            "/// A point.\n///\n/// In 2d.\nstruct P {} /// Shows.\ntrait S { /// As text.\nfn s(self); }",
+            "@!allow(unused) @deny(shadowed-binding, constant-condition) @inline fn f() {}",
+            "/// Doc.\n@warn(a-b) impl S for P { @allow(x) fn s(self) { 1 } }",
+        ];
+        for source in sources {
+            round_trip(&parse(source).unwrap());
+        }
+    }
+
+    #[test]
+    fn prints_readable_source() {
+        let program = parse("fn f(x){if x>0{x+=1;}else{x} } let p=P{a:(1+2)*3};").unwrap();
+        assert_eq!(
+            program.to_string(),
+            "fn f(x) {\n    if x > 0 {\n        x = x + 1;\n    } else {\n        x\n    }\n}\nlet p = P { a: (1 + 2) * 3 };"
+        );
+    }
+
+    // Random trees, the same on every run.
+    struct Trees(Random);
+
+    impl Trees {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0.below(bound)
+        }
+
+        fn boxed(&mut self, depth: u32) -> Box<Expr> {
+            Box::new(self.expr(depth))
+        }
+
+        fn block(&mut self, depth: u32) -> Block {
+            let mut statements = Vec::new();
+            if self.below(2) == 0 {
+                statements.push(Stmt {
+                    kind: StmtKind::Expr(self.expr(depth)),
+                    span: span(),
+                });
+            }
+            Block {
+                statements,
+                value: match self.below(3) {
+                    0 => None,
+                    _ => Some(self.boxed(depth)),
+                },
+                span: span(),
+            }
+        }
+
+        // A tree the parser could have produced.
+        fn expr(&mut self, depth: u32) -> Expr {
+            const OPS: [BinaryOp; 8] = [
+                BinaryOp::Or,
+                BinaryOp::And,
+                BinaryOp::Equal,
+                BinaryOp::Less,
+                BinaryOp::BitXor,
+                BinaryOp::ShiftLeft,
+                BinaryOp::Subtract,
+                BinaryOp::Multiply,
+            ];
+            let ident = |name: &str| Expr {
+                kind: ExprKind::Ident(Symbol::intern(name)),
+                span: span(),
+            };
+            let kind = match if depth == 0 { 0 } else { self.below(16) } {
+                0 => ExprKind::Integer(self.below(10) as i64),
+                1 => ExprKind::Ident(Symbol::intern("a")),
+                2..=4 => ExprKind::Binary {
+                    op: OPS[self.below(OPS.len())],
+                    left: self.boxed(depth - 1),
+                    right: self.boxed(depth - 1),
+                },
+                5 => ExprKind::Unary {
+                    op: UnaryOp::Negate,
+                    operand: self.boxed(depth - 1),
+                },
+                6 => ExprKind::Assign {
+                    target: Box::new(match self.below(2) {
+                        0 => ident("b"),
+                        _ => Expr {
+                            kind: ExprKind::Field {
+                                target: self.boxed(depth - 1),
+                                name: Symbol::intern("c"),
+                            },
+                            span: span(),
+                        },
+                    }),
+                    value: self.boxed(depth - 1),
+                },
+                7 => ExprKind::Range {
+                    start: self.boxed(depth - 1),
+                    end: self.boxed(depth - 1),
+                    inclusive: self.below(2) == 0,
+                },
+                8 => ExprKind::Return(match self.below(2) {
+                    0 => None,
+                    _ => Some(self.boxed(depth - 1)),
+                }),
+                9 => ExprKind::Call {
+                    callee: self.boxed(depth - 1),
+                    args: vec![self.expr(depth - 1)],
+                },
+                10 => ExprKind::Slice {
+                    target: self.boxed(depth - 1),
+                    start: Some(self.boxed(depth - 1)),
+                    end: None,
+                },
+                11 => ExprKind::If {
+                    condition: self.boxed(depth - 1),
+                    then_branch: self.block(depth - 1),
+                    else_branch: match self.below(2) {
+                        0 => None,
+                        _ => Some(Box::new(Expr {
+                            kind: ExprKind::Block(self.block(depth - 1)),
+                            span: span(),
+                        })),
+                    },
+                },
+                12 => ExprKind::Match {
+                    scrutinee: self.boxed(depth - 1),
+                    arms: vec![MatchArm {
+                        pattern: Pattern {
+                            kind: PatternKind::Wildcard,
+                            span: span(),
+                        },
+                        guard: Some(self.expr(depth - 1)),
+                        body: self.expr(depth - 1),
+                        span: span(),
+                    }],
+                },
+                13 => ExprKind::Block(self.block(depth - 1)),
+                14 => ExprKind::Struct {
+                    path: vec![Symbol::intern("P")],
+                    fields: vec![(Symbol::intern("d"), self.expr(depth - 1))],
+                },
+                _ => ExprKind::Map(vec![(self.expr(depth - 1), self.expr(depth - 1))]),
+            };
+            Expr { kind, span: span() }
+        }
+    }
+
+    fn span() -> Span {
+        let start = Position::new(1, 0, 0);
+        Span::new(start, start)
+    }
+
+    #[test]
+    fn parenthesizes_random_trees() {
+        let mut random = Trees(Random(0x2545_f491_4f6c_dd1d));
+        for _ in 0..2000 {
+            let expr = random.expr(4);
+            let program = Program {
+                attributes: Vec::new(),
+                statements: vec![Stmt {
+                    kind: StmtKind::Expr(expr),
+                    span: span(),
+                }],
+            };
+            round_trip(&program);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::parser::ast::Program;
+
+// A compact binary encoding of syntax trees, for tools and caches that would
+// rather not go through JSON. After a header comes postcard's encoding of the
+// program, which tags enum variants by declaration order: variants of AST
+// types may only be appended, and any other change to the AST must bump
+// FORMAT_VERSION so that old readers reject the new layout.
+pub const MAGIC: &[u8; 4] = b"CLAY";
+pub const FORMAT_VERSION: u16 = 6;
+
+pub fn encode(program: &Program) -> Result<Vec<u8>, String> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    postcard::to_extend(program, bytes)
+        .map_err(|err| format!("could not encode syntax tree: {}", err))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Program, String> {
+    if bytes.len() < 6 || &bytes[..4] != MAGIC {
+        return Err("not a clay syntax tree".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "syntax tree format version {} is not supported, expected {}",
+            version, FORMAT_VERSION
+        ));
+    }
+    postcard::from_bytes(&bytes[6..]).map_err(|err| format!("malformed syntax tree: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::binary::{decode, encode};
+    use crate::parser::parser::parse;
+
+    #[test]
+    fn round_trips_programs() {
+        let source =
+            "import std::math; /// Doubles.\nfn f(x: List<Int>) -> Fn(Int) -> (Int, Bool) { if x > 0 { [x, 2.5] } else { #{ \"a\": (x,) } } }";
+        let program = parse(source).unwrap();
+        assert_eq!(decode(&encode(&program).unwrap()).unwrap(), program);
+    }
+
+    #[test]
+    fn keeps_node_tags_stable() {
+        let bytes = encode(&parse("1").unwrap()).unwrap();
+        #[rustfmt::skip]
+        let expected = vec![
+            b'C', b'L', b'A', b'Y', 6, 0,
+            0,                // no file attributes
+            1,                // one statement
+            0, 0, 2,          // StmtKind::Expr, ExprKind::Integer, zigzag-encoded 1
+            1, 0, 0, 1, 1, 1, // expression span
+            1, 0, 0, 1, 1, 1, // statement span
+        ];
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = encode(&parse("1").unwrap()).unwrap();
+        bytes[4] = 9;
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            "syntax tree format version 9 is not supported, expected 6"
+        );
+        assert_eq!(decode(b"{}").unwrap_err(), "not a clay syntax tree");
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::lexer::LexerOptions;
+use crate::lexer::token::{Position, Span};
+use crate::parser::ast::Program;
+use crate::parser::binary::{decode, encode};
+use crate::parser::parser::parse_with_options;
+
+// Where a project's parsed files are cached, relative to its manifest.
+pub const CACHE_DIR: &str = ".clay/cache";
+
+// The version of clay that writes the cache. Entries from other versions
+// are parsed again, since their trees may mean something else.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Syntax trees of files parsed before, kept on disk so that running or
+// checking a project again only parses the files that changed. Each file
+// has one entry, named after a hash of its path, which records the source
+// it was parsed from and how. Only files that parse are cached: errors are
+// found again each time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseCache {
+    dir: PathBuf,
+    // Makes an entry written by another version of clay or with other lexer
+    // options an error instead of a reason to parse again, and leaves out
+    // the trees of files it misses, for builds that must not change what the
+    // cache holds.
+    frozen: bool,
+}
+
+// What the cache holds for a file.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Hit(Program),
+    // Nothing, or the tree of other source.
+    Miss,
+    // The tree of this source, but written by another version of clay or with
+    // other lexer options, or an entry that can't be read.
+    Stale(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    version: String,
+    options: [bool; 3],
+    source: u64,
+    tree: Vec<u8>,
+}
+
+impl ParseCache {
+    pub fn new(dir: impl Into<PathBuf>, frozen: bool) -> ParseCache {
+        ParseCache {
+            dir: dir.into(),
+            frozen,
+        }
+    }
+
+    // Parses `source`, read from `path`, or takes its tree from the cache.
+    // Failing to write the cache only costs time later, so it isn't an error.
+    pub fn parse(
+        &self,
+        path: &Path,
+        source: &str,
+        options: LexerOptions,
+    ) -> Result<Program, Diagnostic> {
+        match self.lookup(path, source, options) {
+            Lookup::Hit(program) => return Ok(program),
+            Lookup::Stale(reason) if self.frozen => {
+                let start = Position::new(1, 0, 0);
+                return Err(Diagnostic::error(
+                    format!("the cached syntax tree of this file is stale: {}", reason),
+                    Span::new(start, start),
+                )
+                .with_note(
+                    "`--frozen` keeps the cache as it is; run without it to parse the file again",
+                ));
+            }
+            Lookup::Stale(_) | Lookup::Miss => {}
+        }
+        let program = parse_with_options(source, options)?;
+        if !self.frozen {
+            let _ = self.store(path, source, options, &program);
+        }
+        Ok(program)
+    }
+
+    pub fn lookup(&self, path: &Path, source: &str, options: LexerOptions) -> Lookup {
+        let bytes = match fs::read(self.entry(path)) {
+            Ok(bytes) => bytes,
+            Err(_) => return Lookup::Miss,
+        };
+        let entry: Entry = match postcard::from_bytes(&bytes) {
+            Ok(entry) => entry,
+            Err(_) => return Lookup::Stale("its entry can't be read".to_string()),
+        };
+        if entry.source != hash(source.as_bytes()) {
+            return Lookup::Miss;
+        }
+        if entry.version != VERSION {
+            return Lookup::Stale(format!("it was written by clay {}", entry.version));
+        }
+        if entry.options != flags(options) {
+            return Lookup::Stale("it was written with other lexer options".to_string());
+        }
+        match decode(&entry.tree) {
+            Ok(program) => Lookup::Hit(program),
+            Err(err) => Lookup::Stale(err),
+        }
+    }
+
+    // Writes the entry beside where it goes and then moves it there, so
+    // another clay reading the cache never sees half of it.
+    fn store(
+        &self,
+        path: &Path,
+        source: &str,
+        options: LexerOptions,
+        program: &Program,
+    ) -> Result<(), String> {
+        let entry = Entry {
+            version: VERSION.to_string(),
+            options: flags(options),
+            source: hash(source.as_bytes()),
+            tree: encode(program)?,
+        };
+        let bytes = postcard::to_allocvec(&entry).map_err(|err| err.to_string())?;
+        let target = self.entry(path);
+        let written = target.with_extension(format!("{}.tmp", process::id()));
+        fs::create_dir_all(&self.dir).map_err(|err| err.to_string())?;
+        fs::write(&written, bytes).map_err(|err| err.to_string())?;
+        fs::rename(&written, &target).map_err(|err| {
+            let _ = fs::remove_file(&written);
+            err.to_string()
+        })
+    }
+
+    fn entry(&self, path: &Path) -> PathBuf {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let name = hash(path.to_string_lossy().as_bytes());
+        self.dir.join(format!("{:016x}.ast", name))
+    }
+}
+
+fn flags(options: LexerOptions) -> [bool; 3] {
+    [options.strict, options.separators, options.suffixes]
+}
+
+// FNV-1a, which unlike the standard library's hasher gives the same hash in
+// every build of clay.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::lexer::lexer::LexerOptions;
+    use crate::parser::cache::{Entry, Lookup, ParseCache};
+    use crate::parser::parser::parse;
+
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("clay-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.clay");
+        fs::write(&file, "").unwrap();
+        (dir.join("cache"), file)
+    }
+
+    #[test]
+    fn reuses_trees_until_the_source_changes() {
+        let (dir, file) = setup("reuse");
+        let cache = ParseCache::new(&dir, false);
+        let options = LexerOptions::default();
+        assert_eq!(cache.lookup(&file, "1 + 2", options), Lookup::Miss);
+        let program = cache.parse(&file, "1 + 2", options).unwrap();
+        assert_eq!(program, parse("1 + 2").unwrap());
+        assert_eq!(cache.lookup(&file, "1 + 2", options), Lookup::Hit(program));
+
+        assert_eq!(cache.lookup(&file, "1 + 3", options), Lookup::Miss);
+        cache.parse(&file, "1 + 3", options).unwrap();
+        assert_eq!(cache.lookup(&file, "1 + 2", options), Lookup::Miss);
+        assert!(cache.parse(&file, "1 +", options).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn parses_stale_trees_again_unless_frozen() {
+        let (dir, file) = setup("stale");
+        let options = LexerOptions::default();
+        let strict = LexerOptions {
+            strict: true,
+            ..options
+        };
+        let cache = ParseCache::new(&dir, false);
+        cache.parse(&file, "1", options).unwrap();
+        let stale = Lookup::Stale("it was written with other lexer options".to_string());
+        assert_eq!(cache.lookup(&file, "1", strict), stale);
+
+        let frozen = ParseCache::new(&dir, true);
+        let error = frozen.parse(&file, "1", strict).unwrap_err();
+        assert_eq!(
+            error.message,
+            "the cached syntax tree of this file is stale: it was written with other lexer options"
+        );
+        cache.parse(&file, "1", strict).unwrap();
+        assert!(matches!(cache.lookup(&file, "1", strict), Lookup::Hit(_)));
+
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut old: Entry = postcard::from_bytes(&fs::read(&entry).unwrap()).unwrap();
+        old.version = "0.0.1".to_string();
+        fs::write(&entry, postcard::to_allocvec(&old).unwrap()).unwrap();
+        let stale = Lookup::Stale("it was written by clay 0.0.1".to_string());
+        assert_eq!(cache.lookup(&file, "1", strict), stale);
+        assert!(frozen.parse(&file, "1", strict).is_err());
+
+        fs::write(&entry, b"not an entry").unwrap();
+        let stale = Lookup::Stale("its entry can't be read".to_string());
+        assert_eq!(cache.lookup(&file, "1", strict), stale);
+        assert_eq!(
+            cache.parse(&file, "1", strict).unwrap(),
+            parse("1").unwrap()
+        );
+        assert!(matches!(cache.lookup(&file, "1", strict), Lookup::Hit(_)));
+    }
+
+    #[test]
+    fn leaves_the_directory_alone_when_frozen() {
+        let (dir, file) = setup("frozen");
+        let frozen = ParseCache::new(&dir, true);
+        let options = LexerOptions::default();
+        assert_eq!(
+            frozen.parse(&file, "1", options).unwrap(),
+            parse("1").unwrap()
+        );
+        assert!(!dir.exists());
+
+        ParseCache::new(&dir, false)
+            .parse(&file, "1", options)
+            .unwrap();
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let written = fs::read(&entry).unwrap();
+        frozen.parse(&file, "2", options).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(fs::read(&entry).unwrap(), written);
+    }
+}
@@ -0,0 +1,880 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::{Span, Token, TokenType};
+use crate::lexer::tree::{flatten_into, token_trees, Delimiter, Group, TokenTree};
+
+// How deeply macros may expand to calls of macros. Expansion recurses on
+// them, so deeper expansions, like a macro that calls itself forever, are
+// an error rather than a stack overflow.
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+// How many tokens a file may expand to. A macro that uses its argument
+// twice, called on a call of itself, doubles with each call, so this stops
+// a short file from expanding without end.
+pub const MAX_EXPANDED_TOKENS: usize = 1_000_000;
+
+// The macro calls expanded into a file's tokens, so errors in what they
+// expanded to can name the call as well as the macro's definition.
+#[derive(Debug, Default)]
+pub struct Expansions {
+    expansions: Vec<Expansion>,
+}
+
+#[derive(Debug)]
+struct Expansion {
+    name: Symbol,
+    call: Span,
+    // The tokens the call expanded to, as indices into the expanded tokens.
+    start: usize,
+    end: usize,
+}
+
+impl Expansions {
+    // Notes the innermost expansion the token at `index` came from, or
+    // that it follows, since an expansion that ends too soon is found wrong
+    // at the token after it.
+    pub fn explain(&self, diagnostic: Diagnostic, index: usize) -> Diagnostic {
+        let expansion = self
+            .expansions
+            .iter()
+            .filter(|expansion| expansion.start <= index && index <= expansion.end)
+            .min_by_key(|expansion| expansion.end - expansion.start);
+        match expansion {
+            Some(expansion) => diagnostic.with_note(format!(
+                "in the expansion of `{}!` at {}:{}",
+                expansion.name,
+                expansion.call.start.line,
+                expansion.call.start.column + 1
+            )),
+            None => diagnostic,
+        }
+    }
+}
+
+// Expands the macros a file defines with `macro name { (pattern) => { template } }`
+// wherever it calls them as `name!(...)`, leaving the definitions out. Files
+// that define no macros come back as they are.
+pub fn expand(tokens: Vec<Token>) -> Result<(Vec<Token>, Expansions), Diagnostic> {
+    if first_definition(&tokens).is_none() {
+        return Ok((tokens, Expansions::default()));
+    }
+
+    let names = tokens
+        .iter()
+        .filter_map(|token| match token.kind {
+            TokenType::Ident(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        expansions: Expansions::default(),
+        hygiene: 0,
+        names,
+    };
+    let trees = expander.define(token_trees(tokens)?)?;
+    let mut out = Vec::new();
+    expander.expand(&trees, &mut out, 0)?;
+    Ok((out, expander.expansions))
+}
+
+// Where the first `macro name {` among `tokens` is.
+pub fn first_definition(tokens: &[Token]) -> Option<Span> {
+    let window = tokens.windows(3).find(|window| {
+        matches!(window[0].kind, TokenType::Ident(name) if name == "macro")
+            && matches!(window[1].kind, TokenType::Ident(_))
+            && window[2].kind == TokenType::LBrace
+    })?;
+    Some(window[0].span.to(window[1].span))
+}
+
+// What a macro variable matches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fragment {
+    // The tokens up to what follows the variable in the pattern, put in
+    // parentheses where they are used so they stay one expression.
+    Expr,
+    Ident,
+    // One token or bracketed group.
+    Tt,
+}
+
+#[derive(Debug)]
+enum Matcher<'a> {
+    Token(TokenType<'a>),
+    Group(TokenType<'a>, Vec<Matcher<'a>>),
+    Variable(Symbol, Fragment),
+    // `$( ... ) sep *` or `+`, and whether it must match at least once.
+    Repeat(Vec<Matcher<'a>>, Option<TokenType<'a>>, bool),
+}
+
+#[derive(Debug)]
+enum Piece<'a> {
+    Token(Token<'a>),
+    Group(Token<'a>, Vec<Piece<'a>>, Option<Token<'a>>),
+    Variable(Symbol, Span),
+    Repeat(Vec<Piece<'a>>, Option<Token<'a>>, Span),
+}
+
+#[derive(Debug)]
+struct Rule<'a> {
+    pattern: Vec<Matcher<'a>>,
+    template: Vec<Piece<'a>>,
+    // The names the template binds with `let` and `for`, which are renamed
+    // in each expansion so they can't capture or shadow the caller's.
+    hygienic: Vec<Symbol>,
+}
+
+#[derive(Debug)]
+struct Macro<'a> {
+    span: Span,
+    rules: Vec<Rule<'a>>,
+}
+
+// What a variable matched in one call.
+#[derive(Debug, Clone)]
+enum Binding<'a> {
+    One(Vec<TokenTree<'a>>, Fragment),
+    // What it matched each time around a repetition.
+    Many(Vec<Binding<'a>>),
+}
+
+type Bindings<'a> = HashMap<Symbol, Binding<'a>>;
+
+struct Expander<'a> {
+    macros: HashMap<Symbol, Macro<'a>>,
+    expansions: Expansions,
+    // How many expansions introduced hygienic names, to number them apart.
+    hygiene: usize,
+    // The names in the file and those hygiene made up, which new ones must
+    // differ from.
+    names: HashSet<Symbol>,
+}
+
+impl<'a> Expander<'a> {
+    // Takes the macro definitions out of `trees`.
+    fn define(&mut self, trees: Vec<TokenTree<'a>>) -> Result<Vec<TokenTree<'a>>, Diagnostic> {
+        let mut rest = Vec::new();
+        let mut trees = trees.into_iter().peekable();
+        while let Some(tree) = trees.next() {
+            let keyword = match &tree {
+                TokenTree::Token(token) if matches!(token.kind, TokenType::Ident(name) if name == "macro") => {
+                    *token
+                }
+                _ => {
+                    rest.push(tree);
+                    continue;
+                }
+            };
+            let name = match trees.peek() {
+                Some(TokenTree::Token(Token {
+                    kind: TokenType::Ident(name),
+                    span,
+                })) => (*name, *span),
+                _ => {
+                    rest.push(tree);
+                    continue;
+                }
+            };
+            trees.next();
+            let body = match trees.next() {
+                Some(TokenTree::Group(Group {
+                    delimiter: Delimiter::Brace,
+                    trees: body,
+                    close: Some(_),
+                    ..
+                })) => body,
+                other => {
+                    let span = other.map_or(name.1, |tree| tree.span());
+                    return Err(Diagnostic::error(
+                        format!("expected `{{` after the name of macro `{}`", name.0),
+                        span,
+                    ));
+                }
+            };
+            if let Some(defined) = self.macros.get(&name.0) {
+                return Err(Diagnostic::error(
+                    format!("macro `{}` is already defined", name.0),
+                    name.1,
+                )
+                .with_note(format!(
+                    "`{}` was first defined at {}:{}",
+                    name.0,
+                    defined.span.start.line,
+                    defined.span.start.column + 1
+                )));
+            }
+            let rules = rules(body, keyword.span.to(name.1))?;
+            let span = keyword.span.to(name.1);
+            self.macros.insert(name.0, Macro { span, rules });
+        }
+        Ok(rest)
+    }
+
+    fn expand(
+        &mut self,
+        trees: &[TokenTree<'a>],
+        out: &mut Vec<Token<'a>>,
+        depth: usize,
+    ) -> Result<(), Diagnostic> {
+        let mut index = 0;
+        while index < trees.len() {
+            let call = match (&trees[index], trees.get(index + 1), trees.get(index + 2)) {
+                (
+                    TokenTree::Token(Token {
+                        kind: TokenType::Ident(name),
+                        span,
+                    }),
+                    Some(TokenTree::Token(Token {
+                        kind: TokenType::Bang,
+                        ..
+                    })),
+                    Some(TokenTree::Group(Group {
+                        trees: args,
+                        close: Some(close),
+                        ..
+                    })),
+                ) if self.macros.contains_key(name) => Some((*name, span.to(close.span), args)),
+                _ => None,
+            };
+            match (call, &trees[index]) {
+                (Some((name, span, args)), _) => {
+                    if depth == MAX_EXPANSION_DEPTH {
+                        return Err(Diagnostic::error(
+                            format!("macros expand more than {} calls deep", MAX_EXPANSION_DEPTH),
+                            span,
+                        ));
+                    }
+                    if out.len() > MAX_EXPANDED_TOKENS {
+                        return Err(Diagnostic::error(
+                            format!("macros expand to more than {} tokens", MAX_EXPANDED_TOKENS),
+                            span,
+                        ));
+                    }
+                    let expanded = self.call(name, span, args)?;
+                    let start = out.len();
+                    self.expand(&token_trees(expanded)?, out, depth + 1)?;
+                    self.expansions.expansions.push(Expansion {
+                        name,
+                        call: span,
+                        start,
+                        end: out.len(),
+                    });
+                    index += 3;
+                }
+                (None, TokenTree::Group(group)) => {
+                    out.push(group.open);
+                    self.expand(&group.trees, out, depth)?;
+                    out.extend(group.close);
+                    index += 1;
+                }
+                (None, TokenTree::Token(token)) => {
+                    out.push(*token);
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The tokens a call of macro `name` with `args` expands to, before the
+    // macros they call are expanded.
+    fn call(
+        &mut self,
+        name: Symbol,
+        span: Span,
+        args: &[TokenTree<'a>],
+    ) -> Result<Vec<Token<'a>>, Diagnostic> {
+        let definition = match self.macros.get(&name) {
+            Some(definition) => definition,
+            None => {
+                return Err(Diagnostic::error(
+                    format!("no macro named `{}`", name),
+                    span,
+                ))
+            }
+        };
+        for rule in &definition.rules {
+            let mut bindings = Bindings::new();
+            if match_all(&rule.pattern, args, &mut bindings) {
+                self.hygiene += 1;
+                let mut renames = HashMap::new();
+                for &name in &rule.hygienic {
+                    renames.insert(name, fresh(&mut self.names, name, self.hygiene));
+                }
+                let mut out = Vec::new();
+                transcribe(&rule.template, &bindings, &renames, &mut out)?;
+                return Ok(out);
+            }
+        }
+        Err(Diagnostic::error(
+            format!("no rule of macro `{}` matches this call", name),
+            span,
+        )
+        .with_note(format!(
+            "`{}` is defined at {}:{}",
+            name,
+            definition.span.start.line,
+            definition.span.start.column + 1
+        )))
+    }
+}
+
+// A name for a binding of `name` a template introduces, as `name_N` with
+// the number of the expansion, or a later number while that is among
+// `names`. The tree prints it like any name, so it parses back the same.
+fn fresh(names: &mut HashSet<Symbol>, name: Symbol, mut number: usize) -> Symbol {
+    loop {
+        let fresh = Symbol::intern(&format!("{}_{}", name, number));
+        if names.insert(fresh) {
+            return fresh;
+        }
+        number += 1;
+    }
+}
+
+// The rules in the body of a macro, as `(pattern) => { template }`, apart
+// by `;` or `,`.
+fn rules(body: Vec<TokenTree>, span: Span) -> Result<Vec<Rule>, Diagnostic> {
+    let mut rules = Vec::new();
+    let mut body = body.into_iter().peekable();
+    while let Some(tree) = body.next() {
+        let pattern = match tree {
+            TokenTree::Group(Group {
+                trees: pattern,
+                close: Some(_),
+                ..
+            }) => pattern,
+            tree => {
+                return Err(Diagnostic::error(
+                    "expected a macro rule, like `($x) => { $x }`",
+                    tree.span(),
+                ))
+            }
+        };
+        let arrow = body.next();
+        if !matches!(&arrow, Some(tree) if tree.kind() == TokenType::FatArrow) {
+            let span = arrow.map_or(span, |tree| tree.span());
+            return Err(Diagnostic::error(
+                "expected `=>` after the pattern of a macro rule",
+                span,
+            ));
+        }
+        let template = match body.next() {
+            Some(TokenTree::Group(Group {
+                delimiter: Delimiter::Brace,
+                trees: template,
+                close: Some(_),
+                ..
+            })) => template,
+            other => {
+                let span = other.map_or(span, |tree| tree.span());
+                return Err(Diagnostic::error(
+                    "expected `{` to start the template of a macro rule",
+                    span,
+                ));
+            }
+        };
+        let pattern = matchers(pattern)?;
+        let template = pieces(template)?;
+        let mut bound = Vec::new();
+        variables(&pattern, &mut bound);
+        check_variables(&template, &bound)?;
+        let mut hygienic = Vec::new();
+        binders(&template, &mut None, &mut hygienic);
+        rules.push(Rule {
+            pattern,
+            template,
+            hygienic,
+        });
+        if let Some(tree) = body.peek() {
+            if matches!(tree.kind(), TokenType::Semicolon | TokenType::Comma) {
+                body.next();
+            }
+        }
+    }
+    if rules.is_empty() {
+        return Err(Diagnostic::error("a macro needs at least one rule", span));
+    }
+    Ok(rules)
+}
+
+fn matchers(trees: Vec<TokenTree>) -> Result<Vec<Matcher>, Diagnostic> {
+    let mut matchers = Vec::new();
+    let mut trees = trees.into_iter().peekable();
+    while let Some(tree) = trees.next() {
+        let dollar = match tree {
+            TokenTree::Token(token) if token.kind == TokenType::Dollar => token,
+            TokenTree::Token(token) => {
+                matchers.push(Matcher::Token(token.kind));
+                continue;
+            }
+            TokenTree::Group(group) => {
+                let inner = self::matchers(group.trees)?;
+                matchers.push(Matcher::Group(group.open.kind, inner));
+                continue;
+            }
+        };
+        match trees.next() {
+            Some(TokenTree::Token(Token {
+                kind: TokenType::Ident(name),
+                ..
+            })) => {
+                let mut fragment = Fragment::Expr;
+                if matches!(trees.peek(), Some(tree) if tree.kind() == TokenType::Colon) {
+                    trees.next();
+                    fragment = match trees.next() {
+                        Some(TokenTree::Token(Token {
+                            kind: TokenType::Ident(kind),
+                            span,
+                        })) => match kind.as_str() {
+                            "expr" => Fragment::Expr,
+                            "ident" => Fragment::Ident,
+                            "tt" => Fragment::Tt,
+                            _ => {
+                                return Err(Diagnostic::error(
+                                    format!("unknown fragment `{}`", kind),
+                                    span,
+                                )
+                                .with_note("variables match an `expr`, an `ident` or a `tt`"))
+                            }
+                        },
+                        other => {
+                            let span = other.map_or(dollar.span, |tree| tree.span());
+                            return Err(Diagnostic::error(
+                                "expected `expr`, `ident` or `tt` after `:`",
+                                span,
+                            ));
+                        }
+                    };
+                }
+                matchers.push(Matcher::Variable(name, fragment));
+            }
+            Some(TokenTree::Group(group)) if group.delimiter == Delimiter::Paren => {
+                let (separator, at_least_once) = repetition(&mut trees, group.open.span)?;
+                let separator = separator.map(|token| token.kind);
+                matchers.push(Matcher::Repeat(
+                    self::matchers(group.trees)?,
+                    separator,
+                    at_least_once,
+                ));
+            }
+            other => {
+                let span = other.map_or(dollar.span, |tree| tree.span());
+                return Err(Diagnostic::error(
+                    "expected a variable name or `(` after `$`",
+                    span,
+                ));
+            }
+        }
+    }
+    Ok(matchers)
+}
+
+// The separator and `*` or `+` after the `$( ... )` of a repetition.
+fn repetition<'a>(
+    trees: &mut std::iter::Peekable<std::vec::IntoIter<TokenTree<'a>>>,
+    span: Span,
+) -> Result<(Option<Token<'a>>, bool), Diagnostic> {
+    let mut separator = None;
+    for _ in 0..2 {
+        match trees.next() {
+            Some(TokenTree::Token(token)) if token.kind == TokenType::Asterisk => {
+                return Ok((separator, false))
+            }
+            Some(TokenTree::Token(token)) if token.kind == TokenType::Plus => {
+                return Ok((separator, true))
+            }
+            Some(TokenTree::Token(token)) if separator.is_none() => separator = Some(token),
+            other => {
+                let span = other.map_or(span, |tree| tree.span());
+                return Err(Diagnostic::error(
+                    "expected `*` or `+` after a repetition",
+                    span,
+                ));
+            }
+        }
+    }
+    Err(Diagnostic::error(
+        "expected `*` or `+` after a repetition",
+        separator.map_or(span, |token: Token| token.span),
+    ))
+}
+
+fn pieces(trees: Vec<TokenTree>) -> Result<Vec<Piece>, Diagnostic> {
+    let mut pieces = Vec::new();
+    let mut trees = trees.into_iter().peekable();
+    while let Some(tree) = trees.next() {
+        let dollar = match tree {
+            TokenTree::Token(token) if token.kind == TokenType::Dollar => token,
+            TokenTree::Token(token) => {
+                pieces.push(Piece::Token(token));
+                continue;
+            }
+            TokenTree::Group(group) => {
+                let inner = self::pieces(group.trees)?;
+                pieces.push(Piece::Group(group.open, inner, group.close));
+                continue;
+            }
+        };
+        match trees.next() {
+            Some(TokenTree::Token(Token {
+                kind: TokenType::Ident(name),
+                span,
+            })) => pieces.push(Piece::Variable(name, dollar.span.to(span))),
+            Some(TokenTree::Group(group)) if group.delimiter == Delimiter::Paren => {
+                let (separator, _) = repetition(&mut trees, group.open.span)?;
+                pieces.push(Piece::Repeat(
+                    self::pieces(group.trees)?,
+                    separator,
+                    dollar.span.to(group.open.span),
+                ));
+            }
+            other => {
+                let span = other.map_or(dollar.span, |tree| tree.span());
+                return Err(Diagnostic::error(
+                    "expected a variable name or `(` after `$`",
+                    span,
+                ));
+            }
+        }
+    }
+    Ok(pieces)
+}
+
+// The variables a pattern binds.
+fn variables(matchers: &[Matcher], out: &mut Vec<Symbol>) {
+    for matcher in matchers {
+        match matcher {
+            Matcher::Variable(name, _) => out.push(*name),
+            Matcher::Group(_, inner) | Matcher::Repeat(inner, _, _) => variables(inner, out),
+            Matcher::Token(_) => {}
+        }
+    }
+}
+
+fn check_variables(pieces: &[Piece], bound: &[Symbol]) -> Result<(), Diagnostic> {
+    for piece in pieces {
+        match piece {
+            Piece::Variable(name, span) if !bound.contains(name) => {
+                return Err(Diagnostic::error(
+                    format!("`${}` isn't bound by the rule's pattern", name),
+                    *span,
+                ))
+            }
+            Piece::Group(_, inner, _) | Piece::Repeat(inner, _, _) => {
+                check_variables(inner, bound)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The names a template binds: those after `let`, `let mut` and `for`.
+// `previous` is the token before the piece being looked at.
+fn binders<'a>(pieces: &[Piece<'a>], previous: &mut Option<TokenType<'a>>, out: &mut Vec<Symbol>) {
+    for piece in pieces {
+        match piece {
+            Piece::Token(token) => {
+                if let TokenType::Ident(name) = token.kind {
+                    let binds = matches!(
+                        previous,
+                        Some(TokenType::Let) | Some(TokenType::Mut) | Some(TokenType::For)
+                    );
+                    if binds && !out.contains(&name) {
+                        out.push(name);
+                    }
+                }
+                *previous = Some(token.kind);
+            }
+            Piece::Group(open, inner, _) => {
+                *previous = Some(open.kind);
+                binders(inner, previous, out);
+                *previous = None;
+            }
+            Piece::Variable(..) | Piece::Repeat(..) => *previous = None,
+        }
+    }
+}
+
+// Whether `matchers` match all of `input`, binding their variables.
+fn match_all<'a>(
+    matchers: &[Matcher<'a>],
+    input: &[TokenTree<'a>],
+    bindings: &mut Bindings<'a>,
+) -> bool {
+    match_prefix(matchers, input, 0, &[], bindings) == Some(input.len())
+}
+
+// Matches `matchers` against `input` from `position`, returning where the
+// match ends. `follow` is what may come after the match, where an `expr`
+// ending it stops.
+fn match_prefix<'a>(
+    matchers: &[Matcher<'a>],
+    input: &[TokenTree<'a>],
+    mut position: usize,
+    follow: &[TokenType<'a>],
+    bindings: &mut Bindings<'a>,
+) -> Option<usize> {
+    for (index, matcher) in matchers.iter().enumerate() {
+        let next = match matchers.get(index + 1) {
+            Some(Matcher::Token(kind)) | Some(Matcher::Group(kind, _)) => vec![*kind],
+            Some(_) => Vec::new(),
+            None => follow.to_vec(),
+        };
+        match matcher {
+            Matcher::Token(kind) => {
+                if input.get(position)?.kind() != *kind
+                    || matches!(input[position], TokenTree::Group(..))
+                {
+                    return None;
+                }
+                position += 1;
+            }
+            Matcher::Group(kind, inner) => match input.get(position)? {
+                TokenTree::Group(group) if group.open.kind == *kind => {
+                    if !match_all(inner, &group.trees, bindings) {
+                        return None;
+                    }
+                    position += 1;
+                }
+                _ => return None,
+            },
+            Matcher::Variable(name, fragment) => {
+                let end = match fragment {
+                    Fragment::Ident => match input.get(position)? {
+                        TokenTree::Token(Token {
+                            kind: TokenType::Ident(_),
+                            ..
+                        }) => position + 1,
+                        _ => return None,
+                    },
+                    Fragment::Tt => {
+                        input.get(position)?;
+                        position + 1
+                    }
+                    Fragment::Expr => {
+                        let rest = &input[position..];
+                        let length = rest
+                            .iter()
+                            .position(|tree| next.contains(&tree.kind()))
+                            .unwrap_or(rest.len());
+                        if length == 0 {
+                            return None;
+                        }
+                        position + length
+                    }
+                };
+                let matched = input[position..end].to_vec();
+                bindings.insert(*name, Binding::One(matched, *fragment));
+                position = end;
+            }
+            Matcher::Repeat(inner, separator, at_least_once) => {
+                let mut inner_follow = next.clone();
+                inner_follow.extend(separator);
+                let mut times: Vec<Bindings<'a>> = Vec::new();
+                loop {
+                    let mut start = position;
+                    if let (Some(separator), false) = (separator, times.is_empty()) {
+                        match input.get(start) {
+                            Some(TokenTree::Token(token)) if token.kind == *separator => start += 1,
+                            _ => break,
+                        }
+                    }
+                    let mut found = Bindings::new();
+                    match match_prefix(inner, input, start, &inner_follow, &mut found) {
+                        Some(end) if end > position => {
+                            position = end;
+                            times.push(found);
+                        }
+                        _ => break,
+                    }
+                }
+                if *at_least_once && times.is_empty() {
+                    return None;
+                }
+                let mut names = Vec::new();
+                variables(inner, &mut names);
+                for name in names {
+                    let each = times
+                        .iter_mut()
+                        .filter_map(|found| found.remove(&name))
+                        .collect();
+                    bindings.insert(name, Binding::Many(each));
+                }
+            }
+        }
+    }
+    Some(position)
+}
+
+fn transcribe<'a>(
+    pieces: &[Piece<'a>],
+    bindings: &Bindings<'a>,
+    renames: &HashMap<Symbol, Symbol>,
+    out: &mut Vec<Token<'a>>,
+) -> Result<(), Diagnostic> {
+    for piece in pieces {
+        match piece {
+            Piece::Token(token) => {
+                let kind = match token.kind {
+                    TokenType::Ident(name) => {
+                        TokenType::Ident(renames.get(&name).copied().unwrap_or(name))
+                    }
+                    kind => kind,
+                };
+                out.push(Token::new(kind, token.span));
+            }
+            Piece::Group(open, inner, close) => {
+                out.push(*open);
+                transcribe(inner, bindings, renames, out)?;
+                out.extend(close);
+            }
+            Piece::Variable(name, span) => match bindings.get(name) {
+                Some(Binding::One(trees, fragment)) => {
+                    let wrap = match (trees.first(), trees.last()) {
+                        (Some(first), Some(last))
+                            if *fragment == Fragment::Expr && trees.len() > 1 =>
+                        {
+                            Some(first.span().to(last.span()))
+                        }
+                        _ => None,
+                    };
+                    if let Some(whole) = wrap {
+                        out.push(Token::new(TokenType::LParen, whole));
+                    }
+                    flatten_into(trees, out);
+                    if let Some(whole) = wrap {
+                        out.push(Token::new(TokenType::RParen, whole));
+                    }
+                }
+                Some(Binding::Many(_)) => {
+                    return Err(Diagnostic::error(
+                        format!("`${}` repeats, so it must be used inside `$( ... )*`", name),
+                        *span,
+                    ))
+                }
+                None => {
+                    return Err(Diagnostic::error(
+                        format!("`${}` isn't bound by the rule's pattern", name),
+                        *span,
+                    ))
+                }
+            },
+            Piece::Repeat(inner, separator, span) => {
+                let mut used = Vec::new();
+                used_variables(inner, &mut used);
+                let mut times: Option<usize> = None;
+                for name in &used {
+                    if let Some(Binding::Many(each)) = bindings.get(name) {
+                        match times {
+                            Some(times) if times != each.len() => {
+                                return Err(Diagnostic::error(
+                                    "the variables of this repetition repeat a different number of times",
+                                    *span,
+                                ))
+                            }
+                            _ => times = Some(each.len()),
+                        }
+                    }
+                }
+                let times = times.ok_or_else(|| {
+                    Diagnostic::error("a repetition must use a variable that repeats", *span)
+                })?;
+                for time in 0..times {
+                    if time > 0 {
+                        out.extend(separator);
+                    }
+                    let mut each = bindings.clone();
+                    for name in &used {
+                        if let Some(Binding::Many(bound)) = bindings.get(name) {
+                            if let Some(binding) = bound.get(time) {
+                                each.insert(*name, binding.clone());
+                            }
+                        }
+                    }
+                    transcribe(inner, &each, renames, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn used_variables(pieces: &[Piece], out: &mut Vec<Symbol>) {
+    for piece in pieces {
+        match piece {
+            Piece::Variable(name, _) if !out.contains(name) => out.push(*name),
+            Piece::Group(_, inner, _) | Piece::Repeat(inner, _, _) => used_variables(inner, out),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parser::parse;
+
+    fn expands_to(source: &str, expected: &str) {
+        let expanded = parse(source).unwrap().to_string();
+        assert_eq!(expanded, parse(expected).unwrap().to_string());
+    }
+
+    #[test]
+    fn expands_calls_with_the_first_rule_that_matches() {
+        expands_to(
+            "macro square { ($x) => { $x * $x } }\n\
+             macro list { () => { [] }; ($($item),+) => { [$($item * 10),+] } }\n\
+             square!(1 + 2); list!(); list![1, f(2, 3)]; square!(square!(2))",
+            "(1 + 2) * (1 + 2); []; [1 * 10, f(2, 3) * 10]; (2 * 2) * (2 * 2)",
+        );
+        expands_to(
+            "macro unless { ($condition { $($body:tt)* }) => { if !$condition { $($body)* } } }\n\
+             unless!(a < b { print(a); b })",
+            "if !(a < b) { print(a); b }",
+        );
+    }
+
+    #[test]
+    fn renames_the_names_a_template_binds() {
+        let program = parse(
+            "macro swap { ($a:ident, $b:ident) => { let tmp = $a; $a = $b; $b = tmp; } }\n\
+             swap!(tmp, other); swap!(x, tmp_2)",
+        )
+        .unwrap();
+        // `tmp_2` is taken, so the second expansion's `tmp` is `tmp_3`.
+        let printed = "let tmp_1 = tmp;\ntmp = other;\nother = tmp_1;\n\
+                       let tmp_3 = x;\nx = tmp_2;\ntmp_2 = tmp_3;";
+        assert_eq!(program.to_string(), printed);
+        assert_eq!(parse(printed).unwrap().to_string(), printed);
+    }
+
+    #[test]
+    fn points_errors_at_the_definition_and_the_call() {
+        let error = parse("macro pair { ($a, $b) => { [$a, $b] } }\n\npair!(1)").unwrap_err();
+        assert_eq!(error.message, "no rule of macro `pair` matches this call");
+        assert_eq!(error.span.start.line, 3);
+        assert_eq!(error.notes(), ["`pair` is defined at 1:1"]);
+
+        let error = parse("macro bad {\n  ($x) => { $x + ) }\n}\nbad!(1)").unwrap_err();
+        assert_eq!(error.span.start.line, 2);
+        assert_eq!(error.notes(), ["in the expansion of `bad!` at 4:1"]);
+
+        let error = parse("macro f { ($x) => { $y } }").unwrap_err();
+        assert_eq!(error.message, "`$y` isn't bound by the rule's pattern");
+
+        let error = parse("macro forever { () => { forever!() } }\nforever!()").unwrap_err();
+        assert_eq!(error.message, "macros expand more than 64 calls deep");
+
+        let source = format!(
+            "macro twice {{ ($x) => {{ [$x, $x] }} }}\n{}1{}",
+            "twice!(".repeat(40),
+            ")".repeat(40)
+        );
+        let error = parse(&source).unwrap_err();
+        assert_eq!(error.message, "macros expand to more than 1000000 tokens");
+    }
+}
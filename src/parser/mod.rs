@@ -0,0 +1,20 @@
+// No input may panic the frontend: what it can't read is a diagnostic.
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::todo,
+        clippy::unimplemented
+    )
+)]
+
+pub mod ast;
+pub mod binary;
+pub mod cache;
+pub mod macros;
+#[allow(clippy::module_inception)]
+pub mod parser;
+pub mod visit;
@@ -0,0 +1,2854 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::diagnostic::suggest::edit_distance;
+use crate::interpreter::stdlib::prelude_enum;
+use crate::lexer::lexer::{dedent, unescape, Lexer, LexerOptions};
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::{Comment, Position, Span, Token, TokenType, KEYWORDS};
+use crate::parser::ast::{
+    Associativity, Attribute, BinaryOp, Block, EnumDecl, Expr, ExprKind, Field, Function, ImplDecl,
+    Import, ImportMember, ImportNames, ImportPath, MatchArm, MethodSig, Param, Pattern,
+    PatternKind, Program, Stmt, StmtKind, StructDecl, TraitDecl, TypeExpr, TypeExprKind, UnaryOp,
+    Variant,
+};
+use crate::parser::macros;
+
+// Binary operators bind with powers taken from `BinaryOp::precedence`, from
+// 3 for `||` up to 21 for `*`; see `binding_power`.
+pub(crate) const ASSIGNMENT_POWER: (u8, u8) = (2, 1);
+// Ranges bind more loosely than any other operator: `0..n + 1` ends at `n + 1`.
+pub(crate) const RANGE_POWER: (u8, u8) = (2, 3);
+// So do pipelines: `n + 1 |> f` is `f(n + 1)`.
+pub(crate) const PIPELINE_POWER: (u8, u8) = (2, 3);
+pub(crate) const PREFIX_POWER: u8 = 23;
+pub(crate) const CALL_POWER: u8 = 25;
+
+// How deeply expressions, blocks, patterns and types may nest. Parsing
+// recurses on nesting, so deeper input is an error rather than a stack
+// overflow.
+pub const MAX_DEPTH: usize = 64;
+
+// How many operators, calls, field accesses and `else if`s may be chained,
+// counting those of the expressions a chain is part of. Each one nests the
+// tree a level deeper without nesting the source, and whatever walks the
+// tree recurses on it, so a longer chain is an error too.
+pub const MAX_CHAIN: usize = 1024;
+
+// Parses a file. No input makes the lexer, macro expansion or the parser
+// panic: whatever they can't read comes back as a diagnostic.
+pub fn parse(source: &str) -> Result<Program, Diagnostic> {
+    parse_with_options(source, LexerOptions::default())
+}
+
+// Lexer, macro and parser errors all have the code `syntax-error`.
+pub fn parse_with_options(source: &str, options: LexerOptions) -> Result<Program, Diagnostic> {
+    let code = |diagnostic: Diagnostic| diagnostic.or_code("syntax-error");
+    let mut lexer = Lexer::with_options(source, options);
+    let tokens = (&mut lexer).collect::<Result<Vec<_>, _>>().map_err(code)?;
+    let (tokens, expansions) = macros::expand(tokens).map_err(code)?;
+    let mut parser = Parser::with_comments(tokens, lexer.comments().to_vec());
+    parser.parse_program().map_err(|diagnostic| {
+        let at = parser.current.min(parser.tokens.len().saturating_sub(1));
+        code(expansions.explain(diagnostic, at))
+    })
+}
+
+pub struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    // In source order, for the doc comments of declarations.
+    comments: Vec<Comment<'a>>,
+    current: usize,
+    eof: Span,
+    // Set while parsing the head of an `if`, `while`, `for` or `match`,
+    // where `name {` starts the body rather than a struct literal.
+    no_struct_literals: bool,
+    // How many of the nested constructs `MAX_DEPTH` limits enclose the token
+    // being parsed.
+    depth: usize,
+    // How many links of the chains `MAX_CHAIN` limits the tree has above the
+    // expression being parsed.
+    chained: usize,
+    // How many function bodies enclose the token being parsed, since `?`
+    // returns from the innermost.
+    functions: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Token<'a>>) -> Parser<'a> {
+        Parser::with_comments(tokens, Vec::new())
+    }
+
+    // A parser that documents declarations with the `///` comments among
+    // `comments`, as the lexer kept them.
+    pub fn with_comments(tokens: Vec<Token<'a>>, comments: Vec<Comment<'a>>) -> Parser<'a> {
+        let end = tokens
+            .last()
+            .map(|token| token.span.end)
+            .unwrap_or_else(|| Position::new(1, 0, 0));
+
+        Parser {
+            tokens,
+            comments,
+            current: 0,
+            eof: Span::new(end, end),
+            no_struct_literals: false,
+            depth: 0,
+            chained: 0,
+            functions: 0,
+        }
+    }
+
+    pub fn parse_program(&mut self) -> Result<Program, Diagnostic> {
+        let mut attributes = Vec::new();
+        while self.check(TokenType::At) && self.peek_nth(1) == Some(TokenType::Bang) {
+            let at = self.expect(TokenType::At, "`@`")?;
+            self.expect(TokenType::Bang, "`!` after `@`")?;
+            attributes.push(self.parse_attribute(at)?);
+        }
+
+        let mut statements = Vec::new();
+
+        loop {
+            while self.eat(TokenType::Semicolon).is_some() {}
+            if self.is_at_end() {
+                break;
+            }
+
+            let stmt = match self.starts_test() {
+                true => self.parse_test()?,
+                false => self.parse_statement()?,
+            };
+            if !ends_with_block(&stmt) && !self.is_at_end() && !self.check(TokenType::Semicolon) {
+                return Err(self.missing_semicolon(&stmt, "`;` after expression"));
+            }
+            statements.push(stmt);
+        }
+
+        check_imports(&statements)?;
+        Ok(Program {
+            attributes,
+            statements,
+        })
+    }
+
+    // Parses the rest of a block whose `{` has already been consumed.
+    fn parse_block(&mut self, open: Token<'a>) -> Result<Block, Diagnostic> {
+        self.nested(|parser| parser.struct_literals(true, |parser| parser.parse_block_body(open)))
+    }
+
+    fn parse_block_body(&mut self, open: Token<'a>) -> Result<Block, Diagnostic> {
+        let mut statements = Vec::new();
+        let mut value = None;
+
+        loop {
+            while self.eat(TokenType::Semicolon).is_some() {}
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                check_imports(&statements)?;
+                return Ok(Block {
+                    statements,
+                    value,
+                    span: open.span.to(close.span),
+                });
+            }
+            if self.is_at_end() {
+                return Err(self.unexpected("`}`"));
+            }
+            if self.starts_test() {
+                return Err(Diagnostic::error(
+                    "test blocks must be at the top level of a file",
+                    self.peek().map_or(self.eof, |token| token.span),
+                ));
+            }
+
+            let stmt = self.parse_statement()?;
+            if self.check(TokenType::RBrace) {
+                if let StmtKind::Expr(expr) = stmt.kind {
+                    value = Some(Box::new(expr));
+                    continue;
+                }
+            }
+
+            if !ends_with_block(&stmt)
+                && !self.check(TokenType::Semicolon)
+                && !self.check(TokenType::RBrace)
+            {
+                return Err(self.missing_semicolon(&stmt, "`;` or `}` after statement"));
+            }
+            statements.push(stmt);
+        }
+    }
+
+    pub fn parse_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let start = self.current;
+        let attributes = self.parse_attributes()?;
+
+        if let Some(keyword) = self.eat(TokenType::Struct) {
+            let doc = self.doc(start);
+            return self.parse_struct(keyword, doc, attributes);
+        }
+
+        if let Some(keyword) = self.eat(TokenType::Enum) {
+            let doc = self.doc(start);
+            return self.parse_enum(keyword, doc, attributes);
+        }
+
+        if let Some(keyword) = self.eat(TokenType::Trait) {
+            let doc = self.doc(start);
+            return self.parse_trait(keyword, doc, attributes);
+        }
+
+        if let Some(keyword) = self.eat(TokenType::Impl) {
+            return self.parse_impl(keyword, attributes);
+        }
+
+        if self.check(TokenType::Fn) && matches!(self.peek_nth(1), Some(TokenType::Ident(_))) {
+            let keyword = self.expect(TokenType::Fn, "`fn`")?;
+            let doc = self.doc(start);
+            let (name, _) = self.expect_ident("function name")?;
+            let function = self.parse_function(keyword, Some(name), doc, attributes)?;
+            return Ok(Stmt {
+                span: function.span,
+                kind: StmtKind::Function(Arc::new(function)),
+            });
+        }
+
+        if let Some(attribute) = attributes.first() {
+            return Err(Diagnostic::error(
+                "attributes can only be put on declarations",
+                attribute.span,
+            ));
+        }
+
+        if let Some(keyword) = self.eat(TokenType::Import) {
+            return self.parse_import(keyword);
+        }
+
+        if let Some(keyword) = self.eat(TokenType::Let) {
+            return self.parse_let(keyword);
+        }
+
+        // A block-like expression at the start of a statement ends it, so
+        // `if a { b } (c, d)` is not read as a call.
+        let expr = if self.starts_block_statement() {
+            self.parse_prefix()?
+        } else {
+            self.parse_expression()?
+        };
+        Ok(Stmt {
+            span: expr.span,
+            kind: StmtKind::Expr(expr),
+        })
+    }
+
+    // `test "name" {`. `test` is only a keyword here, so it can still name
+    // variables and functions.
+    fn starts_test(&self) -> bool {
+        matches!(self.peek_nth(0), Some(TokenType::Ident(name)) if name == "test")
+            && matches!(
+                self.peek_nth(1),
+                Some(TokenType::String(_) | TokenType::RawString(_))
+            )
+    }
+
+    fn parse_test(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword = self.advance().ok_or_else(|| self.unexpected("`test`"))?;
+        let name = match self.peek_nth(0) {
+            Some(TokenType::String(name)) => unescape(name),
+            Some(TokenType::RawString(name)) => name.to_string(),
+            _ => return Err(self.unexpected("test name after `test`")),
+        };
+        self.advance();
+        let open = self.expect(TokenType::LBrace, "`{` after test name")?;
+        let body = self.parse_block(open)?;
+        Ok(Stmt {
+            span: keyword.span.to(body.span),
+            kind: StmtKind::Test { name, body },
+        })
+    }
+
+    // Parses a `let` statement after its keyword.
+    fn parse_let(&mut self, keyword: Token<'a>) -> Result<Stmt, Diagnostic> {
+        let mutable = self.eat(TokenType::Mut).is_some();
+        // `(a, b)`, `Point { x, y }` and `Shape::Circle(r)` are patterns
+        // to destructure the value with.
+        let destructure = match self.peek_nth(0) {
+            Some(TokenType::LParen) => true,
+            Some(TokenType::Ident(_)) => matches!(
+                self.peek_nth(1),
+                Some(TokenType::LBrace) | Some(TokenType::ColonColon)
+            ),
+            _ => false,
+        };
+        if destructure {
+            let pattern = self.parse_pattern()?;
+            let ty = match self.eat(TokenType::Colon) {
+                Some(_) => Some(self.parse_type()?),
+                None => None,
+            };
+            self.expect(TokenType::Equal, "`=` after pattern")?;
+            let value = self.parse_expression()?;
+            return Ok(Stmt {
+                span: keyword.span.to(value.span),
+                kind: StmtKind::Destructure {
+                    pattern,
+                    mutable,
+                    ty,
+                    value,
+                },
+            });
+        }
+        let (name, _) = self.expect_ident("variable name")?;
+        let ty = match self.eat(TokenType::Colon) {
+            Some(_) => Some(self.parse_type()?),
+            None => None,
+        };
+        self.expect(TokenType::Equal, "`=` after variable name")?;
+        let value = self.parse_expression()?;
+        Ok(Stmt {
+            span: keyword.span.to(value.span),
+            kind: StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                value,
+            },
+        })
+    }
+
+    fn starts_block_statement(&self) -> bool {
+        matches!(
+            self.peek().map(|token| token.kind),
+            Some(TokenType::If)
+                | Some(TokenType::While)
+                | Some(TokenType::For)
+                | Some(TokenType::Match)
+                | Some(TokenType::LBrace)
+        )
+    }
+
+    fn parse_function(
+        &mut self,
+        keyword: Token<'a>,
+        name: Option<Symbol>,
+        doc: Option<String>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Function, Diagnostic> {
+        let type_params = self.parse_type_params()?;
+        let (params, returns) = self.parse_signature()?;
+        let open = self.expect(TokenType::LBrace, "`{` before function body")?;
+        self.functions += 1;
+        let body = self.parse_block(open);
+        self.functions -= 1;
+        let body = body?;
+        Ok(Function {
+            name,
+            doc,
+            attributes,
+            type_params,
+            params,
+            returns,
+            span: keyword.span.to(body.span),
+            body,
+        })
+    }
+
+    // Parses a function's parameters and return type, up to its body.
+    fn parse_signature(&mut self) -> Result<(Vec<Param>, Option<TypeExpr>), Diagnostic> {
+        self.expect(TokenType::LParen, "`(` before parameters")?;
+        let mut params = Vec::new();
+        while self.eat(TokenType::RParen).is_none() {
+            let (name, span) = self.expect_ident("parameter name")?;
+            let ty = match self.eat(TokenType::Colon) {
+                Some(_) => Some(self.parse_type()?),
+                None => None,
+            };
+            params.push(Param { name, ty, span });
+            if self.eat(TokenType::Comma).is_none() {
+                self.expect(TokenType::RParen, "`,` or `)` after parameter")?;
+                break;
+            }
+        }
+
+        let returns = match self.eat(TokenType::Arrow) {
+            Some(_) => Some(self.parse_type()?),
+            None => None,
+        };
+        Ok((params, returns))
+    }
+
+    // Parses a type annotation: `Name`, `Name<Args>`, `(A, B)` or
+    // `Fn(A, B) -> C`.
+    // The `<T, ...>` a generic function, struct or enum puts after its
+    // name, if it has one.
+    fn parse_type_params(&mut self) -> Result<Vec<Symbol>, Diagnostic> {
+        let mut params = Vec::new();
+        if self.eat(TokenType::Less).is_none() {
+            return Ok(params);
+        }
+        while self.eat(TokenType::Greater).is_none() {
+            let (name, span) = self.expect_ident("type parameter")?;
+            if params.contains(&name) {
+                return Err(Diagnostic::error(
+                    format!("type parameter `{}` is declared twice", name),
+                    span,
+                ));
+            }
+            params.push(name);
+            if self.eat(TokenType::Comma).is_none() {
+                self.expect(TokenType::Greater, "`,` or `>` after type parameter")?;
+                break;
+            }
+        }
+        Ok(params)
+    }
+
+    fn parse_type(&mut self) -> Result<TypeExpr, Diagnostic> {
+        self.nested(Parser::parse_type_inner)
+    }
+
+    fn parse_type_inner(&mut self) -> Result<TypeExpr, Diagnostic> {
+        if let Some(open) = self.eat(TokenType::LParen) {
+            let (elements, close) = self.parse_types(TokenType::RParen, "`)`")?;
+            return Ok(TypeExpr {
+                kind: TypeExprKind::Tuple(elements),
+                span: open.span.to(close.span),
+            });
+        }
+
+        let (name, span) = self.expect_ident("type")?;
+        if name == "Fn" {
+            self.expect(TokenType::LParen, "`(` after `Fn`")?;
+            let (params, _) = self.parse_types(TokenType::RParen, "`)`")?;
+            self.expect(TokenType::Arrow, "`->` after function parameter types")?;
+            let returns = self.parse_type()?;
+            return Ok(TypeExpr {
+                span: span.to(returns.span),
+                kind: TypeExprKind::Function {
+                    params,
+                    returns: Box::new(returns),
+                },
+            });
+        }
+
+        let (args, span) = match self.eat(TokenType::Less) {
+            Some(_) => {
+                let (args, close) = self.parse_types(TokenType::Greater, "`>`")?;
+                (args, span.to(close.span))
+            }
+            None => (Vec::new(), span),
+        };
+        Ok(TypeExpr {
+            kind: TypeExprKind::Named { name, args },
+            span,
+        })
+    }
+
+    // Parses comma-separated types up to and including `close`.
+    fn parse_types(
+        &mut self,
+        close: TokenType,
+        expected: &str,
+    ) -> Result<(Vec<TypeExpr>, Token<'a>), Diagnostic> {
+        let mut types = Vec::new();
+        loop {
+            if let Some(token) = self.eat(close) {
+                return Ok((types, token));
+            }
+            types.push(self.parse_type()?);
+            if self.eat(TokenType::Comma).is_none() {
+                let token = self.expect(close, &format!("`,` or {} after type", expected))?;
+                return Ok((types, token));
+            }
+        }
+    }
+
+    fn parse_struct(
+        &mut self,
+        keyword: Token<'a>,
+        doc: Option<String>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Stmt, Diagnostic> {
+        let (name, _) = self.expect_ident("struct name")?;
+        let type_params = self.parse_type_params()?;
+        self.expect(TokenType::LBrace, "`{` after struct name")?;
+        let mut fields: Vec<Field> = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let (name, span) = self.expect_ident("field name")?;
+            if fields.iter().any(|field| field.name == name) {
+                return Err(Diagnostic::error(
+                    format!("field `{}` is declared twice", name),
+                    span,
+                ));
+            }
+            let ty = match self.eat(TokenType::Colon) {
+                Some(_) => Some(self.parse_type()?),
+                None => None,
+            };
+            fields.push(Field { name, ty, span });
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}` after field")?;
+            }
+        };
+        Ok(Stmt {
+            kind: StmtKind::Struct(StructDecl {
+                name,
+                doc,
+                attributes,
+                type_params,
+                fields,
+            }),
+            span: keyword.span.to(close.span),
+        })
+    }
+
+    fn parse_trait(
+        &mut self,
+        keyword: Token<'a>,
+        doc: Option<String>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Stmt, Diagnostic> {
+        let (name, _) = self.expect_ident("trait name")?;
+        self.expect(TokenType::LBrace, "`{` after trait name")?;
+        let mut methods: Vec<MethodSig> = Vec::new();
+        let close = loop {
+            while self.eat(TokenType::Semicolon).is_some() {}
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let first = self.current;
+            let method_attributes = self.parse_attributes()?;
+            let start = self.expect(TokenType::Fn, "`fn` or `}` in trait")?;
+            let doc = self.doc(first);
+            let (name, span) = self.expect_ident("method name")?;
+            if methods.iter().any(|method| method.name == name) {
+                return Err(Diagnostic::error(
+                    format!("method `{}` is declared twice", name),
+                    span,
+                ));
+            }
+            let (params, returns) = self.parse_signature()?;
+            expect_receiver(&params, name, span)?;
+            let end = self.expect(TokenType::Semicolon, "`;` after method signature")?;
+            methods.push(MethodSig {
+                name,
+                doc,
+                attributes: method_attributes,
+                params,
+                returns,
+                span: start.span.to(end.span),
+            });
+        };
+        Ok(Stmt {
+            kind: StmtKind::Trait(TraitDecl {
+                name,
+                doc,
+                attributes,
+                methods,
+            }),
+            span: keyword.span.to(close.span),
+        })
+    }
+
+    fn parse_impl(
+        &mut self,
+        keyword: Token<'a>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Stmt, Diagnostic> {
+        let (trait_name, _) = self.expect_ident("trait name")?;
+        self.expect(TokenType::For, "`for` after trait name")?;
+        let (target, _) = self.expect_ident("struct name")?;
+        self.expect(TokenType::LBrace, "`{` after struct name")?;
+        let mut methods: Vec<Arc<Function>> = Vec::new();
+        let close = loop {
+            while self.eat(TokenType::Semicolon).is_some() {}
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let first = self.current;
+            let method_attributes = self.parse_attributes()?;
+            let start = self.expect(TokenType::Fn, "`fn` or `}` in impl")?;
+            let doc = self.doc(first);
+            let (name, span) = self.expect_ident("method name")?;
+            if methods.iter().any(|method| method.name == Some(name)) {
+                return Err(Diagnostic::error(
+                    format!("method `{}` is defined twice", name),
+                    span,
+                ));
+            }
+            let method = self.parse_function(start, Some(name), doc, method_attributes)?;
+            expect_receiver(&method.params, name, span)?;
+            methods.push(Arc::new(method));
+        };
+        Ok(Stmt {
+            kind: StmtKind::Impl(ImplDecl {
+                attributes,
+                trait_name,
+                target,
+                methods,
+            }),
+            span: keyword.span.to(close.span),
+        })
+    }
+
+    fn parse_enum(
+        &mut self,
+        keyword: Token<'a>,
+        doc: Option<String>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Stmt, Diagnostic> {
+        let (name, _) = self.expect_ident("enum name")?;
+        let type_params = self.parse_type_params()?;
+        self.expect(TokenType::LBrace, "`{` after enum name")?;
+        let mut variants: Vec<Variant> = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let (name, mut span) = self.expect_ident("variant name")?;
+            if variants.iter().any(|variant| variant.name == name) {
+                return Err(Diagnostic::error(
+                    format!("variant `{}` is declared twice", name),
+                    span,
+                ));
+            }
+            let mut fields = Vec::new();
+            if self.eat(TokenType::LParen).is_some() {
+                let close = loop {
+                    if let Some(close) = self.eat(TokenType::RParen) {
+                        break close;
+                    }
+                    fields.push(self.parse_type()?);
+                    if self.eat(TokenType::Comma).is_none() {
+                        break self.expect(TokenType::RParen, "`,` or `)`")?;
+                    }
+                };
+                span = span.to(close.span);
+            }
+            variants.push(Variant { name, fields, span });
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}` after variant")?;
+            }
+        };
+        Ok(Stmt {
+            kind: StmtKind::Enum(EnumDecl {
+                name,
+                doc,
+                attributes,
+                type_params,
+                variants,
+            }),
+            span: keyword.span.to(close.span),
+        })
+    }
+
+    // The `@name(...)` attributes before a declaration, if there are any.
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, Diagnostic> {
+        let mut attributes = Vec::new();
+        while let Some(at) = self.eat(TokenType::At) {
+            if self.check(TokenType::Bang) {
+                return Err(Diagnostic::error(
+                    "file attributes must come before the first statement",
+                    at.span,
+                ));
+            }
+            attributes.push(self.parse_attribute(at)?);
+        }
+        Ok(attributes)
+    }
+
+    // Parses an attribute after its `@`, or `@!` for a file's.
+    fn parse_attribute(&mut self, at: Token<'a>) -> Result<Attribute, Diagnostic> {
+        let (name, mut span) = self.expect_ident("attribute name")?;
+        let mut args = Vec::new();
+        if self.eat(TokenType::LParen).is_some() {
+            let close = loop {
+                if let Some(close) = self.eat(TokenType::RParen) {
+                    break close;
+                }
+                let (mut arg, _) = self.expect_ident("attribute argument")?;
+                // `unused-variable` is one name, not a subtraction, and its
+                // parts may be keywords, as in `unreachable-match-arm`.
+                while self.check(TokenType::Minus) {
+                    let part = match self.peek_nth(1) {
+                        Some(kind) if is_word(&kind) => kind.text(),
+                        _ => break,
+                    };
+                    self.advance();
+                    self.advance();
+                    arg = Symbol::intern(&format!("{}-{}", arg, part));
+                }
+                args.push(arg);
+                if self.eat(TokenType::Comma).is_none() {
+                    break self.expect(TokenType::RParen, "`,` or `)` after attribute argument")?;
+                }
+            };
+            span = close.span;
+        }
+        Ok(Attribute {
+            name,
+            args,
+            span: at.span.to(span),
+        })
+    }
+
+    fn parse_import(&mut self, keyword: Token<'a>) -> Result<Stmt, Diagnostic> {
+        if let Some(open) = self.eat(TokenType::LBrace) {
+            let members = self.parse_import_members(open)?;
+            if self.eat_word("from").is_none() {
+                return Err(self.unexpected("`from` after the names to import"));
+            }
+            let (path, end) = self.parse_import_path("module path after `from`")?;
+            return Ok(Stmt {
+                kind: StmtKind::Import(Import {
+                    path,
+                    names: ImportNames::Members(members),
+                }),
+                span: keyword.span.to(end),
+            });
+        }
+
+        let (path, mut end) = self.parse_import_path("module path after `import`")?;
+        let mut alias = None;
+        if self.eat_word("as").is_some() {
+            let (name, span) = self.expect_ident("name after `as`")?;
+            alias = Some(name);
+            end = span;
+        }
+        Ok(Stmt {
+            kind: StmtKind::Import(Import {
+                path,
+                names: ImportNames::Module(alias),
+            }),
+            span: keyword.span.to(end),
+        })
+    }
+
+    // `name` or `name as alias`, separated by commas, up to the closing `}`.
+    fn parse_import_members(&mut self, open: Token<'a>) -> Result<Vec<ImportMember>, Diagnostic> {
+        let mut members = Vec::new();
+        while self.eat(TokenType::RBrace).is_none() {
+            let (name, start) = self.expect_ident("name to import")?;
+            let mut member = ImportMember {
+                name,
+                alias: None,
+                span: start,
+            };
+            if self.eat_word("as").is_some() {
+                let (alias, end) = self.expect_ident("name after `as`")?;
+                member.alias = Some(alias);
+                member.span = start.to(end);
+            }
+            members.push(member);
+            if self.eat(TokenType::Comma).is_none() {
+                self.expect(TokenType::RBrace, "`,` or `}` after imported name")?;
+                break;
+            }
+        }
+        if members.is_empty() {
+            return Err(Diagnostic::error(
+                "an import needs at least one name between `{` and `}`",
+                open.span,
+            ));
+        }
+        Ok(members)
+    }
+
+    fn parse_import_path(&mut self, expected: &str) -> Result<(ImportPath, Span), Diagnostic> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.unexpected(expected)),
+        };
+
+        match token.kind {
+            TokenType::String(path) => Ok((ImportPath::File(unescape(path)), token.span)),
+            TokenType::RawString(path) => Ok((ImportPath::File(path.to_string()), token.span)),
+            TokenType::Ident(name) => {
+                let mut segments = vec![name];
+                let mut end = token.span;
+                while self.eat(TokenType::ColonColon).is_some() {
+                    let (segment, span) = self.expect_ident("module name after `::`")?;
+                    segments.push(segment);
+                    end = span;
+                }
+                Ok((ImportPath::Module(segments), end))
+            }
+            _ => {
+                self.current -= 1;
+                Err(self.unexpected(expected))
+            }
+        }
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.parse_expr_with_power(0)
+    }
+
+    fn parse_expr_with_power(&mut self, min_power: u8) -> Result<Expr, Diagnostic> {
+        self.nested(|parser| parser.parse_operators(min_power))
+    }
+
+    fn parse_operators(&mut self, min_power: u8) -> Result<Expr, Diagnostic> {
+        let mut left = self.parse_prefix()?;
+
+        // The operators after an operand are parsed by helpers of their own,
+        // which keeps this frame small for deeply nested input.
+        while let Some(token) = self.peek() {
+            if matches!(
+                token.kind,
+                TokenType::LParen | TokenType::Period | TokenType::Question | TokenType::LBracket
+            ) {
+                if CALL_POWER < min_power {
+                    break;
+                }
+                self.chain(token)?;
+                self.advance();
+                left = self.parse_postfix(left, token)?;
+                continue;
+            }
+
+            if let Some(compound) = assignment_operator(token.kind) {
+                let (left_power, right_power) = ASSIGNMENT_POWER;
+                if left_power < min_power {
+                    break;
+                }
+                self.chain(token)?;
+                self.advance();
+                left = self.parse_assignment(left, compound, right_power)?;
+                continue;
+            }
+
+            if token.kind == TokenType::Pipeline {
+                let (left_power, right_power) = PIPELINE_POWER;
+                if left_power < min_power {
+                    break;
+                }
+                self.chain(token)?;
+                self.advance();
+                left = self.parse_pipeline(left, right_power)?;
+                continue;
+            }
+
+            if matches!(token.kind, TokenType::DotDot | TokenType::DotDotEq) {
+                let (left_power, right_power) = RANGE_POWER;
+                if left_power < min_power {
+                    break;
+                }
+                self.chain(token)?;
+                self.advance();
+                left = self.parse_range(left, token.kind == TokenType::DotDotEq, right_power)?;
+                continue;
+            }
+
+            let (op, left_power, right_power) = match binary_operator(token.kind) {
+                Some(operator) => operator,
+                None => break,
+            };
+            if left_power < min_power {
+                break;
+            }
+            self.chain(token)?;
+            self.advance();
+            left = self.parse_binary(left, op, right_power)?;
+        }
+
+        Ok(left)
+    }
+
+    // Parses a call, field, method call, `?` or index after `left`, whose
+    // first token has already been consumed.
+    fn parse_postfix(&mut self, left: Expr, token: Token<'a>) -> Result<Expr, Diagnostic> {
+        match token.kind {
+            TokenType::LParen => {
+                let (args, close) = self.parse_arguments()?;
+                Ok(Expr {
+                    span: left.span.to(close.span),
+                    kind: ExprKind::Call {
+                        callee: Box::new(left),
+                        args,
+                    },
+                })
+            }
+            TokenType::Period => {
+                let (name, span) = self.expect_ident("field or method name")?;
+                if self.eat(TokenType::LParen).is_none() {
+                    return Ok(Expr {
+                        span: left.span.to(span),
+                        kind: ExprKind::Field {
+                            target: Box::new(left),
+                            name,
+                        },
+                    });
+                }
+                let method = name;
+                let (args, close) = self.parse_arguments()?;
+                Ok(Expr {
+                    span: left.span.to(close.span),
+                    kind: ExprKind::MethodCall {
+                        receiver: Box::new(left),
+                        method,
+                        args,
+                    },
+                })
+            }
+            TokenType::Question if self.functions == 0 => {
+                Err(Diagnostic::error("`?` used outside a function", token.span))
+            }
+            TokenType::Question => Ok(desugar_try(left, token.span)),
+            _ => self.parse_index(left),
+        }
+    }
+
+    fn parse_assignment(
+        &mut self,
+        left: Expr,
+        compound: Option<BinaryOp>,
+        right_power: u8,
+    ) -> Result<Expr, Diagnostic> {
+        if !matches!(left.kind, ExprKind::Ident(_) | ExprKind::Field { .. }) {
+            return Err(Diagnostic::error("invalid assignment target", left.span));
+        }
+
+        let mut value = self.parse_expr_with_power(right_power)?;
+        // `x op= y` is sugar for `x = x op y`.
+        if let Some(op) = compound {
+            value = Expr {
+                span: left.span.to(value.span),
+                kind: ExprKind::Binary {
+                    op,
+                    left: Box::new(left.clone()),
+                    right: Box::new(value),
+                },
+            };
+        }
+        Ok(Expr {
+            span: left.span.to(value.span),
+            kind: ExprKind::Assign {
+                target: Box::new(left),
+                value: Box::new(value),
+            },
+        })
+    }
+
+    // `value |> f(args)` is sugar for `f(value, args)`, and `value |> f` for
+    // `f(value)`. The call starts at the value, before its callee.
+    fn parse_pipeline(&mut self, value: Expr, right_power: u8) -> Result<Expr, Diagnostic> {
+        let stage = self.parse_expr_with_power(right_power)?;
+        let span = value.span.to(stage.span);
+        let kind = match stage.kind {
+            ExprKind::Call { callee, mut args } => {
+                args.insert(0, value);
+                ExprKind::Call { callee, args }
+            }
+            _ => ExprKind::Call {
+                callee: Box::new(stage),
+                args: vec![value],
+            },
+        };
+        Ok(Expr { kind, span })
+    }
+
+    fn parse_range(
+        &mut self,
+        start: Expr,
+        inclusive: bool,
+        right_power: u8,
+    ) -> Result<Expr, Diagnostic> {
+        let end = self.parse_expr_with_power(right_power)?;
+        Ok(Expr {
+            span: start.span.to(end.span),
+            kind: ExprKind::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            },
+        })
+    }
+
+    fn parse_binary(
+        &mut self,
+        left: Expr,
+        op: BinaryOp,
+        right_power: u8,
+    ) -> Result<Expr, Diagnostic> {
+        let right = self.parse_expr_with_power(right_power)?;
+        Ok(Expr {
+            span: left.span.to(right.span),
+            kind: ExprKind::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+        })
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expr, Diagnostic> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.unexpected("expression")),
+        };
+
+        let kind = match token.kind {
+            TokenType::Integer(n) => ExprKind::Integer(integer_literal(n, token.span)?),
+            TokenType::Float(n) => ExprKind::Float(n),
+            TokenType::String(s) => ExprKind::String(unescape(s)),
+            TokenType::RawString(s) => ExprKind::String(s.to_string()),
+            TokenType::MultilineString(s) => ExprKind::String(dedent(s)),
+            TokenType::True => ExprKind::Bool(true),
+            TokenType::False => ExprKind::Bool(false),
+            TokenType::Ident(name) => return self.parse_path(name, token.span),
+            TokenType::LParen => {
+                return self.struct_literals(true, |parser| parser.parse_parenthesized(token))
+            }
+            TokenType::LBracket => {
+                return self.struct_literals(true, |parser| parser.parse_list(token))
+            }
+            TokenType::Hash => return self.struct_literals(true, |parser| parser.parse_map(token)),
+            TokenType::Match => return self.parse_match(token),
+            TokenType::LBrace => {
+                let block = self.parse_block(token)?;
+                return Ok(Expr {
+                    span: block.span,
+                    kind: ExprKind::Block(block),
+                });
+            }
+            TokenType::If => return self.parse_if(token),
+            TokenType::While => return self.parse_while(token),
+            TokenType::For => return self.parse_for(token),
+            TokenType::Break => ExprKind::Break,
+            TokenType::Continue => ExprKind::Continue,
+            TokenType::Fn => {
+                let function = self.parse_function(token, None, None, Vec::new())?;
+                return Ok(Expr {
+                    span: function.span,
+                    kind: ExprKind::Function(Arc::new(function)),
+                });
+            }
+            TokenType::Return => return self.parse_return(token),
+            TokenType::Minus => return self.parse_unary(UnaryOp::Negate, token),
+            TokenType::Bang => return self.parse_unary(UnaryOp::Not, token),
+            TokenType::Tilde => return self.parse_unary(UnaryOp::BitNot, token),
+            _ => {
+                self.current -= 1;
+                return Err(self.unexpected("expression"));
+            }
+        };
+
+        Ok(Expr {
+            kind,
+            span: token.span,
+        })
+    }
+
+    // Parses a name or `module::Name` path, or a struct literal named by it.
+    fn parse_path(&mut self, name: Symbol, start: Span) -> Result<Expr, Diagnostic> {
+        let mut segments = vec![name];
+        let mut end = start;
+        while self.eat(TokenType::ColonColon).is_some() {
+            let (segment, span) = self.expect_ident("name after `::`")?;
+            segments.push(segment);
+            end = span;
+        }
+        if !self.no_struct_literals && self.check(TokenType::LBrace) {
+            return self.parse_struct_literal(segments, start);
+        }
+        let kind = match segments.len() {
+            1 => ExprKind::Ident(segments.remove(0)),
+            _ => ExprKind::Path(segments),
+        };
+        Ok(Expr {
+            kind,
+            span: start.to(end),
+        })
+    }
+
+    fn parse_while(&mut self, keyword: Token<'a>) -> Result<Expr, Diagnostic> {
+        let condition = self.parse_condition()?;
+        let open = self.expect(TokenType::LBrace, "`{` after loop condition")?;
+        let body = self.parse_block(open)?;
+        Ok(Expr {
+            span: keyword.span.to(body.span),
+            kind: ExprKind::While {
+                condition: Box::new(condition),
+                body,
+            },
+        })
+    }
+
+    fn parse_for(&mut self, keyword: Token<'a>) -> Result<Expr, Diagnostic> {
+        let pattern = self.parse_pattern()?;
+        self.expect(TokenType::In, "`in` after loop pattern")?;
+        let iterable = self.parse_head()?;
+        let open = self.expect(TokenType::LBrace, "`{` after loop iterable")?;
+        let body = self.parse_block(open)?;
+        Ok(Expr {
+            span: keyword.span.to(body.span),
+            kind: ExprKind::For {
+                pattern,
+                iterable: Box::new(iterable),
+                body,
+            },
+        })
+    }
+
+    fn parse_return(&mut self, keyword: Token<'a>) -> Result<Expr, Diagnostic> {
+        let ends_here = match self.peek() {
+            None => true,
+            Some(next) => matches!(
+                next.kind,
+                TokenType::Semicolon
+                    | TokenType::RBrace
+                    | TokenType::RParen
+                    | TokenType::RBracket
+                    | TokenType::Comma
+            ),
+        };
+        if ends_here {
+            return Ok(Expr {
+                kind: ExprKind::Return(None),
+                span: keyword.span,
+            });
+        }
+        let value = self.parse_expression()?;
+        Ok(Expr {
+            span: keyword.span.to(value.span),
+            kind: ExprKind::Return(Some(Box::new(value))),
+        })
+    }
+
+    fn parse_unary(&mut self, op: UnaryOp, operator: Token<'a>) -> Result<Expr, Diagnostic> {
+        let operand = self.parse_expr_with_power(PREFIX_POWER)?;
+        Ok(Expr {
+            span: operator.span.to(operand.span),
+            kind: ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+        })
+    }
+
+    // Parses the condition of an `if` or `while`, the iterable of a `for` or
+    // the scrutinee of a `match`, which are followed by a `{`.
+    fn parse_head(&mut self) -> Result<Expr, Diagnostic> {
+        self.struct_literals(false, Parser::parse_expression)
+    }
+
+    // The condition of an `if` or `while`. An assignment there is almost
+    // always a comparison missing an `=`, so it is an error unless it is in
+    // parentheses, which start before its target.
+    fn parse_condition(&mut self) -> Result<Expr, Diagnostic> {
+        let condition = self.parse_head()?;
+        let target = match &condition.kind {
+            ExprKind::Assign { target, .. } if target.span.start == condition.span.start => target,
+            _ => return Ok(condition),
+        };
+        let error = Diagnostic::error("expected a condition, found an assignment", condition.span);
+        let after = self
+            .tokens
+            .partition_point(|token| token.span.start.char < target.span.end.char);
+        match self.tokens.get(after) {
+            Some(token) if token.kind == TokenType::Equal => {
+                Err(error.with_suggestion("try `==` instead of `=`", token.span, "=="))
+            }
+            _ => Err(error),
+        }
+    }
+
+    // Runs `parse` with struct literals allowed or not. Delimiters allow them
+    // again, so `if f(Point { x: 1 }) { ... }` still parses.
+    fn struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Parser<'a>) -> T) -> T {
+        let previous = std::mem::replace(&mut self.no_struct_literals, !allowed);
+        let result = parse(self);
+        self.no_struct_literals = previous;
+        result
+    }
+
+    // Runs `parse` one level deeper, failing if that is deeper than
+    // `MAX_DEPTH`.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser<'a>) -> Result<T, Diagnostic>,
+    ) -> Result<T, Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            let span = self.peek().map_or(self.eof, |token| token.span);
+            return Err(Diagnostic::error(
+                format!("nested more than {} levels deep", MAX_DEPTH),
+                span,
+            ));
+        }
+        let chained = self.chained;
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        self.chained = chained;
+        result
+    }
+
+    // Counts `link`, an operator or `else if` that nests the tree one level
+    // deeper, failing if that makes the chain longer than `MAX_CHAIN`.
+    fn chain(&mut self, link: Token<'a>) -> Result<(), Diagnostic> {
+        if self.chained == MAX_CHAIN {
+            return Err(Diagnostic::error(
+                format!(
+                    "more than {} operators, calls or `else if`s chained together",
+                    MAX_CHAIN
+                ),
+                link.span,
+            ));
+        }
+        self.chained += 1;
+        Ok(())
+    }
+
+    // Parses the rest of `(inner)` or a tuple after `(`.
+    fn parse_parenthesized(&mut self, open: Token<'a>) -> Result<Expr, Diagnostic> {
+        let inner = self.parse_expression()?;
+        if self.eat(TokenType::Comma).is_none() {
+            let close = self.expect(TokenType::RParen, "`)`")?;
+            return Ok(Expr {
+                kind: inner.kind,
+                span: open.span.to(close.span),
+            });
+        }
+
+        let mut elements = vec![inner];
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RParen) {
+                break close;
+            }
+            elements.push(self.parse_expression()?);
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RParen, "`,` or `)`")?;
+            }
+        };
+        Ok(Expr {
+            kind: ExprKind::Tuple(elements),
+            span: open.span.to(close.span),
+        })
+    }
+
+    fn parse_list(&mut self, open: Token<'a>) -> Result<Expr, Diagnostic> {
+        let mut elements = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBracket) {
+                break close;
+            }
+            elements.push(self.parse_expression()?);
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBracket, "`,` or `]`")?;
+            }
+        };
+        Ok(Expr {
+            kind: ExprKind::List(elements),
+            span: open.span.to(close.span),
+        })
+    }
+
+    // Parses the rest of a map literal after its `#`.
+    fn parse_map(&mut self, hash: Token<'a>) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LBrace, "`{` after `#`")?;
+        let mut entries = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let key = self.parse_expression()?;
+            self.expect(TokenType::Colon, "`:` after map key")?;
+            entries.push((key, self.parse_expression()?));
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}`")?;
+            }
+        };
+        Ok(Expr {
+            kind: ExprKind::Map(entries),
+            span: hash.span.to(close.span),
+        })
+    }
+
+    // Parses the fields of `Name { ... }`, whose name spans `start`.
+    fn parse_struct_literal(&mut self, path: Vec<Symbol>, start: Span) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LBrace, "`{` after struct name")?;
+        let mut fields: Vec<(Symbol, Expr)> = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            let (name, span) = self.expect_ident("field name")?;
+            if fields.iter().any(|(field, _)| *field == name) {
+                return Err(Diagnostic::error(
+                    format!("field `{}` is given twice", name),
+                    span,
+                ));
+            }
+            let value = match self.eat(TokenType::Colon) {
+                Some(_) => self.struct_literals(true, Parser::parse_expression)?,
+                None => Expr {
+                    kind: ExprKind::Ident(name),
+                    span,
+                },
+            };
+            fields.push((name, value));
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}` after field")?;
+            }
+        };
+        Ok(Expr {
+            kind: ExprKind::Struct { path, fields },
+            span: start.to(close.span),
+        })
+    }
+
+    // Parses the rest of an argument list whose `(` has already been consumed.
+    fn parse_arguments(&mut self) -> Result<(Vec<Expr>, Token<'a>), Diagnostic> {
+        self.struct_literals(true, Parser::parse_argument_list)
+    }
+
+    fn parse_argument_list(&mut self) -> Result<(Vec<Expr>, Token<'a>), Diagnostic> {
+        let mut args = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RParen) {
+                break close;
+            }
+            args.push(self.parse_expression()?);
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RParen, "`,` or `)` after argument")?;
+            }
+        };
+        Ok((args, close))
+    }
+
+    // Parses the rest of `target[index]` or `target[start..end]` after `[`.
+    fn parse_index(&mut self, target: Expr) -> Result<Expr, Diagnostic> {
+        self.struct_literals(true, |parser| parser.parse_index_or_slice(target))
+    }
+
+    fn parse_index_or_slice(&mut self, target: Expr) -> Result<Expr, Diagnostic> {
+        let span = target.span;
+        // The bounds stop short of `..`, which belongs to the slice.
+        let start = match self.check(TokenType::DotDot) {
+            true => None,
+            false => Some(Box::new(self.parse_expr_with_power(RANGE_POWER.1)?)),
+        };
+
+        let kind = match (self.eat(TokenType::DotDot), start) {
+            (Some(_), start) => {
+                let end = match self.check(TokenType::RBracket) {
+                    true => None,
+                    false => Some(Box::new(self.parse_expr_with_power(RANGE_POWER.1)?)),
+                };
+                ExprKind::Slice {
+                    target: Box::new(target),
+                    start,
+                    end,
+                }
+            }
+            (None, Some(index)) => ExprKind::Index {
+                target: Box::new(target),
+                index,
+            },
+            (None, None) => return Err(self.unexpected("index or `..` after `[`")),
+        };
+        let close = self.expect(TokenType::RBracket, "`]` after index")?;
+        Ok(Expr {
+            span: span.to(close.span),
+            kind,
+        })
+    }
+
+    // An `else if` chain is read in a loop rather than recursively, so a long
+    // chain doesn't count as deep nesting.
+    fn parse_if(&mut self, keyword: Token<'a>) -> Result<Expr, Diagnostic> {
+        let mut branches = Vec::new();
+        let mut start = keyword.span;
+        let mut else_branch = loop {
+            let condition = self.parse_condition()?;
+            let open = self.expect(TokenType::LBrace, "`{` after if condition")?;
+            let then_branch = self.parse_block(open)?;
+            branches.push((start, condition, then_branch));
+
+            if self.eat(TokenType::Else).is_none() {
+                break None;
+            }
+            match self.advance() {
+                Some(token) if token.kind == TokenType::If => {
+                    self.chain(token)?;
+                    start = token.span;
+                }
+                Some(token) if token.kind == TokenType::LBrace => {
+                    let block = self.parse_block(token)?;
+                    break Some(Expr {
+                        span: block.span,
+                        kind: ExprKind::Block(block),
+                    });
+                }
+                _ => {
+                    self.current -= 1;
+                    return Err(self.unexpected("`{` or `if` after `else`"));
+                }
+            }
+        };
+
+        for (start, condition, then_branch) in branches.into_iter().rev() {
+            let end = match &else_branch {
+                Some(branch) => branch.span,
+                None => then_branch.span,
+            };
+            else_branch = Some(Expr {
+                span: start.to(end),
+                kind: ExprKind::If {
+                    condition: Box::new(condition),
+                    then_branch,
+                    else_branch: else_branch.map(Box::new),
+                },
+            });
+        }
+        else_branch.ok_or_else(|| self.unexpected("`if`"))
+    }
+
+    fn parse_match(&mut self, keyword: Token<'a>) -> Result<Expr, Diagnostic> {
+        let scrutinee = self.parse_head()?;
+        self.expect(TokenType::LBrace, "`{` after match scrutinee")?;
+
+        let mut arms = Vec::new();
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+
+            let pattern = self.parse_pattern()?;
+            let guard = match self.eat(TokenType::If) {
+                Some(_) => Some(self.parse_expression()?),
+                None => None,
+            };
+            self.expect(TokenType::FatArrow, "`=>` after match pattern")?;
+            let body = self.parse_expression()?;
+
+            arms.push(MatchArm {
+                span: pattern.span.to(body.span),
+                pattern,
+                guard,
+                body,
+            });
+
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}` after match arm")?;
+            }
+        };
+
+        Ok(Expr {
+            span: keyword.span.to(close.span),
+            kind: ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+        })
+    }
+
+    pub fn parse_pattern(&mut self) -> Result<Pattern, Diagnostic> {
+        self.nested(Parser::parse_pattern_inner)
+    }
+
+    fn parse_pattern_inner(&mut self) -> Result<Pattern, Diagnostic> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.unexpected("pattern")),
+        };
+
+        let kind = match token.kind {
+            TokenType::Ident(name) if name == "_" => PatternKind::Wildcard,
+            TokenType::Ident(name)
+                if matches!(
+                    self.peek_nth(0),
+                    Some(TokenType::LBrace) | Some(TokenType::ColonColon)
+                ) =>
+            {
+                return self.parse_path_pattern(name, token.span)
+            }
+            // The prelude's variants can be named on their own, so `None`
+            // is a variant rather than a binding.
+            TokenType::Ident(name) if prelude_enum(&name).is_some() => {
+                return self.parse_path_pattern(name, token.span)
+            }
+            TokenType::Ident(name) => PatternKind::Binding(name),
+            TokenType::Integer(n) => PatternKind::Integer(integer_literal(n, token.span)?),
+            TokenType::Float(n) => PatternKind::Float(n),
+            TokenType::String(s) => PatternKind::String(unescape(s)),
+            TokenType::RawString(s) => PatternKind::String(s.to_string()),
+            TokenType::MultilineString(s) => PatternKind::String(dedent(s)),
+            TokenType::True => PatternKind::Bool(true),
+            TokenType::False => PatternKind::Bool(false),
+            TokenType::Minus => {
+                let number = match self.advance() {
+                    Some(number) => number,
+                    None => return Err(self.unexpected("number after `-` in pattern")),
+                };
+                let kind = match number.kind {
+                    TokenType::Integer(n) => {
+                        PatternKind::Integer(-integer_literal(n, number.span)?)
+                    }
+                    TokenType::Float(n) => PatternKind::Float(-n),
+                    _ => {
+                        self.current -= 1;
+                        return Err(self.unexpected("number after `-` in pattern"));
+                    }
+                };
+                return Ok(Pattern {
+                    kind,
+                    span: token.span.to(number.span),
+                });
+            }
+            TokenType::LParen => {
+                let mut elements = Vec::new();
+                let mut trailing_comma = false;
+                let close = loop {
+                    if let Some(close) = self.eat(TokenType::RParen) {
+                        break close;
+                    }
+                    elements.push(self.parse_pattern()?);
+                    trailing_comma = self.eat(TokenType::Comma).is_some();
+                    if !trailing_comma {
+                        break self.expect(TokenType::RParen, "`,` or `)`")?;
+                    }
+                };
+
+                if elements.len() == 1 && !trailing_comma {
+                    let inner = elements.remove(0);
+                    return Ok(Pattern {
+                        kind: inner.kind,
+                        span: token.span.to(close.span),
+                    });
+                }
+                return Ok(Pattern {
+                    kind: PatternKind::Tuple(elements),
+                    span: token.span.to(close.span),
+                });
+            }
+            _ => {
+                self.current -= 1;
+                return Err(self.unexpected("pattern"));
+            }
+        };
+
+        Ok(Pattern {
+            kind,
+            span: token.span,
+        })
+    }
+
+    // Parses a struct or enum variant pattern after its first name.
+    fn parse_path_pattern(&mut self, name: Symbol, start: Span) -> Result<Pattern, Diagnostic> {
+        let mut path = vec![name];
+        let mut end = start;
+        while self.eat(TokenType::ColonColon).is_some() {
+            let (segment, span) = self.expect_ident("name after `::`")?;
+            path.push(segment);
+            end = span;
+        }
+        if self.check(TokenType::LBrace) {
+            return self.parse_struct_pattern(path, start);
+        }
+
+        let mut fields = Vec::new();
+        if self.eat(TokenType::LParen).is_some() {
+            let close = loop {
+                if let Some(close) = self.eat(TokenType::RParen) {
+                    break close;
+                }
+                fields.push(self.parse_pattern()?);
+                if self.eat(TokenType::Comma).is_none() {
+                    break self.expect(TokenType::RParen, "`,` or `)`")?;
+                }
+            };
+            end = close.span;
+        }
+        Ok(Pattern {
+            kind: PatternKind::Variant { path, fields },
+            span: start.to(end),
+        })
+    }
+
+    // Parses `Name { field: pattern, field, .. }` after its path.
+    fn parse_struct_pattern(
+        &mut self,
+        path: Vec<Symbol>,
+        start: Span,
+    ) -> Result<Pattern, Diagnostic> {
+        self.expect(TokenType::LBrace, "`{` after struct name")?;
+        let mut fields: Vec<(Symbol, Pattern)> = Vec::new();
+        let mut rest = false;
+        let close = loop {
+            if let Some(close) = self.eat(TokenType::RBrace) {
+                break close;
+            }
+            if self.eat(TokenType::DotDot).is_some() {
+                rest = true;
+                break self.expect(TokenType::RBrace, "`}` after `..`")?;
+            }
+            let (name, span) = self.expect_ident("field name")?;
+            if fields.iter().any(|(field, _)| *field == name) {
+                return Err(Diagnostic::error(
+                    format!("field `{}` is matched twice", name),
+                    span,
+                ));
+            }
+            let pattern = match self.eat(TokenType::Colon) {
+                Some(_) => self.parse_pattern()?,
+                None => Pattern {
+                    kind: PatternKind::Binding(name),
+                    span,
+                },
+            };
+            fields.push((name, pattern));
+            if self.eat(TokenType::Comma).is_none() {
+                break self.expect(TokenType::RBrace, "`,` or `}` after field")?;
+            }
+        };
+        Ok(Pattern {
+            kind: PatternKind::Struct { path, fields, rest },
+            span: start.to(close.span),
+        })
+    }
+
+    // The `///` comments of a declaration that starts at the token at
+    // `first`, with its attributes if it has any, and whose keyword is the
+    // token last consumed: those after the token before `first` and before
+    // the keyword.
+    fn doc(&self, first: usize) -> Option<String> {
+        let start = self.tokens[self.current - 1].span.start.char;
+        let after = match first.checked_sub(1) {
+            Some(previous) => self.tokens[previous].span.end.char,
+            None => 0,
+        };
+        let first = self
+            .comments
+            .partition_point(|comment| comment.span.start.char < after);
+        let lines: Vec<&str> = self.comments[first..]
+            .iter()
+            .take_while(|comment| comment.span.start.char < start)
+            .filter_map(Comment::doc)
+            .collect();
+        match lines.is_empty() {
+            true => None,
+            false => Some(lines.join("\n")),
+        }
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.current).copied()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<TokenType<'a>> {
+        self.tokens.get(self.current + n).map(|token| token.kind)
+    }
+
+    fn advance(&mut self) -> Option<Token<'a>> {
+        let token = self.peek()?;
+        self.current += 1;
+        Some(token)
+    }
+
+    fn check(&self, kind: TokenType) -> bool {
+        matches!(self.peek(), Some(token) if token.kind == kind)
+    }
+
+    fn eat(&mut self, kind: TokenType) -> Option<Token<'a>> {
+        if kind == TokenType::Greater {
+            self.split_greater();
+        }
+        if self.check(kind) {
+            self.advance()
+        } else {
+            None
+        }
+    }
+
+    // Consumes `word` if it comes next. Words like `as` and `from` only
+    // mean something in a few places, so they aren't keywords.
+    fn eat_word(&mut self, word: &str) -> Option<Token<'a>> {
+        match self.peek() {
+            Some(token) if matches!(token.kind, TokenType::Ident(name) if name == word) => {
+                self.advance()
+            }
+            _ => None,
+        }
+    }
+
+    // Splits the `>` off the front of a `>>`, `>>=` or `>=`, so that
+    // `List<List<Int>>` closes both lists of type arguments and
+    // `let x: List<Int>= y` is a `let`.
+    fn split_greater(&mut self) {
+        let (token, rest) = match self.peek() {
+            Some(token) => match token.kind {
+                TokenType::ShiftRight => (token, TokenType::Greater),
+                TokenType::ShiftRightEqual => (token, TokenType::GreaterEqual),
+                TokenType::GreaterEqual => (token, TokenType::Equal),
+                _ => return,
+            },
+            None => return,
+        };
+        let start = token.span.start;
+        let middle = Position::new(start.line, start.column + 1, start.char + 1);
+        self.tokens[self.current] = Token::new(TokenType::Greater, Span::new(start, middle));
+        let rest = Token::new(rest, Span::new(middle, token.span.end));
+        self.tokens.insert(self.current + 1, rest);
+    }
+
+    fn expect(&mut self, kind: TokenType, expected: &str) -> Result<Token<'a>, Diagnostic> {
+        match self.eat(kind) {
+            Some(token) => Ok(token),
+            None => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn expect_ident(&mut self, expected: &str) -> Result<(Symbol, Span), Diagnostic> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenType::Ident(name),
+                span,
+            }) => {
+                self.advance();
+                Ok((name, span))
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    // The error for a statement followed by something other than a `;`. A
+    // lone name there is usually a misspelled keyword, as in
+    // `whlie x { ... }`, and otherwise the `;` was left out.
+    fn missing_semicolon(&self, stmt: &Stmt, expected: &str) -> Diagnostic {
+        let error = self.unexpected(expected);
+        if let StmtKind::Expr(Expr {
+            kind: ExprKind::Ident(name),
+            span,
+        }) = &stmt.kind
+        {
+            if let Some(keyword) = closest_keyword(name.as_str()) {
+                let message = format!("did you mean `{}`?", keyword);
+                return error.with_suggestion(message, *span, keyword);
+            }
+        }
+        let end = Span::new(stmt.span.end, stmt.span.end);
+        error.with_suggestion("add `;` at the end of the statement", end, ";")
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        match self.peek() {
+            Some(token) => Diagnostic::error(
+                format!("expected {}, found {}", expected, token.kind),
+                token.span,
+            ),
+            None => Diagnostic::error(
+                format!("expected {}, found end of file", expected),
+                self.eof,
+            ),
+        }
+    }
+}
+
+// Methods are called on a struct, which they take as `self`.
+fn expect_receiver(params: &[Param], method: Symbol, span: Span) -> Result<(), Diagnostic> {
+    match params.first() {
+        Some(param) if param.name == "self" => Ok(()),
+        _ => Err(Diagnostic::error(
+            format!(
+                "method `{}` must take `self` as its first parameter",
+                method
+            ),
+            span,
+        )),
+    }
+}
+
+// An import can't bind a name another import or a declaration in the same
+// scope binds too, since one would silently hide the other.
+fn check_imports(statements: &[Stmt]) -> Result<(), Diagnostic> {
+    // Where each name is bound, and whether by an import.
+    let mut bound: HashMap<Symbol, (Span, bool)> = HashMap::new();
+    for stmt in statements {
+        let (names, imported): (Vec<(Symbol, Span)>, _) = match &stmt.kind {
+            StmtKind::Import(import) => {
+                let spans: Vec<Span> = match &import.names {
+                    ImportNames::Module(_) => vec![stmt.span],
+                    ImportNames::Members(members) => {
+                        members.iter().map(|member| member.span).collect()
+                    }
+                };
+                (import.bindings().into_iter().zip(spans).collect(), true)
+            }
+            StmtKind::Function(function) => match function.name {
+                Some(name) => (vec![(name, stmt.span)], false),
+                None => continue,
+            },
+            StmtKind::Struct(StructDecl { name, .. })
+            | StmtKind::Enum(EnumDecl { name, .. })
+            | StmtKind::Trait(TraitDecl { name, .. }) => (vec![(*name, stmt.span)], false),
+            _ => continue,
+        };
+        for (name, span) in names {
+            match bound.get(&name) {
+                // Declarations hiding each other is left to the lints.
+                Some(&(first, first_imported)) if imported || first_imported => {
+                    let how = match first_imported {
+                        true => "imported",
+                        false => "defined",
+                    };
+                    let message = format!("`{}` is already {}", name, how);
+                    return Err(Diagnostic::error(message, span).with_note(format!(
+                        "`{}` is first {} at {}:{}",
+                        name,
+                        how,
+                        first.start.line,
+                        first.start.column + 1
+                    )));
+                }
+                _ => {
+                    bound.insert(name, (span, imported));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Statements that end in a `}` don't need a `;` to separate them from the
+// next statement.
+pub fn ends_with_block(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Function(_)
+        | StmtKind::Struct(_)
+        | StmtKind::Enum(_)
+        | StmtKind::Test { .. }
+        | StmtKind::Trait(_)
+        | StmtKind::Impl(_) => true,
+        StmtKind::Expr(expr) => matches!(
+            expr.kind,
+            ExprKind::Block(_)
+                | ExprKind::Match { .. }
+                | ExprKind::Function(_)
+                | ExprKind::If { .. }
+                | ExprKind::While { .. }
+                | ExprKind::For { .. }
+        ),
+        StmtKind::Import(_) | StmtKind::Let { .. } | StmtKind::Destructure { .. } => false,
+    }
+}
+
+// `value?` is sugar for a match that unwraps `Some` and `Ok` values and
+// returns `None` and `Err` ones from the enclosing function:
+//
+//     match value {
+//         Option::Some(?) => ?,
+//         Result::Ok(?) => ?,
+//         Option::None => return Option::None,
+//         Result::Err(?) => return Result::Err(?),
+//     }
+//
+// `?` can't be written as a name, so the binding never shadows one.
+fn desugar_try(value: Expr, question: Span) -> Expr {
+    let span = value.span.to(question);
+    let expr = |kind| Expr { kind, span };
+    let path = |ty: &str, variant: &str| vec![Symbol::intern(ty), Symbol::intern(variant)];
+    let variant = |ty, name, fields| Pattern {
+        kind: PatternKind::Variant {
+            path: path(ty, name),
+            fields,
+        },
+        span,
+    };
+    let binding = || {
+        vec![Pattern {
+            kind: PatternKind::Binding(Symbol::intern("?")),
+            span,
+        }]
+    };
+    let value_of = || expr(ExprKind::Ident(Symbol::intern("?")));
+    let arm = |pattern, body| MatchArm {
+        pattern,
+        guard: None,
+        body,
+        span,
+    };
+    let arms = vec![
+        arm(variant("Option", "Some", binding()), value_of()),
+        arm(variant("Result", "Ok", binding()), value_of()),
+        arm(
+            variant("Option", "None", Vec::new()),
+            expr(ExprKind::Return(Some(Box::new(expr(ExprKind::Path(
+                path("Option", "None"),
+            )))))),
+        ),
+        arm(
+            variant("Result", "Err", binding()),
+            expr(ExprKind::Return(Some(Box::new(expr(ExprKind::Call {
+                callee: Box::new(expr(ExprKind::Path(path("Result", "Err")))),
+                args: vec![value_of()],
+            }))))),
+        ),
+    ];
+    expr(ExprKind::Match {
+        scrutinee: Box::new(value),
+        arms,
+    })
+}
+
+// Whether `arms` are those of a `?`, whose first binds the name only
+// `desugar_try` can write, so a value they don't match is reported as one
+// `?` can't be applied to.
+pub fn is_try(arms: &[MatchArm]) -> bool {
+    arms.first().is_some_and(|arm| match &arm.pattern.kind {
+        PatternKind::Variant { fields, .. } => {
+            matches!(&fields[..], [Pattern { kind: PatternKind::Binding(name), .. }] if *name == "?")
+        }
+        _ => false,
+    })
+}
+
+// The error for a value `?` is applied to that is neither an `Option` nor a
+// `Result`.
+pub fn not_try(value: &impl std::fmt::Display, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "`?` applied to `{}`, which is not an Option or Result",
+            value
+        ),
+        span,
+    )
+}
+
+// Returns the operator a compound assignment applies, or `None` for `=`.
+// A name or a keyword, which can be part of an attribute argument.
+// The keyword `name` is most likely a misspelling of: one a letter away, or
+// two for the longer keywords, swapping two letters counting as one.
+fn closest_keyword(name: &str) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .map(|keyword| (edit_distance(name, keyword), *keyword))
+        .filter(|(distance, keyword)| *distance == 1 || (*distance == 2 && keyword.len() > 4))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
+}
+
+fn is_word(kind: &TokenType) -> bool {
+    kind.text().chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !matches!(kind, TokenType::Integer(_) | TokenType::Float(_))
+}
+
+fn assignment_operator(kind: TokenType) -> Option<Option<BinaryOp>> {
+    match kind {
+        TokenType::Equal => Some(None),
+        TokenType::PlusEqual => Some(Some(BinaryOp::Add)),
+        TokenType::MinusEqual => Some(Some(BinaryOp::Subtract)),
+        TokenType::AsteriskEqual => Some(Some(BinaryOp::Multiply)),
+        TokenType::SlashEqual => Some(Some(BinaryOp::Divide)),
+        TokenType::AmpersandEqual => Some(Some(BinaryOp::BitAnd)),
+        TokenType::BarEqual => Some(Some(BinaryOp::BitOr)),
+        TokenType::CaretEqual => Some(Some(BinaryOp::BitXor)),
+        TokenType::ShiftLeftEqual => Some(Some(BinaryOp::ShiftLeft)),
+        TokenType::ShiftRightEqual => Some(Some(BinaryOp::ShiftRight)),
+        _ => None,
+    }
+}
+
+fn integer_literal(n: usize, span: Span) -> Result<i64, Diagnostic> {
+    i64::try_from(n).map_err(|_| Diagnostic::error("integer literal is too large", span))
+}
+
+// The operator a token stands for and its left and right binding powers.
+fn binary_operator(kind: TokenType) -> Option<(BinaryOp, u8, u8)> {
+    let op = match kind {
+        TokenType::Or => BinaryOp::Or,
+        TokenType::And => BinaryOp::And,
+        TokenType::DoubleEqual => BinaryOp::Equal,
+        TokenType::BangEqual => BinaryOp::NotEqual,
+        TokenType::Less => BinaryOp::Less,
+        TokenType::LessEqual => BinaryOp::LessEqual,
+        TokenType::Greater => BinaryOp::Greater,
+        TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+        TokenType::Bar => BinaryOp::BitOr,
+        TokenType::Caret => BinaryOp::BitXor,
+        TokenType::Ampersand => BinaryOp::BitAnd,
+        TokenType::ShiftLeft => BinaryOp::ShiftLeft,
+        TokenType::ShiftRight => BinaryOp::ShiftRight,
+        TokenType::Plus => BinaryOp::Add,
+        TokenType::Minus => BinaryOp::Subtract,
+        TokenType::Asterisk => BinaryOp::Multiply,
+        TokenType::Slash => BinaryOp::Divide,
+        TokenType::Percent => BinaryOp::Remainder,
+        _ => return None,
+    };
+    let (left, right) = binding_power(op);
+    Some((op, left, right))
+}
+
+// The left and right binding powers of a binary operator.
+pub(crate) fn binding_power(op: BinaryOp) -> (u8, u8) {
+    let power = op.precedence() * 2 + 1;
+    match op.associativity() {
+        Associativity::Left => (power, power + 1),
+        Associativity::Right => (power, power),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::symbol::{self, Symbol};
+    use crate::parser::ast::{
+        Attribute, BinaryOp, ExprKind, Import, ImportNames, ImportPath, PatternKind, StmtKind,
+        TypeExprKind,
+    };
+    use crate::parser::parser::{parse, MAX_CHAIN, MAX_DEPTH};
+    use crate::random::Random;
+
+    fn parse_expr(source: &str) -> ExprKind {
+        let program = parse(source).unwrap();
+        match program.statements.into_iter().next().unwrap().kind {
+            StmtKind::Expr(expr) => expr.kind,
+            other => panic!("expected an expression statement, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn respects_precedence_and_associativity() {
+        match parse_expr("1 - 2 - 3 * 4") {
+            ExprKind::Binary { op, left, right } => {
+                assert_eq!(op, BinaryOp::Subtract);
+                assert!(matches!(
+                    left.kind,
+                    ExprKind::Binary {
+                        op: BinaryOp::Subtract,
+                        ..
+                    }
+                ));
+                assert!(matches!(
+                    right.kind,
+                    ExprKind::Binary {
+                        op: BinaryOp::Multiply,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn knows_where_parentheses_are_needed() {
+        use BinaryOp::*;
+        assert!(Add.precedence() < Multiply.precedence());
+        assert!(Multiply.needs_parentheses(Add, false));
+        assert!(!Add.needs_parentheses(Multiply, true));
+        // `a - (b - c)` keeps its parentheses, `(a - b) - c` doesn't need them.
+        assert!(Subtract.needs_parentheses(Add, true));
+        assert!(!Subtract.needs_parentheses(Add, false));
+        assert!(Or.needs_parentheses(Or, true));
+        assert!(!And.needs_parentheses(Equal, true));
+        // Bitwise operators bind tighter than comparisons, as in Rust.
+        assert!(!Equal.needs_parentheses(BitAnd, false));
+        assert!(BitAnd.needs_parentheses(BitOr, true));
+        assert!(ShiftLeft.needs_parentheses(BitXor, false));
+        assert!(!ShiftLeft.needs_parentheses(Add, true));
+    }
+
+    #[test]
+    fn strips_the_indentation_of_multiline_strings() {
+        let source = "match x {\n    \"\"\"\n      a\n        b\n      \"\"\" => r#\"\"q\"\"#,\n}";
+        match parse_expr(source) {
+            ExprKind::Match { arms, .. } => {
+                assert_eq!(arms[0].pattern.kind, PatternKind::String("a\n  b".into()));
+                assert_eq!(arms[0].body.kind, ExprKind::String("\"q\"".into()));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn attaches_doc_comments_to_declarations() {
+        let source = "// Not a doc.\n/// Adds.\n///\n/// Twice.\nfn add(a, b) { a + b }\n\
+                      /// Lost, before a call.\nadd(1, 2);\n// Plain.\nstruct P {}\n\
+                      /// Shapes.\n//// Rule.\nenum S {}\n\
+                      impl Show for P {\n    /// Shows.\n    fn show(self) {}\n}";
+        let docs: Vec<Option<String>> = parse(source)
+            .unwrap()
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Function(function) => function.doc.clone(),
+                StmtKind::Struct(decl) => decl.doc.clone(),
+                StmtKind::Enum(decl) => decl.doc.clone(),
+                StmtKind::Impl(decl) => decl.methods[0].doc.clone(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            vec![
+                Some("Adds.\n\nTwice.".to_string()),
+                None,
+                None,
+                Some("Shapes.".to_string()),
+                Some("Shows.".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attaches_attributes_to_declarations() {
+        let source = "@!allow(unused)\n/// Doc.\n@deny(shadowed-binding, x) @inline\nfn f() {}\n\
+                      impl S for P { @allow(a-b-c) fn s(self) {} }";
+        let program = parse(source).unwrap();
+        let names = |attributes: &[Attribute]| -> Vec<String> {
+            attributes
+                .iter()
+                .map(|a| format!("{:?} {:?}", a.name, a.args))
+                .collect()
+        };
+        assert_eq!(names(&program.attributes), ["\"allow\" [\"unused\"]"]);
+        match &program.statements[0].kind {
+            StmtKind::Function(function) => {
+                assert_eq!(
+                    names(&function.attributes),
+                    ["\"deny\" [\"shadowed-binding\", \"x\"]", "\"inline\" []"]
+                );
+                assert_eq!(function.doc.as_deref(), Some("Doc."));
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match &program.statements[1].kind {
+            StmtKind::Impl(decl) => {
+                assert_eq!(
+                    names(&decl.methods[0].attributes),
+                    ["\"allow\" [\"a-b-c\"]"]
+                )
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+
+        let error = |source: &str| parse(source).unwrap_err().message;
+        assert_eq!(
+            error("@allow(x) let y = 1;"),
+            "attributes can only be put on declarations"
+        );
+        assert_eq!(
+            error("fn f() {}\n@!allow(x)"),
+            "file attributes must come before the first statement"
+        );
+    }
+
+    #[test]
+    fn parses_bitwise_operators() {
+        assert_eq!(
+            parse("a & 1 == 0 | b ^ ~c << 2 + 1").unwrap().to_string(),
+            parse("((a & 1) == ((0 | (b ^ (~c << (2 + 1))))))")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            parse("x >>= 1").unwrap().to_string(),
+            parse("x = x >> 1").unwrap().to_string()
+        );
+        // `>>` and `>=` still close lists of type arguments.
+        let program = parse("let xs: List<List<Int>>= [[1]];").unwrap();
+        assert_eq!(program.to_string(), "let xs: List<List<Int>> = [[1]];");
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        match parse_expr("a = b = 1") {
+            ExprKind::Assign { value, .. } => {
+                assert!(matches!(value.kind, ExprKind::Assign { .. }))
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_match_arms() {
+        match parse_expr("match (x, 1) { (0, _) => 1, (n, -2) if n > 0 => n, _ => 0, }") {
+            ExprKind::Match { arms, .. } => {
+                assert_eq!(arms.len(), 3);
+                assert!(matches!(&arms[0].pattern.kind, PatternKind::Tuple(p) if p.len() == 2));
+                assert!(arms[1].guard.is_some());
+                assert_eq!(arms[2].pattern.kind, PatternKind::Wildcard);
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_imports_and_paths() {
+        let program = parse("import \"lib/util.clay\"; import std::math; math::pi").unwrap();
+        assert_eq!(
+            program.statements[0].kind,
+            StmtKind::Import(Import {
+                path: ImportPath::File("lib/util.clay".to_string()),
+                names: ImportNames::Module(None),
+            })
+        );
+        assert_eq!(
+            program.statements[1].kind,
+            StmtKind::Import(Import {
+                path: ImportPath::Module(vec![Symbol::intern("std"), Symbol::intern("math")]),
+                names: ImportNames::Module(None),
+            })
+        );
+        assert!(
+            matches!(&program.statements[2].kind, StmtKind::Expr(e) if e.kind == ExprKind::Path(vec![Symbol::intern("math"), Symbol::intern("pi")]))
+        );
+    }
+
+    #[test]
+    fn parses_aliased_and_selective_imports() {
+        let source =
+            "import std::path as p;\nimport { join, parent as up, } from \"lib/path.clay\";";
+        let program = parse(source).unwrap();
+        let imports: Vec<_> = program
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Import(import) => (import.to_string(), import.bindings(), stmt.span),
+                other => panic!("expected an import, found {:?}", other),
+            })
+            .collect();
+        assert_eq!(imports[0].0, "import std::path as p");
+        assert_eq!(imports[0].1, vec![Symbol::intern("p")]);
+        assert_eq!(imports[0].2.end.column, 21);
+        assert_eq!(
+            imports[1].0,
+            "import { join, parent as up } from \"lib/path.clay\""
+        );
+        assert_eq!(
+            imports[1].1,
+            vec![Symbol::intern("join"), Symbol::intern("up")]
+        );
+        let printed = program.to_string();
+        assert_eq!(parse(&printed).unwrap().to_string(), printed);
+
+        let errors = [
+            (
+                "import {} from std::path",
+                "an import needs at least one name between `{` and `}`",
+            ),
+            (
+                "import { join } std::path",
+                "expected `from` after the names to import, found identifier `std`",
+            ),
+            (
+                "import { join parent } from std::path",
+                "expected `,` or `}` after imported name, found identifier `parent`",
+            ),
+            (
+                "import std::path as",
+                "expected name after `as`, found end of file",
+            ),
+            (
+                "import std::path; import \"path.clay\"",
+                "`path` is already imported",
+            ),
+            (
+                "import { join, path as join } from std::path",
+                "`join` is already imported",
+            ),
+            (
+                "fn f() { import std::os; import { os } from std::os2; }",
+                "`os` is already imported",
+            ),
+            (
+                "fn get() { 1 } import { get } from \"u.clay\";",
+                "`get` is already defined",
+            ),
+            (
+                "import std::path; struct path {}",
+                "`path` is already imported",
+            ),
+        ];
+        for (source, message) in errors {
+            assert_eq!(parse(source).unwrap_err().message, message, "{}", source);
+        }
+        let error = parse("import std::path;\nimport { a, b as path } from m").unwrap_err();
+        assert_eq!((error.span.start.line, error.span.start.column), (2, 12));
+        assert_eq!(error.notes(), ["`path` is first imported at 1:1"]);
+        let error = parse("fn get() { 1 }\nimport { get } from \"u.clay\";").unwrap_err();
+        assert_eq!(error.notes(), ["`get` is first defined at 1:1"]);
+        // Imports in different scopes may bind the same name.
+        assert!(parse("import std::os; fn f() { import \"os.clay\"; }").is_ok());
+        assert!(parse("import { get } from \"u.clay\"; fn f() { fn get() { 2 } }").is_ok());
+    }
+
+    #[test]
+    fn parses_functions_and_calls() {
+        let program = parse("fn add(a, b) { a + b } add(1)(2, 3)").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Function(function) => {
+                assert_eq!(function.name.as_deref(), Some("add"));
+                assert_eq!(function.params.len(), 2);
+                assert!(function.body.value.is_some());
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match &program.statements[1].kind {
+            StmtKind::Expr(expr) => match &expr.kind {
+                ExprKind::Call { callee, args } => {
+                    assert_eq!(args.len(), 2);
+                    assert!(matches!(callee.kind, ExprKind::Call { .. }));
+                }
+                other => panic!("unexpected expression {:?}", other),
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn block_value_is_its_trailing_expression() {
+        match parse_expr("{ a = 1; a }") {
+            ExprKind::Block(block) => {
+                assert_eq!(block.statements.len(), 1);
+                assert!(block.value.is_some());
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        match parse_expr("{ a = 1; }") {
+            ExprKind::Block(block) => assert!(block.value.is_none()),
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_if_else_chains() {
+        match parse_expr("if a { 1 } else if b { 2 } else { 3 }") {
+            ExprKind::If { else_branch, .. } => match else_branch.unwrap().kind {
+                ExprKind::If { else_branch, .. } => {
+                    assert!(matches!(else_branch.unwrap().kind, ExprKind::Block(_)))
+                }
+                other => panic!("unexpected expression {:?}", other),
+            },
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn loops_do_not_need_semicolons() {
+        let program = parse("while x { break } for (a, b) in pairs { continue } 1").unwrap();
+        assert_eq!(program.statements.len(), 3);
+    }
+
+    #[test]
+    fn parses_let_statements() {
+        let program = parse("let x = 1; let mut y = x;").unwrap();
+        assert!(matches!(
+            &program.statements[0].kind,
+            StmtKind::Let { name, mutable: false, .. } if name == "x"
+        ));
+        assert!(matches!(
+            &program.statements[1].kind,
+            StmtKind::Let { name, mutable: true, .. } if name == "y"
+        ));
+        assert_eq!(
+            parse("let mut = 1;").unwrap_err().message,
+            "expected variable name, found `=`"
+        );
+    }
+
+    #[test]
+    fn parses_type_annotations() {
+        let program = parse(
+            "let xs: List<Map<String, Int>> = []; fn f(g: Fn(Int) -> (), n) -> Bool { true }",
+        )
+        .unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Let { ty: Some(ty), .. } => match &ty.kind {
+                TypeExprKind::Named { name, args } => {
+                    assert_eq!(name, "List");
+                    assert!(matches!(
+                        &args[0].kind,
+                        TypeExprKind::Named { name, args } if name == "Map" && args.len() == 2
+                    ));
+                }
+                other => panic!("unexpected type {:?}", other),
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match &program.statements[1].kind {
+            StmtKind::Function(function) => {
+                assert!(matches!(
+                    &function.params[0].ty.as_ref().unwrap().kind,
+                    TypeExprKind::Function { params, returns }
+                        if params.len() == 1 && returns.kind == TypeExprKind::Tuple(Vec::new())
+                ));
+                assert_eq!(function.params[1].ty, None);
+                assert!(function.returns.is_some());
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        assert_eq!(
+            parse("let x: List<Int = 1;").unwrap_err().message,
+            "expected `,` or `>` after type, found `=`"
+        );
+    }
+
+    #[test]
+    fn desugars_compound_assignment() {
+        match parse_expr("x -= 1 + 2") {
+            ExprKind::Assign { value, .. } => match value.kind {
+                ExprKind::Binary {
+                    op: BinaryOp::Subtract,
+                    left,
+                    right,
+                } => {
+                    assert_eq!(left.kind, ExprKind::Ident(Symbol::intern("x")));
+                    assert!(matches!(
+                        right.kind,
+                        ExprKind::Binary {
+                            op: BinaryOp::Add,
+                            ..
+                        }
+                    ));
+                }
+                other => panic!("unexpected expression {:?}", other),
+            },
+            other => panic!("unexpected expression {:?}", other),
+        }
+        assert_eq!(
+            parse("f() += 1").unwrap_err().message,
+            "invalid assignment target"
+        );
+    }
+
+    #[test]
+    fn desugars_pipelines() {
+        // The value goes in front of the stage's own arguments.
+        assert_eq!(
+            parse("x |> f(1) |> g").unwrap().to_string(),
+            parse("g(f(x, 1))").unwrap().to_string()
+        );
+        // Pipelines bind more loosely than arithmetic.
+        assert_eq!(
+            parse("1 + 2 |> f").unwrap().to_string(),
+            parse("f(1 + 2)").unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        // Ranges bind more loosely than arithmetic.
+        match parse_expr("0..n + 1") {
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                assert!(!inclusive);
+                assert_eq!(start.kind, ExprKind::Integer(0));
+                assert!(matches!(
+                    end.kind,
+                    ExprKind::Binary {
+                        op: BinaryOp::Add,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        assert!(matches!(
+            parse_expr("for i in 1..=n { i }"),
+            ExprKind::For { iterable, .. }
+                if matches!(iterable.kind, ExprKind::Range { inclusive: true, .. })
+        ));
+        // Inside brackets, `..` still slices.
+        assert!(matches!(
+            parse_expr("xs[1..n - 1]"),
+            ExprKind::Slice {
+                start: Some(_),
+                end: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_lists_indexing_and_methods() {
+        assert!(
+            matches!(parse_expr("[1, 2, 3,]"), ExprKind::List(elements) if elements.len() == 3)
+        );
+        assert!(
+            matches!(parse_expr("xs[0][1]"), ExprKind::Index { target, .. }
+            if matches!(target.kind, ExprKind::Index { .. }))
+        );
+        assert!(matches!(
+            parse_expr("xs[1..]"),
+            ExprKind::Slice {
+                start: Some(_),
+                end: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse_expr("xs[..2]"),
+            ExprKind::Slice {
+                start: None,
+                end: Some(_),
+                ..
+            }
+        ));
+        assert!(
+            matches!(parse_expr("-xs.len()"), ExprKind::Unary { operand, .. }
+            if matches!(&operand.kind, ExprKind::MethodCall { method, .. } if method == "len"))
+        );
+    }
+
+    #[test]
+    fn parses_map_literals() {
+        match parse_expr("#{ \"a\": 1, (1, 2): [3], }") {
+            ExprKind::Map(entries) => {
+                assert_eq!(entries[0].0.kind, ExprKind::String("a".to_string()));
+                assert!(matches!(entries[1].1.kind, ExprKind::List(_)));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        assert!(matches!(parse_expr("#{}"), ExprKind::Map(entries) if entries.is_empty()));
+        assert_eq!(
+            parse("#{ 1 }").unwrap_err().message,
+            "expected `:` after map key, found `}`"
+        );
+    }
+
+    #[test]
+    fn parses_structs() {
+        let program = parse("struct Point { x: Int, y } Point { y: 2, x }.x").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Struct(decl) => {
+                assert_eq!(decl.name, "Point");
+                assert!(decl.fields[0].ty.is_some() && decl.fields[1].ty.is_none());
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match &program.statements[1].kind {
+            StmtKind::Expr(expr) => match &expr.kind {
+                ExprKind::Field { target, name } => {
+                    assert_eq!(name, "x");
+                    assert!(matches!(
+                        &target.kind,
+                        ExprKind::Struct { path, fields }
+                            if path == &["Point"] && fields[1].1.kind == ExprKind::Ident(Symbol::intern("x"))
+                    ));
+                }
+                other => panic!("unexpected expression {:?}", other),
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+
+        // A `{` after a condition starts the body, not a struct literal.
+        assert!(matches!(parse_expr("if p { 1 }"), ExprKind::If { .. }));
+        match parse_expr("match p { geo::Point { x: 0, .. } => 1, Point { x, y } => 2 }") {
+            ExprKind::Match { scrutinee, arms } => {
+                assert_eq!(scrutinee.kind, ExprKind::Ident(Symbol::intern("p")));
+                assert!(matches!(
+                    &arms[0].pattern.kind,
+                    PatternKind::Struct { path, rest: true, .. } if path.len() == 2
+                ));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        assert_eq!(
+            parse("Point { x: 1, x: 2 }").unwrap_err().message,
+            "field `x` is given twice"
+        );
+    }
+
+    #[test]
+    fn parses_let_patterns() {
+        let program = parse("let mut (a, _) = t; let Point { x, .. } = p; let b = 1").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Destructure {
+                pattern, mutable, ..
+            } => {
+                assert!(mutable);
+                assert!(
+                    matches!(&pattern.kind, PatternKind::Tuple(elements) if elements.len() == 2)
+                );
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        assert!(matches!(
+            &program.statements[1].kind,
+            StmtKind::Destructure { pattern, .. } if matches!(pattern.kind, PatternKind::Struct { .. })
+        ));
+        assert!(matches!(&program.statements[2].kind, StmtKind::Let { .. }));
+    }
+
+    #[test]
+    fn parses_test_blocks_only_at_the_top_level() {
+        let program = parse("test \"adds\" { 1 + 1 } test(\"call\")").unwrap();
+        assert!(matches!(
+            &program.statements[0].kind,
+            StmtKind::Test { name, body } if name == "adds" && body.value.is_some()
+        ));
+        assert!(matches!(&program.statements[1].kind, StmtKind::Expr(_)));
+
+        let err = parse("fn f() { test \"nested\" {} }").unwrap_err();
+        assert_eq!(
+            err.message,
+            "test blocks must be at the top level of a file"
+        );
+    }
+
+    #[test]
+    fn suggests_fixes_for_common_mistakes() {
+        // The column and replacement of the fix for the error in `source`.
+        let fix = |source: &str| {
+            let err = parse(source).unwrap_err();
+            let fixes = err.suggestions();
+            assert_eq!(fixes.len(), 1, "{}", source);
+            let fix = &fixes[0];
+            (
+                fix.message.clone(),
+                fix.span.start.column,
+                fix.replacement.clone(),
+            )
+        };
+
+        let err = parse("if x = 1 { 2 }").unwrap_err();
+        assert_eq!(err.message, "expected a condition, found an assignment");
+        assert_eq!(
+            fix("if x = 1 { 2 }"),
+            ("try `==` instead of `=`".to_string(), 5, "==".to_string())
+        );
+        assert_eq!(fix("while a.b = c { 2 }").1, 10);
+        assert!(parse("while x += 1 { 2 }")
+            .unwrap_err()
+            .suggestions()
+            .is_empty());
+        assert!(parse("if (x = 1) == 1 { 2 }").is_ok());
+        assert!(parse("if (x = true) { 2 }").is_ok());
+        assert!(parse("if (a).b = 1 { 2 }").is_err());
+
+        let semicolon = (
+            "add `;` at the end of the statement".to_string(),
+            9,
+            ";".to_string(),
+        );
+        assert_eq!(fix("let x = 1 let y = 2"), semicolon);
+        assert_eq!(fix("fn f() { a = 1 b }").1, 14);
+
+        assert_eq!(
+            fix("whlie true { 1 }"),
+            ("did you mean `while`?".to_string(), 0, "while".to_string())
+        );
+        assert_eq!(fix("fn f() { retrun 1 }").2, "return");
+        assert_eq!(fix("lte x = 1;").2, "let");
+        assert_eq!(fix("strcut P { x: Int }").2, "struct");
+        assert_eq!(fix("fnc f() { 1 }").2, "fn");
+        assert_eq!(fix("spam eggs").2, ";");
+    }
+
+    #[test]
+    fn parses_type_parameters() {
+        let program = parse(
+            "fn id<T>(x: T) -> T { x } struct Pair<A, B> { left: A, right: B } enum Maybe<T,> { Just(T), Nothing }",
+        )
+        .unwrap();
+        let params: Vec<String> = program
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Function(function) => symbol::join(&function.type_params, " "),
+                StmtKind::Struct(decl) => symbol::join(&decl.type_params, " "),
+                StmtKind::Enum(decl) => symbol::join(&decl.type_params, " "),
+                other => panic!("unexpected statement {:?}", other),
+            })
+            .collect();
+        assert_eq!(params, vec!["T", "A B", "T"]);
+
+        let err = parse("fn f<T, T>(x: T) {}").unwrap_err();
+        assert_eq!(err.message, "type parameter `T` is declared twice");
+    }
+
+    #[test]
+    fn parses_traits_and_impls() {
+        let program = parse(
+            "trait Shape { fn area(self) -> Float; fn scale(self, by: Float); }
+             impl Shape for Circle { fn area(self) { 1.0 } }",
+        )
+        .unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Trait(decl) => {
+                let arities: Vec<_> = decl.methods.iter().map(|m| m.params.len()).collect();
+                assert_eq!(arities, vec![1, 2]);
+                assert!(decl.methods[0].returns.is_some());
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        assert!(matches!(
+            &program.statements[1].kind,
+            StmtKind::Impl(decl)
+                if decl.trait_name == "Shape" && decl.target == "Circle" && decl.methods.len() == 1
+        ));
+
+        let err = parse("trait T { fn f(x); }").unwrap_err();
+        assert_eq!(
+            err.message,
+            "method `f` must take `self` as its first parameter"
+        );
+    }
+
+    #[test]
+    fn parses_enums() {
+        let program = parse("enum Shape { Circle(Float), Rect(Float, Float), Empty, } 1").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Enum(decl) => {
+                let arities: Vec<_> = decl.variants.iter().map(|v| v.fields.len()).collect();
+                assert_eq!(arities, vec![1, 2, 0]);
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match parse_expr("match s { Shape::Rect(w, _) => w, Shape::Empty => 0 }") {
+            ExprKind::Match { arms, .. } => {
+                assert!(matches!(
+                    &arms[0].pattern.kind,
+                    PatternKind::Variant { path, fields } if path.len() == 2 && fields.len() == 2
+                ));
+                assert!(matches!(
+                    &arms[1].pattern.kind,
+                    PatternKind::Variant { fields, .. } if fields.is_empty()
+                ));
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        assert_eq!(
+            parse("enum E { A, A(Int) }").unwrap_err().message,
+            "variant `A` is declared twice"
+        );
+    }
+
+    #[test]
+    fn reports_missing_semicolon() {
+        let err = parse("1 2").unwrap_err();
+        assert_eq!(
+            err.message,
+            "expected `;` after expression, found integer `2`"
+        );
+    }
+
+    #[test]
+    fn reports_unexpected_end_of_file() {
+        let err = parse("(1 +").unwrap_err();
+        assert_eq!(err.message, "expected expression, found end of file");
+    }
+
+    #[test]
+    fn rejects_nesting_deeper_than_the_limit() {
+        let nested = |open: &str, inner: &str, close: &str, depth: usize| {
+            format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+        };
+        assert!(parse(&nested("(", "1", ")", MAX_DEPTH / 2)).is_ok());
+
+        let too_deep = [
+            nested("(", "1", ")", 100_000),
+            nested("-", "1", "", 100_000),
+            nested("{", "1", "}", 100_000),
+            nested("fn f() {", "", "}", 100_000),
+            format!("let {} = t", nested("(", "x", ",)", 100_000)),
+            format!("let x: {} = []", nested("List<", "Int", ">", 100_000)),
+        ];
+        for source in too_deep.iter() {
+            let err = parse(source).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!("nested more than {} levels deep", MAX_DEPTH)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_chains_longer_than_the_limit() {
+        let chain = |link: &str, length: usize| format!("x{}", link.repeat(length));
+        assert!(parse(&chain(" + 1", MAX_CHAIN)).is_ok());
+
+        let too_long = [
+            chain(" + 1", 100_000),
+            chain("()", 100_000),
+            chain(".a", 100_000),
+            chain(" |> f", 100_000),
+            format!("if a {{ 1 }}{}", " else if b { 2 }".repeat(100_000)),
+            // Links in the expressions around a chain count towards it.
+            format!(
+                "{} + ({})",
+                chain(" + 1", MAX_CHAIN / 2),
+                chain(" + 1", MAX_CHAIN / 2)
+            ),
+            format!(
+                "{}.f({})",
+                chain(".a", MAX_CHAIN / 2),
+                chain("()", MAX_CHAIN / 2)
+            ),
+        ];
+        for source in too_long.iter() {
+            let err = parse(source).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!(
+                    "more than {} operators, calls or `else if`s chained together",
+                    MAX_CHAIN
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn parses_long_else_if_chains() {
+        let source = format!(
+            "if a {{ 1 }}{} else {{ 2 }}",
+            " else if b { 3 }".repeat(1000)
+        );
+        assert!(parse(&source).is_ok());
+    }
+
+    // Pieces random sources are made of: tokens, and the starts and ends of
+    // the constructs the parser recurses into or stops in the middle of.
+    const PIECES: [&str; 64] = [
+        "let", "mut", "x", "=", "1", "2.5", "\"s\"", "\"\"\"", "r#\"", "fn", "f", "(", ")", "{",
+        "}", "[", "]", ",", ";", ":", "::", "->", "=>", "|", "||", "+", "-", "*", "..", "..=", ".",
+        "?", "!", "if", "else", "match", "while", "for", "in", "return", "break", "struct", "enum",
+        "trait", "impl", "import", "test", "@", "@!", "#", "#{", "<", ">", "Int", "_", "macro",
+        "m", "$", "$x", "$(", ")*", "m!(", "///", "\n",
+    ];
+
+    const MACRO: &str = "macro m { ($x) => { $x + $x }; ($($y),*) => { [$($y * 2),*] } }\n";
+
+    #[test]
+    fn never_panics_on_random_sources() {
+        for seed in 1..=3000 {
+            let mut random = Random(seed);
+            let pieces: Vec<_> = (0..random.below(40))
+                .map(|_| random.pick(&PIECES))
+                .collect();
+            let mut source = pieces.join(" ");
+            if seed % 2 == 0 {
+                source.insert_str(0, MACRO);
+            }
+            // Whether it parses doesn't matter, only that it returns.
+            let _ = parse(&source);
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting_in_files_with_macros() {
+        let source = format!(
+            "{}m!({}1{})",
+            MACRO,
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        );
+        let err = parse(&source).unwrap_err();
+        assert_eq!(err.message, "brackets nested more than 256 levels deep");
+    }
+}
@@ -0,0 +1,908 @@
+use std::sync::Arc;
+
+use crate::parser::ast::{
+    Block, Expr, ExprKind, Function, MatchArm, Param, Pattern, PatternKind, Stmt, StmtKind,
+    TypeExpr, TypeExprKind,
+};
+
+// Walks a syntax tree. Each method defaults to visiting the node's children,
+// so an implementation only overrides the nodes it cares about and calls the
+// matching `walk_` function to keep descending.
+pub trait Visitor: Sized {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern)
+    }
+
+    fn visit_type(&mut self, ty: &TypeExpr) {
+        walk_type(self, ty)
+    }
+}
+
+pub fn walk_stmt(visitor: &mut impl Visitor, stmt: &Stmt) {
+    match &stmt.kind {
+        StmtKind::Expr(expr) => visitor.visit_expr(expr),
+        StmtKind::Function(function) => visitor.visit_function(function),
+        StmtKind::Import(_) => {}
+        StmtKind::Struct(decl) => {
+            for ty in decl.fields.iter().filter_map(|field| field.ty.as_ref()) {
+                visitor.visit_type(ty);
+            }
+        }
+        StmtKind::Enum(decl) => {
+            for ty in decl.variants.iter().flat_map(|variant| &variant.fields) {
+                visitor.visit_type(ty);
+            }
+        }
+        StmtKind::Let { ty, value, .. } => {
+            if let Some(ty) = ty {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_expr(value);
+        }
+        StmtKind::Destructure {
+            pattern, ty, value, ..
+        } => {
+            if let Some(ty) = ty {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_expr(value);
+            visitor.visit_pattern(pattern);
+        }
+        StmtKind::Test { body, .. } => visitor.visit_block(body),
+        StmtKind::Trait(decl) => {
+            for method in &decl.methods {
+                let params = method.params.iter().filter_map(|param| param.ty.as_ref());
+                for ty in params.chain(&method.returns) {
+                    visitor.visit_type(ty);
+                }
+            }
+        }
+        StmtKind::Impl(decl) => {
+            for method in &decl.methods {
+                visitor.visit_function(method);
+            }
+        }
+    }
+}
+
+pub fn walk_block(visitor: &mut impl Visitor, block: &Block) {
+    for stmt in &block.statements {
+        visitor.visit_stmt(stmt);
+    }
+    if let Some(value) = &block.value {
+        visitor.visit_expr(value);
+    }
+}
+
+pub fn walk_function(visitor: &mut impl Visitor, function: &Function) {
+    for ty in function.params.iter().filter_map(|param| param.ty.as_ref()) {
+        visitor.visit_type(ty);
+    }
+    if let Some(returns) = &function.returns {
+        visitor.visit_type(returns);
+    }
+    visitor.visit_block(&function.body)
+}
+
+pub fn walk_type(visitor: &mut impl Visitor, ty: &TypeExpr) {
+    match &ty.kind {
+        TypeExprKind::Named { args: types, .. } | TypeExprKind::Tuple(types) => {
+            for ty in types {
+                visitor.visit_type(ty);
+            }
+        }
+        TypeExprKind::Function { params, returns } => {
+            for ty in params {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_type(returns);
+        }
+    }
+}
+
+pub fn walk_pattern(visitor: &mut impl Visitor, pattern: &Pattern) {
+    match &pattern.kind {
+        PatternKind::Tuple(patterns)
+        | PatternKind::Variant {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        PatternKind::Struct { fields, .. } => {
+            for (_, pattern) in fields {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn walk_expr(visitor: &mut impl Visitor, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Integer(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Path(_)
+        | ExprKind::Break
+        | ExprKind::Continue => {}
+        ExprKind::Tuple(elements) | ExprKind::List(elements) => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::Struct { fields, .. } => {
+            for (_, value) in fields {
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::Field { target, .. } => visitor.visit_expr(target),
+        ExprKind::Unary { operand, .. } => visitor.visit_expr(operand),
+        ExprKind::Binary { left, right, .. }
+        | ExprKind::Range {
+            start: left,
+            end: right,
+            ..
+        } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Assign { target, value } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            visitor.visit_expr(scrutinee);
+            for arm in arms {
+                visitor.visit_pattern(&arm.pattern);
+                if let Some(guard) = &arm.guard {
+                    visitor.visit_expr(guard);
+                }
+                visitor.visit_expr(&arm.body);
+            }
+        }
+        ExprKind::Block(block) => visitor.visit_block(block),
+        ExprKind::Function(function) => visitor.visit_function(function),
+        ExprKind::Call { callee, args } => {
+            visitor.visit_expr(callee);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::MethodCall { receiver, args, .. } => {
+            visitor.visit_expr(receiver);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::Index { target, index } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(index);
+        }
+        ExprKind::Slice { target, start, end } => {
+            visitor.visit_expr(target);
+            for bound in start.iter().chain(end) {
+                visitor.visit_expr(bound);
+            }
+        }
+        ExprKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expr(else_branch);
+            }
+        }
+        ExprKind::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(body);
+        }
+        ExprKind::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            visitor.visit_pattern(pattern);
+            visitor.visit_expr(iterable);
+            visitor.visit_block(body);
+        }
+    }
+}
+
+// Walks a syntax tree like `Visitor`, with each node open to changes. Shared
+// functions are copied before they are changed.
+pub trait VisitorMut: Sized {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_block(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_function(&mut self, function: &mut Function) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern)
+    }
+
+    fn visit_type(&mut self, ty: &mut TypeExpr) {
+        walk_type_mut(self, ty)
+    }
+}
+
+pub fn walk_stmt_mut(visitor: &mut impl VisitorMut, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Expr(expr) => visitor.visit_expr(expr),
+        StmtKind::Function(function) => visitor.visit_function(Arc::make_mut(function)),
+        StmtKind::Import(_) => {}
+        StmtKind::Struct(decl) => {
+            for ty in decl.fields.iter_mut().filter_map(|field| field.ty.as_mut()) {
+                visitor.visit_type(ty);
+            }
+        }
+        StmtKind::Enum(decl) => {
+            for ty in decl
+                .variants
+                .iter_mut()
+                .flat_map(|variant| &mut variant.fields)
+            {
+                visitor.visit_type(ty);
+            }
+        }
+        StmtKind::Let { ty, value, .. } => {
+            if let Some(ty) = ty {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_expr(value);
+        }
+        StmtKind::Destructure {
+            pattern, ty, value, ..
+        } => {
+            if let Some(ty) = ty {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_expr(value);
+            visitor.visit_pattern(pattern);
+        }
+        StmtKind::Test { body, .. } => visitor.visit_block(body),
+        StmtKind::Trait(decl) => {
+            for method in &mut decl.methods {
+                let params = method
+                    .params
+                    .iter_mut()
+                    .filter_map(|param| param.ty.as_mut());
+                for ty in params.chain(&mut method.returns) {
+                    visitor.visit_type(ty);
+                }
+            }
+        }
+        StmtKind::Impl(decl) => {
+            for method in &mut decl.methods {
+                visitor.visit_function(Arc::make_mut(method));
+            }
+        }
+    }
+}
+
+pub fn walk_block_mut(visitor: &mut impl VisitorMut, block: &mut Block) {
+    for stmt in &mut block.statements {
+        visitor.visit_stmt(stmt);
+    }
+    if let Some(value) = &mut block.value {
+        visitor.visit_expr(value);
+    }
+}
+
+pub fn walk_function_mut(visitor: &mut impl VisitorMut, function: &mut Function) {
+    for ty in function
+        .params
+        .iter_mut()
+        .filter_map(|param| param.ty.as_mut())
+    {
+        visitor.visit_type(ty);
+    }
+    if let Some(returns) = &mut function.returns {
+        visitor.visit_type(returns);
+    }
+    visitor.visit_block(&mut function.body)
+}
+
+pub fn walk_type_mut(visitor: &mut impl VisitorMut, ty: &mut TypeExpr) {
+    match &mut ty.kind {
+        TypeExprKind::Named { args: types, .. } | TypeExprKind::Tuple(types) => {
+            for ty in types {
+                visitor.visit_type(ty);
+            }
+        }
+        TypeExprKind::Function { params, returns } => {
+            for ty in params {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_type(returns);
+        }
+    }
+}
+
+pub fn walk_pattern_mut(visitor: &mut impl VisitorMut, pattern: &mut Pattern) {
+    match &mut pattern.kind {
+        PatternKind::Tuple(patterns)
+        | PatternKind::Variant {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        PatternKind::Struct { fields, .. } => {
+            for (_, pattern) in fields {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn walk_expr_mut(visitor: &mut impl VisitorMut, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Integer(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Path(_)
+        | ExprKind::Break
+        | ExprKind::Continue => {}
+        ExprKind::Tuple(elements) | ExprKind::List(elements) => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::Struct { fields, .. } => {
+            for (_, value) in fields {
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::Field { target, .. } => visitor.visit_expr(target),
+        ExprKind::Unary { operand, .. } => visitor.visit_expr(operand),
+        ExprKind::Binary { left, right, .. }
+        | ExprKind::Range {
+            start: left,
+            end: right,
+            ..
+        } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Assign { target, value } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            visitor.visit_expr(scrutinee);
+            for arm in arms {
+                visitor.visit_pattern(&mut arm.pattern);
+                if let Some(guard) = &mut arm.guard {
+                    visitor.visit_expr(guard);
+                }
+                visitor.visit_expr(&mut arm.body);
+            }
+        }
+        ExprKind::Block(block) => visitor.visit_block(block),
+        ExprKind::Function(function) => visitor.visit_function(Arc::make_mut(function)),
+        ExprKind::Call { callee, args } => {
+            visitor.visit_expr(callee);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::MethodCall { receiver, args, .. } => {
+            visitor.visit_expr(receiver);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::Index { target, index } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(index);
+        }
+        ExprKind::Slice { target, start, end } => {
+            visitor.visit_expr(target);
+            for bound in start.iter_mut().chain(end) {
+                visitor.visit_expr(bound);
+            }
+        }
+        ExprKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expr(else_branch);
+            }
+        }
+        ExprKind::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(body);
+        }
+        ExprKind::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            visitor.visit_pattern(pattern);
+            visitor.visit_expr(iterable);
+            visitor.visit_block(body);
+        }
+    }
+}
+
+// Rebuilds a syntax tree, taking each node and returning its replacement.
+// Each method defaults to rebuilding the node from its folded children with
+// the matching `fold_` function.
+pub trait Fold: Sized {
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        fold_stmt(self, stmt)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        fold_block(self, block)
+    }
+
+    fn fold_function(&mut self, function: Function) -> Function {
+        fold_function(self, function)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Pattern {
+        fold_pattern(self, pattern)
+    }
+
+    fn fold_type(&mut self, ty: TypeExpr) -> TypeExpr {
+        fold_type(self, ty)
+    }
+}
+
+pub fn fold_stmt(folder: &mut impl Fold, stmt: Stmt) -> Stmt {
+    let kind = match stmt.kind {
+        StmtKind::Expr(expr) => StmtKind::Expr(folder.fold_expr(expr)),
+        StmtKind::Function(function) => StmtKind::Function(fold_shared(folder, function)),
+        StmtKind::Import(path) => StmtKind::Import(path),
+        StmtKind::Struct(mut decl) => {
+            for field in &mut decl.fields {
+                field.ty = field.ty.take().map(|ty| folder.fold_type(ty));
+            }
+            StmtKind::Struct(decl)
+        }
+        StmtKind::Enum(mut decl) => {
+            for variant in &mut decl.variants {
+                variant.fields = fold_all(std::mem::take(&mut variant.fields), |ty| {
+                    folder.fold_type(ty)
+                });
+            }
+            StmtKind::Enum(decl)
+        }
+        StmtKind::Let {
+            name,
+            mutable,
+            ty,
+            value,
+        } => StmtKind::Let {
+            name,
+            mutable,
+            ty: ty.map(|ty| folder.fold_type(ty)),
+            value: folder.fold_expr(value),
+        },
+        StmtKind::Destructure {
+            pattern,
+            mutable,
+            ty,
+            value,
+        } => {
+            let ty = ty.map(|ty| folder.fold_type(ty));
+            let value = folder.fold_expr(value);
+            StmtKind::Destructure {
+                pattern: folder.fold_pattern(pattern),
+                mutable,
+                ty,
+                value,
+            }
+        }
+        StmtKind::Test { name, body } => StmtKind::Test {
+            name,
+            body: folder.fold_block(body),
+        },
+        StmtKind::Trait(mut decl) => {
+            for method in &mut decl.methods {
+                for param in &mut method.params {
+                    param.ty = param.ty.take().map(|ty| folder.fold_type(ty));
+                }
+                method.returns = method.returns.take().map(|ty| folder.fold_type(ty));
+            }
+            StmtKind::Trait(decl)
+        }
+        StmtKind::Impl(mut decl) => {
+            decl.methods = fold_all(decl.methods, |method| fold_shared(folder, method));
+            StmtKind::Impl(decl)
+        }
+    };
+    Stmt {
+        kind,
+        span: stmt.span,
+    }
+}
+
+pub fn fold_block(folder: &mut impl Fold, block: Block) -> Block {
+    Block {
+        statements: fold_all(block.statements, |stmt| folder.fold_stmt(stmt)),
+        value: block.value.map(|value| fold_boxed(folder, value)),
+        span: block.span,
+    }
+}
+
+pub fn fold_function(folder: &mut impl Fold, function: Function) -> Function {
+    let params = fold_all(function.params, |param| Param {
+        ty: param.ty.map(|ty| folder.fold_type(ty)),
+        ..param
+    });
+    let returns = function.returns.map(|ty| folder.fold_type(ty));
+    Function {
+        name: function.name,
+        doc: function.doc,
+        attributes: function.attributes,
+        type_params: function.type_params,
+        params,
+        returns,
+        body: folder.fold_block(function.body),
+        span: function.span,
+    }
+}
+
+pub fn fold_type(folder: &mut impl Fold, ty: TypeExpr) -> TypeExpr {
+    let kind = match ty.kind {
+        TypeExprKind::Named { name, args } => TypeExprKind::Named {
+            name,
+            args: fold_all(args, |ty| folder.fold_type(ty)),
+        },
+        TypeExprKind::Tuple(types) => {
+            TypeExprKind::Tuple(fold_all(types, |ty| folder.fold_type(ty)))
+        }
+        TypeExprKind::Function { params, returns } => TypeExprKind::Function {
+            params: fold_all(params, |ty| folder.fold_type(ty)),
+            returns: Box::new(folder.fold_type(*returns)),
+        },
+    };
+    TypeExpr {
+        kind,
+        span: ty.span,
+    }
+}
+
+pub fn fold_pattern(folder: &mut impl Fold, pattern: Pattern) -> Pattern {
+    let kind = match pattern.kind {
+        PatternKind::Tuple(patterns) => {
+            PatternKind::Tuple(fold_all(patterns, |pattern| folder.fold_pattern(pattern)))
+        }
+        PatternKind::Variant { path, fields } => PatternKind::Variant {
+            path,
+            fields: fold_all(fields, |pattern| folder.fold_pattern(pattern)),
+        },
+        PatternKind::Struct { path, fields, rest } => PatternKind::Struct {
+            path,
+            fields: fold_all(fields, |(name, pattern)| {
+                (name, folder.fold_pattern(pattern))
+            }),
+            rest,
+        },
+        other => other,
+    };
+    Pattern {
+        kind,
+        span: pattern.span,
+    }
+}
+
+pub fn fold_expr(folder: &mut impl Fold, expr: Expr) -> Expr {
+    let kind = match expr.kind {
+        kind @ ExprKind::Integer(_)
+        | kind @ ExprKind::Float(_)
+        | kind @ ExprKind::String(_)
+        | kind @ ExprKind::Bool(_)
+        | kind @ ExprKind::Ident(_)
+        | kind @ ExprKind::Path(_)
+        | kind @ ExprKind::Break
+        | kind @ ExprKind::Continue => kind,
+        ExprKind::Tuple(elements) => {
+            ExprKind::Tuple(fold_all(elements, |element| folder.fold_expr(element)))
+        }
+        ExprKind::List(elements) => {
+            ExprKind::List(fold_all(elements, |element| folder.fold_expr(element)))
+        }
+        ExprKind::Map(entries) => ExprKind::Map(fold_all(entries, |(key, value)| {
+            let key = folder.fold_expr(key);
+            (key, folder.fold_expr(value))
+        })),
+        ExprKind::Struct { path, fields } => ExprKind::Struct {
+            path,
+            fields: fold_all(fields, |(name, value)| (name, folder.fold_expr(value))),
+        },
+        ExprKind::Field { target, name } => ExprKind::Field {
+            target: fold_boxed(folder, target),
+            name,
+        },
+        ExprKind::Unary { op, operand } => ExprKind::Unary {
+            op,
+            operand: fold_boxed(folder, operand),
+        },
+        ExprKind::Binary { op, left, right } => {
+            let left = fold_boxed(folder, left);
+            ExprKind::Binary {
+                op,
+                left,
+                right: fold_boxed(folder, right),
+            }
+        }
+        ExprKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let start = fold_boxed(folder, start);
+            ExprKind::Range {
+                start,
+                end: fold_boxed(folder, end),
+                inclusive,
+            }
+        }
+        ExprKind::Assign { target, value } => {
+            let target = fold_boxed(folder, target);
+            ExprKind::Assign {
+                target,
+                value: fold_boxed(folder, value),
+            }
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            let scrutinee = fold_boxed(folder, scrutinee);
+            let arms = fold_all(arms, |arm| {
+                let pattern = folder.fold_pattern(arm.pattern);
+                let guard = arm.guard.map(|guard| folder.fold_expr(guard));
+                MatchArm {
+                    pattern,
+                    guard,
+                    body: folder.fold_expr(arm.body),
+                    span: arm.span,
+                }
+            });
+            ExprKind::Match { scrutinee, arms }
+        }
+        ExprKind::Block(block) => ExprKind::Block(folder.fold_block(block)),
+        ExprKind::Function(function) => ExprKind::Function(fold_shared(folder, function)),
+        ExprKind::Call { callee, args } => {
+            let callee = fold_boxed(folder, callee);
+            ExprKind::Call {
+                callee,
+                args: fold_all(args, |arg| folder.fold_expr(arg)),
+            }
+        }
+        ExprKind::MethodCall {
+            receiver,
+            method,
+            args,
+        } => {
+            let receiver = fold_boxed(folder, receiver);
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args: fold_all(args, |arg| folder.fold_expr(arg)),
+            }
+        }
+        ExprKind::Index { target, index } => {
+            let target = fold_boxed(folder, target);
+            ExprKind::Index {
+                target,
+                index: fold_boxed(folder, index),
+            }
+        }
+        ExprKind::Slice { target, start, end } => {
+            let target = fold_boxed(folder, target);
+            let start = start.map(|start| fold_boxed(folder, start));
+            ExprKind::Slice {
+                target,
+                start,
+                end: end.map(|end| fold_boxed(folder, end)),
+            }
+        }
+        ExprKind::Return(value) => ExprKind::Return(value.map(|value| fold_boxed(folder, value))),
+        ExprKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = fold_boxed(folder, condition);
+            let then_branch = folder.fold_block(then_branch);
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch: else_branch.map(|branch| fold_boxed(folder, branch)),
+            }
+        }
+        ExprKind::While { condition, body } => {
+            let condition = fold_boxed(folder, condition);
+            ExprKind::While {
+                condition,
+                body: folder.fold_block(body),
+            }
+        }
+        ExprKind::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            let pattern = folder.fold_pattern(pattern);
+            let iterable = fold_boxed(folder, iterable);
+            ExprKind::For {
+                pattern,
+                iterable,
+                body: folder.fold_block(body),
+            }
+        }
+    };
+    Expr {
+        kind,
+        span: expr.span,
+    }
+}
+
+fn fold_all<T>(items: Vec<T>, fold: impl FnMut(T) -> T) -> Vec<T> {
+    items.into_iter().map(fold).collect()
+}
+
+fn fold_boxed(folder: &mut impl Fold, expr: Box<Expr>) -> Box<Expr> {
+    Box::new(folder.fold_expr(*expr))
+}
+
+// Folds a function, copying it first if it is shared.
+fn fold_shared(folder: &mut impl Fold, function: Arc<Function>) -> Arc<Function> {
+    let function = Arc::try_unwrap(function).unwrap_or_else(|shared| (*shared).clone());
+    Arc::new(folder.fold_function(function))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::symbol::Symbol;
+    use crate::parser::ast::{Expr, ExprKind, Program, TypeExpr, TypeExprKind};
+    use crate::parser::parser::parse;
+    use crate::parser::visit::{fold_expr, walk_expr_mut, walk_type, Fold, Visitor, VisitorMut};
+
+    #[test]
+    fn visits_every_type() {
+        struct Names(Vec<String>);
+        impl Visitor for Names {
+            fn visit_type(&mut self, ty: &TypeExpr) {
+                if let TypeExprKind::Named { name, .. } = &ty.kind {
+                    self.0.push(name.to_string());
+                }
+                walk_type(self, ty);
+            }
+        }
+
+        let program =
+            parse("struct P { x: Int } fn f(a: List<P>) -> Fn(Bool) -> () { let b: String = a; }")
+                .unwrap();
+        let mut names = Names(Vec::new());
+        program
+            .statements
+            .iter()
+            .for_each(|stmt| names.visit_stmt(stmt));
+        assert_eq!(names.0, vec!["Int", "List", "P", "Bool", "String"]);
+    }
+
+    #[test]
+    fn changes_nodes_in_place() {
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn visit_expr(&mut self, expr: &mut Expr) {
+                if let ExprKind::Ident(name) = &mut expr.kind {
+                    *name = Symbol::intern(&format!("{}_", name));
+                }
+                walk_expr_mut(self, expr);
+            }
+        }
+
+        let mut program = parse("let f = fn(x) { x + y }; f(y)").unwrap();
+        program
+            .statements
+            .iter_mut()
+            .for_each(|stmt| Rename.visit_stmt(stmt));
+        assert_eq!(
+            program.to_string(),
+            "let f = fn(x) {\n    x_ + y_\n};\nf_(y_);"
+        );
+    }
+
+    #[test]
+    fn folds_into_new_nodes() {
+        // Replaces `n` with `-n`, keeping the span.
+        struct Negate;
+        impl Fold for Negate {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                match expr.kind {
+                    ExprKind::Integer(n) => Expr {
+                        kind: ExprKind::Integer(-n),
+                        span: expr.span,
+                    },
+                    _ => fold_expr(self, expr),
+                }
+            }
+        }
+
+        let program = parse("fn f() { [1, 2 * 3] } match f() { _ => 4 }").unwrap();
+        let folded = Program {
+            attributes: program.attributes,
+            statements: program
+                .statements
+                .into_iter()
+                .map(|stmt| Negate.fold_stmt(stmt))
+                .collect(),
+        };
+        assert_eq!(
+            folded.to_string(),
+            "fn f() {\n    [-1, -2 * -3]\n}\nmatch f() {\n    _ => -4,\n}"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod pass;
+#[allow(clippy::module_inception)]
+pub mod pipeline;
@@ -0,0 +1,23 @@
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::parser::ast::Program;
+
+// Pipeline stages, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    // Rewrites the freshly parsed program, e.g. desugaring or instrumentation.
+    Transform,
+    // Inspects the program and reports diagnostics, e.g. lints.
+    Analysis,
+    // Consumes a program that passed analysis without errors, e.g. code generators.
+    Output,
+}
+
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    fn stage(&self) -> Stage {
+        Stage::Analysis
+    }
+
+    fn run(&mut self, program: &mut Program, diagnostics: &mut Vec<Diagnostic>);
+}
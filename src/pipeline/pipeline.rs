@@ -0,0 +1,261 @@
+use std::path::Path;
+
+use crate::analysis::exhaustiveness::ExhaustivenessPass;
+use crate::diagnostic::diagnostic::{Diagnostic, Severity};
+use crate::lexer::lexer::LexerOptions;
+#[cfg(feature = "dynamic-plugins")]
+use crate::lexer::symbol::{self, Symbols};
+use crate::parser::ast::Program;
+use crate::parser::cache::ParseCache;
+use crate::parser::parser::parse_with_options;
+use crate::pipeline::pass::{Pass, Stage};
+
+// Symbol a dynamically loaded plugin exports to register its passes:
+//
+//   #[no_mangle]
+//   pub fn clay_register_passes(pipeline: &mut Pipeline) { ... }
+//
+// Rust has no stable ABI, so plugins must be built with the same compiler
+// and clay version as the host. A plugin links its own copy of clay, so its
+// symbols are looked up in the host's interner from its first `register`;
+// interning a name before then panics there, as its symbols would name
+// something else in the host.
+#[cfg(feature = "dynamic-plugins")]
+pub const PLUGIN_ENTRY_POINT: &[u8] = b"clay_register_passes";
+
+pub struct Pipeline {
+    lexer_options: LexerOptions,
+    // Where files parsed before are taken from, when their source hasn't
+    // changed.
+    cache: Option<ParseCache>,
+    passes: Vec<Box<dyn Pass>>,
+    // Declared after `passes` so plugin code outlives the passes it created.
+    #[cfg(feature = "dynamic-plugins")]
+    libraries: Vec<libloading::Library>,
+    // The host's interner, for the plugins to share.
+    #[cfg(feature = "dynamic-plugins")]
+    symbols: Symbols,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        let mut pipeline = Pipeline::empty();
+        pipeline.register(ExhaustivenessPass);
+        pipeline
+    }
+
+    pub fn empty() -> Pipeline {
+        Pipeline {
+            lexer_options: LexerOptions::default(),
+            cache: None,
+            passes: Vec::new(),
+            #[cfg(feature = "dynamic-plugins")]
+            libraries: Vec::new(),
+            #[cfg(feature = "dynamic-plugins")]
+            symbols: symbol::symbols(),
+        }
+    }
+
+    pub fn register(&mut self, pass: impl Pass + 'static) {
+        // Run by a plugin's copy of clay too, which from here on agrees with
+        // the host on symbols.
+        #[cfg(feature = "dynamic-plugins")]
+        assert!(
+            symbol::share(self.symbols),
+            "a plugin interned a name before registering its passes"
+        );
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn set_lexer_options(&mut self, options: LexerOptions) {
+        self.lexer_options = options;
+    }
+
+    pub fn lexer_options(&self) -> LexerOptions {
+        self.lexer_options
+    }
+
+    pub fn set_cache(&mut self, cache: ParseCache) {
+        self.cache = Some(cache);
+    }
+
+    pub fn cache(&self) -> Option<&ParseCache> {
+        self.cache.as_ref()
+    }
+
+    // Parses `source`, read from `path`, through the cache if there is one.
+    pub fn parse_file(&self, path: &Path, source: &str) -> Result<Program, Diagnostic> {
+        match &self.cache {
+            Some(cache) => cache.parse(path, source, self.lexer_options),
+            None => parse_with_options(source, self.lexer_options),
+        }
+    }
+
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    #[cfg(feature = "dynamic-plugins")]
+    pub fn load_plugin(&mut self, path: &std::path::Path) -> Result<(), String> {
+        type Register = fn(&mut Pipeline);
+
+        // Safety: loading a library runs its initializers and we trust the
+        // entry point's signature; both are inherent to native plugins.
+        unsafe {
+            let library = libloading::Library::new(path)
+                .map_err(|err| format!("could not load plugin `{}`: {}", path.display(), err))?;
+            let register = library
+                .get::<Register>(PLUGIN_ENTRY_POINT)
+                .map_err(|err| format!("`{}` is not a clay plugin: {}", path.display(), err))?;
+            register(self);
+            self.libraries.push(library);
+        }
+        Ok(())
+    }
+
+    // Parses `source` and runs every registered pass stage by stage. Returns
+    // the program when no stage reported an error; warnings and errors are
+    // collected into `diagnostics` either way.
+    pub fn process(&mut self, source: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<Program> {
+        let parsed = parse_with_options(source, self.lexer_options);
+        self.process_parsed(parsed, diagnostics)
+    }
+
+    // Like `process`, for source read from `path`, which may be parsed
+    // already in the cache.
+    pub fn process_file(
+        &mut self,
+        path: &Path,
+        source: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<Program> {
+        let parsed = self.parse_file(path, source);
+        self.process_parsed(parsed, diagnostics)
+    }
+
+    fn process_parsed(
+        &mut self,
+        parsed: Result<Program, Diagnostic>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<Program> {
+        match parsed {
+            Ok(program) => self.run_passes(program, diagnostics),
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                None
+            }
+        }
+    }
+
+    // Runs the passes over a program parsed elsewhere, with `lexer_options`.
+    pub fn run_passes(
+        &mut self,
+        mut program: Program,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<Program> {
+        for stage in [Stage::Transform, Stage::Analysis, Stage::Output].iter() {
+            for pass in self.passes.iter_mut().filter(|pass| pass.stage() == *stage) {
+                pass.run(&mut program, diagnostics);
+            }
+            if has_errors(diagnostics) {
+                return None;
+            }
+        }
+
+        Some(program)
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+    }
+}
+
+fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::diagnostic::Diagnostic;
+    use crate::parser::ast::{Expr, ExprKind, Program, StmtKind};
+    use crate::pipeline::pass::{Pass, Stage};
+    use crate::pipeline::pipeline::Pipeline;
+
+    struct DoubleIntegers;
+
+    impl Pass for DoubleIntegers {
+        fn name(&self) -> &str {
+            "double-integers"
+        }
+
+        fn stage(&self) -> Stage {
+            Stage::Transform
+        }
+
+        fn run(&mut self, program: &mut Program, _: &mut Vec<Diagnostic>) {
+            for stmt in &mut program.statements {
+                if let StmtKind::Expr(Expr {
+                    kind: ExprKind::Integer(n),
+                    ..
+                }) = &mut stmt.kind
+                {
+                    *n *= 2;
+                }
+            }
+        }
+    }
+
+    struct DenyStrings;
+
+    impl Pass for DenyStrings {
+        fn name(&self) -> &str {
+            "deny-strings"
+        }
+
+        fn run(&mut self, program: &mut Program, diagnostics: &mut Vec<Diagnostic>) {
+            for stmt in &program.statements {
+                if let StmtKind::Expr(Expr {
+                    kind: ExprKind::String(_),
+                    span,
+                }) = &stmt.kind
+                {
+                    diagnostics.push(Diagnostic::error("strings are not allowed", *span));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn runs_registered_passes() {
+        let mut pipeline = Pipeline::new();
+        pipeline.register(DoubleIntegers);
+        assert_eq!(
+            pipeline.pass_names(),
+            vec!["exhaustiveness", "double-integers"]
+        );
+
+        let mut diagnostics = Vec::new();
+        let program = pipeline.process("21", &mut diagnostics).unwrap();
+        assert!(matches!(
+            &program.statements[0].kind,
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Integer(42),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn stops_after_a_stage_reports_errors() {
+        let mut pipeline = Pipeline::empty();
+        pipeline.register(DenyStrings);
+
+        let mut diagnostics = Vec::new();
+        assert!(pipeline.process("\"hi\"", &mut diagnostics).is_none());
+        assert_eq!(diagnostics[0].message, "strings are not allowed");
+    }
+}
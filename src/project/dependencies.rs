@@ -0,0 +1,380 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::interpreter::module::{display_path, Alias, ImportMap};
+use crate::project::manifest::{Manifest, MANIFEST};
+
+// Where git dependencies are cloned, relative to the manifest of the project
+// being built. A checkout is used as it is once it exists; deleting it makes
+// the next build clone it again.
+pub const CHECKOUTS: &str = ".clay/deps";
+
+// A `[dependencies]` entry: another clay project, whose modules are
+// imported as `name::module`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    // `{ path = "../utils" }`, relative to the manifest.
+    Path(String),
+    // `{ git = "https://...", rev = "v1" }`: a repository git can clone, at
+    // `rev` if there is one and at its default branch otherwise.
+    Git { url: String, rev: Option<String> },
+}
+
+// A dependency found on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub name: String,
+    pub manifest: Manifest,
+    // Where its modules are: its first root, or else its directory.
+    pub root: PathBuf,
+    // Which project depends on it, for diagnostics.
+    pub required_by: String,
+}
+
+// How imports in `manifest`'s project resolve: through the manifest's own
+// aliases and roots, and through an alias to the root of each dependency,
+// direct or not.
+pub fn import_map(manifest: &Manifest) -> Result<ImportMap, String> {
+    let mut map = manifest.import_map();
+    for dependency in resolve(manifest)? {
+        if map
+            .aliases
+            .iter()
+            .any(|alias| alias.name == dependency.name)
+        {
+            return Err(format!(
+                "`{}` is both an import alias and a dependency",
+                dependency.name
+            ));
+        }
+        map.aliases.push(Alias {
+            declared: format!(
+                "dependency `{}` of `{}`",
+                dependency.name, dependency.required_by
+            ),
+            name: dependency.name,
+            target: dependency.root,
+        });
+    }
+    Ok(map)
+}
+
+// Finds the dependencies of `manifest`, and theirs in turn, nearest first.
+// Git dependencies are cloned into `CHECKOUTS` the first time they are
+// needed. Projects are told apart by name, so two dependencies of the same
+// name must be the same project.
+pub fn resolve(manifest: &Manifest) -> Result<Vec<Resolved>, String> {
+    let mut pending: VecDeque<_> = requires(manifest).into_iter().collect();
+    let mut resolved: Vec<Resolved> = Vec::new();
+
+    while let Some((dependency, base, required_by)) = pending.pop_front() {
+        let dir = locate(&dependency, &base, manifest)?;
+        if let Some(existing) = resolved.iter().find(|r| r.name == dependency.name) {
+            if existing.manifest.dir != dir {
+                return Err(format!(
+                    "dependency `{}` is `{}` for `{}` but `{}` for `{}`",
+                    dependency.name,
+                    display_path(&existing.manifest.dir),
+                    existing.required_by,
+                    display_path(&dir),
+                    required_by
+                ));
+            }
+            continue;
+        }
+
+        let path = dir.join(MANIFEST);
+        if !path.is_file() {
+            return Err(format!(
+                "dependency `{}` at `{}` has no {}",
+                dependency.name,
+                display_path(&dir),
+                MANIFEST
+            ));
+        }
+        let loaded = Manifest::load(&path)?;
+        pending.extend(requires(&loaded));
+        let root = match loaded.roots.first() {
+            Some(root) => loaded.dir.join(root),
+            None => loaded.dir.clone(),
+        };
+        resolved.push(Resolved {
+            name: dependency.name,
+            manifest: loaded,
+            root,
+            required_by,
+        });
+    }
+    Ok(resolved)
+}
+
+// The dependencies `declaring` asks for, with where and by whom.
+fn requires(declaring: &Manifest) -> Vec<(Dependency, PathBuf, String)> {
+    let dependencies = declaring.dependencies.iter().cloned();
+    dependencies
+        .map(|dependency| (dependency, declaring.dir.clone(), declaring.name.clone()))
+        .collect()
+}
+
+// The directory `dependency` is in, declared by the manifest in `base`,
+// cloning it first if it comes from git and hasn't been cloned. Checkouts
+// all go under the project being built, so dependencies share them.
+fn locate(dependency: &Dependency, base: &Path, project: &Manifest) -> Result<PathBuf, String> {
+    let dir = match &dependency.source {
+        Source::Path(path) => base.join(path),
+        Source::Git { url, rev } => {
+            let checkout = project.dir.join(CHECKOUTS).join(&dependency.name);
+            if !checkout.is_dir() {
+                clone(&dependency.name, url, rev.as_deref(), base, &checkout)?;
+            }
+            checkout
+        }
+    };
+    dir.canonicalize().map_err(|err| {
+        format!(
+            "could not find dependency `{}` at `{}`: {}",
+            dependency.name,
+            display_path(&dir),
+            err
+        )
+    })
+}
+
+// Clones `url`, which may be a path relative to `base`, into `checkout`.
+fn clone(
+    name: &str,
+    url: &str,
+    rev: Option<&str>,
+    base: &Path,
+    checkout: &Path,
+) -> Result<(), String> {
+    let failed = |message: String| {
+        format!(
+            "could not clone dependency `{}` from `{}`: {}",
+            name, url, message
+        )
+    };
+    let checkout = base.join(checkout);
+    let mut commands = vec![git(
+        base,
+        &["clone", "--quiet", "--", url, &checkout.to_string_lossy()],
+    )];
+    if let Some(rev) = rev {
+        // A `--` before it would make git read it as a path, so it is
+        // checked here instead, and the one after keeps it from being one.
+        if rev.starts_with('-') {
+            return Err(failed(format!("`{}` is not a revision", rev)));
+        }
+        commands.push(git(&checkout, &["checkout", "--quiet", rev, "--"]));
+    }
+    for mut command in commands {
+        let output = command
+            .output()
+            .map_err(|err| failed(format!("could not run git: {}", err)))?;
+        if !output.status.success() {
+            let _ = std::fs::remove_dir_all(&checkout);
+            return Err(failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(dir).args(args);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::value::Value;
+    use crate::parser::parser::parse;
+    use crate::project::dependencies::{clone, import_map, resolve, CHECKOUTS};
+    use crate::project::manifest::{Manifest, MANIFEST};
+
+    fn write_projects(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clay-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, source) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir.canonicalize().unwrap()
+    }
+
+    fn load(path: &Path) -> Manifest {
+        Manifest::load(&path.join(MANIFEST)).unwrap()
+    }
+
+    #[test]
+    fn resolves_path_dependencies_and_theirs() {
+        let dir = write_projects(
+            "deps",
+            &[
+                (
+                    "app/clay.toml",
+                    "[package]\nname = \"app\"\n[dependencies]\nutils = { path = \"../utils\" }\nmath = { path = \"../math\" }",
+                ),
+                ("app/main.clay", ""),
+                (
+                    "utils/clay.toml",
+                    "[package]\nname = \"utils\"\nroots = [\"src\"]\n[dependencies]\nmath = { path = \"../math\" }",
+                ),
+                ("utils/src/strings.clay", "import math::numbers; x = numbers::x + 1;"),
+                ("math/clay.toml", "[package]\nname = \"math\""),
+                ("math/numbers.clay", "x = 40;"),
+            ],
+        );
+        let resolved = resolve(&load(&dir.join("app"))).unwrap();
+        let found: Vec<_> = resolved
+            .iter()
+            .map(|dependency| (dependency.name.as_str(), dependency.required_by.as_str()))
+            .collect();
+        assert_eq!(found, [("utils", "app"), ("math", "app")]);
+        assert_eq!(resolved[0].root, dir.join("utils/src"));
+        assert_eq!(resolved[1].root, dir.join("math"));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&dir.join("app/main.clay"));
+        interpreter.set_import_map(import_map(&load(&dir.join("app"))).unwrap());
+        let program =
+            parse("import utils::strings; import math::numbers; strings::x + numbers::x").unwrap();
+        assert_eq!(interpreter.run(&program).unwrap(), Value::Integer(81));
+    }
+
+    #[test]
+    fn reports_dependencies_that_cannot_be_resolved() {
+        let dir = write_projects(
+            "bad-deps",
+            &[
+                (
+                    "app/clay.toml",
+                    "[package]\nname = \"app\"\n[dependencies]\nutils = { path = \"../utils\" }\nmath = { path = \"../math\" }",
+                ),
+                (
+                    "utils/clay.toml",
+                    "[package]\nname = \"utils\"\n[dependencies]\nmath = { path = \"../other\" }",
+                ),
+                ("math/clay.toml", "[package]\nname = \"math\""),
+                ("other/clay.toml", "[package]\nname = \"math\""),
+                ("empty/lib.clay", ""),
+                (
+                    "plain/clay.toml",
+                    "[package]\nname = \"plain\"\n[dependencies]\nempty = { path = \"../empty\" }",
+                ),
+                (
+                    "aliased/clay.toml",
+                    "[package]\nname = \"aliased\"\n[imports]\nmath = \"vendor\"\n[dependencies]\nmath = { path = \"../math\" }",
+                ),
+            ],
+        );
+        let error = resolve(&load(&dir.join("app"))).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "dependency `math` is `{}` for `app` but `{}` for `utils`",
+                dir.join("math").display(),
+                dir.join("other").display()
+            )
+        );
+        let error = resolve(&load(&dir.join("plain"))).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "dependency `empty` at `{}` has no clay.toml",
+                dir.join("empty").display()
+            )
+        );
+        let error = import_map(&load(&dir.join("aliased"))).unwrap_err();
+        assert_eq!(error, "`math` is both an import alias and a dependency");
+    }
+
+    #[test]
+    fn clones_git_dependencies_at_a_revision() {
+        let dir = write_projects(
+            "git-deps",
+            &[
+                ("remote/clay.toml", "[package]\nname = \"remote\""),
+                ("remote/lib.clay", "x = 1;"),
+                (
+                    "app/clay.toml",
+                    "[package]\nname = \"app\"\n[dependencies]\nremote = { git = \"../remote\", rev = \"v1\" }",
+                ),
+            ],
+        );
+        // Without git there is nothing to clone from.
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(dir.join("remote"))
+                .args(["-c", "user.name=clay", "-c", "user.email=clay@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "--quiet"]);
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "first"]);
+        git(&["tag", "v1"]);
+        fs::write(dir.join("remote/lib.clay"), "x = 2;").unwrap();
+        git(&["commit", "--quiet", "-am", "second"]);
+
+        let resolved = resolve(&load(&dir.join("app"))).unwrap();
+        let checkout = dir.join("app").join(CHECKOUTS).join("remote");
+        assert_eq!(resolved[0].root, checkout);
+        assert_eq!(
+            fs::read_to_string(checkout.join("lib.clay")).unwrap(),
+            "x = 1;"
+        );
+
+        fs::write(
+            dir.join("app/clay.toml"),
+            "[package]\nname = \"app\"\n[dependencies]\nmissing = { git = \"../missing\" }",
+        )
+        .unwrap();
+        let error = resolve(&load(&dir.join("app"))).unwrap_err();
+        assert!(
+            error.starts_with("could not clone dependency `missing` from `../missing`: "),
+            "{}",
+            error
+        );
+        assert!(!dir.join("app").join(CHECKOUTS).join("missing").exists());
+    }
+
+    #[test]
+    fn passes_urls_and_revisions_to_git_as_arguments() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = write_projects("git-options", &[("app/clay.toml", "")]);
+        let marker = dir.join("marker");
+        let url = format!("--upload-pack=touch {}", marker.display());
+        let checkout = dir.join("app").join(CHECKOUTS).join("evil");
+        assert!(clone("evil", &url, None, &dir, &checkout).is_err());
+        assert!(!marker.exists());
+        let error = clone("evil", "../missing", Some("--orphan=x"), &dir, &checkout).unwrap_err();
+        assert!(
+            error.starts_with("could not clone dependency `evil`"),
+            "{}",
+            error
+        );
+    }
+}
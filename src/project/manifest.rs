@@ -0,0 +1,506 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::interpreter::module::{Alias, ImportMap};
+use crate::lint::lint::Level;
+use crate::project::dependencies::{Dependency, Source};
+
+pub const MANIFEST: &str = "clay.toml";
+
+// A project's `clay.toml`. Only the small part of TOML manifests use is
+// understood: sections, and keys set to strings or lists of strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    // The directory the manifest is in; paths in it are relative to this.
+    pub dir: PathBuf,
+    pub name: String,
+    pub version: String,
+    pub entry: Option<String>,
+    // Directories searched for `import a::b` after the importing file's own.
+    pub roots: Vec<String>,
+    // `[imports]` entries, in the order they are written.
+    pub imports: Vec<(String, String)>,
+    // `[lints]` entries: the level each named lint rule is set to.
+    pub lints: Vec<(String, Level)>,
+    // `[dependencies]` entries, in the order they are written.
+    pub dependencies: Vec<Dependency>,
+}
+
+enum Value {
+    String(String),
+    List(Vec<String>),
+    // `{ key = "value", ... }`, with the keys in the order they are written.
+    Table(Vec<(String, String)>),
+}
+
+impl Manifest {
+    // Finds the manifest of the project `path` belongs to by walking up from
+    // it.
+    pub fn find(path: &Path) -> Option<PathBuf> {
+        let path = path.canonicalize().ok()?;
+        path.ancestors()
+            .map(|dir| dir.join(MANIFEST))
+            .find(|manifest| manifest.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("could not read `{}`: {}", path.display(), err))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Manifest::parse(&source, dir)
+            .map_err(|(line, message)| format!("{}:{}: {}", path.display(), line, message))
+    }
+
+    // Parses a manifest, failing with the line of the first error.
+    pub fn parse(source: &str, dir: &Path) -> Result<Manifest, (usize, String)> {
+        let mut manifest = Manifest {
+            dir: dir.to_path_buf(),
+            name: String::new(),
+            version: String::new(),
+            entry: None,
+            roots: Vec::new(),
+            imports: Vec::new(),
+            lints: Vec::new(),
+            dependencies: Vec::new(),
+        };
+        let mut section = String::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                if !matches!(
+                    section.as_str(),
+                    "package" | "imports" | "lints" | "dependencies"
+                ) {
+                    return Err((number, format!("unknown section `[{}]`", section)));
+                }
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| (number, "expected `key = value`".to_string()))?;
+            let key = parse_key(key.trim()).ok_or_else(|| (number, "invalid key".to_string()))?;
+            let value = parse_value(value.trim()).map_err(|message| (number, message))?;
+
+            match (section.as_str(), key.as_str(), value) {
+                ("package", "name", Value::String(name)) => manifest.name = name,
+                ("package", "version", Value::String(version)) => manifest.version = version,
+                ("package", "entry", Value::String(entry)) => manifest.entry = Some(entry),
+                ("package", "roots", Value::List(roots)) => manifest.roots = roots,
+                ("imports", _, Value::String(target)) => {
+                    if manifest.imports.iter().any(|(name, _)| *name == key) {
+                        return Err((number, format!("`{}` is imported twice", key)));
+                    }
+                    manifest.imports.push((key, target));
+                }
+                ("lints", _, Value::String(level)) => match Level::parse(&level) {
+                    Some(level) => manifest.lints.push((key, level)),
+                    None => {
+                        return Err((
+                            number,
+                            format!("`{}` must be `allow`, `warn` or `deny`", key),
+                        ))
+                    }
+                },
+                ("dependencies", _, Value::Table(table)) => {
+                    // The name becomes a directory under `.clay/deps` as well
+                    // as the first segment of imports.
+                    if !is_identifier(&key) {
+                        return Err((
+                            number,
+                            format!("dependency `{}` must be named with an identifier", key),
+                        ));
+                    }
+                    if manifest.dependencies.iter().any(|dep| dep.name == key) {
+                        return Err((number, format!("`{}` is a dependency twice", key)));
+                    }
+                    let source = dependency_source(&key, table).map_err(|message| (number, message))?;
+                    manifest.dependencies.push(Dependency { name: key, source });
+                }
+                ("dependencies", _, _) => {
+                    return Err((
+                        number,
+                        format!(
+                            "`{}` must be a table, like `{{ path = \"...\" }}` or `{{ git = \"...\" }}`",
+                            key
+                        ),
+                    ))
+                }
+                ("", _, _) => return Err((number, "keys must be inside a section".to_string())),
+                ("package", "name", _)
+                | ("package", "version", _)
+                | ("package", "entry", _)
+                | ("imports", _, _)
+                | ("lints", _, _) => return Err((number, format!("`{}` must be a string", key))),
+                ("package", "roots", _) => {
+                    return Err((number, "`roots` must be a list of strings".to_string()))
+                }
+                (section, key, _) => {
+                    return Err((number, format!("unknown key `{}` in `[{}]`", key, section)))
+                }
+            }
+        }
+
+        if manifest.name.is_empty() {
+            return Err((1, "the manifest has no `name`".to_string()));
+        }
+        Ok(manifest)
+    }
+
+    // How imports in the project resolve: aliases point at directories or
+    // files relative to the manifest, and roots are searched in order.
+    pub fn import_map(&self) -> ImportMap {
+        ImportMap {
+            aliases: self
+                .imports
+                .iter()
+                .map(|(name, target)| Alias {
+                    name: name.clone(),
+                    target: self.dir.join(target),
+                    declared: format!("`{} = \"{}\"` in {}", name, target, MANIFEST),
+                })
+                .collect(),
+            roots: self.roots.iter().map(|root| self.dir.join(root)).collect(),
+        }
+    }
+}
+
+// Where a dependency's `{ ... }` table says its source is: a `path`
+// relative to the manifest, or a `git` repository, optionally at a `rev`.
+fn dependency_source(name: &str, table: Vec<(String, String)>) -> Result<Source, String> {
+    let mut path = None;
+    let mut git = None;
+    let mut rev = None;
+    for (key, value) in table {
+        let slot = match key.as_str() {
+            "path" => &mut path,
+            "git" => &mut git,
+            "rev" => &mut rev,
+            _ => return Err(format!("unknown key `{}` in dependency `{}`", key, name)),
+        };
+        if slot.replace(value).is_some() {
+            return Err(format!("`{}` is set twice in dependency `{}`", key, name));
+        }
+    }
+    // Git would read them as options.
+    for (key, value) in [("git", &git), ("rev", &rev)] {
+        if value.as_deref().is_some_and(|value| value.starts_with('-')) {
+            return Err(format!(
+                "`{}` of dependency `{}` can't start with `-`",
+                key, name
+            ));
+        }
+    }
+    match (path, git, rev) {
+        (Some(path), None, None) => Ok(Source::Path(path)),
+        (None, Some(url), rev) => Ok(Source::Git { url, rev }),
+        (Some(_), None, Some(_)) => Err(format!(
+            "dependency `{}` has a `rev` but no `git` repository",
+            name
+        )),
+        (Some(_), Some(_), _) => Err(format!(
+            "dependency `{}` has both a `path` and a `git` repository",
+            name
+        )),
+        (None, None, _) => Err(format!(
+            "dependency `{}` needs a `path` or a `git` repository",
+            name
+        )),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_key(key: &str) -> Option<String> {
+    if key.starts_with('"') {
+        let (key, rest) = parse_string(key).ok()?;
+        return if rest.trim().is_empty() {
+            Some(key)
+        } else {
+            None
+        };
+    }
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        Some(key.to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if value.starts_with('"') {
+        let (string, rest) = parse_string(value)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after the value".to_string());
+        }
+        return Ok(Value::String(string));
+    }
+
+    if let Some(rest) = value.strip_prefix('{') {
+        return parse_table(rest.trim_start());
+    }
+
+    let mut rest = value
+        .strip_prefix('[')
+        .ok_or_else(|| "expected a string, a list of strings or a table".to_string())?
+        .trim_start();
+    let mut items = Vec::new();
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            if !after.trim().is_empty() {
+                return Err("unexpected text after the value".to_string());
+            }
+            return Ok(Value::List(items));
+        }
+        let (item, after) = parse_string(rest)?;
+        items.push(item);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with(']') {
+            return Err("expected `,` or `]` in the list".to_string());
+        }
+    }
+}
+
+// Parses the rest of an inline table, `key = "value", ... }`, whose values
+// are all strings.
+fn parse_table(mut rest: &str) -> Result<Value, String> {
+    let mut entries = Vec::new();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            if !after.trim().is_empty() {
+                return Err("unexpected text after the value".to_string());
+            }
+            return Ok(Value::Table(entries));
+        }
+        let (key, after) = rest
+            .split_once('=')
+            .ok_or_else(|| "expected `key = value` in the table".to_string())?;
+        let key = parse_key(key.trim()).ok_or_else(|| "invalid key".to_string())?;
+        let (value, after) = parse_string(after.trim_start())?;
+        entries.push((key, value));
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with('}') {
+            return Err("expected `,` or `}` in the table".to_string());
+        }
+    }
+}
+
+// Parses the string `source` starts with, returning it and what follows.
+fn parse_string(source: &str) -> Result<(String, &str), String> {
+    let mut chars = source
+        .strip_prefix('"')
+        .ok_or_else(|| "expected a string".to_string())?
+        .char_indices();
+    let mut string = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &source[index + 2..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => string.push('\n'),
+                Some((_, 't')) => string.push('\t'),
+                Some((_, c @ '"')) | Some((_, c @ '\\')) => string.push(c),
+                _ => return Err("invalid escape in string".to_string()),
+            },
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::lint::lint::Level;
+    use crate::project::dependencies::{Dependency, Source};
+    use crate::project::manifest::Manifest;
+
+    #[test]
+    fn parses_manifests() {
+        let source = "
+            # a comment
+            [package]
+            name = \"demo\"
+            version = \"0.1.0\" # trailing comment
+            roots = [\"src\", \"lib\"]
+
+            [imports]
+            std = \"vendor/std\"
+            \"@utils\" = \"lib/utils\"
+
+            [lints]
+            shadowed-binding = \"deny\"
+        ";
+        let manifest = Manifest::parse(source, Path::new("/project")).unwrap();
+        assert_eq!(manifest.name, "demo");
+        assert_eq!(manifest.version, "0.1.0");
+        assert_eq!(manifest.roots, vec!["src", "lib"]);
+        assert_eq!(
+            manifest.imports,
+            vec![
+                ("std".to_string(), "vendor/std".to_string()),
+                ("@utils".to_string(), "lib/utils".to_string()),
+            ]
+        );
+        assert_eq!(
+            manifest.lints,
+            vec![("shadowed-binding".to_string(), Level::Deny)]
+        );
+        let map = manifest.import_map();
+        assert_eq!(map.aliases[1].target, Path::new("/project/lib/utils"));
+        assert_eq!(map.roots[0], Path::new("/project/src"));
+
+        let error = |source: &str| Manifest::parse(source, Path::new("")).unwrap_err();
+        assert_eq!(
+            error("name = \"x\""),
+            (1, "keys must be inside a section".to_string())
+        );
+        assert_eq!(
+            error("[package]\nname = \"x\"\nroots = \"src\""),
+            (3, "`roots` must be a list of strings".to_string())
+        );
+        assert_eq!(
+            error("[package]\nname = \"x\"\n[imports]\na = \"b\"\na = \"c\""),
+            (5, "`a` is imported twice".to_string())
+        );
+        assert_eq!(
+            error("[package]\nname = \"x"),
+            (2, "unterminated string".to_string())
+        );
+        assert_eq!(
+            error("[package]\nname = \"x\"\n[lints]\nunused-import = \"error\""),
+            (
+                4,
+                "`unused-import` must be `allow`, `warn` or `deny`".to_string()
+            )
+        );
+        assert_eq!(
+            error("[dev-dependencies]"),
+            (1, "unknown section `[dev-dependencies]`".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_dependencies() {
+        let source = "
+            [package]
+            name = \"app\"
+
+            [dependencies]
+            utils = { path = \"../utils\" }
+            json = { git = \"https://example.com/json.git\", rev = \"v1.2\" }
+            \"http_client\" = {git=\"../http\"}
+        ";
+        let manifest = Manifest::parse(source, Path::new("/project")).unwrap();
+        assert_eq!(
+            manifest.dependencies,
+            vec![
+                Dependency {
+                    name: "utils".to_string(),
+                    source: Source::Path("../utils".to_string()),
+                },
+                Dependency {
+                    name: "json".to_string(),
+                    source: Source::Git {
+                        url: "https://example.com/json.git".to_string(),
+                        rev: Some("v1.2".to_string()),
+                    },
+                },
+                Dependency {
+                    name: "http_client".to_string(),
+                    source: Source::Git {
+                        url: "../http".to_string(),
+                        rev: None,
+                    },
+                },
+            ]
+        );
+
+        let error = |dependency: &str| {
+            let source = format!("[package]\nname = \"x\"\n[dependencies]\n{}", dependency);
+            Manifest::parse(&source, Path::new("")).unwrap_err().1
+        };
+        let errors = [
+            (
+                "a = \"1.0\"",
+                "`a` must be a table, like `{ path = \"...\" }` or `{ git = \"...\" }`",
+            ),
+            (
+                "a = {}",
+                "dependency `a` needs a `path` or a `git` repository",
+            ),
+            (
+                "a = { path = \"a\", git = \"b\" }",
+                "dependency `a` has both a `path` and a `git` repository",
+            ),
+            (
+                "a = { path = \"a\", rev = \"b\" }",
+                "dependency `a` has a `rev` but no `git` repository",
+            ),
+            (
+                "a = { version = \"1\" }",
+                "unknown key `version` in dependency `a`",
+            ),
+            (
+                "a = { path = \"a\" path = \"b\" }",
+                "expected `,` or `}` in the table",
+            ),
+            (
+                "a = { path = \"a\" }\na = { path = \"b\" }",
+                "`a` is a dependency twice",
+            ),
+            (
+                "\"../../../escaped\" = { git = \"/tmp/src\" }",
+                "dependency `../../../escaped` must be named with an identifier",
+            ),
+            (
+                "\"a-b\" = { path = \"a\" }",
+                "dependency `a-b` must be named with an identifier",
+            ),
+            (
+                "a = { git = \"--upload-pack=touch x\" }",
+                "`git` of dependency `a` can't start with `-`",
+            ),
+            (
+                "a = { git = \"a\", rev = \"--orphan\" }",
+                "`rev` of dependency `a` can't start with `-`",
+            ),
+        ];
+        for (dependency, message) in errors {
+            assert_eq!(error(dependency), message, "{}", dependency);
+        }
+    }
+}
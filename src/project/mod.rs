@@ -0,0 +1,2 @@
+pub mod dependencies;
+pub mod manifest;
@@ -0,0 +1,30 @@
+// Randomness for the tests across the crate.
+
+// A small xorshift generator, so failures can be replayed from the seed.
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    pub fn pick<'a>(&mut self, pieces: &[&'a str]) -> &'a str {
+        pieces[self.below(pieces.len())]
+    }
+
+    // `count` of `pieces` one after another.
+    pub fn text(&mut self, pieces: &[&str], count: usize) -> String {
+        (0..count).map(|_| self.pick(pieces)).collect()
+    }
+
+    // An offset in `source` a string could be split at.
+    pub fn boundary(&mut self, source: &str) -> usize {
+        let boundaries: Vec<_> = (0..=source.len())
+            .filter(|&index| source.is_char_boundary(index))
+            .collect();
+        boundaries[self.below(boundaries.len())]
+    }
+}
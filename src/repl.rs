@@ -0,0 +1,366 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use clay::interpreter::interpreter::Interpreter;
+use clay::interpreter::profile::format_duration;
+use clay::interpreter::value::Value;
+use clay::lexer::lexer::Lexer;
+use clay::lexer::token::KEYWORDS;
+use clay::lexer::tree::{token_trees, unbalanced, TokenTree, Unbalanced};
+use clay::parser::parser::parse;
+use clay::pipeline::pipeline::Pipeline;
+use clay::typecheck::typecheck::TypeChecker;
+
+use crate::render_diagnostic;
+use crate::stats::Allocations;
+
+const PROMPT: &str = ">> ";
+const CONTINUATION_PROMPT: &str = ".. ";
+const SOURCE_NAME: &str = "<repl>";
+// What each unclosed bracket indents a continuation line by.
+const INDENT: &str = "    ";
+// Where history is kept between sessions, in the home directory.
+const HISTORY_FILE: &str = ".clay_history";
+
+const COMMANDS: [&str; 6] = [":help", ":type", ":time", ":memory", ":snapshot", ":quit"];
+
+const HELP: &str = "commands:
+    :help             show this message
+    :type <expr>      print the inferred type of an expression
+    :time <expr>      evaluate an expression and print how long it took
+    :memory <expr>    evaluate an expression and print what it allocated
+    :snapshot <path>  write a heap snapshot of the session to a file
+    :quit             exit the repl";
+
+pub fn start(pipeline: Pipeline) -> i32 {
+    let mut editor: Editor<Completions, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("error: could not start the repl: {}", err);
+            return 1;
+        }
+    };
+    let mut interpreter = Interpreter::with_pipeline(pipeline);
+    editor.set_helper(Some(Completions::of(&interpreter)));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is none yet the first time.
+        let _ = editor.load_history(path);
+    }
+
+    let status = session(&mut editor, &mut interpreter);
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!(
+                "warning: could not save history to `{}`: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+    status
+}
+
+fn history_path() -> Option<PathBuf> {
+    let variable = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(variable).map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn session(editor: &mut Editor<Completions, DefaultHistory>, interpreter: &mut Interpreter) -> i32 {
+    // Follows the session's definitions so `:type` can see them.
+    let mut types = TypeChecker::new();
+    let mut buffer = String::new();
+
+    loop {
+        let line = match buffer.is_empty() {
+            true => editor.readline(PROMPT),
+            false => editor.readline_with_initial(CONTINUATION_PROMPT, (&indentation(&buffer), "")),
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => return 0,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return 1;
+            }
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(line.as_str());
+            let command = meta_command(line.trim(), interpreter, &mut types);
+            editor.set_helper(Some(Completions::of(interpreter)));
+            match command {
+                Command::Continue => continue,
+                Command::Quit => return 0,
+            }
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if is_incomplete(&buffer) {
+            continue;
+        }
+
+        let _ = editor.add_history_entry(buffer.trim_end());
+        let entry = std::mem::take(&mut buffer);
+        print_value(evaluate(&entry, interpreter, &mut types));
+        if let Some(code) = interpreter.exit_code() {
+            return code;
+        }
+        editor.set_helper(Some(Completions::of(interpreter)));
+    }
+}
+
+// Completes the word before the cursor with a keyword, a builtin or a name
+// the session has defined, and a `:` command with its name.
+struct Completions {
+    names: Vec<String>,
+}
+
+impl Completions {
+    fn of(interpreter: &Interpreter) -> Completions {
+        let globals = interpreter.global_names();
+        let mut names: Vec<String> = KEYWORDS
+            .iter()
+            .map(|keyword| keyword.to_string())
+            .chain(globals.iter().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        Completions { names }
+    }
+
+    // Where the word being completed starts, and what may complete it.
+    fn candidates(&self, line: &str, position: usize) -> (usize, Vec<String>) {
+        let before = &line[..position];
+        if before.starts_with(':') && !before.contains(char::is_whitespace) {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(before));
+            return (0, commands.map(|command| command.to_string()).collect());
+        }
+        let start = before
+            .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+            .len();
+        let word = &before[start..];
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return (position, Vec::new());
+        }
+        let names = self.names.iter().filter(|name| name.starts_with(word));
+        (start, names.cloned().collect())
+    }
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        position: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, position))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+fn print_value(value: Option<Value>) {
+    match value {
+        Some(Value::Unit) | None => {}
+        Some(value) => println!("{}", value),
+    }
+}
+
+enum Command {
+    Continue,
+    Quit,
+}
+
+fn meta_command(line: &str, interpreter: &mut Interpreter, types: &mut TypeChecker) -> Command {
+    let (command, argument) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        ":help" | ":h" => println!("{}", HELP),
+        ":quit" | ":q" => return Command::Quit,
+        ":type" | ":t" if argument.is_empty() => eprintln!("usage: :type <expr>"),
+        ":type" | ":t" => {
+            let inferred = parse(argument).map_err(|diagnostic| vec![diagnostic]);
+            match inferred.and_then(|program| types.infer(&program)) {
+                Ok(ty) => println!("{}", ty),
+                Err(diagnostics) => {
+                    for diagnostic in &diagnostics {
+                        eprint!("{}", render_diagnostic(diagnostic, SOURCE_NAME, argument));
+                    }
+                }
+            }
+        }
+        ":snapshot" if argument.is_empty() => eprintln!("usage: :snapshot <path>"),
+        ":snapshot" => match interpreter.heap_snapshot().save(Path::new(argument)) {
+            Ok(()) => println!("wrote heap snapshot to `{}`", argument),
+            Err(message) => eprintln!("error: {}", message),
+        },
+        ":time" | ":memory" if argument.is_empty() => eprintln!("usage: {} <expr>", command),
+        ":time" => {
+            let start = Instant::now();
+            let value = evaluate(argument, interpreter, types);
+            let elapsed = start.elapsed();
+            print_value(value);
+            println!("time: {}", format_duration(elapsed));
+        }
+        ":memory" => {
+            let start = Allocations::now();
+            let value = evaluate(argument, interpreter, types);
+            let used = Allocations::now().since(start);
+            print_value(value);
+            println!(
+                "memory: {} allocation{}, {} bytes",
+                used.count,
+                if used.count == 1 { "" } else { "s" },
+                used.bytes
+            );
+        }
+        _ => eprintln!("unknown command `{}`, try :help", command),
+    }
+
+    Command::Continue
+}
+
+fn evaluate(source: &str, interpreter: &mut Interpreter, types: &mut TypeChecker) -> Option<Value> {
+    let mut diagnostics = Vec::new();
+    let program = interpreter.pipeline_mut().process(source, &mut diagnostics);
+    for diagnostic in &diagnostics {
+        eprint!("{}", render_diagnostic(diagnostic, SOURCE_NAME, source));
+    }
+    let program = program?;
+    // Type errors were reported above if the pipeline checks types, and
+    // don't stop the entry from running otherwise.
+    types.check(&program);
+
+    match interpreter.run(&program) {
+        Ok(value) => Some(value),
+        Err(diagnostic) => {
+            eprint!("{}", render_diagnostic(&diagnostic, SOURCE_NAME, source));
+            None
+        }
+    }
+}
+
+// Input is incomplete while it has unclosed brackets or an unterminated
+// string; anything else, like a bracket closing nothing, is handed to the
+// parser, which reports real errors.
+fn is_incomplete(source: &str) -> bool {
+    let tokens = match Lexer::new(source).collect::<Result<Vec<_>, _>>() {
+        Ok(tokens) => tokens,
+        Err(diagnostic) => return diagnostic.message == "unterminated string literal",
+    };
+    match token_trees(tokens) {
+        Ok(trees) => matches!(unbalanced(&trees), Some(Unbalanced::Unclosed(_))),
+        Err(_) => false,
+    }
+}
+
+// The indentation a continuation line starts with: one level for each
+// bracket still open at the end of the entry so far.
+fn indentation(source: &str) -> String {
+    let tokens = Lexer::new(source).filter_map(Result::ok).collect();
+    let mut trees = token_trees(tokens).unwrap_or_default();
+    let mut depth = 0;
+    while let Some(TokenTree::Group(group)) = trees.pop() {
+        if group.close.is_some() {
+            break;
+        }
+        depth += 1;
+        trees = group.trees;
+    }
+    INDENT.repeat(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use clay::interpreter::interpreter::Interpreter;
+    use clay::parser::parser::parse;
+
+    use crate::repl::{indentation, is_incomplete, Completions, COMMANDS, HELP};
+
+    #[test]
+    fn detects_incomplete_input() {
+        assert!(is_incomplete("(1 +\n"));
+        assert!(is_incomplete("\"unterminated\n"));
+        assert!(!is_incomplete("(1 + 2)\n"));
+        assert!(!is_incomplete("1 + 2)\n"));
+        assert!(!is_incomplete("(1 + 2]\n"));
+    }
+
+    #[test]
+    fn lists_every_command_in_one_column() {
+        let rows: Vec<&str> = HELP.lines().skip(1).collect();
+        assert_eq!(rows.len(), COMMANDS.len());
+        // Where the description starts, after the spaces padding the command.
+        let column = |row: &str| row.rfind("  ").map(|spaces| spaces + 2);
+        for (row, command) in rows.iter().zip(COMMANDS.iter()) {
+            assert!(row.trim_start().starts_with(command), "{}", row);
+            assert_eq!(column(row), column(rows[0]), "{}", row);
+        }
+    }
+
+    #[test]
+    fn indents_by_the_brackets_left_open() {
+        assert_eq!(indentation("let x = 1;\n"), "");
+        assert_eq!(indentation("fn f() {\n"), "    ");
+        assert_eq!(indentation("fn f() {\n    g(1, [\n"), "            ");
+        assert_eq!(indentation("fn f() {\n    g(1, [2])\n"), "    ");
+    }
+
+    #[test]
+    fn completes_keywords_builtins_and_session_names() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(&parse("let counter = 1; fn count() { counter }").unwrap())
+            .unwrap();
+        let completions = Completions::of(&interpreter);
+
+        assert_eq!(
+            completions.candidates("print(coun", 10),
+            (6, vec!["count".to_string(), "counter".to_string()])
+        );
+        assert_eq!(
+            completions.candidates("whi", 3),
+            (0, vec!["while".to_string()])
+        );
+        assert!(completions
+            .candidates("pri", 3)
+            .1
+            .contains(&"print".to_string()));
+        assert_eq!(completions.candidates("1 + 2", 5), (5, Vec::new()));
+        assert_eq!(
+            completions.candidates(":t", 2),
+            (0, vec![":type".to_string(), ":time".to_string()])
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::lexer::Lexer;
+use crate::lexer::token::{Position, Span, TokenType};
+use crate::parser::ast::{Expr, ExprKind, Function, Pattern, PatternKind, Program, Stmt, StmtKind};
+use crate::parser::visit::{walk_expr, walk_function, walk_pattern, walk_stmt, Visitor};
+
+// Replaces the source text covered by `span`; an empty span inserts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub span: Span,
+    pub text: String,
+}
+
+// Turns edits to a parsed program into edits to its source text. Every edit
+// is anchored to the span of the node it changes, so text outside those spans,
+// including whitespace and layout, comes through untouched.
+pub struct Rewriter<'a> {
+    source: &'a str,
+    edits: Vec<TextEdit>,
+}
+
+impl<'a> Rewriter<'a> {
+    pub fn new(source: &'a str) -> Rewriter<'a> {
+        Rewriter {
+            source,
+            edits: Vec::new(),
+        }
+    }
+
+    // Replaces a node, given its span, with new source text.
+    pub fn replace(&mut self, span: Span, text: impl Into<String>) {
+        self.edits.push(TextEdit {
+            span,
+            text: text.into(),
+        });
+    }
+
+    // Inserts a statement on its own line before `stmt`, indented to match.
+    pub fn insert_before(&mut self, stmt: &Stmt, text: &str) {
+        let start = stmt.span.start;
+        let line_start = start.char - start.column;
+        let indent: String = self.source[line_start..start.char]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        self.insert(start, format!("{}\n{}", text, indent));
+    }
+
+    // Inserts a statement on its own line after `stmt` and its `;`.
+    pub fn insert_after(&mut self, stmt: &Stmt, text: &str) {
+        let mut end = stmt.span.end;
+        let rest = &self.source[end.char..];
+        if let Some(offset) = rest.find(|c: char| !c.is_whitespace()) {
+            if rest[offset..].starts_with(';') && !rest[..offset].contains('\n') {
+                end.column += rest[..offset].chars().count() + 1;
+                end.char += offset + 1;
+            }
+        }
+        let start = stmt.span.start;
+        let indent = " ".repeat(start.column);
+        self.insert(end, format!("\n{}{}", indent, text));
+    }
+
+    // Renames every binding and use of `from`. Names are matched by text, not
+    // by scope, so a shadowing binding of the same name is renamed too.
+    pub fn rename(&mut self, program: &Program, from: &str, to: &str) {
+        let mut renamer = Renamer {
+            source: self.source,
+            from,
+            spans: Vec::new(),
+        };
+        for stmt in &program.statements {
+            renamer.visit_stmt(stmt);
+        }
+        for span in renamer.spans {
+            self.replace(span, to);
+        }
+    }
+
+    // Returns the edits in source order, failing if any two of them overlap.
+    pub fn edits(&self) -> Result<Vec<TextEdit>, Diagnostic> {
+        let mut edits = self.edits.clone();
+        // Stable, so insertions at one point keep the order they were made in.
+        edits.sort_by_key(|edit| (edit.span.start.char, edit.span.end.char));
+        for pair in edits.windows(2) {
+            if pair[1].span.start.char < pair[0].span.end.char {
+                return Err(Diagnostic::error("conflicting edits", pair[1].span));
+            }
+        }
+        Ok(edits)
+    }
+
+    pub fn apply(&self) -> Result<String, Diagnostic> {
+        let mut output = String::with_capacity(self.source.len());
+        let mut copied = 0;
+        for edit in self.edits()? {
+            output.push_str(&self.source[copied..edit.span.start.char]);
+            output.push_str(&edit.text);
+            copied = edit.span.end.char;
+        }
+        output.push_str(&self.source[copied..]);
+        Ok(output)
+    }
+
+    fn insert(&mut self, at: Position, text: String) {
+        self.replace(Span::new(at, at), text);
+    }
+}
+
+struct Renamer<'a> {
+    source: &'a str,
+    from: &'a str,
+    spans: Vec<Span>,
+}
+
+impl<'a> Renamer<'a> {
+    // Finds the first identifier `from` at or after `start`. Declarations
+    // don't record where their name is, so it is found by lexing.
+    fn find_name(&mut self, start: Position) {
+        let found = Lexer::starting_at(self.source, start)
+            .map_while(Result::ok)
+            .find(|token| matches!(token.kind, TokenType::Ident(name) if name == self.from));
+        if let Some(token) = found {
+            self.spans.push(token.span);
+        }
+    }
+}
+
+impl<'a> Visitor for Renamer<'a> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, .. } if name == self.from => self.find_name(stmt.span.start),
+            StmtKind::Function(function) if function.name.as_deref() == Some(self.from) => {
+                self.find_name(stmt.span.start)
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name) if name == self.from => self.spans.push(expr.span),
+            ExprKind::Path(segments) if segments[0] == self.from => {
+                let mut end = expr.span.start;
+                end.column += self.from.chars().count();
+                end.char += self.from.len();
+                self.spans.push(Span::new(expr.span.start, end));
+            }
+            _ => {}
+        }
+        walk_expr(self, expr)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        for param in &function.params {
+            if param.name == self.from {
+                self.spans.push(param.span);
+            }
+        }
+        walk_function(self, function)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Binding(name) = &pattern.kind {
+            if name == self.from {
+                self.spans.push(pattern.span);
+            }
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ast::{ExprKind, StmtKind};
+    use crate::parser::parser::parse;
+    use crate::rewrite::rewrite::Rewriter;
+
+    #[test]
+    fn renames_bindings_and_uses() {
+        let source = "let  total = 0;\nfn add(total, n) {\n    total + n\n}\nfor (total, _) in [(1, 2)] { total }\nmatch total { total => add(total,1) }";
+        let program = parse(source).unwrap();
+        let mut rewriter = Rewriter::new(source);
+        rewriter.rename(&program, "total", "sum");
+        assert_eq!(
+            rewriter.apply().unwrap(),
+            "let  sum = 0;\nfn add(sum, n) {\n    sum + n\n}\nfor (sum, _) in [(1, 2)] { sum }\nmatch sum { sum => add(sum,1) }"
+        );
+    }
+
+    #[test]
+    fn inserts_and_replaces_around_untouched_text() {
+        let source = "fn main() {\n    let x =   1 +  2;\n    x\n}";
+        let program = parse(source).unwrap();
+        let body = match &program.statements[0].kind {
+            StmtKind::Function(function) => &function.body,
+            other => panic!("unexpected statement {:?}", other),
+        };
+        let value = match &body.statements[0].kind {
+            StmtKind::Let { value, .. } => value,
+            other => panic!("unexpected statement {:?}", other),
+        };
+        let right = match &value.kind {
+            ExprKind::Binary { right, .. } => right,
+            other => panic!("unexpected expression {:?}", other),
+        };
+
+        let mut rewriter = Rewriter::new(source);
+        rewriter.replace(right.span, "40");
+        rewriter.insert_before(&body.statements[0], "let y = 0;");
+        rewriter.insert_after(&body.statements[0], "let z = x;");
+        assert_eq!(
+            rewriter.apply().unwrap(),
+            "fn main() {\n    let y = 0;\n    let x =   1 +  40;\n    let z = x;\n    x\n}"
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_edits() {
+        let source = "1 + 2";
+        let program = parse(source).unwrap();
+        let span = program.statements[0].span;
+        let mut rewriter = Rewriter::new(source);
+        rewriter.replace(span, "3");
+        rewriter.replace(span, "4");
+        assert_eq!(rewriter.apply().unwrap_err().message, "conflicting edits");
+    }
+}
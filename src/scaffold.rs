@@ -0,0 +1,280 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    Script,
+    Library,
+    Playground,
+}
+
+impl Template {
+    pub fn parse(name: &str) -> Option<Template> {
+        match name {
+            "script" => Some(Template::Script),
+            "library" | "lib" => Some(Template::Library),
+            "playground" => Some(Template::Playground),
+            _ => None,
+        }
+    }
+}
+
+const TEMPLATES: &str = "`script`, `library` or `playground`";
+
+// Creates a project in a new directory called `name`.
+pub fn new(name: &str, template: Option<Template>) -> i32 {
+    let dir = Path::new(name);
+    if dir.exists() {
+        eprintln!("error: `{}` already exists", name);
+        return 1;
+    }
+    let project = match dir.file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => {
+            eprintln!("error: `{}` is not a valid project directory", name);
+            return 1;
+        }
+    };
+    let code = scaffold(dir, &project, template);
+    if code == 0 {
+        println!("\nnext: `cd {}`, then {}", name, NEXT_STEPS);
+    }
+    code
+}
+
+// Creates a project in the current directory, named after it.
+pub fn init(template: Option<Template>) -> i32 {
+    let dir = match env::current_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            eprintln!("error: could not read the current directory: {}", err);
+            return 1;
+        }
+    };
+    let project = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let code = scaffold(&dir, &project, template);
+    if code == 0 {
+        println!("\nnext: {}", NEXT_STEPS);
+    }
+    code
+}
+
+// What to do with a project once it is created. Both commands find the
+// project's files through its manifest.
+const NEXT_STEPS: &str = "`clay run` to run the project's entry or `clay test` to run its tests";
+
+fn scaffold(dir: &Path, project: &str, template: Option<Template>) -> i32 {
+    let template = match template {
+        Some(template) => template,
+        None => match ask_template() {
+            Ok(template) => template,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                return 1;
+            }
+        },
+    };
+    match create(dir, project, template) {
+        Ok(files) => {
+            for file in files {
+                println!("created {}", file.display());
+            }
+            0
+        }
+        Err(message) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    }
+}
+
+// Asks which template to use when stdin is a terminal, and picks the script
+// template otherwise so `clay new` keeps working in scripts.
+fn ask_template() -> Result<Template, String> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(Template::Script);
+    }
+    loop {
+        print!("template ({}) [script]: ", TEMPLATES);
+        io::stdout().flush().map_err(|err| err.to_string())?;
+        let mut answer = String::new();
+        match stdin.lock().read_line(&mut answer) {
+            Ok(0) => return Ok(Template::Script),
+            Ok(_) => {}
+            Err(err) => return Err(format!("could not read the template: {}", err)),
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(Template::Script);
+        }
+        match Template::parse(answer) {
+            Some(template) => return Ok(template),
+            None => eprintln!("unknown template `{}`, expected {}", answer, TEMPLATES),
+        }
+    }
+}
+
+// Writes the files of `template` into `dir` and returns their paths. Nothing
+// is written if a file the template needs already exists.
+pub fn create(dir: &Path, project: &str, template: Template) -> Result<Vec<PathBuf>, String> {
+    if !valid_name(project) {
+        return Err(format!(
+            "`{}` is not a valid project name, use letters, digits, `-` and `_`",
+            project
+        ));
+    }
+    let files = files(project, template)?;
+    if let Some((path, _)) = files.iter().find(|(path, _)| dir.join(path).exists()) {
+        return Err(format!("`{}` already exists", dir.join(path).display()));
+    }
+
+    let mut created = Vec::new();
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("could not create `{}`: {}", parent.display(), err))?;
+        }
+        fs::write(&path, contents)
+            .map_err(|err| format!("could not write `{}`: {}", path.display(), err))?;
+        created.push(path);
+    }
+    Ok(created)
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn files(project: &str, template: Template) -> Result<Vec<(&'static str, String)>, String> {
+    let (entry, mut files) = match template {
+        Template::Script => (
+            "src/main.clay",
+            vec![
+                ("src/main.clay", SCRIPT_MAIN.to_string()),
+                ("tests/main_test.clay", SCRIPT_TEST.to_string()),
+            ],
+        ),
+        Template::Library => (
+            "src/lib.clay",
+            vec![
+                ("src/lib.clay", LIBRARY.to_string()),
+                ("examples/main.clay", LIBRARY_EXAMPLE.to_string()),
+                ("tests/lib_test.clay", LIBRARY_TEST.to_string()),
+            ],
+        ),
+        // A playground page needs clay to run in the browser, which it can't
+        // do yet.
+        Template::Playground => {
+            return Err(
+                "the `playground` template needs a web build of clay, which does not exist yet"
+                    .to_string(),
+            )
+        }
+    };
+    files.push(("clay.toml", manifest(project, entry)));
+    files.push((".gitignore", GITIGNORE.to_string()));
+    Ok(files)
+}
+
+fn manifest(project: &str, entry: &str) -> String {
+    format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nentry = \"{}\"\n",
+        project, entry
+    )
+}
+
+const GITIGNORE: &str = "# heap snapshots written by `clay run --heap-snapshot`
+*.heap
+# git dependencies and the parse cache
+.clay/
+";
+
+const SCRIPT_MAIN: &str = "fn greet(name) {
+    \"hello, \" + name
+}
+
+greet(\"world\")
+";
+
+const SCRIPT_TEST: &str = "import \"../src/main.clay\";
+
+test \"greets by name\" {
+    assert_eq(main::greet(\"clay\"), \"hello, clay\")
+}
+";
+
+const LIBRARY: &str = "fn greet(name) {
+    \"hello, \" + name
+}
+";
+
+const LIBRARY_EXAMPLE: &str = "import \"../src/lib.clay\";
+
+lib::greet(\"world\")
+";
+
+const LIBRARY_TEST: &str = "import \"../src/lib.clay\";
+
+test \"greets by name\" {
+    assert_eq(lib::greet(\"clay\"), \"hello, clay\")
+}
+";
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use clay::interpreter::interpreter::Interpreter;
+    use clay::parser::parser::parse;
+
+    use crate::scaffold::{create, Template};
+
+    fn run(path: &Path) -> String {
+        let program = parse(&fs::read_to_string(path).unwrap()).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(path);
+        interpreter.run(&program).unwrap().to_string()
+    }
+
+    // How `clay test` exits on the test blocks under `path`.
+    fn test(path: &Path) -> i32 {
+        crate::run(&["test".to_string(), path.display().to_string()])
+    }
+
+    #[test]
+    fn scaffolds_runnable_projects() {
+        let dir = std::env::temp_dir().join(format!("clay-new-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let script = dir.join("script");
+        create(&script, "script", Template::Script).unwrap();
+        assert_eq!(run(&script.join("src/main.clay")), "hello, world");
+        assert_eq!(test(&script.join("tests")), 0);
+        let manifest = fs::read_to_string(script.join("clay.toml")).unwrap();
+        assert!(manifest.contains("name = \"script\"\n"));
+        assert!(manifest.contains("entry = \"src/main.clay\"\n"));
+
+        let library = dir.join("library");
+        create(&library, "library", Template::Library).unwrap();
+        assert_eq!(run(&library.join("examples/main.clay")), "hello, world");
+        assert_eq!(test(&library.join("tests")), 0);
+
+        let err = create(&library, "library", Template::Library).unwrap_err();
+        assert!(err.ends_with("lib.clay` already exists"), "{}", err);
+        let err = create(&dir.join("bad"), "1bad", Template::Script).unwrap_err();
+        assert!(err.starts_with("`1bad` is not a valid project name"));
+        assert!(create(&dir.join("web"), "web", Template::Playground).is_err());
+    }
+}
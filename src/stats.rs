@@ -0,0 +1,77 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Wraps the system allocator to count allocations, so the repl can report
+// how much memory evaluating a snippet allocated.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn record(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed);
+}
+
+// Running totals since the process started; subtract two snapshots to
+// measure a region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Allocations {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl Allocations {
+    pub fn now() -> Allocations {
+        Allocations {
+            count: ALLOCATIONS.load(Ordering::Relaxed),
+            bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn since(self, start: Allocations) -> Allocations {
+        Allocations {
+            count: self.count - start.count,
+            bytes: self.bytes - start.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::Allocations;
+
+    #[test]
+    fn counts_allocations() {
+        let start = Allocations::now();
+        let buffer: Vec<u8> = Vec::with_capacity(4096);
+        let used = Allocations::now().since(start);
+        drop(buffer);
+        assert!(used.count >= 1);
+        assert!(used.bytes >= 4096);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clay::diagnostic::source_map::SourceMap;
+use clay::interpreter::interpreter::Interpreter;
+use clay::interpreter::module::EXTENSION;
+use clay::parser::ast::StmtKind;
+
+use crate::{import_map, pipeline_for, Options, Reporter, EXIT_FAILURE};
+
+// Runs the test blocks in `path`, or in every clay file under it when it is
+// a directory, and prints whether each passed. Each test runs the rest of
+// its file first, in an interpreter of its own. With `--filter`, only the
+// tests whose names contain the filter run.
+pub fn run(path: &Path, options: &Options) -> i32 {
+    let files = match files(path) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let (mut passed, mut failed, mut filtered) = (0, 0, 0);
+    let mut errors = false;
+    for file in files {
+        let display = file
+            .strip_prefix(".")
+            .unwrap_or(&file)
+            .display()
+            .to_string();
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("error: could not read `{}`: {}", display, err);
+                errors = true;
+                continue;
+            }
+        };
+        let mut reporter = Reporter {
+            path: &display,
+            format: options.error_format,
+            sources: SourceMap::new(),
+            failed: false,
+        };
+        reporter.sources.insert(display.as_str(), source.as_str());
+        let imports = match import_map(&file) {
+            Ok(imports) => imports,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                errors = true;
+                continue;
+            }
+        };
+
+        let mut pipeline = pipeline_for("test", options).expect("built before finding tests");
+        let mut diagnostics = Vec::new();
+        let program = pipeline.process(&source, &mut diagnostics);
+        for diagnostic in &diagnostics {
+            reporter.report(diagnostic);
+        }
+        let program = match program {
+            Some(program) => program,
+            None => {
+                errors = true;
+                continue;
+            }
+        };
+
+        for stmt in &program.statements {
+            let (name, body) = match &stmt.kind {
+                StmtKind::Test { name, body } => (name, body),
+                _ => continue,
+            };
+            if options
+                .filter
+                .as_ref()
+                .is_some_and(|filter| !name.contains(filter.as_str()))
+            {
+                filtered += 1;
+                continue;
+            }
+
+            let pipeline = pipeline_for("test", options).expect("built before finding tests");
+            let mut interpreter = Interpreter::with_pipeline(pipeline);
+            interpreter.set_file(&file);
+            interpreter.set_import_map(imports.clone());
+            interpreter.set_sources(reporter.sources.clone());
+            match interpreter.run_test(&program, body) {
+                Ok(()) => {
+                    println!("test {}: {} ... ok", display, name);
+                    passed += 1;
+                }
+                Err(diagnostic) => {
+                    println!("test {}: {} ... FAILED", display, name);
+                    reporter.report(&diagnostic);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let result = if failed > 0 || errors { "FAILED" } else { "ok" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} filtered out",
+        result, passed, failed, filtered
+    );
+    if failed > 0 || errors {
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
+
+// `path` if it is a file, or the clay files under it, sorted so tests run in
+// the same order everywhere. Hidden directories are skipped.
+fn files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return match path.exists() {
+            true => Ok(vec![path.to_path_buf()]),
+            false => Err(format!("`{}` does not exist", path.display())),
+        };
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(|err| format!("could not read `{}`: {}", dir.display(), err))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if path.is_dir() && !hidden {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,1809 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::{
+    BinaryOp, Block, Expr, ExprKind, Function, ImplDecl, MethodSig, Pattern, PatternKind, Program,
+    Stmt, StmtKind, TypeExpr, TypeExprKind, UnaryOp,
+};
+use crate::pipeline::pass::Pass;
+use crate::typecheck::types::{normalize, Type};
+
+pub struct TypeCheckPass;
+
+impl Pass for TypeCheckPass {
+    fn name(&self) -> &str {
+        "typecheck"
+    }
+
+    fn run(&mut self, program: &mut Program, diagnostics: &mut Vec<Diagnostic>) {
+        diagnostics.extend(check(program));
+    }
+}
+
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    TypeChecker::new().check(program)
+}
+
+// Hindley-Milner type inference. Every unannotated parameter, local and
+// return type starts out as a type variable that unification pins down, and
+// functions are generalized where they are bound, so `fn id(x) { x }` has the
+// principal type `Fn('a) -> 'a` and can be called at any type.
+//
+// Clay's operators work on several types, which plain HM can't express. An
+// operator applied to a type variable checks its operand once inference is
+// done, and a function generalized over that variable repeats the check
+// wherever it is used. Values the checker can't see into, like imported
+// names, have the `Unknown` type, which unifies with anything.
+//
+// The checker keeps the bindings of each program it checks, so a REPL can
+// feed it one entry at a time.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<Symbol, Scheme>>,
+    // What each type variable has been unified with, indexed by variable.
+    substitution: Vec<Option<Type>>,
+    // The return types of the functions being checked, innermost last.
+    returns: Vec<Type>,
+    deferred: Vec<Deferred>,
+    // The methods each trait declared so far asks its impls for.
+    traits: HashMap<Symbol, Vec<MethodSig>>,
+    // The structs and enums declared so far.
+    types: HashMap<Symbol, TypeDef>,
+    // What each type parameter in scope stands for, innermost last.
+    type_params: Vec<HashMap<Symbol, Type>>,
+    // The methods impls give each struct, keyed by struct and method name.
+    // Their first parameter is the struct.
+    methods: HashMap<(Symbol, Symbol), Scheme>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// A struct or enum, with the types of its fields or variants written in
+// terms of its type parameters.
+#[derive(Clone)]
+struct TypeDef {
+    params: Vec<Symbol>,
+    kind: TypeDefKind,
+}
+
+#[derive(Clone)]
+enum TypeDefKind {
+    // Unannotated fields are `Unknown`.
+    Struct(Vec<(Symbol, Type)>),
+    Enum(Vec<(Symbol, Vec<Type>)>),
+}
+
+// A type that is polymorphic over `variables`, with the operators that must
+// work on some of them.
+#[derive(Clone)]
+struct Scheme {
+    variables: Vec<u32>,
+    constraints: Vec<(Operator, u32)>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn monomorphic(ty: Type) -> Scheme {
+        Scheme {
+            variables: Vec::new(),
+            constraints: Vec::new(),
+            ty,
+        }
+    }
+}
+
+// An operator applied to an operand whose type was still a variable.
+struct Deferred {
+    op: Operator,
+    ty: Type,
+    span: Span,
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+}
+
+enum Mismatch {
+    Types,
+    // Unifying would have bound the variable to a type containing itself.
+    Infinite(u32, Type),
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            scopes: vec![HashMap::new()],
+            substitution: Vec::new(),
+            returns: Vec::new(),
+            deferred: Vec::new(),
+            traits: HashMap::new(),
+            types: HashMap::new(),
+            type_params: Vec::new(),
+            methods: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    // Checks a program, keeping its top-level bindings for later programs.
+    // Tests run after the rest of the file, so they are checked last and see
+    // everything it defines.
+    pub fn check(&mut self, program: &Program) -> Vec<Diagnostic> {
+        let (tests, statements): (Vec<&Stmt>, Vec<&Stmt>) = program
+            .statements
+            .iter()
+            .partition(|stmt| matches!(stmt.kind, StmtKind::Test { .. }));
+        self.declare_traits(&program.statements);
+        for stmt in statements.into_iter().chain(tests) {
+            self.check_stmt(stmt);
+        }
+        self.finish()
+    }
+
+    // Infers the type of the value a program evaluates to, without keeping
+    // its bindings.
+    pub fn infer(&mut self, program: &Program) -> Result<Type, Vec<Diagnostic>> {
+        self.scopes.push(HashMap::new());
+        self.declare_traits(&program.statements);
+        let mut ty = Type::Unit;
+        for stmt in &program.statements {
+            ty = self.check_stmt(stmt);
+        }
+        self.scopes.pop();
+
+        let diagnostics = self.finish();
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        let ty = self.apply(&ty);
+        Ok(normalize(&[ty]).remove(0))
+    }
+
+    // The type of a top-level binding from the programs checked so far, with
+    // what inference learned about it applied.
+    pub fn binding(&self, name: &str) -> Option<Type> {
+        let scheme = self.scopes[0].get(&Symbol::intern(name))?;
+        Some(normalize(&[self.apply(&scheme.ty)]).remove(0))
+    }
+
+    fn finish(&mut self) -> Vec<Diagnostic> {
+        for deferred in std::mem::take(&mut self.deferred) {
+            let ty = self.apply(&deferred.ty);
+            if !matches!(ty, Type::Var(_) | Type::Unknown) && !supports(deferred.op, &ty) {
+                self.unsupported(deferred.op, &ty, &ty, deferred.span);
+            }
+        }
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    // Returns the statement's value type, which is unit for anything but an
+    // expression.
+    fn check_stmt(&mut self, stmt: &Stmt) -> Type {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => return self.check_expr(expr),
+            StmtKind::Function(function) => {
+                let ty = self.check_function(function);
+                let scheme = self.generalize(ty);
+                self.define(function.name.unwrap_or_default(), scheme);
+            }
+            StmtKind::Import(import) => {
+                for name in import.bindings() {
+                    self.define(name, Scheme::monomorphic(Type::Unknown));
+                }
+            }
+            // The names of structs and enums are values too, which aren't
+            // typed: only the values they make are.
+            StmtKind::Struct(decl) => {
+                self.declare_type(decl.name, &decl.type_params, |checker| {
+                    let fields = decl
+                        .fields
+                        .iter()
+                        .map(|field| match &field.ty {
+                            Some(ty) => (field.name, checker.resolve(ty)),
+                            None => (field.name, Type::Unknown),
+                        })
+                        .collect();
+                    TypeDefKind::Struct(fields)
+                });
+                self.define(decl.name, Scheme::monomorphic(Type::Unknown));
+            }
+            StmtKind::Enum(decl) => {
+                self.declare_type(decl.name, &decl.type_params, |checker| {
+                    let variants = decl
+                        .variants
+                        .iter()
+                        .map(|variant| {
+                            let fields = variant.fields.iter().map(|ty| checker.resolve(ty));
+                            (variant.name, fields.collect())
+                        })
+                        .collect();
+                    TypeDefKind::Enum(variants)
+                });
+                self.define(decl.name, Scheme::monomorphic(Type::Unknown));
+            }
+            StmtKind::Let {
+                name, ty, value, ..
+            } => {
+                let found = self.check_expr(value);
+                let ty = match ty {
+                    Some(annotation) => {
+                        let expected = self.resolve(annotation);
+                        self.expect(&expected, &found, value.span);
+                        expected
+                    }
+                    None => found,
+                };
+                // Only function literals are generalized: a value like `[]`
+                // is shared, so all its uses must agree on a type.
+                let scheme = match value.kind {
+                    ExprKind::Function(_) => self.generalize(ty),
+                    _ => Scheme::monomorphic(ty),
+                };
+                self.define(*name, scheme);
+            }
+            StmtKind::Destructure {
+                pattern, ty, value, ..
+            } => {
+                let found = self.check_expr(value);
+                let ty = match ty {
+                    Some(annotation) => {
+                        let expected = self.resolve(annotation);
+                        self.expect(&expected, &found, value.span);
+                        expected
+                    }
+                    None => found,
+                };
+                self.bind_pattern(pattern, &ty);
+            }
+            StmtKind::Test { body, .. } => {
+                self.check_block(body);
+            }
+            // Declared by `declare_traits` already.
+            StmtKind::Trait(_) => {}
+            StmtKind::Impl(decl) => self.check_impl(decl, stmt.span),
+        }
+        Type::Unit
+    }
+
+    // Knows the traits among `statements` before any is checked, since an
+    // impl may come before the trait it implements, as it can when run.
+    fn declare_traits(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            if let StmtKind::Trait(decl) = &stmt.kind {
+                self.traits.insert(decl.name, decl.methods.clone());
+            }
+        }
+    }
+
+    // An impl must give each method of its trait, and only those, at the
+    // types the trait declares them with.
+    fn check_impl(&mut self, decl: &ImplDecl, span: Span) {
+        let signatures = match self.traits.get(&decl.trait_name) {
+            Some(signatures) => Some(signatures.clone()),
+            None => {
+                self.error(format!("unknown trait `{}`", decl.trait_name), span);
+                None
+            }
+        };
+
+        for method in &decl.methods {
+            let name = method.name.unwrap_or_default();
+            let ty = self.check_method_body(decl.target, method);
+            let scheme = self.generalize(ty.clone());
+            self.methods.insert((decl.target, name), scheme);
+            let signatures = match &signatures {
+                Some(signatures) => signatures,
+                None => continue,
+            };
+            match signatures.iter().find(|signature| signature.name == name) {
+                Some(signature) => {
+                    let expected = self.signature(signature);
+                    self.expect(&expected, &ty, method.span);
+                }
+                None => self.error(
+                    format!("`{}` is not a method of `{}`", name, decl.trait_name),
+                    method.span,
+                ),
+            }
+        }
+
+        for signature in signatures.iter().flatten() {
+            if !decl
+                .methods
+                .iter()
+                .any(|method| method.name == Some(signature.name))
+            {
+                self.error(
+                    format!(
+                        "`{}` does not implement `{}` from `{}`",
+                        decl.target, signature.name, decl.trait_name
+                    ),
+                    span,
+                );
+            }
+        }
+    }
+
+    // Checks a method of `target`, whose `self` is a `target` unless it says
+    // otherwise. A method of a generic struct is generic over the struct's
+    // type parameters.
+    fn check_method_body(&mut self, target: Symbol, method: &Function) -> Type {
+        let params = match self.types.get(&target) {
+            Some(
+                def @ TypeDef {
+                    kind: TypeDefKind::Struct(_),
+                    ..
+                },
+            ) if method
+                .params
+                .first()
+                .is_some_and(|param| param.ty.is_none()) =>
+            {
+                def.params.clone()
+            }
+            _ => return self.check_function(method),
+        };
+        let mut method = method.clone();
+        let span = method.params[0].span;
+        let named = |name| TypeExpr {
+            kind: TypeExprKind::Named {
+                name,
+                args: Vec::new(),
+            },
+            span,
+        };
+        method.params[0].ty = Some(TypeExpr {
+            kind: TypeExprKind::Named {
+                name: target,
+                args: params.iter().map(|param| named(*param)).collect(),
+            },
+            span,
+        });
+        let own = std::mem::replace(&mut method.type_params, params.clone());
+        method
+            .type_params
+            .extend(own.into_iter().filter(|param| !params.contains(param)));
+        self.check_function(&method)
+    }
+
+    // The type of a method a trait declares, with a fresh variable for each
+    // type it leaves out.
+    fn signature(&mut self, signature: &MethodSig) -> Type {
+        let params = signature
+            .params
+            .iter()
+            .map(|param| match &param.ty {
+                Some(ty) => self.resolve(ty),
+                None => self.fresh(),
+            })
+            .collect();
+        let returns = match &signature.returns {
+            Some(ty) => self.resolve(ty),
+            None => self.fresh(),
+        };
+        Type::Fn(params, Box::new(returns))
+    }
+
+    fn check_block(&mut self, block: &Block) -> Type {
+        self.scopes.push(HashMap::new());
+        self.declare_traits(&block.statements);
+        for stmt in &block.statements {
+            self.check_stmt(stmt);
+        }
+        let ty = match &block.value {
+            Some(value) => self.check_expr(value),
+            // A block that always jumps away never produces a value.
+            None if block.statements.last().is_some_and(diverges) => Type::Unknown,
+            None => Type::Unit,
+        };
+        self.scopes.pop();
+        ty
+    }
+
+    fn check_function(&mut self, function: &Function) -> Type {
+        let type_params = function
+            .type_params
+            .iter()
+            .map(|name| (*name, Type::Param(*name)))
+            .collect();
+        self.type_params.push(type_params);
+        let params: Vec<Type> = function
+            .params
+            .iter()
+            .map(|param| match &param.ty {
+                Some(ty) => self.resolve(ty),
+                None => self.fresh(),
+            })
+            .collect();
+        let returns = match &function.returns {
+            Some(ty) => self.resolve(ty),
+            None => self.fresh(),
+        };
+        let ty = Type::Fn(params.clone(), Box::new(returns.clone()));
+
+        self.scopes.push(HashMap::new());
+        if let Some(name) = &function.name {
+            // Lets the function call itself, at the type it is being given.
+            self.define(*name, Scheme::monomorphic(ty.clone()));
+        }
+        for (param, ty) in function.params.iter().zip(params) {
+            self.define(param.name, Scheme::monomorphic(ty));
+        }
+        self.returns.push(returns.clone());
+        let body = self.check_block(&function.body);
+        self.returns.pop();
+        self.scopes.pop();
+
+        let span = match &function.body.value {
+            Some(value) => value.span,
+            None => function.body.span,
+        };
+        self.expect(&returns, &body, span);
+        self.type_params.pop();
+
+        // Outside the function its type parameters are variables, which
+        // generalizing it quantifies over.
+        let fresh = function
+            .type_params
+            .iter()
+            .map(|name| (*name, self.fresh()))
+            .collect();
+        self.apply(&ty).substitute(&fresh)
+    }
+
+    // Records a struct or enum, whose fields or variants `resolve` types
+    // with its type parameters in scope. It is recorded first so that it
+    // can refer to itself.
+    fn declare_type(
+        &mut self,
+        name: Symbol,
+        params: &[Symbol],
+        resolve: impl FnOnce(&mut TypeChecker) -> TypeDefKind,
+    ) {
+        let def = TypeDef {
+            params: params.to_vec(),
+            kind: TypeDefKind::Struct(Vec::new()),
+        };
+        self.types.insert(name, def);
+        self.type_params.push(
+            params
+                .iter()
+                .map(|param| (*param, Type::Param(*param)))
+                .collect(),
+        );
+        let kind = resolve(self);
+        self.type_params.pop();
+        if let Some(def) = self.types.get_mut(&name) {
+            def.kind = kind;
+        }
+    }
+
+    // Gives each of a declared type's parameters a fresh variable, returning
+    // what each stands for and the type itself.
+    fn instantiate_type(&mut self, name: Symbol, def: &TypeDef) -> (HashMap<Symbol, Type>, Type) {
+        let args: Vec<Type> = def.params.iter().map(|_| self.fresh()).collect();
+        let substitution = def.params.iter().copied().zip(args.clone()).collect();
+        (substitution, Type::Named(name, args))
+    }
+
+    // The type of `Enum::Variant`: the enum itself for a variant holding
+    // nothing, or the function that makes one.
+    fn check_variant(&mut self, enum_name: Symbol, variant: Symbol, span: Span) -> Type {
+        let def = match self.types.get(&enum_name) {
+            Some(def) => def.clone(),
+            None => return Type::Unknown,
+        };
+        let variants = match &def.kind {
+            TypeDefKind::Enum(variants) => variants,
+            TypeDefKind::Struct(_) => return Type::Unknown,
+        };
+        let fields = match variants.iter().find(|(name, _)| *name == variant) {
+            Some((_, fields)) => fields,
+            None => {
+                self.error(
+                    format!("enum `{}` has no variant `{}`", enum_name, variant),
+                    span,
+                );
+                return Type::Unknown;
+            }
+        };
+        let (args, ty) = self.instantiate_type(enum_name, &def);
+        match fields.is_empty() {
+            true => ty,
+            false => Type::Fn(
+                fields.iter().map(|field| field.substitute(&args)).collect(),
+                Box::new(ty),
+            ),
+        }
+    }
+
+    fn check_struct(&mut self, path: &[Symbol], fields: &[(Symbol, Expr)], span: Span) -> Type {
+        let found: Vec<Type> = fields
+            .iter()
+            .map(|(_, value)| self.check_expr(value))
+            .collect();
+        let (name, def) = match path {
+            [name] => match self.types.get(name) {
+                Some(def) => (*name, def.clone()),
+                None => return Type::Unknown,
+            },
+            _ => return Type::Unknown,
+        };
+        let declared = match &def.kind {
+            TypeDefKind::Struct(declared) => declared,
+            TypeDefKind::Enum(_) => {
+                self.error(format!("`{}` is an enum, not a struct", name), span);
+                return Type::Unknown;
+            }
+        };
+        let (args, ty) = self.instantiate_type(name, &def);
+        for ((field, value), found) in fields.iter().zip(found) {
+            match declared.iter().find(|(declared, _)| declared == field) {
+                Some((_, expected)) => {
+                    let expected = expected.substitute(&args);
+                    self.expect(&expected, &found, value.span);
+                }
+                None => self.error(
+                    format!("struct `{}` has no field `{}`", name, field),
+                    value.span,
+                ),
+            }
+        }
+        ty
+    }
+
+    fn check_field(&mut self, target: &Type, field: Symbol, span: Span) -> Type {
+        let (name, args) = match self.shallow(target) {
+            Type::Named(name, args) => (name, args),
+            _ => return Type::Unknown,
+        };
+        let def = self.types[&name].clone();
+        let declared = match &def.kind {
+            TypeDefKind::Struct(declared) => declared,
+            TypeDefKind::Enum(_) => return Type::Unknown,
+        };
+        match declared.iter().find(|(declared, _)| *declared == field) {
+            Some((_, ty)) => {
+                let args = def.params.iter().copied().zip(args).collect();
+                ty.substitute(&args)
+            }
+            None => {
+                self.error(format!("struct `{}` has no field `{}`", name, field), span);
+                Type::Unknown
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Integer(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::String(_) => Type::String,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Ident(name) => self.lookup(*name, expr.span),
+            ExprKind::Path(segments) if segments.len() == 2 => {
+                self.check_variant(segments[0], segments[1], expr.span)
+            }
+            ExprKind::Path(_) | ExprKind::Break | ExprKind::Continue => Type::Unknown,
+            ExprKind::Tuple(elements) if elements.is_empty() => Type::Unit,
+            ExprKind::Tuple(elements) => Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.check_expr(element))
+                    .collect(),
+            ),
+            ExprKind::List(elements) => Type::list(self.check_all(elements.iter())),
+            ExprKind::Map(entries) => {
+                let key = self.check_all(entries.iter().map(|(key, _)| key));
+                let value = self.check_all(entries.iter().map(|(_, value)| value));
+                Type::map(key, value)
+            }
+            ExprKind::Struct { path, fields } => self.check_struct(path, fields, expr.span),
+            ExprKind::Field { target, name } => {
+                let target = self.check_expr(target);
+                self.check_field(&target, *name, expr.span)
+            }
+            ExprKind::Unary { op, operand } => {
+                let ty = self.check_expr(operand);
+                match (op, self.shallow(&ty)) {
+                    (UnaryOp::Not, _) => {
+                        self.expect(&Type::Bool, &ty, operand.span);
+                        Type::Bool
+                    }
+                    (_, Type::Var(_)) => {
+                        self.defer(Operator::Unary(*op), &ty, expr.span);
+                        ty
+                    }
+                    (UnaryOp::BitNot, Type::Int) | (_, Type::Unknown) => ty,
+                    (UnaryOp::Negate, Type::Int) | (UnaryOp::Negate, Type::Float) => ty,
+                    (_, other) => {
+                        self.unsupported(Operator::Unary(*op), &other, &other, expr.span);
+                        Type::Unknown
+                    }
+                }
+            }
+            ExprKind::Binary { op, left, right } => self.check_binary(*op, left, right, expr.span),
+            ExprKind::Assign { target, value } => {
+                let found = self.check_expr(value);
+                match &target.kind {
+                    ExprKind::Ident(name) if self.is_bound(*name) => {
+                        let expected = self.lookup(*name, target.span);
+                        self.expect(&expected, &found, value.span);
+                    }
+                    // Assigning to an unbound name defines it.
+                    ExprKind::Ident(name) => self.define(*name, Scheme::monomorphic(found.clone())),
+                    _ => {
+                        let expected = self.check_expr(target);
+                        self.expect(&expected, &found, value.span);
+                    }
+                }
+                found
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let scrutinee = self.check_expr(scrutinee);
+                let ty = self.fresh();
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    self.bind_pattern(&arm.pattern, &scrutinee);
+                    if let Some(guard) = &arm.guard {
+                        let found = self.check_expr(guard);
+                        self.expect(&Type::Bool, &found, guard.span);
+                    }
+                    let found = self.check_expr(&arm.body);
+                    self.expect(&ty, &found, arm.body.span);
+                    self.scopes.pop();
+                }
+                ty
+            }
+            ExprKind::Block(block) => self.check_block(block),
+            ExprKind::Function(function) => self.check_function(function),
+            ExprKind::Call { callee, args } => {
+                let callee_ty = self.check_expr(callee);
+                let found: Vec<Type> = args.iter().map(|arg| self.check_expr(arg)).collect();
+                let name = match &callee.kind {
+                    ExprKind::Ident(name) => format!("`{}`", name),
+                    _ => "function".to_string(),
+                };
+                self.check_call(&callee_ty, &name, args, found, callee.span, expr.span)
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let receiver = self.check_expr(receiver);
+                self.check_method(receiver, method, args, expr.span)
+            }
+            ExprKind::Index { target, index } => {
+                let target_ty = self.check_expr(target);
+                let found = self.check_expr(index);
+                match self.shallow(&target_ty) {
+                    // Lists, tuples and maps can all be indexed, so a variable
+                    // says too little to go on.
+                    Type::Unknown | Type::Var(_) => Type::Unknown,
+                    Type::Map(key, value) => {
+                        self.expect(&key, &found, index.span);
+                        *value
+                    }
+                    Type::List(element) => {
+                        self.expect(&Type::Int, &found, index.span);
+                        *element
+                    }
+                    Type::Tuple(elements) => {
+                        self.expect(&Type::Int, &found, index.span);
+                        match index.kind {
+                            ExprKind::Integer(n) if n >= 0 && (n as usize) < elements.len() => {
+                                elements[n as usize].clone()
+                            }
+                            _ => Type::Unknown,
+                        }
+                    }
+                    other => {
+                        let other = self.display(&[other]).remove(0);
+                        self.error(format!("cannot index into `{}`", other), target.span);
+                        Type::Unknown
+                    }
+                }
+            }
+            ExprKind::Range { start, end, .. } => {
+                for bound in [start, end] {
+                    let found = self.check_expr(bound);
+                    self.expect(&Type::Int, &found, bound.span);
+                }
+                Type::Range
+            }
+            ExprKind::Slice { target, start, end } => {
+                let target_ty = self.check_expr(target);
+                for bound in start.iter().chain(end) {
+                    let found = self.check_expr(bound);
+                    self.expect(&Type::Int, &found, bound.span);
+                }
+                match self.shallow(&target_ty) {
+                    Type::List(_) | Type::String => target_ty,
+                    Type::Unknown | Type::Var(_) => Type::Unknown,
+                    other => {
+                        let other = self.display(&[other]).remove(0);
+                        self.error(format!("cannot slice `{}`", other), target.span);
+                        Type::Unknown
+                    }
+                }
+            }
+            ExprKind::Return(value) => {
+                let found = match value {
+                    Some(value) => self.check_expr(value),
+                    None => Type::Unit,
+                };
+                if let Some(expected) = self.returns.last().cloned() {
+                    self.expect(&expected, &found, expr.span);
+                }
+                Type::Unknown
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let found = self.check_expr(condition);
+                self.expect(&Type::Bool, &found, condition.span);
+                let then_ty = self.check_block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let ty = self.fresh();
+                        self.expect(&ty, &then_ty, then_branch.span);
+                        let else_ty = self.check_expr(else_branch);
+                        self.expect(&ty, &else_ty, else_branch.span);
+                        ty
+                    }
+                    None => Type::Unit,
+                }
+            }
+            ExprKind::While { condition, body } => {
+                let found = self.check_expr(condition);
+                self.expect(&Type::Bool, &found, condition.span);
+                self.check_block(body);
+                Type::Unit
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                let iterable_ty = self.check_expr(iterable);
+                let element = match self.shallow(&iterable_ty) {
+                    Type::List(element) => *element,
+                    Type::String => Type::String,
+                    Type::Range => Type::Int,
+                    Type::Map(key, value) => Type::Tuple(vec![*key, *value]),
+                    Type::Tuple(_) | Type::Var(_) | Type::Unknown => Type::Unknown,
+                    other => {
+                        let other = self.display(&[other]).remove(0);
+                        self.error(format!("cannot iterate over `{}`", other), iterable.span);
+                        Type::Unknown
+                    }
+                };
+                self.scopes.push(HashMap::new());
+                self.bind_pattern(pattern, &element);
+                self.check_block(body);
+                self.scopes.pop();
+                Type::Unit
+            }
+        }
+    }
+
+    fn check_binary(&mut self, op: BinaryOp, left: &Expr, right: &Expr, span: Span) -> Type {
+        let left_ty = self.check_expr(left);
+        let right_ty = self.check_expr(right);
+        let (left_ty, right_ty) = (self.shallow(&left_ty), self.shallow(&right_ty));
+        let numeric = left_ty.is_numeric() && right_ty.is_numeric();
+        let unknown = left_ty == Type::Unknown || right_ty == Type::Unknown;
+        let variable = matches!(left_ty, Type::Var(_)) || matches!(right_ty, Type::Var(_));
+
+        let result = match op {
+            // Only bools have a truth value, so `1 && x` is an error rather
+            // than a test of whether `1` is zero.
+            BinaryOp::And | BinaryOp::Or => {
+                let other = |ty: &Type| !matches!(ty, Type::Bool | Type::Var(_) | Type::Unknown);
+                if other(&left_ty) || other(&right_ty) {
+                    self.unsupported(Operator::Binary(op), &left_ty, &right_ty, span);
+                } else {
+                    self.expect(&Type::Bool, &left_ty, left.span);
+                    self.expect(&Type::Bool, &right_ty, right.span);
+                }
+                return Type::Bool;
+            }
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                if numeric || self.unify(&left_ty, &right_ty).is_ok() {
+                    return Type::Bool;
+                }
+                None
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                match (&left_ty, &right_ty) {
+                    _ if numeric || unknown => Some(Type::Bool),
+                    (Type::String, Type::String) => Some(Type::Bool),
+                    _ if variable => self.unify_operands(op, &left_ty, &right_ty, span),
+                    _ => None,
+                }
+                .map(|_| Type::Bool)
+            }
+            BinaryOp::Add
+            | BinaryOp::Subtract
+            | BinaryOp::Multiply
+            | BinaryOp::Divide
+            | BinaryOp::Remainder => match (&left_ty, &right_ty) {
+                _ if unknown => Some(Type::Unknown),
+                (Type::Int, Type::Int) => Some(Type::Int),
+                _ if numeric => Some(Type::Float),
+                (Type::String, Type::String) if op == BinaryOp::Add => Some(Type::String),
+                _ if variable => self.unify_operands(op, &left_ty, &right_ty, span),
+                _ => None,
+            },
+            BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::ShiftLeft
+            | BinaryOp::ShiftRight => match (&left_ty, &right_ty) {
+                (Type::Int, Type::Int) => Some(Type::Int),
+                (Type::Int, Type::Unknown) | (Type::Unknown, Type::Int) => Some(Type::Int),
+                _ if unknown => Some(Type::Unknown),
+                _ if variable => self.unify_operands(op, &left_ty, &right_ty, span),
+                _ => None,
+            },
+        };
+
+        result.unwrap_or_else(|| {
+            self.unsupported(Operator::Binary(op), &left_ty, &right_ty, span);
+            Type::Unknown
+        })
+    }
+
+    // Gives both operands of an operator the same type, checking later that
+    // the operator works on it.
+    fn unify_operands(
+        &mut self,
+        op: BinaryOp,
+        left: &Type,
+        right: &Type,
+        span: Span,
+    ) -> Option<Type> {
+        self.unify(left, right).ok()?;
+        let ty = self.shallow(left);
+        if matches!(ty, Type::Var(_)) {
+            self.defer(Operator::Binary(op), &ty, span);
+        } else if !supports(Operator::Binary(op), &ty) {
+            return None;
+        }
+        Some(ty)
+    }
+
+    // Types the built-in methods the interpreter provides. A method only lists
+    // have tells us a variable receiver is a list, and likewise for maps.
+    fn check_method(&mut self, receiver: Type, method: &str, args: &[Expr], span: Span) -> Type {
+        let found: Vec<Type> = args.iter().map(|arg| self.check_expr(arg)).collect();
+        let mut receiver = self.shallow(&receiver);
+        if let Type::Var(_) = receiver {
+            let ty = match method {
+                "push" | "pop" | "map" | "filter" => Type::list(self.fresh()),
+                "contains" | "insert" | "remove" | "keys" | "values" => {
+                    Type::map(self.fresh(), self.fresh())
+                }
+                _ => {
+                    return self
+                        .check_free_method(&receiver, method, args, &found, span)
+                        .unwrap_or(Type::Unknown)
+                }
+            };
+            self.expect(&receiver, &ty, span);
+            receiver = ty;
+        }
+
+        if let Type::Named(name, _) = &receiver {
+            // Impls may add methods after the code calling them is checked.
+            let scheme = match self.methods.get(&(*name, Symbol::intern(method))) {
+                Some(scheme) => scheme.clone(),
+                None => {
+                    return self
+                        .check_field_call(&receiver, method, args, &found, span)
+                        .or_else(|| self.check_free_method(&receiver, method, args, &found, span))
+                        .unwrap_or(Type::Unknown)
+                }
+            };
+            return match self.instantiate(&scheme, span) {
+                Type::Fn(params, returns) if !params.is_empty() => {
+                    self.expect(&params[0], &receiver, span);
+                    let name = format!("`{}`", method);
+                    self.check_arguments(&name, &params[1..], args, &found, span);
+                    *returns
+                }
+                _ => Type::Unknown,
+            };
+        }
+
+        let (params, returns) = match (&receiver, method) {
+            (Type::Unknown, _) => return Type::Unknown,
+            (Type::List(_), "len") | (Type::Map(..), "len") | (Type::String, "len") => {
+                (Vec::new(), Type::Int)
+            }
+            (Type::List(element), "push") => (vec![(**element).clone()], Type::Unit),
+            (Type::List(element), "pop") => (Vec::new(), (**element).clone()),
+            (Type::List(element), "map") => {
+                let returns = self.fresh();
+                let callback = Type::Fn(vec![(**element).clone()], Box::new(returns.clone()));
+                (vec![callback], Type::list(returns))
+            }
+            (Type::List(element), "filter") => {
+                let callback = Type::Fn(vec![(**element).clone()], Box::new(Type::Bool));
+                (vec![callback], receiver.clone())
+            }
+            (Type::Map(key, _), "contains") => (vec![(**key).clone()], Type::Bool),
+            (Type::Map(key, value), "insert") => {
+                (vec![(**key).clone(), (**value).clone()], Type::Unit)
+            }
+            (Type::Map(key, value), "remove") => (vec![(**key).clone()], (**value).clone()),
+            (Type::Map(key, _), "keys") => (Vec::new(), Type::list((**key).clone())),
+            (Type::Map(_, value), "values") => (Vec::new(), Type::list((**value).clone())),
+            _ => {
+                if let Some(ty) = self.check_free_method(&receiver, method, args, &found, span) {
+                    return ty;
+                }
+                let receiver = self.display(&[receiver]).remove(0);
+                self.error(format!("`{}` has no method `{}`", receiver, method), span);
+                return Type::Unknown;
+            }
+        };
+        self.check_arguments(&format!("`{}`", method), &params, args, &found, span);
+        returns
+    }
+
+    // `receiver.method(args)` as a call to the function a struct keeps in
+    // its field `method`, if it has one.
+    fn check_field_call(
+        &mut self,
+        receiver: &Type,
+        method: &str,
+        args: &[Expr],
+        found: &[Type],
+        span: Span,
+    ) -> Option<Type> {
+        let field = Symbol::intern(method);
+        let name = match receiver {
+            Type::Named(name, _) => *name,
+            _ => return None,
+        };
+        match &self.types.get(&name)?.kind {
+            TypeDefKind::Struct(fields) if fields.iter().any(|(name, _)| *name == field) => {}
+            _ => return None,
+        }
+        let ty = self.check_field(receiver, field, span);
+        let name = format!("`{}`", method);
+        Some(self.check_call(&ty, &name, args, found.to_vec(), span, span))
+    }
+
+    // The type calling a value of type `callee` returns.
+    fn check_call(
+        &mut self,
+        callee: &Type,
+        name: &str,
+        args: &[Expr],
+        found: Vec<Type>,
+        callee_span: Span,
+        span: Span,
+    ) -> Type {
+        match self.shallow(callee) {
+            Type::Fn(params, returns) => {
+                self.check_arguments(name, &params, args, &found, span);
+                *returns
+            }
+            Type::Var(_) => {
+                let returns = self.fresh();
+                let ty = Type::Fn(found, Box::new(returns.clone()));
+                self.expect(callee, &ty, callee_span);
+                returns
+            }
+            Type::Unknown => Type::Unknown,
+            other => {
+                let other = self.display(&[other]).remove(0);
+                self.error(format!("cannot call `{}`", other), callee_span);
+                Type::Unknown
+            }
+        }
+    }
+
+    // `receiver.method(args)` as `method(receiver, args)`, if a function of
+    // that name is in scope.
+    fn check_free_method(
+        &mut self,
+        receiver: &Type,
+        method: &str,
+        args: &[Expr],
+        found: &[Type],
+        span: Span,
+    ) -> Option<Type> {
+        let name = Symbol::intern(method);
+        if !self.is_bound(name) {
+            return None;
+        }
+        let ty = self.lookup(name, span);
+        match self.shallow(&ty) {
+            Type::Fn(params, returns) if !params.is_empty() => {
+                self.expect(&params[0], receiver, span);
+                let name = format!("`{}`", method);
+                self.check_arguments(&name, &params[1..], args, found, span);
+                Some(*returns)
+            }
+            Type::Var(_) => {
+                let returns = self.fresh();
+                let params = std::iter::once(receiver.clone())
+                    .chain(found.iter().cloned())
+                    .collect();
+                self.expect(&ty, &Type::Fn(params, Box::new(returns.clone())), span);
+                Some(returns)
+            }
+            Type::Unknown => Some(Type::Unknown),
+            _ => None,
+        }
+    }
+
+    fn check_arguments(
+        &mut self,
+        name: &str,
+        params: &[Type],
+        args: &[Expr],
+        found: &[Type],
+        span: Span,
+    ) {
+        if params.len() != args.len() {
+            self.error(
+                format!(
+                    "{} expects {} argument{}, found {}",
+                    name,
+                    params.len(),
+                    if params.len() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                span,
+            );
+            return;
+        }
+        for ((expected, found), arg) in params.iter().zip(found).zip(args) {
+            self.expect(expected, found, arg.span);
+        }
+    }
+
+    // Checks expressions that must share a type, such as list elements, and
+    // returns that type.
+    fn check_all<'e>(&mut self, exprs: impl Iterator<Item = &'e Expr>) -> Type {
+        let ty = self.fresh();
+        for expr in exprs {
+            let found = self.check_expr(expr);
+            self.expect(&ty, &found, expr.span);
+        }
+        ty
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: &Type) {
+        let literal = match &pattern.kind {
+            PatternKind::Wildcard => return,
+            PatternKind::Binding(name) => {
+                return self.define(*name, Scheme::monomorphic(ty.clone()))
+            }
+            PatternKind::Tuple(patterns) => {
+                let elements = match self.shallow(ty) {
+                    Type::Tuple(types) if types.len() == patterns.len() => types,
+                    Type::Var(_) => {
+                        let types: Vec<Type> = patterns.iter().map(|_| self.fresh()).collect();
+                        self.expect(ty, &Type::Tuple(types.clone()), pattern.span);
+                        types
+                    }
+                    Type::Unknown => vec![Type::Unknown; patterns.len()],
+                    other => {
+                        let other = self.display(&[other]).remove(0);
+                        self.error(
+                            format!("mismatched types: expected `{}`, found a tuple", other),
+                            pattern.span,
+                        );
+                        vec![Type::Unknown; patterns.len()]
+                    }
+                };
+                for (pattern, ty) in patterns.iter().zip(&elements) {
+                    self.bind_pattern(pattern, ty);
+                }
+                return;
+            }
+            PatternKind::Struct { path, fields, .. } => {
+                let declared = match (path.as_slice(), self.pattern_type(path, ty, pattern.span)) {
+                    ([name], Some((args, TypeDefKind::Struct(declared)))) => fields
+                        .iter()
+                        .map(
+                            |(field, _)| match declared.iter().find(|(d, _)| d == field) {
+                                Some((_, ty)) => ty.substitute(&args),
+                                None => {
+                                    self.error(
+                                        format!("struct `{}` has no field `{}`", name, field),
+                                        pattern.span,
+                                    );
+                                    Type::Unknown
+                                }
+                            },
+                        )
+                        .collect(),
+                    _ => vec![Type::Unknown; fields.len()],
+                };
+                for ((_, pattern), ty) in fields.iter().zip(&declared) {
+                    self.bind_pattern(pattern, ty);
+                }
+                return;
+            }
+            PatternKind::Variant { path, fields } => {
+                let mut types = match self.pattern_type(path, ty, pattern.span) {
+                    Some((args, TypeDefKind::Enum(variants))) => variants
+                        .into_iter()
+                        .find(|(name, _)| path.last() == Some(name))
+                        .map(|(_, types)| types.iter().map(|ty| ty.substitute(&args)).collect())
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+                types.resize(fields.len(), Type::Unknown);
+                for (pattern, ty) in fields.iter().zip(&types) {
+                    self.bind_pattern(pattern, ty);
+                }
+                return;
+            }
+            PatternKind::Integer(_) => Type::Int,
+            PatternKind::Float(_) => Type::Float,
+            PatternKind::String(_) => Type::String,
+            PatternKind::Bool(_) => Type::Bool,
+        };
+        // Integer and float patterns compare numerically.
+        if !(literal.is_numeric() && self.shallow(ty).is_numeric()) {
+            self.expect(ty, &literal, pattern.span);
+        }
+    }
+
+    // The declared type a struct or variant pattern matches, checked
+    // against the value it is matched against. Only values already known to
+    // be a declared type are checked: a variable could be matched against
+    // patterns of several types, the way `?` matches both `Option` and
+    // `Result` values.
+    fn pattern_type(
+        &mut self,
+        path: &[Symbol],
+        ty: &Type,
+        span: Span,
+    ) -> Option<(HashMap<Symbol, Type>, TypeDefKind)> {
+        if !matches!(self.shallow(ty), Type::Named(..)) {
+            return None;
+        }
+        let name = match path {
+            [name] | [name, _] => *name,
+            _ => return None,
+        };
+        let def = self.types.get(&name)?.clone();
+        let (args, pattern_ty) = self.instantiate_type(name, &def);
+        self.expect(ty, &pattern_ty, span);
+        Some((args, def.kind))
+    }
+
+    fn resolve(&mut self, ty: &TypeExpr) -> Type {
+        match &ty.kind {
+            TypeExprKind::Named { name, args } => {
+                let mut args: Vec<Type> = args.iter().map(|arg| self.resolve(arg)).collect();
+                let param = self
+                    .type_params
+                    .iter()
+                    .rev()
+                    .find_map(|params| params.get(name))
+                    .cloned();
+                let arity = match (&param, self.types.get(name), name.as_str()) {
+                    (Some(_), _, _) => 0,
+                    (None, Some(def), _) => def.params.len(),
+                    (None, None, "Int" | "Float" | "String" | "Bool" | "Range") => 0,
+                    (None, None, "List" | "Option") => 1,
+                    (None, None, "Map" | "Result") => 2,
+                    _ => {
+                        self.error(format!("unknown type `{}`", name), ty.span);
+                        return Type::Unknown;
+                    }
+                };
+                if args.len() != arity {
+                    self.error(
+                        format!(
+                            "`{}` expects {} type argument{}, found {}",
+                            name,
+                            arity,
+                            if arity == 1 { "" } else { "s" },
+                            args.len()
+                        ),
+                        ty.span,
+                    );
+                    return Type::Unknown;
+                }
+                if let Some(param) = param {
+                    return param;
+                }
+                if self.types.contains_key(name) {
+                    return Type::Named(*name, args);
+                }
+                match name.as_str() {
+                    // `?` matches a value against both of the prelude's
+                    // enums at once, so their values aren't typed yet.
+                    "Option" | "Result" => Type::Unknown,
+                    "Int" => Type::Int,
+                    "Float" => Type::Float,
+                    "String" => Type::String,
+                    "Bool" => Type::Bool,
+                    "Range" => Type::Range,
+                    "List" => Type::list(args.remove(0)),
+                    _ => {
+                        let value = args.pop().expect("checked above");
+                        Type::map(args.remove(0), value)
+                    }
+                }
+            }
+            TypeExprKind::Tuple(elements) if elements.is_empty() => Type::Unit,
+            TypeExprKind::Tuple(elements) => Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.resolve(element))
+                    .collect(),
+            ),
+            TypeExprKind::Function { params, returns } => Type::Fn(
+                params.iter().map(|param| self.resolve(param)).collect(),
+                Box::new(self.resolve(returns)),
+            ),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() as u32 - 1)
+    }
+
+    // Follows variable bindings until reaching a type that isn't a bound
+    // variable.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty;
+        while let Type::Var(var) = ty {
+            match &self.substitution[*var as usize] {
+                Some(bound) => ty = bound,
+                None => break,
+            }
+        }
+        ty.clone()
+    }
+
+    // Substitutes every bound variable in `ty`.
+    fn apply(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Var(var) => Type::Var(var),
+            ty => ty.map_variables(&mut |var| self.apply(&Type::Var(var))),
+        }
+    }
+
+    fn unify(&mut self, left: &Type, right: &Type) -> Result<(), Mismatch> {
+        match (self.shallow(left), self.shallow(right)) {
+            (Type::Unknown, _) | (_, Type::Unknown) => Ok(()),
+            (Type::Var(left), Type::Var(right)) if left == right => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                let ty = self.apply(&ty);
+                let mut variables = Vec::new();
+                ty.variables(&mut variables);
+                if variables.contains(&var) {
+                    return Err(Mismatch::Infinite(var, ty));
+                }
+                self.substitution[var as usize] = Some(ty);
+                Ok(())
+            }
+            (Type::Tuple(left), Type::Tuple(right)) if left.len() == right.len() => left
+                .iter()
+                .zip(&right)
+                .try_for_each(|(left, right)| self.unify(left, right)),
+            (Type::List(left), Type::List(right)) => self.unify(&left, &right),
+            (Type::Map(left_key, left_value), Type::Map(right_key, right_value)) => {
+                self.unify(&left_key, &right_key)?;
+                self.unify(&left_value, &right_value)
+            }
+            (Type::Fn(left_params, left_returns), Type::Fn(right_params, right_returns))
+                if left_params.len() == right_params.len() =>
+            {
+                for (left, right) in left_params.iter().zip(&right_params) {
+                    self.unify(left, right)?;
+                }
+                self.unify(&left_returns, &right_returns)
+            }
+            (Type::Named(left, left_args), Type::Named(right, right_args)) if left == right => {
+                left_args
+                    .iter()
+                    .zip(&right_args)
+                    .try_for_each(|(left, right)| self.unify(left, right))
+            }
+            (left, right) if left == right => Ok(()),
+            _ => Err(Mismatch::Types),
+        }
+    }
+
+    // Quantifies over the variables in `ty` that no enclosing binding uses.
+    fn generalize(&self, ty: Type) -> Scheme {
+        let ty = self.apply(&ty);
+        let mut variables = Vec::new();
+        ty.variables(&mut variables);
+
+        let mut bound = Vec::new();
+        for scheme in self.scopes.iter().flat_map(HashMap::values) {
+            let mut used = Vec::new();
+            self.apply(&scheme.ty).variables(&mut used);
+            bound.extend(
+                used.into_iter()
+                    .filter(|var| !scheme.variables.contains(var)),
+            );
+        }
+        variables.retain(|var| !bound.contains(var));
+
+        let mut constraints = Vec::new();
+        for deferred in &self.deferred {
+            if let Type::Var(var) = self.apply(&deferred.ty) {
+                if variables.contains(&var) {
+                    constraints.push((deferred.op, var));
+                }
+            }
+        }
+        Scheme {
+            variables,
+            constraints,
+            ty,
+        }
+    }
+
+    // Gives a scheme's variables fresh names, deferring its operator checks
+    // to `span`, where it is used.
+    fn instantiate(&mut self, scheme: &Scheme, span: Span) -> Type {
+        let fresh: Vec<(u32, Type)> = scheme
+            .variables
+            .iter()
+            .map(|var| (*var, self.fresh()))
+            .collect();
+        let mut rename = |var| {
+            fresh
+                .iter()
+                .find(|(quantified, _)| *quantified == var)
+                .map_or(Type::Var(var), |(_, ty)| ty.clone())
+        };
+        for (op, var) in &scheme.constraints {
+            let ty = rename(*var);
+            self.defer(*op, &ty, span);
+        }
+        scheme.ty.map_variables(&mut rename)
+    }
+
+    // Reports a mismatch unless `found` unifies with `expected`.
+    fn expect(&mut self, expected: &Type, found: &Type, span: Span) {
+        match self.unify(expected, found) {
+            Ok(()) => {}
+            Err(Mismatch::Types) => {
+                let types = self.display(&[expected.clone(), found.clone()]);
+                self.error(
+                    format!(
+                        "mismatched types: expected `{}`, found `{}`",
+                        types[0], types[1]
+                    ),
+                    span,
+                );
+            }
+            Err(Mismatch::Infinite(var, ty)) => {
+                let types = self.display(&[Type::Var(var), ty]);
+                self.error(
+                    format!(
+                        "cannot construct the infinite type `{} = {}`",
+                        types[0], types[1]
+                    ),
+                    span,
+                );
+            }
+        }
+    }
+
+    fn defer(&mut self, op: Operator, ty: &Type, span: Span) {
+        self.deferred.push(Deferred {
+            op,
+            ty: ty.clone(),
+            span,
+        });
+    }
+
+    fn unsupported(&mut self, op: Operator, left: &Type, right: &Type, span: Span) {
+        let types = self.display(&[left.clone(), right.clone()]);
+        let message = match op {
+            Operator::Unary(op) => format!("cannot apply `{}` to `{}`", op, types[0]),
+            Operator::Binary(op) => {
+                format!("cannot apply `{}` to `{}` and `{}`", op, types[0], types[1])
+            }
+        };
+        self.error(message, span);
+    }
+
+    // Renders types for a diagnostic, naming their variables consistently.
+    fn display(&self, types: &[Type]) -> Vec<String> {
+        let types: Vec<Type> = types.iter().map(|ty| self.apply(ty)).collect();
+        normalize(&types).iter().map(Type::to_string).collect()
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::error(message, span).with_code("type-error"));
+    }
+
+    fn define(&mut self, name: Symbol, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .expect("there is always a global scope")
+            .insert(name, scheme);
+    }
+
+    fn is_bound(&self, name: Symbol) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(&name))
+    }
+
+    // Unbound names are left for the interpreter to report.
+    fn lookup(&mut self, name: Symbol, span: Span) -> Type {
+        let scheme = self.scopes.iter().rev().find_map(|scope| scope.get(&name));
+        match scheme {
+            Some(scheme) if scheme.variables.is_empty() => scheme.ty.clone(),
+            Some(scheme) => {
+                let scheme = Scheme {
+                    variables: scheme.variables.clone(),
+                    constraints: scheme.constraints.clone(),
+                    ty: scheme.ty.clone(),
+                };
+                self.instantiate(&scheme, span)
+            }
+            None => Type::Unknown,
+        }
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> TypeChecker {
+        TypeChecker::new()
+    }
+}
+
+fn supports(op: Operator, ty: &Type) -> bool {
+    match op {
+        Operator::Unary(UnaryOp::Negate) => ty.is_numeric(),
+        Operator::Unary(UnaryOp::Not) => *ty == Type::Bool,
+        Operator::Unary(UnaryOp::BitNot) => *ty == Type::Int,
+        Operator::Binary(BinaryOp::Add) => ty.is_numeric() || *ty == Type::String,
+        Operator::Binary(BinaryOp::Less)
+        | Operator::Binary(BinaryOp::LessEqual)
+        | Operator::Binary(BinaryOp::Greater)
+        | Operator::Binary(BinaryOp::GreaterEqual) => ty.is_numeric() || *ty == Type::String,
+        Operator::Binary(BinaryOp::And) | Operator::Binary(BinaryOp::Or) => *ty == Type::Bool,
+        Operator::Binary(BinaryOp::Equal) | Operator::Binary(BinaryOp::NotEqual) => true,
+        Operator::Binary(op) if op.is_bitwise() => *ty == Type::Int,
+        Operator::Binary(_) => ty.is_numeric(),
+    }
+}
+
+fn diverges(stmt: &Stmt) -> bool {
+    matches!(
+        &stmt.kind,
+        StmtKind::Expr(Expr {
+            kind: ExprKind::Return(_) | ExprKind::Break | ExprKind::Continue,
+            ..
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parser::parse;
+    use crate::typecheck::typecheck::{check, TypeChecker};
+
+    fn errors(source: &str) -> Vec<String> {
+        check(&parse(source).unwrap())
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    fn infer(source: &str) -> String {
+        TypeChecker::new()
+            .infer(&parse(source).unwrap())
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn accepts_well_typed_programs() {
+        let source = "
+            let total: Int = 0;
+            let names: List<String> = [];
+            let ages = #{ \"ada\": 36 };
+            fn add(a: Int, b: Int) -> Int { a + b }
+            fn fact(n: Int) -> Int {
+                if n <= 1 { return 1; }
+                n * fact(n - 1)
+            }
+            for (name, age) in ages {
+                names.push(name);
+                total = add(total, age);
+            }
+            let half: Float = total / 2.0;
+            let evens = [1, 2, 3].filter(fn(n) { n % 2 == 0 });
+            let shout: Fn(String) -> String = fn(s: String) { s + \"!\" };
+            match (total, names.len()) {
+                (0, n) if n > 0 => shout(names[0]),
+                _ => \"none\",
+            }
+        ";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn infers_principal_types() {
+        assert_eq!(infer("fn id(x) { x } id"), "Fn('a) -> 'a");
+        assert_eq!(infer("fn(a, b) { (b, a) }"), "Fn('a, 'b) -> ('b, 'a)");
+        assert_eq!(
+            infer("fn compose(f, g) { fn(x) { f(g(x)) } } compose"),
+            "Fn(Fn('a) -> 'b, Fn('c) -> 'a) -> Fn('c) -> 'b"
+        );
+        assert_eq!(
+            infer("fn append(xs, x) { xs.push(x); xs } append"),
+            "Fn(List<'a>, 'a) -> List<'a>"
+        );
+        assert_eq!(
+            infer("fn(xs) { xs.map(fn(x) { x * 2 }) }"),
+            "Fn(List<Int>) -> List<Int>"
+        );
+        assert_eq!(infer("fn(a) { ~a & 255 }"), "Fn(Int) -> Int");
+    }
+
+    #[test]
+    fn keeps_bindings_between_programs() {
+        let mut checker = TypeChecker::new();
+        assert!(checker
+            .check(&parse("fn pair(x) { (x, x) } let xs = [1];").unwrap())
+            .is_empty());
+        let ty = checker.infer(&parse("pair(xs)").unwrap()).unwrap();
+        assert_eq!(ty.to_string(), "(List<Int>, List<Int>)");
+        let ty = checker.infer(&parse("let y = 2; y").unwrap()).unwrap();
+        assert_eq!(ty.to_string(), "Int");
+        assert_eq!(
+            checker.infer(&parse("y").unwrap()).unwrap().to_string(),
+            "_"
+        );
+    }
+
+    #[test]
+    fn instantiates_generic_functions_and_types() {
+        assert_eq!(
+            infer("fn id<T>(x: T) -> T { x } (id(1), id(\"a\"))"),
+            "(Int, String)"
+        );
+        assert_eq!(
+            infer(
+                "struct Pair<A, B> { left: A, right: B }
+                 fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { left: p.right, right: p.left } }
+                 swap(Pair { left: 1, right: [true] })"
+            ),
+            "Pair<List<Bool>, Int>"
+        );
+        assert_eq!(
+            infer(
+                "enum Tree<T> { Leaf, Node(Tree<T>, T, Tree<T>) }
+                 match Tree::Node(Tree::Leaf, 2.5, Tree::Leaf) { Tree::Node(_, v, _) => v, Tree::Leaf => 0.0 }"
+            ),
+            "Float"
+        );
+        assert_eq!(
+            errors(
+                "fn pick<T>(x: T) -> T { 1 }
+                 struct Box<T> { value: T }
+                 let s: String = Box { value: 1 }.value;
+                 let b: Box<Int, Int> = Box { value: 1 };"
+            ),
+            vec![
+                "mismatched types: expected `T`, found `Int`",
+                "mismatched types: expected `String`, found `Int`",
+                "`Box` expects 1 type argument, found 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_impls_to_match_their_trait() {
+        let source = "
+            trait Shape { fn area(self) -> Float; fn name(self) -> String; }
+            struct Circle { radius }
+            impl Shape for Circle {
+                fn area(self) { 1 }
+                fn sides(self) { 0 }
+            }
+            impl Missing for Circle {}
+        ";
+        assert_eq!(
+            errors(source),
+            vec![
+                "mismatched types: expected `Fn(Circle) -> Float`, found `Fn(Circle) -> Int`",
+                "`sides` is not a method of `Shape`",
+                "`Circle` does not implement `name` from `Shape`",
+                "unknown trait `Missing`",
+            ]
+        );
+        assert!(errors(
+            "trait T { fn get(self); } struct S { x } impl T for S { fn get(self) { self.x } }"
+        )
+        .is_empty());
+        // As when it is run, an impl may come before its trait.
+        assert!(errors(
+            "struct P { x } impl Show for P { fn show(self) { \"p\" } } trait Show { fn show(self); }"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn types_method_calls_on_fields_and_functions() {
+        assert_eq!(
+            infer("fn double(n: Int) -> Int { n * 2 } 3.double().double()"),
+            "Int"
+        );
+        assert_eq!(
+            infer("struct C { f: Fn(Int) -> String } let c = C { f: fn(n) { \"\" } }; c.f(1)"),
+            "String"
+        );
+        assert_eq!(
+            errors("fn double(n: Int) -> Int { n * 2 } \"a\".double()"),
+            vec!["mismatched types: expected `Int`, found `String`"]
+        );
+        assert_eq!(
+            errors("fn add(a: Int, b: Int) -> Int { a + b } 1.add()"),
+            vec!["`add` expects 1 argument, found 0"]
+        );
+    }
+
+    #[test]
+    fn types_destructured_bindings() {
+        assert_eq!(
+            infer("let (a, (b, _)) = (1, (\"s\", true)); (b, a)"),
+            "(String, Int)"
+        );
+        assert_eq!(
+            errors("let (a, b): (Int, Int) = (1, \"s\");"),
+            vec!["mismatched types: expected `(Int, Int)`, found `(Int, String)`"]
+        );
+    }
+
+    #[test]
+    fn generalizes_functions_but_not_values() {
+        assert_eq!(
+            errors("fn id(x) { x } let a: Int = id(1); let b: String = id(\"a\");"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            errors("fn id(x) { x } id(1) + id(\"a\")"),
+            vec!["cannot apply `+` to `Int` and `String`"]
+        );
+        assert_eq!(
+            errors("fn add(a, b) { a + b } add(1, 2); add(true, false)"),
+            vec!["cannot apply `+` to `Bool` and `Bool`"]
+        );
+        assert_eq!(
+            errors("let xs = []; xs.push(1); xs.push(\"a\");"),
+            vec!["mismatched types: expected `Int`, found `String`"]
+        );
+    }
+
+    #[test]
+    fn infers_the_types_of_unannotated_code() {
+        assert_eq!(
+            errors("let wrap = fn(x: Int) { [x] }; let y: Int = wrap(1);"),
+            vec!["mismatched types: expected `Int`, found `List<Int>`"]
+        );
+        assert_eq!(
+            errors("fn last(xs) { xs.pop() } let y: Int = last([\"a\"]);"),
+            vec!["mismatched types: expected `Int`, found `String`"]
+        );
+        assert_eq!(
+            errors("let mut x = 1; x = 2.5;"),
+            vec!["mismatched types: expected `Int`, found `Float`"]
+        );
+        assert_eq!(
+            errors("fn negate(x) { -x } negate(true)"),
+            vec!["cannot apply `-` to `Bool`"]
+        );
+        assert_eq!(
+            errors(
+                "fn sum(r) { let mut t = 0; for i in r { t += i; } t } let s: String = sum(1..3);"
+            ),
+            vec!["mismatched types: expected `String`, found `Int`"]
+        );
+        assert_eq!(
+            errors("let r: Range = 0..\"a\";"),
+            vec!["mismatched types: expected `Int`, found `String`"]
+        );
+    }
+
+    #[test]
+    fn rejects_infinite_types() {
+        assert_eq!(
+            errors("fn f(x) { x(x) }"),
+            vec!["cannot construct the infinite type `'a = Fn('a) -> 'b`"]
+        );
+        assert_eq!(
+            errors("fn f(xs) { xs.push(xs) }"),
+            vec!["cannot construct the infinite type `'a = List<'a>`"]
+        );
+    }
+
+    #[test]
+    fn reports_type_errors() {
+        let cases = [
+            ("1 + \"a\"", "cannot apply `+` to `Int` and `String`"),
+            ("-true", "cannot apply `-` to `Bool`"),
+            ("1 && true", "cannot apply `&&` to `Int` and `Bool`"),
+            ("1.5 & 1", "cannot apply `&` to `Float` and `Int`"),
+            ("~2.0", "cannot apply `~` to `Float`"),
+            (
+                "fn f(x) { x || \"a\" }",
+                "cannot apply `||` to `'a` and `String`",
+            ),
+            (
+                "let x: Int = \"a\";",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            (
+                "fn f(a: Int) -> Bool { a }",
+                "mismatched types: expected `Bool`, found `Int`",
+            ),
+            (
+                "fn f(a: Int) { a } f(\"a\")",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            ("fn f(a, b) { a } f(1)", "`f` expects 2 arguments, found 1"),
+            (
+                "let n = if true { 1 } else if false { 2 } else { \"a\" };",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            (
+                "let n: Int = if true { 1 };",
+                "mismatched types: expected `Int`, found `()`",
+            ),
+            (
+                "if 1 { 2 }",
+                "mismatched types: expected `Bool`, found `Int`",
+            ),
+            (
+                "if true { 1 } else { \"a\" }",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            (
+                "[1, \"a\"]",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            (
+                "fn f(g) { g(1) } f(fn(s: String) { s })",
+                "mismatched types: expected `Fn(Int) -> 'a`, found `Fn(String) -> String`",
+            ),
+            (
+                "#{ \"a\": 1 }[1]",
+                "mismatched types: expected `String`, found `Int`",
+            ),
+            ("\"a\".push(1)", "`String` has no method `push`"),
+            ("for x in 5 {}", "cannot iterate over `Int`"),
+            ("5(1)", "cannot call `Int`"),
+            (
+                "match 1 { \"a\" => 1, _ => 2 }",
+                "mismatched types: expected `Int`, found `String`",
+            ),
+            ("let x: Foo = 1;", "unknown type `Foo`"),
+            (
+                "let x: List = [];",
+                "`List` expects 1 type argument, found 0",
+            ),
+        ];
+        for (source, message) in cases.iter() {
+            assert_eq!(errors(source), vec![message.to_string()], "{}", source);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::lexer::symbol::Symbol;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    Unit,
+    // What `a..b` evaluates to; iterating over it yields `Int`s.
+    Range,
+    Tuple(Vec<Type>),
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    // A struct or enum the program declares, with its type arguments.
+    Named(Symbol, Vec<Type>),
+    // A type parameter, inside the generic function or type that declares
+    // it. It is equal only to itself, so code can't assume anything about
+    // the types it stands for.
+    Param(Symbol),
+    // A type variable, standing for a type inference hasn't pinned down yet.
+    Var(u32),
+    // The type of a value the checker can't see into, such as an imported
+    // name. It unifies with every type, leaving those uses to be checked at
+    // run time.
+    Unknown,
+}
+
+impl Type {
+    pub fn list(element: Type) -> Type {
+        Type::List(Box::new(element))
+    }
+
+    pub fn map(key: Type, value: Type) -> Type {
+        Type::Map(Box::new(key), Box::new(value))
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    // Collects the type variables in `self`, each once, in order of
+    // appearance.
+    pub fn variables(&self, variables: &mut Vec<u32>) {
+        match self {
+            Type::Var(var) if !variables.contains(var) => variables.push(*var),
+            Type::Tuple(elements) => {
+                for element in elements {
+                    element.variables(variables);
+                }
+            }
+            Type::List(element) => element.variables(variables),
+            Type::Map(key, value) => {
+                key.variables(variables);
+                value.variables(variables);
+            }
+            Type::Fn(params, returns) => {
+                for param in params {
+                    param.variables(variables);
+                }
+                returns.variables(variables);
+            }
+            Type::Named(_, args) => {
+                for arg in args {
+                    arg.variables(variables);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Replaces each type variable with `replace(var)`.
+    pub fn map_variables(&self, replace: &mut impl FnMut(u32) -> Type) -> Type {
+        self.rebuild(&mut |ty| match ty {
+            Type::Var(var) => Some(replace(*var)),
+            _ => None,
+        })
+    }
+
+    // Replaces the type parameters `args` gives types for.
+    pub fn substitute(&self, args: &HashMap<Symbol, Type>) -> Type {
+        self.rebuild(&mut |ty| match ty {
+            Type::Param(name) => args.get(name).cloned(),
+            _ => None,
+        })
+    }
+
+    // Rebuilds `self`, replacing the types `replace` gives a replacement for.
+    fn rebuild(&self, replace: &mut impl FnMut(&Type) -> Option<Type>) -> Type {
+        if let Some(ty) = replace(self) {
+            return ty;
+        }
+        match self {
+            Type::Tuple(elements) => Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| element.rebuild(replace))
+                    .collect(),
+            ),
+            Type::List(element) => Type::list(element.rebuild(replace)),
+            Type::Map(key, value) => Type::map(key.rebuild(replace), value.rebuild(replace)),
+            Type::Fn(params, returns) => Type::Fn(
+                params.iter().map(|param| param.rebuild(replace)).collect(),
+                Box::new(returns.rebuild(replace)),
+            ),
+            Type::Named(name, args) => {
+                Type::Named(*name, args.iter().map(|arg| arg.rebuild(replace)).collect())
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+// Renumbers type variables in order of appearance across `types`, so they
+// print as 'a, 'b, ... however many variables inference went through.
+pub fn normalize(types: &[Type]) -> Vec<Type> {
+    let mut variables = Vec::new();
+    for ty in types {
+        ty.variables(&mut variables);
+    }
+    types
+        .iter()
+        .map(|ty| {
+            ty.map_variables(&mut |var| {
+                let index = variables.iter().position(|v| *v == var);
+                Type::Var(index.expect("collected above") as u32)
+            })
+        })
+        .collect()
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Unit => write!(f, "()"),
+            Type::Range => write!(f, "Range"),
+            Type::Tuple(elements) => {
+                write!(f, "(")?;
+                write_list(f, elements)?;
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Type::List(element) => write!(f, "List<{}>", element),
+            Type::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            Type::Fn(params, returns) => {
+                write!(f, "Fn(")?;
+                write_list(f, params)?;
+                write!(f, ") -> {}", returns)
+            }
+            Type::Named(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Named(name, args) => {
+                write!(f, "{}<", name)?;
+                write_list(f, args)?;
+                write!(f, ">")
+            }
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Var(var) => {
+                let letter = (b'a' + (var % 26) as u8) as char;
+                match var / 26 {
+                    0 => write!(f, "'{}", letter),
+                    n => write!(f, "'{}{}", letter, n),
+                }
+            }
+            Type::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter, types: &[Type]) -> fmt::Result {
+    for (i, ty) in types.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", ty)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::symbol::Symbol;
+    use crate::typecheck::types::{normalize, Type};
+
+    #[test]
+    fn displays_types_as_written() {
+        let ty = Type::Fn(
+            vec![Type::map(Type::String, Type::list(Type::Int))],
+            Box::new(Type::Tuple(vec![Type::Bool])),
+        );
+        assert_eq!(ty.to_string(), "Fn(Map<String, List<Int>>) -> (Bool,)");
+        let pair = Type::Named(
+            Symbol::intern("Pair"),
+            vec![Type::Param(Symbol::intern("T")), Type::Int],
+        );
+        assert_eq!(pair.to_string(), "Pair<T, Int>");
+    }
+
+    #[test]
+    fn normalizes_variables_in_order_of_appearance() {
+        let types = [
+            Type::Fn(vec![Type::Var(7)], Box::new(Type::Var(3))),
+            Type::list(Type::Var(7)),
+        ];
+        let names: Vec<String> = normalize(&types).iter().map(Type::to_string).collect();
+        assert_eq!(names, vec!["Fn('a) -> 'b", "List<'a>"]);
+        assert_eq!(Type::Var(27).to_string(), "'b1");
+    }
+}
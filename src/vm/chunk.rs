@@ -0,0 +1,206 @@
+use std::rc::Rc;
+
+use crate::interpreter::value::Value;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::{BinaryOp, Pattern, UnaryOp};
+
+// Operands index into the chunk's tables, the vm's globals or the current
+// frame's stack slots, where slot 0 holds the function being run and its
+// arguments follow. Jump targets are absolute offsets into the code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u32),
+    Unit,
+    Pop,
+    PopN(u32),
+    // Drops the given number of values from under the top of the stack,
+    // keeping the top: how a block gets rid of its locals.
+    PopUnder(u32),
+    GetLocal(u32),
+    SetLocal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetGlobal(u32),
+    // Assigns to a global, defining it if it is unbound.
+    SetGlobal(u32),
+    DefineGlobal {
+        index: u32,
+        mutable: bool,
+    },
+    // Moves every captured slot from the given one up off the stack.
+    CloseUpvalues(u32),
+    Tuple(u32),
+    List(u32),
+    Map(u32),
+    // Pops `fields` field name and value pairs, then the struct type called
+    // `name`, and pushes the instance.
+    Struct {
+        name: u32,
+        fields: u32,
+    },
+    // Replaces the enum type `ty` on top of the stack with its variant
+    // `name`, as in `ty::name`.
+    Variant {
+        ty: u32,
+        name: u32,
+    },
+    // Replaces a struct on top of the stack with its field of the given name.
+    GetField(u32),
+    // Pops a struct and a value, assigns the value to the named field and
+    // pushes it back.
+    SetField(u32),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    // Fails unless the top of the stack is a bool.
+    CheckBool,
+    Index,
+    Slice {
+        start: bool,
+        end: bool,
+    },
+    // Pops the end and start of a range and pushes the range.
+    Range {
+        inclusive: bool,
+    },
+    Jump(u32),
+    // Pops a bool and jumps if it is false.
+    JumpIfFalse(u32),
+    Call(u32),
+    // A call whose result the running function returns. A clay function
+    // called this way takes over the caller's frame instead of pushing one,
+    // so recursion in tail position runs in constant space; anything else is
+    // called as by `Call`, and the `Return` after it returns the result.
+    TailCall(u32),
+    Method {
+        name: u32,
+        args: u32,
+        fallback: Fallback,
+    },
+    Closure(u32),
+    // Pops a closure and gives the struct type under it the method of the
+    // given name, leaving the type on the stack.
+    DefineMethod(u32),
+    Return,
+    // Pops a value and pushes what the pattern binds, or jumps if it doesn't
+    // match.
+    Match {
+        pattern: u32,
+        fail: u32,
+    },
+    NoMatch,
+    // Fails like `NoMatch`, for the match a `?` is lowered to.
+    NotTry,
+    // Replaces the top of the stack with a list of the items a `for` loop
+    // visits.
+    Iterate,
+    // Pushes the next item of the list in the given slot, counting in the
+    // slot after it, or jumps once the list is exhausted.
+    Next {
+        slot: u32,
+        done: u32,
+    },
+    // Pops a loop item and pushes what the loop pattern binds.
+    Bind(u32),
+    // Pops the value of a destructuring `let` and pushes what its pattern
+    // binds.
+    Destructure(u32),
+}
+
+impl Op {
+    // The name of the instruction, without its operands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Constant(..) => "Constant",
+            Op::Unit => "Unit",
+            Op::Pop => "Pop",
+            Op::PopN(..) => "PopN",
+            Op::PopUnder(..) => "PopUnder",
+            Op::GetLocal(..) => "GetLocal",
+            Op::SetLocal(..) => "SetLocal",
+            Op::GetUpvalue(..) => "GetUpvalue",
+            Op::SetUpvalue(..) => "SetUpvalue",
+            Op::GetGlobal(..) => "GetGlobal",
+            Op::SetGlobal(..) => "SetGlobal",
+            Op::DefineGlobal { .. } => "DefineGlobal",
+            Op::CloseUpvalues(..) => "CloseUpvalues",
+            Op::Tuple(..) => "Tuple",
+            Op::List(..) => "List",
+            Op::Map(..) => "Map",
+            Op::Struct { .. } => "Struct",
+            Op::Variant { .. } => "Variant",
+            Op::GetField(..) => "GetField",
+            Op::SetField(..) => "SetField",
+            Op::Unary(..) => "Unary",
+            Op::Binary(..) => "Binary",
+            Op::CheckBool => "CheckBool",
+            Op::Index => "Index",
+            Op::Slice { .. } => "Slice",
+            Op::Range { .. } => "Range",
+            Op::Jump(..) => "Jump",
+            Op::JumpIfFalse(..) => "JumpIfFalse",
+            Op::Call(..) => "Call",
+            Op::TailCall(..) => "TailCall",
+            Op::Method { .. } => "Method",
+            Op::Closure(..) => "Closure",
+            Op::DefineMethod(..) => "DefineMethod",
+            Op::Return => "Return",
+            Op::Match { .. } => "Match",
+            Op::NoMatch => "NoMatch",
+            Op::NotTry => "NotTry",
+            Op::Iterate => "Iterate",
+            Op::Next { .. } => "Next",
+            Op::Bind(..) => "Bind",
+            Op::Destructure(..) => "Destructure",
+        }
+    }
+}
+
+// Where `Op::Method` finds the function it calls with the receiver when the
+// receiver has no method of that name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    // The global of the given index, if it is defined.
+    Global(u32),
+    // The value under the receiver, pushed because the name is a variable
+    // of the function being compiled.
+    Stack,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    // The source each instruction was compiled from, for errors.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    // Method names.
+    pub names: Vec<Symbol>,
+    pub functions: Vec<Rc<Prototype>>,
+    pub patterns: Vec<Pattern>,
+}
+
+// Where a closure finds a variable it captures when it is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    // A slot of the function creating the closure.
+    Local(u32),
+    // One of the variables that function captured itself.
+    Upvalue(u32),
+}
+
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: Option<Symbol>,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+impl Prototype {
+    pub fn name(&self) -> &str {
+        match self.name {
+            Some(name) => name.as_str(),
+            None => "<anonymous>",
+        }
+    }
+}
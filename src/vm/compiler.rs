@@ -0,0 +1,1027 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::value::{EnumType, StructType, Value};
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::{Position, Span};
+use crate::parser::ast::{
+    BinaryOp, Block, Expr, ExprKind, Function, MatchArm, Pattern, PatternKind, Program, Stmt,
+    StmtKind,
+};
+use crate::parser::parser::is_try;
+use crate::vm::chunk::{Capture, Chunk, Fallback, Op, Prototype};
+
+struct Local {
+    name: Symbol,
+    slot: u32,
+    mutable: bool,
+    depth: usize,
+    // Whether a closure refers to the local, which then has to be moved
+    // off the stack when it goes out of scope.
+    captured: bool,
+}
+
+struct Loop {
+    // Where `continue` jumps to.
+    start: u32,
+    // The stack height inside the loop, which `break` and `continue` pop
+    // back down to.
+    height: u32,
+    breaks: Vec<usize>,
+}
+
+// The function being compiled. `height` tracks how many values its frame
+// holds at the current instruction, so every local knows its slot.
+struct State {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<(Capture, bool)>,
+    depth: usize,
+    height: u32,
+    loops: Vec<Loop>,
+    // Set just before compiling an expression whose value the function
+    // returns, where a call can reuse the function's frame.
+    tail: bool,
+}
+
+impl State {
+    fn new(depth: usize) -> State {
+        State {
+            chunk: Chunk::default(),
+            locals: Vec::new(),
+            captures: Vec::new(),
+            depth,
+            // Slot 0 holds the function itself.
+            height: 1,
+            loops: Vec::new(),
+            tail: false,
+        }
+    }
+}
+
+// Where a name refers to, and whether it may be assigned.
+enum Variable {
+    Local(u32, bool),
+    Upvalue(u32, bool),
+    Global,
+}
+
+// Compiles a program into the function the vm runs as `<main>`. Top-level
+// names become globals, numbered by their index in `globals`, which grows to
+// hold any new ones. Everything declared in a block or function lives in a
+// stack slot, and functions capture the slots they use from enclosing
+// functions.
+pub fn compile(program: &Program, globals: &mut Vec<Symbol>) -> Result<Rc<Prototype>, Diagnostic> {
+    let mut compiler = Compiler {
+        functions: vec![State::new(0)],
+        globals,
+    };
+
+    let start = Position::new(1, 0, 0);
+    let span = program
+        .statements
+        .last()
+        .map_or(Span::new(start, start), |stmt| stmt.span);
+    // Like the interpreter, a program evaluates to its last statement.
+    match program.statements.split_last() {
+        Some((last, rest)) => {
+            for stmt in rest {
+                compiler.statement(stmt)?;
+            }
+            match &last.kind {
+                StmtKind::Expr(expr) => compiler.expression(expr)?,
+                _ => {
+                    compiler.statement(last)?;
+                    compiler.emit(Op::Unit, span);
+                }
+            }
+        }
+        None => {
+            compiler.emit(Op::Unit, span);
+        }
+    }
+    compiler.emit(Op::Return, span);
+
+    let state = compiler.functions.pop().expect("the main function");
+    Ok(Rc::new(Prototype {
+        name: Some(Symbol::intern("<main>")),
+        arity: 0,
+        chunk: state.chunk,
+        captures: Vec::new(),
+    }))
+}
+
+struct Compiler<'a> {
+    functions: Vec<State>,
+    globals: &'a mut Vec<Symbol>,
+}
+
+impl<'a> Compiler<'a> {
+    fn state(&mut self) -> &mut State {
+        self.functions
+            .last_mut()
+            .expect("a function being compiled")
+    }
+
+    // Appends an instruction and returns its offset.
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let state = self.state();
+        state.height = (i64::from(state.height) + effect(op)) as u32;
+        state.chunk.code.push(op);
+        state.chunk.spans.push(span);
+        state.chunk.code.len() - 1
+    }
+
+    fn offset(&mut self) -> u32 {
+        self.state().chunk.code.len() as u32
+    }
+
+    // Points the jump at `index` to the next instruction.
+    fn patch(&mut self, index: usize) {
+        let target = self.offset();
+        match &mut self.state().chunk.code[index] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            Op::Match { fail: to, .. } | Op::Next { done: to, .. } => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let constants = &mut self.state().chunk.constants;
+        constants.push(value);
+        let index = constants.len() as u32 - 1;
+        self.emit(Op::Constant(index), span);
+    }
+
+    fn name(&mut self, name: Symbol) -> u32 {
+        let names = &mut self.state().chunk.names;
+        match names.iter().position(|&n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                names.push(name);
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    fn global(&mut self, name: Symbol) -> u32 {
+        match self.globals.iter().position(|&n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                self.globals.push(name);
+                self.globals.len() as u32 - 1
+            }
+        }
+    }
+
+    fn is_global(&self) -> bool {
+        self.functions.len() == 1 && self.functions[0].depth == 0
+    }
+
+    // Declares a local in the slot at the top of the stack, or the one about
+    // to be pushed when `pending` is set.
+    fn declare(&mut self, name: Symbol, mutable: bool, pending: bool) {
+        let state = self.state();
+        let slot = if pending {
+            state.height
+        } else {
+            state.height - 1
+        };
+        state.locals.push(Local {
+            name,
+            slot,
+            mutable,
+            depth: state.depth,
+            captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().depth += 1;
+    }
+
+    // Drops the locals of the innermost scope, keeping the value on top of
+    // them when `keep` is set.
+    fn end_scope(&mut self, keep: bool, span: Span) {
+        let state = self.state();
+        state.depth -= 1;
+        let depth = state.depth;
+        let first = state.locals.iter().position(|local| local.depth > depth);
+        let first = match first {
+            Some(first) => first,
+            None => return,
+        };
+        let slot = state.locals[first].slot;
+        let count = (state.locals.len() - first) as u32;
+        let captured = state.locals[first..].iter().any(|local| local.captured);
+        state.locals.truncate(first);
+
+        if captured {
+            self.emit(Op::CloseUpvalues(slot), span);
+        }
+        match keep {
+            true => self.emit(Op::PopUnder(count), span),
+            false => self.emit(Op::PopN(count), span),
+        };
+    }
+
+    fn resolve(&mut self, name: Symbol) -> Variable {
+        let function = self.functions.len() - 1;
+        if let Some((slot, mutable)) = self.resolve_local(function, name) {
+            return Variable::Local(slot, mutable);
+        }
+        match self.resolve_upvalue(function, name) {
+            Some((index, mutable)) => Variable::Upvalue(index, mutable),
+            None => Variable::Global,
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: Symbol) -> Option<(u32, bool)> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| (local.slot, local.mutable))
+    }
+
+    fn capture_local(&mut self, function: usize, name: Symbol) -> Option<(u32, bool)> {
+        let local = self.functions[function]
+            .locals
+            .iter_mut()
+            .rev()
+            .find(|local| local.name == name)?;
+        local.captured = true;
+        Some((local.slot, local.mutable))
+    }
+
+    // Finds `name` in an enclosing function, threading the capture through
+    // every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: Symbol) -> Option<(u32, bool)> {
+        if function == 0 {
+            return None;
+        }
+        let (capture, mutable) = match self.capture_local(function - 1, name) {
+            Some((slot, mutable)) => (Capture::Local(slot), mutable),
+            None => {
+                let (index, mutable) = self.resolve_upvalue(function - 1, name)?;
+                (Capture::Upvalue(index), mutable)
+            }
+        };
+
+        let captures = &mut self.functions[function].captures;
+        let index = match captures.iter().position(|(c, _)| *c == capture) {
+            Some(index) => index,
+            None => {
+                captures.push((capture, mutable));
+                captures.len() - 1
+            }
+        };
+        Some((index as u32, mutable))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expression(expr)?;
+                self.emit(Op::Pop, stmt.span);
+            }
+            StmtKind::Function(function) => {
+                let name = function.name.unwrap_or_default();
+                self.binding(name, true, true, stmt.span, |compiler| {
+                    compiler.function(function)
+                })?;
+            }
+            StmtKind::Let {
+                name,
+                mutable,
+                value,
+                ..
+            } => {
+                let recursive = matches!(value.kind, ExprKind::Function(_));
+                self.binding(*name, *mutable, recursive, stmt.span, |compiler| {
+                    compiler.expression(value)
+                })?;
+            }
+            StmtKind::Destructure {
+                pattern,
+                mutable,
+                value,
+                ..
+            } => {
+                self.expression(value)?;
+                let index = self.pattern(pattern);
+                self.emit(Op::Destructure(index), pattern.span);
+                let mut names = Vec::new();
+                bindings(pattern, &mut names);
+                if self.is_global() {
+                    // The last binding is on top of the stack.
+                    for name in names.iter().rev() {
+                        self.state().height += 1;
+                        let index = self.global(*name);
+                        let op = Op::DefineGlobal {
+                            index,
+                            mutable: *mutable,
+                        };
+                        self.emit(op, stmt.span);
+                    }
+                } else {
+                    for name in &names {
+                        self.state().height += 1;
+                        self.declare(*name, *mutable, false);
+                    }
+                }
+            }
+            StmtKind::Import(_) => {
+                return Err(Diagnostic::error(
+                    "the vm backend does not support imports yet",
+                    stmt.span,
+                ))
+            }
+            StmtKind::Struct(decl) => {
+                let ty = StructType {
+                    name: decl.name,
+                    fields: decl.fields.iter().map(|field| field.name).collect(),
+                    methods: RefCell::default(),
+                };
+                self.binding(decl.name, false, false, stmt.span, |compiler| {
+                    compiler.constant(Value::StructType(Rc::new(ty)), stmt.span);
+                    Ok(())
+                })?;
+            }
+            StmtKind::Enum(decl) => {
+                let ty = EnumType {
+                    name: decl.name,
+                    variants: decl
+                        .variants
+                        .iter()
+                        .map(|variant| (variant.name, variant.fields.len()))
+                        .collect(),
+                };
+                self.binding(decl.name, false, false, stmt.span, |compiler| {
+                    compiler.constant(Value::EnumType(Rc::new(ty)), stmt.span);
+                    Ok(())
+                })?;
+            }
+            // Traits only matter to the type checker.
+            StmtKind::Trait(_) => {}
+            StmtKind::Impl(decl) => {
+                self.expression(&Expr {
+                    kind: ExprKind::Ident(decl.target),
+                    span: stmt.span,
+                })?;
+                for method in &decl.methods {
+                    self.function(method)?;
+                    let name = self.name(method.name.unwrap_or_default());
+                    self.emit(Op::DefineMethod(name), method.span);
+                }
+                self.emit(Op::Pop, stmt.span);
+            }
+            // Tests only run under `clay test`.
+            StmtKind::Test { .. } => {}
+        }
+        Ok(())
+    }
+
+    // Binds `name` to the value `value` compiles. A function value can see
+    // its own name so it can call itself, as it can in the interpreter.
+    fn binding(
+        &mut self,
+        name: Symbol,
+        mutable: bool,
+        recursive: bool,
+        span: Span,
+        value: impl FnOnce(&mut Self) -> Result<(), Diagnostic>,
+    ) -> Result<(), Diagnostic> {
+        if self.is_global() {
+            value(self)?;
+            let index = self.global(name);
+            self.emit(Op::DefineGlobal { index, mutable }, span);
+        } else if recursive {
+            self.declare(name, mutable, true);
+            value(self)?;
+        } else {
+            value(self)?;
+            self.declare(name, mutable, false);
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        let span = expr.span;
+        let tail = std::mem::take(&mut self.state().tail);
+        match &expr.kind {
+            ExprKind::Integer(n) => self.constant(Value::Integer(*n), span),
+            ExprKind::Float(n) => self.constant(Value::Float(*n), span),
+            ExprKind::String(s) => self.constant(Value::String(s.clone()), span),
+            ExprKind::Bool(b) => self.constant(Value::Bool(*b), span),
+            ExprKind::Ident(name) => {
+                let op = match self.resolve(*name) {
+                    Variable::Local(slot, _) => Op::GetLocal(slot),
+                    Variable::Upvalue(index, _) => Op::GetUpvalue(index),
+                    Variable::Global => Op::GetGlobal(self.global(*name)),
+                };
+                self.emit(op, span);
+            }
+            // Only `Enum::Variant`: modules can't be imported yet.
+            ExprKind::Path(segments) if segments.len() == 2 => {
+                self.expression(&Expr {
+                    kind: ExprKind::Ident(segments[0]),
+                    span,
+                })?;
+                let ty = self.name(segments[0]);
+                let name = self.name(segments[1]);
+                self.emit(Op::Variant { ty, name }, span);
+            }
+            ExprKind::Path(_) => {
+                return Err(Diagnostic::error(
+                    "the vm backend does not support modules yet",
+                    span,
+                ))
+            }
+            ExprKind::Tuple(elements) => {
+                self.expressions(elements)?;
+                self.emit(Op::Tuple(elements.len() as u32), span);
+            }
+            ExprKind::List(elements) => {
+                self.expressions(elements)?;
+                self.emit(Op::List(elements.len() as u32), span);
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.emit(Op::Map(entries.len() as u32), span);
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let fallback = match self.resolve(*method) {
+                    Variable::Local(slot, _) => {
+                        self.emit(Op::GetLocal(slot), span);
+                        Fallback::Stack
+                    }
+                    Variable::Upvalue(index, _) => {
+                        self.emit(Op::GetUpvalue(index), span);
+                        Fallback::Stack
+                    }
+                    Variable::Global => Fallback::Global(self.global(*method)),
+                };
+                self.expression(receiver)?;
+                self.expressions(args)?;
+                let name = self.name(*method);
+                let args = args.len() as u32;
+                self.emit(
+                    Op::Method {
+                        name,
+                        args,
+                        fallback,
+                    },
+                    span,
+                );
+            }
+            ExprKind::Struct { path, fields } => {
+                let ty = match path.as_slice() {
+                    [ty] => ty,
+                    _ => {
+                        return Err(Diagnostic::error(
+                            "the vm backend does not support modules yet",
+                            span,
+                        ))
+                    }
+                };
+                self.expression(&Expr {
+                    kind: ExprKind::Ident(*ty),
+                    span,
+                })?;
+                for (name, value) in fields {
+                    self.constant(Value::String(name.to_string()), value.span);
+                    self.expression(value)?;
+                }
+                let name = self.name(*ty);
+                let count = fields.len() as u32;
+                self.emit(
+                    Op::Struct {
+                        name,
+                        fields: count,
+                    },
+                    span,
+                );
+            }
+            ExprKind::Field { target, name } => {
+                self.expression(target)?;
+                let name = self.name(*name);
+                self.emit(Op::GetField(name), span);
+            }
+            ExprKind::Index { target, index } => {
+                self.expression(target)?;
+                self.expression(index)?;
+                self.emit(Op::Index, span);
+            }
+            ExprKind::Slice { target, start, end } => {
+                self.expression(target)?;
+                if let Some(start) = start {
+                    self.expression(start)?;
+                }
+                if let Some(end) = end {
+                    self.expression(end)?;
+                }
+                let (start, end) = (start.is_some(), end.is_some());
+                self.emit(Op::Slice { start, end }, span);
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                let inclusive = *inclusive;
+                self.emit(Op::Range { inclusive }, span);
+            }
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand)?;
+                self.emit(Op::Unary(*op), span);
+            }
+            ExprKind::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                self.expression(left)?;
+                let short = self.emit(Op::JumpIfFalse(0), left.span);
+                self.expression(right)?;
+                self.emit(Op::CheckBool, right.span);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(short);
+                self.state().height -= 1;
+                self.constant(Value::Bool(false), span);
+                self.patch(end);
+            }
+            ExprKind::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => {
+                self.expression(left)?;
+                let long = self.emit(Op::JumpIfFalse(0), left.span);
+                self.constant(Value::Bool(true), span);
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(long);
+                self.state().height -= 1;
+                self.expression(right)?;
+                self.emit(Op::CheckBool, right.span);
+                self.patch(end);
+            }
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(Op::Binary(*op), span);
+            }
+            ExprKind::Assign { target, value } => {
+                let name = match &target.kind {
+                    ExprKind::Ident(name) => name,
+                    ExprKind::Field { target, name } => {
+                        self.expression(value)?;
+                        self.expression(target)?;
+                        let name = self.name(*name);
+                        self.emit(Op::SetField(name), span);
+                        return Ok(());
+                    }
+                    _ => return Err(Diagnostic::error("invalid assignment target", target.span)),
+                };
+                self.expression(value)?;
+                let op = match self.resolve(*name) {
+                    Variable::Local(_, false) | Variable::Upvalue(_, false) => {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "cannot assign to immutable variable `{}`, consider declaring it with `let mut`",
+                                name
+                            ),
+                            target.span,
+                        ))
+                    }
+                    Variable::Local(slot, true) => Op::SetLocal(slot),
+                    Variable::Upvalue(index, true) => Op::SetUpvalue(index),
+                    // Assigning to a name no scope declares defines it, but
+                    // as a global: the vm can't add slots to a frame at run
+                    // time.
+                    Variable::Global => Op::SetGlobal(self.global(*name)),
+                };
+                self.emit(op, target.span);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.match_expression(scrutinee, arms, tail, span)?
+            }
+            ExprKind::Block(block) => {
+                self.state().tail = tail;
+                self.block(block)?
+            }
+            ExprKind::Function(function) => self.function(function)?,
+            ExprKind::Call { callee, args } => {
+                self.expression(callee)?;
+                self.expressions(args)?;
+                let args = args.len() as u32;
+                self.emit(
+                    if tail {
+                        Op::TailCall(args)
+                    } else {
+                        Op::Call(args)
+                    },
+                    span,
+                );
+            }
+            ExprKind::Return(value) => {
+                if self.functions.len() == 1 {
+                    return Err(Diagnostic::error("`return` outside of a function", span));
+                }
+                match value {
+                    Some(value) => {
+                        self.state().tail = true;
+                        self.expression(value)?
+                    }
+                    None => {
+                        self.emit(Op::Unit, span);
+                    }
+                }
+                self.emit(Op::Return, span);
+                // Code after the return is unreachable but still expects the
+                // expression to have left a value.
+                self.state().height += 1;
+            }
+            ExprKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let otherwise = self.emit(Op::JumpIfFalse(0), condition.span);
+                self.state().tail = tail;
+                self.block(then_branch)?;
+                let end = self.emit(Op::Jump(0), span);
+                self.patch(otherwise);
+                self.state().height -= 1;
+                match else_branch {
+                    Some(else_branch) => {
+                        self.state().tail = tail;
+                        self.expression(else_branch)?
+                    }
+                    None => {
+                        self.emit(Op::Unit, span);
+                    }
+                }
+                self.patch(end);
+            }
+            ExprKind::While { condition, body } => {
+                let start = self.offset();
+                self.expression(condition)?;
+                let done = self.emit(Op::JumpIfFalse(0), condition.span);
+                let height = self.state().height;
+                self.body(start, height, body)?;
+                self.emit(Op::Jump(start), span);
+                self.patch(done);
+                self.end_loop();
+                self.emit(Op::Unit, span);
+            }
+            ExprKind::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable)?;
+                self.emit(Op::Iterate, iterable.span);
+                let slot = self.state().height - 1;
+                self.constant(Value::Integer(0), span);
+
+                let start = self.offset();
+                let done = self.emit(Op::Next { slot, done: 0 }, span);
+                let index = self.pattern(pattern);
+                self.emit(Op::Bind(index), pattern.span);
+                self.begin_scope();
+                self.declare_pattern(pattern);
+                self.body(start, slot + 2, body)?;
+                self.end_scope(false, body.span);
+                self.emit(Op::Jump(start), span);
+                self.patch(done);
+                self.end_loop();
+                self.emit(Op::PopN(2), span);
+                self.emit(Op::Unit, span);
+            }
+            ExprKind::Break | ExprKind::Continue => {
+                let name = match expr.kind {
+                    ExprKind::Break => "break",
+                    _ => "continue",
+                };
+                let state = self.state();
+                let (start, height) = match state.loops.last() {
+                    Some(innermost) => (innermost.start, innermost.height),
+                    None => {
+                        return Err(Diagnostic::error(
+                            format!("`{}` outside of a loop", name),
+                            span,
+                        ))
+                    }
+                };
+                let current = state.height;
+                let locals = state.locals.iter().any(|local| local.slot >= height);
+                if locals {
+                    self.emit(Op::CloseUpvalues(height), span);
+                }
+                if current > height {
+                    self.emit(Op::PopN(current - height), span);
+                }
+                match expr.kind {
+                    ExprKind::Break => {
+                        let jump = self.emit(Op::Jump(0), span);
+                        let innermost = self.state().loops.last_mut().expect("checked above");
+                        innermost.breaks.push(jump);
+                    }
+                    _ => {
+                        self.emit(Op::Jump(start), span);
+                    }
+                }
+                self.state().height = current + 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn expressions(&mut self, exprs: &[Expr]) -> Result<(), Diagnostic> {
+        for expr in exprs {
+            self.expression(expr)?;
+        }
+        Ok(())
+    }
+
+    // Compiles a block, whose value is in tail position if the block is.
+    fn block(&mut self, block: &Block) -> Result<(), Diagnostic> {
+        let tail = std::mem::take(&mut self.state().tail);
+        self.begin_scope();
+        for stmt in &block.statements {
+            self.statement(stmt)?;
+        }
+        match &block.value {
+            Some(value) => {
+                self.state().tail = tail;
+                self.expression(value)?
+            }
+            None => {
+                self.emit(Op::Unit, block.span);
+            }
+        }
+        self.end_scope(true, block.span);
+        Ok(())
+    }
+
+    // Compiles a loop body whose `continue` jumps to `start` after popping
+    // the stack down to `height`. The loop's breaks are patched by
+    // `end_loop`.
+    fn body(&mut self, start: u32, height: u32, body: &Block) -> Result<(), Diagnostic> {
+        self.state().loops.push(Loop {
+            start,
+            height,
+            breaks: Vec::new(),
+        });
+        self.block(body)?;
+        self.emit(Op::Pop, body.span);
+        Ok(())
+    }
+
+    fn end_loop(&mut self) {
+        let innermost = self.state().loops.pop().expect("a loop being compiled");
+        for jump in innermost.breaks {
+            self.patch(jump);
+        }
+        self.state().height = innermost.height;
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), Diagnostic> {
+        let mut state = State::new(1);
+        for param in &function.params {
+            state.locals.push(Local {
+                name: param.name,
+                slot: state.height,
+                mutable: true,
+                depth: 1,
+                captured: false,
+            });
+            state.height += 1;
+        }
+        state.tail = true;
+        self.functions.push(state);
+
+        self.block(&function.body)?;
+        self.emit(Op::Return, function.body.span);
+        let state = self.functions.pop().expect("pushed above");
+
+        let prototype = Prototype {
+            name: function.name,
+            arity: function.params.len(),
+            chunk: state.chunk,
+            captures: state.captures.into_iter().map(|(c, _)| c).collect(),
+        };
+        let functions = &mut self.state().chunk.functions;
+        functions.push(Rc::new(prototype));
+        let index = functions.len() as u32 - 1;
+        self.emit(Op::Closure(index), function.span);
+        Ok(())
+    }
+
+    // Tries each arm in turn against the scrutinee, which stays on the stack
+    // under the arm's bindings until the match is done.
+    fn match_expression(
+        &mut self,
+        scrutinee: &Expr,
+        arms: &[MatchArm],
+        tail: bool,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        self.expression(scrutinee)?;
+        let slot = self.state().height - 1;
+
+        let mut ends = Vec::new();
+        for arm in arms {
+            self.emit(Op::GetLocal(slot), arm.pattern.span);
+            let pattern = self.pattern(&arm.pattern);
+            let fail = self.emit(Op::Match { pattern, fail: 0 }, arm.pattern.span);
+            self.begin_scope();
+            let count = self.declare_pattern(&arm.pattern);
+
+            let guard = match &arm.guard {
+                Some(guard) => {
+                    self.expression(guard)?;
+                    Some(self.emit(Op::JumpIfFalse(0), guard.span))
+                }
+                None => None,
+            };
+            self.state().tail = tail;
+            self.expression(&arm.body)?;
+            self.end_scope(true, arm.span);
+            ends.push(self.emit(Op::Jump(0), arm.span));
+
+            if let Some(guard) = guard {
+                self.patch(guard);
+                self.state().height = slot + 1 + count;
+                if count > 0 {
+                    self.emit(Op::CloseUpvalues(slot + 1), arm.span);
+                    self.emit(Op::PopN(count), arm.span);
+                }
+            }
+            self.patch(fail);
+            self.state().height = slot + 1;
+        }
+
+        match is_try(arms) {
+            true => self.emit(Op::NotTry, span),
+            false => self.emit(Op::NoMatch, span),
+        };
+        for end in ends {
+            self.patch(end);
+        }
+        self.state().height = slot + 2;
+        self.emit(Op::PopUnder(1), span);
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> u32 {
+        let patterns = &mut self.state().chunk.patterns;
+        patterns.push(pattern.clone());
+        patterns.len() as u32 - 1
+    }
+
+    // Declares the names a pattern binds, which `Match` and `Bind` push in
+    // the same order. Returns how many there are.
+    fn declare_pattern(&mut self, pattern: &Pattern) -> u32 {
+        let mut names = Vec::new();
+        bindings(pattern, &mut names);
+        for name in &names {
+            self.state().height += 1;
+            self.declare(*name, true, false);
+        }
+        u32::try_from(names.len()).expect("patterns bind few names")
+    }
+}
+
+fn bindings(pattern: &Pattern, names: &mut Vec<Symbol>) {
+    match &pattern.kind {
+        PatternKind::Binding(name) => names.push(*name),
+        PatternKind::Tuple(patterns)
+        | PatternKind::Variant {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                bindings(pattern, names);
+            }
+        }
+        PatternKind::Struct { fields, .. } => {
+            for (_, pattern) in fields {
+                bindings(pattern, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+// How an instruction changes the height of the stack, not counting the
+// values `Match` and `Bind` push for a pattern's bindings.
+fn effect(op: Op) -> i64 {
+    let count = |n: u32| i64::from(n);
+    match op {
+        Op::Constant(_)
+        | Op::Unit
+        | Op::GetLocal(_)
+        | Op::GetUpvalue(_)
+        | Op::GetGlobal(_)
+        | Op::Closure(_)
+        | Op::Next { .. } => 1,
+        Op::Pop
+        | Op::DefineGlobal { .. }
+        | Op::Binary(_)
+        | Op::Index
+        | Op::Range { .. }
+        | Op::JumpIfFalse(_) => -1,
+        Op::Return | Op::Match { .. } | Op::Bind(_) | Op::Destructure(_) => -1,
+        Op::PopN(n) | Op::PopUnder(n) | Op::Call(n) | Op::TailCall(n) => -count(n),
+        Op::Method {
+            args,
+            fallback: Fallback::Global(_),
+            ..
+        } => -count(args),
+        Op::Method { args, .. } => -count(args) - 1,
+        Op::Tuple(n) | Op::List(n) => 1 - count(n),
+        Op::Map(n) => 1 - 2 * count(n),
+        Op::Struct { fields, .. } => -2 * count(fields),
+        Op::SetField(_) | Op::DefineMethod(_) => -1,
+        Op::Slice { start, end } => -(start as i64) - (end as i64),
+        Op::SetLocal(_)
+        | Op::SetUpvalue(_)
+        | Op::SetGlobal(_)
+        | Op::CloseUpvalues(_)
+        | Op::Unary(_)
+        | Op::GetField(_)
+        | Op::Variant { .. }
+        | Op::CheckBool
+        | Op::Jump(_)
+        | Op::NoMatch
+        | Op::NotTry
+        | Op::Iterate => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ast::BinaryOp;
+    use crate::parser::parser::parse;
+    use crate::vm::chunk::Op;
+    use crate::vm::compiler::compile;
+
+    fn code(source: &str) -> Vec<Op> {
+        let mut globals = Vec::new();
+        let prototype = compile(&parse(source).unwrap(), &mut globals).unwrap();
+        prototype.chunk.code.clone()
+    }
+
+    #[test]
+    fn patches_jumps_and_assigns_slots() {
+        assert_eq!(
+            code("if x { 1 } else { 2 }"),
+            vec![
+                Op::GetGlobal(0),
+                Op::JumpIfFalse(4),
+                Op::Constant(0),
+                Op::Jump(5),
+                Op::Constant(1),
+                Op::Return,
+            ]
+        );
+        assert_eq!(
+            code("{ let a = 1; let b = a; a + b }"),
+            vec![
+                Op::Constant(0),
+                Op::GetLocal(1),
+                Op::GetLocal(1),
+                Op::GetLocal(2),
+                Op::Binary(BinaryOp::Add),
+                Op::PopUnder(2),
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_what_the_vm_cannot_run() {
+        let mut globals = Vec::new();
+        let err = compile(&parse("import std::math").unwrap(), &mut globals).unwrap_err();
+        assert_eq!(err.message, "the vm backend does not support imports yet");
+        let err = compile(
+            &parse("while true { fn() { break } }").unwrap(),
+            &mut globals,
+        );
+        assert_eq!(err.unwrap_err().message, "`break` outside of a loop");
+    }
+}
@@ -0,0 +1,1108 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::interpreter::environment::Environment;
+use crate::interpreter::heap::{self, HeapSnapshot};
+use crate::interpreter::interpreter::{
+    binary, call_method, construct, construct_variant, define_method, enum_variant, get_field,
+    index_value, iterate, map_key, match_pattern, range, set_field, slice_value, unary, unknown,
+};
+use crate::interpreter::io::Io;
+use crate::interpreter::profile::{Profile, ROOT};
+use crate::interpreter::stdlib::{self, Builtins};
+use crate::interpreter::system::System;
+use crate::interpreter::value::Value;
+use crate::lexer::symbol::Symbol;
+use crate::lexer::token::Span;
+use crate::parser::ast::Program;
+use crate::parser::parser::not_try;
+use crate::vm::chunk::{Capture, Fallback, Op, Prototype};
+use crate::vm::compiler::compile;
+
+// Deep enough for any reasonable recursion, shallow enough to report a
+// runaway one before it exhausts memory.
+const MAX_FRAMES: usize = 100_000;
+
+pub struct Closure {
+    pub prototype: Rc<Prototype>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.prototype.name())
+    }
+}
+
+// A variable a closure captured. It stays in its stack slot while the
+// function that declared it is running, so both see every assignment, and
+// moves into the upvalue when that slot goes away.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // The stack index of slot 0.
+    base: usize,
+}
+
+impl Frame {
+    // The span of the instruction being run.
+    fn span(&self) -> Span {
+        self.closure.prototype.chunk.spans[self.ip - 1]
+    }
+}
+
+struct Global {
+    value: Value,
+    mutable: bool,
+}
+
+// Runs programs compiled to bytecode. Globals persist between runs, like the
+// interpreter's top-level environment.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // The names the compiler numbered globals by, and their values once
+    // they are defined.
+    names: Vec<Symbol>,
+    globals: Vec<Option<Global>>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // What a global that was never defined falls back to.
+    builtins: Builtins,
+    // The code the program called `exit` with, once it has.
+    exit_code: Option<i32>,
+    // Recorded while the host asks for it.
+    profile: Option<Profile>,
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm::default()
+    }
+
+    // Makes `value` callable as `name`, unless a program defines `name`.
+    pub fn register_builtin(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.builtins.register(name, value);
+    }
+
+    // Sends the I/O functions of the standard library through `io`.
+    pub fn set_io(&mut self, io: Rc<RefCell<dyn Io>>) {
+        self.builtins.set_io(io);
+    }
+
+    // Makes `env` describe `system` rather than the machine clay runs on.
+    pub fn set_system(&mut self, system: Rc<dyn System>) {
+        self.builtins.set_system(system);
+    }
+
+    // Starts recording how often each function is called, how long its calls
+    // take and how often each instruction runs, until `take_profile`.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    // The profile recorded since `start_profile`.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        let mut profile = self.profile.take()?;
+        profile.finish();
+        Some(profile)
+    }
+
+    // Sets the arguments the `args` builtin returns.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.builtins.set_args(args);
+    }
+
+    // The code the program asked to exit with, by calling `exit` or by
+    // returning it from `main` in `run_script`.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<Value, Diagnostic> {
+        let closure = Closure {
+            prototype: compile(program, &mut self.names)
+                .map_err(|diagnostic| diagnostic.or_code("runtime-error"))?,
+            upvalues: Vec::new(),
+        };
+        self.globals.resize_with(self.names.len(), || None);
+        let span = closure.prototype.chunk.spans[0];
+        self.call_top(Value::Compiled(Rc::new(closure)), span)
+    }
+
+    // Runs `program` like `Interpreter::run_script` does: then its `main`
+    // function, if it declares one, whose integer result is the exit code.
+    pub fn run_script(&mut self, program: &Program) -> Result<Value, Diagnostic> {
+        let value = self.run(program)?;
+        let main = self.names.iter().position(|name| name.as_str() == "main");
+        let main = main.and_then(|index| self.globals[index].as_ref());
+        let (main, span) = match main.map(|global| &global.value) {
+            Some(Value::Compiled(closure)) if self.exit_code.is_none() => {
+                let span = closure.prototype.chunk.spans[0];
+                (Value::Compiled(closure.clone()), span)
+            }
+            _ => return Ok(value),
+        };
+        match self.call_top(main, span)? {
+            Value::Integer(code) if self.exit_code.is_none() => {
+                self.exit_code = Some(stdlib::exit_code(&Value::Integer(code), span)?);
+                Ok(Value::Unit)
+            }
+            value => Ok(value),
+        }
+    }
+
+    // Calls `function` with no arguments from the host, leaving the stack
+    // empty however it returns. `exit` fails the call, but ends the run
+    // without an error.
+    fn call_top(&mut self, function: Value, span: Span) -> Result<Value, Diagnostic> {
+        let result = self.call_value(function, Vec::new(), span);
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        match (result, self.builtins.take_exit()) {
+            (Err(_), Some(code)) => {
+                self.exit_code = Some(code);
+                Ok(Value::Unit)
+            }
+            (result, _) => result.map_err(|diagnostic| diagnostic.or_code("runtime-error")),
+        }
+    }
+
+    pub fn heap_snapshot(&self) -> HeapSnapshot {
+        let mut environment = Environment::new();
+        for (name, global) in self.names.iter().zip(&self.globals) {
+            if let Some(global) = global {
+                environment.declare(*name, global.value.clone(), global.mutable);
+            }
+        }
+        heap::snapshot(&Rc::new(RefCell::new(environment)))
+    }
+
+    // Calls `function` and runs until it returns.
+    fn call_value(
+        &mut self,
+        function: Value,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        if let Value::Native(_) | Value::Constructor(..) = function {
+            return self.run_builtin(function, args, span);
+        }
+        let closure = self.callable(&function, args.len(), span)?;
+        self.enter_call(closure.prototype.name());
+        let prototype = closure.prototype.clone();
+        let base = self.stack.len();
+        self.stack.push(function);
+        self.stack.extend(args);
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+        });
+        // The program itself is what calls are traced from.
+        self.execute().map_err(|diagnostic| match prototype.name() {
+            ROOT => diagnostic,
+            name => diagnostic.called_from(name, span),
+        })
+    }
+
+    // The value of the global of the given index, or the builtin of its
+    // name if the program never defined it.
+    fn global(&self, index: u32) -> Option<Value> {
+        match &self.globals[index as usize] {
+            Some(global) => Some(global.value.clone()),
+            None => self.builtins.get(self.names[index as usize]),
+        }
+    }
+
+    // The globals that have values, and the builtins.
+    fn defined(&self) -> Vec<Symbol> {
+        let names = self.names.iter().zip(&self.globals);
+        names
+            .filter(|(_, global)| global.is_some())
+            .map(|(name, _)| *name)
+            .chain(self.builtins.names())
+            .collect()
+    }
+
+    fn callable(&self, callee: &Value, args: usize, span: Span) -> Result<Rc<Closure>, Diagnostic> {
+        let closure = match callee {
+            Value::Compiled(closure) => closure,
+            other => {
+                return Err(Diagnostic::error(
+                    format!("cannot call a {}", other.type_name()),
+                    span,
+                ))
+            }
+        };
+
+        let arity = closure.prototype.arity;
+        if args != arity {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` expects {} argument{}, found {}",
+                    closure.prototype.name(),
+                    arity,
+                    if arity == 1 { "" } else { "s" },
+                    args
+                ),
+                span,
+            ));
+        }
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(Diagnostic::error("stack overflow", span));
+        }
+        Ok(closure.clone())
+    }
+
+    // Tells the profile, if one is being recorded, that a call started.
+    fn enter_call(&mut self, name: &str) {
+        if let Some(profile) = &mut self.profile {
+            profile.enter(name);
+        }
+    }
+
+    fn exit_call(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            profile.exit();
+        }
+    }
+
+    // Calls a native function, counting its calls in the profile, or an
+    // enum variant's constructor.
+    fn run_builtin(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        let name = match &callee {
+            Value::Native(native) => Some(native.name),
+            _ => None,
+        };
+        if let Some(name) = name {
+            self.enter_call(name);
+        }
+        let result = call_builtin(callee, args, span);
+        if name.is_some() {
+            self.exit_call();
+        }
+        result
+    }
+
+    // Runs the innermost frame until it returns. An error drops the frames
+    // of the calls it unwinds through, tracing each the way the interpreter
+    // does, all but the outermost, which the caller traces.
+    fn execute(&mut self) -> Result<Value, Diagnostic> {
+        let mut frame = self.frames.pop().expect("a frame to run");
+        let floor = self.frames.len();
+        let mut diagnostic = match self.run_frame(&mut frame, floor) {
+            Ok(value) => return Ok(value),
+            Err(diagnostic) => diagnostic,
+        };
+        while self.frames.len() > floor {
+            let caller = self.frames.pop().expect("checked above");
+            diagnostic = diagnostic.called_from(frame.closure.prototype.name(), caller.span());
+            frame = caller;
+        }
+        Err(diagnostic)
+    }
+
+    // Runs `frame` and the calls it makes until it returns, down to `floor`
+    // frames. The running frame is kept out of `frames` so the loop doesn't
+    // have to look it up.
+    fn run_frame(&mut self, frame: &mut Frame, floor: usize) -> Result<Value, Diagnostic> {
+        loop {
+            let op = frame.closure.prototype.chunk.code[frame.ip];
+            frame.ip += 1;
+            if let Some(profile) = &mut self.profile {
+                profile.count(op.name());
+            }
+
+            match op {
+                Op::Constant(index) => {
+                    let value = frame.closure.prototype.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Unit => self.stack.push(Value::Unit),
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::PopN(count) => {
+                    let len = self.stack.len() - count as usize;
+                    self.stack.truncate(len);
+                }
+                Op::PopUnder(count) => {
+                    let top = self.pop();
+                    let len = self.stack.len() - count as usize;
+                    self.stack.truncate(len);
+                    self.stack.push(top);
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.peek().clone();
+                    self.stack[frame.base + slot as usize] = value;
+                }
+                Op::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let value = self.peek().clone();
+                    match &mut *frame.closure.upvalues[index as usize].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                Op::GetGlobal(index) => {
+                    let value = self.global(index).ok_or_else(|| {
+                        let name = self.names[index as usize];
+                        let message = format!("unknown variable `{}`", name);
+                        let diagnostic = Diagnostic::error(message, frame.span());
+                        unknown(diagnostic, &[name], 0, frame.span(), &self.defined())
+                    })?;
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(index) => {
+                    let value = self.peek().clone();
+                    match &mut self.globals[index as usize] {
+                        Some(global) if global.mutable => global.value = value,
+                        Some(_) => {
+                            return Err(Diagnostic::error(
+                                format!(
+                                    "cannot assign to immutable variable `{}`, consider declaring it with `let mut`",
+                                    self.names[index as usize]
+                                ),
+                                frame.span(),
+                            ))
+                        }
+                        unbound => {
+                            *unbound = Some(Global {
+                                value,
+                                mutable: true,
+                            })
+                        }
+                    }
+                }
+                Op::DefineGlobal { index, mutable } => {
+                    let value = self.pop();
+                    self.globals[index as usize] = Some(Global { value, mutable });
+                }
+                Op::CloseUpvalues(slot) => self.close_upvalues(frame.base + slot as usize),
+                Op::Tuple(count) => {
+                    let values = self.pop_many(count as usize);
+                    self.stack.push(Value::Tuple(values));
+                }
+                Op::List(count) => {
+                    let values = self.pop_many(count as usize);
+                    self.stack.push(Value::list(values));
+                }
+                Op::Map(count) => {
+                    let values = self.pop_many(2 * count as usize);
+                    let mut map = BTreeMap::new();
+                    let mut values = values.into_iter();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        map.insert(map_key(&key, frame.span())?, value);
+                    }
+                    self.stack.push(Value::map(map));
+                }
+                Op::Struct { name, fields } => {
+                    let values = self.pop_many(2 * fields as usize);
+                    let ty = self.pop();
+                    let mut fields = Vec::new();
+                    let mut values = values.into_iter();
+                    while let (Some(Value::String(field)), Some(value)) =
+                        (values.next(), values.next())
+                    {
+                        fields.push((Symbol::intern(&field), value));
+                    }
+                    let name = &frame.closure.prototype.chunk.names[name as usize];
+                    self.stack.push(construct(ty, name, fields, frame.span())?);
+                }
+                Op::Variant { ty, name } => {
+                    let names = &frame.closure.prototype.chunk.names;
+                    let value = match self.pop() {
+                        Value::EnumType(ty) => {
+                            enum_variant(&ty, names[name as usize], frame.span())?
+                        }
+                        other => {
+                            return Err(Diagnostic::error(
+                                format!(
+                                    "`{}` is a {}, not a module",
+                                    names[ty as usize],
+                                    other.type_name()
+                                ),
+                                frame.span(),
+                            ))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::GetField(name) => {
+                    let name = frame.closure.prototype.chunk.names[name as usize];
+                    let target = self.pop();
+                    self.stack.push(get_field(&target, name, frame.span())?);
+                }
+                Op::SetField(name) => {
+                    let name = frame.closure.prototype.chunk.names[name as usize];
+                    let target = self.pop();
+                    let value = self.pop();
+                    set_field(&target, name, value.clone(), frame.span())?;
+                    self.stack.push(value);
+                }
+                Op::Unary(op) => {
+                    let value = self.pop();
+                    self.stack.push(unary(op, value, frame.span())?);
+                }
+                Op::Binary(op) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(binary(op, left, right, frame.span())?);
+                }
+                Op::CheckBool => {
+                    expect_bool(self.peek(), frame.span())?;
+                }
+                Op::Index => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    self.stack.push(index_value(&target, &index, frame.span())?);
+                }
+                Op::Slice { start, end } => {
+                    let end = if end { Some(self.pop()) } else { None };
+                    let start = if start { Some(self.pop()) } else { None };
+                    let target = self.pop();
+                    self.stack
+                        .push(slice_value(&target, start, end, frame.span())?);
+                }
+                Op::Range { inclusive } => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    self.stack.push(range(start, end, inclusive, frame.span())?);
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    let condition = self.pop();
+                    if !expect_bool(&condition, frame.span())? {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Call(args) => {
+                    let index = self.stack.len() - args as usize - 1;
+                    if let Value::Native(_) | Value::Constructor(..) = &self.stack[index] {
+                        let args = self.pop_many(args as usize);
+                        let callee = self.pop();
+                        let result = self.run_builtin(callee, args, frame.span())?;
+                        self.stack.push(result);
+                        continue;
+                    }
+                    let closure = self.callable(&self.stack[index], args as usize, frame.span())?;
+                    self.enter_call(closure.prototype.name());
+                    let caller = std::mem::replace(
+                        frame,
+                        Frame {
+                            closure,
+                            ip: 0,
+                            base: index,
+                        },
+                    );
+                    self.frames.push(caller);
+                }
+                Op::TailCall(args) => {
+                    let index = self.stack.len() - args as usize - 1;
+                    if let Value::Native(_) | Value::Constructor(..) = &self.stack[index] {
+                        let args = self.pop_many(args as usize);
+                        let callee = self.pop();
+                        let result = self.run_builtin(callee, args, frame.span())?;
+                        self.stack.push(result);
+                        continue;
+                    }
+                    let closure = self.callable(&self.stack[index], args as usize, frame.span())?;
+                    // The callee and its arguments move down to where the
+                    // returning function's frame started.
+                    self.exit_call();
+                    self.enter_call(closure.prototype.name());
+                    self.close_upvalues(frame.base);
+                    self.stack.drain(frame.base..index);
+                    frame.closure = closure;
+                    frame.ip = 0;
+                }
+                Op::Method {
+                    name,
+                    args,
+                    fallback,
+                } => {
+                    let method = &frame.closure.prototype.chunk.names[name as usize];
+                    let args = self.pop_many(args as usize);
+                    let receiver = self.pop();
+                    let function = match fallback {
+                        Fallback::Global(index) => self.global(index),
+                        Fallback::Stack => Some(self.pop()),
+                    };
+                    let span = frame.span();
+                    let result =
+                        call_method(receiver, method, args, function, span, |function, args| {
+                            self.call_value(function, args, span)
+                        })?;
+                    self.stack.push(result);
+                }
+                Op::DefineMethod(name) => {
+                    let name = frame.closure.prototype.chunk.names[name as usize];
+                    let method = self.pop();
+                    define_method(self.peek(), name, method, frame.span())?;
+                }
+                Op::Closure(index) => {
+                    let prototype = frame.closure.prototype.chunk.functions[index as usize].clone();
+                    let upvalues = prototype
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.capture(frame.base + *slot as usize),
+                            Capture::Upvalue(index) => {
+                                frame.closure.upvalues[*index as usize].clone()
+                            }
+                        })
+                        .collect();
+                    let closure = Closure {
+                        prototype,
+                        upvalues,
+                    };
+                    self.stack.push(Value::Compiled(Rc::new(closure)));
+                }
+                Op::Return => {
+                    self.exit_call();
+                    let result = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() == floor {
+                        return Ok(result);
+                    }
+                    *frame = self.frames.pop().expect("checked above");
+                    self.stack.push(result);
+                }
+                Op::Match { pattern, fail } => {
+                    let value = self.pop();
+                    let pattern = &frame.closure.prototype.chunk.patterns[pattern as usize];
+                    let mut bindings = Vec::new();
+                    if match_pattern(pattern, &value, &mut bindings) {
+                        self.stack
+                            .extend(bindings.into_iter().map(|(_, value)| value));
+                    } else {
+                        frame.ip = fail as usize;
+                    }
+                }
+                Op::NoMatch => {
+                    return Err(Diagnostic::error(
+                        format!("no match arm matched value `{}`", self.peek()),
+                        frame.span(),
+                    ))
+                }
+                Op::NotTry => return Err(not_try(self.peek(), frame.span())),
+                Op::Iterate => {
+                    let value = self.pop();
+                    let items = iterate(value, frame.span())?;
+                    self.stack.push(Value::list(items));
+                }
+                Op::Next { slot, done } => {
+                    let slot = frame.base + slot as usize;
+                    let index = match self.stack[slot + 1] {
+                        Value::Integer(index) => index as usize,
+                        _ => unreachable!("loops count in an integer slot"),
+                    };
+                    let item = match &self.stack[slot] {
+                        Value::List(items) => items.borrow().get(index).cloned(),
+                        _ => unreachable!("loops iterate over a list"),
+                    };
+                    match item {
+                        Some(item) => {
+                            self.stack[slot + 1] = Value::Integer(index as i64 + 1);
+                            self.stack.push(item);
+                        }
+                        None => frame.ip = done as usize,
+                    }
+                }
+                Op::Bind(pattern) | Op::Destructure(pattern) => {
+                    let value = self.pop();
+                    let pattern = &frame.closure.prototype.chunk.patterns[pattern as usize];
+                    let mut bindings = Vec::new();
+                    if !match_pattern(pattern, &value, &mut bindings) {
+                        let what = match op {
+                            Op::Bind(_) => "loop",
+                            _ => "`let`",
+                        };
+                        return Err(Diagnostic::error(
+                            format!("{} pattern does not match value `{}`", what, value),
+                            frame.span(),
+                        ));
+                    }
+                    self.stack
+                        .extend(bindings.into_iter().map(|(_, value)| value));
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("the compiler balances the stack")
+    }
+
+    // Returns the upvalue for a stack slot, sharing it with every closure
+    // that already captured the slot.
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot));
+        if let Some(upvalue) = open {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // Moves the values of every captured slot from `from` up into their
+    // upvalues, before those slots are popped.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+// Calls a function that runs without a frame of its own: a native function
+// or an enum variant's constructor.
+fn call_builtin(callee: Value, args: Vec<Value>, span: Span) -> Result<Value, Diagnostic> {
+    match callee {
+        Value::Native(native) => native.call(&args, span),
+        Value::Constructor(ty, index) => construct_variant(&ty, index, args, span),
+        other => unreachable!("{} is not a builtin", other.type_name()),
+    }
+}
+
+fn expect_bool(value: &Value, span: Span) -> Result<bool, Diagnostic> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        other => Err(Diagnostic::error(
+            format!("expected bool, found {}", other.type_name()),
+            span,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::value::Value;
+    use crate::parser::parser::parse;
+    use crate::vm::vm::Vm;
+
+    fn run(source: &str) -> Value {
+        Vm::new().run(&parse(source).unwrap()).unwrap()
+    }
+
+    // Runs `source` on both backends, which must agree.
+    fn same(source: &str) -> String {
+        let program = parse(source).unwrap();
+        let tree = Interpreter::new().run(&program).unwrap().to_string();
+        let vm = Vm::new().run(&program).unwrap().to_string();
+        assert_eq!(vm, tree);
+        vm
+    }
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        assert_eq!(same("1 + 2 * 3 - 4 / 2"), "5");
+        assert_eq!(
+            same("fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fib(15)"),
+            "610"
+        );
+        let source = "
+            let mut total = 0;
+            for (key, value) in #{ \"a\": 1, \"b\": 2, \"c\": 3 } {
+                if key == \"b\" { continue }
+                total += value;
+            }
+            let mut i = 0;
+            while true { i += 1; if i == 4 { break } }
+            let label = match (total, i) {
+                (4, n) if n > 10 => \"big\",
+                (4, n) => { let twice = n * 2; \"n=\" + \"\" },
+                _ => \"other\",
+            };
+            (total, i, label, [1, 2, 3].filter(fn(x) { x != 2 }), \"clay\"[1..3])
+        ";
+        assert_eq!(same(source), "(4, 4, n=, [1, 3], la)");
+    }
+
+    #[test]
+    fn compares_separate_values_that_contain_themselves() {
+        let source = "
+            let a = [1]; a.push(a);
+            let b = [1]; b.push(b);
+            let m = #{}; m.insert(\"m\", m);
+            let n = #{}; n.insert(\"m\", n);
+            (a == b, m == n, a == [1, [2]])
+        ";
+        assert_eq!(same(source), "(true, true, false)");
+    }
+
+    #[test]
+    fn supports_structs() {
+        let source = "
+            struct Pair { left, right }
+            fn swap(pair) {
+                match pair { Pair { left, right } => Pair { left: right, right: left } }
+            }
+            let mut p = swap(Pair { left: 1, right: [2] });
+            p.right = p.right + 1;
+            let f = fn() { p.left.push(3); p.left };
+            (f(), p)
+        ";
+        assert_eq!(same(source), "([2, 3], Pair { left: [2, 3], right: 2 })");
+    }
+
+    #[test]
+    fn erases_type_parameters() {
+        let source = "
+            struct Pair<A, B> { left: A, right: B }
+            enum Maybe<T> { Just(T), Nothing }
+            fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { left: p.right, right: p.left } }
+            fn get<T>(m: Maybe<T>, default: T) -> T {
+                match m { Maybe::Just(x) => x, Maybe::Nothing => default }
+            }
+            (swap(Pair { left: 1, right: \"a\" }), get(Maybe::Just(2), 0), get(Maybe::Nothing, \"b\"))
+        ";
+        assert_eq!(same(source), "(Pair { left: a, right: 1 }, 2, b)");
+    }
+
+    #[test]
+    fn dispatches_methods_from_impls() {
+        let source = "
+            trait Shape { fn area(self); fn scale(self, by); }
+            struct Square { side }
+            struct Rect { width, height }
+            impl Shape for Square {
+                fn area(self) { self.side * self.side }
+                fn scale(self, by) { Square { side: self.side * by } }
+            }
+            impl Shape for Rect {
+                fn area(self) { self.width * self.height }
+                fn scale(self, by) { Rect { width: self.width * by, height: self.height * by } }
+            }
+            [Square { side: 2 }, Rect { width: 1, height: 3 }].map(fn(s) { s.scale(2).area() })
+        ";
+        assert_eq!(same(source), "[16, 12]");
+    }
+
+    #[test]
+    fn falls_back_to_functions_for_method_calls() {
+        let source = "
+            struct Counter { step, bump }
+            fn twice(n, f) { f(f(n)) }
+            fn total(xs) { xs.len() * 10 }
+            fn run() {
+                let add = fn(a, b) { a + b };
+                let c = Counter { step: 2, bump: fn(n) { n + 1 } };
+                (c.bump(1), 3.twice(fn(n) { n * c.step }), [1, 2].total(), 1.add(2).add(3))
+            }
+            run()
+        ";
+        assert_eq!(same(source), "(2, 12, 20, 6)");
+    }
+
+    #[test]
+    fn evaluates_only_the_taken_branch() {
+        let source = "
+            let seen = [];
+            fn see(n) { seen.push(n); n }
+            let n = 5;
+            let size = if n > 9 { see(\"big\") } else if n > 3 { see(\"mid\") } else { see(\"small\") };
+            let scaled = 1 + if n > 9 { see(1) } else { see(2) } * 10;
+            (size, scaled, if n > 9 { see(0) }, seen)
+        ";
+        assert_eq!(same(source), "(mid, 21, (), [mid, 2])");
+    }
+
+    #[test]
+    fn short_circuits_logical_operators() {
+        let source = "
+            let seen = [];
+            fn see(b) { seen.push(b); b }
+            let results = (see(false) && see(true), see(true) || see(false), see(true) && see(false));
+            (results, seen)
+        ";
+        assert_eq!(
+            same(source),
+            "((false, true, false), [false, true, true, false])"
+        );
+        for source in ["1 && true", "false || \"a\""] {
+            let program = parse(source).unwrap();
+            let tree = Interpreter::new().run(&program).unwrap_err();
+            let vm = Vm::new().run(&program).unwrap_err();
+            assert_eq!((vm.message, vm.span), (tree.message, tree.span));
+        }
+    }
+
+    #[test]
+    fn supports_question_marks() {
+        let source = "
+            fn get(map, key) { if map.contains(key) { Some(map[key]) } else { None } }
+            fn sum(map) {
+                let mut total = 0;
+                for key in [\"a\", \"b\"] { total += get(map, key)?; }
+                Some(total)
+            }
+            fn checked(n) { if n < 0 { Err(n) } else { Ok(n) } }
+            fn twice(n) { Ok(checked(n)? + checked(n)?) }
+            (sum(#{ \"a\": 1, \"b\": 2 }), sum(#{ \"a\": 1 }), twice(2), twice(-1))
+        ";
+        assert_eq!(
+            same(source),
+            "(Option::Some(3), Option::None, Result::Ok(4), Result::Err(-1))"
+        );
+    }
+
+    #[test]
+    fn supports_ranges() {
+        let source = "
+            let mut squares = [];
+            for i in 0..=3 { squares.push(i * i); }
+            let n = 3;
+            let mut count = 0;
+            for _ in n..n + 2 { count += 1; }
+            (squares, count, 5..2, len(5..2))
+        ";
+        assert_eq!(same(source), "([0, 1, 4, 9], 2, 5..2, 0)");
+    }
+
+    #[test]
+    fn supports_destructuring() {
+        let source = "
+            let (a, b) = (1, [2]);
+            fn swap(pair) {
+                let (left, right) = pair;
+                let f = fn() { left };
+                (right, f())
+            }
+            let mut (c, d) = swap((a, b));
+            c.push(3);
+            d += 1;
+            (a, b, c, d)
+        ";
+        assert_eq!(same(source), "(1, [2, 3], [2, 3], 2)");
+    }
+
+    #[test]
+    fn supports_enums() {
+        let source = "
+            enum Tree { Leaf, Node(Tree, Int, Tree) }
+            fn insert(tree, n) {
+                match tree {
+                    Tree::Leaf => Tree::Node(Tree::Leaf, n, Tree::Leaf),
+                    Tree::Node(left, m, right) if n < m => Tree::Node(insert(left, n), m, right),
+                    Tree::Node(left, m, right) => Tree::Node(left, m, insert(right, n)),
+                }
+            }
+            fn total(tree) {
+                match tree { Tree::Leaf => 0, Tree::Node(l, n, r) => total(l) + n + total(r) }
+            }
+            let mut tree = Tree::Leaf;
+            for n in [3, 1, 2] { tree = insert(tree, n); }
+            let node = Tree::Node;
+            (total(tree), node(Tree::Leaf, 1, Tree::Leaf), [4].map(fn(n) { Tree::Node(Tree::Leaf, n, Tree::Leaf) }))
+        ";
+        assert_eq!(
+            same(source),
+            "(6, Tree::Node(Tree::Leaf, 1, Tree::Leaf), [Tree::Node(Tree::Leaf, 4, Tree::Leaf)])"
+        );
+    }
+
+    #[test]
+    fn matches_the_prelude_variants_by_their_own_names() {
+        let source = "
+            fn unwrap_or(value, default) {
+                match value { Some(Ok(n)) => n, Some(Err(_)) => default, None => default }
+            }
+            ([Some(Ok(1)), Some(Err(2)), None].map(fn(v) { unwrap_or(v, 0) }),
+                match Some(1) { None => \"none\", _ => \"some\" })
+        ";
+        assert_eq!(same(source), "([1, 0, 0], some)");
+    }
+
+    #[test]
+    fn reports_values_question_marks_do_not_apply_to() {
+        let err = Vm::new()
+            .run(&parse("fn f() { 5? } f()").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "`?` applied to `5`, which is not an Option or Result"
+        );
+    }
+
+    #[test]
+    fn captures_variables_by_reference() {
+        let source = "
+            fn counter() {
+                let mut n = 0;
+                (fn() { n += 1; n }, fn() { n })
+            }
+            let pair = counter();
+            let increment = pair[0];
+            increment();
+            increment();
+            let closures = [];
+            for i in [1, 2, 3] { closures.push(fn() { i * 10 }); }
+            (pair[1](), closures.map(fn(f) { f() }))
+        ";
+        assert_eq!(same(source), "(2, [10, 20, 30])");
+
+        let source = "
+            {
+                fn even(n) { if n == 0 { true } else { !even(n - 1) } }
+                let adder = fn(a) { fn(b) { fn(c) { a + b + c } } };
+                (even(7), adder(1)(2)(3))
+            }
+        ";
+        assert_eq!(same(source), "(false, 6)");
+    }
+
+    #[test]
+    fn keeps_globals_between_runs() {
+        let mut vm = Vm::new();
+        vm.run(&parse("let mut x = 1; fn get() { x }").unwrap())
+            .unwrap();
+        let value = vm.run(&parse("x = x + 1; get()").unwrap()).unwrap();
+        assert_eq!(value, Value::Integer(2));
+    }
+
+    #[test]
+    fn reports_errors_where_the_interpreter_does() {
+        for source in [
+            "let x = 1;\nx = 2",
+            "fn f(a) { a }\nf(1, 2)",
+            "1 + \"a\"",
+            "match 3 {\n1 => 2 }",
+            "if 1 { 2 }",
+            "[1][5]",
+            "for (a, b) in [1] { a }",
+            "3()",
+            "let count = 1; fn f() { coutn }\nf()",
+            "fn inner(n) { n / 0 }\nfn outer(n) { inner(n) + 1 }\nouter(1)",
+            "[1, 2].map(fn(n) { n.len() })",
+        ] {
+            let program = parse(source).unwrap();
+            let tree = Interpreter::new().run(&program).unwrap_err();
+            let vm = Vm::new().run(&program).unwrap_err();
+            assert_eq!(
+                (&vm.message, vm.span, vm.suggestions()),
+                (&tree.message, tree.span, tree.suggestions()),
+                "{}",
+                source
+            );
+            assert_eq!(
+                (vm.calls(), vm.notes()),
+                (tree.calls(), tree.notes()),
+                "{}",
+                source
+            );
+        }
+
+        let program = parse("fn f() { 1 + f() }\nf()").unwrap();
+        let err = Vm::new().run(&program).unwrap_err();
+        assert_eq!(err.message, "stack overflow");
+        assert_eq!(run("let f = fn() { 1 }; f()"), Value::Integer(1));
+    }
+
+    #[test]
+    fn profiles_calls_and_instructions() {
+        let source = "fn count(n) { if n == 0 { 0 } else { count(n - 1) } }\nlen([count(3)])";
+        let mut vm = Vm::new();
+        vm.start_profile();
+        vm.run(&parse(source).unwrap()).unwrap();
+        let profile = vm.take_profile().unwrap();
+        let mut calls: Vec<_> = profile
+            .functions()
+            .into_iter()
+            .map(|(name, calls)| (name.to_string(), calls.count))
+            .collect();
+        calls.sort();
+        assert_eq!(
+            calls,
+            [
+                ("<main>".to_string(), 1),
+                ("count".to_string(), 4),
+                ("len".to_string(), 1)
+            ]
+        );
+        let opcodes = profile.opcodes();
+        assert!(opcodes.contains(&("TailCall", 3)));
+        assert!(opcodes.contains(&("Return", 2)));
+        // Each tail call ends the call it replaces.
+        assert!(profile
+            .folded()
+            .lines()
+            .all(|line| !line.contains("count;count")));
+    }
+
+    #[test]
+    fn reuses_frames_for_calls_in_tail_position() {
+        let source = "
+            fn count(n, total) { if n == 0 { total } else { count(n - 1, total + 1) } }
+            fn even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fn odd(n) { if n == 0 { false } else { return even(n - 1); } }
+            fn sum(list, i, total) {
+                match i == list.len() {
+                    true => total,
+                    false => { let next = i + 1; sum(list, next, total + list[i]) },
+                }
+            }
+            (count(1000000, 0), even(1000001), sum([1, 2, 3], 0, 0))
+        ";
+        assert_eq!(run(source).to_string(), "(1000000, false, 6)");
+        assert_eq!(
+            same("fn f(n, g) { if n == 0 { g(n) } else { f(n - 1, g) } }\nf(3, print)"),
+            "()"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use clay::interpreter::module::{display_path, ModuleLoader, EXTENSION};
+use clay::pipeline::pipeline::Pipeline;
+use clay::project::manifest::{Manifest, MANIFEST};
+
+// How often the files are looked at. Polling needs nothing from the
+// operating system and reading a project's modification times is cheap.
+const POLL: Duration = Duration::from_millis(250);
+
+// When each file a command may read was last modified.
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+// The files a command may read.
+#[derive(Debug, PartialEq)]
+enum Watched {
+    // Every clay file and manifest under a project's directory, or under a
+    // directory given outside a project, looked for again each time.
+    Dir(PathBuf),
+    // A file outside a project and the files it imports, found again after
+    // each run.
+    Files(Vec<PathBuf>),
+}
+
+impl Watched {
+    fn describe(&self) -> String {
+        match self {
+            Watched::Dir(dir) => format!("`{}`", display_path(dir)),
+            Watched::Files(files) => files
+                .iter()
+                .map(|file| format!("`{}`", display_path(file)))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+// Runs clay with `args` again every time a file the command may read
+// changes, until interrupted.
+pub fn start(args: &[String], path: &Path) -> i32 {
+    loop {
+        // Looked at before the run, so a change made while it runs starts
+        // another one.
+        let files = watched(path);
+        let last = snapshot(&files);
+        if io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+        }
+        let code = crate::run(args);
+        println!(
+            "\n[exited with {}; watching {} for changes]",
+            code,
+            files.describe()
+        );
+        let _ = io::stdout().flush();
+
+        loop {
+            thread::sleep(POLL);
+            if snapshot(&files) != last {
+                // Editors often write a file in several steps, so the files
+                // get a moment to settle before the command runs.
+                thread::sleep(POLL);
+                break;
+            }
+        }
+    }
+}
+
+// The files `path` may depend on: its project's, the directory it names, or
+// the file and what it imports.
+fn watched(path: &Path) -> Watched {
+    if let Some(manifest) = Manifest::find(path) {
+        if let Some(dir) = manifest.parent() {
+            return Watched::Dir(dir.to_path_buf());
+        }
+    }
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if path.is_dir() {
+        return Watched::Dir(path);
+    }
+    let mut files = vec![path.clone()];
+    if let Ok(source) = fs::read_to_string(&path) {
+        let mut loader = ModuleLoader::new(Pipeline::new());
+        loader.set_root(&path);
+        if let Ok(program) = loader.pipeline_mut().parse_file(&path, &source) {
+            files.extend(loader.imports(&program, Some(&path)));
+        }
+    }
+    Watched::Files(files)
+}
+
+fn snapshot(watched: &Watched) -> Snapshot {
+    match watched {
+        Watched::Dir(root) => scan(root),
+        // A file that can't be read is left out, so it being created or
+        // removed is a change too.
+        Watched::Files(files) => files
+            .iter()
+            .filter_map(|file| {
+                let modified = fs::metadata(file).and_then(|data| data.modified());
+                Some((file.clone(), modified.ok()?))
+            })
+            .collect(),
+    }
+}
+
+// The clay files and manifests under `root`, skipping hidden directories,
+// which hold checkouts and caches clay itself writes.
+fn scan(root: &Path) -> Snapshot {
+    let mut files = Snapshot::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            if path.is_dir() {
+                if !name.to_string_lossy().starts_with('.') {
+                    dirs.push(path);
+                }
+            } else if name == MANIFEST || path.extension().is_some_and(|e| e == EXTENSION) {
+                if let Ok(modified) = entry.metadata().and_then(|data| data.modified()) {
+                    files.insert(path, modified);
+                }
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use crate::watch::{snapshot, watched, Watched};
+
+    #[test]
+    fn notices_changes_to_project_files() {
+        let dir = std::env::temp_dir().join(format!("clay-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join(".clay/cache")).unwrap();
+        fs::write(dir.join("clay.toml"), "[package]\nname = \"app\"").unwrap();
+        fs::write(dir.join("src/main.clay"), "1").unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let project = Watched::Dir(dir.clone());
+        assert_eq!(watched(&dir.join("src/main.clay")), project);
+
+        let before = snapshot(&project);
+        assert_eq!(before.len(), 2);
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join(".clay/cache/main.clay"), "").unwrap();
+        assert_eq!(snapshot(&project), before);
+
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.join("src/main.clay"))
+            .unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_ne!(snapshot(&project), before);
+        fs::write(dir.join("src/util.clay"), "").unwrap();
+        assert_eq!(snapshot(&project).len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watches_a_file_outside_a_project_and_its_imports() {
+        let dir = std::env::temp_dir().join(format!("clay-watch-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(
+            dir.join("main.clay"),
+            "import { get } from \"util.clay\";\nget()",
+        )
+        .unwrap();
+        fs::write(dir.join("util.clay"), "fn get() { 1 }").unwrap();
+        fs::write(dir.join("other.clay"), "2").unwrap();
+        fs::write(dir.join("nested/deep.clay"), "3").unwrap();
+        let dir = dir.canonicalize().unwrap();
+        if dir.ancestors().any(|dir| dir.join("clay.toml").is_file()) {
+            return fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let files = watched(&dir.join("main.clay"));
+        assert_eq!(
+            files,
+            Watched::Files(vec![dir.join("main.clay"), dir.join("util.clay")])
+        );
+        let before = snapshot(&files);
+        assert_eq!(before.len(), 2);
+        fs::write(dir.join("nested/new.clay"), "").unwrap();
+        assert_eq!(snapshot(&files), before);
+        fs::remove_file(dir.join("util.clay")).unwrap();
+        assert_ne!(snapshot(&files), before);
+
+        assert_eq!(watched(&dir), Watched::Dir(dir.clone()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
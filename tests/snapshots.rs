@@ -0,0 +1,102 @@
+// Golden tests for the lexer and parser. Each `tests/cases/*.clay` file has
+// a `.snap` file beside it recording its tokens and either its syntax tree,
+// printed back as source, or the diagnostic it fails with. A change to either
+// shows up as a difference from the recorded snapshot.
+//
+// To add a case, write the `.clay` file and run
+//
+//     CLAY_UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//
+// which writes every snapshot anew; review the changes before committing.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use clay::diagnostic::render::render;
+use clay::lexer::lexer::Lexer;
+use clay::parser::parser::parse;
+
+fn snapshot(name: &str, source: &str) -> String {
+    let mut snapshot = String::from("-- tokens\n");
+    for token in Lexer::new(source).take_while(Result::is_ok).flatten() {
+        let start = token.span.start;
+        writeln!(
+            snapshot,
+            "{}:{}\t{:?}",
+            start.line,
+            start.column + 1,
+            token.kind
+        )
+        .unwrap();
+    }
+    match parse(source) {
+        Ok(program) => writeln!(snapshot, "-- ast\n{}", program).unwrap(),
+        Err(diagnostic) => {
+            let rendered = render(&diagnostic, name, source);
+            write!(snapshot, "-- diagnostics\n{}", rendered).unwrap();
+        }
+    }
+    snapshot
+}
+
+#[test]
+fn cases_match_their_snapshots() {
+    let update = env::var_os("CLAY_UPDATE_SNAPSHOTS").is_some();
+    let cases = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cases");
+    let mut paths: Vec<_> = fs::read_dir(&cases)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "clay")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no cases in {}", cases.display());
+
+    let mut failures = Vec::new();
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let actual = snapshot(&name, &fs::read_to_string(&path).unwrap());
+        let snap = path.with_extension("snap");
+        if update {
+            fs::write(&snap, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&snap).unwrap_or_default();
+        if actual != expected {
+            failures.push(difference(&name, &expected, &actual));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with CLAY_UPDATE_SNAPSHOTS=1 to accept the changes",
+        failures.join("\n")
+    );
+}
+
+// Describes where a snapshot first differs from what was recorded.
+fn difference(name: &str, expected: &str, actual: &str) -> String {
+    if expected.is_empty() {
+        return format!("{}: no snapshot recorded", name);
+    }
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (expected, actual) => {
+                return format!(
+                    "{}: line {} differs\n  expected: {}\n  actual:   {}",
+                    name,
+                    line,
+                    expected.unwrap_or("<end of snapshot>"),
+                    actual.unwrap_or("<end of snapshot>"),
+                )
+            }
+        }
+    }
+    unreachable!("the snapshots differ")
+}